@@ -0,0 +1,403 @@
+//! Microphone capture gated by push-to-talk or voice activity.
+//!
+//! The capture stream runs continuously but only forwards audio to the
+//! encoder while the [`TransmitGate`] is open. When it closes an empty
+//! buffer is sent so the encoder ends the transmission cleanly.
+//!
+//! Echo cancellation and gain control, see [`crate::processing`], run on
+//! every buffer before it is metered or analyzed for voice activity.
+
+use crate::encoder::SAMPLE_RATE;
+use crate::level::LevelMeter;
+use crate::processing::{AudioProcessor, EchoReference, ProcessingConfig};
+use crate::recorder::Recorder;
+use crate::vad::{VadConfig, VoiceActivityDetector};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, SampleRate};
+use fleet_net_common::error::FleetNetError;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info};
+
+pub use fleet_net_common::audio::TransmitMode;
+
+/// Decides when captured audio is transmitted.
+///
+/// Tracks which radios are keyed (ids must be below 64), the transmit mode
+/// and the voice activity settings, and publishes whether the microphone is
+/// currently on the air.
+#[derive(Debug)]
+pub struct TransmitGate {
+    keyed: AtomicU64,
+    voice_activity: AtomicBool,
+    muted: AtomicBool,
+    vad_config: Mutex<VadConfig>,
+    processing: Mutex<ProcessingConfig>,
+    input_level: LevelMeter,
+    transmitting: watch::Sender<bool>,
+}
+
+impl Default for TransmitGate {
+    fn default() -> Self {
+        Self {
+            keyed: AtomicU64::new(0),
+            voice_activity: AtomicBool::new(false),
+            muted: AtomicBool::new(false),
+            vad_config: Mutex::new(VadConfig::default()),
+            processing: Mutex::new(ProcessingConfig::default()),
+            input_level: LevelMeter::new(),
+            transmitting: watch::Sender::new(false),
+        }
+    }
+}
+
+impl TransmitGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&self, radio_id: u8) {
+        self.keyed.fetch_or(radio_bit(radio_id), Ordering::AcqRel);
+    }
+
+    pub fn release(&self, radio_id: u8) {
+        self.keyed.fetch_and(!radio_bit(radio_id), Ordering::AcqRel);
+    }
+
+    /// Releases every radio, e.g. when the window loses its hotkeys.
+    pub fn release_all(&self) {
+        self.keyed.store(0, Ordering::Release);
+    }
+
+    /// Whether any radio's PTT key is held.
+    pub fn is_keyed(&self) -> bool {
+        self.keyed.load(Ordering::Acquire) != 0
+    }
+
+    /// Radios currently keyed, in ascending order.
+    pub fn keyed_radios(&self) -> Vec<u8> {
+        let keyed = self.keyed.load(Ordering::Acquire);
+        (0..64).filter(|id| keyed & (1 << id) != 0).collect()
+    }
+
+    pub fn mode(&self) -> TransmitMode {
+        if self.voice_activity.load(Ordering::Acquire) {
+            TransmitMode::VoiceActivity
+        } else {
+            TransmitMode::PushToTalk
+        }
+    }
+
+    pub fn set_mode(&self, mode: TransmitMode) {
+        self.voice_activity
+            .store(mode == TransmitMode::VoiceActivity, Ordering::Release);
+    }
+
+    /// Whether the microphone is muted, overriding PTT and voice activity.
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Acquire)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Release);
+    }
+
+    pub fn vad_config(&self) -> VadConfig {
+        *self.vad_config.lock().unwrap()
+    }
+
+    pub fn set_vad_config(&self, config: VadConfig) {
+        *self.vad_config.lock().unwrap() = config;
+    }
+
+    pub fn processing_config(&self) -> ProcessingConfig {
+        *self.processing.lock().unwrap()
+    }
+
+    pub fn set_processing_config(&self, config: ProcessingConfig) {
+        *self.processing.lock().unwrap() = config;
+    }
+
+    /// Microphone level, measured whether or not audio is being sent so the
+    /// UI can show it while setting up voice activity.
+    pub fn input_level(&self) -> &LevelMeter {
+        &self.input_level
+    }
+
+    /// Whether captured audio is currently being sent.
+    pub fn is_transmitting(&self) -> bool {
+        *self.transmitting.borrow()
+    }
+
+    /// Notifies whenever transmission starts or stops, e.g. to drive a
+    /// squelch indicator.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.transmitting.subscribe()
+    }
+
+    fn set_transmitting(&self, transmitting: bool) {
+        self.transmitting
+            .send_if_modified(|current| std::mem::replace(current, transmitting) != transmitting);
+    }
+}
+
+fn radio_bit(radio_id: u8) -> u64 {
+    debug_assert!(radio_id < 64, "radio id {radio_id} out of range");
+    1u64 << (radio_id & 63)
+}
+
+/// Decides what each captured buffer turns into for the encoder.
+struct GateState {
+    was_open: bool,
+    vad: VoiceActivityDetector,
+    processor: AudioProcessor,
+    /// What is being played, for echo cancellation.
+    echo: Option<Arc<EchoReference>>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl GateState {
+    fn new(gate: &TransmitGate) -> Self {
+        Self {
+            was_open: false,
+            vad: VoiceActivityDetector::new(gate.vad_config()),
+            processor: AudioProcessor::new(gate.processing_config()),
+            echo: None,
+            recorder: None,
+        }
+    }
+
+    /// Returns the mono buffer to forward, an empty buffer at the end of a
+    /// transmission, or `None` while the gate stays closed.
+    fn process(
+        &mut self,
+        gate: &TransmitGate,
+        samples: &[f32],
+        channels: usize,
+    ) -> Option<Vec<f32>> {
+        let mut mono = downmix(samples, channels);
+        if let Ok(config) = gate.processing.try_lock() {
+            self.processor.set_config(*config);
+        }
+        self.processor.process(&mut mono, self.echo.as_deref());
+        gate.input_level.record(&mono);
+        let open = match gate.mode() {
+            TransmitMode::PushToTalk => gate.is_keyed(),
+            TransmitMode::VoiceActivity => {
+                // Never block the audio thread on a settings change.
+                if let Ok(config) = gate.vad_config.try_lock() {
+                    self.vad.set_config(*config);
+                }
+                // Analyze every buffer so the noise floor keeps adapting.
+                self.vad.process(&mono) || gate.is_keyed()
+            }
+        } && !gate.is_muted();
+        gate.set_transmitting(open);
+
+        let was_open = std::mem::replace(&mut self.was_open, open);
+        match (was_open, open) {
+            (_, true) => {
+                if let Some(recorder) = &self.recorder {
+                    recorder.record_transmit(&mono);
+                }
+                Some(mono)
+            }
+            (true, false) => Some(Vec::new()),
+            (false, false) => None,
+        }
+    }
+}
+
+/// Averages interleaved `channels` down to mono.
+fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+fn device_error(context: &str, err: impl std::fmt::Display) -> FleetNetError {
+    FleetNetError::AudioError(Cow::Owned(format!("{context}: {err}")))
+}
+
+/// Names of the input devices on the default host.
+pub fn input_device_names() -> Result<Vec<String>, FleetNetError> {
+    let host = cpal::default_host();
+    let devices = host
+        .input_devices()
+        .map_err(|e| device_error("Failed to list input devices", e))?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+fn find_input_device(name: Option<&str>) -> Result<cpal::Device, FleetNetError> {
+    let host = cpal::default_host();
+    let device = match name {
+        None => host.default_input_device(),
+        Some(name) => host
+            .input_devices()
+            .map_err(|e| device_error("Failed to list input devices", e))?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name)),
+    };
+
+    device.ok_or_else(|| {
+        FleetNetError::AudioError(Cow::Owned(format!(
+            "Input device not found: {}",
+            name.unwrap_or("default")
+        )))
+    })
+}
+
+/// A running input stream; capture stops when it is dropped.
+pub struct CaptureStream {
+    _stream: cpal::Stream,
+    device_name: String,
+}
+
+impl CaptureStream {
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+}
+
+/// Starts capturing from the named input device, or the default one, and
+/// forwards mono audio to `frames` while `gate` lets it through.
+///
+/// Pass the reference given to [`crate::output::start_playback`] as `echo`
+/// to cancel what the speakers play from the microphone, and the mixer's
+/// `recorder` to include transmissions in session recordings.
+///
+/// The device must support 32-bit float input at [`SAMPLE_RATE`]. Buffers are
+/// dropped rather than blocking the audio thread if the encoder falls behind.
+pub fn start_capture(
+    device_name: Option<&str>,
+    gate: Arc<TransmitGate>,
+    frames: mpsc::Sender<Vec<f32>>,
+    echo: Option<Arc<EchoReference>>,
+    recorder: Option<Arc<Recorder>>,
+) -> Result<CaptureStream, FleetNetError> {
+    let device = find_input_device(device_name)?;
+    let name = device.name().unwrap_or_else(|_| "unknown".to_string());
+
+    let config = device
+        .supported_input_configs()
+        .map_err(|e| device_error("Failed to query input device", e))?
+        .filter(|range| range.sample_format() == SampleFormat::F32)
+        .find_map(|range| range.try_with_sample_rate(SampleRate(SAMPLE_RATE)))
+        .ok_or_else(|| {
+            FleetNetError::AudioError(Cow::Owned(format!(
+                "Input device {name} does not support {SAMPLE_RATE} Hz float input"
+            )))
+        })?;
+    let channels = usize::from(config.channels());
+
+    let mut state = GateState::new(&gate);
+    state.echo = echo;
+    state.recorder = recorder;
+    let stream = device
+        .build_input_stream(
+            &config.into(),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                if let Some(buffer) = state.process(&gate, data, channels) {
+                    let _ = frames.try_send(buffer);
+                }
+            },
+            |err| error!("Input stream error: {err}"),
+            None,
+        )
+        .map_err(|e| device_error("Failed to open input stream", e))?;
+    stream
+        .play()
+        .map_err(|e| device_error("Failed to start input stream", e))?;
+
+    info!("Capturing voice audio from {name} ({channels} channels)");
+    Ok(CaptureStream {
+        _stream: stream,
+        device_name: name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate_tracks_keyed_radios() {
+        let gate = TransmitGate::new();
+        assert!(!gate.is_keyed());
+
+        gate.press(1);
+        gate.press(3);
+        gate.release(1);
+        assert!(gate.is_keyed());
+        assert_eq!(gate.keyed_radios(), vec![3]);
+
+        gate.release_all();
+        assert!(!gate.is_keyed());
+    }
+
+    #[test]
+    fn test_closing_gate_ends_transmission_once() {
+        let gate = TransmitGate::new();
+        let mut state = GateState::new(&gate);
+        let mut squelch = gate.subscribe();
+        let stereo = [0.2, 0.4, 0.6, 0.8];
+
+        assert_eq!(state.process(&gate, &stereo, 2), None);
+        gate.press(0);
+        let forwarded = state.process(&gate, &stereo, 2).unwrap();
+        assert!((forwarded[0] - 0.3).abs() < 1e-6 && (forwarded[1] - 0.7).abs() < 1e-6);
+        assert!(squelch.has_changed().unwrap());
+        assert!(*squelch.borrow_and_update());
+
+        gate.release(0);
+        assert_eq!(state.process(&gate, &stereo, 2), Some(Vec::new()));
+        assert_eq!(state.process(&gate, &stereo, 2), None);
+        assert!(!*squelch.borrow_and_update());
+    }
+
+    #[test]
+    fn test_muted_gate_never_transmits() {
+        let gate = TransmitGate::new();
+        let mut state = GateState::new(&gate);
+        let mono = [0.5; 4];
+
+        gate.press(0);
+        assert!(state.process(&gate, &mono, 1).is_some());
+        gate.set_muted(true);
+        assert_eq!(state.process(&gate, &mono, 1), Some(Vec::new()));
+        assert_eq!(state.process(&gate, &mono, 1), None);
+        assert!(!gate.is_transmitting());
+
+        gate.set_muted(false);
+        assert!(state.process(&gate, &mono, 1).is_some());
+
+        // The meter keeps running while muted
+        assert!((gate.input_level().take().peak_db - -6.0206).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_voice_activity_mode_opens_on_speech() {
+        let gate = TransmitGate::new();
+        gate.set_mode(TransmitMode::VoiceActivity);
+        gate.set_vad_config(VadConfig {
+            hangover: std::time::Duration::ZERO,
+            ..VadConfig::default()
+        });
+        let mut state = GateState::new(&gate);
+
+        let silence = [0.0; 480];
+        let speech: Vec<f32> = (0..480)
+            .map(|i| if i % 2 == 0 { 0.3 } else { -0.3 })
+            .collect();
+        assert_eq!(state.process(&gate, &silence, 1), None);
+        assert_eq!(state.process(&gate, &speech, 1).map(|b| b.len()), Some(480));
+        assert!(gate.is_transmitting());
+        assert_eq!(state.process(&gate, &silence, 1), Some(Vec::new()));
+        assert!(!gate.is_transmitting());
+    }
+}
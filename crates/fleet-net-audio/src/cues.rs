@@ -0,0 +1,270 @@
+//! Radio sound cues: mic click, roger beep and squelch tail.
+//!
+//! Cues are played by the mixer on the radio a transmission is heard on: a
+//! click when someone keys up, and a roger beep followed by the squelch tail
+//! when they let go. The local user's own transmissions get the click and
+//! the roger beep as sidetone. Which cues play depends on the radio type.
+//!
+//! Built-in cues are synthesized; any of them can be replaced by a WAV file.
+
+use crate::effects::RadioTypes;
+use crate::encoder::SAMPLE_RATE;
+use fleet_net_common::error::FleetNetError;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Longest custom sample accepted.
+const MAX_CUE_SECONDS: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CueKind {
+    MicClick,
+    RogerBeep,
+    SquelchTail,
+}
+
+/// A moment in a transmission that may be marked by cues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CueEvent {
+    TransmitStart,
+    TransmitEnd,
+    ReceiveStart,
+    ReceiveEnd,
+}
+
+/// Which cues a radio plays, and how loud.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CueConfig {
+    pub mic_click: bool,
+    pub roger_beep: bool,
+    pub squelch_tail: bool,
+    /// Cue volume, 0.0 to 2.0.
+    pub volume: f32,
+}
+
+impl CueConfig {
+    pub const SILENT: Self = Self {
+        mic_click: false,
+        roger_beep: false,
+        squelch_tail: false,
+        volume: 1.0,
+    };
+
+    /// The cues a kind of radio plays out of the box.
+    pub fn for_radio(radio_type: RadioTypes) -> Self {
+        let (mic_click, roger_beep, squelch_tail) = match radio_type {
+            RadioTypes::Hf => (true, false, true),
+            RadioTypes::Uhf | RadioTypes::Vhf => (true, true, true),
+            RadioTypes::Satellite => (false, true, false),
+            RadioTypes::Quantum => (false, false, false),
+        };
+        Self {
+            mic_click,
+            roger_beep,
+            squelch_tail,
+            volume: 1.0,
+        }
+    }
+
+    /// Cues for `event`, in the order they are played.
+    pub fn cues(&self, event: CueEvent) -> Vec<CueKind> {
+        let wanted = match event {
+            CueEvent::TransmitStart | CueEvent::ReceiveStart => {
+                vec![(self.mic_click, CueKind::MicClick)]
+            }
+            CueEvent::TransmitEnd => vec![(self.roger_beep, CueKind::RogerBeep)],
+            CueEvent::ReceiveEnd => vec![
+                (self.roger_beep, CueKind::RogerBeep),
+                (self.squelch_tail, CueKind::SquelchTail),
+            ],
+        };
+        wanted
+            .into_iter()
+            .filter_map(|(enabled, kind)| enabled.then_some(kind))
+            .collect()
+    }
+}
+
+/// Samples played for each cue.
+#[derive(Debug, Clone)]
+pub struct CueBank {
+    samples: HashMap<CueKind, Arc<[f32]>>,
+}
+
+impl Default for CueBank {
+    fn default() -> Self {
+        let samples = [CueKind::MicClick, CueKind::RogerBeep, CueKind::SquelchTail]
+            .into_iter()
+            .map(|kind| (kind, synthesize(kind).into()))
+            .collect();
+        Self { samples }
+    }
+}
+
+impl CueBank {
+    /// Mono samples at [`SAMPLE_RATE`] for `kind`.
+    pub fn get(&self, kind: CueKind) -> Arc<[f32]> {
+        self.samples[&kind].clone()
+    }
+
+    /// Replaces the samples for `kind`, e.g. with ones from [`load_cue_sample`].
+    pub fn set(&mut self, kind: CueKind, samples: Vec<f32>) {
+        self.samples.insert(kind, samples.into());
+    }
+
+    /// Goes back to the built-in sound for `kind`.
+    pub fn reset(&mut self, kind: CueKind) {
+        self.samples.insert(kind, synthesize(kind).into());
+    }
+}
+
+/// Reads a WAV file as mono samples at [`SAMPLE_RATE`].
+///
+/// Channels are averaged and other sample rates are converted, so any short
+/// recording works as a cue.
+pub fn load_cue_sample(path: &Path) -> Result<Vec<f32>, FleetNetError> {
+    let sample_error = |e: hound::Error| {
+        FleetNetError::AudioError(Cow::Owned(format!(
+            "Failed to read cue sample {}: {e}",
+            path.display()
+        )))
+    };
+    let mut reader = hound::WavReader::open(path).map_err(sample_error)?;
+    let spec = reader.spec();
+    if reader.duration() > spec.sample_rate * MAX_CUE_SECONDS {
+        return Err(FleetNetError::AudioError(Cow::Owned(format!(
+            "Cue sample {} is longer than {MAX_CUE_SECONDS} seconds",
+            path.display()
+        ))));
+    }
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>(),
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 * scale))
+                .collect()
+        }
+    }
+    .map_err(sample_error)?;
+
+    let channels = usize::from(spec.channels.max(1));
+    let mono: Vec<f32> = interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok(resample(&mono, spec.sample_rate))
+}
+
+/// Linearly resamples `samples` from `rate` to [`SAMPLE_RATE`].
+fn resample(samples: &[f32], rate: u32) -> Vec<f32> {
+    if rate == SAMPLE_RATE || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let step = f64::from(rate) / f64::from(SAMPLE_RATE);
+    let len = (samples.len() as f64 / step) as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+            samples[index] + (next - samples[index]) * fraction
+        })
+        .collect()
+}
+
+fn seconds(duration: f32) -> usize {
+    (SAMPLE_RATE as f32 * duration) as usize
+}
+
+fn tone(frequency: f32, duration: f32, amplitude: f32) -> impl Iterator<Item = f32> {
+    let len = seconds(duration);
+    // 5 ms fades keep the tone from clicking.
+    let fade = seconds(0.005).min(len / 2).max(1);
+    (0..len).map(move |i| {
+        let envelope = (i.min(len - 1 - i) as f32 / fade as f32).min(1.0);
+        amplitude * envelope * (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin()
+    })
+}
+
+/// The built-in sound for `kind`.
+fn synthesize(kind: CueKind) -> Vec<f32> {
+    match kind {
+        CueKind::MicClick => {
+            let len = seconds(0.006);
+            (0..len)
+                .map(|i| {
+                    let decay = 1.0 - i as f32 / len as f32;
+                    0.5 * decay * decay * (2.0 * PI * 1_800.0 * i as f32 / SAMPLE_RATE as f32).sin()
+                })
+                .collect()
+        }
+        CueKind::RogerBeep => tone(1_000.0, 0.09, 0.3)
+            .chain(tone(1_400.0, 0.09, 0.3))
+            .collect(),
+        CueKind::SquelchTail => {
+            let len = seconds(0.18);
+            let mut state = 0x2545_f491u32;
+            (0..len)
+                .map(|i| {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    let noise = (state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0;
+                    0.25 * noise * (1.0 - i as f32 / len as f32)
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cue_config_orders_receive_end_cues() {
+        let config = CueConfig {
+            mic_click: true,
+            roger_beep: true,
+            squelch_tail: true,
+            volume: 1.0,
+        };
+        assert_eq!(
+            config.cues(CueEvent::ReceiveEnd),
+            vec![CueKind::RogerBeep, CueKind::SquelchTail]
+        );
+        // The squelch only closes on the receiving side
+        assert_eq!(config.cues(CueEvent::TransmitEnd), vec![CueKind::RogerBeep]);
+        assert!(CueConfig::SILENT.cues(CueEvent::ReceiveStart).is_empty());
+    }
+
+    #[test]
+    fn test_load_cue_sample_converts_to_mono_48k() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("beep.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 24_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..240 {
+            writer.write_sample(i16::MAX / 2).unwrap();
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let samples = load_cue_sample(&path).unwrap();
+        assert_eq!(samples.len(), 480);
+        assert!(samples.iter().all(|&sample| (sample - 0.25).abs() < 1e-3));
+    }
+}
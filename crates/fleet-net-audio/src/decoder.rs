@@ -0,0 +1,50 @@
+//! Opus decoding of received voice frames.
+
+use crate::encoder::SAMPLE_RATE;
+use fleet_net_common::error::FleetNetError;
+use std::borrow::Cow;
+
+/// Samples in the longest Opus frame (120ms) at [`SAMPLE_RATE`].
+pub const MAX_FRAME_SAMPLES: usize = SAMPLE_RATE as usize / 1000 * 120;
+
+/// A codec turning compressed frames back into mono PCM.
+pub trait FrameDecoder: Send {
+    /// Decodes `payload` into `output`, returning the number of samples written.
+    ///
+    /// With `fec` set, `payload` is the packet following a lost one and the
+    /// lost frame is reconstructed from its forward error correction data.
+    fn decode_frame(
+        &mut self,
+        payload: &[u8],
+        output: &mut [f32],
+        fec: bool,
+    ) -> Result<usize, FleetNetError>;
+
+    /// Synthesizes `output.len()` samples to cover a lost frame.
+    fn conceal(&mut self, output: &mut [f32]) -> Result<usize, FleetNetError>;
+}
+
+impl FrameDecoder for opus::Decoder {
+    fn decode_frame(
+        &mut self,
+        payload: &[u8],
+        output: &mut [f32],
+        fec: bool,
+    ) -> Result<usize, FleetNetError> {
+        self.decode_float(payload, output, fec).map_err(opus_error)
+    }
+
+    fn conceal(&mut self, output: &mut [f32]) -> Result<usize, FleetNetError> {
+        // An empty payload asks libopus for packet loss concealment.
+        self.decode_float(&[], output, false).map_err(opus_error)
+    }
+}
+
+fn opus_error(err: opus::Error) -> FleetNetError {
+    FleetNetError::AudioError(Cow::Owned(format!("Opus error: {err}")))
+}
+
+/// Creates a mono Opus decoder at [`SAMPLE_RATE`].
+pub fn new_opus_decoder() -> Result<opus::Decoder, FleetNetError> {
+    opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono).map_err(opus_error)
+}
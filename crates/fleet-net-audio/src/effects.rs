@@ -0,0 +1,436 @@
+//! Radio effect DSP applied to received voice.
+//!
+//! Each [`RadioTypes`] maps to a [`RadioEffect`] preset. The chain band-limits
+//! the signal to the radio's pass band, drives it into soft clipping, mixes in
+//! static, and randomly fades the signal to mimic propagation decay.
+//!
+//! Encrypted traffic heard without its key is played by a [`Scrambler`]
+//! instead of being decoded, and then goes through the same chain.
+
+use crate::encoder::SAMPLE_RATE;
+use fleet_net_common::channel::{Modulation, RadioChannelConfig};
+// Re-exported as radios are configured by type alongside their effects
+pub use fleet_net_common::channel::RadioTypes;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// Average fades per second at full decay.
+const FADES_PER_SECOND: f32 = 1.5;
+
+/// Time constant for gain changes, keeping fades free of clicks.
+const FADE_SMOOTHING_SECONDS: f32 = 0.005;
+
+/// Level of scrambled traffic, about -14 dBFS RMS at the peak of a pulse.
+const SCRAMBLE_LEVEL: f32 = 0.35;
+
+/// Rate at which scrambled traffic warbles, like a cipher's sync bursts.
+const SCRAMBLE_PULSE_HZ: f32 = 12.5;
+
+// Mapped to RadioTypes for a radio to know how to process the audio.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RadioEffect {
+    pub low_cut: f32,    // Low cut frequency in Hz
+    pub high_cut: f32,   // High cut frequency in Hz
+    pub distortion: f32, // Clipping drive, 0.0 (clean) to 1.0
+    pub noise: f32,      // Static level mixed in, 0.0 to 1.0
+    pub decay: f32,      // Simulate decay with random noise interruption, 0.0 to 1.0
+}
+
+impl RadioEffect {
+    /// The effect preset used for audio received on this kind of radio.
+    pub fn for_radio_type(radio_type: RadioTypes) -> RadioEffect {
+        match radio_type {
+            RadioTypes::Hf => RadioEffect {
+                low_cut: 400.0,
+                high_cut: 2_400.0,
+                distortion: 0.5,
+                noise: 0.06,
+                decay: 0.4,
+            },
+            RadioTypes::Uhf => RadioEffect {
+                low_cut: 300.0,
+                high_cut: 3_400.0,
+                distortion: 0.2,
+                noise: 0.015,
+                decay: 0.05,
+            },
+            RadioTypes::Vhf => RadioEffect {
+                low_cut: 250.0,
+                high_cut: 3_000.0,
+                distortion: 0.3,
+                noise: 0.025,
+                decay: 0.1,
+            },
+            RadioTypes::Satellite => RadioEffect {
+                low_cut: 300.0,
+                high_cut: 3_400.0,
+                distortion: 0.15,
+                noise: 0.01,
+                decay: 0.02,
+            },
+            RadioTypes::Quantum => RadioEffect::CLEAN,
+        }
+    }
+
+    /// The effect for audio received on a tuned radio channel: the preset
+    /// of its band, adjusted for its modulation.
+    pub fn for_channel(config: &RadioChannelConfig) -> RadioEffect {
+        let effect = RadioEffect::for_radio_type(config.radio_type());
+        match config.modulation {
+            Modulation::Am => effect,
+            // FM's capture effect suppresses most background static
+            Modulation::Fm => RadioEffect {
+                noise: effect.noise / 2.0,
+                ..effect
+            },
+            // Sideband voice is narrow and slightly detuned
+            Modulation::Ssb => RadioEffect {
+                high_cut: effect.high_cut.min(2_700.0),
+                distortion: (effect.distortion + 0.1).min(1.0),
+                ..effect
+            },
+        }
+    }
+
+    /// Full-band audio with no coloring.
+    pub const CLEAN: RadioEffect = RadioEffect {
+        low_cut: 20.0,
+        high_cut: 20_000.0,
+        distortion: 0.0,
+        noise: 0.0,
+        decay: 0.0,
+    };
+}
+
+/// Second-order IIR filter (RBJ audio EQ cookbook), transposed direct form II.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn low_pass(cutoff: f32) -> Self {
+        let (cos, alpha) = Self::prepare(cutoff);
+        Self::normalized(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    fn high_pass(cutoff: f32) -> Self {
+        let (cos, alpha) = Self::prepare(cutoff);
+        Self::normalized(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    /// Returns `(cos w0, alpha)` for a Butterworth (Q = 1/sqrt 2) section.
+    fn prepare(cutoff: f32) -> (f32, f32) {
+        let nyquist = SAMPLE_RATE as f32 / 2.0;
+        let cutoff = cutoff.clamp(10.0, nyquist * 0.95);
+        let w0 = 2.0 * PI * cutoff / SAMPLE_RATE as f32;
+        (w0.cos(), w0.sin() * std::f32::consts::FRAC_1_SQRT_2)
+    }
+
+    fn normalized(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Small deterministic noise source; audio static does not need a CSPRNG.
+#[derive(Debug, Clone, Copy)]
+struct XorShift(u32);
+
+impl XorShift {
+    fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_unit(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Uniform in `[-1, 1)`.
+    fn next_bipolar(&mut self) -> f32 {
+        self.next_unit() * 2.0 - 1.0
+    }
+}
+
+/// Stateful implementation of a [`RadioEffect`] for one audio stream.
+#[derive(Debug, Clone)]
+pub struct RadioEffectProcessor {
+    effect: RadioEffect,
+    /// Two cascaded sections per edge give a 24 dB/octave slope.
+    high_pass: [Biquad; 2],
+    low_pass: [Biquad; 2],
+    rng: XorShift,
+    gain: f32,
+    target_gain: f32,
+    fade_remaining: u32,
+    smoothing: f32,
+}
+
+impl RadioEffectProcessor {
+    pub fn new(effect: RadioEffect) -> Self {
+        Self::with_seed(effect, 0x9E37_79B9)
+    }
+
+    /// Creates a processor whose static and fades follow `seed`.
+    pub fn with_seed(effect: RadioEffect, seed: u32) -> Self {
+        Self {
+            effect,
+            high_pass: [Biquad::high_pass(effect.low_cut); 2],
+            low_pass: [Biquad::low_pass(effect.high_cut); 2],
+            rng: XorShift(seed.max(1)),
+            gain: 1.0,
+            target_gain: 1.0,
+            fade_remaining: 0,
+            smoothing: 1.0 - (-1.0 / (FADE_SMOOTHING_SECONDS * SAMPLE_RATE as f32)).exp(),
+        }
+    }
+
+    pub fn effect(&self) -> &RadioEffect {
+        &self.effect
+    }
+
+    /// Applies the effect in place to mono samples at [`SAMPLE_RATE`].
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let distortion = self.effect.distortion.clamp(0.0, 1.0);
+        let drive = 1.0 + distortion * 20.0;
+        let drive_norm = drive.tanh();
+        let noise = self.effect.noise.clamp(0.0, 1.0);
+        let decay = self.effect.decay.clamp(0.0, 1.0);
+        let fade_chance = decay * FADES_PER_SECOND / SAMPLE_RATE as f32;
+
+        for sample in samples.iter_mut() {
+            let mut x = *sample;
+            for filter in &mut self.high_pass {
+                x = filter.process(x);
+            }
+
+            if distortion > 0.0 {
+                x = (x * drive).tanh() / drive_norm;
+            }
+
+            self.update_fade(decay, fade_chance);
+            // Static swells as the signal fades out.
+            let static_level = noise + decay * 0.2 * (1.0 - self.gain);
+            x = x * self.gain + self.rng.next_bipolar() * static_level;
+
+            for filter in &mut self.low_pass {
+                x = filter.process(x);
+            }
+            *sample = x.clamp(-1.0, 1.0);
+        }
+    }
+
+    fn update_fade(&mut self, decay: f32, fade_chance: f32) {
+        if self.fade_remaining > 0 {
+            self.fade_remaining -= 1;
+            if self.fade_remaining == 0 {
+                self.target_gain = 1.0;
+            }
+        } else if decay > 0.0 && self.rng.next_unit() < fade_chance {
+            // Fades last 20-150ms and dip deeper the stronger the decay.
+            let duration = 0.02 + self.rng.next_unit() * 0.13;
+            self.fade_remaining = (duration * SAMPLE_RATE as f32) as u32;
+            self.target_gain = 1.0 - decay * (0.5 + 0.5 * self.rng.next_unit());
+        }
+        self.gain += (self.target_gain - self.gain) * self.smoothing;
+    }
+}
+
+/// The sound of encrypted traffic heard without its key: static pulsing
+/// at [`SCRAMBLE_PULSE_HZ`], for one stream.
+#[derive(Debug, Clone)]
+pub struct Scrambler {
+    rng: XorShift,
+    /// Position within the current pulse, from 0 to 1.
+    phase: f32,
+}
+
+impl Scrambler {
+    pub fn new() -> Self {
+        Self {
+            rng: XorShift(0x85EB_CA6B),
+            phase: 0.0,
+        }
+    }
+
+    /// Overwrites `samples` with the next stretch of scrambled audio.
+    pub fn fill(&mut self, samples: &mut [f32]) {
+        let step = SCRAMBLE_PULSE_HZ / SAMPLE_RATE as f32;
+        for sample in samples.iter_mut() {
+            let envelope = 0.6 + 0.4 * (2.0 * PI * self.phase).sin();
+            *sample = self.rng.next_bipolar() * SCRAMBLE_LEVEL * envelope;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+impl Default for Scrambler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vad::rms_db;
+
+    /// One second of a sine at `frequency` Hz.
+    fn sine(frequency: f32, amplitude: f32) -> Vec<f32> {
+        (0..SAMPLE_RATE)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// Gain in dB applied to a tone, measured after the filters settle.
+    fn gain_db(effect: RadioEffect, frequency: f32) -> f32 {
+        let input = sine(frequency, 0.1);
+        let mut output = input.clone();
+        RadioEffectProcessor::new(effect).process(&mut output);
+        let settled = SAMPLE_RATE as usize / 10;
+        rms_db(&output[settled..]) - rms_db(&input[settled..])
+    }
+
+    fn filter_only(low_cut: f32, high_cut: f32) -> RadioEffect {
+        RadioEffect {
+            low_cut,
+            high_cut,
+            ..RadioEffect::CLEAN
+        }
+    }
+
+    #[test]
+    fn test_band_pass_follows_cutoffs() {
+        let effect = filter_only(300.0, 3_000.0);
+        assert!(gain_db(effect, 1_000.0).abs() < 1.0);
+        assert!(gain_db(effect, 80.0) < -30.0);
+        assert!(gain_db(effect, 10_000.0) < -30.0);
+
+        // Quantum radios pass the full voice band untouched
+        assert!(gain_db(RadioEffect::for_radio_type(RadioTypes::Quantum), 150.0).abs() < 0.5);
+        assert!(gain_db(RadioEffect::for_radio_type(RadioTypes::Quantum), 8_000.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_channel_tuning_picks_the_band_effect() {
+        let mut config = RadioChannelConfig {
+            frequency_hz: 251_000_000,
+            modulation: Modulation::Am,
+            max_range_m: None,
+            crypto_key_id: None,
+        };
+        assert_eq!(
+            RadioEffect::for_channel(&config),
+            RadioEffect::for_radio_type(RadioTypes::Vhf)
+        );
+
+        config.frequency_hz = 8_992_000;
+        config.modulation = Modulation::Ssb;
+        assert_eq!(config.radio_type(), RadioTypes::Hf);
+        assert!(
+            RadioEffect::for_channel(&config).distortion
+                > RadioEffect::for_radio_type(RadioTypes::Hf).distortion
+        );
+    }
+
+    #[test]
+    fn test_distortion_soft_clips_peaks() {
+        let input = sine(1_000.0, 0.9);
+        let mut output = input.clone();
+        RadioEffectProcessor::new(RadioEffect {
+            distortion: 1.0,
+            ..RadioEffect::CLEAN
+        })
+        .process(&mut output);
+
+        let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= 1.0);
+        // Clipping squares off the wave, raising RMS relative to peak
+        let crest_in = 0.9 / 10f32.powf(rms_db(&input) / 20.0);
+        let crest_out = peak / 10f32.powf(rms_db(&output) / 20.0);
+        assert!(crest_out < crest_in * 0.85);
+    }
+
+    #[test]
+    fn test_noise_and_decay() {
+        let mut silence = vec![0.0; SAMPLE_RATE as usize];
+        RadioEffectProcessor::new(RadioEffect::CLEAN).process(&mut silence);
+        assert!(silence.iter().all(|&s| s == 0.0));
+
+        let mut hiss = vec![0.0; SAMPLE_RATE as usize];
+        RadioEffectProcessor::new(RadioEffect {
+            noise: 0.1,
+            ..RadioEffect::CLEAN
+        })
+        .process(&mut hiss);
+        assert!(rms_db(&hiss) > -30.0);
+
+        // Strong decay produces audible fades in a steady tone
+        let mut faded = sine(1_000.0, 0.5);
+        faded.extend(sine(1_000.0, 0.5));
+        faded.extend(sine(1_000.0, 0.5));
+        RadioEffectProcessor::with_seed(
+            RadioEffect {
+                decay: 1.0,
+                ..RadioEffect::CLEAN
+            },
+            7,
+        )
+        .process(&mut faded);
+        let quietest = faded.chunks(480).map(rms_db).fold(f32::INFINITY, f32::min);
+        assert!(quietest < rms_db(&sine(1_000.0, 0.5)) - 6.0);
+    }
+
+    #[test]
+    fn test_scrambler_pulses_static() {
+        let mut scrambled = vec![0.0; SAMPLE_RATE as usize];
+        Scrambler::new().fill(&mut scrambled);
+        assert!(scrambled.iter().all(|s| s.abs() <= SCRAMBLE_LEVEL));
+
+        // Loud enough to notice, and swelling with every pulse
+        assert!(rms_db(&scrambled) > -25.0);
+        let pulse = (SAMPLE_RATE as f32 / SCRAMBLE_PULSE_HZ) as usize;
+        let quarters: Vec<f32> = scrambled[..pulse].chunks(pulse / 4).map(rms_db).collect();
+        assert!(quarters[0] > quarters[2] + 3.0, "{quarters:?}");
+    }
+}
@@ -0,0 +1,738 @@
+//! Opus encoding of captured microphone audio into signed voice packets.
+//!
+//! Capture callbacks deliver buffers of arbitrary length; [`VoiceEncoder`]
+//! slices them into fixed Opus frames and stamps each packet with its
+//! sequence number, media timestamp and frame duration before signing it
+//! with the session UDP key.
+//!
+//! With DTX enabled, silent frames are not encoded. The first frame of a
+//! pause, and one every [`DTX_REFRESH_MS`] after, goes out as an empty DTX
+//! packet; the rest are skipped, their sequence numbers left as a gap the
+//! receivers play as silence.
+//!
+//! Encoders following a feedback source adapt to how well listeners hear
+//! them: every [`BitrateFeedback`] relayed by the server backs the bitrate
+//! off and raises forward error correction while packets are lost, and
+//! steps it back up towards the configured bitrate once they are not,
+//! always within the range the channel audio policies allow.
+//!
+//! For low-bandwidth links, consecutive frames can be batched into one
+//! packet with [`EncoderConfig::batch_frames`], trading latency for less
+//! per-packet overhead. A batch goes out once full, or early before a DTX
+//! pause or when [`VoiceEncoder::flush`] ends a transmission.
+
+use crate::vad::rms_db;
+use fleet_net_common::audio::ReceptionQuality;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::MIN_OPUS_BITRATE;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::packet::{
+    AudioPacket, PacketHeader, SpeakerPosition, DTX_REFRESH_MS, MAX_AUDIO_LENGTH, MAX_BATCH_FRAMES,
+};
+use std::borrow::Cow;
+use tokio::sync::{mpsc, watch};
+
+/// Sample rate of all voice audio on the wire.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Largest Opus frame the encoder may produce (RFC 6716, section 3.2.1).
+pub const MAX_OPUS_FRAME_SIZE: usize = 1275;
+
+/// Opus frame durations usable for voice, in milliseconds.
+const VALID_FRAME_DURATIONS: [u8; 4] = [10, 20, 40, 60];
+
+/// Frames quieter than this are silence to DTX, in dBFS.
+const DTX_SILENCE_DB: f32 = -60.0;
+
+/// Loss at which the bitrate backs off, in percent.
+const BACKOFF_LOSS_PERCENT: u8 = 5;
+
+/// Loss and jitter below which the bitrate steps back up.
+const CLEAN_LOSS_PERCENT: u8 = 1;
+const CLEAN_JITTER_MS: u16 = 40;
+
+/// Bitrate regained per clean feedback, in bits per second.
+const BITRATE_STEP: i32 = 4_000;
+
+/// Longest audio one batch may hold, in milliseconds. Like DTX packets,
+/// batches must follow each other closely enough for the server not to
+/// take the wait for the end of the transmission.
+const MAX_BATCH_MS: u32 = DTX_REFRESH_MS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderConfig {
+    /// Duration of each Opus frame in milliseconds: 10, 20, 40 or 60.
+    pub frame_duration_ms: u8,
+    /// Target bitrate in bits per second.
+    pub bitrate: i32,
+    /// Embed forward error correction so receivers can recover lost frames.
+    pub inband_fec: bool,
+    /// Expected packet loss in percent, tunes how much FEC is added.
+    pub expected_packet_loss: i32,
+    /// Discontinuous transmission: skip silent frames instead of sending them.
+    pub dtx: bool,
+    /// Frames batched into each packet; 1 sends every frame on its own.
+    pub batch_frames: u8,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            frame_duration_ms: 20,
+            bitrate: 32_000,
+            inband_fec: true,
+            expected_packet_loss: 5,
+            dtx: true,
+            batch_frames: 1,
+        }
+    }
+}
+
+impl EncoderConfig {
+    /// Mono samples per frame at [`SAMPLE_RATE`].
+    pub fn frame_size(&self) -> usize {
+        SAMPLE_RATE as usize / 1000 * usize::from(self.frame_duration_ms)
+    }
+
+    /// This configuration adapted to `feedback`, starting from `current`
+    /// and never above this configuration's own bitrate.
+    pub fn adapted(&self, current: &EncoderConfig, feedback: &BitrateFeedback) -> EncoderConfig {
+        let max = i32::try_from(feedback.max_bitrate).unwrap_or(i32::MAX);
+        let min = i32::try_from(feedback.min_bitrate.max(MIN_OPUS_BITRATE)).unwrap_or(max);
+        let ceiling = self.bitrate.min(max).max(min);
+        let loss = feedback.quality.loss_percent.min(100);
+
+        let bitrate = if loss >= BACKOFF_LOSS_PERCENT {
+            current.bitrate - current.bitrate / 4
+        } else if loss < CLEAN_LOSS_PERCENT && feedback.quality.jitter_ms < CLEAN_JITTER_MS {
+            current.bitrate + BITRATE_STEP
+        } else {
+            current.bitrate
+        };
+        EncoderConfig {
+            bitrate: bitrate.clamp(min.min(ceiling), ceiling),
+            inband_fec: self.inband_fec || loss > 0,
+            expected_packet_loss: i32::from(loss).max(self.expected_packet_loss),
+            ..*current
+        }
+    }
+
+    fn validate(&self) -> Result<(), FleetNetError> {
+        if !VALID_FRAME_DURATIONS.contains(&self.frame_duration_ms) {
+            return Err(FleetNetError::AudioError(Cow::Owned(format!(
+                "Unsupported Opus frame duration {}ms",
+                self.frame_duration_ms
+            ))));
+        }
+        let batch_ms = u32::from(self.frame_duration_ms) * u32::from(self.batch_frames);
+        if !(1..=MAX_BATCH_FRAMES).contains(&self.batch_frames) || batch_ms > MAX_BATCH_MS {
+            return Err(FleetNetError::AudioError(Cow::Owned(format!(
+                "Cannot batch {} frames of {}ms",
+                self.batch_frames, self.frame_duration_ms
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// How well a speaker's listeners hear them and the bitrates they may use,
+/// as relayed in [`ControlMessage::ReceptionFeedback`].
+///
+/// [`ControlMessage::ReceptionFeedback`]: fleet_net_protocol::message::ControlMessage::ReceptionFeedback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitrateFeedback {
+    pub quality: ReceptionQuality,
+    pub min_bitrate: u32,
+    pub max_bitrate: u32,
+}
+
+/// A codec turning one frame of mono PCM into a compressed frame.
+pub trait FrameEncoder: Send {
+    /// Encodes `pcm` into `output`, returning the number of bytes written.
+    fn encode_frame(&mut self, pcm: &[f32], output: &mut [u8]) -> Result<usize, FleetNetError>;
+
+    /// Applies the bitrate and FEC settings of `config`.
+    fn configure(&mut self, config: &EncoderConfig) -> Result<(), FleetNetError>;
+
+    /// Discards codec state at the end of a transmission.
+    fn reset(&mut self) -> Result<(), FleetNetError>;
+}
+
+impl FrameEncoder for opus::Encoder {
+    fn encode_frame(&mut self, pcm: &[f32], output: &mut [u8]) -> Result<usize, FleetNetError> {
+        self.encode_float(pcm, output).map_err(opus_error)
+    }
+
+    fn configure(&mut self, config: &EncoderConfig) -> Result<(), FleetNetError> {
+        self.set_bitrate(opus::Bitrate::Bits(config.bitrate))
+            .map_err(opus_error)?;
+        self.set_inband_fec(config.inband_fec).map_err(opus_error)?;
+        self.set_packet_loss_perc(config.expected_packet_loss)
+            .map_err(opus_error)
+    }
+
+    fn reset(&mut self) -> Result<(), FleetNetError> {
+        self.reset_state().map_err(opus_error)
+    }
+}
+
+fn opus_error(err: opus::Error) -> FleetNetError {
+    FleetNetError::AudioError(Cow::Owned(format!("Opus error: {err}")))
+}
+
+/// Creates a mono VoIP Opus encoder configured from `config`.
+pub fn new_opus_encoder(config: &EncoderConfig) -> Result<opus::Encoder, FleetNetError> {
+    let mut encoder =
+        opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Voip)
+            .map_err(opus_error)?;
+    encoder.configure(config)?;
+    Ok(encoder)
+}
+
+/// Packetizes captured audio for one session.
+pub struct VoiceEncoder<E: FrameEncoder = opus::Encoder> {
+    codec: E,
+    /// The configuration asked for, which feedback adapts from.
+    preferred: EncoderConfig,
+    config: EncoderConfig,
+    key: HmacKey,
+    user_id: UserId,
+    channel_id: ChannelId,
+    signal_strength: u8,
+    /// Where the speaker is, from game telemetry.
+    position: Option<watch::Receiver<Option<SpeakerPosition>>>,
+    /// Reception feedback relayed by the server.
+    feedback: Option<watch::Receiver<Option<BitrateFeedback>>>,
+    sequence: u16,
+    /// Media clock in milliseconds, advanced by one frame duration per packet.
+    timestamp: u32,
+    /// Milliseconds since the last DTX packet, while paused.
+    paused_ms: Option<u32>,
+    /// Encoded frames waiting for a batch to fill, with the header of the
+    /// first.
+    batch: Vec<Vec<u8>>,
+    batch_header: Option<PacketHeader>,
+    pending: Vec<f32>,
+    output: Vec<u8>,
+}
+
+impl VoiceEncoder {
+    /// Creates an Opus-backed encoder for `user_id` talking on `channel_id`.
+    pub fn new(
+        config: EncoderConfig,
+        key: HmacKey,
+        user_id: UserId,
+        channel_id: ChannelId,
+    ) -> Result<Self, FleetNetError> {
+        let codec = new_opus_encoder(&config)?;
+        Self::with_codec(codec, config, key, user_id, channel_id)
+    }
+}
+
+impl<E: FrameEncoder> VoiceEncoder<E> {
+    pub fn with_codec(
+        codec: E,
+        config: EncoderConfig,
+        key: HmacKey,
+        user_id: UserId,
+        channel_id: ChannelId,
+    ) -> Result<Self, FleetNetError> {
+        config.validate()?;
+        Ok(Self {
+            codec,
+            preferred: config,
+            config,
+            key,
+            user_id,
+            channel_id,
+            signal_strength: u8::MAX,
+            position: None,
+            feedback: None,
+            sequence: 0,
+            timestamp: 0,
+            paused_ms: None,
+            batch: Vec::with_capacity(config.batch_frames.into()),
+            batch_header: None,
+            pending: Vec::with_capacity(config.frame_size() * 2),
+            output: vec![0; MAX_OPUS_FRAME_SIZE],
+        })
+    }
+
+    /// The configuration in effect, as adapted to the latest feedback.
+    pub fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    /// Routes subsequent packets to `channel_id`.
+    pub fn set_channel(&mut self, channel_id: ChannelId) {
+        self.channel_id = channel_id;
+    }
+
+    pub fn set_signal_strength(&mut self, signal_strength: u8) {
+        self.signal_strength = signal_strength;
+    }
+
+    /// Attaches the latest position published on `positions` to every
+    /// subsequent packet, or none while it is `None`.
+    pub fn set_position_source(&mut self, positions: watch::Receiver<Option<SpeakerPosition>>) {
+        self.position = Some(positions);
+    }
+
+    /// Adapts the bitrate and FEC to each feedback published on `feedback`.
+    pub fn set_feedback_source(&mut self, feedback: watch::Receiver<Option<BitrateFeedback>>) {
+        self.feedback = Some(feedback);
+    }
+
+    /// Adapts the codec to `feedback` from the server.
+    pub fn adapt(&mut self, feedback: &BitrateFeedback) -> Result<(), FleetNetError> {
+        let adapted = self.preferred.adapted(&self.config, feedback);
+        if adapted != self.config {
+            self.codec.configure(&adapted)?;
+            self.config = adapted;
+        }
+        Ok(())
+    }
+
+    /// Buffers captured mono samples and returns a packet for every complete frame.
+    pub fn push_samples(&mut self, samples: &[f32]) -> Result<Vec<AudioPacket>, FleetNetError> {
+        let feedback = self
+            .feedback
+            .as_mut()
+            .filter(|feedback| feedback.has_changed().unwrap_or(false))
+            .and_then(|feedback| *feedback.borrow_and_update());
+        if let Some(feedback) = feedback {
+            self.adapt(&feedback)?;
+        }
+        self.pending.extend_from_slice(samples);
+
+        let frame_size = self.config.frame_size();
+        let mut packets = Vec::with_capacity(self.pending.len() / frame_size);
+        let mut offset = 0;
+        while self.pending.len() - offset >= frame_size {
+            let frame = &self.pending[offset..offset + frame_size];
+            offset += frame_size;
+            if self.config.dtx && rms_db(frame) < DTX_SILENCE_DB {
+                packets.extend(self.flush()?);
+                packets.extend(self.pause());
+                continue;
+            }
+            let len = self.codec.encode_frame(frame, &mut self.output)?;
+            self.paused_ms = None;
+            self.packetize(len, &mut packets)?;
+        }
+        self.pending.drain(..offset);
+
+        Ok(packets)
+    }
+
+    /// Sends the frames waiting for a batch to fill, if any.
+    pub fn flush(&mut self) -> Result<Option<AudioPacket>, FleetNetError> {
+        let Some(header) = self.batch_header.take() else {
+            return Ok(None);
+        };
+        let position = self.current_position();
+        let packet = match self.batch.len() {
+            1 => AudioPacket::new_signed_at(header, self.batch.remove(0), position, &self.key),
+            _ => AudioPacket::new_batch_signed(header, &self.batch, position, &self.key)?,
+        };
+        self.batch.clear();
+        Ok(Some(packet))
+    }
+
+    /// A bare header without audio, which registers the address voice for
+    /// this session is sent back to before it speaks.
+    pub fn registration(&mut self) -> AudioPacket {
+        let header = self.next_header();
+        AudioPacket::new_signed(header, Vec::new(), &self.key)
+    }
+
+    /// Ends a transmission, dropping any partial frame and resetting codec
+    /// state. Frames waiting for a batch are dropped too; [`Self::flush`]
+    /// them first.
+    ///
+    /// Sequence numbers and timestamps keep counting so receivers can tell
+    /// transmissions apart.
+    pub fn reset(&mut self) -> Result<(), FleetNetError> {
+        self.pending.clear();
+        self.paused_ms = None;
+        self.batch.clear();
+        self.batch_header = None;
+        self.codec.reset()
+    }
+
+    /// Skips a silent frame, returning a DTX packet for the first frame of
+    /// a pause and then one every [`DTX_REFRESH_MS`].
+    fn pause(&mut self) -> Option<AudioPacket> {
+        let frame_ms = u32::from(self.config.frame_duration_ms);
+        let due = match self.paused_ms {
+            Some(paused_ms) if paused_ms + frame_ms < DTX_REFRESH_MS => {
+                self.paused_ms = Some(paused_ms + frame_ms);
+                false
+            }
+            _ => {
+                self.paused_ms = Some(0);
+                true
+            }
+        };
+        let header = self.next_header();
+        due.then(|| AudioPacket::new_dtx(header, &self.key))
+    }
+
+    /// Packetizes the `len` bytes just encoded into `packets`, or batches
+    /// them until the batch is full.
+    fn packetize(
+        &mut self,
+        len: usize,
+        packets: &mut Vec<AudioPacket>,
+    ) -> Result<(), FleetNetError> {
+        let header = self.next_header();
+        let frame = self.output[..len].to_vec();
+        if self.config.batch_frames <= 1 {
+            let position = self.current_position();
+            packets.push(AudioPacket::new_signed_at(
+                header, frame, position, &self.key,
+            ));
+            return Ok(());
+        }
+
+        // Each frame takes its length in the extension header too
+        let batched: usize = self.batch.iter().map(|frame| frame.len() + 2).sum();
+        if batched + len + 2 > usize::from(MAX_AUDIO_LENGTH) {
+            packets.extend(self.flush()?);
+        }
+        self.batch_header.get_or_insert(header);
+        self.batch.push(frame);
+        if self.batch.len() >= usize::from(self.config.batch_frames) {
+            packets.extend(self.flush()?);
+        }
+        Ok(())
+    }
+
+    fn current_position(&self) -> Option<SpeakerPosition> {
+        self.position
+            .as_ref()
+            .and_then(|positions| *positions.borrow())
+    }
+
+    /// The header of the next frame, advancing the sequence and media clock.
+    fn next_header(&mut self) -> PacketHeader {
+        let header = PacketHeader {
+            channel_id: self.channel_id,
+            user_id: self.user_id,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            signal_strength: self.signal_strength,
+            frame_duration: self.config.frame_duration_ms,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self
+            .timestamp
+            .wrapping_add(u32::from(self.config.frame_duration_ms));
+        header
+    }
+}
+
+/// Encodes captured buffers from `frames` until the capture side hangs up,
+/// forwarding finished packets to `packets` for the voice socket.
+///
+/// An empty buffer marks the end of a transmission.
+pub async fn run_encoder<E: FrameEncoder>(
+    mut encoder: VoiceEncoder<E>,
+    mut frames: mpsc::Receiver<Vec<f32>>,
+    packets: mpsc::Sender<AudioPacket>,
+) -> Result<(), FleetNetError> {
+    while let Some(samples) = frames.recv().await {
+        if samples.is_empty() {
+            let last = encoder.flush()?;
+            encoder.reset()?;
+            if let Some(packet) = last {
+                if packets.send(packet).await.is_err() {
+                    return Ok(());
+                }
+            }
+            continue;
+        }
+
+        for packet in encoder.push_samples(&samples)? {
+            if packets.send(packet).await.is_err() {
+                // Voice socket closed; nothing left to encode for.
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    /// Stands in for Opus: emits the frame length and first sample.
+    struct FakeCodec;
+
+    impl FrameEncoder for FakeCodec {
+        fn encode_frame(&mut self, pcm: &[f32], output: &mut [u8]) -> Result<usize, FleetNetError> {
+            output[..2].copy_from_slice(&(pcm.len() as u16).to_be_bytes());
+            output[2] = (pcm[0] * 100.0) as u8;
+            Ok(3)
+        }
+
+        fn configure(&mut self, _config: &EncoderConfig) -> Result<(), FleetNetError> {
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<(), FleetNetError> {
+            Ok(())
+        }
+    }
+
+    fn test_key() -> HmacKey {
+        HmacKey::from_bytes(b"test_session_key_32_bytes_long!!")
+    }
+
+    fn test_encoder() -> VoiceEncoder<FakeCodec> {
+        VoiceEncoder::with_codec(
+            FakeCodec,
+            EncoderConfig::default(),
+            test_key(),
+            user(7),
+            channel(3),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_packets_are_sequenced_and_signed() {
+        let mut encoder = test_encoder();
+        let frame_size = encoder.config().frame_size();
+        assert_eq!(frame_size, 960);
+
+        // Two and a half frames yield two packets; the rest is held back
+        let packets = encoder
+            .push_samples(&vec![0.5; frame_size * 5 / 2])
+            .unwrap();
+        assert_eq!(packets.len(), 2);
+        let packets_after = encoder.push_samples(&vec![0.1; frame_size / 2]).unwrap();
+        assert_eq!(packets_after.len(), 1);
+
+        let key = test_key();
+        for (index, packet) in packets.iter().chain(&packets_after).enumerate() {
+            let header = packet.header;
+            assert_eq!(header.sequence, index as u16);
+            assert_eq!(header.timestamp, index as u32 * 20);
+            assert_eq!(header.frame_duration, 20);
+            assert_eq!((header.user_id, header.channel_id), (user(7), channel(3)));
+            assert!(header.validate_hmac(&key, &packet.opus_payload));
+            assert_eq!(&packet.opus_payload[..2], &960u16.to_be_bytes());
+        }
+        // The third frame starts with the leftover samples of the first buffer
+        assert_eq!(packets_after[0].opus_payload[2], 50);
+    }
+
+    #[test]
+    fn test_registration_is_a_signed_bare_header_in_sequence() {
+        let mut encoder = test_encoder();
+        let registration = encoder.registration();
+        assert!(registration.opus_payload.is_empty());
+        assert!(registration.validate_hmac(&test_key()));
+        assert_eq!(registration.to_bytes().len(), PacketHeader::SIZE);
+
+        let frame = vec![0.5; encoder.config().frame_size()];
+        let packets = encoder.push_samples(&frame).unwrap();
+        assert_eq!(packets[0].header.sequence, registration.header.sequence + 1);
+    }
+
+    #[test]
+    fn test_packets_carry_the_latest_position() {
+        let mut encoder = test_encoder();
+        let frame = vec![0.5; encoder.config().frame_size()];
+        let (positions, receiver) = watch::channel(None);
+        encoder.set_position_source(receiver);
+
+        assert_eq!(encoder.push_samples(&frame).unwrap()[0].position, None);
+
+        let position = SpeakerPosition {
+            x: 10.0,
+            y: -20.0,
+            z: 300.0,
+            yaw: 90.0,
+            pitch: 0.0,
+        };
+        positions.send_replace(Some(position));
+        let packet = encoder.push_samples(&frame).unwrap().remove(0);
+        assert_eq!(packet.position, Some(position));
+        assert!(packet.validate_hmac(&test_key()));
+    }
+
+    #[test]
+    fn test_silence_is_skipped_with_periodic_dtx_packets() {
+        let mut encoder = test_encoder();
+        let frame_size = encoder.config().frame_size();
+        let speech = vec![0.5; frame_size];
+        let silence = vec![0.0; frame_size * 30];
+
+        let mut packets = encoder.push_samples(&speech).unwrap();
+        packets.extend(encoder.push_samples(&silence).unwrap());
+        packets.extend(encoder.push_samples(&speech).unwrap());
+
+        // The pause opens with a DTX packet and refreshes it every 400ms
+        let sent: Vec<_> = packets
+            .iter()
+            .map(|packet| (packet.header.sequence, packet.header.dtx))
+            .collect();
+        assert_eq!(sent, [(0, false), (1, true), (21, true), (31, false)]);
+        for packet in packets.iter().filter(|packet| packet.header.dtx) {
+            assert!(packet.opus_payload.is_empty());
+            assert!(packet.validate_hmac(&test_key()));
+        }
+        assert_eq!(packets[3].header.timestamp, 31 * 20);
+
+        // Without DTX every frame is encoded
+        let config = EncoderConfig {
+            dtx: false,
+            ..EncoderConfig::default()
+        };
+        let mut encoder =
+            VoiceEncoder::with_codec(FakeCodec, config, test_key(), user(7), channel(3)).unwrap();
+        let packets = encoder.push_samples(&silence).unwrap();
+        assert_eq!(packets.len(), 30);
+        assert!(packets.iter().all(|packet| !packet.header.dtx));
+    }
+
+    #[test]
+    fn test_frames_are_batched_until_full_or_flushed() {
+        let config = EncoderConfig {
+            batch_frames: 3,
+            ..EncoderConfig::default()
+        };
+        let mut encoder =
+            VoiceEncoder::with_codec(FakeCodec, config, test_key(), user(7), channel(3)).unwrap();
+        let frame_size = encoder.config().frame_size();
+
+        // Two frames wait for the third, then go out together
+        assert!(encoder
+            .push_samples(&vec![0.5; frame_size * 2])
+            .unwrap()
+            .is_empty());
+        let packets = encoder.push_samples(&vec![0.1; frame_size]).unwrap();
+        assert_eq!(packets.len(), 1);
+        let batch = packets[0].clone();
+        assert_eq!((batch.header.sequence, batch.header.frames), (0, 3));
+        assert_eq!(batch.header.duration_ms(), 60);
+        assert!(batch.validate_hmac(&test_key()));
+        let frames = batch.into_frames().unwrap();
+        let sent: Vec<_> = frames
+            .iter()
+            .map(|frame| (frame.header.sequence, frame.opus_payload[2]))
+            .collect();
+        assert_eq!(sent, [(0, 50), (1, 50), (2, 10)]);
+
+        // A pause or the end of a transmission sends what is left
+        encoder.push_samples(&vec![0.5; frame_size]).unwrap();
+        let packets = encoder.push_samples(&vec![0.0; frame_size]).unwrap();
+        let sent: Vec<_> = packets
+            .iter()
+            .map(|packet| {
+                (
+                    packet.header.sequence,
+                    packet.header.frames,
+                    packet.header.dtx,
+                )
+            })
+            .collect();
+        assert_eq!(sent, [(3, 1, false), (4, 1, true)]);
+        encoder.push_samples(&vec![0.5; frame_size * 2]).unwrap();
+        let last = encoder.flush().unwrap().expect("two frames are waiting");
+        assert_eq!((last.header.sequence, last.header.frames), (5, 2));
+        assert!(encoder.flush().unwrap().is_none());
+
+        let config = EncoderConfig {
+            frame_duration_ms: 60,
+            batch_frames: 8,
+            ..EncoderConfig::default()
+        };
+        assert!(
+            VoiceEncoder::with_codec(FakeCodec, config, test_key(), user(7), channel(3)).is_err()
+        );
+    }
+
+    #[test]
+    fn test_bitrate_adapts_to_feedback_within_the_policy() {
+        let mut encoder = test_encoder();
+        let (feedback, receiver) = watch::channel(None);
+        encoder.set_feedback_source(receiver);
+        let frame = vec![0.5; encoder.config().frame_size()];
+        let report = |loss_percent, min_bitrate, max_bitrate| BitrateFeedback {
+            quality: ReceptionQuality {
+                loss_percent,
+                jitter_ms: 10,
+            },
+            min_bitrate,
+            max_bitrate,
+        };
+
+        // Loss backs off and asks for more FEC
+        feedback.send_replace(Some(report(20, 6_000, 64_000)));
+        encoder.push_samples(&frame).unwrap();
+        assert_eq!(encoder.config().bitrate, 24_000);
+        assert_eq!(encoder.config().expected_packet_loss, 20);
+        assert!(encoder.config().inband_fec);
+
+        // Feedback counts once, however many frames follow
+        encoder.push_samples(&frame).unwrap();
+        assert_eq!(encoder.config().bitrate, 24_000);
+
+        // Clean reception climbs back, but not past the preferred bitrate
+        for _ in 0..5 {
+            feedback.send_replace(Some(report(0, 6_000, 64_000)));
+            encoder.push_samples(&frame).unwrap();
+        }
+        assert_eq!(encoder.config().bitrate, 32_000);
+        assert_eq!(encoder.config().expected_packet_loss, 5);
+
+        // The channel policy wins over both
+        encoder.adapt(&report(0, 6_000, 16_000)).unwrap();
+        assert_eq!(encoder.config().bitrate, 16_000);
+        for _ in 0..10 {
+            encoder.adapt(&report(50, 12_000, 16_000)).unwrap();
+        }
+        assert_eq!(encoder.config().bitrate, 12_000);
+    }
+
+    #[test]
+    fn test_reset_drops_partial_frame_and_rejects_bad_duration() {
+        let mut encoder = test_encoder();
+        let frame_size = encoder.config().frame_size();
+
+        encoder.push_samples(&vec![0.5; frame_size / 2]).unwrap();
+        encoder.reset().unwrap();
+        encoder.set_channel(channel(4));
+        let packets = encoder.push_samples(&vec![0.1; frame_size]).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].opus_payload[2], 10);
+        assert_eq!(packets[0].header.channel_id, channel(4));
+
+        let config = EncoderConfig {
+            frame_duration_ms: 15,
+            ..EncoderConfig::default()
+        };
+        assert!(
+            VoiceEncoder::with_codec(FakeCodec, config, test_key(), user(7), channel(3)).is_err()
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! Per-speaker jitter buffer.
+//!
+//! Packets are held until `target_depth` frames are queued, then released in
+//! sequence order at the playback rate. Gaps are reported so the decoder can
+//! conceal them, and an empty buffer puts the stream back into buffering.
+//! After a DTX packet the sender skips packets on purpose, so gaps for up
+//! to [`DTX_REFRESH_MS`] are played as silence instead.
+//! Interarrival jitter is estimated as in RTP (RFC 3550) for diagnostics.
+//!
+//! Sequence numbers skipped by an arriving packet are tracked as missing,
+//! outside DTX pauses, so they can be asked for again on channels that
+//! allow retransmission; see [`JitterBuffer::take_missing`]. Duplicates,
+//! e.g. a retransmission racing the original, are dropped and counted.
+
+use fleet_net_protocol::packet::{AudioPacket, DTX_REFRESH_MS};
+use std::collections::HashMap;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitterConfig {
+    /// Frames queued before playback starts or resumes after an underrun.
+    pub target_depth: usize,
+    /// Packets further ahead than this resynchronize the buffer.
+    pub max_depth: usize,
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        Self {
+            target_depth: 3,
+            max_depth: 25,
+        }
+    }
+}
+
+/// Counters for a single speaker's stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JitterStats {
+    pub received: u64,
+    /// Frames that never arrived in time and were concealed.
+    pub lost: u64,
+    /// Packets that arrived after their slot had been played.
+    pub late: u64,
+    /// Packets that arrived a second time while still queued.
+    pub duplicates: u64,
+    /// Missing packets handed out by [`JitterBuffer::take_missing`].
+    pub requested: u64,
+    /// Requested packets that arrived in time to be played.
+    pub recovered: u64,
+    pub underruns: u64,
+    /// Smoothed variation in packet transit time, in milliseconds.
+    pub jitter_ms: f64,
+}
+
+/// What the playback side should do for the next frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JitterOutput {
+    /// Still filling; play silence.
+    Buffering,
+    /// The next packet in sequence.
+    Frame(AudioPacket),
+    /// The next packet is missing; `fec` holds the following packet's payload
+    /// when it has already arrived, so its embedded FEC data can recover the frame.
+    Missing { fec: Option<Vec<u8>> },
+    /// The buffer ran dry and is buffering again.
+    Underrun,
+    /// The sender is pausing in discontinuous transmission; play silence.
+    Silence,
+}
+
+#[derive(Debug)]
+pub struct JitterBuffer {
+    config: JitterConfig,
+    packets: HashMap<u16, AudioPacket>,
+    next_sequence: Option<u16>,
+    buffering: bool,
+    /// Gaps left to play as silence after a DTX packet.
+    pause_frames: usize,
+    /// Highest sequence received, and whether that packet was DTX.
+    highest: Option<(u16, bool)>,
+    /// Sequences skipped by later packets and not yet requested.
+    missing: Vec<u16>,
+    /// Sequences requested and not yet arrived.
+    requested: Vec<u16>,
+    stats: JitterStats,
+    /// First arrival, the reference point for transit times.
+    epoch: Option<Instant>,
+    /// Arrival minus send timestamp of the previous packet, in milliseconds.
+    last_transit_ms: Option<f64>,
+}
+
+impl JitterBuffer {
+    pub fn new(config: JitterConfig) -> Self {
+        Self {
+            config,
+            packets: HashMap::with_capacity(config.max_depth),
+            next_sequence: None,
+            buffering: true,
+            pause_frames: 0,
+            highest: None,
+            missing: Vec::new(),
+            requested: Vec::new(),
+            stats: JitterStats::default(),
+            epoch: None,
+            last_transit_ms: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn is_buffering(&self) -> bool {
+        self.buffering
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    pub fn push(&mut self, packet: AudioPacket) {
+        self.push_at(packet, Instant::now());
+    }
+
+    /// Queues a packet that arrived at `arrival`.
+    pub fn push_at(&mut self, packet: AudioPacket, arrival: Instant) {
+        let sequence = packet.header.sequence;
+        if self.packets.contains_key(&sequence) {
+            self.stats.duplicates += 1;
+            return;
+        }
+        self.stats.received += 1;
+        self.update_jitter(packet.header.timestamp, arrival);
+        let requested = match self.requested.iter().position(|&s| s == sequence) {
+            Some(index) => {
+                self.requested.swap_remove(index);
+                true
+            }
+            None => false,
+        };
+
+        match self.next_sequence {
+            None => self.next_sequence = Some(sequence),
+            Some(next) => {
+                let offset = sequence.wrapping_sub(next) as i16;
+                if offset < 0 {
+                    if self.buffering || self.pause_frames > 0 {
+                        // Nothing played yet, or the sender ended its pause
+                        // earlier than our clock; start from this packet.
+                        self.next_sequence = Some(sequence);
+                    } else {
+                        self.stats.late += 1;
+                        return;
+                    }
+                } else if offset as usize > self.config.max_depth {
+                    // The sender restarted or we fell far behind; resync.
+                    self.packets.clear();
+                    self.missing.clear();
+                    self.requested.clear();
+                    self.highest = None;
+                    self.buffering = true;
+                    self.next_sequence = Some(sequence);
+                }
+            }
+        }
+
+        if requested {
+            self.stats.recovered += 1;
+        }
+        self.track_gaps(sequence, packet.header.dtx);
+        self.packets.insert(sequence, packet);
+    }
+
+    /// Notes the sequences between the highest so far and `sequence` as
+    /// missing, unless the sender skipped them in a DTX pause.
+    fn track_gaps(&mut self, sequence: u16, dtx: bool) {
+        if let Some((highest, paused)) = self.highest {
+            let ahead = sequence.wrapping_sub(highest) as i16;
+            if ahead <= 0 {
+                self.missing.retain(|&missing| missing != sequence);
+                return;
+            }
+            if !paused {
+                let skipped = (ahead as u16 - 1).min(self.config.max_depth as u16);
+                self.missing
+                    .extend((1..=skipped).map(|gap| highest.wrapping_add(gap)));
+            }
+        }
+        self.highest = Some((sequence, dtx));
+    }
+
+    /// Sequences found missing since the last call that have not been
+    /// played yet, oldest first, e.g. to ask the server to send them
+    /// again. Each is handed out once.
+    pub fn take_missing(&mut self) -> Vec<u16> {
+        let Some(next) = self.next_sequence else {
+            self.missing.clear();
+            self.requested.clear();
+            return Vec::new();
+        };
+        let pending = |sequence: &u16| sequence.wrapping_sub(next) as i16 >= 0;
+        self.requested.retain(pending);
+        let mut missing: Vec<u16> = self.missing.drain(..).filter(pending).collect();
+        missing.sort_by_key(|&sequence| sequence.wrapping_sub(next));
+        self.stats.requested += missing.len() as u64;
+        self.requested.extend(&missing);
+        missing
+    }
+
+    fn update_jitter(&mut self, timestamp_ms: u32, arrival: Instant) {
+        let epoch = *self.epoch.get_or_insert(arrival);
+        let arrival_ms = arrival.saturating_duration_since(epoch).as_secs_f64() * 1000.0;
+        let transit = arrival_ms - f64::from(timestamp_ms);
+        if let Some(last) = self.last_transit_ms.replace(transit) {
+            let delta = (transit - last).abs();
+            self.stats.jitter_ms += (delta - self.stats.jitter_ms) / 16.0;
+        }
+    }
+
+    /// Returns the next frame to play. Call once per frame duration.
+    pub fn pop(&mut self) -> JitterOutput {
+        let Some(next) = self.next_sequence else {
+            return JitterOutput::Buffering;
+        };
+
+        if self.buffering {
+            if self.packets.len() < self.config.target_depth {
+                return JitterOutput::Buffering;
+            }
+            self.buffering = false;
+        }
+
+        if let Some(packet) = self.packets.remove(&next) {
+            self.next_sequence = Some(next.wrapping_add(1));
+            self.pause_frames = if packet.header.dtx {
+                let frame_ms = u32::from(packet.header.frame_duration).max(1);
+                (DTX_REFRESH_MS / frame_ms) as usize + self.config.target_depth
+            } else {
+                0
+            };
+            return JitterOutput::Frame(packet);
+        }
+
+        if self.pause_frames > 0 {
+            self.pause_frames -= 1;
+            self.next_sequence = Some(next.wrapping_add(1));
+            return JitterOutput::Silence;
+        }
+
+        if self.packets.is_empty() {
+            self.stats.underruns += 1;
+            self.buffering = true;
+            self.next_sequence = None;
+            return JitterOutput::Underrun;
+        }
+
+        self.stats.lost += 1;
+        let following = next.wrapping_add(1);
+        self.next_sequence = Some(following);
+        JitterOutput::Missing {
+            fec: self
+                .packets
+                .get(&following)
+                .map(|packet| packet.opus_payload.clone()),
+        }
+    }
+
+    /// Drops all queued packets, e.g. when the speaker stops transmitting.
+    pub fn reset(&mut self) {
+        self.packets.clear();
+        self.next_sequence = None;
+        self.buffering = true;
+        self.pause_frames = 0;
+        self.highest = None;
+        self.missing.clear();
+        self.requested.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::types::{ChannelId, UserId};
+    use fleet_net_protocol::packet::PacketHeader;
+    use std::time::Duration;
+
+    fn packet(sequence: u16) -> AudioPacket {
+        AudioPacket {
+            header: PacketHeader {
+                channel_id: ChannelId::new(1).unwrap(),
+                user_id: UserId::new(2).unwrap(),
+                sequence,
+                timestamp: u32::from(sequence) * 20,
+                signal_strength: 255,
+                frame_duration: 20,
+                dtx: false,
+                frames: 1,
+                padded: false,
+                audio_length: 1,
+                hmac_prefix: 0,
+            },
+            opus_payload: vec![sequence as u8],
+            position: None,
+        }
+    }
+
+    fn dtx(sequence: u16) -> AudioPacket {
+        let mut packet = packet(sequence);
+        packet.header.dtx = true;
+        packet.header.audio_length = 0;
+        packet.opus_payload.clear();
+        packet
+    }
+
+    fn sequence_of(output: JitterOutput) -> Option<u16> {
+        match output {
+            JitterOutput::Frame(packet) => Some(packet.header.sequence),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_reorders_and_conceals_gaps() {
+        let mut buffer = JitterBuffer::new(JitterConfig::default());
+
+        // Arrives out of order across the u16 wrap, with 1 missing
+        buffer.push(packet(u16::MAX));
+        assert_eq!(buffer.pop(), JitterOutput::Buffering);
+        buffer.push(packet(2));
+        buffer.push(packet(0));
+        buffer.push(packet(u16::MAX - 1));
+
+        assert_eq!(sequence_of(buffer.pop()), Some(u16::MAX - 1));
+        assert_eq!(sequence_of(buffer.pop()), Some(u16::MAX));
+        assert_eq!(sequence_of(buffer.pop()), Some(0));
+        assert_eq!(buffer.pop(), JitterOutput::Missing { fec: Some(vec![2]) });
+
+        // The missing packet shows up after its slot was concealed
+        buffer.push(packet(1));
+        assert_eq!(sequence_of(buffer.pop()), Some(2));
+
+        let stats = buffer.stats();
+        assert_eq!((stats.lost, stats.late), (1, 1));
+    }
+
+    #[test]
+    fn test_jitter_tracks_arrival_variation() {
+        let start = Instant::now();
+        let mut steady = JitterBuffer::new(JitterConfig::default());
+        let mut bursty = JitterBuffer::new(JitterConfig::default());
+        for sequence in 0..50u16 {
+            let sent = Duration::from_millis(u64::from(sequence) * 20);
+            steady.push_at(packet(sequence), start + sent);
+            // Every other packet is held up by 10ms
+            let delay = Duration::from_millis(u64::from(sequence % 2) * 10);
+            bursty.push_at(packet(sequence), start + sent + delay);
+        }
+
+        assert!(steady.stats().jitter_ms < 1e-9);
+        let jitter = bursty.stats().jitter_ms;
+        assert!((9.0..=10.0).contains(&jitter), "jitter {jitter}");
+    }
+
+    #[test]
+    fn test_underrun_rebuffers() {
+        let mut buffer = JitterBuffer::new(JitterConfig::default());
+        for sequence in 10..13 {
+            buffer.push(packet(sequence));
+        }
+        for _ in 0..3 {
+            assert!(sequence_of(buffer.pop()).is_some());
+        }
+        assert_eq!(buffer.pop(), JitterOutput::Underrun);
+        assert_eq!(buffer.stats().underruns, 1);
+
+        // Playback waits for the target depth again
+        buffer.push(packet(13));
+        assert_eq!(buffer.pop(), JitterOutput::Buffering);
+        buffer.push(packet(14));
+        buffer.push(packet(15));
+        assert_eq!(sequence_of(buffer.pop()), Some(13));
+
+        // A large jump resynchronizes instead of concealing hundreds of frames
+        buffer.push(packet(500));
+        assert!(buffer.is_buffering());
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn test_dtx_gaps_play_as_silence() {
+        let mut buffer = JitterBuffer::new(JitterConfig::default());
+        buffer.push(packet(0));
+        buffer.push(packet(1));
+        buffer.push(dtx(2));
+        for sequence in 0..3 {
+            assert_eq!(sequence_of(buffer.pop()), Some(sequence));
+        }
+
+        // The skipped frames are neither lost nor an underrun
+        for _ in 3..23 {
+            assert_eq!(buffer.pop(), JitterOutput::Silence);
+        }
+        buffer.push(dtx(22));
+        buffer.push(packet(30));
+        buffer.push(packet(31));
+        assert_eq!(sequence_of(buffer.pop()), Some(22));
+        for _ in 23..30 {
+            assert_eq!(buffer.pop(), JitterOutput::Silence);
+        }
+        assert_eq!(sequence_of(buffer.pop()), Some(30));
+        assert_eq!(sequence_of(buffer.pop()), Some(31));
+
+        // Speech conceals gaps again, and a pause ends if nothing follows
+        buffer.push(packet(33));
+        assert!(matches!(buffer.pop(), JitterOutput::Missing { .. }));
+        assert_eq!(sequence_of(buffer.pop()), Some(33));
+        buffer.push(dtx(34));
+        assert_eq!(sequence_of(buffer.pop()), Some(34));
+        for _ in 0..23 {
+            assert_eq!(buffer.pop(), JitterOutput::Silence);
+        }
+        assert_eq!(buffer.pop(), JitterOutput::Underrun);
+
+        let stats = buffer.stats();
+        assert_eq!((stats.lost, stats.late, stats.underruns), (1, 0, 1));
+    }
+
+    #[test]
+    fn test_missing_sequences_are_requested_once() {
+        let mut buffer = JitterBuffer::new(JitterConfig::default());
+        for sequence in [0, 1, 4] {
+            buffer.push(packet(sequence));
+        }
+        assert_eq!(buffer.take_missing(), vec![2, 3]);
+        assert!(buffer.take_missing().is_empty());
+
+        // 3 is sent again in time and races its original; 2 never shows
+        buffer.push(packet(3));
+        buffer.push(packet(3));
+        assert_eq!(sequence_of(buffer.pop()), Some(0));
+        assert_eq!(sequence_of(buffer.pop()), Some(1));
+        assert!(matches!(buffer.pop(), JitterOutput::Missing { .. }));
+        assert_eq!(sequence_of(buffer.pop()), Some(3));
+        buffer.push(packet(2));
+
+        // Packets skipped in a DTX pause are not missing
+        buffer.push(dtx(5));
+        buffer.push(packet(9));
+        buffer.push(packet(11));
+        assert_eq!(buffer.take_missing(), vec![10]);
+
+        let stats = buffer.stats();
+        assert_eq!(
+            (stats.requested, stats.recovered, stats.duplicates),
+            (3, 1, 1)
+        );
+        assert_eq!((stats.lost, stats.late), (1, 1));
+    }
+}
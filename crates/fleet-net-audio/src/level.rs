@@ -0,0 +1,125 @@
+//! RMS and peak level metering for the UI.
+//!
+//! Meters are written from the audio threads and read at a much lower rate
+//! by whoever draws them, so a [`LevelMeter`] keeps the loudest level seen
+//! since it was last read instead of the most recent one, ensuring short
+//! peaks are never missed.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Level reported for digital silence, in dBFS.
+pub const SILENCE_DB: f32 = -96.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AudioLevel {
+    /// Root mean square level in dBFS.
+    pub rms_db: f32,
+    /// Highest absolute sample in dBFS.
+    pub peak_db: f32,
+}
+
+impl AudioLevel {
+    pub const SILENT: Self = Self {
+        rms_db: SILENCE_DB,
+        peak_db: SILENCE_DB,
+    };
+
+    pub fn measure(samples: &[f32]) -> Self {
+        let (rms, peak) = linear_levels(samples);
+        Self::from_linear(rms, peak)
+    }
+
+    fn from_linear(rms: f32, peak: f32) -> Self {
+        Self {
+            rms_db: amplitude_db(rms),
+            peak_db: amplitude_db(peak),
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.peak_db <= SILENCE_DB
+    }
+}
+
+impl Default for AudioLevel {
+    fn default() -> Self {
+        Self::SILENT
+    }
+}
+
+/// Loudest level recorded since the meter was last read.
+#[derive(Debug, Default)]
+pub struct LevelMeter {
+    // Linear amplitudes as f32 bits; non-negative floats order like their bits.
+    rms: AtomicU32,
+    peak: AtomicU32,
+}
+
+impl LevelMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, samples: &[f32]) {
+        let (rms, peak) = linear_levels(samples);
+        self.rms.fetch_max(rms.to_bits(), Ordering::Relaxed);
+        self.peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Returns the loudest level since the last call and starts over.
+    pub fn take(&self) -> AudioLevel {
+        let rms = f32::from_bits(self.rms.swap(0, Ordering::Relaxed));
+        let peak = f32::from_bits(self.peak.swap(0, Ordering::Relaxed));
+        AudioLevel::from_linear(rms, peak)
+    }
+}
+
+/// RMS and peak amplitude of `samples`, both non-negative.
+fn linear_levels(samples: &[f32]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    // NaN samples from a misbehaving device must not poison the meter.
+    let sanitize = |value: f32| if value.is_finite() { value } else { 0.0 };
+    (sanitize(mean_square.sqrt()), sanitize(peak))
+}
+
+fn amplitude_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return SILENCE_DB;
+    }
+    (20.0 * amplitude.log10()).max(SILENCE_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_square_wave() {
+        let level = AudioLevel::measure(&[0.5, -0.5, 0.5, -0.5]);
+        assert!((level.rms_db - -6.0206).abs() < 1e-3);
+        assert!((level.peak_db - -6.0206).abs() < 1e-3);
+
+        assert_eq!(AudioLevel::measure(&[]), AudioLevel::SILENT);
+        assert!(AudioLevel::measure(&[0.0; 16]).is_silent());
+    }
+
+    #[test]
+    fn test_meter_keeps_loudest_level_until_read() {
+        let click = [0.1, -1.0, 0.1, 0.1];
+        let meter = LevelMeter::new();
+        meter.record(&click);
+        meter.record(&[0.25; 4]);
+
+        let level = meter.take();
+        assert_eq!(level, AudioLevel::measure(&click));
+        assert!(level.peak_db.abs() < 1e-3);
+        assert!(level.rms_db < level.peak_db);
+
+        assert!(meter.take().is_silent());
+    }
+}
@@ -1 +1,13 @@
-
+pub mod capture;
+pub mod cues;
+pub mod decoder;
+pub mod effects;
+pub mod encoder;
+pub mod jitter;
+pub mod level;
+pub mod mixer;
+pub mod output;
+pub mod processing;
+pub mod recorder;
+pub mod storage;
+pub mod vad;
@@ -0,0 +1,1515 @@
+//! Mixing of remote speakers into a stereo output.
+//!
+//! Each speaker gets its own jitter buffer and decoder. Every output frame
+//! pulls one frame's worth of samples from each speaker, scales it by the
+//! speaker's volume, colors it with the radio effect of the channel it was
+//! heard on and pans it by the radio that channel is tuned on. Radios marked
+//! as priority duck every other radio while someone is talking on them.
+//!
+//! Intercom channels, the always-on crew net, are mixed apart from radios:
+//! centered, without radio effects, at their own volume and on their own
+//! bus. While someone talks on the intercom every radio is ducked by the
+//! intercom's [`IntercomMix::radio_duck`], and whether priority radio
+//! traffic ducks the intercom in turn is up to the user.
+//!
+//! Scrambled packets, which stand in for encrypted traffic the local user
+//! has no key for, are played as noise instead of being decoded.
+//!
+//! Sound cues such as the roger beep are played on the radio a transmission
+//! is heard on when a speaker starts and stops, see [`crate::cues`].
+//!
+//! Each radio plays on an output bus, so radios can be routed to different
+//! devices. Every bus is mixed in the same pass and queued until its device
+//! asks for it.
+
+use crate::cues::{CueBank, CueConfig, CueEvent};
+use crate::decoder::{new_opus_decoder, FrameDecoder, MAX_FRAME_SAMPLES};
+use crate::effects::{RadioEffect, RadioEffectProcessor, Scrambler};
+use crate::encoder::SAMPLE_RATE;
+use crate::jitter::{JitterBuffer, JitterConfig, JitterOutput, JitterStats};
+use crate::level::{AudioLevel, LevelMeter};
+use crate::recorder::Recorder;
+use fleet_net_common::audio::{ReceptionQuality, UserAudioState};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::packet::AudioPacket;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MixerConfig {
+    /// Duration of each mixed output frame in milliseconds.
+    pub frame_duration_ms: u8,
+    pub jitter: JitterConfig,
+    /// Output frames a speaker may stay silent before its decoder is released.
+    pub idle_frames: u32,
+    /// Output frames a speaker still counts as talking after their last packet,
+    /// bridging gaps between words.
+    pub speaking_hold_frames: u32,
+}
+
+impl Default for MixerConfig {
+    fn default() -> Self {
+        Self {
+            frame_duration_ms: 20,
+            jitter: JitterConfig::default(),
+            // 10 seconds of 20ms frames
+            idle_frames: 500,
+            speaking_hold_frames: 15,
+        }
+    }
+}
+
+impl MixerConfig {
+    /// Mono samples per output frame at [`SAMPLE_RATE`].
+    pub fn frame_size(&self) -> usize {
+        SAMPLE_RATE as usize / 1000 * usize::from(self.frame_duration_ms)
+    }
+}
+
+struct SpeakerStream<D> {
+    jitter: JitterBuffer,
+    decoder: D,
+    channel_id: ChannelId,
+    /// Decoded samples not yet mixed.
+    decoded: VecDeque<f32>,
+    /// Samples per frame of the last packet, used to size concealment.
+    last_frame_samples: usize,
+    idle_frames: u32,
+    effect: Option<RadioEffectProcessor>,
+    scrambler: Scrambler,
+    level: LevelMeter,
+    /// Between the start and end cues of a transmission.
+    transmitting: bool,
+    /// Counters as of the last reception report.
+    reported: JitterStats,
+}
+
+impl<D> SpeakerStream<D> {
+    /// Applies `effect` to `samples`, rebuilding the processor if it changed.
+    fn apply_effect(&mut self, effect: Option<&RadioEffect>, samples: &mut [f32]) {
+        match effect {
+            None => self.effect = None,
+            Some(effect) => {
+                let processor = match &mut self.effect {
+                    Some(processor) if processor.effect() == effect => processor,
+                    slot => slot.insert(RadioEffectProcessor::new(*effect)),
+                };
+                processor.process(samples);
+            }
+        }
+    }
+}
+
+/// How loud a remote speaker has been since levels were last taken.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SpeakerLevel {
+    pub user_id: UserId,
+    pub channel_id: ChannelId,
+    #[serde(flatten)]
+    pub level: AudioLevel,
+}
+
+/// Received voice quality across every speaker heard so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct VoiceStats {
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    /// Packets that arrived too late to be played.
+    pub packets_late: u64,
+    /// Lost packets that were sent again and arrived in time.
+    pub packets_recovered: u64,
+    /// Share of packets lost or late, from 0 to 100.
+    pub loss_percent: f64,
+    /// Average interarrival jitter of current speakers, in milliseconds.
+    pub jitter_ms: f64,
+}
+
+/// An independently mixed stereo output, usually played on its own device.
+pub type OutputBus = u8;
+
+/// The bus everything plays on unless a radio is routed elsewhere.
+pub const DEFAULT_BUS: OutputBus = 0;
+
+/// Frames queued for a bus whose device is not reading, before the oldest
+/// are dropped. Devices running on slightly different clocks drift apart,
+/// and this bounds the latency the slower one accumulates.
+const MAX_QUEUED_FRAMES: usize = 5;
+
+/// Gain applied to dimmed radios, about -12 dB.
+pub const DIM_GAIN: f32 = 0.25;
+
+/// How a radio's channel is placed in the mix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RadioMix {
+    /// Radio volume, 0.0 to 2.0.
+    pub volume: f32,
+    /// From -1.0 (left ear) to 1.0 (right ear).
+    pub pan: f32,
+    pub muted: bool,
+    /// Always played at [`DIM_GAIN`].
+    pub dimmed: bool,
+    /// Dims all non-priority radios while audio is playing on this one.
+    pub priority: bool,
+    pub output: OutputBus,
+}
+
+impl Default for RadioMix {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            pan: 0.0,
+            muted: false,
+            dimmed: false,
+            priority: false,
+            output: DEFAULT_BUS,
+        }
+    }
+}
+
+impl RadioMix {
+    /// Left and right gains, given whether a priority radio is active.
+    pub fn gains(&self, priority_active: bool) -> (f32, f32) {
+        if self.muted {
+            return (0.0, 0.0);
+        }
+        let mut gain = self.volume.clamp(0.0, 2.0);
+        if self.dimmed {
+            gain *= DIM_GAIN;
+        }
+        if priority_active && !self.priority {
+            gain *= DIM_GAIN;
+        }
+        let (left, right) = pan_gains(self.pan);
+        (left * gain, right * gain)
+    }
+}
+
+/// How an intercom channel is placed in the mix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntercomMix {
+    /// Intercom volume, 0.0 to 2.0.
+    pub volume: f32,
+    pub muted: bool,
+    /// Gain applied to every radio while audio is playing on the intercom,
+    /// 1.0 to leave them alone.
+    pub radio_duck: f32,
+    /// Dims the intercom like any other radio while a priority radio is active.
+    pub ducked_by_priority: bool,
+    pub output: OutputBus,
+}
+
+impl Default for IntercomMix {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+            radio_duck: DIM_GAIN,
+            ducked_by_priority: false,
+            output: DEFAULT_BUS,
+        }
+    }
+}
+
+impl IntercomMix {
+    /// Gain of both sides, given whether a priority radio is active.
+    pub fn gain(&self, priority_active: bool) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        let gain = self.volume.clamp(0.0, 2.0);
+        if priority_active && self.ducked_by_priority {
+            gain * DIM_GAIN
+        } else {
+            gain
+        }
+    }
+}
+
+/// What is talking in the current frame, deciding who ducks whom.
+#[derive(Debug, Clone, Copy)]
+struct Ducking {
+    priority_active: bool,
+    /// Gain applied to radios because of intercom traffic.
+    radio_gain: f32,
+}
+
+/// Left and right gains and the bus of audio heard on `channel_id`.
+fn placement(
+    radios: &HashMap<ChannelId, RadioMix>,
+    intercoms: &HashMap<ChannelId, IntercomMix>,
+    channel_id: ChannelId,
+    ducking: Ducking,
+) -> (f32, f32, OutputBus) {
+    if let Some(intercom) = intercoms.get(&channel_id) {
+        let gain = intercom.gain(ducking.priority_active);
+        return (gain, gain, intercom.output);
+    }
+    let radio = radios.get(&channel_id).copied().unwrap_or_default();
+    let (left, right) = radio.gains(ducking.priority_active);
+    (
+        left * ducking.radio_gain,
+        right * ducking.radio_gain,
+        radio.output,
+    )
+}
+
+/// Stereo gains for a pan position from -1.0 (left) to 1.0 (right).
+///
+/// Centered audio plays at full level on both sides.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+/// A sound cue partway through playing.
+struct PlayingCue {
+    samples: Arc<[f32]>,
+    channel_id: ChannelId,
+    volume: f32,
+    /// Samples of silence before the cue starts.
+    delay: usize,
+    position: usize,
+}
+
+/// The latest mixed frame of one bus and what its device has yet to play.
+#[derive(Default)]
+struct Bus {
+    frame: Vec<f32>,
+    queue: VecDeque<f32>,
+}
+
+type DecoderFactory<D> = Box<dyn Fn() -> Result<D, FleetNetError> + Send>;
+
+pub struct Mixer<D: FrameDecoder = opus::Decoder> {
+    config: MixerConfig,
+    new_decoder: DecoderFactory<D>,
+    speakers: HashMap<UserId, SpeakerStream<D>>,
+    user_volumes: HashMap<UserId, f32>,
+    muted_users: HashSet<UserId>,
+    radios: HashMap<ChannelId, RadioMix>,
+    intercoms: HashMap<ChannelId, IntercomMix>,
+    channel_effects: HashMap<ChannelId, RadioEffect>,
+    deafened: bool,
+    cue_bank: CueBank,
+    channel_cues: HashMap<ChannelId, CueConfig>,
+    playing_cues: Vec<PlayingCue>,
+    recorder: Option<Arc<Recorder>>,
+    /// Counters of speakers that have been released.
+    retired: JitterStats,
+    scratch: Vec<f32>,
+    buses: HashMap<OutputBus, Bus>,
+}
+
+impl Mixer {
+    /// Creates a mixer decoding speakers with Opus.
+    pub fn new(config: MixerConfig) -> Self {
+        Self::with_decoder_factory(config, new_opus_decoder)
+    }
+}
+
+impl<D: FrameDecoder> Mixer<D> {
+    pub fn with_decoder_factory(
+        config: MixerConfig,
+        new_decoder: impl Fn() -> Result<D, FleetNetError> + Send + 'static,
+    ) -> Self {
+        Self {
+            config,
+            new_decoder: Box::new(new_decoder),
+            speakers: HashMap::new(),
+            user_volumes: HashMap::new(),
+            muted_users: HashSet::new(),
+            radios: HashMap::new(),
+            intercoms: HashMap::new(),
+            channel_effects: HashMap::new(),
+            deafened: false,
+            cue_bank: CueBank::default(),
+            channel_cues: HashMap::new(),
+            playing_cues: Vec::new(),
+            recorder: None,
+            retired: JitterStats::default(),
+            scratch: vec![0.0; MAX_FRAME_SAMPLES],
+            buses: HashMap::from([(DEFAULT_BUS, Bus::new(config.frame_size()))]),
+        }
+    }
+
+    pub fn config(&self) -> &MixerConfig {
+        &self.config
+    }
+
+    /// Sets a speaker's playback volume, 0.0 to 2.0.
+    pub fn set_user_volume(&mut self, user_id: UserId, volume: f32) {
+        self.user_volumes.insert(user_id, volume.clamp(0.0, 2.0));
+    }
+
+    /// Silences a speaker locally without affecting what others hear.
+    pub fn set_user_muted(&mut self, user_id: UserId, muted: bool) {
+        if muted {
+            self.muted_users.insert(user_id);
+        } else {
+            self.muted_users.remove(&user_id);
+        }
+    }
+
+    /// Restores every speaker to full volume and unmutes them, e.g. when
+    /// switching servers.
+    pub fn reset_users(&mut self) {
+        self.user_volumes.clear();
+        self.muted_users.clear();
+    }
+
+    /// Applies the volume from a speaker's audio state.
+    pub fn apply_audio_state(&mut self, state: &UserAudioState) {
+        self.set_user_volume(state.user_id, state.volume);
+    }
+
+    /// Silences playback while still decoding, so speaking indicators keep working.
+    pub fn set_deafened(&mut self, deafened: bool) {
+        self.deafened = deafened;
+    }
+
+    /// Feeds every speaker's decoded audio to `recorder` while it records.
+    pub fn set_recorder(&mut self, recorder: Option<Arc<Recorder>>) {
+        self.recorder = recorder;
+    }
+
+    /// Places speakers heard on `channel_id` according to the radio tuned to it.
+    pub fn set_radio_mix(&mut self, channel_id: ChannelId, mix: RadioMix) {
+        let frame_size = self.config.frame_size();
+        self.buses
+            .entry(mix.output)
+            .or_insert_with(|| Bus::new(frame_size));
+        self.radios.insert(channel_id, mix);
+    }
+
+    /// Plays `channel_id` centered at full volume again.
+    pub fn clear_radio_mix(&mut self, channel_id: ChannelId) {
+        self.radios.remove(&channel_id);
+    }
+
+    /// Mixes `channel_id` as an intercom rather than a radio.
+    pub fn set_intercom_mix(&mut self, channel_id: ChannelId, mix: IntercomMix) {
+        let frame_size = self.config.frame_size();
+        self.buses
+            .entry(mix.output)
+            .or_insert_with(|| Bus::new(frame_size));
+        self.intercoms.insert(channel_id, mix);
+    }
+
+    /// Mixes `channel_id` as a radio again.
+    pub fn clear_intercom_mix(&mut self, channel_id: ChannelId) {
+        self.intercoms.remove(&channel_id);
+    }
+
+    /// Colors audio heard on `channel_id` with `effect`, or plays it clean with `None`.
+    pub fn set_channel_effect(&mut self, channel_id: ChannelId, effect: Option<RadioEffect>) {
+        match effect {
+            Some(effect) => self.channel_effects.insert(channel_id, effect),
+            None => self.channel_effects.remove(&channel_id),
+        };
+    }
+
+    /// Replaces the samples played for sound cues.
+    pub fn set_cue_bank(&mut self, bank: CueBank) {
+        self.cue_bank = bank;
+    }
+
+    /// Plays `cues` for transmissions on `channel_id`, or none with `None`.
+    pub fn set_channel_cues(&mut self, channel_id: ChannelId, cues: Option<CueConfig>) {
+        match cues {
+            Some(cues) => self.channel_cues.insert(channel_id, cues),
+            None => self.channel_cues.remove(&channel_id),
+        };
+    }
+
+    /// Plays the cues for `event` on `channel_id`, e.g. when the local user
+    /// keys up. Received transmissions are cued automatically.
+    pub fn play_cue(&mut self, channel_id: ChannelId, event: CueEvent) {
+        queue_cues(
+            &mut self.playing_cues,
+            &self.cue_bank,
+            self.channel_cues.get(&channel_id),
+            channel_id,
+            event,
+        );
+    }
+
+    /// Forgets a speaker, e.g. when they leave the channel.
+    pub fn remove_speaker(&mut self, user_id: UserId) {
+        if let Some(stream) = self.speakers.remove(&user_id) {
+            retire(&mut self.retired, stream.jitter.stats());
+        }
+    }
+
+    pub fn speaker_count(&self) -> usize {
+        self.speakers.len()
+    }
+
+    /// Speakers currently talking, with the channel they are heard on.
+    pub fn active_speakers(&self) -> Vec<(UserId, ChannelId)> {
+        self.speakers
+            .iter()
+            .filter(|(_, stream)| stream.idle_frames < self.config.speaking_hold_frames)
+            .map(|(user_id, stream)| (*user_id, stream.channel_id))
+            .collect()
+    }
+
+    /// Decoded level of every active speaker since the last call, before
+    /// local volume is applied.
+    pub fn take_speaker_levels(&self) -> Vec<SpeakerLevel> {
+        self.speakers
+            .iter()
+            .filter(|(_, stream)| stream.idle_frames < self.config.speaking_hold_frames)
+            .map(|(user_id, stream)| SpeakerLevel {
+                user_id: *user_id,
+                channel_id: stream.channel_id,
+                level: stream.level.take(),
+            })
+            .collect()
+    }
+
+    /// Packet loss and jitter over everyone heard since the mixer was created.
+    pub fn voice_stats(&self) -> VoiceStats {
+        let mut totals = self.retired;
+        for stream in self.speakers.values() {
+            retire(&mut totals, stream.jitter.stats());
+        }
+        let expected = totals.received + totals.lost;
+        let jitter_ms = if self.speakers.is_empty() {
+            0.0
+        } else {
+            self.speakers
+                .values()
+                .map(|stream| stream.jitter.stats().jitter_ms)
+                .sum::<f64>()
+                / self.speakers.len() as f64
+        };
+
+        VoiceStats {
+            packets_received: totals.received,
+            packets_lost: totals.lost,
+            packets_late: totals.late,
+            packets_recovered: totals.recovered,
+            loss_percent: if expected == 0 {
+                0.0
+            } else {
+                (totals.lost + totals.late) as f64 * 100.0 / expected as f64
+            },
+            jitter_ms,
+        }
+    }
+
+    /// How well each speaker heard since the last call was received, for
+    /// [`ControlMessage::ReceptionReport`]s to the server.
+    ///
+    /// [`ControlMessage::ReceptionReport`]: fleet_net_protocol::message::ControlMessage::ReceptionReport
+    pub fn take_reception(&mut self) -> Vec<(UserId, ChannelId, ReceptionQuality)> {
+        self.speakers
+            .iter_mut()
+            .filter_map(|(user_id, stream)| {
+                let stats = stream.jitter.stats();
+                let received = stats.received - stream.reported.received;
+                let lost = stats.lost - stream.reported.lost;
+                let late = stats.late - stream.reported.late;
+                stream.reported = stats;
+                if received == 0 {
+                    return None;
+                }
+                let loss_percent = ((lost + late) * 100 / (received + lost)).min(100) as u8;
+                let quality = ReceptionQuality {
+                    loss_percent,
+                    jitter_ms: stats.jitter_ms.round().min(f64::from(u16::MAX)) as u16,
+                };
+                Some((*user_id, stream.channel_id, quality))
+            })
+            .collect()
+    }
+
+    /// Packets each speaker is missing that could still be played, for
+    /// [`ControlMessage::RetransmitRequest`]s on channels that allow them.
+    /// Each is only returned once.
+    ///
+    /// [`ControlMessage::RetransmitRequest`]: fleet_net_protocol::message::ControlMessage::RetransmitRequest
+    pub fn take_missing(&mut self) -> Vec<(UserId, ChannelId, Vec<u16>)> {
+        self.speakers
+            .iter_mut()
+            .filter_map(|(user_id, stream)| {
+                let missing = stream.jitter.take_missing();
+                (!missing.is_empty()).then_some((*user_id, stream.channel_id, missing))
+            })
+            .collect()
+    }
+
+    pub fn speaker_stats(&self, user_id: UserId) -> Option<JitterStats> {
+        self.speakers
+            .get(&user_id)
+            .map(|stream| stream.jitter.stats())
+    }
+
+    /// Queues a received packet, which must already have passed HMAC validation.
+    /// Batched packets are queued frame by frame.
+    pub fn push_packet(&mut self, packet: AudioPacket) -> Result<(), FleetNetError> {
+        if packet.header.frames > 1 {
+            for frame in packet.into_frames()? {
+                self.push_packet(frame)?;
+            }
+            return Ok(());
+        }
+
+        let user_id = packet.header.user_id;
+        let stream = match self.speakers.entry(user_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(SpeakerStream {
+                jitter: JitterBuffer::new(self.config.jitter),
+                decoder: (self.new_decoder)()?,
+                channel_id: packet.header.channel_id,
+                decoded: VecDeque::new(),
+                last_frame_samples: self.config.frame_size(),
+                idle_frames: 0,
+                effect: None,
+                scrambler: Scrambler::new(),
+                level: LevelMeter::new(),
+                transmitting: false,
+                reported: JitterStats::default(),
+            }),
+        };
+
+        stream.channel_id = packet.header.channel_id;
+        stream.idle_frames = 0;
+        stream.jitter.push(packet);
+        Ok(())
+    }
+
+    /// Mixes one output frame of the default bus into `out` as interleaved
+    /// stereo; audio routed to other buses is discarded.
+    ///
+    /// `out` must hold `2 * frame_size()` samples. Speakers that underrun
+    /// contribute silence rather than stalling the others.
+    pub fn mix_frame(&mut self, out: &mut [f32]) {
+        debug_assert_eq!(out.len(), self.config.frame_size() * 2);
+        self.mix_buses();
+        out.copy_from_slice(&self.buses[&DEFAULT_BUS].frame);
+    }
+
+    /// Mixes one frame into the `frame` of every bus.
+    fn mix_buses(&mut self) {
+        let frame_size = self.config.frame_size();
+        for bus in self.buses.values_mut() {
+            bus.frame.fill(0.0);
+        }
+
+        // Decode everyone first to learn whether a priority radio or the
+        // intercom is active.
+        for (user_id, stream) in &mut self.speakers {
+            if let Err(e) = fill_decoded(stream, frame_size, &mut self.scratch) {
+                warn!("Dropping audio from user {user_id}: {e}");
+                stream.jitter.reset();
+                stream.decoded.clear();
+            }
+        }
+        let mut ducking = Ducking {
+            priority_active: false,
+            radio_gain: 1.0,
+        };
+        for stream in self
+            .speakers
+            .values()
+            .filter(|stream| !stream.decoded.is_empty())
+        {
+            if let Some(intercom) = self.intercoms.get(&stream.channel_id) {
+                if !intercom.muted {
+                    ducking.radio_gain =
+                        ducking.radio_gain.min(intercom.radio_duck.clamp(0.0, 1.0));
+                }
+            } else if self
+                .radios
+                .get(&stream.channel_id)
+                .is_some_and(|radio| radio.priority && !radio.muted)
+            {
+                ducking.priority_active = true;
+            }
+        }
+
+        let idle_limit = self.config.idle_frames;
+        let hold_frames = self.config.speaking_hold_frames;
+        self.speakers.retain(|user_id, stream| {
+            let cues = self.channel_cues.get(&stream.channel_id);
+            if stream.decoded.is_empty() {
+                stream.idle_frames += 1;
+                if stream.transmitting && stream.idle_frames >= hold_frames {
+                    stream.transmitting = false;
+                    let event = CueEvent::ReceiveEnd;
+                    queue_cues(
+                        &mut self.playing_cues,
+                        &self.cue_bank,
+                        cues,
+                        stream.channel_id,
+                        event,
+                    );
+                }
+                let keep = stream.idle_frames < idle_limit;
+                if !keep {
+                    retire(&mut self.retired, stream.jitter.stats());
+                }
+                return keep;
+            }
+            if !stream.transmitting {
+                stream.transmitting = true;
+                let event = CueEvent::ReceiveStart;
+                queue_cues(
+                    &mut self.playing_cues,
+                    &self.cue_bank,
+                    cues,
+                    stream.channel_id,
+                    event,
+                );
+            }
+
+            let volume = if self.muted_users.contains(user_id) {
+                0.0
+            } else {
+                self.user_volumes.get(user_id).copied().unwrap_or(1.0)
+            };
+            let (left, right, output) =
+                placement(&self.radios, &self.intercoms, stream.channel_id, ducking);
+            let bus = self
+                .buses
+                .entry(output)
+                .or_insert_with(|| Bus::new(frame_size));
+
+            let available = stream.decoded.len().min(frame_size);
+            let samples = &mut self.scratch[..available];
+            for (dst, sample) in samples.iter_mut().zip(stream.decoded.drain(..available)) {
+                *dst = sample;
+            }
+            stream.level.record(samples);
+            if let Some(recorder) = &self.recorder {
+                recorder.record_speaker(*user_id, samples);
+            }
+            // Intercoms stay clean even when a radio is tuned to them too
+            let effect = self
+                .channel_effects
+                .get(&stream.channel_id)
+                .filter(|_| !self.intercoms.contains_key(&stream.channel_id));
+            stream.apply_effect(effect, samples);
+
+            for (frame, &sample) in bus.frame.chunks_exact_mut(2).zip(samples.iter()) {
+                let sample = sample * volume;
+                frame[0] += sample * left;
+                frame[1] += sample * right;
+            }
+            true
+        });
+
+        if let Some(recorder) = &self.recorder {
+            recorder.end_frame(frame_size);
+        }
+        self.playing_cues.retain_mut(|cue| {
+            let (left, right, output) =
+                placement(&self.radios, &self.intercoms, cue.channel_id, ducking);
+            let bus = self
+                .buses
+                .entry(output)
+                .or_insert_with(|| Bus::new(frame_size));
+
+            let skip = cue.delay.min(frame_size);
+            cue.delay -= skip;
+            let remaining = &cue.samples[cue.position..];
+            let played = remaining.len().min(frame_size - skip);
+            for (frame, &sample) in bus.frame.chunks_exact_mut(2).skip(skip).zip(remaining) {
+                let sample = sample * cue.volume;
+                frame[0] += sample * left;
+                frame[1] += sample * right;
+            }
+            cue.position += played;
+            cue.position < cue.samples.len()
+        });
+
+        let deafened = self.deafened;
+        for sample in self.buses.values_mut().flat_map(|bus| bus.frame.iter_mut()) {
+            *sample = if deafened {
+                0.0
+            } else {
+                sample.clamp(-1.0, 1.0)
+            };
+        }
+    }
+
+    /// Fills a device buffer for the default bus with `channels`
+    /// interleaved channels, see [`Mixer::fill_bus`].
+    pub fn fill(&mut self, data: &mut [f32], channels: usize) {
+        self.fill_bus(DEFAULT_BUS, data, channels);
+    }
+
+    /// Fills a device buffer for `bus` with `channels` interleaved channels.
+    ///
+    /// Mono devices receive the average of both sides; channels beyond the
+    /// first two are left silent. Frames mixed for other buses meanwhile are
+    /// queued for their devices.
+    pub fn fill_bus(&mut self, bus: OutputBus, data: &mut [f32], channels: usize) {
+        let frame_size = self.config.frame_size();
+        let frames = data.len() / channels.max(1);
+        let max_queued = frame_size * 2 * MAX_QUEUED_FRAMES;
+        self.buses
+            .entry(bus)
+            .or_insert_with(|| Bus::new(frame_size));
+
+        while self.buses[&bus].queue.len() < frames * 2 {
+            self.mix_buses();
+            for (&id, other) in &mut self.buses {
+                other.queue.extend(&other.frame);
+                let excess = other.queue.len().saturating_sub(max_queued);
+                if id != bus && excess > 0 {
+                    other.queue.drain(..excess);
+                }
+            }
+        }
+
+        let queue = &mut self.buses.get_mut(&bus).expect("bus was just added").queue;
+        for out in data.chunks_exact_mut(channels.max(1)) {
+            let left = queue.pop_front().unwrap_or(0.0);
+            let right = queue.pop_front().unwrap_or(0.0);
+            match out {
+                [mono] => *mono = (left + right) * 0.5,
+                [l, r, rest @ ..] => {
+                    *l = left;
+                    *r = right;
+                    rest.fill(0.0);
+                }
+                [] => {}
+            }
+        }
+    }
+}
+
+impl Bus {
+    fn new(frame_size: usize) -> Self {
+        Self {
+            frame: vec![0.0; frame_size * 2],
+            queue: VecDeque::with_capacity(frame_size * 2 * MAX_QUEUED_FRAMES),
+        }
+    }
+}
+
+/// Starts the cues `config` plays for `event`, one after another.
+fn queue_cues(
+    playing: &mut Vec<PlayingCue>,
+    bank: &CueBank,
+    config: Option<&CueConfig>,
+    channel_id: ChannelId,
+    event: CueEvent,
+) {
+    let Some(config) = config else {
+        return;
+    };
+    let mut delay = 0;
+    for kind in config.cues(event) {
+        let samples = bank.get(kind);
+        let len = samples.len();
+        playing.push(PlayingCue {
+            samples,
+            channel_id,
+            volume: config.volume.clamp(0.0, 2.0),
+            delay,
+            position: 0,
+        });
+        delay += len;
+    }
+}
+
+/// Adds a released speaker's counters to `totals`.
+fn retire(totals: &mut JitterStats, stats: JitterStats) {
+    totals.received += stats.received;
+    totals.lost += stats.lost;
+    totals.late += stats.late;
+    totals.duplicates += stats.duplicates;
+    totals.requested += stats.requested;
+    totals.recovered += stats.recovered;
+    totals.underruns += stats.underruns;
+}
+
+/// Decodes from the jitter buffer until `stream` holds a full output frame
+/// or the buffer has nothing more to give.
+fn fill_decoded<D: FrameDecoder>(
+    stream: &mut SpeakerStream<D>,
+    frame_size: usize,
+    scratch: &mut [f32],
+) -> Result<(), FleetNetError> {
+    while stream.decoded.len() < frame_size {
+        let samples = match stream.jitter.pop() {
+            JitterOutput::Buffering | JitterOutput::Underrun => return Ok(()),
+            JitterOutput::Frame(packet) if packet.is_scrambled() => {
+                let samples =
+                    usize::from(packet.header.frame_duration) * SAMPLE_RATE as usize / 1000;
+                let samples = samples.min(scratch.len());
+                stream.scrambler.fill(&mut scratch[..samples]);
+                stream.last_frame_samples = samples;
+                samples
+            }
+            JitterOutput::Frame(packet) if packet.header.dtx => {
+                let samples =
+                    usize::from(packet.header.frame_duration) * SAMPLE_RATE as usize / 1000;
+                let samples = samples.min(scratch.len());
+                scratch[..samples].fill(0.0);
+                stream.last_frame_samples = samples;
+                samples
+            }
+            JitterOutput::Silence => {
+                let len = stream.last_frame_samples.min(scratch.len());
+                scratch[..len].fill(0.0);
+                len
+            }
+            JitterOutput::Frame(packet) => {
+                let samples = stream
+                    .decoder
+                    .decode_frame(&packet.opus_payload, scratch, false)?;
+                stream.last_frame_samples = samples;
+                samples
+            }
+            JitterOutput::Missing { fec: Some(next) } if !next.is_empty() => {
+                let len = stream.last_frame_samples.min(scratch.len());
+                stream
+                    .decoder
+                    .decode_frame(&next, &mut scratch[..len], true)?
+            }
+            JitterOutput::Missing { .. } => {
+                let len = stream.last_frame_samples.min(scratch.len());
+                stream.decoder.conceal(&mut scratch[..len])?
+            }
+        };
+        stream.decoded.extend(&scratch[..samples]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cues::CueKind;
+    use fleet_net_protocol::packet::PacketHeader;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    /// Decodes a payload of `[level]` into a constant frame of `level / 100`.
+    struct FakeDecoder;
+
+    impl FrameDecoder for FakeDecoder {
+        fn decode_frame(
+            &mut self,
+            payload: &[u8],
+            output: &mut [f32],
+            _fec: bool,
+        ) -> Result<usize, FleetNetError> {
+            let len = 960.min(output.len());
+            output[..len].fill(f32::from(payload[0]) / 100.0);
+            Ok(len)
+        }
+
+        fn conceal(&mut self, output: &mut [f32]) -> Result<usize, FleetNetError> {
+            output.fill(0.0);
+            Ok(output.len())
+        }
+    }
+
+    fn packet(user_id: UserId, channel_id: ChannelId, sequence: u16, level: u8) -> AudioPacket {
+        AudioPacket {
+            header: PacketHeader {
+                channel_id,
+                user_id,
+                sequence,
+                timestamp: u32::from(sequence) * 20,
+                signal_strength: 255,
+                frame_duration: 20,
+                dtx: false,
+                frames: 1,
+                padded: false,
+                audio_length: 1,
+                hmac_prefix: 0,
+            },
+            opus_payload: vec![level],
+            position: None,
+        }
+    }
+
+    fn test_mixer() -> Mixer<FakeDecoder> {
+        Mixer::with_decoder_factory(MixerConfig::default(), || Ok(FakeDecoder))
+    }
+
+    #[test]
+    fn test_mixes_speakers_with_volume_and_pan() {
+        let mut mixer = test_mixer();
+        mixer.set_user_volume(user(1), 0.5);
+        mixer.set_radio_mix(
+            channel(20),
+            RadioMix {
+                pan: -1.0,
+                ..RadioMix::default()
+            },
+        );
+
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 40))
+                .unwrap();
+            mixer
+                .push_packet(packet(user(2), channel(20), sequence, 30))
+                .unwrap();
+        }
+
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        // User 1 is centered at half volume, user 2 is hard left
+        assert!((out[0] - 0.5).abs() < 1e-6);
+        assert!((out[1] - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_priority_radio_dims_the_others() {
+        let mut mixer = test_mixer();
+        // UHF in the left ear with priority, VHF in the right ear
+        mixer.set_radio_mix(
+            channel(10),
+            RadioMix {
+                pan: -1.0,
+                priority: true,
+                ..RadioMix::default()
+            },
+        );
+        mixer.set_radio_mix(
+            channel(20),
+            RadioMix {
+                pan: 1.0,
+                volume: 0.8,
+                ..RadioMix::default()
+            },
+        );
+
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(2), channel(20), sequence, 50))
+                .unwrap();
+        }
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        assert_eq!(out[0], 0.0);
+        assert!((out[1] - 0.4).abs() < 1e-6);
+
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 30))
+                .unwrap();
+        }
+        mixer.mix_frame(&mut out);
+        assert!((out[0] - 0.3).abs() < 1e-6);
+        assert!((out[1] - 0.4 * DIM_GAIN).abs() < 1e-6);
+
+        // Muted radios are silent and a manually dimmed one stays dimmed
+        mixer.set_radio_mix(
+            channel(10),
+            RadioMix {
+                muted: true,
+                ..RadioMix::default()
+            },
+        );
+        mixer.set_radio_mix(
+            channel(20),
+            RadioMix {
+                dimmed: true,
+                ..RadioMix::default()
+            },
+        );
+        mixer.mix_frame(&mut out);
+        assert!((out[0] - 0.5 * DIM_GAIN).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_intercom_ducks_the_radios() {
+        let mut mixer = test_mixer();
+        mixer.set_radio_mix(
+            channel(20),
+            RadioMix {
+                pan: 1.0,
+                ..RadioMix::default()
+            },
+        );
+        mixer.set_intercom_mix(channel(30), IntercomMix::default());
+        // A radio tuned to the intercom too doesn't color it
+        mixer.set_channel_effect(
+            channel(30),
+            Some(RadioEffect {
+                distortion: 1.0,
+                ..RadioEffect::CLEAN
+            }),
+        );
+
+        for sequence in 0..4 {
+            mixer
+                .push_packet(packet(user(2), channel(20), sequence, 50))
+                .unwrap();
+            mixer
+                .push_packet(packet(user(3), channel(30), sequence, 30))
+                .unwrap();
+        }
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        // The intercom plays centered, the radio under it
+        assert!((out[0] - 0.3).abs() < 1e-6);
+        assert!((out[1] - (0.3 + 0.5 * DIM_GAIN)).abs() < 1e-6);
+
+        // A muted intercom leaves the radios alone
+        mixer.set_intercom_mix(
+            channel(30),
+            IntercomMix {
+                muted: true,
+                ..IntercomMix::default()
+            },
+        );
+        mixer.mix_frame(&mut out);
+        assert_eq!(out[0], 0.0);
+        assert!((out[1] - 0.5).abs() < 1e-6);
+
+        // Priority traffic only ducks an intercom that asks for it
+        mixer.set_radio_mix(
+            channel(20),
+            RadioMix {
+                pan: 1.0,
+                priority: true,
+                ..RadioMix::default()
+            },
+        );
+        mixer.set_intercom_mix(
+            channel(30),
+            IntercomMix {
+                radio_duck: 1.0,
+                ducked_by_priority: true,
+                ..IntercomMix::default()
+            },
+        );
+        mixer.mix_frame(&mut out);
+        assert!((out[0] - 0.3 * DIM_GAIN).abs() < 1e-6);
+        assert!((out[1] - (0.3 * DIM_GAIN + 0.5)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_channel_effect_colors_only_its_channel() {
+        let mut mixer = test_mixer();
+        for (channel_id, pan) in [(10, -1.0), (20, 1.0)] {
+            mixer.set_radio_mix(
+                channel(channel_id),
+                RadioMix {
+                    pan,
+                    ..RadioMix::default()
+                },
+            );
+        }
+        mixer.set_channel_effect(
+            channel(20),
+            Some(RadioEffect {
+                distortion: 1.0,
+                ..RadioEffect::CLEAN
+            }),
+        );
+
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 20))
+                .unwrap();
+            mixer
+                .push_packet(packet(user(2), channel(20), sequence, 20))
+                .unwrap();
+        }
+
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        // Left is clean, right is driven into saturation
+        assert!(out.chunks(2).all(|frame| (frame[0] - 0.2).abs() < 1e-6));
+        let right_peak = out.chunks(2).fold(0.0f32, |peak, frame| peak.max(frame[1]));
+        assert!(right_peak > 0.5);
+    }
+
+    #[test]
+    fn test_scrambled_packets_play_as_noise() {
+        let mut mixer = test_mixer();
+        for sequence in 0..3 {
+            let mut scrambled = packet(user(1), channel(10), sequence, 0);
+            scrambled.header = scrambled.header.scrambled();
+            scrambled.opus_payload.clear();
+            // The fake decoder would panic on the empty payload
+            mixer.push_packet(scrambled).unwrap();
+        }
+
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        let loud = out.iter().filter(|s| s.abs() > 0.01).count();
+        assert!(loud > out.len() / 2, "{loud}");
+        assert_eq!(mixer.active_speakers(), vec![(user(1), channel(10))]);
+    }
+
+    #[test]
+    fn test_dtx_pauses_play_silence_and_hold_the_speaker() {
+        let mut mixer = Mixer::with_decoder_factory(
+            MixerConfig {
+                speaking_hold_frames: 2,
+                ..MixerConfig::default()
+            },
+            || Ok(FakeDecoder),
+        );
+        mixer
+            .push_packet(packet(user(1), channel(10), 0, 50))
+            .unwrap();
+        mixer
+            .push_packet(packet(user(1), channel(10), 1, 50))
+            .unwrap();
+        let mut pause = packet(user(1), channel(10), 2, 0);
+        pause.header.dtx = true;
+        // The fake decoder would panic on the empty payload
+        pause.opus_payload.clear();
+        mixer.push_packet(pause).unwrap();
+
+        let mut out = vec![0.0; 960 * 2];
+        for _ in 0..2 {
+            mixer.mix_frame(&mut out);
+            assert!(out.iter().any(|&sample| sample != 0.0));
+        }
+        for _ in 0..10 {
+            mixer.mix_frame(&mut out);
+            assert!(out.iter().all(|&sample| sample == 0.0));
+            assert_eq!(mixer.active_speakers(), vec![(user(1), channel(10))]);
+        }
+        let stats = mixer.speaker_stats(user(1)).unwrap();
+        assert_eq!((stats.lost, stats.underruns), (0, 0));
+    }
+
+    #[test]
+    fn test_underrun_outputs_silence_and_idle_speakers_are_released() {
+        let mut mixer = Mixer::with_decoder_factory(
+            MixerConfig {
+                idle_frames: 3,
+                ..MixerConfig::default()
+            },
+            || Ok(FakeDecoder),
+        );
+
+        // Not yet at the jitter target depth
+        mixer
+            .push_packet(packet(user(1), channel(10), 0, 50))
+            .unwrap();
+        let mut out = vec![1.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+
+        mixer.mix_frame(&mut out);
+        assert_eq!(mixer.speaker_count(), 1);
+        mixer.mix_frame(&mut out);
+        assert_eq!(mixer.speaker_count(), 0);
+    }
+
+    #[test]
+    fn test_active_speakers_hold_through_short_gaps() {
+        let mut mixer = Mixer::with_decoder_factory(
+            MixerConfig {
+                speaking_hold_frames: 2,
+                ..MixerConfig::default()
+            },
+            || Ok(FakeDecoder),
+        );
+        assert!(mixer.active_speakers().is_empty());
+
+        mixer
+            .push_packet(packet(user(4), channel(12), 0, 50))
+            .unwrap();
+        assert_eq!(mixer.active_speakers(), vec![(user(4), channel(12))]);
+
+        // Still buffering, so nothing is decoded yet
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        assert_eq!(mixer.active_speakers(), vec![(user(4), channel(12))]);
+        mixer.mix_frame(&mut out);
+        assert!(mixer.active_speakers().is_empty());
+        assert_eq!(mixer.speaker_count(), 1);
+    }
+
+    #[test]
+    fn test_locally_muted_user_is_silent() {
+        let mut mixer = test_mixer();
+        mixer.set_user_volume(user(2), 0.5);
+        mixer.set_user_muted(user(1), true);
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 40))
+                .unwrap();
+            mixer
+                .push_packet(packet(user(2), channel(10), sequence, 40))
+                .unwrap();
+        }
+
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        assert!((out[0] - 0.2).abs() < 1e-6);
+
+        mixer.reset_users();
+        mixer.mix_frame(&mut out);
+        assert!((out[0] - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_voice_stats_outlive_released_speakers() {
+        let mut mixer = test_mixer();
+        for sequence in [0, 2, 3] {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 40))
+                .unwrap();
+        }
+        let mut out = vec![0.0; 960 * 2];
+        for _ in 0..3 {
+            mixer.mix_frame(&mut out);
+        }
+        mixer.remove_speaker(user(1));
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(2), channel(10), sequence, 40))
+                .unwrap();
+        }
+
+        let stats = mixer.voice_stats();
+        assert_eq!((stats.packets_received, stats.packets_lost), (6, 1));
+        assert!((stats.loss_percent - 100.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_missing_packets_are_listed_for_retransmission() {
+        let mut mixer = test_mixer();
+        for sequence in [0, 1, 3] {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 50))
+                .unwrap();
+        }
+        assert_eq!(mixer.take_missing(), vec![(user(1), channel(10), vec![2])]);
+        assert!(mixer.take_missing().is_empty());
+
+        // Sent again before its slot, it plays as if never lost
+        mixer
+            .push_packet(packet(user(1), channel(10), 2, 50))
+            .unwrap();
+        let mut out = vec![0.0; 960 * 2];
+        for _ in 0..4 {
+            mixer.mix_frame(&mut out);
+        }
+        let stats = mixer.voice_stats();
+        assert_eq!((stats.packets_lost, stats.packets_recovered), (0, 1));
+    }
+
+    #[test]
+    fn test_batched_packets_play_frame_by_frame() {
+        let mut mixer = test_mixer();
+        let key =
+            fleet_net_protocol::hmac::HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = packet(user(1), channel(10), 0, 0).header;
+        let frames = [vec![20], vec![40], vec![60]];
+        let batch = AudioPacket::new_batch_signed(header, &frames, None, &key).unwrap();
+        mixer.push_packet(batch).unwrap();
+
+        let mut out = vec![0.0; 960 * 2];
+        let mut played = Vec::new();
+        for _ in 0..3 {
+            mixer.mix_frame(&mut out);
+            played.push((out[0] * 100.0).round() as u8);
+        }
+        assert_eq!(played, [20, 40, 60]);
+        assert_eq!(mixer.speaker_stats(user(1)).unwrap().received, 3);
+    }
+
+    #[test]
+    fn test_reception_is_reported_per_interval() {
+        let mut mixer = test_mixer();
+        for sequence in [0, 1, 2, 4] {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 50))
+                .unwrap();
+        }
+        let mut out = vec![0.0; 960 * 2];
+        for _ in 0..5 {
+            mixer.mix_frame(&mut out);
+        }
+
+        // Four packets arrived and one went missing
+        let reception = mixer.take_reception();
+        assert_eq!(reception.len(), 1);
+        let (user_id, channel_id, quality) = reception[0];
+        assert_eq!((user_id, channel_id), (user(1), channel(10)));
+        assert_eq!(quality.loss_percent, 20);
+
+        // Nothing new since, so nothing to report
+        assert!(mixer.take_reception().is_empty());
+        mixer
+            .push_packet(packet(user(1), channel(10), 5, 50))
+            .unwrap();
+        mixer.mix_frame(&mut out);
+        assert_eq!(mixer.take_reception()[0].2.loss_percent, 0);
+    }
+
+    #[test]
+    fn test_speaker_levels_are_metered_per_speaker() {
+        let mut mixer = test_mixer();
+        mixer.set_user_volume(user(2), 0.0);
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 50))
+                .unwrap();
+            mixer
+                .push_packet(packet(user(2), channel(20), sequence, 25))
+                .unwrap();
+        }
+
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        let mut levels = mixer.take_speaker_levels();
+        levels.sort_by_key(|level| level.user_id);
+        assert_eq!(levels.len(), 2);
+        assert!((levels[0].level.peak_db - -6.0206).abs() < 1e-3);
+        // Metered before local volume, so a turned down speaker still lights up
+        assert_eq!(levels[1].channel_id, channel(20));
+        assert!((levels[1].level.rms_db - -12.0412).abs() < 1e-3);
+
+        assert!(mixer
+            .take_speaker_levels()
+            .iter()
+            .all(|level| level.level.is_silent()));
+    }
+
+    #[test]
+    fn test_deafened_mixer_outputs_silence() {
+        let mut mixer = test_mixer();
+        mixer.set_deafened(true);
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 40))
+                .unwrap();
+        }
+
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+        assert_eq!(mixer.active_speakers(), vec![(user(1), channel(10))]);
+
+        mixer.set_deafened(false);
+        mixer.mix_frame(&mut out);
+        assert!(out[0] > 0.0);
+    }
+
+    #[test]
+    fn test_fill_handles_odd_device_buffers() {
+        let mut mixer = test_mixer();
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 20))
+                .unwrap();
+        }
+
+        // Mono device with a buffer that does not align to frames
+        let mut data = vec![0.0; 700];
+        mixer.fill(&mut data, 1);
+        mixer.fill(&mut data, 1);
+        assert!(data.iter().all(|&sample| (sample - 0.2).abs() < 1e-6));
+
+        // Surround device: only front left and right carry audio
+        let mut data = vec![1.0; 6 * 100];
+        mixer.fill(&mut data, 6);
+        assert!((data[0] - 0.2).abs() < 1e-6);
+        assert!(data[2..6].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_radios_play_on_their_own_bus() {
+        let mut mixer = test_mixer();
+        // Intercom on channel 20 goes to the speakers on bus 1
+        mixer.set_radio_mix(
+            channel(20),
+            RadioMix {
+                output: 1,
+                ..RadioMix::default()
+            },
+        );
+        for sequence in 0..4 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 20))
+                .unwrap();
+            mixer
+                .push_packet(packet(user(2), channel(20), sequence, 30))
+                .unwrap();
+        }
+
+        let mut headset = vec![0.0; 960 * 2];
+        mixer.fill(&mut headset, 2);
+        assert!(headset.iter().all(|&sample| (sample - 0.2).abs() < 1e-6));
+
+        // Already mixed while the headset was filled
+        let mut speakers = vec![0.0; 960 * 2];
+        mixer.fill_bus(1, &mut speakers, 2);
+        assert!(speakers.iter().all(|&sample| (sample - 0.3).abs() < 1e-6));
+
+        // A bus nobody reads keeps only a few frames
+        for _ in 0..10 {
+            mixer.fill(&mut headset, 2);
+        }
+        assert_eq!(mixer.buses[&1].queue.len(), 960 * 2 * MAX_QUEUED_FRAMES);
+    }
+
+    #[test]
+    fn test_transmissions_are_cued_on_their_radio() {
+        let mut mixer = Mixer::with_decoder_factory(
+            MixerConfig {
+                speaking_hold_frames: 2,
+                ..MixerConfig::default()
+            },
+            || Ok(FakeDecoder),
+        );
+        let mut bank = CueBank::default();
+        bank.set(CueKind::MicClick, vec![0.1; 4]);
+        bank.set(CueKind::RogerBeep, vec![0.2; 4]);
+        bank.set(CueKind::SquelchTail, vec![0.3; 4]);
+        mixer.set_cue_bank(bank);
+        mixer.set_channel_cues(
+            channel(10),
+            Some(CueConfig {
+                mic_click: true,
+                roger_beep: true,
+                squelch_tail: true,
+                volume: 1.0,
+            }),
+        );
+
+        // Silent voice so only the cues are heard
+        for sequence in 0..3 {
+            mixer
+                .push_packet(packet(user(1), channel(10), sequence, 0))
+                .unwrap();
+        }
+        let mut out = vec![0.0; 960 * 2];
+        mixer.mix_frame(&mut out);
+        assert_eq!(out[..8], [0.1; 8]);
+        assert!(out[8..].iter().all(|&sample| sample == 0.0));
+
+        // Roger beep then squelch tail once the speaker has stopped
+        for _ in 0..4 {
+            mixer.mix_frame(&mut out);
+        }
+        assert_eq!(out[..8], [0.2; 8]);
+        assert_eq!(out[8..16], [0.3; 8]);
+        assert!(out[16..].iter().all(|&sample| sample == 0.0));
+
+        // Channels without cues stay quiet
+        mixer.play_cue(channel(20), CueEvent::TransmitEnd);
+        mixer.mix_frame(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+}
@@ -0,0 +1,211 @@
+//! Playback of the mixed voice stream on output devices.
+//!
+//! Each output bus of the [`Mixer`] can play on its own device, e.g. one
+//! radio on a headset and the intercom on desk speakers. A
+//! [`PlaybackRouter`] keeps one stream per bus open on a dedicated thread,
+//! since audio streams cannot move between threads on every platform.
+
+use crate::encoder::SAMPLE_RATE;
+use crate::mixer::{Mixer, OutputBus, DEFAULT_BUS};
+use crate::processing::EchoReference;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, SampleRate};
+use fleet_net_common::error::FleetNetError;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use tracing::{error, info};
+
+fn device_error(context: &str, err: impl std::fmt::Display) -> FleetNetError {
+    FleetNetError::AudioError(Cow::Owned(format!("{context}: {err}")))
+}
+
+/// Names of the output devices on the default host.
+pub fn output_device_names() -> Result<Vec<String>, FleetNetError> {
+    let host = cpal::default_host();
+    let devices = host
+        .output_devices()
+        .map_err(|e| device_error("Failed to list output devices", e))?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+fn find_output_device(name: Option<&str>) -> Result<cpal::Device, FleetNetError> {
+    let host = cpal::default_host();
+    let device = match name {
+        None => host.default_output_device(),
+        Some(name) => host
+            .output_devices()
+            .map_err(|e| device_error("Failed to list output devices", e))?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name)),
+    };
+
+    device.ok_or_else(|| {
+        FleetNetError::AudioError(Cow::Owned(format!(
+            "Output device not found: {}",
+            name.unwrap_or("default")
+        )))
+    })
+}
+
+/// A running output stream; playback stops when it is dropped.
+pub struct PlaybackStream {
+    _stream: cpal::Stream,
+    device_name: String,
+}
+
+impl PlaybackStream {
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+}
+
+/// Starts playing `bus` of `mixer` on the named output device, or the
+/// default one.
+///
+/// The device must support 32-bit float output at [`SAMPLE_RATE`]. If the
+/// mixer is busy when the device asks for audio, silence is played for that
+/// buffer rather than blocking the audio thread. Everything played is also
+/// recorded to `echo`, if given, for the capture side's echo canceller.
+pub fn start_playback(
+    device_name: Option<&str>,
+    bus: OutputBus,
+    mixer: Arc<Mutex<Mixer>>,
+    echo: Option<Arc<EchoReference>>,
+) -> Result<PlaybackStream, FleetNetError> {
+    let device = find_output_device(device_name)?;
+    let name = device.name().unwrap_or_else(|_| "unknown".to_string());
+
+    let config = device
+        .supported_output_configs()
+        .map_err(|e| device_error("Failed to query output device", e))?
+        .filter(|range| range.sample_format() == SampleFormat::F32)
+        .find_map(|range| range.try_with_sample_rate(SampleRate(SAMPLE_RATE)))
+        .ok_or_else(|| {
+            FleetNetError::AudioError(Cow::Owned(format!(
+                "Output device {name} does not support {SAMPLE_RATE} Hz float output"
+            )))
+        })?;
+    let channels = usize::from(config.channels());
+
+    let stream = device
+        .build_output_stream(
+            &config.into(),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                match mixer.try_lock() {
+                    Ok(mut mixer) => mixer.fill_bus(bus, data, channels),
+                    Err(_) => data.fill(0.0),
+                }
+                if let Some(echo) = &echo {
+                    echo.push_interleaved(data, channels);
+                }
+            },
+            |err| error!("Output stream error: {err}"),
+            None,
+        )
+        .map_err(|e| device_error("Failed to open output stream", e))?;
+    stream
+        .play()
+        .map_err(|e| device_error("Failed to start output stream", e))?;
+
+    info!("Playing voice audio bus {bus} on {name} ({channels} channels)");
+    Ok(PlaybackStream {
+        _stream: stream,
+        device_name: name,
+    })
+}
+
+enum RouteCommand {
+    Route {
+        bus: OutputBus,
+        device_name: Option<String>,
+        reply: mpsc::Sender<Result<(), FleetNetError>>,
+    },
+    Stop {
+        bus: OutputBus,
+    },
+}
+
+/// Plays each output bus of a mixer on the device it is routed to.
+///
+/// Streams live on a background thread that exits when the router is dropped,
+/// which stops all playback.
+pub struct PlaybackRouter {
+    commands: mpsc::Sender<RouteCommand>,
+}
+
+impl PlaybackRouter {
+    /// Starts the playback thread. Only the default bus feeds `echo`, as the
+    /// echo canceller models a single speaker-to-microphone path.
+    pub fn spawn(
+        mixer: Arc<Mutex<Mixer>>,
+        echo: Option<Arc<EchoReference>>,
+    ) -> Result<Self, FleetNetError> {
+        let (commands, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("fleet-net-playback".to_string())
+            .spawn(move || run_router(receiver, mixer, echo))
+            .map_err(|e| device_error("Failed to start playback thread", e))?;
+        Ok(Self { commands })
+    }
+
+    /// Plays `bus` on the named device, or the default one. Nothing changes
+    /// if the bus already plays there.
+    pub fn route(&self, bus: OutputBus, device_name: Option<&str>) -> Result<(), FleetNetError> {
+        let (reply, response) = mpsc::channel();
+        self.commands
+            .send(RouteCommand::Route {
+                bus,
+                device_name: device_name.map(str::to_string),
+                reply,
+            })
+            .map_err(|_| stopped())?;
+        response.recv().map_err(|_| stopped())?
+    }
+
+    /// Stops playing `bus`; its audio is discarded until it is routed again.
+    pub fn stop(&self, bus: OutputBus) {
+        // If the thread has exited nothing is playing anyway.
+        let _ = self.commands.send(RouteCommand::Stop { bus });
+    }
+}
+
+fn stopped() -> FleetNetError {
+    FleetNetError::AudioError(Cow::Borrowed("Playback thread has stopped"))
+}
+
+fn run_router(
+    commands: mpsc::Receiver<RouteCommand>,
+    mixer: Arc<Mutex<Mixer>>,
+    echo: Option<Arc<EchoReference>>,
+) {
+    let mut streams: HashMap<OutputBus, (Option<String>, PlaybackStream)> = HashMap::new();
+    for command in commands {
+        match command {
+            RouteCommand::Route {
+                bus,
+                device_name,
+                reply,
+            } => {
+                if streams
+                    .get(&bus)
+                    .is_some_and(|(current, _)| *current == device_name)
+                {
+                    let _ = reply.send(Ok(()));
+                    continue;
+                }
+                // Close the old stream first; some devices allow one stream only.
+                streams.remove(&bus);
+                let echo = echo.clone().filter(|_| bus == DEFAULT_BUS);
+                let result = start_playback(device_name.as_deref(), bus, mixer.clone(), echo).map(
+                    |stream| {
+                        streams.insert(bus, (device_name, stream));
+                    },
+                );
+                let _ = reply.send(result);
+            }
+            RouteCommand::Stop { bus } => {
+                streams.remove(&bus);
+            }
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! Echo cancellation and automatic gain control for captured audio.
+//!
+//! Users on speakers rather than a headset would otherwise transmit everyone
+//! they hear back onto the channel. The echo canceller learns the path from
+//! the speakers to the microphone with an NLMS adaptive filter and subtracts
+//! the predicted echo, using the played audio from an [`EchoReference`].
+//!
+//! Gain control then brings quiet microphones up to a consistent level.
+//! Gain rises slowly and falls quickly, and it is held while only background
+//! noise is present so noise is never pumped up between words.
+
+use crate::encoder::SAMPLE_RATE;
+use crate::vad::rms_db;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Buffers quieter than this are treated as background noise by gain control.
+const GAIN_NOISE_GATE_DB: f32 = -50.0;
+
+/// How quickly gain changes, in dB per second.
+const GAIN_RISE_DB: f32 = 10.0;
+const GAIN_FALL_DB: f32 = 60.0;
+
+/// Adaptation step size of the echo canceller, between 0 and 2.
+const ECHO_STEP: f32 = 0.5;
+
+/// Far-end audio kept for the echo canceller; older audio is dropped so the
+/// reference cannot drift arbitrarily far behind the microphone.
+const REFERENCE_CAPACITY: usize = SAMPLE_RATE as usize / 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProcessingConfig {
+    pub echo_cancellation: bool,
+    /// Longest echo path the canceller models, in milliseconds.
+    pub echo_tail_ms: u16,
+    pub gain_control: bool,
+    /// Level gain control aims for, in dBFS.
+    pub target_level_db: f32,
+    /// Most gain control may amplify or attenuate, in dB.
+    pub max_gain_db: f32,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            echo_cancellation: false,
+            echo_tail_ms: 64,
+            gain_control: false,
+            target_level_db: -18.0,
+            max_gain_db: 24.0,
+        }
+    }
+}
+
+/// Mono audio sent to the speakers, shared between playback and capture.
+#[derive(Debug, Default)]
+pub struct EchoReference {
+    samples: Mutex<VecDeque<f32>>,
+}
+
+impl EchoReference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a buffer of interleaved output with `channels` channels.
+    pub fn push_interleaved(&self, data: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let mut samples = self.samples.lock().unwrap();
+        samples.extend(
+            data.chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        let excess = samples.len().saturating_sub(REFERENCE_CAPACITY);
+        samples.drain(..excess);
+    }
+
+    /// Takes the oldest `out.len()` samples, padding with silence if playback
+    /// has fallen behind.
+    pub fn pull(&self, out: &mut [f32]) {
+        let mut samples = self.samples.lock().unwrap();
+        let available = samples.len().min(out.len());
+        for (dst, sample) in out.iter_mut().zip(samples.drain(..available)) {
+            *dst = sample;
+        }
+        out[available..].fill(0.0);
+    }
+}
+
+/// NLMS adaptive filter subtracting the far-end echo from the microphone.
+#[derive(Debug)]
+pub struct EchoCanceller {
+    weights: Vec<f32>,
+    /// Far-end history stored twice so the newest-first window is always a
+    /// contiguous slice.
+    history: Vec<f32>,
+    position: usize,
+    /// Energy of the far-end window.
+    far_power: f32,
+}
+
+impl EchoCanceller {
+    pub fn new(taps: usize) -> Self {
+        let taps = taps.max(1);
+        Self {
+            weights: vec![0.0; taps],
+            history: vec![0.0; taps * 2],
+            position: 0,
+            far_power: 0.0,
+        }
+    }
+
+    pub fn taps(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Removes the echo of `far` from `near` in place. Both buffers must be
+    /// the same length and cover the same moment in time.
+    pub fn process(&mut self, near: &mut [f32], far: &[f32]) {
+        debug_assert_eq!(near.len(), far.len());
+        let taps = self.taps();
+
+        let far_peak = self
+            .history
+            .iter()
+            .chain(far)
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let near_peak = near.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        // The echo path never amplifies, so near-end audio louder than anything
+        // recently played is the local user talking; adapting on it would
+        // teach the filter to cancel their voice.
+        let double_talk = near_peak > far_peak;
+
+        for (sample, &x) in near.iter_mut().zip(far) {
+            self.position = (self.position + taps - 1) % taps;
+            let oldest = self.history[self.position];
+            self.history[self.position] = x;
+            self.history[self.position + taps] = x;
+            self.far_power = (self.far_power + x * x - oldest * oldest).max(0.0);
+
+            let window = &self.history[self.position..self.position + taps];
+            let estimate: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+            let error = *sample - estimate;
+
+            if !double_talk && self.far_power > f32::EPSILON {
+                let step = ECHO_STEP * error / (self.far_power + f32::EPSILON);
+                for (w, x) in self.weights.iter_mut().zip(window) {
+                    *w += step * x;
+                }
+            }
+            *sample = error;
+        }
+    }
+}
+
+/// Slowly adapting gain toward a target speech level.
+#[derive(Debug)]
+pub struct GainControl {
+    target_db: f32,
+    max_gain_db: f32,
+    gain_db: f32,
+}
+
+impl GainControl {
+    pub fn new(target_db: f32, max_gain_db: f32) -> Self {
+        Self {
+            target_db,
+            max_gain_db: max_gain_db.abs(),
+            gain_db: 0.0,
+        }
+    }
+
+    /// Gain currently applied, in dB.
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let level = rms_db(samples);
+        let previous = self.gain_db;
+        if level > GAIN_NOISE_GATE_DB {
+            let desired = (self.target_db - level).clamp(-self.max_gain_db, self.max_gain_db);
+            let seconds = samples.len() as f32 / SAMPLE_RATE as f32;
+            self.gain_db = if desired > previous {
+                (previous + GAIN_RISE_DB * seconds).min(desired)
+            } else {
+                (previous - GAIN_FALL_DB * seconds).max(desired)
+            };
+        }
+
+        // Ramp across the buffer to avoid clicks when the gain moves.
+        let from = db_to_gain(previous);
+        let to = db_to_gain(self.gain_db);
+        let step = (to - from) / samples.len() as f32;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = (*sample * (from + step * i as f32)).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// The processing chain applied to captured mono audio before it is
+/// metered, analyzed for voice activity and encoded.
+#[derive(Debug)]
+pub struct AudioProcessor {
+    config: ProcessingConfig,
+    echo: Option<EchoCanceller>,
+    gain: Option<GainControl>,
+    far: Vec<f32>,
+}
+
+impl AudioProcessor {
+    pub fn new(config: ProcessingConfig) -> Self {
+        let mut processor = Self {
+            config,
+            echo: None,
+            gain: None,
+            far: Vec::new(),
+        };
+        processor.rebuild();
+        processor
+    }
+
+    pub fn config(&self) -> &ProcessingConfig {
+        &self.config
+    }
+
+    /// Applies new settings; stages are only reset when their settings change.
+    pub fn set_config(&mut self, config: ProcessingConfig) {
+        if config != self.config {
+            self.config = config;
+            self.rebuild();
+        }
+    }
+
+    fn rebuild(&mut self) {
+        let taps = SAMPLE_RATE as usize / 1000 * usize::from(self.config.echo_tail_ms);
+        self.echo = self
+            .config
+            .echo_cancellation
+            .then(|| match self.echo.take() {
+                Some(echo) if echo.taps() == taps => echo,
+                _ => EchoCanceller::new(taps),
+            });
+        self.gain = self
+            .config
+            .gain_control
+            .then(|| GainControl::new(self.config.target_level_db, self.config.max_gain_db));
+    }
+
+    pub fn process(&mut self, samples: &mut [f32], reference: Option<&EchoReference>) {
+        if let (Some(echo), Some(reference)) = (&mut self.echo, reference) {
+            self.far.resize(samples.len(), 0.0);
+            reference.pull(&mut self.far);
+            echo.process(samples, &self.far);
+        }
+        if let Some(gain) = &mut self.gain {
+            gain.process(samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic white noise in `[-amplitude, amplitude]`.
+    fn noise(len: usize, amplitude: f32, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 * amplitude - amplitude
+            })
+            .collect()
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum()
+    }
+
+    #[test]
+    fn test_echo_canceller_converges_on_delayed_echo() {
+        let far = noise(48_000, 0.5, 1);
+        // The room returns the far end 12 samples later at half volume, plus a reflection
+        let mut near: Vec<f32> = (0..far.len())
+            .map(|i| {
+                let direct = if i >= 12 { far[i - 12] * 0.5 } else { 0.0 };
+                let reflection = if i >= 30 { far[i - 30] * -0.2 } else { 0.0 };
+                direct + reflection
+            })
+            .collect();
+        let original = near.clone();
+
+        let mut canceller = EchoCanceller::new(64);
+        for (near, far) in near.chunks_mut(480).zip(far.chunks(480)) {
+            canceller.process(near, far);
+        }
+
+        let tail = far.len() - 4800..;
+        let reduction_db = 10.0 * (energy(&original[tail.clone()]) / energy(&near[tail])).log10();
+        assert!(
+            reduction_db > 30.0,
+            "only {reduction_db:.1} dB of echo removed"
+        );
+    }
+
+    #[test]
+    fn test_echo_canceller_keeps_local_speech() {
+        let speech = noise(4800, 0.3, 2);
+        let mut near = speech.clone();
+        let mut canceller = EchoCanceller::new(64);
+
+        // Nothing is playing, so nothing is removed
+        for near in near.chunks_mut(480) {
+            canceller.process(near, &[0.0; 480]);
+        }
+        assert_eq!(near, speech);
+    }
+
+    #[test]
+    fn test_gain_control_raises_quiet_speech_but_not_noise() {
+        let mut gain = GainControl::new(-18.0, 24.0);
+        // 4 seconds at -40 dBFS
+        for _ in 0..400 {
+            let mut quiet = vec![0.01; 480];
+            gain.process(&mut quiet);
+        }
+        assert!(
+            (gain.gain_db() - 22.0).abs() < 0.1,
+            "gain {}",
+            gain.gain_db()
+        );
+
+        let mut gain = GainControl::new(-18.0, 24.0);
+        for _ in 0..400 {
+            let mut hiss = noise(480, 0.001, 3);
+            gain.process(&mut hiss);
+        }
+        assert_eq!(gain.gain_db(), 0.0);
+
+        // Loud input is brought down within half a second
+        let mut gain = GainControl::new(-18.0, 24.0);
+        for _ in 0..50 {
+            let mut loud = vec![0.9; 480];
+            gain.process(&mut loud);
+        }
+        assert!(
+            (gain.gain_db() - -17.08).abs() < 0.1,
+            "gain {}",
+            gain.gain_db()
+        );
+    }
+
+    #[test]
+    fn test_processor_pulls_reference_only_for_echo_cancellation() {
+        let reference = EchoReference::new();
+        reference.push_interleaved(&[0.5, 0.5, 0.25, 0.25], 2);
+
+        let mut processor = AudioProcessor::new(ProcessingConfig::default());
+        let mut samples = [0.1, 0.1];
+        processor.process(&mut samples, Some(&reference));
+        assert_eq!(samples, [0.1, 0.1]);
+
+        processor.set_config(ProcessingConfig {
+            echo_cancellation: true,
+            ..ProcessingConfig::default()
+        });
+        processor.process(&mut samples, Some(&reference));
+        let mut rest = [1.0; 2];
+        reference.pull(&mut rest);
+        assert_eq!(rest, [0.0, 0.0]);
+    }
+}
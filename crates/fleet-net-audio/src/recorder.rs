@@ -0,0 +1,405 @@
+//! Multitrack session recording for debriefs.
+//!
+//! Track 0 holds what this client transmitted and every other track one
+//! remote speaker, assigned the first time they talk. Once every track is
+//! taken, later speakers share the last one. Audio is written as a 16-bit
+//! multichannel WAV file with a `.json` sidecar naming who is on each track.
+//!
+//! The recording is clocked by the mixer, so it advances while audio is being
+//! played. Frames are handed to a writer thread, which also ends the recording
+//! early if free disk space drops below a safety margin.
+
+use crate::encoder::SAMPLE_RATE;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::types::UserId;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use tracing::warn;
+
+/// Frames queued for the writer thread before new ones are dropped.
+const WRITE_QUEUE_FRAMES: usize = 250;
+
+/// Transmitted audio kept while waiting for the mixer to write it.
+const TRANSMIT_BACKLOG: usize = SAMPLE_RATE as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecorderConfig {
+    /// Tracks in the file, including the transmit track.
+    pub max_tracks: u16,
+    /// Recording stops when less than this much disk space is left.
+    pub min_free_bytes: u64,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            max_tracks: 8,
+            min_free_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrackLabel {
+    pub track: u16,
+    /// Speakers heard on this track; empty for the transmit track.
+    pub user_ids: Vec<UserId>,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordingSummary {
+    pub path: PathBuf,
+    pub duration_ms: u64,
+    pub tracks: Vec<TrackLabel>,
+    /// Why the recording ended before it was stopped, if it did.
+    pub stopped_early: Option<String>,
+}
+
+/// Records every speaker and the local transmission while started.
+///
+/// Shared between the mixer, the capture stream and the UI.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    config: RecorderConfig,
+    active: Mutex<Option<ActiveRecording>>,
+    labels: Mutex<HashMap<UserId, String>>,
+}
+
+#[derive(Debug)]
+struct ActiveRecording {
+    path: PathBuf,
+    /// Speakers on each track after the transmit track.
+    speakers: Vec<Vec<UserId>>,
+    /// This frame's audio per track, before interleaving.
+    pending: Vec<Vec<f32>>,
+    transmit: VecDeque<f32>,
+    frames: SyncSender<Vec<i16>>,
+    writer: JoinHandle<WriterResult>,
+}
+
+#[derive(Debug)]
+struct WriterResult {
+    samples_per_track: u64,
+    stopped_early: Option<String>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a recording is running; false once the writer gave up.
+    pub fn is_recording(&self) -> bool {
+        self.active
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|active| !active.writer.is_finished())
+    }
+
+    /// Names `user_id` in the sidecar of current and future recordings.
+    pub fn set_label(&self, user_id: UserId, label: impl Into<String>) {
+        self.labels.lock().unwrap().insert(user_id, label.into());
+    }
+
+    /// Starts recording to the WAV file at `path`.
+    pub fn start(&self, path: &Path) -> Result<(), FleetNetError> {
+        let mut active = self.active.lock().unwrap();
+        if active.is_some() {
+            return Err(FleetNetError::AudioError(Cow::Borrowed(
+                "A recording is already running",
+            )));
+        }
+
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        check_free_space(dir, self.config.min_free_bytes)?;
+
+        let tracks = self.config.max_tracks.max(2);
+        let spec = hound::WavSpec {
+            channels: tracks,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let wav = hound::WavWriter::create(path, spec).map_err(wav_error)?;
+
+        let (frames, frames_rx) = mpsc::sync_channel(WRITE_QUEUE_FRAMES);
+        let (dir, min_free_bytes) = (dir.to_path_buf(), self.config.min_free_bytes);
+        let writer = std::thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || write_frames(wav, frames_rx, tracks, &dir, min_free_bytes))?;
+
+        *active = Some(ActiveRecording {
+            path: path.to_path_buf(),
+            speakers: Vec::new(),
+            pending: vec![Vec::new(); usize::from(tracks)],
+            transmit: VecDeque::new(),
+            frames,
+            writer,
+        });
+        Ok(())
+    }
+
+    /// Stops recording, finishes the file and writes its track labels.
+    pub fn stop(&self) -> Result<RecordingSummary, FleetNetError> {
+        let active = self
+            .active
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(FleetNetError::AudioError(Cow::Borrowed(
+                "No recording is running",
+            )))?;
+        let ActiveRecording {
+            path,
+            speakers,
+            frames,
+            writer,
+            ..
+        } = active;
+
+        // Closing the queue lets the writer finish the file.
+        drop(frames);
+        let result = writer
+            .join()
+            .map_err(|_| FleetNetError::AudioError(Cow::Borrowed("Recording writer panicked")))?;
+
+        let labels = self.labels.lock().unwrap();
+        let mut tracks = vec![TrackLabel {
+            track: 0,
+            user_ids: Vec::new(),
+            label: "Transmitted".to_string(),
+        }];
+        tracks.extend(speakers.into_iter().enumerate().map(|(i, user_ids)| {
+            let label = match user_ids.as_slice() {
+                [user_id] => labels
+                    .get(user_id)
+                    .cloned()
+                    .unwrap_or_else(|| format!("User {user_id}")),
+                _ => "Other speakers".to_string(),
+            };
+            TrackLabel {
+                track: i as u16 + 1,
+                user_ids,
+                label,
+            }
+        }));
+
+        let summary = RecordingSummary {
+            duration_ms: result.samples_per_track * 1000 / u64::from(SAMPLE_RATE),
+            path,
+            tracks,
+            stopped_early: result.stopped_early,
+        };
+        let sidecar = serde_json::to_vec_pretty(&summary)?;
+        std::fs::write(summary.path.with_extension("json"), sidecar)?;
+        Ok(summary)
+    }
+
+    /// Queues audio the local user transmitted.
+    pub fn record_transmit(&self, samples: &[f32]) {
+        let mut active = self.active.lock().unwrap();
+        let Some(active) = active.as_mut() else {
+            return;
+        };
+        active.transmit.extend(samples);
+        let excess = active.transmit.len().saturating_sub(TRANSMIT_BACKLOG);
+        active.transmit.drain(..excess);
+    }
+
+    /// Adds one speaker's decoded audio to the frame being mixed.
+    pub fn record_speaker(&self, user_id: UserId, samples: &[f32]) {
+        let mut active = self.active.lock().unwrap();
+        let Some(active) = active.as_mut() else {
+            return;
+        };
+        let track = active.track_for(user_id);
+        let pending = &mut active.pending[track];
+        if pending.len() < samples.len() {
+            pending.resize(samples.len(), 0.0);
+        }
+        for (dst, sample) in pending.iter_mut().zip(samples) {
+            *dst += sample;
+        }
+    }
+
+    /// Writes the frame of `frame_size` samples per track mixed since the
+    /// last call.
+    pub fn end_frame(&self, frame_size: usize) {
+        let mut active = self.active.lock().unwrap();
+        let Some(active) = active.as_mut() else {
+            return;
+        };
+
+        let available = active.transmit.len().min(frame_size);
+        active.pending[0].clear();
+        active.pending[0].extend(active.transmit.drain(..available));
+
+        let tracks = active.pending.len();
+        let mut frame = vec![0i16; frame_size * tracks];
+        for (track, samples) in active.pending.iter_mut().enumerate() {
+            for (i, sample) in samples.iter().take(frame_size).enumerate() {
+                frame[i * tracks + track] = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            }
+            samples.clear();
+        }
+
+        match active.frames.try_send(frame) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => warn!("Recording writer is behind, dropping a frame"),
+        }
+    }
+}
+
+impl ActiveRecording {
+    fn track_for(&mut self, user_id: UserId) -> usize {
+        if let Some(index) = self.speakers.iter().position(|ids| ids.contains(&user_id)) {
+            return index + 1;
+        }
+        if self.speakers.len() + 1 < self.pending.len() {
+            self.speakers.push(vec![user_id]);
+        } else {
+            self.speakers.last_mut().unwrap().push(user_id);
+        }
+        self.speakers.len()
+    }
+}
+
+fn write_frames(
+    mut wav: hound::WavWriter<BufWriter<File>>,
+    frames: mpsc::Receiver<Vec<i16>>,
+    tracks: u16,
+    dir: &Path,
+    min_free_bytes: u64,
+) -> WriterResult {
+    let mut samples_per_track = 0u64;
+    let mut since_check = 0u64;
+    let mut stopped_early = None;
+
+    for frame in frames {
+        if let Err(e) = frame
+            .iter()
+            .try_for_each(|&sample| wav.write_sample(sample))
+        {
+            stopped_early = Some(format!("Failed to write recording: {e}"));
+            break;
+        }
+        let written = (frame.len() / usize::from(tracks)) as u64;
+        samples_per_track += written;
+        since_check += written;
+
+        // Once a second, keep the header valid and make sure the disk has room.
+        if since_check >= u64::from(SAMPLE_RATE) {
+            since_check = 0;
+            if let Err(e) = wav.flush() {
+                warn!("Failed to flush recording: {e}");
+            }
+            if let Err(e) = check_free_space(dir, min_free_bytes) {
+                warn!("Stopping recording: {e}");
+                stopped_early = Some(e.to_string());
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = wav.finalize() {
+        stopped_early.get_or_insert(format!("Failed to finish recording: {e}"));
+    }
+    WriterResult {
+        samples_per_track,
+        stopped_early,
+    }
+}
+
+fn check_free_space(dir: &Path, min_free_bytes: u64) -> Result<(), FleetNetError> {
+    let available = fs4::available_space(dir)?;
+    if available < min_free_bytes {
+        return Err(FleetNetError::AudioError(Cow::Owned(format!(
+            "Only {} MiB of disk space left",
+            available / (1024 * 1024)
+        ))));
+    }
+    Ok(())
+}
+
+fn wav_error(err: hound::Error) -> FleetNetError {
+    FleetNetError::AudioError(Cow::Owned(format!("Failed to create recording: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn recorder(max_tracks: u16) -> Recorder {
+        Recorder::new(RecorderConfig {
+            max_tracks,
+            min_free_bytes: 0,
+        })
+    }
+
+    #[test]
+    fn test_records_speakers_on_their_own_tracks() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("session.wav");
+        let recorder = recorder(3);
+        recorder.set_label(user(7), "Viper");
+
+        recorder.start(&path).unwrap();
+        assert!(recorder.is_recording());
+        recorder.record_transmit(&[0.5; 4]);
+        recorder.record_speaker(user(7), &[0.25; 4]);
+        recorder.record_speaker(user(9), &[-0.25; 4]);
+        recorder.end_frame(4);
+        // A third speaker shares the last track
+        recorder.record_speaker(user(11), &[0.25; 4]);
+        recorder.end_frame(4);
+        let summary = recorder.stop().unwrap();
+        assert!(!recorder.is_recording());
+
+        assert_eq!(summary.stopped_early, None);
+        let labels: Vec<_> = summary.tracks.iter().map(|t| t.label.as_str()).collect();
+        assert_eq!(labels, ["Transmitted", "Viper", "Other speakers"]);
+        assert_eq!(summary.tracks[2].user_ids, vec![user(9), user(11)]);
+        assert!(path.with_extension("json").exists());
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 3);
+        assert_eq!(reader.duration(), 8);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(&samples[..3], &[16383, 8191, -8191]);
+        assert_eq!(&samples[12..15], &[0, 0, 8191]);
+    }
+
+    #[test]
+    fn test_refuses_to_start_without_disk_space() {
+        let dir = TempDir::new().unwrap();
+        let recorder = Recorder::new(RecorderConfig {
+            min_free_bytes: u64::MAX,
+            ..RecorderConfig::default()
+        });
+
+        let result = recorder.start(&dir.path().join("session.wav"));
+        assert!(matches!(result, Err(FleetNetError::AudioError(_))));
+        assert!(!recorder.is_recording());
+        assert!(recorder.stop().is_err());
+    }
+}
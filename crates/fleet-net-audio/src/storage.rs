@@ -0,0 +1,461 @@
+//! Where finished recordings are kept, and for how long.
+//!
+//! The recorder always writes to local disk. Once a recording is stopped, a
+//! [`RecordingUploader`] copies the WAV file and its `.json` sidecar to a
+//! [`RecordingStorage`], either a local directory (e.g. a network share) or
+//! an S3-compatible bucket, and deletes the local copies once stored. Long
+//! after-action recordings then don't pile up on the machine that made them.
+//! Buckets need the `s3` feature.
+//!
+//! After every upload the storage is pruned under its [`RetentionPolicy`].
+//! A recording is the WAV file and sidecar sharing a name, and they are
+//! always removed together, oldest recordings first.
+
+use fleet_net_common::error::FleetNetError;
+use futures_util::TryStreamExt;
+#[cfg(feature = "s3")]
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectMeta, ObjectStore, WriteMultipart};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Size of the parts recordings are uploaded in.
+const UPLOAD_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+
+/// Attempts at storing a recording before it is left on local disk.
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for each one after.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Which backend recordings are stored in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// A directory, created if missing.
+    Local { dir: PathBuf },
+    /// A bucket on S3 or a compatible service such as MinIO.
+    #[cfg(feature = "s3")]
+    S3(S3Config),
+}
+
+#[cfg(feature = "s3")]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Service URL for S3-compatible services; AWS when `None`.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Key prefix recordings are stored under, e.g. `squadron/aar`.
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// Keeps the secret out of logs.
+#[cfg(feature = "s3")]
+impl std::fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Config")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("endpoint", &self.endpoint)
+            .field("prefix", &self.prefix)
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Which stored recordings are removed; all are kept by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Recordings older than this many days are removed.
+    #[serde(default)]
+    pub max_age_days: Option<u32>,
+    /// Beyond this many bytes in total, the oldest recordings are removed.
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// The recordings this policy removes at `now`, oldest first.
+    pub fn expired<'a>(
+        &self,
+        recordings: &'a [StoredRecording],
+        now: SystemTime,
+    ) -> Vec<&'a StoredRecording> {
+        let cutoff = self
+            .max_age_days
+            .and_then(|days| now.checked_sub(Duration::from_secs(u64::from(days) * 86_400)));
+
+        let mut newest_first: Vec<_> = recordings.iter().collect();
+        newest_first.sort_by_key(|recording| std::cmp::Reverse(recording.modified));
+        let mut total = 0u64;
+        let mut expired: Vec<_> = newest_first
+            .into_iter()
+            .filter(|recording| {
+                total = total.saturating_add(recording.bytes);
+                cutoff.is_some_and(|cutoff| recording.modified < cutoff)
+                    || self.max_total_bytes.is_some_and(|max| total > max)
+            })
+            .collect();
+        expired.reverse();
+        expired
+    }
+}
+
+/// A recording in storage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StoredRecording {
+    /// File name without extension, e.g. `session-1718000000`.
+    pub name: String,
+    /// Size of its files together.
+    pub bytes: u64,
+    /// When its newest file was last written.
+    pub modified: SystemTime,
+}
+
+/// A place recordings are stored in.
+#[derive(Debug, Clone)]
+pub struct RecordingStorage {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    /// The directory of a local backend, which needs no upload from itself.
+    local_dir: Option<PathBuf>,
+}
+
+impl RecordingStorage {
+    pub fn new(config: &StorageConfig) -> Result<Self, FleetNetError> {
+        match config {
+            StorageConfig::Local { dir } => {
+                std::fs::create_dir_all(dir)?;
+                let dir = dir.canonicalize()?;
+                let store = LocalFileSystem::new_with_prefix(&dir).map_err(storage_error)?;
+                Ok(Self {
+                    store: Arc::new(store),
+                    prefix: ObjectPath::default(),
+                    local_dir: Some(dir),
+                })
+            }
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(s3) => {
+                let mut builder = AmazonS3Builder::new()
+                    .with_bucket_name(&s3.bucket)
+                    .with_region(&s3.region)
+                    .with_access_key_id(&s3.access_key_id)
+                    .with_secret_access_key(&s3.secret_access_key);
+                if let Some(endpoint) = &s3.endpoint {
+                    builder = builder
+                        .with_endpoint(endpoint)
+                        .with_allow_http(endpoint.starts_with("http://"));
+                }
+                let store = builder.build().map_err(storage_error)?;
+                Ok(Self {
+                    store: Arc::new(store),
+                    prefix: ObjectPath::from(s3.prefix.as_str()),
+                    local_dir: None,
+                })
+            }
+        }
+    }
+
+    /// Whether `file` is already in this storage, i.e. written straight into
+    /// the directory of a local backend.
+    pub fn holds(&self, file: &Path) -> bool {
+        let Some(local_dir) = &self.local_dir else {
+            return false;
+        };
+        file.parent()
+            .and_then(|dir| dir.canonicalize().ok())
+            .is_some_and(|dir| dir == *local_dir)
+    }
+
+    /// Copies `file` into storage under its file name, in parts so large
+    /// recordings are never held in memory. Returns the bytes stored.
+    pub async fn store(&self, file: &Path) -> Result<u64, FleetNetError> {
+        let name = file.file_name().and_then(|name| name.to_str()).ok_or(
+            FleetNetError::FileSystemError(Cow::Borrowed("Recording has no file name")),
+        )?;
+        let location = self.prefix.child(name);
+
+        let upload = self
+            .store
+            .put_multipart(&location)
+            .await
+            .map_err(storage_error)?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, UPLOAD_CHUNK_BYTES);
+        let mut source = tokio::fs::File::open(file).await?;
+        let mut buffer = vec![0; UPLOAD_CHUNK_BYTES];
+        let mut bytes = 0u64;
+        loop {
+            let read = match source.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    let _ = writer.abort().await;
+                    return Err(e.into());
+                }
+            };
+            if let Err(e) = writer.wait_for_capacity(2).await {
+                let _ = writer.abort().await;
+                return Err(storage_error(e));
+            }
+            writer.write(&buffer[..read]);
+            bytes += read as u64;
+        }
+        writer.finish().await.map_err(storage_error)?;
+        Ok(bytes)
+    }
+
+    /// Every stored recording, in no particular order.
+    pub async fn list(&self) -> Result<Vec<StoredRecording>, FleetNetError> {
+        Ok(group_recordings(&self.objects().await?))
+    }
+
+    /// Removes the recordings `policy` expires at `now` and returns them.
+    pub async fn prune(
+        &self,
+        policy: &RetentionPolicy,
+        now: SystemTime,
+    ) -> Result<Vec<StoredRecording>, FleetNetError> {
+        let objects = self.objects().await?;
+        let recordings = group_recordings(&objects);
+        let expired: Vec<_> = policy
+            .expired(&recordings, now)
+            .into_iter()
+            .cloned()
+            .collect();
+
+        for object in &objects {
+            let name = object.location.filename().map(recording_name);
+            if expired
+                .iter()
+                .any(|recording| Some(recording.name.as_str()) == name)
+            {
+                self.store
+                    .delete(&object.location)
+                    .await
+                    .map_err(storage_error)?;
+            }
+        }
+        Ok(expired)
+    }
+
+    async fn objects(&self) -> Result<Vec<ObjectMeta>, FleetNetError> {
+        self.store
+            .list(Some(&self.prefix))
+            .try_collect()
+            .await
+            .map_err(storage_error)
+    }
+}
+
+/// Groups stored files into the recordings they belong to.
+fn group_recordings(objects: &[ObjectMeta]) -> Vec<StoredRecording> {
+    let mut recordings: HashMap<&str, StoredRecording> = HashMap::new();
+    for object in objects {
+        let Some(name) = object.location.filename().map(recording_name) else {
+            continue;
+        };
+        let modified = SystemTime::from(object.last_modified);
+        let recording = recordings.entry(name).or_insert_with(|| StoredRecording {
+            name: name.to_string(),
+            bytes: 0,
+            modified,
+        });
+        recording.bytes += object.size;
+        recording.modified = recording.modified.max(modified);
+    }
+    recordings.into_values().collect()
+}
+
+/// The recording a stored file belongs to: its name up to the extension.
+fn recording_name(file_name: &str) -> &str {
+    file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem)
+}
+
+fn storage_error(err: object_store::Error) -> FleetNetError {
+    FleetNetError::FileSystemError(Cow::Owned(format!("Recording storage failed: {err}")))
+}
+
+/// Hands finished recordings to a background task that stores them.
+#[derive(Debug, Clone)]
+pub struct RecordingUploader {
+    queue: mpsc::UnboundedSender<PathBuf>,
+}
+
+impl RecordingUploader {
+    /// The uploader and the task doing its work, which the caller spawns on
+    /// its runtime. The task ends once every uploader is dropped.
+    pub fn new(
+        storage: RecordingStorage,
+        policy: RetentionPolicy,
+    ) -> (Self, impl Future<Output = ()> + Send) {
+        let (queue, files) = mpsc::unbounded_channel();
+        (Self { queue }, upload_recordings(storage, policy, files))
+    }
+
+    /// Queues the WAV file at `path` and its sidecar for storage.
+    pub fn upload(&self, path: &Path) {
+        // Only fails once the task is gone, leaving the files where they are
+        let _ = self.queue.send(path.to_path_buf());
+    }
+}
+
+async fn upload_recordings(
+    storage: RecordingStorage,
+    policy: RetentionPolicy,
+    mut files: mpsc::UnboundedReceiver<PathBuf>,
+) {
+    prune(&storage, &policy).await;
+    while let Some(path) = files.recv().await {
+        if !storage.holds(&path) {
+            upload(&storage, &path).await;
+        }
+        prune(&storage, &policy).await;
+    }
+}
+
+/// Stores a recording with its sidecar, retrying with backoff, and deletes
+/// the local files once both are stored.
+async fn upload(storage: &RecordingStorage, path: &Path) {
+    let sidecar = path.with_extension("json");
+    let mut delay = RETRY_DELAY;
+    for attempt in 1..=UPLOAD_ATTEMPTS {
+        let stored = match storage.store(path).await {
+            Ok(bytes) if sidecar.exists() => storage.store(&sidecar).await.map(|_| bytes),
+            result => result,
+        };
+        match stored {
+            Ok(bytes) => {
+                info!(bytes, "Stored recording {}", path.display());
+                for file in [path, sidecar.as_path()] {
+                    if let Err(e) = std::fs::remove_file(file) {
+                        warn!("Failed to remove {}: {e}", file.display());
+                    }
+                }
+                return;
+            }
+            Err(e) if attempt < UPLOAD_ATTEMPTS => {
+                warn!("Failed to store {}, retrying: {e}", path.display());
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => warn!("Leaving {} on local disk: {e}", path.display()),
+        }
+    }
+}
+
+async fn prune(storage: &RecordingStorage, policy: &RetentionPolicy) {
+    match storage.prune(policy, SystemTime::now()).await {
+        Ok(removed) => {
+            for recording in removed {
+                info!(
+                    "Removed recording {} under the retention policy",
+                    recording.name
+                );
+            }
+        }
+        Err(e) => warn!("Failed to prune recordings: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn recording(name: &str, bytes: u64, age_days: u32) -> StoredRecording {
+        StoredRecording {
+            name: name.to_string(),
+            bytes,
+            modified: SystemTime::UNIX_EPOCH + DAY * (100 - age_days),
+        }
+    }
+
+    fn names(recordings: &[&StoredRecording]) -> Vec<String> {
+        recordings.iter().map(|r| r.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_retention_removes_oldest_recordings_first() {
+        let now = SystemTime::UNIX_EPOCH + DAY * 100;
+        let recordings = [
+            recording("b", 40, 5),
+            recording("d", 40, 0),
+            recording("a", 40, 30),
+            recording("c", 40, 2),
+        ];
+        assert!(RetentionPolicy::default()
+            .expired(&recordings, now)
+            .is_empty());
+
+        let by_age = RetentionPolicy {
+            max_age_days: Some(7),
+            max_total_bytes: None,
+        };
+        assert_eq!(names(&by_age.expired(&recordings, now)), ["a"]);
+
+        let by_size = RetentionPolicy {
+            max_age_days: None,
+            max_total_bytes: Some(100),
+        };
+        assert_eq!(names(&by_size.expired(&recordings, now)), ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_recordings_and_prunes_storage() {
+        let recordings = TempDir::new().unwrap();
+        let stored = TempDir::new().unwrap();
+        let storage = RecordingStorage::new(&StorageConfig::Local {
+            dir: stored.path().to_path_buf(),
+        })
+        .unwrap();
+        let policy = RetentionPolicy {
+            max_age_days: None,
+            max_total_bytes: Some(20),
+        };
+        let (uploader, task) = RecordingUploader::new(storage.clone(), policy);
+        let task = tokio::spawn(task);
+
+        for (name, contents) in [("first", "0123456789"), ("second", "01234567")] {
+            let path = recordings.path().join(format!("{name}.wav"));
+            std::fs::write(&path, contents).unwrap();
+            std::fs::write(path.with_extension("json"), "{}").unwrap();
+            assert!(!storage.holds(&path));
+            uploader.upload(&path);
+            // Modification times order the recordings
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        drop(uploader);
+        task.await.unwrap();
+
+        // Uploaded recordings leave local disk
+        assert_eq!(std::fs::read_dir(recordings.path()).unwrap().count(), 0);
+        // 24 bytes with both, so the first went
+        let listed = storage.list().await.unwrap();
+        assert_eq!(names(&listed.iter().collect::<Vec<_>>()), ["second"]);
+        assert_eq!(listed[0].bytes, 10);
+        assert_eq!(
+            std::fs::read_to_string(stored.path().join("second.wav")).unwrap(),
+            "01234567"
+        );
+        assert!(storage.holds(&stored.path().join("second.wav")));
+    }
+}
@@ -0,0 +1,200 @@
+//! Energy-based voice activity detection.
+//!
+//! The detector tracks the background noise floor of the microphone and
+//! opens when a buffer is sufficiently louder than it. A hangover keeps the
+//! transmission open through short pauses between words.
+
+use crate::encoder::SAMPLE_RATE;
+use crate::level::SILENCE_DB;
+use std::time::Duration;
+
+/// Quietest level considered speech regardless of the noise floor, in dBFS.
+const MIN_SPEECH_DB: f32 = -55.0;
+
+/// How quickly the noise floor rises, in dB per second, while idle and while
+/// speech is detected. The floor drops immediately to quieter levels.
+const FLOOR_RISE_IDLE_DB: f32 = 3.0;
+const FLOOR_RISE_ACTIVE_DB: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VadConfig {
+    /// From 0.0 (only loud speech opens) to 1.0 (quiet speech opens).
+    pub sensitivity: f32,
+    /// How long transmission stays open after speech stops.
+    pub hangover: Duration,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.5,
+            hangover: Duration::from_millis(300),
+        }
+    }
+}
+
+impl VadConfig {
+    /// Required margin above the noise floor, 24 dB at the lowest
+    /// sensitivity down to 6 dB at the highest.
+    fn margin_db(&self) -> f32 {
+        24.0 - 18.0 * self.sensitivity.clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VoiceActivityDetector {
+    config: VadConfig,
+    noise_floor_db: f32,
+    level_db: f32,
+    /// Samples of hangover left before the detector closes.
+    hangover_remaining: u64,
+    active: bool,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(config: VadConfig) -> Self {
+        Self {
+            config,
+            noise_floor_db: -60.0,
+            level_db: SILENCE_DB,
+            hangover_remaining: 0,
+            active: false,
+        }
+    }
+
+    pub fn config(&self) -> &VadConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: VadConfig) {
+        self.config = config;
+    }
+
+    /// Level of the last processed buffer, in dBFS.
+    pub fn level_db(&self) -> f32 {
+        self.level_db
+    }
+
+    pub fn noise_floor_db(&self) -> f32 {
+        self.noise_floor_db
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Analyzes a buffer of mono samples and returns whether to transmit it.
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        if samples.is_empty() {
+            return self.active;
+        }
+
+        self.level_db = rms_db(samples);
+        let seconds = samples.len() as f32 / SAMPLE_RATE as f32;
+        let threshold = (self.noise_floor_db + self.config.margin_db()).max(MIN_SPEECH_DB);
+        let speech = self.level_db > threshold;
+
+        if speech {
+            self.hangover_remaining =
+                (self.config.hangover.as_secs_f64() * f64::from(SAMPLE_RATE)) as u64;
+            self.active = true;
+        } else if self.hangover_remaining > 0 {
+            self.hangover_remaining = self.hangover_remaining.saturating_sub(samples.len() as u64);
+        } else {
+            self.active = false;
+        }
+
+        if self.level_db < self.noise_floor_db {
+            self.noise_floor_db = self.level_db.max(SILENCE_DB);
+        } else {
+            let rise = if speech {
+                FLOOR_RISE_ACTIVE_DB
+            } else {
+                FLOOR_RISE_IDLE_DB
+            };
+            self.noise_floor_db = (self.noise_floor_db + rise * seconds).min(self.level_db);
+        }
+
+        self.active
+    }
+
+    /// Closes the detector immediately, e.g. when switching transmit modes.
+    pub fn reset(&mut self) {
+        self.hangover_remaining = 0;
+        self.active = false;
+    }
+}
+
+/// Root mean square level of `samples` in dBFS.
+pub fn rms_db(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return SILENCE_DB;
+    }
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    if mean_square <= 0.0 {
+        return SILENCE_DB;
+    }
+    (10.0 * mean_square.log10()).max(SILENCE_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10ms of a 440 Hz tone at `amplitude`.
+    fn tone(amplitude: f32) -> Vec<f32> {
+        (0..480)
+            .map(|i| amplitude * (i as f32 * 440.0 * std::f32::consts::TAU / 48_000.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_opens_on_speech_and_holds_for_hangover() {
+        let mut vad = VoiceActivityDetector::new(VadConfig {
+            sensitivity: 0.5,
+            hangover: Duration::from_millis(50),
+        });
+
+        let noise = tone(0.001);
+        for _ in 0..50 {
+            assert!(!vad.process(&noise));
+        }
+
+        assert!(vad.process(&tone(0.3)));
+
+        // Five 10ms buffers of hangover, then closed
+        for _ in 0..5 {
+            assert!(vad.process(&noise));
+        }
+        assert!(!vad.process(&noise));
+    }
+
+    #[test]
+    fn test_sensitivity_and_noise_floor_adaptation() {
+        let quiet_speech = tone(0.01);
+        let background = tone(0.002);
+
+        let mut strict = VoiceActivityDetector::new(VadConfig {
+            sensitivity: 0.0,
+            hangover: Duration::ZERO,
+        });
+        let mut sensitive = VoiceActivityDetector::new(VadConfig {
+            sensitivity: 1.0,
+            hangover: Duration::ZERO,
+        });
+        for _ in 0..20 {
+            strict.process(&background);
+            sensitive.process(&background);
+        }
+        assert!(!strict.process(&quiet_speech));
+        assert!(sensitive.process(&quiet_speech));
+
+        // A constant hum eventually becomes the floor instead of speech
+        let hum = tone(0.05);
+        for _ in 0..5_000 {
+            sensitive.process(&hum);
+        }
+        assert!(!sensitive.is_active());
+        assert!((sensitive.noise_floor_db() - rms_db(&hum)).abs() < 1.0);
+    }
+}
@@ -0,0 +1,382 @@
+//! The control connection to the current server.
+//!
+//! Reconnection is handled by [`ServerConnection`]; this module owns the
+//! handle and forwards every state change to the UI as a `connection_state`
+//! event so it can show a reconnecting banner with the retry countdown.
+//!
+//! [`get_connection_stats`] gathers round trip, packet loss and jitter
+//! figures for the connection doctor panel.
+//!
+//! A bookmarked server's fallback endpoints are raced against its address
+//! on every connection. Once connected, UDP is probed through the server's
+//! ping responder; if it goes unanswered, voice is tunneled through the
+//! control connection instead.
+//!
+//! Servers are verified against the platform's root certificates by default.
+//! Self-signed servers use either an explicit CA file or a certificate pinned
+//! on first use, see [`crate::trust`].
+
+use crate::events;
+use crate::servers::ServerBookmarks;
+use crate::session::{self, SessionControls};
+use crate::trust::{self, TrustStore};
+use crate::updates;
+use crate::volumes::UserAudioStore;
+use fleet_net_audio::mixer::{Mixer, VoiceStats};
+use fleet_net_common::types::UserId;
+use fleet_net_protocol::client::{
+    ConnectionState, ConnectionStats, Credentials, ReconnectPolicy, ServerConnection,
+    TlsServerConnector, VoiceTransport,
+};
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::AudioPacket;
+use fleet_net_protocol::tls::{FingerprintVerifier, TlsConfig};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Event emitted whenever the connection state changes.
+pub const CONNECTION_STATE_EVENT: &str = "connection_state";
+
+/// How long a clean disconnect may take to flush queued messages.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the UDP probe waits for each answer before tunneling voice.
+const VOICE_PROBE_TIMEOUT: Duration = Duration::from_millis(750);
+
+/// How the server's certificate is verified.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ServerTrust {
+    /// Certificates issued by a CA in the platform trust store.
+    #[default]
+    System,
+    /// Certificates issued by the CA certificate in this PEM file.
+    CaFile { path: String },
+    /// Any certificate on first use, then only that one.
+    FirstUse,
+}
+
+/// Everything the connection doctor shows.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionDiagnostics {
+    pub state: ConnectionState,
+    #[serde(flatten)]
+    pub control: ConnectionStats,
+    pub voice: VoiceStats,
+}
+
+pub struct ConnectionManager {
+    connection: Mutex<Option<ServerConnection>>,
+    /// Shares the address the connection last reached with it.
+    connector: Mutex<Option<TlsServerConnector>>,
+    /// UDP port the connected server takes voice on, from its `ServerInfo`.
+    voice_port: Mutex<Option<u16>>,
+    /// Source of voice packet loss and jitter figures.
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl ConnectionManager {
+    pub fn new(mixer: Arc<Mutex<Mixer>>) -> Self {
+        Self {
+            connection: Mutex::new(None),
+            connector: Mutex::new(None),
+            voice_port: Mutex::new(None),
+            mixer,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.connection.lock().unwrap().as_ref().map_or(
+            ConnectionState::Disconnected {
+                reason: None,
+                code: None,
+                min_client_version: None,
+            },
+            |conn| conn.state(),
+        )
+    }
+
+    /// Sends `message` to the server, queueing it while reconnecting.
+    pub fn send(&self, message: ControlMessage) -> Result<(), String> {
+        self.connection
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or_else(|| "Not connected to a server".to_string())?
+            .send(message)
+            .map_err(|e| e.to_string())
+    }
+
+    /// The server endpoint the connection last reached.
+    pub fn connected_address(&self) -> Option<SocketAddr> {
+        self.connector
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(TlsServerConnector::connected_address)
+    }
+
+    pub fn set_voice_port(&self, port: Option<u16>) {
+        *self.voice_port.lock().unwrap() = port;
+    }
+
+    /// Where voice datagrams go: the relay the server assigned this session,
+    /// or the voice port of the server itself.
+    pub fn voice_address(&self) -> Option<SocketAddr> {
+        let connection = self.connection.lock().unwrap();
+        let relay = connection.as_ref().and_then(ServerConnection::voice_relay);
+        relay.or_else(|| {
+            let port = (*self.voice_port.lock().unwrap())?;
+            self.connected_address()
+                .map(|server| SocketAddr::new(server.ip(), port))
+        })
+    }
+
+    /// The user and voice key of the current session, once authenticated.
+    pub fn voice_session(&self) -> Option<(UserId, HmacKey)> {
+        let connection = self.connection.lock().unwrap();
+        let connection = connection.as_ref()?;
+        match connection.state() {
+            ConnectionState::Connected {
+                user_id: Some(user_id),
+                ..
+            } => connection.udp_key().map(|key| (user_id, key)),
+            _ => None,
+        }
+    }
+
+    /// Plays a voice datagram from the server if it was signed with the
+    /// current session's key.
+    pub fn receive_datagram(&self, datagram: &[u8]) {
+        let Some((_, key)) = self.voice_session() else {
+            return;
+        };
+        let packet = match AudioPacket::from_bytes(datagram) {
+            Ok(packet) if packet.validate_hmac(&key) => packet,
+            Ok(_) => {
+                debug!("Dropping voice datagram with a bad HMAC");
+                return;
+            }
+            Err(e) => {
+                debug!("Dropping voice datagram: {e}");
+                return;
+            }
+        };
+        if let Err(e) = self.mixer.lock().unwrap().push_packet(packet) {
+            debug!("Dropping voice datagram: {e}");
+        }
+    }
+
+    /// Plays a voice packet the server sent through the control connection.
+    pub fn receive_tunneled(&self, packet: &[u8]) {
+        let result = AudioPacket::from_bytes(packet)
+            .map_err(Into::into)
+            .and_then(|packet| self.mixer.lock().unwrap().push_packet(packet));
+        if let Err(e) = result {
+            debug!("Dropping tunneled voice packet: {e}");
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.lock().unwrap().is_some()
+    }
+
+    pub fn diagnostics(&self) -> ConnectionDiagnostics {
+        let control = self
+            .connection
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(ServerConnection::stats)
+            .unwrap_or_default();
+        ConnectionDiagnostics {
+            state: self.state(),
+            control,
+            voice: self.mixer.lock().unwrap().voice_stats(),
+        }
+    }
+}
+
+/// A connection verified by certificate fingerprint rather than a CA.
+struct Tofu {
+    address: String,
+    verifier: Arc<FingerprintVerifier>,
+}
+
+impl Tofu {
+    /// Pins the certificate on first use and reports certificate changes.
+    fn observe<R: Runtime>(&self, app: &AppHandle<R>, state: &ConnectionState) {
+        match state {
+            ConnectionState::Connected { .. } if self.verifier.pinned().is_none() => {
+                if let Some(presented) = self.verifier.presented() {
+                    self.verifier.pin(&presented);
+                    let trust = app.state::<TrustStore>();
+                    if let Err(e) = trust.trust(app, &self.address, &presented) {
+                        warn!("Failed to save trusted certificate: {e}");
+                    }
+                }
+            }
+            ConnectionState::Disconnected { .. } if self.verifier.is_mismatch() => {
+                if let (Some(pinned), Some(presented)) =
+                    (self.verifier.pinned(), self.verifier.presented())
+                {
+                    trust::notify_changed(app, &self.address, &pinned, &presented);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn forward_state<R: Runtime>(
+    app: &AppHandle<R>,
+    connection: &ServerConnection,
+    tofu: Option<Tofu>,
+) {
+    let mut states = connection.subscribe_state();
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let state = states.borrow_and_update().clone();
+            if let Some(tofu) = &tofu {
+                tofu.observe(&app, &state);
+            }
+            if let Err(e) = app.emit(CONNECTION_STATE_EVENT, &state) {
+                warn!("Failed to emit connection state: {e}");
+            }
+            // A fresh session starts with no subscriptions and default state.
+            if let ConnectionState::Connected { resumed: false, .. } = state {
+                session::restore(&app);
+            }
+            if let ConnectionState::Disconnected {
+                reason,
+                code,
+                min_client_version,
+            } = state
+            {
+                if let Some(min_version) = min_client_version {
+                    updates::notify_required(&app, min_version);
+                }
+                events::emit_disconnected(&app, reason, code);
+            }
+            if states.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Connects to `address` (`host:port`), verifying its certificate as chosen
+/// by `trust_mode`. Any existing connection is closed first.
+#[tauri::command]
+pub async fn connect_server(
+    app: AppHandle,
+    state: State<'_, ConnectionManager>,
+    trust: State<'_, TrustStore>,
+    address: String,
+    token: String,
+    trust_mode: Option<ServerTrust>,
+) -> Result<(), String> {
+    let (tls, tofu) = match trust_mode.unwrap_or_default() {
+        ServerTrust::System => (TlsConfig::new_client_with_system_roots(), None),
+        ServerTrust::CaFile { path } => (
+            TlsConfig::new_client(Path::new(&path)).map_err(|e| e.to_string())?,
+            None,
+        ),
+        ServerTrust::FirstUse => {
+            let verifier = Arc::new(FingerprintVerifier::new(trust.fingerprint_for(&address)));
+            let tofu = Tofu {
+                address: address.clone(),
+                verifier: verifier.clone(),
+            };
+            (TlsConfig::new_client_pinned(verifier), Some(tofu))
+        }
+    };
+    let client_config = tls
+        .client_config
+        .ok_or_else(|| "TLS client configuration is missing".to_string())?;
+    let connector = TlsServerConnector::new(address.clone(), client_config)
+        .map_err(|e| e.to_string())?
+        .with_fallbacks(app.state::<ServerBookmarks>().endpoints(&address));
+
+    // Close first so the old connection's final state reaches the UI before the new one's.
+    if let Some(previous) = state.connection.lock().unwrap().take() {
+        previous.close();
+    }
+
+    app.state::<UserAudioStore>().activate(&address);
+    if let Err(e) = app.state::<ServerBookmarks>().touch(&app, &address) {
+        warn!("Failed to update server bookmark: {e}");
+    }
+
+    let (inbound, inbound_rx) = mpsc::unbounded_channel();
+    events::spawn_message_pump(&app, inbound_rx);
+
+    *state.connector.lock().unwrap() = Some(connector.clone());
+    state.set_voice_port(None);
+    app.state::<SessionControls>()
+        .set_voice_transport(VoiceTransport::default());
+
+    // Async commands run on the Tauri runtime, which the connection task joins.
+    let connection = ServerConnection::spawn(
+        connector,
+        Credentials {
+            token,
+            client_version: Cow::Borrowed(env!("CARGO_PKG_VERSION")),
+        },
+        ReconnectPolicy::default(),
+        inbound,
+    );
+    forward_state(&app, &connection, tofu);
+
+    *state.connection.lock().unwrap() = Some(connection);
+    Ok(())
+}
+
+/// Probes whether voice can reach the server over UDP through its ping
+/// responder on `ping_port`. Servers without one are assumed reachable.
+pub fn spawn_voice_probe<R: Runtime>(app: &AppHandle<R>, ping_port: Option<u16>) {
+    let controls = app.state::<SessionControls>();
+    let ping_address = ping_port.zip(app.state::<ConnectionManager>().connected_address());
+    let Some((port, server)) = ping_address else {
+        controls.set_voice_transport(VoiceTransport::Udp);
+        return;
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let transport =
+            VoiceTransport::probe(SocketAddr::new(server.ip(), port), VOICE_PROBE_TIMEOUT).await;
+        app.state::<SessionControls>()
+            .set_voice_transport(transport);
+    });
+}
+
+/// Closes the connection after sending any queued messages.
+#[tauri::command]
+pub async fn disconnect_server(state: State<'_, ConnectionManager>) -> Result<(), String> {
+    let connection = state.connection.lock().unwrap().take();
+    if let Some(connection) = connection {
+        connection.disconnect(DISCONNECT_TIMEOUT).await;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_connection_state(state: State<'_, ConnectionManager>) -> ConnectionState {
+    state.state()
+}
+
+/// Round trip, reconnect and error history of the control connection, plus
+/// packet loss and jitter of received voice.
+#[tauri::command]
+pub fn get_connection_stats(state: State<'_, ConnectionManager>) -> ConnectionDiagnostics {
+    state.diagnostics()
+}
@@ -0,0 +1,175 @@
+//! Roger beep, squelch tail and mic click settings.
+//!
+//! Which cues play is chosen per radio type, and any built-in sound can be
+//! replaced with a WAV file. Received transmissions are cued by the mixer;
+//! this module cues the local user's own transmissions on the radios they
+//! key.
+
+use crate::radio::RadioState;
+use crate::settings;
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::cues::{load_cue_sample, CueBank, CueConfig, CueEvent, CueKind};
+use fleet_net_audio::effects::RadioTypes;
+use fleet_net_audio::mixer::Mixer;
+use fleet_net_common::types::ChannelId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Runtime, State};
+use tracing::warn;
+
+const SETTINGS_FILE: &str = "sound_cues.json";
+
+const RADIO_TYPES: [RadioTypes; 5] = [
+    RadioTypes::Hf,
+    RadioTypes::Uhf,
+    RadioTypes::Vhf,
+    RadioTypes::Satellite,
+    RadioTypes::Quantum,
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CueSettings {
+    /// Cues per radio type; missing types use [`CueConfig::for_radio`].
+    #[serde(default)]
+    pub radio_types: HashMap<RadioTypes, CueConfig>,
+    /// WAV files replacing built-in sounds.
+    #[serde(default)]
+    pub samples: HashMap<CueKind, String>,
+}
+
+pub struct SoundCues {
+    settings: Mutex<CueSettings>,
+    bank: Mutex<CueBank>,
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl SoundCues {
+    pub fn new(mixer: Arc<Mutex<Mixer>>) -> Self {
+        Self {
+            settings: Mutex::new(CueSettings::default()),
+            bank: Mutex::new(CueBank::default()),
+            mixer,
+        }
+    }
+
+    /// Replaces the sound for `kind` with the WAV file at `path`, or restores
+    /// the built-in one with `None`.
+    fn set_sample(&self, kind: CueKind, path: Option<&str>) -> Result<(), String> {
+        let mut bank = self.bank.lock().unwrap();
+        match path {
+            Some(path) => {
+                let samples = load_cue_sample(Path::new(path)).map_err(|e| e.to_string())?;
+                bank.set(kind, samples);
+            }
+            None => bank.reset(kind),
+        }
+        self.mixer.lock().unwrap().set_cue_bank(bank.clone());
+        Ok(())
+    }
+}
+
+/// Restores cue settings and starts cueing local transmissions.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let saved: CueSettings = settings::load(app, SETTINGS_FILE)?.unwrap_or_default();
+    let state = app.state::<SoundCues>();
+    let radios = app.state::<RadioState>();
+    for (&radio_type, &cues) in &saved.radio_types {
+        radios.set_cue_config(radio_type, cues);
+    }
+    for (&kind, path) in &saved.samples {
+        // A moved sample file should not stop the client from starting.
+        if let Err(e) = state.set_sample(kind, Some(path)) {
+            warn!("Using the built-in {kind:?} sound: {e}");
+        }
+    }
+    *state.settings.lock().unwrap() = saved;
+
+    spawn_transmit_cues(app);
+    Ok(())
+}
+
+/// Plays the start and end cues on every radio keyed for a transmission.
+fn spawn_transmit_cues<R: Runtime>(app: &AppHandle<R>) {
+    let mut transmitting = app.state::<Arc<TransmitGate>>().subscribe();
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        // Channels the current transmission went out on.
+        let mut keyed: Vec<ChannelId> = Vec::new();
+        while transmitting.changed().await.is_ok() {
+            let open = *transmitting.borrow_and_update();
+            let event = if open {
+                let radio_ids = app.state::<Arc<TransmitGate>>().keyed_radios();
+                keyed = app
+                    .state::<RadioState>()
+                    .radios()
+                    .iter()
+                    .filter(|radio| radio_ids.contains(&radio.id))
+                    .map(|radio| radio.channel_id)
+                    .collect();
+                CueEvent::TransmitStart
+            } else {
+                CueEvent::TransmitEnd
+            };
+
+            let cues = app.state::<SoundCues>();
+            let mut mixer = cues.mixer.lock().unwrap();
+            for &channel_id in &keyed {
+                mixer.play_cue(channel_id, event);
+            }
+        }
+    });
+}
+
+/// Cue settings with every radio type filled in.
+#[tauri::command]
+pub fn get_sound_cues(state: State<'_, SoundCues>, radios: State<'_, RadioState>) -> CueSettings {
+    CueSettings {
+        radio_types: RADIO_TYPES
+            .into_iter()
+            .map(|radio_type| (radio_type, radios.cue_config(radio_type)))
+            .collect(),
+        samples: state.settings.lock().unwrap().samples.clone(),
+    }
+}
+
+#[tauri::command]
+pub fn set_radio_cues(
+    app: AppHandle,
+    state: State<'_, SoundCues>,
+    radios: State<'_, RadioState>,
+    radio_type: RadioTypes,
+    cues: CueConfig,
+) -> Result<(), String> {
+    if !cues.volume.is_finite() {
+        return Err("Cue volume must be a number".to_string());
+    }
+    let cues = CueConfig {
+        volume: cues.volume.clamp(0.0, 2.0),
+        ..cues
+    };
+    radios.set_cue_config(radio_type, cues);
+
+    let mut settings = state.settings.lock().unwrap();
+    settings.radio_types.insert(radio_type, cues);
+    settings::save(&app, SETTINGS_FILE, &*settings)
+}
+
+/// Plays the WAV file at `path` for `kind`, or the built-in sound with `None`.
+#[tauri::command]
+pub fn set_cue_sample(
+    app: AppHandle,
+    state: State<'_, SoundCues>,
+    kind: CueKind,
+    path: Option<String>,
+) -> Result<(), String> {
+    state.set_sample(kind, path.as_deref())?;
+
+    let mut settings = state.settings.lock().unwrap();
+    match path {
+        Some(path) => settings.samples.insert(kind, path),
+        None => settings.samples.remove(&kind),
+    };
+    settings::save(&app, SETTINGS_FILE, &*settings)
+}
@@ -0,0 +1,302 @@
+//! Bridges server state to the frontend as Tauri events.
+//!
+//! Control messages from the server are translated into a few coarse events
+//! the UI subscribes to, and the mixer is watched for remote speakers starting
+//! and stopping, so the frontend updates reactively instead of polling.
+//! Microphone and speaker levels are sampled at a lower rate for meters.
+
+use crate::connection::{self, ConnectionManager};
+use crate::locale::LocaleState;
+use crate::overlay::OverlayState;
+use crate::radio;
+use crate::reception::{ReceptionFeedback, RetransmitChannels};
+use crate::session::SessionControls;
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::encoder::BitrateFeedback;
+use fleet_net_audio::level::AudioLevel;
+use fleet_net_audio::mixer::{Mixer, SpeakerLevel};
+use fleet_net_audio::recorder::Recorder;
+use fleet_net_common::error::FleetNetErrorCode;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::message::ControlMessage;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+pub const USER_JOINED_EVENT: &str = "user-joined";
+pub const USER_LEFT_EVENT: &str = "user-left";
+/// Channel membership or subscriptions changed; the payload is the server message.
+pub const CHANNEL_UPDATED_EVENT: &str = "channel-updated";
+/// A user changed their mute or deafen state.
+pub const USER_STATE_CHANGED_EVENT: &str = "user-state-changed";
+/// A user set or cleared their nickname.
+pub const NICKNAME_CHANGED_EVENT: &str = "nickname-changed";
+/// A user set themselves away, do not disturb, in game or back online.
+pub const PRESENCE_CHANGED_EVENT: &str = "presence-changed";
+/// A group was formed, changed members or leader, or disbanded.
+pub const GROUP_UPDATED_EVENT: &str = "group-updated";
+pub const USER_SPEAKING_EVENT: &str = "user-speaking";
+/// Someone started or stopped transmitting on any channel, as the server
+/// saw it, including channels this client doesn't hear.
+pub const NET_ACTIVITY_EVENT: &str = "net-activity";
+/// A higher ranking user cut off this client's transmission on a channel
+/// with preemption.
+pub const STEPPED_ON_EVENT: &str = "stepped-on";
+pub const SERVER_INFO_EVENT: &str = "server-info";
+pub const SERVER_ERROR_EVENT: &str = "server-error";
+/// Microphone and remote speaker levels; only sent while there is sound.
+pub const AUDIO_LEVELS_EVENT: &str = "audio-levels";
+/// The connection closed for good and will not be retried.
+pub const DISCONNECTED_EVENT: &str = "disconnected";
+
+/// How often the mixer is checked for speakers starting or stopping.
+const SPEAKING_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often level meters are updated, about 15 times a second.
+const LEVEL_INTERVAL: Duration = Duration::from_millis(66);
+
+#[derive(Debug, Clone, Serialize)]
+struct UserSpeakingPayload {
+    user_id: UserId,
+    channel_id: ChannelId,
+    speaking: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AudioLevelsPayload {
+    input: AudioLevel,
+    /// Remote users transmitting right now, with how loud they are.
+    speakers: Vec<SpeakerLevel>,
+}
+
+impl AudioLevelsPayload {
+    fn is_silent(&self) -> bool {
+        self.input.is_silent() && self.speakers.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DisconnectedPayload {
+    reason: Option<String>,
+    code: Option<FleetNetErrorCode>,
+    /// Why the connection ended, in the user's language.
+    text: String,
+}
+
+/// A server error with its message in the user's language.
+#[derive(Debug, Clone, Serialize)]
+struct ServerErrorPayload {
+    #[serde(flatten)]
+    message: ControlMessage,
+    text: String,
+}
+
+fn emit<R: Runtime, S: Serialize + Clone>(app: &AppHandle<R>, event: &str, payload: S) {
+    if let Err(e) = app.emit(event, payload) {
+        warn!("Failed to emit {event} event: {e}");
+    }
+}
+
+/// Translates one server message into its frontend event, if any.
+fn dispatch<R: Runtime>(app: &AppHandle<R>, message: ControlMessage) {
+    let event = match &message {
+        ControlMessage::UserJoined {
+            user_id,
+            username,
+            nickname,
+            ..
+        } => {
+            let name = nickname.as_ref().unwrap_or(username);
+            // Recordings name speakers by who they were at the time.
+            app.state::<Arc<Recorder>>()
+                .set_label(*user_id, name.clone());
+            app.state::<OverlayState>().set_name(*user_id, name.clone());
+            USER_JOINED_EVENT
+        }
+        ControlMessage::NicknameChanged {
+            user_id,
+            nickname: Some(nickname),
+        } => {
+            app.state::<Arc<Recorder>>()
+                .set_label(*user_id, nickname.clone());
+            app.state::<OverlayState>()
+                .set_name(*user_id, nickname.clone());
+            NICKNAME_CHANGED_EVENT
+        }
+        // The frontend still knows the username to fall back to.
+        ControlMessage::NicknameChanged { .. } => NICKNAME_CHANGED_EVENT,
+        ControlMessage::UserLeft { user_id } => {
+            app.state::<OverlayState>().remove_name(*user_id);
+            USER_LEFT_EVENT
+        }
+        ControlMessage::SessionResumed {
+            subscribed_channels,
+            ..
+        } => {
+            radio::reconcile(app, subscribed_channels);
+            CHANNEL_UPDATED_EVENT
+        }
+        ControlMessage::SubscriptionsChanged {
+            retransmit_channels,
+            ..
+        } => {
+            app.state::<RetransmitChannels>().set(retransmit_channels);
+            CHANNEL_UPDATED_EVENT
+        }
+        ControlMessage::ChannelJoined { .. }
+        | ControlMessage::ChannelLeft { .. }
+        | ControlMessage::UserChangedChannel { .. }
+        | ControlMessage::ChannelInfoChanged { .. } => CHANNEL_UPDATED_EVENT,
+        ControlMessage::UserStateChanged { .. } => USER_STATE_CHANGED_EVENT,
+        ControlMessage::PresenceChanged { .. } => PRESENCE_CHANGED_EVENT,
+        ControlMessage::UserSpeaking { .. } => NET_ACTIVITY_EVENT,
+        ControlMessage::TransmissionSteppedOn { .. } => STEPPED_ON_EVENT,
+        ControlMessage::GroupChanged { .. } | ControlMessage::GroupDisbanded { .. } => {
+            GROUP_UPDATED_EVENT
+        }
+        ControlMessage::ServerInfo {
+            limits,
+            voice_padding,
+            ping_port,
+            voice_port,
+            ..
+        } => {
+            let controls = app.state::<SessionControls>();
+            controls.set_limits(limits.unwrap_or_default());
+            controls.set_voice_padding(*voice_padding);
+            app.state::<ConnectionManager>().set_voice_port(*voice_port);
+            connection::spawn_voice_probe(app, *ping_port);
+            SERVER_INFO_EVENT
+        }
+        ControlMessage::VoiceTunnel { packet } => {
+            app.state::<ConnectionManager>().receive_tunneled(packet);
+            return;
+        }
+        ControlMessage::ReceptionFeedback {
+            quality,
+            min_bitrate,
+            max_bitrate,
+            ..
+        } => {
+            app.state::<ReceptionFeedback>().update(BitrateFeedback {
+                quality: *quality,
+                min_bitrate: *min_bitrate,
+                max_bitrate: *max_bitrate,
+            });
+            return;
+        }
+        ControlMessage::Error { code, .. } => {
+            let text = app.state::<LocaleState>().error(*code);
+            emit(
+                app,
+                SERVER_ERROR_EVENT,
+                ServerErrorPayload { message, text },
+            );
+            return;
+        }
+        other => {
+            debug!("Unhandled server message: {other:?}");
+            return;
+        }
+    };
+    emit(app, event, message);
+}
+
+/// Emits an event for every message the connection reader delivers.
+pub fn spawn_message_pump<R: Runtime>(
+    app: &AppHandle<R>,
+    mut inbound: mpsc::UnboundedReceiver<ControlMessage>,
+) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        while let Some(message) = inbound.recv().await {
+            dispatch(&app, message);
+        }
+    });
+}
+
+pub fn emit_disconnected<R: Runtime>(
+    app: &AppHandle<R>,
+    reason: Option<String>,
+    code: Option<FleetNetErrorCode>,
+) {
+    let locale = app.state::<LocaleState>();
+    let text = match code {
+        Some(code) => locale.translate("disconnect-error", &[("reason", &locale.error(code))]),
+        None => locale.translate("disconnect-closed", &[]),
+    };
+    emit(
+        app,
+        DISCONNECTED_EVENT,
+        DisconnectedPayload { reason, code, text },
+    );
+}
+
+/// Emits `user-speaking` whenever a remote speaker starts or stops talking.
+pub fn spawn_speaking_monitor<R: Runtime>(app: &AppHandle<R>, mixer: Arc<Mutex<Mixer>>) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut speaking: HashSet<(UserId, ChannelId)> = HashSet::new();
+        let mut interval = tokio::time::interval(SPEAKING_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let active: HashSet<_> = mixer
+                .lock()
+                .unwrap()
+                .active_speakers()
+                .into_iter()
+                .collect();
+
+            for &(user_id, channel_id) in active.difference(&speaking) {
+                emit(
+                    &app,
+                    USER_SPEAKING_EVENT,
+                    UserSpeakingPayload {
+                        user_id,
+                        channel_id,
+                        speaking: true,
+                    },
+                );
+            }
+            for &(user_id, channel_id) in speaking.difference(&active) {
+                emit(
+                    &app,
+                    USER_SPEAKING_EVENT,
+                    UserSpeakingPayload {
+                        user_id,
+                        channel_id,
+                        speaking: false,
+                    },
+                );
+            }
+            speaking = active;
+        }
+    });
+}
+
+/// Emits `audio-levels` while the microphone or any remote speaker is audible,
+/// plus one silent update so meters fall back to zero.
+pub fn spawn_level_meter<R: Runtime>(app: &AppHandle<R>, mixer: Arc<Mutex<Mixer>>) {
+    let gate = app.state::<Arc<TransmitGate>>().inner().clone();
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut was_silent = true;
+        let mut interval = tokio::time::interval(LEVEL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let payload = AudioLevelsPayload {
+                input: gate.input_level().take(),
+                speakers: mixer.lock().unwrap().take_speaker_levels(),
+            };
+            let silent = payload.is_silent();
+            if !(silent && was_silent) {
+                emit(&app, AUDIO_LEVELS_EVENT, payload);
+            }
+            was_silent = silent;
+        }
+    });
+}
@@ -0,0 +1,73 @@
+//! Per-install token guarding the localhost endpoints.
+//!
+//! Any web page open in the user's browser can reach 127.0.0.1, so the
+//! overlay and game telemetry endpoints only answer requests carrying this
+//! token as `?token=`. It is generated on first start and kept in the config
+//! directory; [`get_local_token`] lets the settings show the URLs to paste
+//! into an overlay or game plugin.
+
+use crate::settings;
+use axum::http::StatusCode;
+use fleet_net_common::secret::constant_time_eq;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Runtime, State};
+
+const TOKEN_FILE: &str = "local_token.json";
+
+/// Random bytes in a token, hex encoded.
+const TOKEN_BYTES: usize = 32;
+
+#[derive(Default)]
+pub struct LocalToken(Mutex<Arc<str>>);
+
+impl LocalToken {
+    pub fn get(&self) -> Arc<str> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// The `?token=` query of a request to a localhost endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct TokenQuery {
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Accepts requests presenting `expected`, compared in constant time.
+pub fn authorize(expected: &str, query: &TokenQuery) -> Result<(), StatusCode> {
+    let presented = query.token.as_deref().unwrap_or_default();
+    if !expected.is_empty() && constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+fn generate() -> Result<String, String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rustls::crypto::ring::default_provider()
+        .secure_random
+        .fill(&mut bytes)
+        .map_err(|_| "Failed to generate the local endpoint token".to_string())?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Restores the token, generating and saving one on first start.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let token: String = match settings::load(app, TOKEN_FILE)? {
+        Some(token) => token,
+        None => {
+            let token = generate()?;
+            settings::save(app, TOKEN_FILE, &token)?;
+            token
+        }
+    };
+    *app.state::<LocalToken>().0.lock().unwrap() = token.into();
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_local_token(state: State<'_, LocalToken>) -> String {
+    state.get().to_string()
+}
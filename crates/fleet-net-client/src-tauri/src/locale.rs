@@ -0,0 +1,127 @@
+//! The user's language and the catalogs its text comes from.
+//!
+//! English is built in; other catalogs are `<locale>.ftl` files in the
+//! bundled `locales` resource directory. Server errors and disconnect
+//! reasons are rendered from their error codes, so they follow the chosen
+//! language instead of the server's.
+
+use crate::settings;
+use fleet_net_common::error::FleetNetErrorCode;
+use fleet_net_common::i18n::{Catalog, Localizer, DEFAULT_LOCALE};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Runtime, State};
+use tracing::warn;
+
+const SETTINGS_FILE: &str = "locale.json";
+
+pub struct LocaleState {
+    localizer: Mutex<Localizer>,
+    locale: Mutex<String>,
+}
+
+impl Default for LocaleState {
+    fn default() -> Self {
+        Self {
+            localizer: Mutex::new(Localizer::new()),
+            locale: Mutex::new(DEFAULT_LOCALE.to_string()),
+        }
+    }
+}
+
+impl LocaleState {
+    /// `key` in the chosen language with `args` filled in.
+    pub fn translate(&self, key: &str, args: &[(&str, &str)]) -> String {
+        let locale = self.locale.lock().unwrap();
+        self.localizer.lock().unwrap().translate(&locale, key, args)
+    }
+
+    /// The message for an error `code` in the chosen language.
+    pub fn error(&self, code: FleetNetErrorCode) -> String {
+        self.translate(code.translation_key(), &[])
+    }
+}
+
+/// Loads the bundled catalogs and restores the chosen language.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let state = app.state::<LocaleState>();
+    if let Ok(dir) = app.path().resource_dir() {
+        let mut localizer = state.localizer.lock().unwrap();
+        for catalog in load_catalogs(&dir.join("locales")) {
+            localizer.add(catalog);
+        }
+    }
+    if let Some(locale) = settings::load::<String, R>(app, SETTINGS_FILE)? {
+        *state.locale.lock().unwrap() = locale;
+    }
+    Ok(())
+}
+
+/// Every readable `<locale>.ftl` catalog in `dir`; broken ones are logged
+/// and skipped so a bad translation never keeps the client from starting.
+fn load_catalogs(dir: &std::path::Path) -> Vec<Catalog> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ftl"))
+        .filter_map(|path| {
+            let locale = path.file_stem()?.to_str()?.to_string();
+            let source = std::fs::read_to_string(&path)
+                .map_err(|e| warn!("Failed to read {}: {e}", path.display()))
+                .ok()?;
+            Catalog::parse(locale, &source)
+                .map_err(|e| warn!("Ignoring catalog {}: {e}", path.display()))
+                .ok()
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub fn get_locale(state: State<'_, LocaleState>) -> String {
+    state.locale.lock().unwrap().clone()
+}
+
+/// Locales with a catalog, for the language picker.
+#[tauri::command]
+pub fn get_locales(state: State<'_, LocaleState>) -> Vec<String> {
+    let mut locales: Vec<String> = state
+        .localizer
+        .lock()
+        .unwrap()
+        .locales()
+        .map(str::to_string)
+        .collect();
+    locales.sort();
+    locales
+}
+
+#[tauri::command]
+pub fn set_locale(
+    app: AppHandle,
+    state: State<'_, LocaleState>,
+    locale: String,
+) -> Result<(), String> {
+    if locale.is_empty() || locale.len() > 35 {
+        return Err("Not a locale".to_string());
+    }
+    *state.locale.lock().unwrap() = locale.clone();
+    settings::save(&app, SETTINGS_FILE, &locale)
+}
+
+/// `key` in the chosen language, for text the frontend shows itself.
+#[tauri::command]
+pub fn translate(
+    state: State<'_, LocaleState>,
+    key: String,
+    args: Option<HashMap<String, String>>,
+) -> String {
+    let args = args.unwrap_or_default();
+    let args: Vec<(&str, &str)> = args
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    state.translate(&key, &args)
+}
@@ -1,7 +1,155 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod connection;
+mod cues;
+mod events;
+mod local_token;
+mod locale;
+mod overlay;
+mod processing;
+mod ptt;
+mod radio;
+mod reception;
+mod recording;
+mod servers;
+mod session;
+mod settings;
+mod srs;
+mod telemetry;
+mod transmit;
+mod trust;
+mod updates;
+mod voice;
+mod volumes;
+
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::mixer::{Mixer, MixerConfig};
+use fleet_net_audio::output::PlaybackRouter;
+use fleet_net_audio::recorder::{Recorder, RecorderConfig};
+use std::sync::{Arc, Mutex};
+
 fn main() {
+    let gate = Arc::new(TransmitGate::new());
+    let recorder = Arc::new(Recorder::new(RecorderConfig::default()));
+    let mut mixer = Mixer::new(MixerConfig::default());
+    mixer.set_recorder(Some(recorder.clone()));
+    let mixer = Arc::new(Mutex::new(mixer));
+    let playback =
+        PlaybackRouter::spawn(mixer.clone(), None).expect("failed to start playback thread");
+
     tauri::Builder::default()
+        .manage(ptt::PttState::new(gate.clone()))
+        .manage(session::SessionControls::new(gate.clone(), mixer.clone()))
+        .manage(processing::ProcessingProfiles::new(gate.clone()))
+        .manage(local_token::LocalToken::default())
+        .manage(overlay::OverlayState::new(gate.clone(), mixer.clone()))
+        .manage(srs::SrsInterop::new(gate.clone()))
+        .manage(telemetry::GameTelemetry::default())
+        .manage(reception::ReceptionFeedback::default())
+        .manage(reception::RetransmitChannels::default())
+        .manage(gate)
+        .manage(recorder)
+        .manage(recording::RecordingStore::default())
+        .manage(connection::ConnectionManager::new(mixer.clone()))
+        .manage(trust::TrustStore::default())
+        .manage(locale::LocaleState::default())
+        .manage(servers::ServerBookmarks::default())
+        .manage(radio::RadioState::new(mixer.clone(), playback))
+        .manage(radio::RadioPresets::default())
+        .manage(cues::SoundCues::new(mixer.clone()))
+        .manage(volumes::UserAudioStore::new(mixer.clone()))
+        .plugin(ptt::plugin())
+        .setup(|app| {
+            locale::setup(app.handle())?;
+            ptt::setup(app.handle())?;
+            transmit::setup(app.handle())?;
+            processing::setup(app.handle())?;
+            trust::setup(app.handle())?;
+            servers::setup(app.handle())?;
+            radio::setup(app.handle())?;
+            cues::setup(app.handle())?;
+            local_token::setup(app.handle())?;
+            overlay::setup(app.handle())?;
+            srs::setup(app.handle())?;
+            telemetry::setup(app.handle())?;
+            volumes::setup(app.handle())?;
+            recording::setup(app.handle())?;
+            voice::setup(app.handle())?;
+            events::spawn_level_meter(app.handle(), mixer.clone());
+            events::spawn_speaking_monitor(app.handle(), mixer.clone());
+            reception::spawn_reports(app.handle(), mixer.clone());
+            reception::spawn_retransmit_requests(app.handle(), mixer);
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            servers::ping_servers,
+            servers::get_server_bookmarks,
+            servers::save_server_bookmark,
+            servers::remove_server_bookmark,
+            servers::ping_bookmarks,
+            connection::connect_server,
+            connection::disconnect_server,
+            connection::get_connection_state,
+            connection::get_connection_stats,
+            session::join_channel,
+            session::leave_channel,
+            session::get_self_state,
+            session::set_self_mute,
+            session::set_self_deafen,
+            session::get_presence,
+            session::set_presence,
+            session::set_nickname,
+            session::create_group,
+            session::join_group,
+            session::leave_group,
+            trust::get_trusted_certs,
+            trust::trust_certificate,
+            trust::remove_trusted_cert,
+            ptt::get_ptt_bindings,
+            ptt::set_ptt_binding,
+            ptt::clear_ptt_binding,
+            radio::get_radios,
+            radio::add_radio,
+            radio::remove_radio,
+            radio::update_radio,
+            radio::tune_radio,
+            radio::get_intercom,
+            radio::set_intercom,
+            radio::get_output_devices,
+            cues::get_sound_cues,
+            cues::set_radio_cues,
+            cues::set_cue_sample,
+            radio::get_radio_presets,
+            radio::save_radio_preset,
+            radio::load_radio_preset,
+            radio::delete_radio_preset,
+            volumes::get_user_audio,
+            volumes::set_user_volume,
+            volumes::set_user_muted,
+            recording::start_recording,
+            recording::stop_recording,
+            recording::is_recording,
+            recording::get_recording_storage,
+            recording::set_recording_storage,
+            transmit::get_transmit_settings,
+            transmit::set_transmit_settings,
+            processing::get_audio_processing,
+            processing::set_audio_processing,
+            local_token::get_local_token,
+            overlay::get_overlay_settings,
+            overlay::set_overlay_settings,
+            srs::get_srs_settings,
+            srs::set_srs_settings,
+            srs::set_srs_channels,
+            telemetry::get_telemetry_settings,
+            telemetry::set_telemetry_settings,
+            telemetry::get_game_position,
+            updates::check_for_update,
+            locale::get_locale,
+            locale::get_locales,
+            locale::set_locale,
+            locale::translate,
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
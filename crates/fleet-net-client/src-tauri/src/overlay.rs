@@ -0,0 +1,303 @@
+//! Localhost endpoint for game and streaming overlays.
+//!
+//! Overlays such as an OBS browser source or an in-game HUD read who is
+//! talking, which radios are keyed and the push-to-talk state, either once
+//! from `GET /state` or as a stream of updates from the `/ws` WebSocket.
+//! The endpoint only listens on the loopback interface and is off by default.
+//! Both routes require the install's [`LocalToken`] as `?token=`, so web
+//! pages the user visits cannot read who is talking.
+
+use crate::local_token::{self, LocalToken, TokenQuery};
+use crate::radio::RadioState;
+use crate::settings;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State as AxumState};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::mixer::Mixer;
+use fleet_net_common::types::{ChannelId, UserId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, Runtime, State};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+const SETTINGS_FILE: &str = "overlay.json";
+
+/// How often the published state is refreshed.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OverlaySettings {
+    pub enabled: bool,
+    /// Port on 127.0.0.1 the endpoint listens on.
+    pub port: u16,
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 37_373,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OverlaySpeaker {
+    pub user_id: UserId,
+    pub username: Option<String>,
+    pub channel_id: ChannelId,
+    /// The radio the speaker is heard on, if any.
+    pub radio_id: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OverlayRadio {
+    pub id: u8,
+    pub channel_id: ChannelId,
+    pub frequency_mhz: Option<f64>,
+    /// Push-to-talk is held for this radio.
+    pub keyed: bool,
+    /// Someone is talking on this radio's channel.
+    pub receiving: bool,
+}
+
+/// Everything an overlay is told.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OverlaySnapshot {
+    pub speakers: Vec<OverlaySpeaker>,
+    pub radios: Vec<OverlayRadio>,
+    /// Push-to-talk is held for any radio.
+    pub ptt_pressed: bool,
+    /// The local user's microphone is on the air.
+    pub transmitting: bool,
+}
+
+pub struct OverlayState {
+    settings: Mutex<OverlaySettings>,
+    /// Usernames of everyone seen on the server, since audio only carries ids.
+    names: Mutex<HashMap<UserId, String>>,
+    snapshot: watch::Sender<OverlaySnapshot>,
+    server: Mutex<Option<JoinHandle<()>>>,
+    gate: Arc<TransmitGate>,
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl OverlayState {
+    pub fn new(gate: Arc<TransmitGate>, mixer: Arc<Mutex<Mixer>>) -> Self {
+        Self {
+            settings: Mutex::new(OverlaySettings::default()),
+            names: Mutex::new(HashMap::new()),
+            snapshot: watch::Sender::new(OverlaySnapshot::default()),
+            server: Mutex::new(None),
+            gate,
+            mixer,
+        }
+    }
+
+    pub fn set_name(&self, user_id: UserId, username: String) {
+        self.names.lock().unwrap().insert(user_id, username);
+    }
+
+    pub fn remove_name(&self, user_id: UserId) {
+        self.names.lock().unwrap().remove(&user_id);
+    }
+
+    fn build_snapshot<R: Runtime>(&self, app: &AppHandle<R>) -> OverlaySnapshot {
+        let radios = app.state::<RadioState>().radios();
+        let active = self.mixer.lock().unwrap().active_speakers();
+        let keyed = self.gate.keyed_radios();
+        let names = self.names.lock().unwrap();
+
+        OverlaySnapshot {
+            speakers: active
+                .iter()
+                .map(|&(user_id, channel_id)| OverlaySpeaker {
+                    user_id,
+                    username: names.get(&user_id).cloned(),
+                    channel_id,
+                    radio_id: radios
+                        .iter()
+                        .find(|radio| radio.channel_id == channel_id)
+                        .map(|radio| radio.id),
+                })
+                .collect(),
+            radios: radios
+                .iter()
+                .map(|radio| OverlayRadio {
+                    id: radio.id,
+                    channel_id: radio.channel_id,
+                    frequency_mhz: radio.frequency_mhz,
+                    keyed: keyed.contains(&radio.id),
+                    receiving: active
+                        .iter()
+                        .any(|&(_, channel_id)| channel_id == radio.channel_id),
+                })
+                .collect(),
+            ptt_pressed: !keyed.is_empty(),
+            transmitting: self.gate.is_transmitting(),
+        }
+    }
+
+    /// Stops the endpoint and, if enabled, starts it again on the configured port.
+    async fn restart(&self, token: Arc<str>) -> Result<(), String> {
+        if let Some(server) = self.server.lock().unwrap().take() {
+            server.abort();
+        }
+        let settings = *self.settings.lock().unwrap();
+        if !settings.enabled {
+            return Ok(());
+        }
+
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, settings.port));
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| format!("Failed to listen on {address}: {e}"))?;
+        info!("Overlay endpoint listening on http://{address}");
+        let router = router(EndpointState {
+            snapshots: self.snapshot.subscribe(),
+            token,
+        });
+        *self.server.lock().unwrap() = Some(tauri::async_runtime::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                warn!("Overlay endpoint stopped: {e}");
+            }
+        }));
+        Ok(())
+    }
+}
+
+/// Restores overlay settings, starts the endpoint if enabled and keeps the
+/// published state current.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let saved: Option<OverlaySettings> = settings::load(app, SETTINGS_FILE)?;
+    *app.state::<OverlayState>().settings.lock().unwrap() = saved.unwrap_or_default();
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<OverlayState>();
+        if let Err(e) = state.restart(app.state::<LocalToken>().get()).await {
+            warn!("{e}");
+        }
+
+        let mut interval = tokio::time::interval(PUBLISH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let snapshot = state.build_snapshot(&app);
+            state.snapshot.send_if_modified(|current| {
+                let changed = *current != snapshot;
+                *current = snapshot;
+                changed
+            });
+        }
+    });
+    Ok(())
+}
+
+#[derive(Clone)]
+struct EndpointState {
+    snapshots: watch::Receiver<OverlaySnapshot>,
+    token: Arc<str>,
+}
+
+fn router(state: EndpointState) -> Router {
+    Router::new()
+        .route("/state", get(current_state))
+        .route("/ws", get(websocket))
+        .with_state(state)
+}
+
+async fn current_state(
+    AxumState(state): AxumState<EndpointState>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = local_token::authorize(&state.token, &query) {
+        return status.into_response();
+    }
+    let snapshot = state.snapshots.borrow().clone();
+    let mut response = Json(snapshot).into_response();
+    // Browser sources are served from other origins; the token already
+    // proves the page was set up by the user, so its origin may read this.
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        let response_headers = response.headers_mut();
+        response_headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        response_headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    response
+}
+
+async fn websocket(
+    AxumState(state): AxumState<EndpointState>,
+    Query(query): Query<TokenQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    if let Err(status) = local_token::authorize(&state.token, &query) {
+        return status.into_response();
+    }
+    upgrade.on_upgrade(move |socket| stream_snapshots(socket, state.snapshots))
+}
+
+/// Sends the current state, then every change until the overlay disconnects.
+async fn stream_snapshots(mut socket: WebSocket, mut snapshots: watch::Receiver<OverlaySnapshot>) {
+    let mut send = true;
+    loop {
+        if send {
+            let json = match serde_json::to_string(&*snapshots.borrow_and_update()) {
+                Ok(json) => json,
+                Err(e) => {
+                    warn!("Failed to encode overlay state: {e}");
+                    break;
+                }
+            };
+            if socket.send(Message::Text(json.into())).await.is_err() {
+                break;
+            }
+        }
+
+        send = tokio::select! {
+            changed = snapshots.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                true
+            }
+            // Overlays only listen; anything they send is ignored.
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => false,
+            },
+        };
+    }
+}
+
+#[tauri::command]
+pub fn get_overlay_settings(state: State<'_, OverlayState>) -> OverlaySettings {
+    *state.settings.lock().unwrap()
+}
+
+/// Applies new overlay settings, restarting the endpoint if needed.
+#[tauri::command]
+pub async fn set_overlay_settings(
+    app: AppHandle,
+    state: State<'_, OverlayState>,
+    settings: OverlaySettings,
+) -> Result<(), String> {
+    if settings.port == 0 {
+        return Err("Overlay port must not be 0".to_string());
+    }
+    let previous = std::mem::replace(&mut *state.settings.lock().unwrap(), settings);
+    if previous != settings {
+        state.restart(app.state::<LocalToken>().get()).await?;
+    }
+    settings::save(&app, SETTINGS_FILE, &settings)
+}
@@ -0,0 +1,95 @@
+//! Echo cancellation and gain control profiles, one per input device.
+//!
+//! A headset needs no echo cancellation while a laptop microphone next to its
+//! speakers does, so settings are remembered per input device and the
+//! profile of the device in use is applied to the capture pipeline.
+
+use crate::settings;
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::processing::ProcessingConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Runtime, State};
+
+const SETTINGS_FILE: &str = "audio_processing.json";
+
+/// Profile key for the system default input device.
+const DEFAULT_DEVICE: &str = "default";
+
+pub struct ProcessingProfiles {
+    profiles: Mutex<HashMap<String, ProcessingConfig>>,
+    /// Input device being captured from, `None` for the system default.
+    active_device: Mutex<Option<String>>,
+    gate: Arc<TransmitGate>,
+}
+
+impl ProcessingProfiles {
+    pub fn new(gate: Arc<TransmitGate>) -> Self {
+        Self {
+            profiles: Mutex::new(HashMap::new()),
+            active_device: Mutex::new(None),
+            gate,
+        }
+    }
+
+    fn profile(&self, device: Option<&str>) -> ProcessingConfig {
+        self.profiles
+            .lock()
+            .unwrap()
+            .get(device.unwrap_or(DEFAULT_DEVICE))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Switches to `device`'s profile, e.g. when the input device changes.
+    pub fn activate(&self, device: Option<&str>) {
+        let mut active = self.active_device.lock().unwrap();
+        self.gate.set_processing_config(self.profile(device));
+        *active = device.map(str::to_string);
+    }
+}
+
+/// Restores saved profiles and applies the default device's.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let profiles: HashMap<String, ProcessingConfig> =
+        settings::load(app, SETTINGS_FILE)?.unwrap_or_default();
+    let state = app.state::<ProcessingProfiles>();
+    *state.profiles.lock().unwrap() = profiles;
+    state.activate(None);
+    Ok(())
+}
+
+/// Processing settings for `device`, or the default input device.
+#[tauri::command]
+pub fn get_audio_processing(
+    state: State<'_, ProcessingProfiles>,
+    device: Option<String>,
+) -> ProcessingConfig {
+    state.profile(device.as_deref())
+}
+
+#[tauri::command]
+pub fn set_audio_processing(
+    app: AppHandle,
+    state: State<'_, ProcessingProfiles>,
+    device: Option<String>,
+    config: ProcessingConfig,
+) -> Result<(), String> {
+    if !(config.target_level_db.is_finite() && config.max_gain_db.is_finite()) {
+        return Err("Gain control levels must be numbers".to_string());
+    }
+    if !(1..=500).contains(&config.echo_tail_ms) {
+        return Err("Echo tail must be between 1 and 500 ms".to_string());
+    }
+
+    let active = state.active_device.lock().unwrap();
+    let mut profiles = state.profiles.lock().unwrap();
+    profiles.insert(
+        device.as_deref().unwrap_or(DEFAULT_DEVICE).to_string(),
+        config,
+    );
+    if *active == device {
+        state.gate.set_processing_config(config);
+    }
+    settings::save(&app, SETTINGS_FILE, &*profiles)
+}
@@ -0,0 +1,156 @@
+//! Push-to-talk bound to global hotkeys.
+//!
+//! Shortcuts are registered with the OS rather than the webview, so they fire
+//! while a game has focus. Each binding keys one radio, and the capture
+//! pipeline transmits while any bound shortcut is held down.
+
+use crate::radio::MAX_RADIO_ID;
+use crate::settings;
+use fleet_net_audio::capture::TransmitGate;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tauri::plugin::TauriPlugin;
+use tauri::{AppHandle, Manager, Runtime, State};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+use tracing::warn;
+
+const BINDINGS_FILE: &str = "ptt_bindings.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PttBinding {
+    pub radio_id: u8,
+    /// Accelerator string such as `"Shift+F1"` or `"CommandOrControl+Space"`.
+    pub shortcut: String,
+}
+
+pub struct PttState {
+    bindings: Mutex<Vec<PttBinding>>,
+    gate: Arc<TransmitGate>,
+}
+
+impl PttState {
+    pub fn new(gate: Arc<TransmitGate>) -> Self {
+        Self {
+            bindings: Mutex::new(Vec::new()),
+            gate,
+        }
+    }
+
+    /// Radios keyed by `shortcut`; several radios may share one key.
+    fn radios_for(&self, shortcut: &Shortcut) -> Vec<u8> {
+        let bindings = self.bindings.lock().unwrap();
+        bindings
+            .iter()
+            .filter(|binding| {
+                binding
+                    .shortcut
+                    .parse::<Shortcut>()
+                    .is_ok_and(|bound| bound.id() == shortcut.id())
+            })
+            .map(|binding| binding.radio_id)
+            .collect()
+    }
+}
+
+/// Global shortcut plugin that drives the transmit gate from [`PttState`].
+pub fn plugin<R: Runtime>() -> TauriPlugin<R> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(|app, shortcut, event| {
+            let state = app.state::<PttState>();
+            for radio_id in state.radios_for(shortcut) {
+                match event.state() {
+                    ShortcutState::Pressed => state.gate.press(radio_id),
+                    ShortcutState::Released => state.gate.release(radio_id),
+                }
+            }
+        })
+        .build()
+}
+
+/// Restores persisted bindings and registers their shortcuts.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let bindings: Vec<PttBinding> = settings::load(app, BINDINGS_FILE)?.unwrap_or_default();
+    for binding in &bindings {
+        if let Err(e) = register(app, &binding.shortcut) {
+            warn!("Could not register PTT shortcut {}: {e}", binding.shortcut);
+        }
+    }
+    *app.state::<PttState>().bindings.lock().unwrap() = bindings;
+    Ok(())
+}
+
+fn register<R: Runtime>(app: &AppHandle<R>, shortcut: &str) -> Result<(), String> {
+    let global_shortcut = app.global_shortcut();
+    if global_shortcut.is_registered(shortcut) {
+        return Ok(());
+    }
+    global_shortcut
+        .register(shortcut)
+        .map_err(|e| e.to_string())
+}
+
+/// Unregisters `shortcut` unless another binding still uses it.
+fn unregister_unused<R: Runtime>(app: &AppHandle<R>, bindings: &[PttBinding], shortcut: &str) {
+    if bindings.iter().any(|binding| binding.shortcut == shortcut) {
+        return;
+    }
+    if let Err(e) = app.global_shortcut().unregister(shortcut) {
+        warn!("Could not unregister PTT shortcut {shortcut}: {e}");
+    }
+}
+
+#[tauri::command]
+pub fn get_ptt_bindings(state: State<'_, PttState>) -> Vec<PttBinding> {
+    state.bindings.lock().unwrap().clone()
+}
+
+/// Binds `shortcut` as push-to-talk for `radio_id`, replacing its previous binding.
+#[tauri::command]
+pub fn set_ptt_binding(
+    app: AppHandle,
+    state: State<'_, PttState>,
+    radio_id: u8,
+    shortcut: String,
+) -> Result<(), String> {
+    if radio_id > MAX_RADIO_ID {
+        return Err(format!("Radio id {radio_id} is out of range"));
+    }
+    shortcut
+        .parse::<Shortcut>()
+        .map_err(|e| format!("Invalid shortcut {shortcut}: {e}"))?;
+
+    let mut bindings = state.bindings.lock().unwrap();
+    register(&app, &shortcut)?;
+    let previous = bindings
+        .iter()
+        .position(|binding| binding.radio_id == radio_id)
+        .map(|index| bindings.remove(index));
+    bindings.push(PttBinding { radio_id, shortcut });
+    if let Some(previous) = previous {
+        unregister_unused(&app, &bindings, &previous.shortcut);
+    }
+
+    // A radio keyed by the old shortcut must not stay stuck on.
+    state.gate.release(radio_id);
+    settings::save(&app, BINDINGS_FILE, bindings.as_slice())
+}
+
+#[tauri::command]
+pub fn clear_ptt_binding(
+    app: AppHandle,
+    state: State<'_, PttState>,
+    radio_id: u8,
+) -> Result<(), String> {
+    let mut bindings = state.bindings.lock().unwrap();
+    let Some(index) = bindings
+        .iter()
+        .position(|binding| binding.radio_id == radio_id)
+    else {
+        return Ok(());
+    };
+
+    let removed = bindings.remove(index);
+    unregister_unused(&app, &bindings, &removed.shortcut);
+    state.gate.release(radio_id);
+    settings::save(&app, BINDINGS_FILE, bindings.as_slice())
+}
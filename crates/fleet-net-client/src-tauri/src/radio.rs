@@ -1,32 +1,675 @@
-use crate::types::ChannelId;
+//! The radios a user has configured and how they shape received audio.
+//!
+//! Each radio monitors one channel, and several radios can be on the air at
+//! once. Whenever the set of monitored channels changes the server is asked
+//! to subscribe to exactly those channels, and a `radio_subscriptions` event
+//! carries the new set to the UI. After a session resumes, the server's
+//! subscriptions are reconciled with the radios in case they drifted apart.
+//!
+//! Next to the radios sits the intercom, the crew's always-on channel. It is
+//! subscribed like a radio but mixed apart from them, clean and centered,
+//! with its own volume and its own say in how much it ducks the radios
+//! while someone talks on it.
+//!
+//! Radios can play on their own output device, e.g. UHF on the headset and
+//! the intercom on desk speakers. Every device in use gets an output bus of
+//! the mixer, played by the [`PlaybackRouter`]; a radio whose device cannot
+//! be opened falls back to the default device.
+//!
+//! The whole radio stack can be saved as a named preset, grouped by profile
+//! (typically a game or unit), and loaded back in one step.
+
+use crate::connection::ConnectionManager;
+use crate::settings;
+use fleet_net_audio::cues::CueConfig;
+use fleet_net_audio::effects::{RadioEffect, RadioTypes};
+use fleet_net_audio::mixer::{IntercomMix, Mixer, OutputBus, RadioMix, DEFAULT_BUS};
+use fleet_net_audio::output::{output_device_names, PlaybackRouter};
+use fleet_net_common::types::ChannelId;
+use fleet_net_protocol::message::ControlMessage;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use tracing::warn;
+
+/// Highest radio id; radio ids double as transmit gate bits, see
+/// [`fleet_net_audio::capture::TransmitGate`].
+pub const MAX_RADIO_ID: u8 = 63;
+
+/// Event emitted when the set of monitored channels changes.
+pub const SUBSCRIPTIONS_EVENT: &str = "radio_subscriptions";
+
+const PRESETS_FILE: &str = "radio_presets.json";
+const INTERCOM_FILE: &str = "intercom.json";
+
+#[derive(Debug, Clone, Serialize)]
+struct SubscriptionsPayload {
+    subscribed_channels: Vec<ChannelId>,
+}
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// The monitored channels before and after a radio change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelChange {
+    pub before: Vec<ChannelId>,
+    pub after: Vec<ChannelId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Radio {
     pub id: u8,
     pub radio_type: RadioTypes,
     pub channel_id: ChannelId,
+    /// Frequency shown on the radio's dial, in MHz, when the channel has one.
+    #[serde(default)]
+    pub frequency_mhz: Option<f64>,
     pub volume: f32,
     pub pan_lr: f32,
     pub is_dimmed: bool,
     pub is_muted: bool,
     pub has_priority: bool,
+    /// Output device the radio plays on, `None` for the default one.
+    #[serde(default)]
+    pub output_device: Option<String>,
+}
+
+impl Radio {
+    pub fn mix(&self, output: OutputBus) -> RadioMix {
+        RadioMix {
+            volume: self.volume,
+            pan: self.pan_lr,
+            muted: self.is_muted,
+            dimmed: self.is_dimmed,
+            priority: self.has_priority,
+            output,
+        }
+    }
+}
+
+/// The always-on crew channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Intercom {
+    pub channel_id: ChannelId,
+    pub volume: f32,
+    pub is_muted: bool,
+    /// Gain applied to every radio while someone talks on the intercom, 0.0
+    /// to 1.0.
+    pub radio_duck: f32,
+    /// Whether priority radio traffic dims the intercom too.
+    pub ducked_by_priority: bool,
+    /// Output device the intercom plays on, `None` for the default one.
+    #[serde(default)]
+    pub output_device: Option<String>,
+}
+
+impl Intercom {
+    pub fn mix(&self, output: OutputBus) -> IntercomMix {
+        IntercomMix {
+            volume: self.volume,
+            muted: self.is_muted,
+            radio_duck: self.radio_duck,
+            ducked_by_priority: self.ducked_by_priority,
+            output,
+        }
+    }
+}
+
+/// The mixer bus each output device in use plays.
+type OutputBuses = HashMap<String, OutputBus>;
+
+pub struct RadioState {
+    radios: Mutex<Vec<Radio>>,
+    intercom: Mutex<Option<Intercom>>,
+    mixer: Arc<Mutex<Mixer>>,
+    router: PlaybackRouter,
+    buses: Mutex<OutputBuses>,
+    /// Sound cues per radio type, where they differ from the defaults.
+    cues: Mutex<HashMap<RadioTypes, CueConfig>>,
+}
+
+impl RadioState {
+    pub fn new(mixer: Arc<Mutex<Mixer>>, router: PlaybackRouter) -> Self {
+        Self {
+            radios: Mutex::new(Vec::new()),
+            intercom: Mutex::new(None),
+            mixer,
+            router,
+            buses: Mutex::new(HashMap::new()),
+            cues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sound cues played by radios of `radio_type`.
+    pub fn cue_config(&self, radio_type: RadioTypes) -> CueConfig {
+        self.cues
+            .lock()
+            .unwrap()
+            .get(&radio_type)
+            .copied()
+            .unwrap_or_else(|| CueConfig::for_radio(radio_type))
+    }
+
+    /// Changes the sound cues of every radio of `radio_type`.
+    pub fn set_cue_config(&self, radio_type: RadioTypes, cues: CueConfig) {
+        let radios = self.radios.lock().unwrap();
+        self.cues.lock().unwrap().insert(radio_type, cues);
+        let mut mixer = self.mixer.lock().unwrap();
+        for radio in radios.iter().filter(|radio| radio.radio_type == radio_type) {
+            mixer.set_channel_cues(radio.channel_id, Some(cues));
+        }
+    }
+
+    /// Applies everything about `radio` to its channel in the mixer.
+    fn tune_channel(&self, mixer: &mut Mixer, radio: &Radio, buses: &OutputBuses) {
+        let channel_id = radio.channel_id;
+        let output = output_bus(buses, radio.output_device.as_ref());
+        mixer.set_radio_mix(channel_id, radio.mix(output));
+        mixer.set_channel_effect(
+            channel_id,
+            Some(RadioEffect::for_radio_type(radio.radio_type)),
+        );
+        mixer.set_channel_cues(channel_id, Some(self.cue_config(radio.radio_type)));
+    }
+
+    /// Hands `channel_id` to another radio still tuned to it, or lets it play
+    /// untouched once no radio monitors it.
+    fn release_channel(
+        &self,
+        mixer: &mut Mixer,
+        radios: &[Radio],
+        buses: &OutputBuses,
+        channel_id: ChannelId,
+    ) {
+        match radios.iter().find(|radio| radio.channel_id == channel_id) {
+            Some(radio) => self.tune_channel(mixer, radio, buses),
+            None => {
+                mixer.clear_radio_mix(channel_id);
+                mixer.set_channel_effect(channel_id, None);
+                mixer.set_channel_cues(channel_id, None);
+            }
+        }
+    }
+
+    /// Starts playing the default bus on the default output device.
+    pub fn start_playback(&self) -> Result<(), String> {
+        self.router
+            .route(DEFAULT_BUS, None)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Gives every output device used by `radios` or the intercom a bus and
+    /// plays it there, closing devices nothing uses anymore.
+    fn route_outputs(&self, radios: &[Radio], intercom: Option<&Intercom>) -> OutputBuses {
+        let devices: Vec<&String> = radios
+            .iter()
+            .filter_map(|radio| radio.output_device.as_ref())
+            .chain(intercom.and_then(|intercom| intercom.output_device.as_ref()))
+            .collect();
+        let mut buses = self.buses.lock().unwrap();
+        buses.retain(|device, bus| {
+            let used = devices.contains(&device);
+            if !used {
+                self.router.stop(*bus);
+            }
+            used
+        });
+
+        for device in devices {
+            if buses.contains_key(device) {
+                continue;
+            }
+            let Some(bus) = (DEFAULT_BUS + 1..=OutputBus::MAX)
+                .find(|bus| buses.values().all(|used| used != bus))
+            else {
+                warn!("No output bus left for {device}, using the default device");
+                continue;
+            };
+            match self.router.route(bus, Some(device)) {
+                Ok(()) => {
+                    buses.insert(device.clone(), bus);
+                }
+                Err(e) => warn!("Playing on the default device instead of {device}: {e}"),
+            }
+        }
+        buses.clone()
+    }
+
+    /// Adds or replaces the radio with `radio.id` and applies it to the mixer.
+    ///
+    /// Returns how the set of monitored channels changed, if it did.
+    pub fn upsert(&self, radio: Radio) -> Option<ChannelChange> {
+        let mut radios = self.radios.lock().unwrap();
+        let intercom = self.intercom.lock().unwrap();
+        let before = channels(&radios, intercom.as_ref());
+        let channel_id = radio.channel_id;
+        let previous = match radios.iter_mut().find(|existing| existing.id == radio.id) {
+            Some(existing) => Some(std::mem::replace(existing, radio.clone())),
+            None => {
+                radios.push(radio.clone());
+                None
+            }
+        };
+
+        let buses = self.route_outputs(&radios, intercom.as_ref());
+        let mut mixer = self.mixer.lock().unwrap();
+        if let Some(previous) = previous.filter(|previous| previous.channel_id != channel_id) {
+            self.release_channel(&mut mixer, &radios, &buses, previous.channel_id);
+        }
+        self.tune_channel(&mut mixer, &radio, &buses);
+
+        let after = channels(&radios, intercom.as_ref());
+        (after != before).then_some(ChannelChange { before, after })
+    }
+
+    /// Removes the radio with `id`, returning how the set of monitored
+    /// channels changed, if it did.
+    pub fn remove(&self, id: u8) -> Result<Option<ChannelChange>, String> {
+        let mut radios = self.radios.lock().unwrap();
+        let intercom = self.intercom.lock().unwrap();
+        let before = channels(&radios, intercom.as_ref());
+        let index = radios
+            .iter()
+            .position(|radio| radio.id == id)
+            .ok_or_else(|| format!("No radio with id {id}"))?;
+        let removed = radios.remove(index);
+
+        let buses = self.route_outputs(&radios, intercom.as_ref());
+        self.release_channel(
+            &mut self.mixer.lock().unwrap(),
+            &radios,
+            &buses,
+            removed.channel_id,
+        );
+
+        let after = channels(&radios, intercom.as_ref());
+        Ok((after != before).then_some(ChannelChange { before, after }))
+    }
+
+    /// Replaces every radio at once, e.g. when loading a preset.
+    pub fn replace_all(&self, new_radios: Vec<Radio>) -> Option<ChannelChange> {
+        let mut radios = self.radios.lock().unwrap();
+        let intercom = self.intercom.lock().unwrap();
+        let before = channels(&radios, intercom.as_ref());
+        *radios = new_radios;
+
+        let buses = self.route_outputs(&radios, intercom.as_ref());
+        let mut mixer = self.mixer.lock().unwrap();
+        for &channel_id in &before {
+            self.release_channel(&mut mixer, &radios, &buses, channel_id);
+        }
+        for radio in radios.iter() {
+            self.tune_channel(&mut mixer, radio, &buses);
+        }
+
+        let after = channels(&radios, intercom.as_ref());
+        (after != before).then_some(ChannelChange { before, after })
+    }
+
+    /// Replaces the intercom, or drops it with `None`, returning how the
+    /// set of monitored channels changed, if it did.
+    pub fn set_intercom(&self, new_intercom: Option<Intercom>) -> Option<ChannelChange> {
+        let radios = self.radios.lock().unwrap();
+        let mut intercom = self.intercom.lock().unwrap();
+        let before = channels(&radios, intercom.as_ref());
+        let previous = std::mem::replace(&mut *intercom, new_intercom);
+
+        let buses = self.route_outputs(&radios, intercom.as_ref());
+        let mut mixer = self.mixer.lock().unwrap();
+        if let Some(previous) = previous {
+            mixer.clear_intercom_mix(previous.channel_id);
+        }
+        if let Some(intercom) = &*intercom {
+            let output = output_bus(&buses, intercom.output_device.as_ref());
+            mixer.set_intercom_mix(intercom.channel_id, intercom.mix(output));
+        }
+
+        let after = channels(&radios, intercom.as_ref());
+        (after != before).then_some(ChannelChange { before, after })
+    }
+
+    pub fn intercom(&self) -> Option<Intercom> {
+        self.intercom.lock().unwrap().clone()
+    }
+
+    pub fn radios(&self) -> Vec<Radio> {
+        self.radios.lock().unwrap().clone()
+    }
+
+    fn get(&self, id: u8) -> Result<Radio, String> {
+        self.radios
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|radio| radio.id == id)
+            .cloned()
+            .ok_or_else(|| format!("No radio with id {id}"))
+    }
+
+    /// Distinct channels monitored by any radio or the intercom, in
+    /// ascending order.
+    pub fn monitored_channels(&self) -> Vec<ChannelId> {
+        let radios = self.radios.lock().unwrap();
+        channels(&radios, self.intercom.lock().unwrap().as_ref())
+    }
+
+    /// Lowest radio id not yet in use.
+    fn next_id(&self) -> Option<u8> {
+        let radios = self.radios.lock().unwrap();
+        (0..=MAX_RADIO_ID).find(|id| radios.iter().all(|radio| radio.id != *id))
+    }
+}
+
+/// Distinct channels monitored by `radios` and `intercom`, in ascending order.
+fn channels(radios: &[Radio], intercom: Option<&Intercom>) -> Vec<ChannelId> {
+    let mut channels: Vec<_> = radios
+        .iter()
+        .map(|radio| radio.channel_id)
+        .chain(intercom.map(|intercom| intercom.channel_id))
+        .collect();
+    channels.sort_unstable();
+    channels.dedup();
+    channels
+}
+
+/// The bus of `device`; the default one if it is unavailable.
+fn output_bus(buses: &OutputBuses, device: Option<&String>) -> OutputBus {
+    device
+        .and_then(|device| buses.get(device))
+        .copied()
+        .unwrap_or(DEFAULT_BUS)
+}
+
+/// Radio stacks saved per profile, then by preset name.
+type PresetMap = BTreeMap<String, BTreeMap<String, Vec<Radio>>>;
+
+#[derive(Default)]
+pub struct RadioPresets {
+    presets: Mutex<PresetMap>,
+}
+
+/// Restores saved radio presets and the intercom, and starts playback.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let presets: PresetMap = settings::load(app, PRESETS_FILE)?.unwrap_or_default();
+    *app.state::<RadioPresets>().presets.lock().unwrap() = presets;
+    let intercom = settings::load::<Option<Intercom>, _>(app, INTERCOM_FILE)?.flatten();
+    if let Some(intercom) = intercom.filter(|intercom| validate_intercom(intercom).is_ok()) {
+        sync_subscriptions(app, app.state::<RadioState>().set_intercom(Some(intercom)));
+    }
+    // A machine without speakers can still transmit.
+    if let Err(e) = app.state::<RadioState>().start_playback() {
+        warn!("Failed to start playback: {e}");
+    }
+    Ok(())
+}
+
+/// Asks the server to move from subscriptions `before` to `after`.
+fn send_subscription_changes(
+    connection: &ConnectionManager,
+    before: &[ChannelId],
+    after: &[ChannelId],
+) {
+    let subscribe = after
+        .iter()
+        .filter(|channel_id| !before.contains(channel_id))
+        .map(|&channel_id| ControlMessage::SubscribeChannel { channel_id });
+    let unsubscribe = before
+        .iter()
+        .filter(|channel_id| !after.contains(channel_id))
+        .map(|&channel_id| ControlMessage::UnsubscribeChannel { channel_id });
+    for message in subscribe.chain(unsubscribe) {
+        if let Err(e) = connection.send(message) {
+            warn!("Failed to update radio subscriptions: {e}");
+        }
+    }
+}
+
+/// Brings the server's subscriptions in line with the radios, for when a
+/// resumed session reports a set that no longer matches.
+pub fn reconcile<R: Runtime>(app: &AppHandle<R>, server_channels: &[ChannelId]) {
+    let monitored = app.state::<RadioState>().monitored_channels();
+    if monitored != server_channels {
+        send_subscription_changes(
+            &app.state::<ConnectionManager>(),
+            server_channels,
+            &monitored,
+        );
+    }
+}
+
+/// Subscribes the connection to newly monitored channels, drops the ones no
+/// radio uses anymore and tells the UI.
+pub(crate) fn sync_subscriptions<R: Runtime>(app: &AppHandle<R>, change: Option<ChannelChange>) {
+    let Some(ChannelChange { before, after }) = change else {
+        return;
+    };
+
+    let connection = app.state::<ConnectionManager>();
+    if connection.is_connected() {
+        send_subscription_changes(&connection, &before, &after);
+    }
+
+    if let Err(e) = app.emit(
+        SUBSCRIPTIONS_EVENT,
+        SubscriptionsPayload {
+            subscribed_channels: after,
+        },
+    ) {
+        warn!("Failed to emit radio subscriptions: {e}");
+    }
+}
+
+#[tauri::command]
+pub fn get_radios(state: State<'_, RadioState>) -> Vec<Radio> {
+    state.radios()
+}
+
+/// Adds a radio tuned to `channel_id` with default volume and pan.
+#[tauri::command]
+pub fn add_radio(
+    app: AppHandle,
+    state: State<'_, RadioState>,
+    radio_type: RadioTypes,
+    channel_id: ChannelId,
+) -> Result<Radio, String> {
+    let id = state
+        .next_id()
+        .ok_or_else(|| format!("At most {} radios are supported", MAX_RADIO_ID as u16 + 1))?;
+    let radio = Radio {
+        id,
+        radio_type,
+        channel_id,
+        frequency_mhz: None,
+        volume: 1.0,
+        pan_lr: 0.0,
+        is_dimmed: false,
+        is_muted: false,
+        has_priority: false,
+        output_device: None,
+    };
+    sync_subscriptions(&app, state.upsert(radio.clone()));
+    Ok(radio)
+}
+
+#[tauri::command]
+pub fn remove_radio(app: AppHandle, state: State<'_, RadioState>, id: u8) -> Result<(), String> {
+    sync_subscriptions(&app, state.remove(id)?);
+    Ok(())
+}
+
+/// Applies a radio's type, channel, volume, pan, mute, dim, priority and
+/// output device settings.
+#[tauri::command]
+pub fn update_radio(
+    app: AppHandle,
+    state: State<'_, RadioState>,
+    radio: Radio,
+) -> Result<(), String> {
+    validate(&radio)?;
+    sync_subscriptions(&app, state.upsert(radio));
+    Ok(())
+}
+
+fn validate(radio: &Radio) -> Result<(), String> {
+    if radio.id > MAX_RADIO_ID {
+        return Err(format!("Radio id {} is out of range", radio.id));
+    }
+    if !(radio.volume.is_finite() && radio.pan_lr.is_finite()) {
+        return Err("Radio volume and pan must be numbers".to_string());
+    }
+    if radio
+        .frequency_mhz
+        .is_some_and(|mhz| !(mhz.is_finite() && mhz > 0.0))
+    {
+        return Err("Radio frequency must be a positive number".to_string());
+    }
+    if radio
+        .output_device
+        .as_ref()
+        .is_some_and(|device| device.trim().is_empty())
+    {
+        return Err("Radio output device must not be empty".to_string());
+    }
+    Ok(())
+}
+
+/// Tunes radio `id` to `channel_id`, keeping its other settings.
+#[tauri::command]
+pub fn tune_radio(
+    app: AppHandle,
+    state: State<'_, RadioState>,
+    id: u8,
+    channel_id: ChannelId,
+    frequency_mhz: Option<f64>,
+) -> Result<Radio, String> {
+    let radio = Radio {
+        channel_id,
+        frequency_mhz,
+        ..state.get(id)?
+    };
+    validate(&radio)?;
+    sync_subscriptions(&app, state.upsert(radio.clone()));
+    Ok(radio)
+}
+
+#[tauri::command]
+pub fn get_intercom(state: State<'_, RadioState>) -> Option<Intercom> {
+    state.intercom()
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub enum RadioTypes {
-    Hf = 0,
-    Uhf = 1,
-    Vhf = 2,
-    Satellite = 3,
-    Quantum = 4,
+/// Applies the intercom's channel, volume, mute, ducking and output device
+/// settings, or turns the intercom off with `None`.
+#[tauri::command]
+pub fn set_intercom(
+    app: AppHandle,
+    state: State<'_, RadioState>,
+    intercom: Option<Intercom>,
+) -> Result<(), String> {
+    if let Some(intercom) = &intercom {
+        validate_intercom(intercom)?;
+    }
+    sync_subscriptions(&app, state.set_intercom(intercom.clone()));
+    settings::save(&app, INTERCOM_FILE, &intercom)
+}
+
+fn validate_intercom(intercom: &Intercom) -> Result<(), String> {
+    if !intercom.volume.is_finite() {
+        return Err("Intercom volume must be a number".to_string());
+    }
+    if !(0.0..=1.0).contains(&intercom.radio_duck) {
+        return Err("Intercom radio ducking must be between 0.0 and 1.0".to_string());
+    }
+    if intercom
+        .output_device
+        .as_ref()
+        .is_some_and(|device| device.trim().is_empty())
+    {
+        return Err("Intercom output device must not be empty".to_string());
+    }
+    Ok(())
+}
+
+/// Names of the output devices radios can be routed to.
+#[tauri::command]
+pub fn get_output_devices() -> Result<Vec<String>, String> {
+    output_device_names().map_err(|e| e.to_string())
+}
+
+/// Saved preset names, by profile.
+#[tauri::command]
+pub fn get_radio_presets(presets: State<'_, RadioPresets>) -> BTreeMap<String, Vec<String>> {
+    presets
+        .presets
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(profile, named)| (profile.clone(), named.keys().cloned().collect()))
+        .collect()
+}
+
+/// Saves the current radios as preset `name` under `profile`, replacing any
+/// preset of that name.
+#[tauri::command]
+pub fn save_radio_preset(
+    app: AppHandle,
+    state: State<'_, RadioState>,
+    presets: State<'_, RadioPresets>,
+    profile: String,
+    name: String,
+) -> Result<(), String> {
+    if profile.trim().is_empty() || name.trim().is_empty() {
+        return Err("Preset profile and name must not be empty".to_string());
+    }
+    let radios = state.radios.lock().unwrap().clone();
+    let mut presets = presets.presets.lock().unwrap();
+    presets.entry(profile).or_default().insert(name, radios);
+    settings::save(&app, PRESETS_FILE, &*presets)
+}
+
+/// Replaces the current radios with preset `name` from `profile`.
+#[tauri::command]
+pub fn load_radio_preset(
+    app: AppHandle,
+    state: State<'_, RadioState>,
+    presets: State<'_, RadioPresets>,
+    profile: String,
+    name: String,
+) -> Result<Vec<Radio>, String> {
+    let radios = presets
+        .presets
+        .lock()
+        .unwrap()
+        .get(&profile)
+        .and_then(|named| named.get(&name))
+        .cloned()
+        .ok_or_else(|| format!("No preset {name:?} for {profile:?}"))?;
+    // The file may have been edited by hand.
+    radios.iter().try_for_each(validate)?;
+    let mut ids: Vec<_> = radios.iter().map(|radio| radio.id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() != radios.len() {
+        return Err(format!("Preset {name:?} uses a radio id twice"));
+    }
+
+    sync_subscriptions(&app, state.replace_all(radios.clone()));
+    Ok(radios)
 }
 
-// Mapped to RadioTypes for a radio to know how to process the audio.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct RadioEffect {
-    pub low_cut: f32,    // Low cut frequency
-    pub high_cut: f32,   // High cut frequency
-    pub distortion: f32, // Apply distortion effect
-    pub decay: f32,      // Simulate decay with random noise interruption.
+#[tauri::command]
+pub fn delete_radio_preset(
+    app: AppHandle,
+    presets: State<'_, RadioPresets>,
+    profile: String,
+    name: String,
+) -> Result<(), String> {
+    let mut presets = presets.presets.lock().unwrap();
+    let named = presets
+        .get_mut(&profile)
+        .filter(|named| named.contains_key(&name))
+        .ok_or_else(|| format!("No preset {name:?} for {profile:?}"))?;
+    named.remove(&name);
+    if named.is_empty() {
+        presets.remove(&profile);
+    }
+    settings::save(&app, PRESETS_FILE, &*presets)
 }
@@ -0,0 +1,129 @@
+//! Reception reports and adaptive bitrate.
+//!
+//! Every [`REPORT_INTERVAL`] the packet loss and jitter the mixer measured
+//! for each remote speaker is reported to the server. The server answers the
+//! reports of our own listeners with a `reception_feedback` message, which is
+//! published on a watch channel that voice encoders follow with
+//! `VoiceEncoder::set_feedback_source` to adapt their bitrate and FEC.
+//!
+//! On channels the server lists as allowing retransmission, packets the
+//! mixer finds missing are asked for again every [`RETRANSMIT_INTERVAL`],
+//! in time for most of them to still be played.
+
+use crate::connection::ConnectionManager;
+use fleet_net_audio::encoder::BitrateFeedback;
+use fleet_net_audio::mixer::Mixer;
+use fleet_net_common::types::ChannelId;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::MAX_RETRANSMIT_SEQUENCES;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::sync::watch;
+use tracing::debug;
+
+/// How often reception is reported for every speaker heard meanwhile.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often missing packets are asked for again, once per frame.
+pub const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The latest feedback on how well our listeners hear us.
+pub struct ReceptionFeedback {
+    current: watch::Sender<Option<BitrateFeedback>>,
+}
+
+impl Default for ReceptionFeedback {
+    fn default() -> Self {
+        Self {
+            current: watch::Sender::new(None),
+        }
+    }
+}
+
+impl ReceptionFeedback {
+    /// Follows feedback from now on, for `VoiceEncoder::set_feedback_source`.
+    pub fn subscribe(&self) -> watch::Receiver<Option<BitrateFeedback>> {
+        self.current.subscribe()
+    }
+
+    pub fn update(&self, feedback: BitrateFeedback) {
+        self.current.send_replace(Some(feedback));
+    }
+}
+
+/// Channels whose lost packets may be asked for again, as last listed in
+/// `subscriptions_changed`.
+#[derive(Default)]
+pub struct RetransmitChannels(Mutex<HashSet<ChannelId>>);
+
+impl RetransmitChannels {
+    pub fn set(&self, channel_ids: &[ChannelId]) {
+        *self.0.lock().unwrap() = channel_ids.iter().copied().collect();
+    }
+
+    pub fn contains(&self, channel_id: ChannelId) -> bool {
+        self.0.lock().unwrap().contains(&channel_id)
+    }
+}
+
+/// Sends a [`ControlMessage::ReceptionReport`] for every speaker heard
+/// since the last report, while connected.
+pub fn spawn_reports<R: Runtime>(app: &AppHandle<R>, mixer: Arc<Mutex<Mixer>>) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let reception = mixer.lock().unwrap().take_reception();
+            let connection = app.state::<ConnectionManager>();
+            if !connection.is_connected() {
+                continue;
+            }
+            for (user_id, channel_id, quality) in reception {
+                let report = ControlMessage::ReceptionReport {
+                    user_id,
+                    channel_id,
+                    quality,
+                };
+                if let Err(e) = connection.send(report) {
+                    debug!("Failed to send reception report: {e}");
+                }
+            }
+        }
+    });
+}
+
+/// Sends a [`ControlMessage::RetransmitRequest`] for the packets missing
+/// from each speaker heard on a channel that allows it, while connected.
+pub fn spawn_retransmit_requests<R: Runtime>(app: &AppHandle<R>, mixer: Arc<Mutex<Mixer>>) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(RETRANSMIT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let missing = mixer.lock().unwrap().take_missing();
+            let connection = app.state::<ConnectionManager>();
+            if missing.is_empty() || !connection.is_connected() {
+                continue;
+            }
+            let channels = app.state::<RetransmitChannels>();
+            for (user_id, channel_id, sequences) in missing {
+                if !channels.contains(channel_id) {
+                    continue;
+                }
+                for sequences in sequences.chunks(MAX_RETRANSMIT_SEQUENCES) {
+                    let request = ControlMessage::RetransmitRequest {
+                        user_id,
+                        channel_id,
+                        sequences: sequences.to_vec(),
+                    };
+                    if let Err(e) = connection.send(request) {
+                        debug!("Failed to send retransmit request: {e}");
+                    }
+                }
+            }
+        }
+    });
+}
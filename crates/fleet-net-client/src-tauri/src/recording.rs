@@ -0,0 +1,181 @@
+//! Local session recordings for after-action reviews.
+//!
+//! Recordings only capture what this client heard and transmitted; nothing
+//! is sent to the server. Files go to the app's data directory unless the
+//! user picks a path.
+//!
+//! When a storage backend is configured, stopped recordings are moved to it
+//! in the background, along with any left behind by an earlier failed
+//! upload. Without one they stay in the recordings directory. Either way
+//! the retention policy prunes what is stored.
+
+use crate::settings;
+use fleet_net_audio::recorder::{Recorder, RecordingSummary};
+use fleet_net_audio::storage::{
+    RecordingStorage, RecordingUploader, RetentionPolicy, StorageConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, Runtime, State};
+use tracing::warn;
+
+const SETTINGS_FILE: &str = "recording_storage.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordingStorageSettings {
+    /// Where stopped recordings are moved; `None` keeps them local.
+    #[serde(default)]
+    pub storage: Option<StorageConfig>,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+}
+
+/// The storage settings and the uploader following them.
+#[derive(Default)]
+pub struct RecordingStore {
+    settings: Mutex<RecordingStorageSettings>,
+    uploader: Mutex<Option<RecordingUploader>>,
+}
+
+impl RecordingStore {
+    /// Replaces the uploader with one for `settings`; the previous one
+    /// finishes what it was handed first.
+    fn restart<R: Runtime>(
+        &self,
+        app: &AppHandle<R>,
+        settings: RecordingStorageSettings,
+    ) -> Result<(), String> {
+        let config = match &settings.storage {
+            Some(config) => config.clone(),
+            None => StorageConfig::Local {
+                dir: recordings_dir(app)?,
+            },
+        };
+        let storage = RecordingStorage::new(&config).map_err(|e| e.to_string())?;
+        let (uploader, task) = RecordingUploader::new(storage, settings.retention);
+        tauri::async_runtime::spawn(task);
+        *self.uploader.lock().unwrap() = Some(uploader);
+        *self.settings.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    /// Hands a stopped recording to the uploader. Recordings saved outside
+    /// the recordings directory only move when a backend is configured.
+    fn stored<R: Runtime>(&self, app: &AppHandle<R>, path: &Path) {
+        let configured = self.settings.lock().unwrap().storage.is_some();
+        let in_recordings_dir =
+            recordings_dir(app).is_ok_and(|dir| path.parent() == Some(dir.as_path()));
+        if let Some(uploader) = &*self.uploader.lock().unwrap() {
+            if configured || in_recordings_dir {
+                uploader.upload(path);
+            }
+        }
+    }
+}
+
+fn recordings_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("No data directory: {e}"))?
+        .join("recordings");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Recordings still in `dir`, which an earlier run did not move.
+fn leftover_recordings(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to read {}: {e}", dir.display());
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wav"))
+        .collect()
+}
+
+fn default_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(recordings_dir(app)?.join(format!("session-{started}.wav")))
+}
+
+/// Restores the storage settings, pruning and uploading what was left.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let saved: Option<RecordingStorageSettings> = settings::load(app, SETTINGS_FILE)?;
+    let store = app.state::<RecordingStore>();
+    if let Err(e) = store.restart(app, saved.unwrap_or_default()) {
+        // A broken backend must not keep the client from starting
+        warn!("Recording storage disabled: {e}");
+        store.restart(app, RecordingStorageSettings::default())?;
+    }
+
+    // Nothing is recording yet, so every file there is finished
+    if store.settings.lock().unwrap().storage.is_some() {
+        for path in leftover_recordings(&recordings_dir(app)?) {
+            store.stored(app, &path);
+        }
+    }
+    Ok(())
+}
+
+/// Starts recording to `path`, or a new file in the recordings directory,
+/// and returns the path used.
+#[tauri::command]
+pub fn start_recording(
+    app: AppHandle,
+    recorder: State<'_, Arc<Recorder>>,
+    path: Option<String>,
+) -> Result<String, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_path(&app)?,
+    };
+    recorder.start(&path).map_err(|e| e.to_string())?;
+    Ok(path.display().to_string())
+}
+
+/// Stops recording and queues the file for storage.
+#[tauri::command]
+pub fn stop_recording(
+    app: AppHandle,
+    recorder: State<'_, Arc<Recorder>>,
+    store: State<'_, RecordingStore>,
+) -> Result<RecordingSummary, String> {
+    let summary = recorder.stop().map_err(|e| e.to_string())?;
+    store.stored(&app, &summary.path);
+    Ok(summary)
+}
+
+/// Whether a recording is running; false once it stopped itself, e.g. when
+/// the disk filled up. `stop_recording` then reports why.
+#[tauri::command]
+pub fn is_recording(recorder: State<'_, Arc<Recorder>>) -> bool {
+    recorder.is_recording()
+}
+
+#[tauri::command]
+pub fn get_recording_storage(store: State<'_, RecordingStore>) -> RecordingStorageSettings {
+    store.settings.lock().unwrap().clone()
+}
+
+/// Switches storage backend or retention policy, keeping the previous
+/// settings if the backend can't be set up.
+#[tauri::command]
+pub fn set_recording_storage(
+    app: AppHandle,
+    store: State<'_, RecordingStore>,
+    settings: RecordingStorageSettings,
+) -> Result<(), String> {
+    store.restart(&app, settings.clone())?;
+    settings::save(&app, SETTINGS_FILE, &settings)
+}
@@ -0,0 +1,202 @@
+//! Server discovery helpers and saved server bookmarks exposed to the UI.
+//!
+//! Bookmarks remember how to reach a server: its address and any fallback
+//! endpoints, a display name, the identity last used there and whether to
+//! connect on startup. A
+//! bookmark's certificate fingerprint is kept in the [`TrustStore`] so
+//! first-use trust checks it like any other pinned certificate.
+
+use crate::settings;
+use crate::trust::TrustStore;
+use fleet_net_protocol::ping::{self, PingResult};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, Runtime, State};
+use tokio::task::JoinSet;
+
+const BOOKMARKS_FILE: &str = "servers.json";
+
+const PING_SAMPLES: u32 = 3;
+const PING_TIMEOUT: Duration = Duration::from_millis(750);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerBookmark {
+    /// `host:port` of the control connection; identifies the bookmark.
+    pub address: String,
+    /// Other `host:port` endpoints of the same server, such as its IPv6
+    /// address or a fallback port for networks blocking the usual one.
+    /// They are raced against `address` on every connection.
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    pub name: String,
+    /// `host:port` of the server's ping responder, if it has one.
+    #[serde(default)]
+    pub ping_endpoint: Option<String>,
+    /// SHA-256 of the server certificate, lowercase hex.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Identity the user last connected with.
+    #[serde(default)]
+    pub identity: Option<String>,
+    #[serde(default)]
+    pub auto_connect: bool,
+    /// Unix time of the last connection, in seconds.
+    #[serde(default)]
+    pub last_connected: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BookmarkStatus {
+    pub address: String,
+    /// `None` when the server has no ping endpoint or did not answer.
+    pub ping: Option<PingResult>,
+}
+
+#[derive(Default)]
+pub struct ServerBookmarks {
+    bookmarks: Mutex<Vec<ServerBookmark>>,
+}
+
+impl ServerBookmarks {
+    /// Records a connection to `address`, if it is bookmarked.
+    pub fn touch<R: Runtime>(&self, app: &AppHandle<R>, address: &str) -> Result<(), String> {
+        let mut bookmarks = self.bookmarks.lock().unwrap();
+        let Some(bookmark) = bookmarks.iter_mut().find(|b| b.address == address) else {
+            return Ok(());
+        };
+        bookmark.last_connected = Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        settings::save(app, BOOKMARKS_FILE, bookmarks.as_slice())
+    }
+
+    /// Fallback endpoints of the server at `address`, if it is bookmarked.
+    pub fn endpoints(&self, address: &str) -> Vec<String> {
+        self.bookmarks
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|b| b.address == address)
+            .map(|b| b.endpoints.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Restores saved bookmarks.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let bookmarks: Vec<ServerBookmark> = settings::load(app, BOOKMARKS_FILE)?.unwrap_or_default();
+    *app.state::<ServerBookmarks>().bookmarks.lock().unwrap() = bookmarks;
+    Ok(())
+}
+
+/// Resolves `endpoint` and pings it, or `None` if it cannot be reached.
+async fn ping_endpoint(endpoint: &str) -> Option<PingResult> {
+    let address = tokio::net::lookup_host(endpoint).await.ok()?.next()?;
+    ping::measure_rtt(address, PING_SAMPLES, PING_TIMEOUT)
+        .await
+        .ok()
+}
+
+/// Measures latency to each `host:port` ping endpoint and returns the
+/// reachable ones, nearest first, so the UI can suggest the closest region.
+#[tauri::command]
+pub async fn ping_servers(endpoints: Vec<String>) -> Result<Vec<PingResult>, String> {
+    let mut addresses = Vec::with_capacity(endpoints.len());
+    for endpoint in &endpoints {
+        // Unresolvable hosts are simply left out of the ranking.
+        if let Ok(mut resolved) = tokio::net::lookup_host(endpoint.as_str()).await {
+            addresses.extend(resolved.next());
+        }
+    }
+
+    Ok(ping::rank_by_latency(&addresses, PING_SAMPLES, PING_TIMEOUT).await)
+}
+
+/// Saved bookmarks, most recently used first, with pinned fingerprints filled in.
+#[tauri::command]
+pub fn get_server_bookmarks(
+    state: State<'_, ServerBookmarks>,
+    trust: State<'_, TrustStore>,
+) -> Vec<ServerBookmark> {
+    let mut bookmarks = state.bookmarks.lock().unwrap().clone();
+    for bookmark in &mut bookmarks {
+        if bookmark.fingerprint.is_none() {
+            bookmark.fingerprint = trust.fingerprint_for(&bookmark.address);
+        }
+    }
+    bookmarks.sort_by_key(|bookmark| Reverse(bookmark.last_connected));
+    bookmarks
+}
+
+/// Adds `bookmark`, or replaces the one with the same address.
+#[tauri::command]
+pub fn save_server_bookmark(
+    app: AppHandle,
+    state: State<'_, ServerBookmarks>,
+    trust: State<'_, TrustStore>,
+    bookmark: ServerBookmark,
+) -> Result<(), String> {
+    if bookmark.address.trim().is_empty() {
+        return Err("Server address must not be empty".to_string());
+    }
+    if let Some(fingerprint) = &bookmark.fingerprint {
+        let valid = fingerprint.len() == 64 && fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err("Fingerprint must be a SHA-256 hash in hex".to_string());
+        }
+        if trust.fingerprint_for(&bookmark.address).as_deref() != Some(fingerprint) {
+            trust.trust(&app, &bookmark.address, fingerprint)?;
+        }
+    }
+
+    let mut bookmarks = state.bookmarks.lock().unwrap();
+    match bookmarks.iter_mut().find(|b| b.address == bookmark.address) {
+        Some(existing) => {
+            *existing = ServerBookmark {
+                // The UI does not track connection times.
+                last_connected: existing.last_connected,
+                ..bookmark
+            }
+        }
+        None => bookmarks.push(bookmark),
+    }
+    settings::save(&app, BOOKMARKS_FILE, bookmarks.as_slice())
+}
+
+#[tauri::command]
+pub fn remove_server_bookmark(
+    app: AppHandle,
+    state: State<'_, ServerBookmarks>,
+    address: String,
+) -> Result<(), String> {
+    let mut bookmarks = state.bookmarks.lock().unwrap();
+    bookmarks.retain(|bookmark| bookmark.address != address);
+    settings::save(&app, BOOKMARKS_FILE, bookmarks.as_slice())
+}
+
+/// Pings every bookmarked server that has a ping endpoint, all at once.
+#[tauri::command]
+pub async fn ping_bookmarks(
+    state: State<'_, ServerBookmarks>,
+) -> Result<Vec<BookmarkStatus>, String> {
+    let bookmarks = state.bookmarks.lock().unwrap().clone();
+    let mut probes = JoinSet::new();
+    for bookmark in bookmarks {
+        probes.spawn(async move {
+            let ping = match &bookmark.ping_endpoint {
+                Some(endpoint) => ping_endpoint(endpoint).await,
+                None => None,
+            };
+            BookmarkStatus {
+                address: bookmark.address,
+                ping,
+            }
+        });
+    }
+    Ok(probes.join_all().await)
+}
@@ -0,0 +1,244 @@
+//! Channel membership, the user's own mute and deafen state, and presence.
+//!
+//! Muting closes the transmit gate and deafening silences playback locally,
+//! so both take effect immediately and also while disconnected. The server is
+//! told with a `UserStateChange` so other users see the new state, and with a
+//! `SetPresence` when the user picks a different presence. Nicknames live on
+//! the server, so they can only be changed while connected. Input is checked
+//! against the limits the server sent in its `ServerInfo`.
+
+use crate::connection::ConnectionManager;
+use crate::radio::RadioState;
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::mixer::Mixer;
+use fleet_net_common::group::Group;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::types::{ChannelId, GroupId};
+use fleet_net_common::user::{Presence, User};
+use fleet_net_common::validation::Validate;
+use fleet_net_protocol::client::VoiceTransport;
+use fleet_net_protocol::message::ControlMessage;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Runtime, State};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SelfState {
+    pub muted: bool,
+    pub deafened: bool,
+}
+
+impl SelfState {
+    fn message(&self) -> ControlMessage {
+        ControlMessage::UserStateChange {
+            self_muted: self.muted,
+            self_deafened: self.deafened,
+        }
+    }
+}
+
+pub struct SessionControls {
+    state: Mutex<SelfState>,
+    presence: Mutex<Presence>,
+    limits: Mutex<ServerLimits>,
+    voice_padding: Mutex<Option<u16>>,
+    voice_transport: Mutex<VoiceTransport>,
+    gate: Arc<TransmitGate>,
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl SessionControls {
+    pub fn new(gate: Arc<TransmitGate>, mixer: Arc<Mutex<Mixer>>) -> Self {
+        Self {
+            state: Mutex::new(SelfState::default()),
+            presence: Mutex::new(Presence::Online),
+            limits: Mutex::new(ServerLimits::default()),
+            voice_padding: Mutex::new(None),
+            voice_transport: Mutex::new(VoiceTransport::default()),
+            gate,
+            mixer,
+        }
+    }
+
+    pub fn state(&self) -> SelfState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn presence(&self) -> Presence {
+        self.presence.lock().unwrap().clone()
+    }
+
+    /// Limits of the server last connected to, or the defaults.
+    pub fn limits(&self) -> ServerLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    pub fn set_limits(&self, limits: ServerLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    /// Block size the server last connected to wants voice packets padded
+    /// to with `pad_datagram`; `None` sends them unpadded.
+    pub fn voice_padding(&self) -> Option<u16> {
+        *self.voice_padding.lock().unwrap()
+    }
+
+    pub fn set_voice_padding(&self, block: Option<u16>) {
+        *self.voice_padding.lock().unwrap() = block;
+    }
+
+    /// Whether voice goes to the server last connected to over UDP or
+    /// through the control connection, as its ping responder suggested.
+    pub fn voice_transport(&self) -> VoiceTransport {
+        *self.voice_transport.lock().unwrap()
+    }
+
+    pub fn set_voice_transport(&self, transport: VoiceTransport) {
+        *self.voice_transport.lock().unwrap() = transport;
+    }
+
+    /// Applies `update` locally and returns the resulting state.
+    fn update(&self, update: impl FnOnce(&mut SelfState)) -> SelfState {
+        let mut state = self.state.lock().unwrap();
+        update(&mut state);
+        // Deafened users cannot talk either, as in most voice clients.
+        self.gate.set_muted(state.muted || state.deafened);
+        self.mixer.lock().unwrap().set_deafened(state.deafened);
+        *state
+    }
+}
+
+/// Re-sends radio subscriptions, mute state and transmit mode after a fresh
+/// (not resumed) session starts, since the server knows nothing about this
+/// client yet.
+pub fn restore<R: Runtime>(app: &AppHandle<R>) {
+    let connection = app.state::<ConnectionManager>();
+    let channels = app.state::<RadioState>().monitored_channels();
+    let controls = app.state::<SessionControls>();
+    let presence = controls.presence();
+    let messages = channels
+        .into_iter()
+        .map(|channel_id| ControlMessage::SubscribeChannel { channel_id })
+        .chain(std::iter::once(controls.state().message()))
+        .chain(std::iter::once(ControlMessage::SetTransmitMode {
+            mode: controls.gate.mode(),
+        }))
+        // Everyone starts online, so only other presences need sending.
+        .chain((presence != Presence::Online).then_some(ControlMessage::SetPresence { presence }));
+    for message in messages {
+        if let Err(e) = connection.send(message) {
+            warn!("Failed to restore session state: {e}");
+        }
+    }
+}
+
+/// Tells the server about a new self state when connected.
+fn announce(connection: &ConnectionManager, state: SelfState) -> Result<SelfState, String> {
+    if connection.is_connected() {
+        connection.send(state.message())?;
+    }
+    Ok(state)
+}
+
+#[tauri::command]
+pub fn join_channel(
+    connection: State<'_, ConnectionManager>,
+    channel_id: ChannelId,
+) -> Result<(), String> {
+    connection.send(ControlMessage::JoinChannel { channel_id })
+}
+
+#[tauri::command]
+pub fn leave_channel(
+    connection: State<'_, ConnectionManager>,
+    channel_id: ChannelId,
+) -> Result<(), String> {
+    connection.send(ControlMessage::LeaveChannel { channel_id })
+}
+
+#[tauri::command]
+pub fn get_self_state(controls: State<'_, SessionControls>) -> SelfState {
+    controls.state()
+}
+
+#[tauri::command]
+pub fn set_self_mute(
+    connection: State<'_, ConnectionManager>,
+    controls: State<'_, SessionControls>,
+    muted: bool,
+) -> Result<SelfState, String> {
+    let state = controls.update(|state| state.muted = muted);
+    announce(&connection, state)
+}
+
+/// Deafening also keeps the microphone closed; undeafening restores the
+/// previous mute setting.
+#[tauri::command]
+pub fn set_self_deafen(
+    connection: State<'_, ConnectionManager>,
+    controls: State<'_, SessionControls>,
+    deafened: bool,
+) -> Result<SelfState, String> {
+    let state = controls.update(|state| state.deafened = deafened);
+    announce(&connection, state)
+}
+
+#[tauri::command]
+pub fn get_presence(controls: State<'_, SessionControls>) -> Presence {
+    controls.presence()
+}
+
+/// Remembers `presence` for later sessions on this run and tells the server
+/// when connected.
+#[tauri::command]
+pub fn set_presence(
+    connection: State<'_, ConnectionManager>,
+    controls: State<'_, SessionControls>,
+    presence: Presence,
+) -> Result<Presence, String> {
+    presence
+        .validate(&controls.limits())
+        .map_err(|e| e.to_string())?;
+    *controls.presence.lock().unwrap() = presence.clone();
+    if connection.is_connected() {
+        connection.send(ControlMessage::SetPresence {
+            presence: presence.clone(),
+        })?;
+    }
+    Ok(presence)
+}
+
+/// Sets this user's nickname on the connected server, or clears it when
+/// `nickname` is `None`.
+#[tauri::command]
+pub fn set_nickname(
+    connection: State<'_, ConnectionManager>,
+    controls: State<'_, SessionControls>,
+    nickname: Option<String>,
+) -> Result<(), String> {
+    if let Some(nickname) = &nickname {
+        User::validate_nickname(nickname, &controls.limits()).map_err(|e| e.to_string())?;
+    }
+    connection.send(ControlMessage::SetNickname { nickname })
+}
+
+/// Forms a group led by this user, e.g. a fireteam.
+#[tauri::command]
+pub fn create_group(connection: State<'_, ConnectionManager>, name: String) -> Result<(), String> {
+    Group::validate_name(&name).map_err(|e| e.to_string())?;
+    connection.send(ControlMessage::CreateGroup { name })
+}
+
+#[tauri::command]
+pub fn join_group(
+    connection: State<'_, ConnectionManager>,
+    group_id: GroupId,
+) -> Result<(), String> {
+    connection.send(ControlMessage::JoinGroup { group_id })
+}
+
+#[tauri::command]
+pub fn leave_group(connection: State<'_, ConnectionManager>) -> Result<(), String> {
+    connection.send(ControlMessage::LeaveGroup)
+}
@@ -0,0 +1,51 @@
+//! JSON settings files in the app's config directory.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager, Runtime};
+use tracing::warn;
+
+fn settings_path<R: Runtime>(app: &AppHandle<R>, file: &str) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| format!("No config directory: {e}"))?;
+    Ok(dir.join(file))
+}
+
+/// Reads `file`, returning `None` if it does not exist yet.
+///
+/// An unreadable file is logged and treated as missing so a corrupt settings
+/// file never prevents the client from starting.
+pub fn load<T: DeserializeOwned, R: Runtime>(
+    app: &AppHandle<R>,
+    file: &str,
+) -> Result<Option<T>, String> {
+    let path = settings_path(app, file)?;
+    match std::fs::read(&path) {
+        Ok(contents) => match serde_json::from_slice(&contents) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                warn!("Ignoring unreadable settings in {}: {e}", path.display());
+                Ok(None)
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {e}", path.display())),
+    }
+}
+
+pub fn save<T: Serialize + ?Sized, R: Runtime>(
+    app: &AppHandle<R>,
+    file: &str,
+    value: &T,
+) -> Result<(), String> {
+    let path = settings_path(app, file)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    }
+    let contents = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    std::fs::write(&path, contents).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
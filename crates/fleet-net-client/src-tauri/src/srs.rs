@@ -0,0 +1,238 @@
+//! Following the cockpit radios of a game through the DCS-SRS export.
+//!
+//! When enabled, the client listens on the loopback interface for the
+//! export DCS-SRS scripts send and keeps the radio stack in step with it:
+//! each game radio becomes the radio with the same index, tuned to the
+//! channel on its net, and the game's push-to-talk keys the selected radio.
+//! Guard receivers get radios of their own, [`GUARD_RADIO_OFFSET`] ids
+//! higher. Volume, pan and output settings the user gave a radio are kept
+//! when it is retuned.
+//!
+//! The client does not hold the channel tree, so the UI passes the server's
+//! channels in with `set_srs_channels` after connecting.
+
+use crate::radio::{self, Radio, RadioState, MAX_RADIO_ID};
+use crate::settings;
+use fleet_net_audio::capture::TransmitGate;
+use fleet_net_audio::effects::RadioTypes;
+use fleet_net_common::channel::{Channel, ChannelTree};
+use fleet_net_common::srs::{SrsExport, SrsTuning, SRS_EXPORT_PORT};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, Runtime, State};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+const SETTINGS_FILE: &str = "srs_interop.json";
+
+/// Radio id of the guard receiver of game radio 0.
+pub const GUARD_RADIO_OFFSET: u8 = 32;
+
+/// Exports are small; DCS-SRS keeps them well under this.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SrsSettings {
+    pub enabled: bool,
+    /// Port on 127.0.0.1 the export is received on.
+    pub port: u16,
+}
+
+impl Default for SrsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: SRS_EXPORT_PORT,
+        }
+    }
+}
+
+pub struct SrsInterop {
+    settings: Mutex<SrsSettings>,
+    channels: Mutex<ChannelTree>,
+    /// The tunings last applied to the radio stack.
+    tunings: Mutex<Vec<SrsTuning>>,
+    /// The radio the game's push-to-talk keyed.
+    keyed: Mutex<Option<u8>>,
+    listener: Mutex<Option<JoinHandle<()>>>,
+    gate: Arc<TransmitGate>,
+}
+
+impl SrsInterop {
+    pub fn new(gate: Arc<TransmitGate>) -> Self {
+        Self {
+            settings: Mutex::new(SrsSettings::default()),
+            channels: Mutex::new(ChannelTree::new()),
+            tunings: Mutex::new(Vec::new()),
+            keyed: Mutex::new(None),
+            listener: Mutex::new(None),
+            gate,
+        }
+    }
+
+    /// Retunes the radios and keys push-to-talk as `export` says.
+    fn apply<R: Runtime>(&self, app: &AppHandle<R>, export: &SrsExport) {
+        let tunings = export.tunings(&self.channels.lock().unwrap());
+        let mut applied = self.tunings.lock().unwrap();
+        if *applied != tunings {
+            let state = app.state::<RadioState>();
+            let radios = radios_for(&tunings, &state.radios());
+            radio::sync_subscriptions(app, state.replace_all(radios));
+            *applied = tunings;
+        }
+
+        let keyed = export
+            .transmitting_radio()
+            .and_then(|index| u8::try_from(index).ok())
+            .filter(|&id| applied.iter().any(|t| !t.guard && t.radio == id as usize));
+        self.key(keyed);
+    }
+
+    /// Moves the game's push-to-talk to `radio_id`, releasing the radio it
+    /// held before.
+    fn key(&self, radio_id: Option<u8>) {
+        let mut keyed = self.keyed.lock().unwrap();
+        if *keyed == radio_id {
+            return;
+        }
+        if let Some(previous) = keyed.take() {
+            self.gate.release(previous);
+        }
+        if let Some(id) = radio_id {
+            self.gate.press(id);
+        }
+        *keyed = radio_id;
+    }
+
+    /// Stops listening and, if enabled, listens again on the configured port.
+    async fn restart<R: Runtime>(&self, app: &AppHandle<R>) -> Result<(), String> {
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            listener.abort();
+        }
+        self.key(None);
+        self.tunings.lock().unwrap().clear();
+        let settings = *self.settings.lock().unwrap();
+        if !settings.enabled {
+            return Ok(());
+        }
+
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, settings.port));
+        let socket = UdpSocket::bind(address)
+            .await
+            .map_err(|e| format!("Failed to listen for SRS exports on {address}: {e}"))?;
+        info!("Listening for SRS exports on {address}");
+        let app = app.clone();
+        *self.listener.lock().unwrap() = Some(tauri::async_runtime::spawn(async move {
+            receive_exports(app, socket).await;
+        }));
+        Ok(())
+    }
+}
+
+async fn receive_exports<R: Runtime>(app: AppHandle<R>, socket: UdpSocket) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        let len = match socket.recv(&mut buffer).await {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("SRS export listener stopped: {e}");
+                break;
+            }
+        };
+        match SrsExport::parse(&buffer[..len]) {
+            Ok(export) => app.state::<SrsInterop>().apply(&app, &export),
+            Err(e) => debug!("Ignoring malformed SRS export: {e}"),
+        }
+    }
+}
+
+/// The radio stack for `tunings`, keeping the settings of `current` radios
+/// with the same ids.
+fn radios_for(tunings: &[SrsTuning], current: &[Radio]) -> Vec<Radio> {
+    tunings
+        .iter()
+        .filter_map(|tuning| {
+            let index = u8::try_from(tuning.radio).ok()?;
+            let id = if tuning.guard {
+                index.checked_add(GUARD_RADIO_OFFSET)?
+            } else {
+                index
+            };
+            if id > MAX_RADIO_ID || (!tuning.guard && id >= GUARD_RADIO_OFFSET) {
+                return None;
+            }
+
+            let radio = match current.iter().find(|radio| radio.id == id) {
+                Some(radio) => radio.clone(),
+                None => Radio {
+                    id,
+                    radio_type: RadioTypes::for_frequency(tuning.frequency_hz),
+                    channel_id: tuning.channel_id,
+                    frequency_mhz: None,
+                    volume: 1.0,
+                    pan_lr: 0.0,
+                    is_dimmed: false,
+                    is_muted: false,
+                    has_priority: false,
+                    output_device: None,
+                },
+            };
+            Some(Radio {
+                channel_id: tuning.channel_id,
+                frequency_mhz: Some(tuning.frequency_hz as f64 / 1_000_000.0),
+                ..radio
+            })
+        })
+        .collect()
+}
+
+/// Restores the interop settings and starts listening if enabled.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let saved: Option<SrsSettings> = settings::load(app, SETTINGS_FILE)?;
+    *app.state::<SrsInterop>().settings.lock().unwrap() = saved.unwrap_or_default();
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = app.state::<SrsInterop>().restart(&app).await {
+            warn!("{e}");
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_srs_settings(state: State<'_, SrsInterop>) -> SrsSettings {
+    *state.settings.lock().unwrap()
+}
+
+/// Applies new interop settings, restarting the listener if needed.
+#[tauri::command]
+pub async fn set_srs_settings(
+    app: AppHandle,
+    state: State<'_, SrsInterop>,
+    settings: SrsSettings,
+) -> Result<(), String> {
+    if settings.port == 0 {
+        return Err("SRS export port must not be 0".to_string());
+    }
+    let previous = std::mem::replace(&mut *state.settings.lock().unwrap(), settings);
+    if previous != settings {
+        state.restart(&app).await?;
+    }
+    settings::save(&app, SETTINGS_FILE, &settings)
+}
+
+/// Sets the server channels game radios are matched against; the radios
+/// are retuned with the next export.
+#[tauri::command]
+pub fn set_srs_channels(
+    state: State<'_, SrsInterop>,
+    channels: Vec<Channel>,
+) -> Result<(), String> {
+    let tree = ChannelTree::from_channels(channels).map_err(|e| e.to_string())?;
+    *state.channels.lock().unwrap() = tree;
+    state.tunings.lock().unwrap().clear();
+    Ok(())
+}
@@ -0,0 +1,242 @@
+//! Localhost endpoint for game plugins reporting the player's position.
+//!
+//! Plugins send the player's position and heading as a JSON
+//! [`SpeakerPosition`], e.g. `{"x": 120.5, "y": -3400.0, "z": 35.2,
+//! "yaw": 270.0, "pitch": 0.0}`, either as UDP datagrams or as text messages
+//! on the `/ws` WebSocket, both on the same port of the loopback interface.
+//! The latest position is published on a watch channel, which voice
+//! encoders follow with `VoiceEncoder::set_position_source` so it travels
+//! after the payload of every outgoing packet. A position not refreshed for
+//! [`STALE_AFTER`] is dropped, as the game has most likely closed.
+//!
+//! The endpoint is off by default. The WebSocket requires the install's
+//! [`LocalToken`] as `?token=`, so web pages the user visits cannot move the
+//! player's voice around; browsers cannot send UDP.
+
+use crate::local_token::{self, LocalToken, TokenQuery};
+use crate::settings;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State as AxumState};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use fleet_net_protocol::packet::SpeakerPosition;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, Runtime, State};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+const SETTINGS_FILE: &str = "game_telemetry.json";
+
+/// How long a position is kept without an update.
+pub const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// Position updates are a few dozen bytes.
+const MAX_DATAGRAM: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+    /// Port on 127.0.0.1 that UDP and WebSocket updates arrive on.
+    pub port: u16,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 37_374,
+        }
+    }
+}
+
+/// The latest position and when it arrived.
+struct Positions {
+    current: watch::Sender<Option<SpeakerPosition>>,
+    updated: Mutex<Option<Instant>>,
+}
+
+impl Positions {
+    /// Publishes the position in `json`, ignoring anything else.
+    fn accept(&self, json: &[u8]) {
+        match serde_json::from_slice::<SpeakerPosition>(json) {
+            Ok(position) if position.is_finite() => {
+                *self.updated.lock().unwrap() = Some(Instant::now());
+                self.current.send_replace(Some(position));
+            }
+            Ok(_) => debug!("Ignoring position with non-finite coordinates"),
+            Err(e) => debug!("Ignoring malformed position update: {e}"),
+        }
+    }
+
+    /// Drops the position if no update arrived within [`STALE_AFTER`].
+    fn expire(&self) {
+        let mut updated = self.updated.lock().unwrap();
+        if updated.is_some_and(|at| at.elapsed() >= STALE_AFTER) {
+            *updated = None;
+            self.current.send_replace(None);
+        }
+    }
+}
+
+pub struct GameTelemetry {
+    settings: Mutex<TelemetrySettings>,
+    positions: Arc<Positions>,
+    listeners: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for GameTelemetry {
+    fn default() -> Self {
+        Self {
+            settings: Mutex::new(TelemetrySettings::default()),
+            positions: Arc::new(Positions {
+                current: watch::Sender::new(None),
+                updated: Mutex::new(None),
+            }),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl GameTelemetry {
+    /// Follows the position from now on, for `VoiceEncoder::set_position_source`.
+    pub fn subscribe(&self) -> watch::Receiver<Option<SpeakerPosition>> {
+        self.positions.current.subscribe()
+    }
+
+    /// Stops the endpoint and, if enabled, starts it again on the configured port.
+    async fn restart(&self, token: Arc<str>) -> Result<(), String> {
+        for listener in self.listeners.lock().unwrap().drain(..) {
+            listener.abort();
+        }
+        let settings = *self.settings.lock().unwrap();
+        if !settings.enabled {
+            *self.positions.updated.lock().unwrap() = None;
+            self.positions.current.send_replace(None);
+            return Ok(());
+        }
+
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, settings.port));
+        let socket = UdpSocket::bind(address)
+            .await
+            .map_err(|e| format!("Failed to listen for game telemetry on udp://{address}: {e}"))?;
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| format!("Failed to listen for game telemetry on ws://{address}: {e}"))?;
+        info!("Game telemetry listening on udp://{address} and ws://{address}/ws");
+
+        let router = Router::new()
+            .route("/ws", get(websocket))
+            .with_state(EndpointState {
+                positions: self.positions.clone(),
+                token,
+            });
+        let positions = self.positions.clone();
+        *self.listeners.lock().unwrap() = vec![
+            tauri::async_runtime::spawn(receive_datagrams(socket, positions)),
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = axum::serve(listener, router).await {
+                    warn!("Game telemetry endpoint stopped: {e}");
+                }
+            }),
+        ];
+        Ok(())
+    }
+}
+
+async fn receive_datagrams(socket: UdpSocket, positions: Arc<Positions>) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        match socket.recv(&mut buffer).await {
+            Ok(len) => positions.accept(&buffer[..len]),
+            Err(e) => {
+                warn!("Game telemetry listener stopped: {e}");
+                break;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct EndpointState {
+    positions: Arc<Positions>,
+    token: Arc<str>,
+}
+
+async fn websocket(
+    AxumState(state): AxumState<EndpointState>,
+    Query(query): Query<TokenQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    if let Err(status) = local_token::authorize(&state.token, &query) {
+        return status.into_response();
+    }
+    upgrade.on_upgrade(move |socket| receive_messages(socket, state.positions))
+}
+
+/// Takes position updates until the plugin disconnects.
+async fn receive_messages(mut socket: WebSocket, positions: Arc<Positions>) {
+    while let Some(Ok(message)) = socket.recv().await {
+        match message {
+            Message::Text(text) => positions.accept(text.as_str().as_bytes()),
+            Message::Binary(bytes) => positions.accept(&bytes),
+            Message::Close(_) => break,
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+    }
+}
+
+/// Restores telemetry settings, starts the endpoint if enabled and expires
+/// positions that stopped updating.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let saved: Option<TelemetrySettings> = settings::load(app, SETTINGS_FILE)?;
+    *app.state::<GameTelemetry>().settings.lock().unwrap() = saved.unwrap_or_default();
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<GameTelemetry>();
+        if let Err(e) = state.restart(app.state::<LocalToken>().get()).await {
+            warn!("{e}");
+        }
+
+        let mut interval = tokio::time::interval(STALE_AFTER / 5);
+        loop {
+            interval.tick().await;
+            state.positions.expire();
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_telemetry_settings(state: State<'_, GameTelemetry>) -> TelemetrySettings {
+    *state.settings.lock().unwrap()
+}
+
+/// Applies new telemetry settings, restarting the endpoint if needed.
+#[tauri::command]
+pub async fn set_telemetry_settings(
+    app: AppHandle,
+    state: State<'_, GameTelemetry>,
+    settings: TelemetrySettings,
+) -> Result<(), String> {
+    if settings.port == 0 {
+        return Err("Telemetry port must not be 0".to_string());
+    }
+    let previous = std::mem::replace(&mut *state.settings.lock().unwrap(), settings);
+    if previous != settings {
+        state.restart(app.state::<LocalToken>().get()).await?;
+    }
+    settings::save(&app, SETTINGS_FILE, &settings)
+}
+
+/// The position last reported by a game, if it is still current.
+#[tauri::command]
+pub fn get_game_position(state: State<'_, GameTelemetry>) -> Option<SpeakerPosition> {
+    *state.positions.current.borrow()
+}
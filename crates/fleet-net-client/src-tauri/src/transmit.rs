@@ -0,0 +1,147 @@
+//! Transmit mode selection and the squelch indicator.
+//!
+//! Users choose between push-to-talk and voice activity detection. Whenever
+//! the microphone goes on or off the air a `squelch` event is emitted so the
+//! UI can light its transmit indicator in either mode.
+//!
+//! The server is told the mode too, since channels may refuse voice
+//! activated transmissions. When a transmission keys radios on several
+//! channels, the server is told to send it out on all of them, which it only
+//! does for users allowed to simulcast.
+
+use crate::connection::ConnectionManager;
+use crate::radio::RadioState;
+use crate::settings;
+use fleet_net_audio::capture::{TransmitGate, TransmitMode};
+use fleet_net_audio::vad::VadConfig;
+use fleet_net_common::types::ChannelId;
+use fleet_net_protocol::message::ControlMessage;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use tracing::warn;
+
+const SETTINGS_FILE: &str = "transmit.json";
+
+/// Event emitted when transmission starts or stops.
+pub const SQUELCH_EVENT: &str = "squelch";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TransmitSettings {
+    pub mode: TransmitMode,
+    /// From 0.0 (only loud speech opens) to 1.0 (quiet speech opens).
+    pub vad_sensitivity: f32,
+    /// How long transmission stays open after speech stops.
+    pub vad_hangover_ms: u64,
+}
+
+impl TransmitSettings {
+    fn from_gate(gate: &TransmitGate) -> Self {
+        let vad = gate.vad_config();
+        Self {
+            mode: gate.mode(),
+            vad_sensitivity: vad.sensitivity,
+            vad_hangover_ms: vad.hangover.as_millis() as u64,
+        }
+    }
+
+    fn apply(&self, gate: &TransmitGate) {
+        gate.set_vad_config(VadConfig {
+            sensitivity: self.vad_sensitivity.clamp(0.0, 1.0),
+            hangover: Duration::from_millis(self.vad_hangover_ms),
+        });
+        gate.set_mode(self.mode);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+struct SquelchPayload {
+    open: bool,
+}
+
+/// Restores the persisted transmit settings and starts forwarding squelch
+/// changes to the UI.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let gate = app.state::<Arc<TransmitGate>>();
+    if let Some(saved) = settings::load::<TransmitSettings, _>(app, SETTINGS_FILE)? {
+        saved.apply(&gate);
+    }
+
+    let mut squelch = gate.subscribe();
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        // Targets the server was last told about.
+        let mut targets: Vec<ChannelId> = Vec::new();
+        while squelch.changed().await.is_ok() {
+            let open = *squelch.borrow_and_update();
+            if open {
+                declare_targets(&app, &mut targets);
+            }
+            if let Err(e) = app.emit(SQUELCH_EVENT, SquelchPayload { open }) {
+                warn!("Failed to emit squelch event: {e}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Declares the channels of the radios keyed for a starting transmission as
+/// its targets when there are several, and clears them once there aren't.
+///
+/// Several targets are declared again on every transmission, so a new
+/// session picks them up without tracking reconnects.
+fn declare_targets<R: Runtime>(app: &AppHandle<R>, targets: &mut Vec<ChannelId>) {
+    let radio_ids = app.state::<Arc<TransmitGate>>().keyed_radios();
+    let mut keyed: Vec<ChannelId> = app
+        .state::<RadioState>()
+        .radios()
+        .iter()
+        .filter(|radio| radio_ids.contains(&radio.id))
+        .map(|radio| radio.channel_id)
+        .collect();
+    keyed.sort_unstable();
+    keyed.dedup();
+    if keyed.len() < 2 {
+        keyed.clear();
+        if targets.is_empty() {
+            return;
+        }
+    }
+
+    let connection = app.state::<ConnectionManager>();
+    if !connection.is_connected() {
+        return;
+    }
+    if let Err(e) = connection.send(ControlMessage::SetTransmitTargets {
+        channel_ids: keyed.clone(),
+    }) {
+        warn!("Failed to declare transmit targets: {e}");
+        return;
+    }
+    *targets = keyed;
+}
+
+#[tauri::command]
+pub fn get_transmit_settings(gate: State<'_, Arc<TransmitGate>>) -> TransmitSettings {
+    TransmitSettings::from_gate(&gate)
+}
+
+#[tauri::command]
+pub fn set_transmit_settings(
+    app: AppHandle,
+    connection: State<'_, ConnectionManager>,
+    gate: State<'_, Arc<TransmitGate>>,
+    settings: TransmitSettings,
+) -> Result<(), String> {
+    if !settings.vad_sensitivity.is_finite() {
+        return Err("VAD sensitivity must be a number between 0 and 1".to_string());
+    }
+    settings.apply(&gate);
+    if connection.is_connected() {
+        connection.send(ControlMessage::SetTransmitMode {
+            mode: settings.mode,
+        })?;
+    }
+    settings::save(&app, SETTINGS_FILE, &TransmitSettings::from_gate(&gate))
+}
@@ -0,0 +1,131 @@
+//! Trust-on-first-use certificates for self-signed servers.
+//!
+//! The first time the client connects to a server in first-use trust mode,
+//! the server's certificate fingerprint is pinned for that address. Later
+//! connections must present the same certificate; if it changes the
+//! connection is refused and a `certificate_changed` event asks the user
+//! whether to trust the new one.
+
+use crate::settings;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use tracing::warn;
+
+const TRUST_FILE: &str = "trusted_servers.json";
+
+/// Event emitted when a server presents a different certificate than pinned.
+pub const CERTIFICATE_CHANGED_EVENT: &str = "certificate_changed";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedServer {
+    /// `host:port` the fingerprint is pinned for.
+    pub address: String,
+    /// SHA-256 of the server certificate, lowercase hex.
+    pub fingerprint: String,
+    /// Unix time the certificate was trusted, in seconds.
+    pub trusted_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CertificateChangedPayload<'a> {
+    address: &'a str,
+    pinned: &'a str,
+    presented: &'a str,
+}
+
+#[derive(Default)]
+pub struct TrustStore {
+    servers: Mutex<Vec<TrustedServer>>,
+}
+
+impl TrustStore {
+    pub fn fingerprint_for(&self, address: &str) -> Option<String> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|server| server.address == address)
+            .map(|server| server.fingerprint.clone())
+    }
+
+    /// Pins `fingerprint` for `address`, replacing any earlier pin, and persists the store.
+    pub fn trust<R: Runtime>(
+        &self,
+        app: &AppHandle<R>,
+        address: &str,
+        fingerprint: &str,
+    ) -> Result<(), String> {
+        let trusted_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut servers = self.servers.lock().unwrap();
+        servers.retain(|server| server.address != address);
+        servers.push(TrustedServer {
+            address: address.to_string(),
+            fingerprint: fingerprint.to_ascii_lowercase(),
+            trusted_at,
+        });
+        settings::save(app, TRUST_FILE, servers.as_slice())
+    }
+}
+
+/// Restores pinned fingerprints.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let servers: Vec<TrustedServer> = settings::load(app, TRUST_FILE)?.unwrap_or_default();
+    *app.state::<TrustStore>().servers.lock().unwrap() = servers;
+    Ok(())
+}
+
+/// Asks the user whether to trust the certificate `address` now presents.
+pub fn notify_changed<R: Runtime>(
+    app: &AppHandle<R>,
+    address: &str,
+    pinned: &str,
+    presented: &str,
+) {
+    let payload = CertificateChangedPayload {
+        address,
+        pinned,
+        presented,
+    };
+    if let Err(e) = app.emit(CERTIFICATE_CHANGED_EVENT, payload) {
+        warn!("Failed to emit certificate change: {e}");
+    }
+}
+
+#[tauri::command]
+pub fn get_trusted_certs(state: State<'_, TrustStore>) -> Vec<TrustedServer> {
+    state.servers.lock().unwrap().clone()
+}
+
+/// Trusts `fingerprint` for `address`, e.g. after the user accepts a changed certificate.
+#[tauri::command]
+pub fn trust_certificate(
+    app: AppHandle,
+    state: State<'_, TrustStore>,
+    address: String,
+    fingerprint: String,
+) -> Result<(), String> {
+    let valid = fingerprint.len() == 64 && fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err("Fingerprint must be a SHA-256 hash in hex".to_string());
+    }
+    state.trust(&app, &address, &fingerprint)
+}
+
+/// Forgets the pinned certificate for `address`; the next connection trusts
+/// whatever certificate the server presents.
+#[tauri::command]
+pub fn remove_trusted_cert(
+    app: AppHandle,
+    state: State<'_, TrustStore>,
+    address: String,
+) -> Result<(), String> {
+    let mut servers = state.servers.lock().unwrap();
+    servers.retain(|server| server.address != address);
+    settings::save(&app, TRUST_FILE, servers.as_slice())
+}
@@ -0,0 +1,107 @@
+//! Checks for newer client releases.
+//!
+//! Releases are described by a small JSON manifest published alongside each
+//! GitHub release. The UI can check it on demand with [`check_for_update`];
+//! when a server refuses this client as too old, an `update-required` event
+//! is emitted with the version the server asked for and the latest release,
+//! so the user is pointed at the download instead of a bare auth error.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Runtime};
+use tracing::warn;
+
+/// Event emitted when the server requires a newer client.
+pub const UPDATE_REQUIRED_EVENT: &str = "update-required";
+
+const MANIFEST_URL: &str =
+    "https://github.com/Cephy314/fleet-net/releases/latest/download/release.json";
+
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Version of this client build.
+const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The release manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub version: Version,
+    /// Download page for the release.
+    pub url: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateStatus {
+    pub current_version: String,
+    /// Latest published release, if the manifest could be fetched.
+    pub latest: Option<Release>,
+    pub update_available: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct UpdateRequiredPayload {
+    current_version: String,
+    min_version: String,
+    latest: Option<Release>,
+}
+
+fn current_version() -> Version {
+    Version::parse(CLIENT_VERSION).expect("CARGO_PKG_VERSION is valid semver")
+}
+
+async fn fetch_release() -> Result<Release, String> {
+    let client = reqwest::Client::builder()
+        .timeout(MANIFEST_TIMEOUT)
+        .user_agent(concat!("fleet-net-client/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(|e| e.to_string())?;
+    client
+        .get(MANIFEST_URL)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to fetch release manifest: {e}"))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid release manifest: {e}"))
+}
+
+/// Tells the UI the server needs at least `min_version`, along with the
+/// latest release if one can be found.
+pub fn notify_required<R: Runtime>(app: &AppHandle<R>, min_version: String) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let latest = match fetch_release().await {
+            Ok(release) => Some(release),
+            Err(e) => {
+                warn!("{e}");
+                None
+            }
+        };
+        // A release too old for the server would not help.
+        let latest = latest.filter(|release| {
+            Version::parse(&min_version).map_or(true, |min| release.version >= min)
+        });
+        let payload = UpdateRequiredPayload {
+            current_version: CLIENT_VERSION.to_string(),
+            min_version,
+            latest,
+        };
+        if let Err(e) = app.emit(UPDATE_REQUIRED_EVENT, payload) {
+            warn!("Failed to emit {UPDATE_REQUIRED_EVENT} event: {e}");
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn check_for_update() -> Result<UpdateStatus, String> {
+    let latest = fetch_release().await?;
+    Ok(UpdateStatus {
+        current_version: CLIENT_VERSION.to_string(),
+        update_available: latest.version > current_version(),
+        latest: Some(latest),
+    })
+}
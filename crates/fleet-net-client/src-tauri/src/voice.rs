@@ -0,0 +1,318 @@
+//! Sending the microphone to the server.
+//!
+//! The default input device is captured for as long as the app runs, and
+//! whatever the transmit gate lets through is encoded for the channel of the
+//! first keyed radio, or else of the first radio or the intercom. Packets
+//! are signed with the session's voice key and sent as UDP datagrams to the
+//! relay the server assigned, or else to its voice port, padded to the
+//! block size the server asks for so their sizes give nothing away. Voice
+//! the server sends back to the same socket is played like any other. When
+//! the probe found UDP blocked, or the server has no voice port, packets are
+//! tunneled through the control connection instead, see
+//! [`VoiceTransport::prepare`].
+//!
+//! The server learns where to send our UDP voice from our packets, so a new
+//! session registers its address with a bare header right away, and again
+//! whenever it has been silent for [`REGISTRATION_INTERVAL`] so NAT
+//! mappings stay open.
+//!
+//! The encoder follows [`ReceptionFeedback`] to adapt its bitrate and FEC
+//! to how well our listeners hear us, and [`GameTelemetry`] to attach the
+//! speaker's position. It is rebuilt whenever a new session starts, since
+//! each session gets a key of its own.
+
+use crate::connection::ConnectionManager;
+use crate::radio::RadioState;
+use crate::reception::ReceptionFeedback;
+use crate::session::SessionControls;
+use crate::telemetry::GameTelemetry;
+use fleet_net_audio::capture::{self, TransmitGate};
+use fleet_net_audio::encoder::{EncoderConfig, VoiceEncoder};
+use fleet_net_audio::recorder::Recorder;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::client::{OutgoingVoice, VoiceTransport};
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::packet::AudioPacket;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Captured buffers waiting for the encoder; newer audio is dropped beyond.
+const CAPTURE_QUEUE: usize = 32;
+
+/// How often a new session is looked for to register its voice address.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long voice may be silent before the address is registered again.
+const REGISTRATION_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Voice datagrams are small; padded ones stay well under this.
+const MAX_DATAGRAM: usize = 2048;
+
+/// Encodes captured audio for one session.
+struct Outgoing {
+    encoder: VoiceEncoder,
+    user_id: UserId,
+    key: HmacKey,
+}
+
+/// The UDP socket voice goes out on, bound for one address family, and the
+/// task playing what comes back on it.
+struct VoiceSocket {
+    socket: Arc<UdpSocket>,
+    receiver: JoinHandle<()>,
+}
+
+impl Drop for VoiceSocket {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+impl VoiceSocket {
+    async fn bind<R: Runtime>(app: &AppHandle<R>, destination: SocketAddr) -> Option<Self> {
+        let local = match destination {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = match UdpSocket::bind(local).await {
+            Ok(socket) => Arc::new(socket),
+            Err(e) => {
+                warn!("Failed to bind the voice socket: {e}");
+                return None;
+            }
+        };
+        let receiver = tauri::async_runtime::spawn(receive(app.clone(), socket.clone()));
+        Some(Self { socket, receiver })
+    }
+
+    fn suits(&self, destination: SocketAddr) -> bool {
+        self.socket
+            .local_addr()
+            .is_ok_and(|local| local.is_ipv4() == destination.is_ipv4())
+    }
+}
+
+/// Where the send loop is with the current session.
+#[derive(Default)]
+struct VoiceLink {
+    outgoing: Option<Outgoing>,
+    socket: Option<VoiceSocket>,
+    /// When a packet last went out, which registers the address too.
+    sent_at: Option<Instant>,
+}
+
+impl VoiceLink {
+    /// Follows the current session, building a new encoder when one starts.
+    /// Returns whether there is a session to send voice for.
+    fn follow_session<R: Runtime>(&mut self, app: &AppHandle<R>, channel_id: ChannelId) -> bool {
+        let Some((user_id, key)) = app.state::<ConnectionManager>().voice_session() else {
+            self.outgoing = None;
+            return false;
+        };
+        if self
+            .outgoing
+            .as_ref()
+            .is_none_or(|current| current.user_id != user_id || current.key != key)
+        {
+            self.outgoing = start_session(app, user_id, key, channel_id);
+            self.sent_at = None;
+        }
+        let Some(outgoing) = self.outgoing.as_mut() else {
+            return false;
+        };
+        outgoing.encoder.set_channel(channel_id);
+        true
+    }
+
+    /// Encodes a captured buffer and sends the packets it completes. An
+    /// empty buffer means the gate closed, which ends the transmission.
+    async fn encode<R: Runtime>(&mut self, app: &AppHandle<R>, samples: Vec<f32>) {
+        let Some(channel_id) = transmit_channel(app) else {
+            return;
+        };
+        if !self.follow_session(app, channel_id) {
+            return;
+        }
+        let Some(outgoing) = self.outgoing.as_mut() else {
+            return;
+        };
+        let packets = if samples.is_empty() {
+            let last = outgoing.encoder.flush();
+            if let Err(e) = outgoing.encoder.reset() {
+                warn!("Failed to reset the voice encoder: {e}");
+            }
+            last.map(|packet| packet.into_iter().collect())
+        } else {
+            outgoing.encoder.push_samples(&samples)
+        };
+        match packets {
+            Ok(packets) => self.send(app, &packets).await,
+            Err(e) => warn!("Failed to encode voice: {e}"),
+        }
+    }
+
+    /// Registers the voice address of a new session, or of one that has
+    /// been silent for [`REGISTRATION_INTERVAL`].
+    async fn keep_registered<R: Runtime>(&mut self, app: &AppHandle<R>) {
+        let Some(channel_id) = transmit_channel(app) else {
+            return;
+        };
+        // Tunneled voice comes back on the control connection anyway
+        if app.state::<SessionControls>().voice_transport() == VoiceTransport::Tunnel
+            || app.state::<ConnectionManager>().voice_address().is_none()
+        {
+            return;
+        }
+        if !self.follow_session(app, channel_id)
+            || self
+                .sent_at
+                .is_some_and(|at| at.elapsed() < REGISTRATION_INTERVAL)
+        {
+            return;
+        }
+        if let Some(outgoing) = self.outgoing.as_mut() {
+            let registration = outgoing.encoder.registration();
+            self.send(app, &[registration]).await;
+        }
+    }
+
+    /// Sends `packets` without waiting; voice that can't go out right away
+    /// is too late to be worth sending.
+    async fn send<R: Runtime>(&mut self, app: &AppHandle<R>, packets: &[AudioPacket]) {
+        if packets.is_empty() {
+            return;
+        }
+        let connection = app.state::<ConnectionManager>();
+        let controls = app.state::<SessionControls>();
+        let transport = controls.voice_transport();
+        let padding = controls.voice_padding();
+        let udp_address = connection.voice_address();
+        for packet in packets {
+            match transport.prepare(packet, padding, udp_address) {
+                OutgoingVoice::Datagram(destination, datagram) => {
+                    let Some(socket) = self.socket_for(app, destination).await else {
+                        return;
+                    };
+                    if let Err(e) = socket.try_send_to(&datagram, destination) {
+                        debug!("Dropping voice packet to {destination}: {e}");
+                    }
+                }
+                OutgoingVoice::Tunneled(message) => {
+                    if let Err(e) = connection.send(message) {
+                        debug!("Dropping tunneled voice packet: {e}");
+                    }
+                }
+            }
+        }
+        self.sent_at = Some(Instant::now());
+    }
+
+    /// The socket for sending to `destination`, bound anew when its address
+    /// family changed.
+    async fn socket_for<R: Runtime>(
+        &mut self,
+        app: &AppHandle<R>,
+        destination: SocketAddr,
+    ) -> Option<&UdpSocket> {
+        if !self
+            .socket
+            .as_ref()
+            .is_some_and(|socket| socket.suits(destination))
+        {
+            self.socket = VoiceSocket::bind(app, destination).await;
+        }
+        self.socket.as_ref().map(|socket| socket.socket.as_ref())
+    }
+}
+
+/// Starts capturing and sending voice whenever connected.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let (frames, mut frames_rx) = mpsc::channel(CAPTURE_QUEUE);
+    let gate = app.state::<Arc<TransmitGate>>().inner().clone();
+    let recorder = app.state::<Arc<Recorder>>().inner().clone();
+    // Detached: cpal streams can't move between threads, so this one keeps
+    // the capture stream alive for the rest of the run.
+    std::thread::Builder::new()
+        .name("fleet-net-capture".to_string())
+        .spawn(
+            move || match capture::start_capture(None, gate, frames, None, Some(recorder)) {
+                Ok(_stream) => loop {
+                    std::thread::park();
+                },
+                Err(e) => warn!("Failed to start microphone capture: {e}"),
+            },
+        )
+        .map_err(|e| format!("Failed to start capture thread: {e}"))?;
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut link = VoiceLink::default();
+        let mut checks = tokio::time::interval(SESSION_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                // Never matches if capture failed to start; voice is still heard.
+                Some(samples) = frames_rx.recv() => link.encode(&app, samples).await,
+                _ = checks.tick() => link.keep_registered(&app).await,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Builds the encoder for a new session.
+fn start_session<R: Runtime>(
+    app: &AppHandle<R>,
+    user_id: UserId,
+    key: HmacKey,
+    channel_id: ChannelId,
+) -> Option<Outgoing> {
+    let mut encoder =
+        match VoiceEncoder::new(EncoderConfig::default(), key.clone(), user_id, channel_id) {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                warn!("Failed to create the voice encoder: {e}");
+                return None;
+            }
+        };
+    encoder.set_feedback_source(app.state::<ReceptionFeedback>().subscribe());
+    encoder.set_position_source(app.state::<GameTelemetry>().subscribe());
+    Some(Outgoing {
+        encoder,
+        user_id,
+        key,
+    })
+}
+
+/// The channel of the first keyed radio, or else of the first radio, as in
+/// voice activated mode, or of the intercom.
+fn transmit_channel<R: Runtime>(app: &AppHandle<R>) -> Option<ChannelId> {
+    let keyed = app.state::<Arc<TransmitGate>>().keyed_radios();
+    let radio_state = app.state::<RadioState>();
+    let radios = radio_state.radios();
+    radios
+        .iter()
+        .find(|radio| keyed.contains(&radio.id))
+        .or_else(|| radios.first())
+        .map(|radio| radio.channel_id)
+        .or_else(|| radio_state.intercom().map(|intercom| intercom.channel_id))
+}
+
+/// Plays voice arriving on `socket` until the socket is replaced.
+async fn receive<R: Runtime>(app: AppHandle<R>, socket: Arc<UdpSocket>) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        match socket.recv(&mut buffer).await {
+            Ok(len) => app
+                .state::<ConnectionManager>()
+                .receive_datagram(&buffer[..len]),
+            // ICMP errors from earlier sends surface here on some platforms
+            Err(e) => debug!("Failed to receive voice: {e}"),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Per-user playback volume and local mute.
+//!
+//! These only change what this client hears; the server and other users are
+//! unaffected. Settings are remembered per server address, since user ids
+//! are only meaningful on the server that assigned them.
+
+use crate::settings;
+use fleet_net_audio::mixer::Mixer;
+use fleet_net_common::types::UserId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Runtime, State};
+
+const SETTINGS_FILE: &str = "user_audio.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UserAudio {
+    /// Playback volume, 0.0 to 2.0.
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for UserAudio {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+/// Settings per server address, then per user.
+type ServerUserAudio = HashMap<String, HashMap<UserId, UserAudio>>;
+
+pub struct UserAudioStore {
+    servers: Mutex<ServerUserAudio>,
+    /// Address of the server the settings currently apply to.
+    current: Mutex<Option<String>>,
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl UserAudioStore {
+    pub fn new(mixer: Arc<Mutex<Mixer>>) -> Self {
+        Self {
+            servers: Mutex::new(HashMap::new()),
+            current: Mutex::new(None),
+            mixer,
+        }
+    }
+
+    /// Applies the settings saved for `address` to the mixer.
+    pub fn activate(&self, address: &str) {
+        let mut current = self.current.lock().unwrap();
+        let servers = self.servers.lock().unwrap();
+        let mut mixer = self.mixer.lock().unwrap();
+        mixer.reset_users();
+        for (&user_id, audio) in servers.get(address).into_iter().flatten() {
+            apply(&mut mixer, user_id, audio);
+        }
+        *current = Some(address.to_string());
+    }
+
+    /// Changes one user's settings on the current server and persists them.
+    fn update<R: Runtime>(
+        &self,
+        app: &AppHandle<R>,
+        user_id: UserId,
+        update: impl FnOnce(&mut UserAudio),
+    ) -> Result<UserAudio, String> {
+        let current = self.current.lock().unwrap();
+        let address = current
+            .as_deref()
+            .ok_or_else(|| "Not connected to a server".to_string())?;
+
+        let mut servers = self.servers.lock().unwrap();
+        let users = servers.entry(address.to_string()).or_default();
+        let audio = users.entry(user_id).or_default();
+        update(audio);
+        let audio = *audio;
+        // Defaults need not be remembered.
+        if audio == UserAudio::default() {
+            users.remove(&user_id);
+        }
+
+        apply(&mut self.mixer.lock().unwrap(), user_id, &audio);
+        settings::save(app, SETTINGS_FILE, &*servers)?;
+        Ok(audio)
+    }
+}
+
+fn apply(mixer: &mut Mixer, user_id: UserId, audio: &UserAudio) {
+    mixer.set_user_volume(user_id, audio.volume);
+    mixer.set_user_muted(user_id, audio.muted);
+}
+
+/// Restores saved per-user settings.
+pub fn setup<R: Runtime>(app: &AppHandle<R>) -> Result<(), String> {
+    let servers: ServerUserAudio = settings::load(app, SETTINGS_FILE)?.unwrap_or_default();
+    *app.state::<UserAudioStore>().servers.lock().unwrap() = servers;
+    Ok(())
+}
+
+/// Settings for every user with a non-default volume or mute on the current server.
+#[tauri::command]
+pub fn get_user_audio(state: State<'_, UserAudioStore>) -> HashMap<UserId, UserAudio> {
+    let current = state.current.lock().unwrap();
+    current
+        .as_ref()
+        .and_then(|address| state.servers.lock().unwrap().get(address).cloned())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub fn set_user_volume(
+    app: AppHandle,
+    state: State<'_, UserAudioStore>,
+    user_id: UserId,
+    volume: f32,
+) -> Result<UserAudio, String> {
+    if !volume.is_finite() {
+        return Err("Volume must be a number".to_string());
+    }
+    state.update(&app, user_id, |audio| audio.volume = volume.clamp(0.0, 2.0))
+}
+
+#[tauri::command]
+pub fn set_user_muted(
+    app: AppHandle,
+    state: State<'_, UserAudioStore>,
+    user_id: UserId,
+    muted: bool,
+) -> Result<UserAudio, String> {
+    state.update(&app, user_id, |audio| audio.muted = muted)
+}
@@ -0,0 +1,145 @@
+//! Permission resolution with and without the cache, for 1000 users in a
+//! 200 channel layout: 20 categories of 9 channels each.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fleet_net_common::channel::{
+    AudioPolicy, Channel, ChannelPermissions, ChannelTree, ChannelType,
+};
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::permission_cache::PermissionCache;
+use fleet_net_common::role::Role;
+use fleet_net_common::types::{ChannelId, UserId};
+use std::collections::HashMap;
+
+const USERS: u16 = 1000;
+const CATEGORIES: u16 = 20;
+const CHANNELS_PER_CATEGORY: u16 = 9;
+
+const ROLE_IDS: [&str; 5] = ["admin", "officer", "pilot", "crew", "guest"];
+
+fn roles() -> Vec<Role> {
+    ROLE_IDS
+        .iter()
+        .enumerate()
+        .map(|(priority, &id)| {
+            Role::new(id.to_string(), id.to_string())
+                .with_permissions(Permissions::CONNECT | Permissions::LISTEN | Permissions::SPEAK)
+                .with_priority(priority as i32 + 1)
+        })
+        .collect()
+}
+
+fn overrides(seed: u16) -> HashMap<String, ChannelPermissions> {
+    let allow = Permissions::WHISPER | Permissions::SUBSCRIBE_RADIO;
+    let deny = Permissions::SPEAK;
+    [
+        (ROLE_IDS[usize::from(seed) % ROLE_IDS.len()], allow, deny),
+        (
+            ROLE_IDS[usize::from(seed + 2) % ROLE_IDS.len()],
+            deny,
+            allow,
+        ),
+    ]
+    .into_iter()
+    .map(|(role, allow, deny)| (role.to_string(), ChannelPermissions { allow, deny }))
+    .collect()
+}
+
+fn layout() -> ChannelTree {
+    let mut channels = Vec::new();
+    for category in 0..CATEGORIES {
+        let category_id = ChannelId::new(category * (CHANNELS_PER_CATEGORY + 1) + 1).unwrap();
+        channels.push(Channel {
+            id: category_id,
+            name: format!("Category {category}"),
+            description: None,
+            channel_type: ChannelType::Category,
+            role_permissions: overrides(category),
+            position: u32::from(category),
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        });
+        for child in 1..=CHANNELS_PER_CATEGORY {
+            channels.push(Channel {
+                id: ChannelId::new(category_id.get() + child).unwrap(),
+                name: format!("Channel {category}.{child}"),
+                description: None,
+                channel_type: ChannelType::Voice,
+                role_permissions: overrides(child),
+                position: u32::from(child),
+                parent_id: Some(category_id),
+                topic: None,
+                icon: None,
+                metadata: HashMap::new(),
+                radio: None,
+                audio_policy: AudioPolicy::default(),
+            });
+        }
+    }
+    ChannelTree::from_channels(channels).unwrap()
+}
+
+/// Each user holds two roles, highest priority first.
+fn user_roles(roles: &[Role]) -> Vec<(UserId, Vec<Role>)> {
+    (1..=USERS)
+        .map(|id| {
+            let first = usize::from(id) % roles.len();
+            let second = (first + 1 + usize::from(id) % 3) % roles.len();
+            let mut held = vec![roles[first].clone(), roles[second].clone()];
+            held.sort_by_key(|role| role.priority);
+            (UserId::new(id).unwrap(), held)
+        })
+        .collect()
+}
+
+fn bench_permission_resolution(c: &mut Criterion) {
+    let tree = layout();
+    let users = user_roles(&roles());
+    let channel_ids: Vec<ChannelId> = tree.iter().map(|(_, channel)| channel.id).collect();
+    assert_eq!(channel_ids.len(), 200);
+
+    c.bench_function("resolve_one_user", |b| {
+        let (_, held) = &users[0];
+        let channel_id = *channel_ids.last().unwrap();
+        b.iter(|| black_box(tree.user_permissions(black_box(channel_id), held).unwrap()))
+    });
+
+    c.bench_function("resolve_1000_users_uncached", |b| {
+        let mut channels = channel_ids.iter().cycle();
+        b.iter(|| {
+            let channel_id = *channels.next().unwrap();
+            for (_, held) in &users {
+                black_box(tree.user_permissions(channel_id, held).unwrap());
+            }
+        })
+    });
+
+    c.bench_function("resolve_1000_users_cached", |b| {
+        let mut cache = PermissionCache::new();
+        for &channel_id in &channel_ids {
+            for (user_id, held) in &users {
+                cache
+                    .get_or_compute(*user_id, channel_id, &tree, held)
+                    .unwrap();
+            }
+        }
+        let mut channels = channel_ids.iter().cycle();
+        b.iter(|| {
+            let channel_id = *channels.next().unwrap();
+            for (user_id, held) in &users {
+                black_box(
+                    cache
+                        .get_or_compute(*user_id, channel_id, &tree, held)
+                        .unwrap(),
+                );
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_permission_resolution);
+criterion_main!(benches);
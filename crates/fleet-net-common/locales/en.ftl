@@ -0,0 +1,35 @@
+# English strings shown to users, the fallback for every other locale.
+#
+# Each line is `key = text`; `{ $name }` is replaced with a value supplied
+# when the text is shown.
+
+## Errors, keyed by FleetNetErrorCode::translation_key
+
+error-network-error = The connection to the server failed.
+error-malformed-packet = A voice packet could not be read.
+error-malformed-message = The server sent a message this client could not read.
+error-encryption-error = The connection could not be secured.
+error-rate-limited = You are doing that too often. Try again in a moment.
+error-auth-failed = Sign-in failed. Check your credentials and try again.
+error-client-outdated = This client is too old for the server. Update to connect.
+error-banned = You are banned from this server.
+error-server-full = The server is full.
+error-no-permission = You do not have permission to do that.
+error-channel-full = That channel is full.
+error-muted = You are muted on this server.
+error-invalid-request = The request was not valid.
+error-channel-not-found = That channel no longer exists.
+error-user-not-found = That user is no longer connected.
+error-invalid-state = That is not possible right now.
+error-audio-error = Something went wrong with audio.
+error-internal-error = Something went wrong on the server.
+error-unknown = Something went wrong.
+
+## Disconnect reasons
+
+disconnect-closed = Disconnected.
+disconnect-error = Disconnected: { $reason }
+
+## Message of the day
+
+motd-default = Welcome to { $server }. { $users } online.
@@ -0,0 +1,341 @@
+//! Proptest strategies for the shared types.
+//!
+//! Only available with the `proptest` feature, so other crates can
+//! property-test round trips, validation and permission resolution against
+//! the same generators. Every strategy yields values that pass validation
+//! under [`ServerLimits::default`]; tests that need invalid input should
+//! break a generated value on purpose.
+//!
+//! # Examples
+//!
+//! ```
+//! use fleet_net_common::arbitrary;
+//! use fleet_net_common::limits::ServerLimits;
+//! use fleet_net_common::validation::Validate;
+//! use proptest::prelude::*;
+//!
+//! proptest!(|(channel in arbitrary::channel())| {
+//!     prop_assert!(channel.validate(&ServerLimits::default()).is_ok());
+//! });
+//! ```
+
+use crate::audio::{ReceptionQuality, TransmitMode};
+use crate::channel::{
+    AudioPolicy, Channel, ChannelPermissions, ChannelType, Modulation, RadioChannelConfig,
+};
+use crate::group::Group;
+use crate::limits::{
+    MAX_CHANNEL_METADATA_ENTRIES, MAX_GROUP_SIZE, MAX_OPUS_BITRATE, MAX_RADIO_FREQUENCY_HZ,
+    MIN_OPUS_BITRATE,
+};
+use crate::permission::Permissions;
+use crate::restriction::{RestrictionKind, TimedRestriction};
+use crate::role::Role;
+use crate::template::ServerTemplate;
+use crate::types::{ChannelId, GroupId, UserId};
+use crate::user::Presence;
+use chrono::{DateTime, Utc};
+use proptest::collection::{hash_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::select;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(doc)]
+use crate::limits::ServerLimits;
+
+/// Role ids the role and channel strategies share, so generated channels
+/// carry overrides for generated roles.
+pub const ROLE_IDS: [&str; 4] = ["admin", "officer", "member", "guest"];
+
+pub fn user_id() -> impl Strategy<Value = UserId> {
+    (1..=u16::MAX).prop_map(|id| UserId::new(id).unwrap())
+}
+
+pub fn channel_id() -> impl Strategy<Value = ChannelId> {
+    (1..=u16::MAX).prop_map(|id| ChannelId::new(id).unwrap())
+}
+
+pub fn group_id() -> impl Strategy<Value = GroupId> {
+    (1..=u16::MAX).prop_map(|id| GroupId::new(id).unwrap())
+}
+
+/// Any set of bits, including ones no flag defines yet.
+pub fn permissions() -> impl Strategy<Value = Permissions> {
+    any::<u64>().prop_map(Permissions::from_bits_retain)
+}
+
+pub fn channel_permissions() -> impl Strategy<Value = ChannelPermissions> {
+    (permissions(), permissions()).prop_map(|(allow, deny)| ChannelPermissions { allow, deny })
+}
+
+/// A display name such as a nickname or group name: at most 32 bytes with
+/// no surrounding whitespace.
+pub fn display_name() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9]([A-Za-z0-9 _-]{0,30}[A-Za-z0-9])?"
+}
+
+pub fn role() -> impl Strategy<Value = Role> {
+    (
+        select(&ROLE_IDS[..]),
+        display_name(),
+        permissions(),
+        vec("[1-9][0-9]{16,18}", 0..3),
+        any::<u32>(),
+    )
+        .prop_map(|(id, name, permissions, discord_role_ids, priority)| Role {
+            id: id.to_string(),
+            name,
+            permissions,
+            discord_role_ids,
+            priority,
+        })
+}
+
+pub fn channel_type() -> impl Strategy<Value = ChannelType> {
+    prop_oneof![
+        Just(ChannelType::Voice),
+        Just(ChannelType::Radio),
+        Just(ChannelType::Category),
+        Just(ChannelType::Intercom),
+    ]
+}
+
+pub fn radio_config() -> impl Strategy<Value = RadioChannelConfig> {
+    (
+        1..=MAX_RADIO_FREQUENCY_HZ,
+        prop_oneof![
+            Just(Modulation::Am),
+            Just(Modulation::Fm),
+            Just(Modulation::Ssb)
+        ],
+        option::of(1..=u32::MAX),
+        option::of("[a-z0-9-]{1,32}"),
+    )
+        .prop_map(|(frequency_hz, modulation, max_range_m, crypto_key_id)| {
+            RadioChannelConfig {
+                frequency_hz,
+                modulation,
+                max_range_m,
+                crypto_key_id,
+            }
+        })
+}
+
+pub fn audio_policy() -> impl Strategy<Value = AudioPolicy> {
+    (
+        MIN_OPUS_BITRATE..=MAX_OPUS_BITRATE,
+        MIN_OPUS_BITRATE..=MAX_OPUS_BITRATE,
+        any::<bool>(),
+        any::<bool>(),
+        option::of(1..=3600u32),
+        any::<bool>(),
+        any::<bool>(),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(
+                a,
+                b,
+                force_ptt,
+                vad_forbidden,
+                max_transmit_secs,
+                spectator_mode,
+                preemption,
+                retransmit,
+            )| AudioPolicy {
+                min_bitrate: a.min(b),
+                max_bitrate: a.max(b),
+                force_ptt,
+                vad_forbidden,
+                max_transmit_secs,
+                spectator_mode,
+                preemption,
+                retransmit,
+            },
+        )
+}
+
+/// Topic, icon and metadata of a channel, as in
+/// `ControlMessage::UpdateChannelInfo`.
+pub fn channel_info(
+) -> impl Strategy<Value = (Option<String>, Option<String>, HashMap<String, String>)> {
+    (
+        option::of(".{0,64}"),
+        option::of("[a-z0-9_-]{1,64}"),
+        hash_map("[a-z_]{1,16}", ".{0,32}", 0..=MAX_CHANNEL_METADATA_ENTRIES),
+    )
+}
+
+/// A channel whose radio settings match its type and which is never its
+/// own parent.
+pub fn channel() -> impl Strategy<Value = Channel> {
+    (
+        channel_id(),
+        (display_name(), option::of(".{0,128}"), channel_type()),
+        hash_map(
+            select(&ROLE_IDS[..]),
+            channel_permissions(),
+            0..=ROLE_IDS.len(),
+        ),
+        (any::<u32>(), option::of(channel_id())),
+        channel_info(),
+        (option::of(radio_config()), audio_policy()),
+    )
+        .prop_map(
+            |(
+                id,
+                (name, description, channel_type),
+                role_permissions,
+                (position, parent_id),
+                (topic, icon, metadata),
+                (radio, audio_policy),
+            )| Channel {
+                id,
+                name,
+                description,
+                radio: radio.filter(|_| channel_type == ChannelType::Radio),
+                channel_type,
+                role_permissions: role_permissions
+                    .into_iter()
+                    .map(|(role, permissions)| (role.to_string(), permissions))
+                    .collect(),
+                position,
+                parent_id: parent_id.filter(|parent| *parent != id),
+                topic,
+                icon,
+                metadata,
+                audio_policy,
+            },
+        )
+}
+
+pub fn presence() -> impl Strategy<Value = Presence> {
+    prop_oneof![
+        Just(Presence::Online),
+        Just(Presence::Away),
+        Just(Presence::DoNotDisturb),
+        "[A-Za-z0-9][A-Za-z0-9 :]{0,40}".prop_map(|game| Presence::InGame { game }),
+    ]
+}
+
+pub fn transmit_mode() -> impl Strategy<Value = TransmitMode> {
+    prop_oneof![
+        Just(TransmitMode::PushToTalk),
+        Just(TransmitMode::VoiceActivity),
+    ]
+}
+
+pub fn reception_quality() -> impl Strategy<Value = ReceptionQuality> {
+    (0u8..=100, any::<u16>()).prop_map(|(loss_percent, jitter_ms)| ReceptionQuality {
+        loss_percent,
+        jitter_ms,
+    })
+}
+
+pub fn restriction_kind() -> impl Strategy<Value = RestrictionKind> {
+    prop_oneof![
+        Just(RestrictionKind::Mute),
+        Just(RestrictionKind::Deafen),
+        Just(RestrictionKind::Ban),
+    ]
+}
+
+/// A restriction expiring, if at all, on a whole second.
+pub fn timed_restriction() -> impl Strategy<Value = TimedRestriction> {
+    (
+        restriction_kind(),
+        option::of(0..4_102_444_800i64),
+        option::of(".{0,64}"),
+        user_id(),
+    )
+        .prop_map(|(kind, expires_at, reason, issued_by)| TimedRestriction {
+            kind,
+            expires_at: expires_at.and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0)),
+            reason,
+            issued_by,
+        })
+}
+
+/// A group whose leader is one of its members.
+pub fn group() -> impl Strategy<Value = Group> {
+    (
+        group_id(),
+        display_name(),
+        vec(user_id(), 1..=MAX_GROUP_SIZE as usize),
+    )
+        .prop_map(|(id, name, mut members)| {
+            members.sort();
+            members.dedup();
+            Group {
+                id,
+                name,
+                leader: members[0],
+                members,
+            }
+        })
+}
+
+/// A template with a role for each of [`ROLE_IDS`] and a few top-level
+/// channels, so every override names a role of the template.
+pub fn template() -> impl Strategy<Value = ServerTemplate> {
+    (vec(channel(), 0..4), vec(role(), ROLE_IDS.len())).prop_map(|(channels, roles)| {
+        let mut seen = HashSet::new();
+        ServerTemplate {
+            channels: channels
+                .into_iter()
+                .filter(|channel| seen.insert(channel.id))
+                .map(|channel| Channel {
+                    parent_id: None,
+                    ..channel
+                })
+                .collect(),
+            roles: roles
+                .into_iter()
+                .zip(ROLE_IDS)
+                .map(|(role, id)| Role {
+                    id: id.to_string(),
+                    ..role
+                })
+                .collect(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ServerLimits;
+    use crate::validation::Validate;
+
+    proptest! {
+        #[test]
+        fn prop_generated_values_are_valid(
+            channel in channel(),
+            group in group(),
+            presence in presence(),
+            template in template(),
+        ) {
+            let limits = ServerLimits::default();
+            prop_assert!(channel.validate(&limits).is_ok());
+            prop_assert!(group.validate(&limits).is_ok());
+            prop_assert!(presence.validate(&limits).is_ok());
+            prop_assert!(template.validate(&limits).is_ok());
+        }
+
+        #[test]
+        fn prop_resolution_grants_only_base_or_allowed_bits(
+            channel in channel(),
+            roles in vec(role(), 0..4),
+        ) {
+            let channel = Channel { parent_id: None, ..channel };
+            let granted = channel.compute_user_permissions(&roles, |_| None).unwrap();
+            let allowed = roles
+                .iter()
+                .filter_map(|role| channel.role_permissions.get(&role.id))
+                .fold(Permissions::empty(), |acc, overrides| acc | overrides.allow);
+            let base = roles.first().map_or(Permissions::empty(), |role| role.permissions);
+            prop_assert!((base | allowed).contains(granted));
+        }
+    }
+}
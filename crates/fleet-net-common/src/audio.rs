@@ -1,11 +1,53 @@
 //! Audio state management for Fleet Net users.
 //!
 //! This module provides structures and utilities for managing user audio states,
-//! including mute/deafen status and volume control.
+//! including mute/deafen status and volume control, the transmit modes
+//! clients announce so servers can enforce channel audio policies, and the
+//! reception quality listeners report so speakers can adapt their bitrate.
+//!
+//! State changes go through the setters on [`UserAudioState`], which apply
+//! the precedence rules (deafening implies muting, server state overrides
+//! self state) and report each effective change as an [`AudioStateChange`]
+//! for broadcasting, so client and server agree on what a user can do.
 
+use crate::restriction::RestrictionKind;
 use crate::types::UserId;
 use serde::{Deserialize, Serialize};
 
+/// How a client decides when to transmit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransmitMode {
+    /// Transmit only while a radio's PTT key is held.
+    #[default]
+    PushToTalk,
+    /// Transmit whenever speech is detected; PTT keys still force transmission.
+    VoiceActivity,
+}
+
+/// How well a speaker is heard, as measured by a listener's jitter buffer
+/// over one report interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReceptionQuality {
+    /// Share of the speaker's packets that never arrived in time, in percent.
+    pub loss_percent: u8,
+    /// Smoothed variation in packet transit time, in milliseconds.
+    pub jitter_ms: u16,
+}
+
+/// A user's effective mute and deafen state after a change.
+///
+/// Flags are effective values: a deafened user is reported as muted too,
+/// whether or not they were muted separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AudioStateChange {
+    pub user_id: UserId,
+    pub self_muted: bool,
+    pub self_deafened: bool,
+    pub server_muted: bool,
+    pub server_deafened: bool,
+}
+
 /// Represents the complete audio state for a user in a voice channel.
 ///
 /// This struct tracks both server-side and client-side audio states,
@@ -23,7 +65,7 @@ use serde::{Deserialize, Serialize};
 /// use fleet_net_common::audio::UserAudioState;
 /// use fleet_net_common::types::UserId;
 ///
-/// let mut audio_state = UserAudioState::new(42);
+/// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
 /// assert!(audio_state.can_speak());
 ///
 /// audio_state.set_away();
@@ -69,9 +111,10 @@ impl UserAudioState {
     ///
     /// ```
     /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::types::UserId;
     ///
-    /// let audio_state = UserAudioState::new(123);
-    /// assert_eq!(audio_state.user_id, 123);
+    /// let audio_state = UserAudioState::new(UserId::new(123).unwrap());
+    /// assert_eq!(audio_state.user_id.get(), 123);
     /// assert_eq!(audio_state.volume, 1.0);
     /// ```
     pub fn new(user_id: UserId) -> Self {
@@ -98,8 +141,9 @@ impl UserAudioState {
     ///
     /// ```
     /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::types::UserId;
     ///
-    /// let mut audio_state = UserAudioState::new(42);
+    /// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
     /// assert!(audio_state.can_speak());
     ///
     /// audio_state.is_muted = true;
@@ -123,8 +167,9 @@ impl UserAudioState {
     ///
     /// ```
     /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::types::UserId;
     ///
-    /// let mut audio_state = UserAudioState::new(42);
+    /// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
     /// assert!(audio_state.can_hear());
     ///
     /// audio_state.is_self_deafened = true;
@@ -144,8 +189,9 @@ impl UserAudioState {
     ///
     /// ```
     /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::types::UserId;
     ///
-    /// let mut audio_state = UserAudioState::new(42);
+    /// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
     /// audio_state.set_away();
     ///
     /// assert!(audio_state.is_self_muted);
@@ -171,8 +217,9 @@ impl UserAudioState {
     ///
     /// ```
     /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::types::UserId;
     ///
-    /// let mut audio_state = UserAudioState::new(42);
+    /// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
     ///
     /// audio_state.set_volume(1.5);
     /// assert_eq!(audio_state.volume, 1.5);
@@ -187,14 +234,95 @@ impl UserAudioState {
         // Clamp volume between silence (0.0) and maximum boost (2.0)
         self.volume = volume.clamp(0.0, 2.0);
     }
-}
 
-impl Default for UserAudioState {
-    /// Creates a default UserAudioState with user_id 0.
+    /// The effective state, as broadcast to other users.
+    pub fn effective(&self) -> AudioStateChange {
+        AudioStateChange {
+            user_id: self.user_id,
+            self_muted: self.is_self_muted || self.is_self_deafened,
+            self_deafened: self.is_self_deafened,
+            server_muted: self.is_muted || self.is_deafened,
+            server_deafened: self.is_deafened,
+        }
+    }
+
+    /// Applies a user's own mute and deafen choice, as sent in a
+    /// `UserStateChange`.
+    ///
+    /// Returns the new effective state, or `None` if nothing changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::types::UserId;
+    ///
+    /// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
+    /// let change = audio_state.set_self_state(false, true).unwrap();
+    ///
+    /// // Deafened users are muted as well
+    /// assert!(change.self_muted);
+    /// assert!(audio_state.set_self_state(false, true).is_none());
+    /// ```
+    pub fn set_self_state(&mut self, muted: bool, deafened: bool) -> Option<AudioStateChange> {
+        self.track(|state| {
+            state.is_self_muted = muted;
+            state.is_self_deafened = deafened;
+        })
+    }
+
+    /// Server mutes or unmutes the user.
+    ///
+    /// Unmuting a server deafened user changes nothing effective, since they
+    /// stay muted until undeafened.
+    pub fn set_server_muted(&mut self, muted: bool) -> Option<AudioStateChange> {
+        self.track(|state| state.is_muted = muted)
+    }
+
+    /// Server deafens or undeafens the user. Deafening also mutes; a
+    /// separate server mute outlasts the deafen.
+    pub fn set_server_deafened(&mut self, deafened: bool) -> Option<AudioStateChange> {
+        self.track(|state| state.is_deafened = deafened)
+    }
+
+    /// Applies a moderator imposing (`active`) or lifting a restriction.
     ///
-    /// This is primarily used for testing or placeholder purposes.
-    /// In production, use `UserAudioState::new()` with a valid user_id.
-    fn default() -> Self {
-        Self::new(0)
+    /// Bans do not affect audio state and never produce a change.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::audio::UserAudioState;
+    /// use fleet_net_common::restriction::RestrictionKind;
+    /// use fleet_net_common::types::UserId;
+    ///
+    /// let mut audio_state = UserAudioState::new(UserId::new(42).unwrap());
+    /// audio_state.apply_restriction(RestrictionKind::Mute, true);
+    /// audio_state.apply_restriction(RestrictionKind::Deafen, true);
+    ///
+    /// // Lifting the deafen leaves the earlier mute in place
+    /// let change = audio_state
+    ///     .apply_restriction(RestrictionKind::Deafen, false)
+    ///     .unwrap();
+    /// assert!(change.server_muted && !change.server_deafened);
+    /// ```
+    pub fn apply_restriction(
+        &mut self,
+        kind: RestrictionKind,
+        active: bool,
+    ) -> Option<AudioStateChange> {
+        match kind {
+            RestrictionKind::Mute => self.set_server_muted(active),
+            RestrictionKind::Deafen => self.set_server_deafened(active),
+            RestrictionKind::Ban => None,
+        }
+    }
+
+    /// Runs `update`, returning the new effective state if it changed.
+    fn track(&mut self, update: impl FnOnce(&mut Self)) -> Option<AudioStateChange> {
+        let before = self.effective();
+        update(self);
+        let after = self.effective();
+        (after != before).then_some(after)
     }
 }
@@ -0,0 +1,302 @@
+//! Audit log entries and queries.
+//!
+//! The server records every moderation and administration action as an
+//! [`AuditLogEntry`]. Users holding VIEW_AUDIT_LOG read the log a page at a
+//! time with an [`AuditLogQuery`], newest entries first, through the admin
+//! API or the client's admin panel; both share these types.
+
+use crate::error::FleetNetError;
+use crate::limits::ServerLimits;
+use crate::types::{ChannelId, UserId};
+use crate::validation::{Constraint, FieldErrors, Validate};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Entries returned per page when a query does not ask for a size.
+pub const DEFAULT_AUDIT_PAGE_SIZE: u32 = 50;
+
+/// Most entries a single page can hold.
+pub const MAX_AUDIT_PAGE_SIZE: u32 = 500;
+
+/// What was done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    UserKicked,
+    UserMoved,
+    UserRestricted,
+    RestrictionLifted,
+    RoleCreated,
+    RoleUpdated,
+    RoleDeleted,
+    RoleAssigned,
+    RoleUnassigned,
+    ChannelCreated,
+    ChannelUpdated,
+    ChannelDeleted,
+    ServerSettingsChanged,
+}
+
+/// What an action was done to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditTarget {
+    User { user_id: UserId },
+    Channel { channel_id: ChannelId },
+    Role { role_id: String },
+    Server,
+}
+
+/// A single recorded action.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::Utc;
+/// use fleet_net_common::audit::{AuditAction, AuditLogEntry, AuditTarget};
+/// use fleet_net_common::types::UserId;
+/// use std::collections::HashMap;
+///
+/// let entry = AuditLogEntry {
+///     id: 1,
+///     actor: UserId::new(1),
+///     action: AuditAction::RoleAssigned,
+///     target: AuditTarget::User {
+///         user_id: UserId::new(2).unwrap(),
+///     },
+///     timestamp: Utc::now(),
+///     details: HashMap::from([("role_id".to_string(), "pilot".to_string())]),
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    /// Increases with every entry, so it also orders the log.
+    pub id: u64,
+
+    /// The user who acted; `None` for actions the server took on its own,
+    /// such as lifting an expired ban.
+    pub actor: Option<UserId>,
+
+    pub action: AuditAction,
+
+    pub target: AuditTarget,
+
+    pub timestamp: DateTime<Utc>,
+
+    /// Action-specific context, e.g. the reason given or the old and new
+    /// values of a changed field.
+    #[serde(default)]
+    pub details: HashMap<String, String>,
+}
+
+/// Filters and paging for reading the audit log.
+///
+/// Every filter that is set must match. Pages run from newest to oldest;
+/// pass the previous page's [`AuditLogPage::next_before`] as `before` to
+/// fetch the next one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditLogQuery {
+    pub actor: Option<UserId>,
+
+    pub target: Option<AuditTarget>,
+
+    /// Actions to include; empty includes all.
+    pub actions: Vec<AuditAction>,
+
+    /// Only entries at or after this time.
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only entries before this time.
+    pub until: Option<DateTime<Utc>>,
+
+    /// Only entries with a lower id, to continue from an earlier page.
+    pub before: Option<u64>,
+
+    /// Page size, [`DEFAULT_AUDIT_PAGE_SIZE`] when `None`.
+    pub limit: Option<u32>,
+}
+
+/// One page of audit log entries, newest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditLogEntry>,
+
+    /// Cursor for the next page, or `None` if this was the last one.
+    pub next_before: Option<u64>,
+}
+
+/// Checks the page size and time range.
+impl Validate for AuditLogQuery {
+    fn check(&self, errors: &mut FieldErrors, _limits: &ServerLimits) {
+        if let Some(limit) = self.limit {
+            if !(1..=MAX_AUDIT_PAGE_SIZE).contains(&limit) {
+                errors.add(
+                    "limit",
+                    Constraint::OutOfRange {
+                        min: 1,
+                        max: MAX_AUDIT_PAGE_SIZE.into(),
+                    },
+                );
+            }
+        }
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if since > until {
+                errors.add("since", Constraint::Invalid(Cow::Borrowed("after_until")));
+            }
+        }
+    }
+}
+
+impl AuditLogQuery {
+    /// Whether `entry` passes every filter, ignoring paging.
+    pub fn matches(&self, entry: &AuditLogEntry) -> bool {
+        self.actor.is_none_or(|actor| entry.actor == Some(actor))
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| entry.target == *target)
+            && (self.actions.is_empty() || self.actions.contains(&entry.action))
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+
+    /// The page of `entries` this query selects, for stores that keep the
+    /// log in memory.
+    ///
+    /// `entries` must be in ascending id order, as they were recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chrono::Utc;
+    /// use fleet_net_common::audit::{AuditAction, AuditLogEntry, AuditLogQuery, AuditTarget};
+    /// use std::collections::HashMap;
+    ///
+    /// let log: Vec<_> = (1..=5)
+    ///     .map(|id| AuditLogEntry {
+    ///         id,
+    ///         actor: None,
+    ///         action: AuditAction::ServerSettingsChanged,
+    ///         target: AuditTarget::Server,
+    ///         timestamp: Utc::now(),
+    ///         details: HashMap::new(),
+    ///     })
+    ///     .collect();
+    ///
+    /// let mut query = AuditLogQuery {
+    ///     limit: Some(2),
+    ///     ..Default::default()
+    /// };
+    /// let page = query.paginate(&log);
+    /// assert_eq!(page.entries[0].id, 5);
+    /// assert_eq!(page.next_before, Some(4));
+    ///
+    /// query.before = page.next_before;
+    /// assert_eq!(query.paginate(&log).entries[0].id, 3);
+    /// ```
+    pub fn paginate(&self, entries: &[AuditLogEntry]) -> AuditLogPage {
+        let limit = self.limit.unwrap_or(DEFAULT_AUDIT_PAGE_SIZE) as usize;
+        let mut matching = entries
+            .iter()
+            .rev()
+            .filter(|entry| self.before.is_none_or(|before| entry.id < before))
+            .filter(|entry| self.matches(entry));
+        let page: Vec<AuditLogEntry> = matching.by_ref().take(limit).cloned().collect();
+        let next_before = match (page.last(), matching.next()) {
+            (Some(last), Some(_)) => Some(last.id),
+            _ => None,
+        };
+        AuditLogPage {
+            entries: page,
+            next_before,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u64, actor: u16, action: AuditAction) -> AuditLogEntry {
+        AuditLogEntry {
+            id,
+            actor: UserId::new(actor),
+            action,
+            target: AuditTarget::Channel {
+                channel_id: ChannelId::new(1).unwrap(),
+            },
+            timestamp: Utc::now(),
+            details: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_audit_query_filters_and_pages() {
+        let log: Vec<_> = (1..=10)
+            .map(|id| {
+                let action = if id % 2 == 0 {
+                    AuditAction::ChannelUpdated
+                } else {
+                    AuditAction::ChannelCreated
+                };
+                entry(id, (id % 3) as u16 + 1, action)
+            })
+            .collect();
+
+        let mut query = AuditLogQuery {
+            actions: vec![AuditAction::ChannelUpdated],
+            limit: Some(3),
+            ..Default::default()
+        };
+        let page = query.paginate(&log);
+        let ids: Vec<_> = page.entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [10, 8, 6]);
+        assert_eq!(page.next_before, Some(6));
+
+        query.before = page.next_before;
+        let page = query.paginate(&log);
+        let ids: Vec<_> = page.entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [4, 2]);
+        assert_eq!(page.next_before, None);
+
+        // Actor 1 acted on ids 3, 6 and 9
+        let by_actor = AuditLogQuery {
+            actor: UserId::new(1),
+            ..Default::default()
+        };
+        assert_eq!(by_actor.paginate(&log).entries.len(), 3);
+    }
+
+    #[test]
+    fn test_audit_query_validation_and_wire_format() {
+        let query = AuditLogQuery {
+            limit: Some(MAX_AUDIT_PAGE_SIZE + 1),
+            since: Some(Utc::now()),
+            until: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        let err = query.validate(&ServerLimits::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: limit: out_of_range(1..=500); since: after_until"
+        );
+
+        // Clients may send only the filters they use
+        let query: AuditLogQuery =
+            serde_json::from_str(r#"{"target":{"type":"role","role_id":"pilot"}}"#).unwrap();
+        assert!(query.validate(&ServerLimits::default()).is_ok());
+        assert_eq!(
+            query.target,
+            Some(AuditTarget::Role {
+                role_id: "pilot".to_string()
+            })
+        );
+
+        let json = serde_json::to_value(entry(1, 1, AuditAction::UserKicked)).unwrap();
+        assert_eq!(json["action"], "user_kicked");
+        assert_eq!(json["target"]["type"], "channel");
+    }
+}
@@ -10,11 +10,30 @@
 //! - Inherits permissions from parent channels
 //! - Uses priority-based role resolution
 //! - Allows partial permission overrides (only override specific permissions)
+//!
+//! # Channel Tree
+//!
+//! A [`ChannelTree`] owns all of a server's channels and guarantees the
+//! parent links form a tree: every parent exists and there are no cycles,
+//! so walking up from any channel always ends at a root.
 
+use crate::audio::TransmitMode;
+use crate::error::FleetNetError;
+use crate::limits::ServerLimits;
+use crate::permission::Permissions;
 use crate::types::ChannelId;
+use crate::validation::{Constraint, FieldErrors, Validate};
 use crate::Role;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+pub use crate::limits::{
+    MAX_CHANNEL_DESCRIPTION_LEN, MAX_CHANNEL_ICON_LEN, MAX_CHANNEL_METADATA_ENTRIES,
+    MAX_CHANNEL_METADATA_KEY_LEN, MAX_CHANNEL_METADATA_VALUE_LEN, MAX_CHANNEL_NAME_LEN,
+    MAX_CHANNEL_TOPIC_LEN, MAX_CRYPTO_KEY_ID_LEN, MAX_OPUS_BITRATE, MAX_RADIO_FREQUENCY_HZ,
+    MIN_OPUS_BITRATE,
+};
 
 /// Represents a channel in the Fleet Net system.
 ///
@@ -31,20 +50,26 @@ use std::collections::HashMap;
 /// # Examples
 ///
 /// ```
-/// use fleet_net_common::channel::{Channel, ChannelType};
+/// use fleet_net_common::channel::{AudioPolicy, Channel, ChannelType};
+/// use fleet_net_common::types::ChannelId;
 /// use std::collections::HashMap;
 ///
 /// let channel = Channel {
-///     id: 1,
+///     id: ChannelId::new(1).unwrap(),
 ///     name: "General".to_string(),
 ///     description: Some("Main voice channel".to_string()),
 ///     channel_type: ChannelType::Voice,
 ///     role_permissions: HashMap::new(),
 ///     position: 0,
 ///     parent_id: None,
+///     topic: None,
+///     icon: None,
+///     metadata: HashMap::new(),
+///     radio: None,
+///     audio_policy: AudioPolicy::default(),
 /// };
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Channel {
     /// Unique identifier for the channel.
     pub id: ChannelId,
@@ -56,7 +81,7 @@ pub struct Channel {
     /// Useful for explaining channel purpose or rules.
     pub description: Option<String>,
 
-    /// Type of channel (Voice, Radio, or Category).
+    /// Type of channel (Voice, Radio, Category or Intercom).
     pub channel_type: ChannelType,
 
     /// Role-specific permission overrides for this channel.
@@ -70,6 +95,28 @@ pub struct Channel {
     /// Parent channel ID for nested channels.
     /// Voice/Radio channels can be nested under Categories.
     pub parent_id: Option<ChannelId>,
+
+    /// Short line shown at the top of the channel, e.g. the current tasking.
+    #[serde(default)]
+    pub topic: Option<String>,
+
+    /// Identifier of the icon shown next to the channel, e.g. `radio-tower`.
+    /// Clients map it to an image; unknown icons fall back to the default.
+    #[serde(default)]
+    pub icon: Option<String>,
+
+    /// Free-form key/value pairs for game integrations, e.g. an ATIS
+    /// frequency or the map grid of the unit using the channel.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+
+    /// How a radio channel is tuned; only valid on [`ChannelType::Radio`].
+    #[serde(default)]
+    pub radio: Option<RadioChannelConfig>,
+
+    /// How audio may be sent in this channel.
+    #[serde(default)]
+    pub audio_policy: AudioPolicy,
 }
 
 /// Types of channels supported by Fleet Net.
@@ -86,6 +133,197 @@ pub enum ChannelType {
     /// Category for organizing other channels.
     /// Cannot be joined directly but can contain permissions.
     Category,
+
+    /// Intercom for the crew of one vehicle or ship.
+    /// Subscribed next to radios like a radio channel, but always on,
+    /// clean of radio effects and mixed apart from the radios.
+    Intercom,
+}
+
+/// Modulation of a radio channel. Receivers only hear transmissions with the
+/// modulation they are set to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Modulation {
+    Am,
+    Fm,
+    /// Single sideband, common on HF.
+    Ssb,
+}
+
+/// Kind of radio, which decides how received audio sounds and how far a
+/// transmission carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RadioTypes {
+    Hf = 0,
+    Uhf = 1,
+    Vhf = 2,
+    Satellite = 3,
+    Quantum = 4,
+}
+
+impl RadioTypes {
+    /// The kind of radio that operates at `frequency_hz`: HF below 30 MHz,
+    /// VHF below 300 MHz, UHF below 3 GHz and satellite above.
+    pub fn for_frequency(frequency_hz: u64) -> RadioTypes {
+        match frequency_hz {
+            0..30_000_000 => RadioTypes::Hf,
+            30_000_000..300_000_000 => RadioTypes::Vhf,
+            300_000_000..3_000_000_000 => RadioTypes::Uhf,
+            _ => RadioTypes::Satellite,
+        }
+    }
+}
+
+/// Tuning of a radio channel, shared by the server, which links channels on
+/// the same net, and the client, which shapes received audio to match.
+///
+/// Radio channels with the same frequency, modulation and crypto key form a
+/// net: a transmission on one is heard on all of them. Radio channels on the
+/// frequency with another key, or none, pick up encrypted traffic as noise.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::channel::{Modulation, RadioChannelConfig};
+///
+/// let guard = RadioChannelConfig {
+///     frequency_hz: 243_000_000,
+///     modulation: Modulation::Am,
+///     max_range_m: None,
+///     crypto_key_id: None,
+/// };
+/// let secure = RadioChannelConfig {
+///     crypto_key_id: Some("kilo-1".to_string()),
+///     ..guard.clone()
+/// };
+///
+/// assert_eq!(guard.frequency_mhz(), 243.0);
+/// // Listeners without the key do not hear encrypted traffic
+/// assert!(!guard.same_net(&secure));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RadioChannelConfig {
+    pub frequency_hz: u64,
+
+    pub modulation: Modulation,
+
+    /// Farthest distance a transmission carries, in meters; `None` for no
+    /// limit.
+    #[serde(default)]
+    pub max_range_m: Option<u32>,
+
+    /// Id of the simulated key traffic is encrypted with; `None` for clear
+    /// traffic. Only the id is shared, there is no actual encryption.
+    #[serde(default)]
+    pub crypto_key_id: Option<String>,
+}
+
+impl RadioChannelConfig {
+    /// The frequency in MHz, as shown on radio dials.
+    pub fn frequency_mhz(&self) -> f64 {
+        self.frequency_hz as f64 / 1_000_000.0
+    }
+
+    /// The kind of radio operating on this frequency.
+    pub fn radio_type(&self) -> RadioTypes {
+        RadioTypes::for_frequency(self.frequency_hz)
+    }
+
+    /// Whether transmissions on `self` are heard on `other`.
+    pub fn same_net(&self, other: &RadioChannelConfig) -> bool {
+        self.same_frequency(other) && self.crypto_key_id == other.crypto_key_id
+    }
+
+    /// Whether `other` picks up transmissions on `self`, whatever their keys.
+    pub fn same_frequency(&self, other: &RadioChannelConfig) -> bool {
+        self.frequency_hz == other.frequency_hz && self.modulation == other.modulation
+    }
+}
+
+/// Rules for audio sent in a channel, enforced by the server as it routes
+/// voice packets.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::audio::TransmitMode;
+/// use fleet_net_common::channel::AudioPolicy;
+///
+/// // A net where every call is keyed and kept short
+/// let policy = AudioPolicy {
+///     force_ptt: true,
+///     max_transmit_secs: Some(30),
+///     ..AudioPolicy::default()
+/// };
+///
+/// assert!(policy.allows_mode(TransmitMode::PushToTalk));
+/// assert!(!policy.allows_mode(TransmitMode::VoiceActivity));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioPolicy {
+    /// Lowest average bitrate allowed, in bits per second.
+    pub min_bitrate: u32,
+
+    /// Highest average bitrate allowed, in bits per second.
+    pub max_bitrate: u32,
+
+    /// Clients switch to push-to-talk while in this channel; voice
+    /// activated transmissions are refused.
+    pub force_ptt: bool,
+
+    /// Voice activated transmissions are refused, but clients may stay in
+    /// voice activity mode and key their radios by hand.
+    pub vad_forbidden: bool,
+
+    /// Longest single transmission, in seconds; `None` for no limit.
+    pub max_transmit_secs: Option<u32>,
+
+    /// Members without [`Permissions::SPEAK`] join as spectators, e.g. Zeus
+    /// observers or streamers: they hear the channel, but their audio is
+    /// refused.
+    ///
+    /// [`Permissions::SPEAK`]: crate::permission::Permissions::SPEAK
+    pub spectator_mode: bool,
+
+    /// One transmission at a time: a sender whose roles rank higher than
+    /// the one on the air takes the channel over, and whoever ranks lower
+    /// is stepped on and cut off until the channel is free.
+    pub preemption: bool,
+
+    /// Listeners may ask for a lost packet to be sent again, once, for
+    /// control-critical nets where a burst of loss is longer than forward
+    /// error correction can cover.
+    pub retransmit: bool,
+}
+
+impl AudioPolicy {
+    /// Whether clients in `mode` may transmit in the channel.
+    pub fn allows_mode(&self, mode: TransmitMode) -> bool {
+        mode == TransmitMode::PushToTalk || !(self.force_ptt || self.vad_forbidden)
+    }
+
+    /// Whether `bitrate`, in bits per second, is within the allowed range.
+    pub fn allows_bitrate(&self, bitrate: u32) -> bool {
+        (self.min_bitrate..=self.max_bitrate).contains(&bitrate)
+    }
+}
+
+impl Default for AudioPolicy {
+    /// No restrictions beyond what Opus can encode.
+    fn default() -> Self {
+        Self {
+            min_bitrate: MIN_OPUS_BITRATE,
+            max_bitrate: MAX_OPUS_BITRATE,
+            force_ptt: false,
+            vad_forbidden: false,
+            max_transmit_secs: None,
+            spectator_mode: false,
+            preemption: false,
+            retransmit: false,
+        }
+    }
 }
 
 /// Permission overrides for a specific role in a channel.
@@ -104,23 +342,23 @@ pub enum ChannelType {
 ///
 /// ```
 /// use fleet_net_common::channel::ChannelPermissions;
-/// use fleet_net_common::permission::permissions;
+/// use fleet_net_common::permission::Permissions;
 ///
 /// // Allow speaking but deny moving users
 /// let perms = ChannelPermissions {
-///     allow: permissions::SPEAK,
-///     deny: permissions::MOVE_USERS,
+///     allow: Permissions::SPEAK,
+///     deny: Permissions::MOVE_USERS,
 /// };
 /// ```
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct ChannelPermissions {
-    /// Bitmask of explicitly allowed permissions.
+    /// Explicitly allowed permissions.
     /// These permissions are granted regardless of role permissions.
-    pub allow: u64,
+    pub allow: Permissions,
 
-    /// Bitmask of explicitly denied permissions.
+    /// Explicitly denied permissions.
     /// These permissions are denied regardless of role permissions.
-    pub deny: u64,
+    pub deny: Permissions,
 }
 
 impl ChannelPermissions {
@@ -137,27 +375,108 @@ impl ChannelPermissions {
     ///
     /// ```
     /// use fleet_net_common::channel::ChannelPermissions;
+    /// use fleet_net_common::permission::Permissions;
     ///
     /// let perms = ChannelPermissions {
-    ///     allow: 0b111,  // Allow first 3 permissions
-    ///     deny:  0b010,  // Deny second permission
+    ///     allow: Permissions::CONNECT | Permissions::SPEAK | Permissions::LISTEN,
+    ///     deny: Permissions::SPEAK,
     /// };
     ///
-    /// assert_eq!(perms.compute_final_permissions(), 0b101);
+    /// assert_eq!(
+    ///     perms.compute_final_permissions(),
+    ///     Permissions::CONNECT | Permissions::LISTEN
+    /// );
     /// ```
-    pub fn compute_final_permissions(&self) -> u64 {
+    pub fn compute_final_permissions(&self) -> Permissions {
         // Only the allowed permissions, minus any denied ones
         self.allow & !self.deny
     }
 }
 
+/// Deepest channel nesting accepted when resolving permissions.
+pub const MAX_CHANNEL_DEPTH: usize = 64;
+
 impl Channel {
+    /// Checks a topic, icon and metadata before they replace this channel's.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error listing every offending field, with
+    /// metadata entries reported as `metadata.<key>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::channel::Channel;
+    /// use fleet_net_common::limits::ServerLimits;
+    /// use std::collections::HashMap;
+    ///
+    /// let limits = ServerLimits::default();
+    /// let metadata = HashMap::from([("atis".to_string(), "121.500".to_string())]);
+    /// let topic = Some("CAS tasking".to_string());
+    /// assert!(Channel::validate_info(&topic, &None, &metadata, &limits).is_ok());
+    /// let icon = Some("radio tower".to_string());
+    /// assert!(Channel::validate_info(&None, &icon, &metadata, &limits).is_err());
+    /// ```
+    pub fn validate_info(
+        topic: &Option<String>,
+        icon: &Option<String>,
+        metadata: &HashMap<String, String>,
+        limits: &ServerLimits,
+    ) -> Result<(), FleetNetError> {
+        let mut errors = FieldErrors::new();
+        Self::check_info(&mut errors, topic, icon, metadata, limits);
+        errors.into_result()
+    }
+
+    /// Records every problem with a channel's topic, icon and metadata in
+    /// `errors`, see [`Channel::validate_info`].
+    pub fn check_info(
+        errors: &mut FieldErrors,
+        topic: &Option<String>,
+        icon: &Option<String>,
+        metadata: &HashMap<String, String>,
+        limits: &ServerLimits,
+    ) {
+        if let Some(topic) = topic {
+            errors.check_length("topic", topic, 0, limits.max_channel_topic_len);
+        }
+        if let Some(icon) = icon {
+            errors.check_length("icon", icon, 1, MAX_CHANNEL_ICON_LEN);
+            // Icons name assets, so keep them to what is safe in a file name
+            if !icon
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                errors.add("icon", Constraint::InvalidFormat);
+            }
+        }
+        if metadata.len() > MAX_CHANNEL_METADATA_ENTRIES {
+            errors.add(
+                "metadata",
+                Constraint::TooLong(MAX_CHANNEL_METADATA_ENTRIES),
+            );
+        }
+        for (key, value) in metadata {
+            if key.is_empty() || key.len() > MAX_CHANNEL_METADATA_KEY_LEN {
+                errors.add("metadata", Constraint::Invalid(Cow::Borrowed("key_length")));
+            } else {
+                errors.check_length(
+                    format!("metadata.{key}"),
+                    value,
+                    0,
+                    MAX_CHANNEL_METADATA_VALUE_LEN,
+                );
+            }
+        }
+    }
+
     /// Computes the effective permissions for a user in this channel.
     ///
     /// This method implements a sophisticated permission resolution system:
     /// 1. Checks role-specific overrides in priority order
     /// 2. Only applies permissions that haven't been set by higher priority roles
-    /// 3. Recursively inherits from parent channels
+    /// 3. Inherits from parent channels, all the way up to the root
     /// 4. Falls back to base role permissions
     ///
     /// # Arguments
@@ -167,7 +486,13 @@ impl Channel {
     ///
     /// # Returns
     ///
-    /// The final computed permission bitmask for the user.
+    /// The final computed permissions for the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the parent links loop back on
+    /// themselves or nest deeper than [`MAX_CHANNEL_DEPTH`]. A missing
+    /// parent is treated as the root.
     ///
     /// # Algorithm Details
     ///
@@ -176,6 +501,9 @@ impl Channel {
     /// This allows partial overrides where a role only affects specific
     /// permissions without touching others.
     ///
+    /// Parents are collected first and then resolved from the root down,
+    /// so no recursion is involved.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -186,15 +514,64 @@ impl Channel {
     /// let permissions = channel.compute_user_permissions(
     ///     &roles,
     ///     |parent_id| None  // No parent channels
-    /// );
+    /// )?;
+    /// # Ok::<(), fleet_net_common::error::FleetNetError>(())
     /// ```
     pub fn compute_user_permissions(
         &self,
         user_roles: &[Role],
         get_parent_channel: impl Fn(ChannelId) -> Option<Channel>,
-    ) -> u64 {
-        let mut final_permissions = 0u64;
-        let mut checked_permissions = 0u64;
+    ) -> Result<Permissions, FleetNetError> {
+        // Overrides of this channel and each parent, nearest first
+        let mut overrides = vec![self.role_overrides(user_roles)];
+        let mut visited = HashSet::from([self.id]);
+        let mut next = self.parent_id;
+        while let Some(parent) = next.and_then(&get_parent_channel) {
+            if !visited.insert(parent.id) {
+                return Err(FleetNetError::invalid_field(
+                    "parent_id",
+                    Constraint::Invalid(Cow::Borrowed("cycle")),
+                ));
+            }
+            if overrides.len() > MAX_CHANNEL_DEPTH {
+                return Err(FleetNetError::invalid_field(
+                    "parent_id",
+                    Constraint::TooLong(MAX_CHANNEL_DEPTH),
+                ));
+            }
+            overrides.push(parent.role_overrides(user_roles));
+            next = parent.parent_id;
+        }
+
+        let base_permissions = user_roles
+            .first()
+            .map_or(Permissions::empty(), |role| role.permissions);
+
+        // Resolve from the root down; each level sees its parent's result
+        let mut inherited: Option<Permissions> = None;
+        for (mut final_permissions, mut checked_permissions) in overrides.into_iter().rev() {
+            // Inherit permissions from parent channel for any unset bits
+            if let Some(parent_perms) = inherited {
+                // Only use parent permissions for bits we haven't set
+                final_permissions |= parent_perms & !checked_permissions;
+                // Update checked_permissions to include parent's contributions
+                checked_permissions |= parent_perms;
+            }
+
+            // For any still unset permissions, use the highest priority role's base permissions
+            final_permissions |= base_permissions & !checked_permissions;
+            inherited = Some(final_permissions);
+        }
+
+        // `overrides` always holds this channel, so the loop ran at least once
+        Ok(inherited.unwrap_or_default())
+    }
+
+    /// Applies this channel's role overrides, returning the resulting
+    /// permissions and the bits they decided.
+    fn role_overrides(&self, user_roles: &[Role]) -> (Permissions, Permissions) {
+        let mut final_permissions = Permissions::empty();
+        let mut checked_permissions = Permissions::empty();
 
         // Process each role in priority order (highest priority first)
         for role in user_roles {
@@ -212,54 +589,425 @@ impl Channel {
             }
         }
 
-        // Inherit permissions from parent channel for any unset bits
-        if let Some(parent_id) = self.parent_id {
-            if let Some(parent) = get_parent_channel(parent_id) {
-                let parent_perms = parent.compute_user_permissions(user_roles, get_parent_channel);
-                // Only use parent permissions for bits we haven't set
-                final_permissions |= parent_perms & !checked_permissions;
-                // Update checked_permissions to include parent's contributions
-                checked_permissions |= parent_perms;
+        (final_permissions, checked_permissions)
+    }
+}
+
+/// Checks the fields a client can edit, with errors like
+/// `name: too_long(100)`.
+impl Validate for Channel {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        errors.check_length("name", self.name.trim(), 1, limits.max_channel_name_len);
+        if let Some(description) = &self.description {
+            errors.check_length(
+                "description",
+                description,
+                0,
+                limits.max_channel_description_len,
+            );
+        }
+        if self.parent_id == Some(self.id) {
+            errors.add("parent_id", Constraint::Invalid(Cow::Borrowed("cycle")));
+        }
+        Channel::check_info(errors, &self.topic, &self.icon, &self.metadata, limits);
+        if let Some(radio) = &self.radio {
+            if self.channel_type != ChannelType::Radio {
+                errors.add(
+                    "radio",
+                    Constraint::Invalid(Cow::Borrowed("not_radio_channel")),
+                );
             }
+            errors.check_nested("radio", radio, limits);
         }
+        errors.check_nested("audio_policy", &self.audio_policy, limits);
+    }
+}
 
-        // For any still unset permissions, use the highest priority role's base permissions
-        if let Some(role) = user_roles.first() {
-            final_permissions |= role.permissions & !checked_permissions;
+impl Validate for RadioChannelConfig {
+    fn check(&self, errors: &mut FieldErrors, _limits: &ServerLimits) {
+        if !(1..=MAX_RADIO_FREQUENCY_HZ).contains(&self.frequency_hz) {
+            errors.add(
+                "frequency_hz",
+                Constraint::OutOfRange {
+                    min: 1,
+                    max: MAX_RADIO_FREQUENCY_HZ as i64,
+                },
+            );
+        }
+        if self.max_range_m == Some(0) {
+            errors.add("max_range_m", Constraint::TooShort(1));
         }
+        if let Some(key_id) = &self.crypto_key_id {
+            errors.check_length("crypto_key_id", key_id, 1, MAX_CRYPTO_KEY_ID_LEN);
+        }
+    }
+}
 
-        final_permissions
+impl Validate for AudioPolicy {
+    fn check(&self, errors: &mut FieldErrors, _limits: &ServerLimits) {
+        let range = MIN_OPUS_BITRATE..=MAX_OPUS_BITRATE;
+        for (field, bitrate) in [
+            ("min_bitrate", self.min_bitrate),
+            ("max_bitrate", self.max_bitrate),
+        ] {
+            if !range.contains(&bitrate) {
+                errors.add(
+                    field,
+                    Constraint::OutOfRange {
+                        min: MIN_OPUS_BITRATE.into(),
+                        max: MAX_OPUS_BITRATE.into(),
+                    },
+                );
+            }
+        }
+        if self.min_bitrate > self.max_bitrate {
+            errors.add(
+                "min_bitrate",
+                Constraint::Invalid(Cow::Borrowed("above_max_bitrate")),
+            );
+        }
+        if self.max_transmit_secs == Some(0) {
+            errors.add("max_transmit_secs", Constraint::TooShort(1));
+        }
+    }
+}
+
+/// All channels of a server, arranged by their parent links.
+///
+/// The tree rejects changes that would leave a channel pointing at a
+/// missing parent or make a channel its own ancestor. Siblings are shown
+/// in `position` order, ties broken by id.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::channel::{AudioPolicy, Channel, ChannelTree, ChannelType};
+/// use fleet_net_common::types::ChannelId;
+/// use std::collections::HashMap;
+///
+/// let channel = |id: u16, parent_id: Option<u16>| Channel {
+///     id: ChannelId::new(id).unwrap(),
+///     name: format!("Channel {id}"),
+///     description: None,
+///     channel_type: ChannelType::Voice,
+///     role_permissions: HashMap::new(),
+///     position: 0,
+///     parent_id: parent_id.and_then(ChannelId::new),
+///     topic: None,
+///     icon: None,
+///     metadata: HashMap::new(),
+///     radio: None,
+///     audio_policy: AudioPolicy::default(),
+/// };
+///
+/// let mut tree = ChannelTree::new();
+/// tree.insert(channel(1, None)).unwrap();
+/// tree.insert(channel(2, Some(1))).unwrap();
+///
+/// // Channel 1 cannot move under its own child
+/// assert!(tree.insert(channel(1, Some(2))).is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChannelTree {
+    channels: HashMap<ChannelId, Channel>,
+    /// Bumped whenever a change could affect resolved permissions.
+    generation: u64,
+}
+
+impl ChannelTree {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a tree from `channels`, in any order.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if a channel id is repeated, a parent is
+    /// missing or the parent links contain a cycle.
+    pub fn from_channels(
+        channels: impl IntoIterator<Item = Channel>,
+    ) -> Result<Self, FleetNetError> {
+        let mut tree = Self::new();
+        for channel in channels {
+            let id = channel.id;
+            if tree.channels.insert(id, channel).is_some() {
+                return Err(FleetNetError::invalid_field("id", Constraint::Duplicate));
+            }
+        }
+        for channel in tree.channels.values() {
+            tree.check_parent(channel.id, channel.parent_id)?;
+        }
+        Ok(tree)
+    }
+
+    /// Adds `channel`, or replaces the channel with the same id.
+    ///
+    /// Returns the replaced channel, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the parent does not exist or the
+    /// channel would become its own ancestor.
+    pub fn insert(&mut self, channel: Channel) -> Result<Option<Channel>, FleetNetError> {
+        self.check_parent(channel.id, channel.parent_id)?;
+        self.generation += 1;
+        Ok(self.channels.insert(channel.id, channel))
+    }
+
+    /// Removes a channel that has no children.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the channel has children, so removing
+    /// it would orphan them.
+    pub fn remove(&mut self, id: ChannelId) -> Result<Option<Channel>, FleetNetError> {
+        if self
+            .channels
+            .values()
+            .any(|channel| channel.parent_id == Some(id))
+        {
+            return Err(FleetNetError::invalid_field(
+                "id",
+                Constraint::Invalid(Cow::Borrowed("has_children")),
+            ));
+        }
+        self.generation += 1;
+        Ok(self.channels.remove(&id))
+    }
+
+    /// Moves a channel under `parent_id`, or to the top level with `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if either channel does not exist or the
+    /// move would create a cycle.
+    pub fn set_parent(
+        &mut self,
+        id: ChannelId,
+        parent_id: Option<ChannelId>,
+    ) -> Result<(), FleetNetError> {
+        if !self.channels.contains_key(&id) {
+            return Err(Self::unknown());
+        }
+        self.check_parent(id, parent_id)?;
+        if let Some(channel) = self.channels.get_mut(&id) {
+            channel.parent_id = parent_id;
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Moves a channel to `index` among its siblings, renumbering the
+    /// siblings' positions from zero.
+    ///
+    /// An index past the end moves the channel last.
+    pub fn reorder(&mut self, id: ChannelId, index: usize) -> Result<(), FleetNetError> {
+        let parent_id = self.get(id).ok_or_else(Self::unknown)?.parent_id;
+        let mut siblings: Vec<ChannelId> = self
+            .children(parent_id)
+            .map(|channel| channel.id)
+            .filter(|&sibling| sibling != id)
+            .collect();
+        siblings.insert(index.min(siblings.len()), id);
+        for (position, sibling) in siblings.into_iter().enumerate() {
+            if let Some(channel) = self.channels.get_mut(&sibling) {
+                channel.position = position as u32;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: ChannelId) -> Option<&Channel> {
+        self.channels.get(&id)
+    }
+
+    pub fn contains(&self, id: ChannelId) -> bool {
+        self.channels.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Counter bumped by every change to channels, their overrides or
+    /// their parents, for caches of resolved permissions.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Direct children of `parent_id` in display order; `None` lists the
+    /// top-level channels.
+    pub fn children(&self, parent_id: Option<ChannelId>) -> impl Iterator<Item = &Channel> {
+        let mut children: Vec<&Channel> = self
+            .channels
+            .values()
+            .filter(|channel| channel.parent_id == parent_id)
+            .collect();
+        children.sort_by_key(|channel| (channel.position, channel.id));
+        children.into_iter()
+    }
+
+    /// The parents of a channel, nearest first.
+    pub fn ancestors(&self, id: ChannelId) -> impl Iterator<Item = &Channel> {
+        let mut next = self.get(id).and_then(|channel| channel.parent_id);
+        std::iter::from_fn(move || {
+            let parent = self.get(next?)?;
+            next = parent.parent_id;
+            Some(parent)
+        })
+    }
+
+    /// Every channel in display order: each channel is followed by its
+    /// children, with their depth in the tree.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Channel)> {
+        let mut ordered = Vec::with_capacity(self.channels.len());
+        let mut stack: Vec<(usize, &Channel)> =
+            self.children(None).map(|channel| (0, channel)).collect();
+        stack.reverse();
+        while let Some((depth, channel)) = stack.pop() {
+            ordered.push((depth, channel));
+            let first_child = stack.len();
+            stack.extend(
+                self.children(Some(channel.id))
+                    .map(|child| (depth + 1, child)),
+            );
+            stack[first_child..].reverse();
+        }
+        ordered.into_iter()
+    }
+
+    /// The radio channels on the same net as `id`, including `id` itself;
+    /// empty if `id` is not a tuned radio channel.
+    pub fn radio_net(&self, id: ChannelId) -> Vec<ChannelId> {
+        let Some(tuning) = self.get(id).and_then(|channel| channel.radio.as_ref()) else {
+            return Vec::new();
+        };
+        let mut net: Vec<ChannelId> = self
+            .channels
+            .values()
+            .filter(|channel| {
+                channel.channel_type == ChannelType::Radio
+                    && channel
+                        .radio
+                        .as_ref()
+                        .is_some_and(|radio| radio.same_net(tuning))
+            })
+            .map(|channel| channel.id)
+            .collect();
+        net.sort();
+        net
+    }
+
+    /// The radio channels on the frequency of the encrypted channel `id`
+    /// without its key, which hear its traffic as noise; empty if `id` is
+    /// not an encrypted radio channel.
+    pub fn radio_net_without_key(&self, id: ChannelId) -> Vec<ChannelId> {
+        let Some(tuning) = self
+            .get(id)
+            .and_then(|channel| channel.radio.as_ref())
+            .filter(|radio| radio.crypto_key_id.is_some())
+        else {
+            return Vec::new();
+        };
+        let mut overhearing: Vec<ChannelId> = self
+            .channels
+            .values()
+            .filter(|channel| {
+                channel.channel_type == ChannelType::Radio
+                    && channel.radio.as_ref().is_some_and(|radio| {
+                        radio.same_frequency(tuning) && !radio.same_net(tuning)
+                    })
+            })
+            .map(|channel| channel.id)
+            .collect();
+        overhearing.sort();
+        overhearing
+    }
+
+    /// Computes a user's permissions in a channel, see
+    /// [`Channel::compute_user_permissions`].
+    pub fn user_permissions(
+        &self,
+        id: ChannelId,
+        user_roles: &[Role],
+    ) -> Result<Permissions, FleetNetError> {
+        let channel = self.get(id).ok_or_else(Self::unknown)?;
+        channel.compute_user_permissions(user_roles, |parent_id| self.get(parent_id).cloned())
+    }
+
+    /// Checks that `id` may have `parent_id` as its parent.
+    fn check_parent(
+        &self,
+        id: ChannelId,
+        parent_id: Option<ChannelId>,
+    ) -> Result<(), FleetNetError> {
+        let mut next = parent_id;
+        // Bounded by the tree size in case the links are not yet validated
+        for _ in 0..=self.channels.len() {
+            let Some(ancestor) = next else {
+                return Ok(());
+            };
+            if ancestor == id {
+                return Err(FleetNetError::invalid_field(
+                    "parent_id",
+                    Constraint::Invalid(Cow::Borrowed("cycle")),
+                ));
+            }
+            next = self
+                .get(ancestor)
+                .ok_or_else(|| FleetNetError::invalid_field("parent_id", Constraint::NotFound))?
+                .parent_id;
+        }
+        Err(FleetNetError::invalid_field(
+            "parent_id",
+            Constraint::Invalid(Cow::Borrowed("cycle")),
+        ))
+    }
+
+    fn unknown() -> FleetNetError {
+        FleetNetError::invalid_field("channel_id", Constraint::NotFound)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::permissions;
+    use proptest::prelude::*;
 
     fn create_test_channel(id: u16) -> Channel {
         Channel {
-            id,
+            id: ChannelId::new(id).unwrap(),
             name: "Test Channel".to_string(),
             description: Some("A test channel".to_string()),
             channel_type: ChannelType::Voice,
             role_permissions: HashMap::new(),
             position: 0,
             parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
         }
     }
 
     #[test]
     fn test_compute_final_permissions_deny_overrides_allow() {
         let perms = ChannelPermissions {
-            allow: permissions::SPEAK | permissions::LISTEN,
-            deny: permissions::SPEAK,
+            allow: Permissions::SPEAK | Permissions::LISTEN,
+            deny: Permissions::SPEAK,
         };
 
         let final_perms = perms.compute_final_permissions();
 
-        assert_eq!(final_perms & permissions::SPEAK, 0); // Speak should be denied
-        assert_ne!(final_perms & permissions::LISTEN, 0); // Listen should still be allowed
+        assert!(!final_perms.contains(Permissions::SPEAK)); // Speak should be denied
+        assert!(final_perms.contains(Permissions::LISTEN)); // Listen should still be allowed
     }
 
     #[test]
@@ -270,30 +1018,30 @@ mod tests {
         channel.role_permissions.insert(
             "admin".to_string(),
             ChannelPermissions {
-                allow: permissions::SPEAK,
-                deny: 0,
+                allow: Permissions::SPEAK,
+                deny: Permissions::empty(),
             },
         );
         channel.role_permissions.insert(
             "member".to_string(),
             ChannelPermissions {
-                allow: 0,
-                deny: permissions::SPEAK,
+                allow: Permissions::empty(),
+                deny: Permissions::SPEAK,
             },
         );
 
         // User with admin role (higher priority) should be able to speak.
         let admin_role = Role::new("admin".to_string(), "Admin".to_string())
-            .with_permissions(0)
+            .with_permissions(Permissions::empty())
             .with_priority(1);
         let member_role = Role::new("member".to_string(), "Member".to_string())
-            .with_permissions(permissions::SPEAK)
+            .with_permissions(Permissions::SPEAK)
             .with_priority(10);
 
         let roles = vec![admin_role, member_role];
-        let perms = channel.compute_user_permissions(&roles, |_| None);
+        let perms = channel.compute_user_permissions(&roles, |_| None).unwrap();
 
-        assert_ne!(perms & permissions::SPEAK, 0); // Admin should have permission to speak
+        assert!(perms.contains(Permissions::SPEAK)); // Admin should have permission to speak
     }
 
     #[test]
@@ -304,30 +1052,32 @@ mod tests {
         child.parent_id = Some(parent.id);
 
         // Add grandparent setup
-        let mut grandparent = create_test_channel(0);
+        let mut grandparent = create_test_channel(3);
         grandparent.role_permissions.insert(
             "member".to_string(),
             ChannelPermissions {
-                allow: permissions::LISTEN | permissions::SPEAK,
-                deny: 0,
+                allow: Permissions::LISTEN | Permissions::SPEAK,
+                deny: Permissions::empty(),
             },
         );
 
-        // Make parent's parent_id = Some(0)
-        parent.parent_id = Some(0);
+        parent.parent_id = Some(grandparent.id);
 
-        let member_role = Role::new("member".to_string(), "Member".to_string()).with_permissions(0);
+        let member_role = Role::new("member".to_string(), "Member".to_string())
+            .with_permissions(Permissions::empty());
 
         let roles = [member_role];
-        let perms = child.compute_user_permissions(&roles, |id| match id {
-            0 => Some(grandparent.clone()),
-            1 => Some(parent.clone()),
-            _ => None,
-        });
+        let perms = child
+            .compute_user_permissions(&roles, |id| match id.get() {
+                3 => Some(grandparent.clone()),
+                1 => Some(parent.clone()),
+                _ => None,
+            })
+            .unwrap();
 
         // Should inherit SPEAK and LISTEN from parent
-        assert_ne!(perms & permissions::SPEAK, 0);
-        assert_ne!(perms & permissions::LISTEN, 0);
+        assert!(perms.contains(Permissions::SPEAK));
+        assert!(perms.contains(Permissions::LISTEN));
     }
 
     #[test]
@@ -335,12 +1085,12 @@ mod tests {
         let channel = create_test_channel(1);
 
         let role = Role::new("member".to_string(), "Member".to_string())
-            .with_permissions(permissions::SPEAK | permissions::CONNECT); // Only has LISTEN permission
+            .with_permissions(Permissions::SPEAK | Permissions::CONNECT); // Only has LISTEN permission
 
         let roles = [role];
-        let perms = channel.compute_user_permissions(&roles, |_| None);
+        let perms = channel.compute_user_permissions(&roles, |_| None).unwrap();
 
-        assert_eq!(perms, permissions::SPEAK | permissions::CONNECT); // Should return base role permissions
+        assert_eq!(perms, Permissions::SPEAK | Permissions::CONNECT); // Should return base role permissions
     }
 
     #[test]
@@ -348,12 +1098,12 @@ mod tests {
         let channel = create_test_channel(1);
 
         // let role = Role::new("member".to_string(), "Member".to_string())
-        //     .with_permissions(permissions::SPEAK | permissions::CONNECT); // Only has LISTEN permission
+        //     .with_permissions(Permissions::SPEAK | Permissions::CONNECT); // Only has LISTEN permission
 
         let roles: Vec<Role> = vec![];
-        let perms = channel.compute_user_permissions(&roles, |_| None);
+        let perms = channel.compute_user_permissions(&roles, |_| None).unwrap();
 
-        assert_eq!(perms, 0); // No Roles = No Permissions
+        assert!(perms.is_empty()); // No Roles = No Permissions
     }
 
     #[test]
@@ -364,36 +1114,36 @@ mod tests {
         channel.role_permissions.insert(
             "member".to_string(),
             ChannelPermissions {
-                allow: permissions::SPEAK | permissions::LISTEN,
-                deny: 0,
+                allow: Permissions::SPEAK | Permissions::LISTEN,
+                deny: Permissions::empty(),
             },
         );
 
         channel.role_permissions.insert(
             "banned".to_string(),
             ChannelPermissions {
-                allow: 0,
-                deny: permissions::SPEAK, // Banned role denies speaking and listening
+                allow: Permissions::empty(),
+                deny: Permissions::SPEAK, // Banned role denies speaking and listening
             },
         );
 
         // Roles sorted by priority - banned has higher priority than member.
 
         let banned_role = Role::new("banned".to_string(), "Banned".to_string())
-            .with_permissions(0)
+            .with_permissions(Permissions::empty())
             .with_priority(10);
 
         let member_role = Role::new("member".to_string(), "Member".to_string())
-            .with_permissions(permissions::SPEAK)
+            .with_permissions(Permissions::SPEAK)
             .with_priority(5);
 
         let roles = [banned_role, member_role];
 
-        let perms = channel.compute_user_permissions(&roles, |_| None);
+        let perms = channel.compute_user_permissions(&roles, |_| None).unwrap();
 
         // Banned role should override member role, so no permissions should be granted
-        assert_eq!(perms & permissions::SPEAK, 0); // Speak should be denied
-        assert_ne!(perms & permissions::LISTEN, 0); // Listen should be allowed
+        assert!(!perms.contains(Permissions::SPEAK)); // Speak should be denied
+        assert!(perms.contains(Permissions::LISTEN)); // Listen should be allowed
     }
 
     #[test]
@@ -404,33 +1154,328 @@ mod tests {
         channel.role_permissions.insert(
             "admin".to_string(),
             ChannelPermissions {
-                allow: permissions::SPEAK | permissions::LISTEN | permissions::CONNECT,
-                deny: 0,
+                allow: Permissions::SPEAK | Permissions::LISTEN | Permissions::CONNECT,
+                deny: Permissions::empty(),
             },
         );
         channel.role_permissions.insert(
             "banned".to_string(),
             ChannelPermissions {
-                allow: permissions::CONNECT,
-                deny: permissions::SPEAK | permissions::LISTEN, // Banned role denies
+                allow: Permissions::CONNECT,
+                deny: Permissions::SPEAK | Permissions::LISTEN, // Banned role denies
             },
         );
 
         // Admin has higher priority than banned.
         let admin_role = Role::new("admin".to_string(), "Admin".to_string())
-            .with_permissions(0)
+            .with_permissions(Permissions::empty())
             .with_priority(10);
 
         let banned_role = Role::new("banned".to_string(), "Banned".to_string())
-            .with_permissions(0)
+            .with_permissions(Permissions::empty())
             .with_priority(5);
 
         // User has both admin and banned roles.
         let roles = [admin_role, banned_role];
-        let perms = channel.compute_user_permissions(&roles, |_| None);
+        let perms = channel.compute_user_permissions(&roles, |_| None).unwrap();
+
+        assert!(perms.contains(Permissions::SPEAK));
+        assert!(perms.contains(Permissions::LISTEN));
+        assert!(perms.contains(Permissions::CONNECT)); // Admin should have all permissions, even if banned.
+    }
+
+    fn tree_channel(id: u16, parent: Option<u16>, position: u32) -> Channel {
+        Channel {
+            parent_id: parent.and_then(ChannelId::new),
+            position,
+            ..create_test_channel(id)
+        }
+    }
+
+    #[test]
+    fn test_channel_tree_rejects_cycles_and_missing_parents() {
+        // Children may come before their parents
+        let mut tree = ChannelTree::from_channels([
+            tree_channel(3, Some(2), 0),
+            tree_channel(2, Some(1), 0),
+            tree_channel(1, None, 0),
+        ])
+        .unwrap();
+
+        let id = |id| ChannelId::new(id).unwrap();
+        assert!(tree.set_parent(id(1), Some(id(3))).is_err());
+        assert!(tree.set_parent(id(2), Some(id(2))).is_err());
+        assert!(tree.insert(tree_channel(4, Some(9), 0)).is_err());
+        assert!(tree.remove(id(2)).is_err());
 
-        assert_ne!(perms & permissions::SPEAK, 0);
-        assert_ne!(perms & permissions::LISTEN, 0);
-        assert_ne!(perms & permissions::CONNECT, 0); // Admin should have all permissions, even if banned.
+        let ancestors: Vec<u16> = tree.ancestors(id(3)).map(|c| c.id.get()).collect();
+        assert_eq!(ancestors, vec![2, 1]);
+
+        // A cycle in stored data is caught rather than looping forever
+        let cyclic =
+            ChannelTree::from_channels([tree_channel(1, Some(2), 0), tree_channel(2, Some(1), 0)]);
+        assert!(cyclic.is_err());
+    }
+
+    #[test]
+    fn test_channel_tree_display_order() {
+        let mut tree = ChannelTree::from_channels([
+            tree_channel(1, None, 1),
+            tree_channel(2, None, 0),
+            tree_channel(3, Some(1), 0),
+            tree_channel(4, Some(1), 1),
+            tree_channel(5, Some(2), 0),
+        ])
+        .unwrap();
+
+        let order = |tree: &ChannelTree| -> Vec<(usize, u16)> {
+            tree.iter().map(|(depth, c)| (depth, c.id.get())).collect()
+        };
+        assert_eq!(order(&tree), vec![(0, 2), (1, 5), (0, 1), (1, 3), (1, 4)]);
+
+        // Move channel 4 ahead of its sibling
+        tree.reorder(ChannelId::new(4).unwrap(), 0).unwrap();
+        assert_eq!(order(&tree), vec![(0, 2), (1, 5), (0, 1), (1, 4), (1, 3)]);
+        assert_eq!(tree.get(ChannelId::new(3).unwrap()).unwrap().position, 1);
+    }
+
+    #[test]
+    fn test_compute_user_permissions_rejects_cycles() {
+        let mut first = create_test_channel(1);
+        let mut second = create_test_channel(2);
+        first.parent_id = Some(second.id);
+        second.parent_id = Some(first.id);
+
+        let roles = [Role::new("member".to_string(), "Member".to_string())];
+        let result = first.compute_user_permissions(&roles, |id| match id.get() {
+            1 => Some(first.clone()),
+            2 => Some(second.clone()),
+            _ => None,
+        });
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+
+        // A chain deeper than the limit is rejected too
+        let chain = |id: ChannelId| {
+            let mut channel = create_test_channel(id.get());
+            channel.parent_id = ChannelId::new(id.get() + 1);
+            Some(channel)
+        };
+        let leaf = chain(ChannelId::new(1).unwrap()).unwrap();
+        assert!(leaf.compute_user_permissions(&roles, chain).is_err());
+    }
+
+    #[test]
+    fn test_channel_validate_reports_each_field() {
+        let mut channel = create_test_channel(1);
+        assert!(channel.validate(&ServerLimits::default()).is_ok());
+
+        channel.name = "x".repeat(MAX_CHANNEL_NAME_LEN + 1);
+        channel.description = Some("x".repeat(MAX_CHANNEL_DESCRIPTION_LEN + 1));
+        let Err(FleetNetError::ValidationError(errors)) =
+            channel.validate(&ServerLimits::default())
+        else {
+            panic!("expected a validation error");
+        };
+        assert_eq!(
+            errors.to_string(),
+            "name: too_long(100); description: too_long(1024)"
+        );
+
+        channel.name = "   ".to_string();
+        channel.description = None;
+        let err = channel.validate(&ServerLimits::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Validation error: name: too_short(1)");
+    }
+
+    #[test]
+    fn test_channel_info_validation() {
+        let mut channel = create_test_channel(1);
+        channel.topic = Some("Strike package briefing".to_string());
+        channel.icon = Some("radio-tower".to_string());
+        channel
+            .metadata
+            .insert("atis".to_string(), "121.500".to_string());
+        assert!(channel.validate(&ServerLimits::default()).is_ok());
+
+        channel.icon = Some("../tower".to_string());
+        channel.metadata.insert(
+            "grid".to_string(),
+            "x".repeat(MAX_CHANNEL_METADATA_VALUE_LEN + 1),
+        );
+        let err = channel.validate(&ServerLimits::default()).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("icon: invalid_format"), "{message}");
+        assert!(
+            message.contains("metadata.grid: too_long(256)"),
+            "{message}"
+        );
+
+        let crowded = (0..=MAX_CHANNEL_METADATA_ENTRIES)
+            .map(|i| (format!("key{i}"), String::new()))
+            .collect();
+        assert!(Channel::validate_info(&None, &None, &crowded, &ServerLimits::default()).is_err());
+    }
+
+    fn radio_channel(id: u16, frequency_hz: u64, crypto_key_id: Option<&str>) -> Channel {
+        Channel {
+            channel_type: ChannelType::Radio,
+            radio: Some(RadioChannelConfig {
+                frequency_hz,
+                modulation: Modulation::Am,
+                max_range_m: None,
+                crypto_key_id: crypto_key_id.map(String::from),
+            }),
+            ..create_test_channel(id)
+        }
+    }
+
+    #[test]
+    fn test_radio_config_validation() {
+        let mut channel = radio_channel(1, 251_000_000, Some("kilo-1"));
+        assert!(channel.validate(&ServerLimits::default()).is_ok());
+
+        channel.channel_type = ChannelType::Voice;
+        if let Some(radio) = &mut channel.radio {
+            radio.frequency_hz = 0;
+            radio.max_range_m = Some(0);
+        }
+        let err = channel.validate(&ServerLimits::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: radio: not_radio_channel; \
+             radio.frequency_hz: out_of_range(1..=300000000000); radio.max_range_m: too_short(1)"
+        );
+
+        // Older peers leave the tuning out entirely
+        let json = serde_json::to_value(create_test_channel(2)).unwrap();
+        let mut json = json.as_object().unwrap().clone();
+        json.remove("radio");
+        let channel: Channel = serde_json::from_value(json.into()).unwrap();
+        assert!(channel.radio.is_none());
+    }
+
+    #[test]
+    fn test_audio_policy_validation() {
+        let mut channel = create_test_channel(1);
+        channel.audio_policy = AudioPolicy {
+            min_bitrate: 64_000,
+            max_bitrate: 16_000,
+            max_transmit_secs: Some(0),
+            ..AudioPolicy::default()
+        };
+        let err = channel.validate(&ServerLimits::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: audio_policy.min_bitrate: above_max_bitrate; \
+             audio_policy.max_transmit_secs: too_short(1)"
+        );
+
+        channel.audio_policy = AudioPolicy {
+            max_bitrate: MAX_OPUS_BITRATE + 1,
+            ..AudioPolicy::default()
+        };
+        assert!(channel.validate(&ServerLimits::default()).is_err());
+
+        // Policies may be sent partially filled in
+        let policy: AudioPolicy = serde_json::from_str(r#"{"force_ptt":true}"#).unwrap();
+        assert!(!policy.allows_mode(TransmitMode::VoiceActivity));
+        assert!(policy.allows_bitrate(MAX_OPUS_BITRATE));
+    }
+
+    #[test]
+    fn test_radio_net_links_matching_channels() {
+        let tree = ChannelTree::from_channels([
+            radio_channel(1, 251_000_000, None),
+            radio_channel(2, 251_000_000, None),
+            radio_channel(3, 251_000_000, Some("kilo-1")),
+            radio_channel(4, 243_000_000, None),
+            create_test_channel(5),
+        ])
+        .unwrap();
+
+        let ids = |ids: &[u16]| -> Vec<ChannelId> {
+            ids.iter().map(|&id| ChannelId::new(id).unwrap()).collect()
+        };
+        assert_eq!(tree.radio_net(ChannelId::new(1).unwrap()), ids(&[1, 2]));
+        assert_eq!(tree.radio_net(ChannelId::new(3).unwrap()), ids(&[3]));
+        assert!(tree.radio_net(ChannelId::new(5).unwrap()).is_empty());
+
+        // Radios on the frequency without the key get encrypted traffic only
+        assert_eq!(
+            tree.radio_net_without_key(ChannelId::new(3).unwrap()),
+            ids(&[1, 2])
+        );
+        assert!(tree
+            .radio_net_without_key(ChannelId::new(1).unwrap())
+            .is_empty());
+    }
+
+    /// The recursive resolution this module used before it became iterative.
+    fn recursive_permissions(
+        channel: &Channel,
+        user_roles: &[Role],
+        get_parent_channel: &impl Fn(ChannelId) -> Option<Channel>,
+    ) -> Permissions {
+        let (mut final_permissions, mut checked_permissions) = channel.role_overrides(user_roles);
+        if let Some(parent) = channel.parent_id.and_then(get_parent_channel) {
+            let parent_perms = recursive_permissions(&parent, user_roles, get_parent_channel);
+            final_permissions |= parent_perms & !checked_permissions;
+            checked_permissions |= parent_perms;
+        }
+        if let Some(role) = user_roles.first() {
+            final_permissions |= role.permissions & !checked_permissions;
+        }
+        final_permissions
+    }
+
+    fn arb_overrides() -> impl Strategy<Value = HashMap<String, ChannelPermissions>> {
+        proptest::collection::hash_map(
+            prop_oneof!["admin", "member", "guest"].prop_map(String::from),
+            (0u64..16, 0u64..16).prop_map(|(allow, deny)| ChannelPermissions {
+                allow: Permissions::from_bits_retain(allow),
+                deny: Permissions::from_bits_retain(deny),
+            }),
+            0..3,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_iterative_matches_recursive_on_trees(
+            // Each channel's parent is an earlier channel, so there are no cycles
+            channels in proptest::collection::vec(
+                (any::<prop::sample::Index>(), any::<bool>(), arb_overrides()),
+                1..10,
+            ),
+            roles in proptest::collection::vec(
+                (prop_oneof!["admin", "member", "guest"], 0u64..16),
+                0..3,
+            ),
+        ) {
+            let channels: Vec<Channel> = channels
+                .into_iter()
+                .enumerate()
+                .map(|(i, (parent, root, role_permissions))| Channel {
+                    parent_id: (i > 0 && !root)
+                        .then(|| ChannelId::new(parent.index(i) as u16 + 1).unwrap()),
+                    role_permissions,
+                    ..create_test_channel(i as u16 + 1)
+                })
+                .collect();
+            let roles: Vec<Role> = roles
+                .into_iter()
+                .map(|(id, bits)| {
+                    Role::new(id.clone(), id).with_permissions(Permissions::from_bits_retain(bits))
+                })
+                .collect();
+            let get = |id: ChannelId| channels.get(usize::from(id.get()) - 1).cloned();
+
+            for channel in &channels {
+                prop_assert_eq!(
+                    channel.compute_user_permissions(&roles, get).unwrap(),
+                    recursive_permissions(channel, &roles, &get)
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,97 @@
+//! Injectable time source for time-dependent logic.
+//!
+//! Rate limiters and activity tracking ask a [`Clock`] for the time rather
+//! than calling [`Instant::now`] themselves, so tests can swap in a
+//! [`ManualClock`] and step time forward instead of sleeping. Timers that
+//! run on tokio, such as heartbeats, use `tokio::time` instead and are
+//! tested with `tokio::time::pause`; see `fleet_test_support::time`.
+//!
+//! # Examples
+//!
+//! ```
+//! use fleet_net_common::clock::{Clock, ManualClock};
+//! use std::time::Duration;
+//!
+//! let clock = ManualClock::new();
+//! let start = clock.now();
+//! clock.advance(Duration::from_secs(30));
+//! assert_eq!(clock.now() - start, Duration::from_secs(30));
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The real monotonic clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The clock used when none is injected.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one and hand another to
+/// the code under test.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<Instant>>);
+
+impl ManualClock {
+    /// A clock stopped at the current time.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+
+    /// This clock, for injecting into the code under test.
+    pub fn shared(&self) -> Arc<dyn Clock> {
+        Arc::new(self.clone())
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_moves_only_when_advanced() {
+        let clock = ManualClock::new();
+        let shared = clock.shared();
+        let start = shared.now();
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(shared.now(), start);
+
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!(shared.now() - start, Duration::from_millis(1_500));
+        assert!(SystemClock.now() >= start);
+    }
+}
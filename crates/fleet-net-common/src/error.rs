@@ -2,8 +2,16 @@
 //!
 //! This module defines the common error types used throughout the Fleet Net system.
 //! All errors implement the standard `Error` trait and provide human-readable messages.
+//!
+//! Errors sent to the other side of a connection carry a [`FleetNetErrorCode`]
+//! as well, so the receiver can branch on the kind of failure and show a
+//! localized message instead of parsing English text.
 
+use crate::validation::{Constraint, FieldErrors};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
 use thiserror::Error;
 
 /// Central error type for all Fleet Net operations.
@@ -85,6 +93,196 @@ pub enum FleetNetError {
 
     #[error("Encryption error: {0}")]
     EncryptionError(Cow<'static, str>),
+
+    /// Requests rejected because their content is invalid.
+    ///
+    /// Lists each offending field and the constraint it broke.
+    /// This variant covers:
+    /// - Out-of-range or oversized fields
+    /// - Requests that reference unknown entities
+    /// - Operations that are not meaningful, such as self-reports
+    #[error("Validation error: {0}")]
+    ValidationError(FieldErrors),
+
+    /// Requests that are not allowed in the session's current state.
+    ///
+    /// This variant covers:
+    /// - Sending audio or joining channels before authenticating
+    /// - Illegal lifecycle transitions, such as resuming a disconnecting session
+    #[error("Session state error: {0}")]
+    SessionStateError(Cow<'static, str>),
+
+    /// Requests refused because the sender made too many of them recently.
+    #[error("Rate limited: {0}")]
+    RateLimited(Cow<'static, str>),
+}
+
+impl FleetNetError {
+    /// A validation error for a single field.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::error::FleetNetError;
+    /// use fleet_net_common::validation::Constraint;
+    ///
+    /// let err = FleetNetError::invalid_field("context", Constraint::TooLong(1000));
+    /// assert_eq!(err.to_string(), "Validation error: context: too_long(1000)");
+    /// ```
+    pub fn invalid_field(field: impl Into<Cow<'static, str>>, constraint: Constraint) -> Self {
+        FleetNetError::ValidationError(FieldErrors::single(field, constraint))
+    }
+
+    /// The machine-readable code reported for this error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::borrow::Cow;
+    /// use fleet_net_common::error::{FleetNetError, FleetNetErrorCode};
+    ///
+    /// let err = FleetNetError::PermissionError(Cow::Borrowed("Missing permission to speak"));
+    /// assert_eq!(err.code(), FleetNetErrorCode::NoPermission);
+    /// ```
+    pub fn code(&self) -> FleetNetErrorCode {
+        match self {
+            FleetNetError::NetworkError(_) => FleetNetErrorCode::NetworkError,
+            FleetNetError::AudioError(_) => FleetNetErrorCode::AudioError,
+            FleetNetError::PacketError(_) => FleetNetErrorCode::MalformedPacket,
+            FleetNetError::JsonError(_) => FleetNetErrorCode::MalformedMessage,
+            FleetNetError::AuthError(_) => FleetNetErrorCode::AuthFailed,
+            FleetNetError::PermissionError(_) => FleetNetErrorCode::NoPermission,
+            FleetNetError::FileSystemError(_) => FleetNetErrorCode::InternalError,
+            FleetNetError::EncryptionError(_) => FleetNetErrorCode::EncryptionError,
+            FleetNetError::ValidationError(_) => FleetNetErrorCode::InvalidRequest,
+            FleetNetError::SessionStateError(_) => FleetNetErrorCode::InvalidState,
+            FleetNetError::RateLimited(_) => FleetNetErrorCode::RateLimited,
+        }
+    }
+}
+
+macro_rules! error_codes {
+    ($($(#[$meta:meta])* $variant:ident = $number:literal, $name:literal, $key:literal;)*) => {
+        /// Stable, machine-readable error codes.
+        ///
+        /// Each code has a number and a SCREAMING_SNAKE_CASE name, neither of
+        /// which ever changes meaning. Codes are serialized by name; names
+        /// this build does not know deserialize as [`Unknown`](Self::Unknown)
+        /// so newer servers can add codes without breaking older clients.
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// use fleet_net_common::error::FleetNetErrorCode;
+        ///
+        /// let code: FleetNetErrorCode = "CHANNEL_FULL".parse().unwrap();
+        /// assert_eq!(code, FleetNetErrorCode::ChannelFull);
+        /// assert_eq!(code.number(), 3001);
+        /// assert_eq!(FleetNetErrorCode::from_number(3001), Some(code));
+        /// assert_eq!(code.translation_key(), "error-channel-full");
+        /// ```
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+        pub enum FleetNetErrorCode {
+            $($(#[$meta])* $variant,)*
+
+            /// A code added after this build.
+            #[serde(other)]
+            Unknown,
+        }
+
+        impl FleetNetErrorCode {
+            /// Every known code, excluding [`Unknown`](Self::Unknown).
+            pub const ALL: &'static [FleetNetErrorCode] = &[$(FleetNetErrorCode::$variant),*];
+
+            /// The stable numeric code; [`Unknown`](Self::Unknown) is 0.
+            pub const fn number(self) -> u16 {
+                match self {
+                    $(FleetNetErrorCode::$variant => $number,)*
+                    FleetNetErrorCode::Unknown => 0,
+                }
+            }
+
+            /// The stable name, as sent on the wire.
+            pub const fn as_str(self) -> &'static str {
+                match self {
+                    $(FleetNetErrorCode::$variant => $name,)*
+                    FleetNetErrorCode::Unknown => "UNKNOWN",
+                }
+            }
+
+            /// The key of this code's message in a
+            /// [`Catalog`](crate::i18n::Catalog), e.g. `error-channel-full`.
+            pub const fn translation_key(self) -> &'static str {
+                match self {
+                    $(FleetNetErrorCode::$variant => $key,)*
+                    FleetNetErrorCode::Unknown => "error-unknown",
+                }
+            }
+
+            /// The code numbered `number`, if it is known.
+            pub fn from_number(number: u16) -> Option<Self> {
+                Self::ALL.iter().copied().find(|code| code.number() == number)
+            }
+        }
+    };
+}
+
+error_codes! {
+    /// The connection failed or timed out.
+    NetworkError = 1000, "NETWORK_ERROR", "error-network-error";
+    /// A voice packet could not be parsed or failed its integrity check.
+    MalformedPacket = 1001, "MALFORMED_PACKET", "error-malformed-packet";
+    /// A control message could not be parsed.
+    MalformedMessage = 1002, "MALFORMED_MESSAGE", "error-malformed-message";
+    EncryptionError = 1003, "ENCRYPTION_ERROR", "error-encryption-error";
+    /// Too many requests in a short time.
+    RateLimited = 1004, "RATE_LIMITED", "error-rate-limited";
+
+    /// The credentials were rejected.
+    AuthFailed = 2000, "AUTH_FAILED", "error-auth-failed";
+    /// The client is older than the server accepts.
+    ClientOutdated = 2001, "CLIENT_OUTDATED", "error-client-outdated";
+    /// The user is banned from the server.
+    Banned = 2002, "BANNED", "error-banned";
+    /// The server has reached its user limit.
+    ServerFull = 2003, "SERVER_FULL", "error-server-full";
+
+    /// The user lacks the permission the request needs.
+    NoPermission = 3000, "NO_PERMISSION", "error-no-permission";
+    /// The channel has reached its user limit.
+    ChannelFull = 3001, "CHANNEL_FULL", "error-channel-full";
+    /// The user is server-muted.
+    Muted = 3002, "MUTED", "error-muted";
+
+    /// The request was malformed or not meaningful.
+    InvalidRequest = 4000, "INVALID_REQUEST", "error-invalid-request";
+    ChannelNotFound = 4001, "CHANNEL_NOT_FOUND", "error-channel-not-found";
+    UserNotFound = 4002, "USER_NOT_FOUND", "error-user-not-found";
+    /// The request is not allowed yet, e.g. before authenticating.
+    InvalidState = 4003, "INVALID_STATE", "error-invalid-state";
+
+    AudioError = 5000, "AUDIO_ERROR", "error-audio-error";
+    /// An unexpected failure on the other side.
+    InternalError = 5001, "INTERNAL_ERROR", "error-internal-error";
+}
+
+impl fmt::Display for FleetNetErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FleetNetErrorCode {
+    type Err = FleetNetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|code| code.as_str() == s)
+            .ok_or_else(|| FleetNetError::invalid_field("code", Constraint::NotFound))
+    }
 }
 
 impl From<serde_json::Error> for FleetNetError {
@@ -98,3 +296,29 @@ impl From<std::io::Error> for FleetNetError {
         FleetNetError::NetworkError(Cow::Owned(err.to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_error_codes_are_unique_and_round_trip() {
+        let numbers: HashSet<u16> = FleetNetErrorCode::ALL.iter().map(|c| c.number()).collect();
+        assert_eq!(numbers.len(), FleetNetErrorCode::ALL.len());
+
+        for &code in FleetNetErrorCode::ALL {
+            let json = serde_json::to_string(&code).unwrap();
+            assert_eq!(json, format!("\"{code}\""));
+            assert_eq!(
+                serde_json::from_str::<FleetNetErrorCode>(&json).unwrap(),
+                code
+            );
+            assert_eq!(code.as_str().parse::<FleetNetErrorCode>().unwrap(), code);
+        }
+
+        // Codes from newer peers are not an error
+        let unknown: FleetNetErrorCode = serde_json::from_str("\"SOLAR_FLARE\"").unwrap();
+        assert_eq!(unknown, FleetNetErrorCode::Unknown);
+    }
+}
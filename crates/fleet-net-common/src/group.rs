@@ -0,0 +1,185 @@
+//! Groups of users, such as fireteams or squads.
+//!
+//! Users create and join groups to talk to each other without a channel of
+//! their own: a group is a quick whisper target and lets the server apply
+//! squad-based routing rules. A user is in at most one group at a time.
+
+use crate::error::FleetNetError;
+use crate::limits::ServerLimits;
+use crate::types::{GroupId, UserId};
+use crate::validation::{Constraint, FieldErrors, Validate};
+use serde::{Deserialize, Serialize};
+
+pub use crate::limits::{MAX_GROUP_NAME_LEN, MAX_GROUP_SIZE};
+
+/// A group and its members.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::group::Group;
+/// use fleet_net_common::types::{GroupId, UserId};
+///
+/// let lead = UserId::new(1).unwrap();
+/// let wingman = UserId::new(2).unwrap();
+/// let mut group = Group::new(GroupId::new(1).unwrap(), "Viper".to_string(), lead);
+/// group.add_member(wingman);
+///
+/// // The longest-standing member takes over when the leader leaves
+/// group.remove_member(lead);
+/// assert_eq!(group.leader, wingman);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    pub id: GroupId,
+
+    pub name: String,
+
+    pub leader: UserId,
+
+    /// Members in the order they joined, the leader included.
+    pub members: Vec<UserId>,
+}
+
+impl Group {
+    /// A group with `leader` as its only member.
+    pub fn new(id: GroupId, name: String, leader: UserId) -> Self {
+        Self {
+            id,
+            name,
+            leader,
+            members: vec![leader],
+        }
+    }
+
+    /// Checks a requested group name: 1 to [`MAX_GROUP_NAME_LEN`] bytes,
+    /// with no surrounding whitespace or control characters.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error for the `name` field.
+    pub fn validate_name(name: &str) -> Result<(), FleetNetError> {
+        let mut errors = FieldErrors::new();
+        Self::check_name(&mut errors, name);
+        errors.into_result()
+    }
+
+    /// Records any problem with a requested group name in `errors`, see
+    /// [`Group::validate_name`].
+    pub fn check_name(errors: &mut FieldErrors, name: &str) {
+        if name.is_empty() || name.len() > MAX_GROUP_NAME_LEN {
+            errors.check_length("name", name, 1, MAX_GROUP_NAME_LEN);
+        } else if name.trim() != name || name.chars().any(char::is_control) {
+            errors.add("name", Constraint::InvalidFormat);
+        }
+    }
+
+    pub fn is_member(&self, user_id: UserId) -> bool {
+        self.members.contains(&user_id)
+    }
+
+    /// Whether the group has room for no one else under `limits`.
+    pub fn is_full(&self, limits: &ServerLimits) -> bool {
+        self.members.len() >= limits.max_group_size as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Adds `user_id` as the newest member. Returns whether they were added,
+    /// i.e. were not a member already.
+    pub fn add_member(&mut self, user_id: UserId) -> bool {
+        if self.is_member(user_id) {
+            return false;
+        }
+        self.members.push(user_id);
+        true
+    }
+
+    /// Removes `user_id`, handing the lead to the longest-standing member if
+    /// they led the group. Returns whether they were a member.
+    ///
+    /// The leader of an emptied group is left as is; callers disband it.
+    pub fn remove_member(&mut self, user_id: UserId) -> bool {
+        let before = self.members.len();
+        self.members.retain(|&member| member != user_id);
+        if self.leader == user_id {
+            if let Some(&next) = self.members.first() {
+                self.leader = next;
+            }
+        }
+        self.members.len() != before
+    }
+
+    /// Everyone in the group except `user_id`, e.g. to whisper to.
+    pub fn others(&self, user_id: UserId) -> impl Iterator<Item = UserId> + '_ {
+        self.members
+            .iter()
+            .copied()
+            .filter(move |&member| member != user_id)
+    }
+}
+
+/// Checks the name and that the group is within `limits.max_group_size`.
+impl Validate for Group {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        Group::check_name(errors, &self.name);
+        if self.members.len() > limits.max_group_size as usize {
+            errors.add(
+                "members",
+                Constraint::TooLong(limits.max_group_size as usize),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    #[test]
+    fn test_group_membership_and_leadership() {
+        let limits = ServerLimits {
+            max_group_size: 3,
+            ..ServerLimits::default()
+        };
+        let mut group = Group::new(GroupId::new(1).unwrap(), "Viper".to_string(), user(1));
+        assert!(group.add_member(user(2)));
+        assert!(!group.add_member(user(2)));
+        assert!(!group.is_full(&limits));
+        assert!(group.add_member(user(3)));
+        assert!(group.is_full(&limits));
+        assert_eq!(
+            group.others(user(2)).collect::<Vec<_>>(),
+            [user(1), user(3)]
+        );
+
+        assert!(group.remove_member(user(1)));
+        assert_eq!(group.leader, user(2));
+        assert!(!group.remove_member(user(1)));
+        group.remove_member(user(2));
+        group.remove_member(user(3));
+        assert!(group.is_empty());
+    }
+
+    #[test]
+    fn test_group_name_validation() {
+        assert!(Group::validate_name("Viper 1").is_ok());
+        for invalid in [
+            "",
+            " Viper",
+            "Viper\t1",
+            &"x".repeat(MAX_GROUP_NAME_LEN + 1),
+        ] {
+            assert!(
+                Group::validate_name(invalid).is_err(),
+                "{invalid:?} should be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Localized user-facing text.
+//!
+//! Error messages, disconnect reasons and the message of the day are looked
+//! up by key in a [`Catalog`] for the user's locale. Catalogs use the simple
+//! message subset of [Fluent](https://projectfluent.org): one `key = text`
+//! per line, `#` comments, and `{ $name }` placeholders, so translators can
+//! work with Fluent tooling.
+//!
+//! Every [`FleetNetErrorCode`] carries the key of its message, so a client
+//! shows a server's error in its user's language instead of the English
+//! detail sent with it. The English catalog is built in and is the fallback
+//! for keys other catalogs lack.
+
+use crate::error::{FleetNetError, FleetNetErrorCode};
+use crate::validation::{Constraint, FieldErrors};
+use std::collections::HashMap;
+
+/// The locale of the built-in catalog.
+pub const DEFAULT_LOCALE: &str = "en";
+
+const ENGLISH: &str = include_str!("../locales/en.ftl");
+
+/// The messages of one locale, by key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Catalog {
+    locale: String,
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Reads a catalog in the Fluent subset described in the module docs.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error naming every line that is neither blank,
+    /// a comment nor a `key = text` message.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::i18n::Catalog;
+    ///
+    /// let catalog = Catalog::parse("de", "error-banned = Du bist gebannt.").unwrap();
+    /// assert_eq!(catalog.get("error-banned"), Some("Du bist gebannt."));
+    /// assert!(Catalog::parse("de", "kein Schlüssel").is_err());
+    /// ```
+    pub fn parse(locale: impl Into<String>, source: &str) -> Result<Self, FleetNetError> {
+        let mut messages = HashMap::new();
+        let mut errors = FieldErrors::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, text)) if is_valid_key(key.trim()) => {
+                    messages.insert(key.trim().to_string(), text.trim().to_string());
+                }
+                _ => errors.add(format!("line {}", index + 1), Constraint::InvalidFormat),
+            }
+        }
+        errors.into_result()?;
+        Ok(Self {
+            locale: locale.into(),
+            messages,
+        })
+    }
+
+    /// The built-in English catalog.
+    pub fn english() -> Self {
+        Self::parse(DEFAULT_LOCALE, ENGLISH).expect("built-in catalog is well formed")
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// The untranslated text of `key`, placeholders and all.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+
+    /// The text of `key` with `args` filled in, see [`render`].
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> Option<String> {
+        self.get(key).map(|text| render(text, args))
+    }
+}
+
+/// Fluent identifiers: a letter followed by letters, digits, `-` or `_`.
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Fills the `{ $name }` placeholders in `template` from `args`.
+///
+/// Placeholders without a matching argument are left as they are, so a
+/// missing value shows up in the text rather than silently disappearing.
+/// Operators write message of the day templates in the same syntax.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::i18n::render;
+///
+/// let motd = render("Welcome to { $server }, { $user }!", &[("server", "JTF-2")]);
+/// assert_eq!(motd, "Welcome to JTF-2, { $user }!");
+/// ```
+pub fn render(template: &str, args: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let placeholder = &rest[..=end];
+        let value = placeholder[1..placeholder.len() - 1]
+            .trim()
+            .strip_prefix('$')
+            .and_then(|name| args.iter().find(|(arg, _)| *arg == name.trim()))
+            .map(|(_, value)| *value);
+        output.push_str(value.unwrap_or(placeholder));
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Catalogs for every locale a client or server ships, with English as the
+/// fallback.
+#[derive(Debug, Clone)]
+pub struct Localizer {
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Default for Localizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Localizer {
+    /// A localizer knowing only the built-in English catalog.
+    pub fn new() -> Self {
+        let english = Catalog::english();
+        Self {
+            catalogs: HashMap::from([(english.locale().to_string(), english)]),
+        }
+    }
+
+    /// Adds `catalog`, replacing any earlier one for the same locale.
+    pub fn add(&mut self, catalog: Catalog) {
+        self.catalogs.insert(catalog.locale().to_string(), catalog);
+    }
+
+    /// The locales with a catalog, in no particular order.
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        self.catalogs.keys().map(String::as_str)
+    }
+
+    /// The text of `key` in `locale` with `args` filled in.
+    ///
+    /// Falls back from a regional locale such as `pt-BR` to its language,
+    /// then to English, and finally to the key itself so a missing
+    /// translation is visible but never fatal.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::i18n::{Catalog, Localizer};
+    ///
+    /// let mut localizer = Localizer::new();
+    /// localizer.add(Catalog::parse("fr", "error-server-full = Le serveur est plein.").unwrap());
+    ///
+    /// assert_eq!(localizer.translate("fr-CA", "error-server-full", &[]), "Le serveur est plein.");
+    /// assert_eq!(localizer.translate("fr", "error-banned", &[]), "You are banned from this server.");
+    /// ```
+    pub fn translate(&self, locale: &str, key: &str, args: &[(&str, &str)]) -> String {
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+        [locale, language, DEFAULT_LOCALE]
+            .into_iter()
+            .filter_map(|locale| self.catalogs.get(locale))
+            .find_map(|catalog| catalog.format(key, args))
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    /// The message for an error `code` in `locale`.
+    pub fn error(&self, locale: &str, code: FleetNetErrorCode) -> String {
+        self.translate(locale, code.translation_key(), &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_error_code_has_english_text() {
+        let english = Catalog::english();
+        for &code in FleetNetErrorCode::ALL
+            .iter()
+            .chain([FleetNetErrorCode::Unknown].iter())
+        {
+            assert!(
+                english.get(code.translation_key()).is_some(),
+                "{code} has no English text"
+            );
+        }
+    }
+
+    #[test]
+    fn test_catalog_parsing_and_rendering() {
+        let source = "# comment\n\nmotd = Hallo { $user }!\nbroken\n= no key\n";
+        let err = Catalog::parse("de", source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: line 4: invalid_format; line 5: invalid_format"
+        );
+
+        let catalog = Catalog::parse("de", "motd = Hallo {$user}, { $user }!").unwrap();
+        assert_eq!(
+            catalog.format("motd", &[("user", "Viper")]).unwrap(),
+            "Hallo Viper, Viper!"
+        );
+
+        // Unbalanced and non-variable braces pass through
+        assert_eq!(render("{ literal } and {", &[]), "{ literal } and {");
+    }
+
+    #[test]
+    fn test_missing_translations_fall_back() {
+        let localizer = Localizer::new();
+        assert_eq!(
+            localizer.error("de", FleetNetErrorCode::ServerFull),
+            "The server is full."
+        );
+        assert_eq!(localizer.translate("de", "no-such-key", &[]), "no-such-key");
+    }
+}
@@ -14,43 +14,71 @@
 //!
 //! # Module Organization
 //!
+//! - `arbitrary` - Proptest strategies, with the `proptest` feature
 //! - `audio` - Audio state management for users
+//! - `audit` - Audit log entries and queries
 //! - `channel` - Channel structures and permission resolution
+//! - `clock` - Injectable time source for tests
 //! - `error` - Common error types
+//! - `group` - Groups such as fireteams
+//! - `i18n` - Localized user-facing text
+//! - `limits` - Size and count limits shared by client and server
 //! - `logging` - Logging configuration utilities
 //! - `permission` - Permission system with bitflags
+//! - `permission_cache` - Cache of resolved channel permissions
+//! - `restriction` - Timed mutes and bans
 //! - `role` - Role-based access control
+//! - `secret` - Constant-time comparison of tokens and secrets
 //! - `session` - User session management
-//! - `types` - Core type aliases
-//! - `user` - User representation with Discord integration
+//! - `srs` - Interop with the DCS-SRS game radio export
+//! - `teamspeak` - Importing the structure of a TeamSpeak 3 server
+//! - `template` - Channel and role setups for export and import
+//! - `types` - User and channel identifiers
+//! - `user` - User representation with Discord integration and presence
+//! - `validation` - Field-level validation errors
 //!
 //! # Examples
 //!
 //! ```
-//! use fleet_net_common::{User, Role, PermissionSet, permissions};
+//! use fleet_net_common::{User, Role, PermissionSet, Permissions};
+//! use fleet_net_common::types::UserId;
 //!
 //! // Create a new user
-//! let user = User::new(123);
+//! let user = User::new(UserId::new(123).unwrap());
 //!
 //! // Create a role with permissions
 //! let admin_role = Role::new("admin".to_string(), "Administrator".to_string())
-//!     .with_permissions(permissions::ADMINISTRATOR);
+//!     .with_permissions(Permissions::ADMINISTRATOR);
 //! ```
 
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
 pub mod audio;
+pub mod audit;
 pub mod channel;
+pub mod clock;
 pub mod error;
+pub mod group;
+pub mod i18n;
+pub mod limits;
 pub mod logging;
 pub mod permission;
+pub mod permission_cache;
+pub mod restriction;
 pub mod role;
+pub mod secret;
 pub mod session;
+pub mod srs;
+pub mod teamspeak;
+pub mod template;
 pub mod types;
 pub mod user;
+pub mod validation;
 
 // Re-export commonly used types for convenience
 pub use audio::UserAudioState;
 pub use channel::{Channel, ChannelPermissions, ChannelType};
-pub use permission::{permissions, PermissionSet};
+pub use permission::{PermissionSet, Permissions};
 pub use role::Role;
-pub use session::{Session, SessionState};
-pub use user::{DiscordUser, User};
+pub use session::{Session, SessionSnapshot, SessionState};
+pub use user::{DiscordUser, Presence, User};
@@ -0,0 +1,169 @@
+//! Size and count limits.
+//!
+//! Every limit a server enforces has a default here, and [`ServerLimits`]
+//! bundles the ones an operator can change. Every
+//! [`Validate`](crate::validation::Validate) implementation checks against a
+//! [`ServerLimits`], and servers send theirs to
+//! clients in the `ServerInfo` message so UIs can check input before sending
+//! it.
+
+use serde::{Deserialize, Serialize};
+
+/// Default maximum number of channels on a server.
+pub const MAX_CHANNELS: u32 = 1000;
+
+/// Default maximum number of radio channels a user can monitor at once.
+pub const MAX_SUBSCRIPTIONS: u32 = 16;
+
+/// Maximum length of a server name, in bytes.
+pub const MAX_SERVER_NAME_LEN: usize = 100;
+
+/// Maximum length of a region name, in bytes.
+pub const MAX_REGION_LEN: usize = 32;
+
+/// Maximum length of a message of the day template, in bytes.
+pub const MAX_MOTD_LEN: usize = 1024;
+
+/// Maximum length of a channel name, in bytes.
+pub const MAX_CHANNEL_NAME_LEN: usize = 100;
+
+/// Maximum length of a channel description, in bytes.
+pub const MAX_CHANNEL_DESCRIPTION_LEN: usize = 1024;
+
+/// Maximum length of a channel topic, in bytes.
+pub const MAX_CHANNEL_TOPIC_LEN: usize = 256;
+
+/// Maximum length of a channel icon identifier, in bytes.
+pub const MAX_CHANNEL_ICON_LEN: usize = 64;
+
+/// Maximum number of metadata entries on a channel.
+pub const MAX_CHANNEL_METADATA_ENTRIES: usize = 16;
+
+/// Maximum length of a channel metadata key, in bytes.
+pub const MAX_CHANNEL_METADATA_KEY_LEN: usize = 64;
+
+/// Maximum length of a channel metadata value, in bytes.
+pub const MAX_CHANNEL_METADATA_VALUE_LEN: usize = 256;
+
+/// Maximum length of a radio channel's simulated crypto key id, in bytes.
+pub const MAX_CRYPTO_KEY_ID_LEN: usize = 32;
+
+/// Highest frequency a radio channel can be tuned to, in hertz.
+pub const MAX_RADIO_FREQUENCY_HZ: u64 = 300_000_000_000;
+
+/// Default maximum number of members in a group.
+pub const MAX_GROUP_SIZE: u32 = 16;
+
+/// Maximum length of a group name, in bytes.
+pub const MAX_GROUP_NAME_LEN: usize = 32;
+
+/// Maximum number of roles on a server, including `@everyone`.
+pub const MAX_ROLES: usize = 256;
+
+/// Maximum length of a role id, in bytes.
+pub const MAX_ROLE_ID_LEN: usize = 64;
+
+/// Maximum length of a role name, in bytes.
+pub const MAX_ROLE_NAME_LEN: usize = 100;
+
+/// Lowest bitrate Opus encodes at, in bits per second.
+pub const MIN_OPUS_BITRATE: u32 = 6_000;
+
+/// Highest bitrate Opus encodes at, in bits per second.
+pub const MAX_OPUS_BITRATE: u32 = 510_000;
+
+/// Maximum length of a nickname, in bytes.
+pub const MAX_NICKNAME_LEN: usize = 32;
+
+/// Maximum length of the game name in an in-game presence, in bytes.
+pub const MAX_GAME_LEN: usize = 128;
+
+/// Maximum length of a moderation reason, in bytes.
+pub const MAX_REASON_LEN: usize = 500;
+
+/// Maximum length of an authentication token, in bytes.
+pub const MAX_TOKEN_LEN: usize = 4096;
+
+/// Maximum size of a single control message on the wire, in bytes.
+pub const MAX_CONTROL_MESSAGE_LEN: usize = 1024 * 1024;
+
+/// The limits a server enforces, sent to clients when they connect.
+///
+/// Fields missing from a peer's message take their defaults, so servers can
+/// add limits without breaking older clients.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::limits::ServerLimits;
+/// use fleet_net_common::user::User;
+///
+/// // A milsim unit allowing only short callsigns
+/// let limits = ServerLimits {
+///     max_nickname_len: 12,
+///     ..ServerLimits::default()
+/// };
+///
+/// assert!(User::validate_nickname("Viper 1-1", &limits).is_ok());
+/// assert!(User::validate_nickname("Viper 1-1 Lead", &limits).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerLimits {
+    /// Most users connected at once; `None` for no limit.
+    pub max_users: Option<u32>,
+    pub max_channels: u32,
+    pub max_subscriptions: u32,
+    pub max_group_size: u32,
+    pub max_server_name_len: usize,
+    pub max_channel_name_len: usize,
+    pub max_channel_description_len: usize,
+    pub max_channel_topic_len: usize,
+    pub max_nickname_len: usize,
+    pub max_game_len: usize,
+    pub max_reason_len: usize,
+    pub max_token_len: usize,
+    pub max_message_len: usize,
+}
+
+impl ServerLimits {
+    /// The limits used unless an operator configures others.
+    pub const DEFAULT: Self = Self {
+        max_users: None,
+        max_channels: MAX_CHANNELS,
+        max_subscriptions: MAX_SUBSCRIPTIONS,
+        max_group_size: MAX_GROUP_SIZE,
+        max_server_name_len: MAX_SERVER_NAME_LEN,
+        max_channel_name_len: MAX_CHANNEL_NAME_LEN,
+        max_channel_description_len: MAX_CHANNEL_DESCRIPTION_LEN,
+        max_channel_topic_len: MAX_CHANNEL_TOPIC_LEN,
+        max_nickname_len: MAX_NICKNAME_LEN,
+        max_game_len: MAX_GAME_LEN,
+        max_reason_len: MAX_REASON_LEN,
+        max_token_len: MAX_TOKEN_LEN,
+        max_message_len: MAX_CONTROL_MESSAGE_LEN,
+    };
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_fill_in_missing_fields() {
+        let limits: ServerLimits =
+            serde_json::from_str(r#"{"max_users":64,"max_nickname_len":16}"#).unwrap();
+        assert_eq!(limits.max_users, Some(64));
+        assert_eq!(limits.max_nickname_len, 16);
+        assert_eq!(limits.max_channel_name_len, MAX_CHANNEL_NAME_LEN);
+
+        let json = serde_json::to_value(ServerLimits::default()).unwrap();
+        assert_eq!(json["max_subscriptions"], MAX_SUBSCRIPTIONS);
+    }
+}
@@ -3,7 +3,18 @@
 //! This module provides centralized logging setup using the `tracing` ecosystem.
 //! It configures structured logging with appropriate filtering for debugging
 //! and production environments.
+//!
+//! Malformed input from peers is logged through a [`ViolationLog`], which
+//! samples per peer so a hostile client cannot flood the log.
 
+use crate::clock::{self, Clock};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
 use tracing_subscriber;
 
 /// Initializes the tracing/logging system for Fleet Net.
@@ -43,3 +54,206 @@ pub fn init_tracing() {
         .with_env_filter("fleet_net=debug")
         .init();
 }
+
+/// Violations logged in full per peer and window before the rest are only
+/// counted.
+pub const DEFAULT_VIOLATIONS_LOGGED: u32 = 5;
+
+/// How long a peer's violations are counted before its budget resets.
+pub const DEFAULT_VIOLATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Peers tracked at once; beyond this, violations from new peers are only
+/// counted in [`ViolationLog::untracked`].
+const MAX_TRACKED_PEERS: usize = 4096;
+
+/// What [`ViolationLog::record`] did with a violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampled {
+    /// Logged, along with how many of the peer's violations went unlogged
+    /// in its previous window.
+    Logged { suppressed: u64 },
+    /// Only counted.
+    Suppressed,
+}
+
+#[derive(Debug)]
+struct PeerViolations {
+    window_start: Instant,
+    logged: u32,
+    suppressed: u64,
+}
+
+/// Logs malformed packets and messages without letting a hostile peer flood
+/// the log.
+///
+/// Each peer gets a budget of fully logged violations per window; the rest
+/// are counted and the count is logged with the peer's next logged
+/// violation. Entries are structured, with `peer`, `kind` and `detail`
+/// fields, under the `fleet_net::violations` target so they can be filtered
+/// separately.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::logging::{Sampled, ViolationLog};
+/// use std::time::Duration;
+///
+/// let log = ViolationLog::new(2, Duration::from_secs(60));
+/// let peer = "203.0.113.7:5000";
+///
+/// assert_eq!(log.record(&peer, "MALFORMED_PACKET", &"bad header"), Sampled::Logged { suppressed: 0 });
+/// assert_eq!(log.record(&peer, "MALFORMED_PACKET", &"bad header"), Sampled::Logged { suppressed: 0 });
+/// assert_eq!(log.record(&peer, "MALFORMED_PACKET", &"bad header"), Sampled::Suppressed);
+/// ```
+#[derive(Debug)]
+pub struct ViolationLog<K> {
+    logged_per_window: u32,
+    window: Duration,
+    peers: Mutex<HashMap<K, PeerViolations>>,
+    untracked: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+impl<K: Eq + Hash + Clone + fmt::Display> Default for ViolationLog<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIOLATIONS_LOGGED, DEFAULT_VIOLATION_WINDOW)
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Display> ViolationLog<K> {
+    pub fn new(logged_per_window: u32, window: Duration) -> Self {
+        Self {
+            logged_per_window,
+            window,
+            peers: Mutex::new(HashMap::new()),
+            untracked: AtomicU64::new(0),
+            clock: clock::system(),
+        }
+    }
+
+    /// Reads the time for [`ViolationLog::record`] from `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Records a `kind` of violation by `peer`, logging it if the peer's
+    /// budget allows.
+    pub fn record(&self, peer: &K, kind: &str, detail: &dyn fmt::Display) -> Sampled {
+        self.record_at(peer, kind, detail, self.clock.now())
+    }
+
+    /// [`ViolationLog::record`] at a given time.
+    pub fn record_at(
+        &self,
+        peer: &K,
+        kind: &str,
+        detail: &dyn fmt::Display,
+        now: Instant,
+    ) -> Sampled {
+        let mut peers = self.peers.lock().unwrap();
+        if !peers.contains_key(peer) && peers.len() >= MAX_TRACKED_PEERS {
+            peers.retain(|_, violations| now.duration_since(violations.window_start) < self.window);
+            if peers.len() >= MAX_TRACKED_PEERS {
+                self.untracked.fetch_add(1, Ordering::Relaxed);
+                return Sampled::Suppressed;
+            }
+        }
+        let violations = peers.entry(peer.clone()).or_insert(PeerViolations {
+            window_start: now,
+            logged: 0,
+            suppressed: 0,
+        });
+
+        let mut suppressed = 0;
+        if now.duration_since(violations.window_start) >= self.window {
+            suppressed = std::mem::take(&mut violations.suppressed);
+            violations.window_start = now;
+            violations.logged = 0;
+        }
+        if violations.logged >= self.logged_per_window {
+            violations.suppressed += 1;
+            return Sampled::Suppressed;
+        }
+        violations.logged += 1;
+        drop(peers);
+
+        warn!(
+            target: "fleet_net::violations",
+            peer = %peer,
+            kind,
+            detail = %detail,
+            suppressed,
+            "Protocol violation"
+        );
+        Sampled::Logged { suppressed }
+    }
+
+    /// Violations only counted because too many peers were being tracked.
+    pub fn untracked(&self) -> u64 {
+        self.untracked.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_violations_are_sampled_per_peer_and_window() {
+        let log = ViolationLog::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        let (noisy, quiet) = ("198.51.100.1:4000", "198.51.100.2:4000");
+
+        for _ in 0..2 {
+            assert!(matches!(
+                log.record_at(&noisy, "MALFORMED_PACKET", &"short", start),
+                Sampled::Logged { .. }
+            ));
+        }
+        for _ in 0..3 {
+            assert_eq!(
+                log.record_at(&noisy, "MALFORMED_PACKET", &"short", start),
+                Sampled::Suppressed
+            );
+        }
+
+        // One noisy peer does not use up another's budget
+        assert_eq!(
+            log.record_at(&quiet, "MALFORMED_MESSAGE", &"bad json", start),
+            Sampled::Logged { suppressed: 0 }
+        );
+
+        // The next window reports what was skipped
+        let later = start + Duration::from_secs(10);
+        assert_eq!(
+            log.record_at(&noisy, "MALFORMED_PACKET", &"short", later),
+            Sampled::Logged { suppressed: 3 }
+        );
+        assert_eq!(log.untracked(), 0);
+    }
+
+    #[test]
+    fn test_windows_follow_the_injected_clock() {
+        let clock = ManualClock::new();
+        let log = ViolationLog::new(1, Duration::from_secs(60)).with_clock(clock.shared());
+        let peer = "198.51.100.3:4000";
+
+        log.record(&peer, "MALFORMED_PACKET", &"short");
+        assert_eq!(
+            log.record(&peer, "MALFORMED_PACKET", &"short"),
+            Sampled::Suppressed
+        );
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(
+            log.record(&peer, "MALFORMED_PACKET", &"short"),
+            Sampled::Suppressed
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            log.record(&peer, "MALFORMED_PACKET", &"short"),
+            Sampled::Logged { suppressed: 2 }
+        );
+    }
+}
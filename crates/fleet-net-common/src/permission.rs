@@ -6,32 +6,339 @@
 //! # Architecture
 //!
 //! The permission system uses a 64-bit bitmask where each bit represents
-//! a specific permission. The ADMINISTRATOR permission (bit 63) acts as
-//! a special override that grants all permissions.
+//! a specific permission, named by the [`Permissions`] flags. The
+//! ADMINISTRATOR permission (bit 63) acts as a special override that grants
+//! all permissions.
+//!
+//! Bits without a name are preserved rather than dropped, so a server or
+//! client that predates a permission passes it through untouched.
+//!
+//! Every permission also has a snake_case name. [`Permissions`] are
+//! serialized as the list of their names, e.g. `["speak", "listen"]`, so
+//! permissions stay readable in control messages and config files; a raw
+//! bitmask is accepted when deserializing as well.
+
+use crate::error::FleetNetError;
+use crate::validation::Constraint;
+use bitflags::bitflags;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+
+macro_rules! permission_flags {
+    ($($(#[$($attr:tt)*])* $flag:ident = $bit:expr, $name:literal;)*) => {
+        bitflags! {
+            /// Permission flags for the Fleet Net system.
+            ///
+            /// Each permission is represented as a single bit in a 64-bit integer,
+            /// allowing for efficient permission checking and combination.
+            ///
+            /// # Permission Hierarchy
+            ///
+            /// - ADMINISTRATOR (bit 63) - Overrides all other permissions
+            /// - Management permissions - Control server structure (channels, roles, settings)
+            /// - Moderation permissions - Control users (kick, ban, mute, audit log)
+            /// - Basic permissions - Core functionality (connect, speak, listen, whisper)
+            ///
+            /// # Examples
+            ///
+            /// ```
+            /// use fleet_net_common::permission::Permissions;
+            ///
+            /// let perms = Permissions::SPEAK | Permissions::LISTEN;
+            /// assert!(perms.contains(Permissions::SPEAK));
+            /// assert_eq!(perms.to_string(), "speak | listen");
+            ///
+            /// // Bits this build has no name for are kept
+            /// let future = Permissions::from_bits_retain(1 << 40);
+            /// assert_eq!((perms | future).bits(), 0b110 | 1 << 40);
+            /// ```
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+            pub struct Permissions: u64 {
+                $($(#[$($attr)*])* const $flag = $bit;)*
+
+                // Every other bit is known too, so unnamed bits survive
+                // truncation and complement.
+                const _ = !0;
+            }
+        }
+
+        impl Permissions {
+            /// Every named permission with its snake_case name, lowest bit first.
+            ///
+            /// Admin UIs list this to show every permission the build knows about.
+            pub const NAMED: &'static [(&'static str, Permissions)] =
+                &[$(($name, Permissions::$flag)),*];
+        }
+    };
+}
+
+permission_flags! {
+    /// Allows connecting to the server.
+    /// This is the most basic permission required for any interaction.
+    CONNECT = 1 << 0, "connect";
+
+    /// Allows transmitting audio in voice channels.
+    /// Users without this permission can still listen but cannot speak.
+    SPEAK = 1 << 1, "speak";
+
+    /// Allows receiving audio in voice channels.
+    /// Users without this permission cannot hear others.
+    LISTEN = 1 << 2, "listen";
+
+    /// Allows moving other users between voice channels.
+    /// This is a moderation permission typically granted to staff.
+    MOVE_USERS = 1 << 3, "move_users";
+
+    /// Allows server-muting other users.
+    /// Muted users cannot transmit audio regardless of their SPEAK permission.
+    MUTE_USERS = 1 << 4, "mute_users";
+
+    /// Allows removing users from the server temporarily.
+    /// Kicked users can rejoin unless also banned.
+    KICK_USERS = 1 << 5, "kick_users";
+
+    /// Allows permanently banning users from the server.
+    /// Banned users cannot rejoin until unbanned.
+    BAN_USERS = 1 << 6, "ban_users";
+
+    /// Allows creating, modifying, and deleting channels.
+    /// This includes changing channel permissions and properties.
+    MANAGE_CHANNELS = 1 << 7, "manage_channels";
+
+    /// Allows creating, modifying, and deleting roles.
+    /// This includes changing role permissions and assignments.
+    MANAGE_ROLES = 1 << 8, "manage_roles";
+
+    /// Allows sending text messages in channels.
+    SEND_MESSAGES = 1 << 9, "send_messages";
+
+    /// Allows speaking privately to individual users or groups.
+    WHISPER = 1 << 10, "whisper";
+
+    /// Allows transmitting over other speakers, who are ducked while
+    /// a priority speaker talks.
+    PRIORITY_SPEAKER = 1 << 11, "priority_speaker";
+
+    /// Allows creating temporary channels, which are removed once empty.
+    CREATE_TEMP_CHANNELS = 1 << 12, "create_temp_channels";
+
+    /// Allows recording audio in channels.
+    /// Other users are told when a recording is running.
+    RECORD = 1 << 13, "record";
+
+    /// Allows changing server-wide settings such as limits and the welcome message.
+    MANAGE_SERVER = 1 << 14, "manage_server";
+
+    /// Allows reading the audit log of moderation and management actions.
+    VIEW_AUDIT_LOG = 1 << 15, "view_audit_log";
+
+    /// Allows users to move themselves between channels.
+    /// Without it, only users with MOVE_USERS can move them.
+    MOVE_SELF = 1 << 16, "move_self";
+
+    /// Allows subscribing to radio channels to monitor them.
+    SUBSCRIBE_RADIO = 1 << 17, "subscribe_radio";
+
+    /// Allows users to set their own nickname on this server.
+    CHANGE_NICKNAME = 1 << 18, "change_nickname";
 
-/// A set of permissions represented as a bitmask.
+    /// Allows transmitting on several channels at once, e.g. keying two
+    /// radios to relay an order to both nets.
+    SIMULCAST = 1 << 19, "simulcast";
+
+    /// Master permission that grants all capabilities.
+    /// Users with this permission bypass all permission checks.
+    ADMINISTRATOR = 1 << 63, "administrator";
+}
+
+impl Permissions {
+    /// The name of a single permission bit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    ///
+    /// assert_eq!(Permissions::MOVE_USERS.name(), Some("move_users"));
+    /// assert_eq!((Permissions::SPEAK | Permissions::LISTEN).name(), None);
+    /// ```
+    pub fn name(self) -> Option<&'static str> {
+        Self::NAMED
+            .iter()
+            .find(|&&(_, flag)| flag == self)
+            .map(|&(name, _)| name)
+    }
+
+    /// The permission bit called `name`, also accepting `bit<N>` for
+    /// bits without a name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    ///
+    /// assert_eq!(Permissions::parse_name("speak"), Some(Permissions::SPEAK));
+    /// assert_eq!(Permissions::parse_name("bit40").map(|p| p.bits()), Some(1 << 40));
+    /// assert_eq!(Permissions::parse_name("fly"), None);
+    /// ```
+    pub fn parse_name(name: &str) -> Option<Self> {
+        if let Some(&(_, flag)) = Self::NAMED.iter().find(|&&(named, _)| named == name) {
+            return Some(flag);
+        }
+        let bit: u32 = name.strip_prefix("bit")?.parse().ok()?;
+        (bit < u64::BITS).then(|| Self::from_bits_retain(1 << bit))
+    }
+
+    /// Iterates over the individual bits set, lowest first, including
+    /// bits without a name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    ///
+    /// let perms = Permissions::LISTEN | Permissions::CONNECT;
+    /// let set: Vec<Permissions> = perms.iter_bits().collect();
+    /// assert_eq!(set, vec![Permissions::CONNECT, Permissions::LISTEN]);
+    /// ```
+    pub fn iter_bits(self) -> impl Iterator<Item = Self> {
+        (0..u64::BITS)
+            .map(|bit| Self::from_bits_retain(1 << bit))
+            .filter(move |&flag| self.contains(flag))
+    }
+
+    /// Names of the permissions set, lowest bit first.
+    ///
+    /// Bits without a name are listed as `bit<N>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    ///
+    /// let perms = Permissions::SPEAK | Permissions::from_bits_retain(1 << 40);
+    /// assert_eq!(perms.names(), vec!["speak", "bit40"]);
+    /// ```
+    pub fn names(self) -> Vec<Cow<'static, str>> {
+        self.iter_bits()
+            .map(|flag| match flag.name() {
+                Some(name) => Cow::Borrowed(name),
+                None => Cow::Owned(format!("bit{}", flag.bits().trailing_zeros())),
+            })
+            .collect()
+    }
+
+    /// Builds a set from permission names, as produced by [`names`](Self::names).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    ///
+    /// let perms = Permissions::from_names(["speak", "listen"]).unwrap();
+    /// assert_eq!(perms, Permissions::SPEAK | Permissions::LISTEN);
+    /// assert!(Permissions::from_names(["fly"]).is_err());
+    /// ```
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, FleetNetError> {
+        names.into_iter().try_fold(Self::empty(), |set, name| {
+            let flag = Self::parse_name(name).ok_or_else(|| {
+                FleetNetError::invalid_field(
+                    "permissions",
+                    Constraint::Invalid(Cow::Owned(format!("unknown({name})"))),
+                )
+            })?;
+            Ok(set | flag)
+        })
+    }
+}
+
+impl fmt::Display for Permissions {
+    /// Writes the permission names separated by ` | `, or `none`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        for (i, name) in self.names().iter().enumerate() {
+            if i > 0 {
+                f.write_str(" | ")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Permissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names = self.names();
+        let mut seq = serializer.serialize_seq(Some(names.len()))?;
+        for name in &names {
+            seq.serialize_element(name)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Permissions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PermissionVisitor;
+
+        impl<'de> Visitor<'de> for PermissionVisitor {
+            type Value = Permissions;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of permission names or a permission bitmask")
+            }
+
+            fn visit_u64<E: de::Error>(self, bits: u64) -> Result<Self::Value, E> {
+                Ok(Permissions::from_bits_retain(bits))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut set = Permissions::empty();
+                while let Some(name) = seq.next_element::<Cow<'de, str>>()? {
+                    set |= Permissions::parse_name(&name)
+                        .ok_or_else(|| de::Error::custom(format!("unknown permission `{name}`")))?;
+                }
+                Ok(set)
+            }
+        }
+
+        deserializer.deserialize_any(PermissionVisitor)
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// A user's effective permissions.
 ///
-/// PermissionSet provides methods to check, add, and remove permissions
-/// efficiently using bitwise operations. The ADMINISTRATOR permission
-/// acts as a master override.
+/// PermissionSet wraps [`Permissions`] with the checks used at runtime:
+/// the ADMINISTRATOR permission acts as a master override.
 ///
 /// # Examples
 ///
 /// ```
-/// use fleet_net_common::permission::{PermissionSet, permissions};
+/// use fleet_net_common::permission::{PermissionSet, Permissions};
 ///
 /// let mut perms = PermissionSet::new();
-/// perms.add(permissions::SPEAK);
-/// perms.add(permissions::LISTEN);
+/// perms.add(Permissions::SPEAK);
+/// perms.add(Permissions::LISTEN);
 ///
-/// assert!(perms.has(permissions::SPEAK));
-/// assert!(!perms.has(permissions::BAN_USERS));
+/// assert!(perms.has(Permissions::SPEAK));
+/// assert!(!perms.has(Permissions::BAN_USERS));
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct PermissionSet {
-    /// Bitmask representing the user's permissions.
-    /// Each bit corresponds to a specific permission defined in the permissions module.
-    permissions: u64,
+    /// The permissions granted to the user.
+    permissions: Permissions,
 }
 
 impl PermissionSet {
@@ -46,13 +353,14 @@ impl PermissionSet {
     /// assert_eq!(perms.has_any(&[]), false);
     /// ```
     pub fn new() -> Self {
-        Self { permissions: 0 }
+        Self {
+            permissions: Permissions::empty(),
+        }
     }
 
     /// Creates a PermissionSet from a raw bitmask.
     ///
-    /// This is useful for deserializing permissions or creating
-    /// a set with multiple permissions at once.
+    /// Bits without a name are kept, see [`Permissions::from_bits_retain`].
     ///
     /// # Arguments
     ///
@@ -61,14 +369,35 @@ impl PermissionSet {
     /// # Examples
     ///
     /// ```
-    /// use fleet_net_common::permission::{PermissionSet, permissions};
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
     ///
-    /// let perms = PermissionSet::from_bits(permissions::SPEAK | permissions::LISTEN);
-    /// assert!(perms.has(permissions::SPEAK));
-    /// assert!(perms.has(permissions::LISTEN));
+    /// let perms = PermissionSet::from_bits(0b110);
+    /// assert!(perms.has(Permissions::SPEAK));
+    /// assert!(perms.has(Permissions::LISTEN));
     /// ```
     pub fn from_bits(permissions: u64) -> Self {
-        Self { permissions }
+        Self {
+            permissions: Permissions::from_bits_retain(permissions),
+        }
+    }
+
+    /// Returns the raw bitmask of this set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
+    ///
+    /// let perms = PermissionSet::from(Permissions::SPEAK);
+    /// assert_eq!(perms.bits(), Permissions::SPEAK.bits());
+    /// ```
+    pub fn bits(&self) -> u64 {
+        self.permissions.bits()
+    }
+
+    /// Returns the permissions in this set, without expanding ADMINISTRATOR.
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
     }
 
     /// Adds a permission to the set.
@@ -82,18 +411,17 @@ impl PermissionSet {
     /// # Examples
     ///
     /// ```
-    /// use fleet_net_common::permission::{PermissionSet, permissions};
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
     ///
     /// let mut perms = PermissionSet::new();
-    /// perms.add(permissions::CONNECT);
-    /// perms.add(permissions::SPEAK | permissions::LISTEN);
+    /// perms.add(Permissions::CONNECT);
+    /// perms.add(Permissions::SPEAK | Permissions::LISTEN);
     ///
-    /// assert!(perms.has(permissions::CONNECT));
-    /// assert!(perms.has(permissions::SPEAK));
-    /// assert!(perms.has(permissions::LISTEN));
+    /// assert!(perms.has(Permissions::CONNECT));
+    /// assert!(perms.has(Permissions::SPEAK));
+    /// assert!(perms.has(Permissions::LISTEN));
     /// ```
-    pub fn add(&mut self, permission: u64) {
-        // Use bitwise OR to add permission bits
+    pub fn add(&mut self, permission: Permissions) {
         self.permissions |= permission;
     }
 
@@ -108,20 +436,19 @@ impl PermissionSet {
     /// # Examples
     ///
     /// ```
-    /// use fleet_net_common::permission::{PermissionSet, permissions};
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
     ///
-    /// let mut perms = PermissionSet::from_bits(
-    ///     permissions::CONNECT | permissions::SPEAK | permissions::LISTEN
+    /// let mut perms = PermissionSet::from(
+    ///     Permissions::CONNECT | Permissions::SPEAK | Permissions::LISTEN
     /// );
     ///
-    /// perms.remove(permissions::SPEAK);
-    /// assert!(perms.has(permissions::CONNECT));
-    /// assert!(!perms.has(permissions::SPEAK));
-    /// assert!(perms.has(permissions::LISTEN));
+    /// perms.remove(Permissions::SPEAK);
+    /// assert!(perms.has(Permissions::CONNECT));
+    /// assert!(!perms.has(Permissions::SPEAK));
+    /// assert!(perms.has(Permissions::LISTEN));
     /// ```
-    pub fn remove(&mut self, permission: u64) {
-        // Use bitwise AND with negation to remove permission bits
-        self.permissions &= !permission;
+    pub fn remove(&mut self, permission: Permissions) {
+        self.permissions.remove(permission);
     }
 
     /// Checks if the set contains a specific permission.
@@ -140,25 +467,25 @@ impl PermissionSet {
     /// # Examples
     ///
     /// ```
-    /// use fleet_net_common::permission::{PermissionSet, permissions};
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
     ///
     /// let mut perms = PermissionSet::new();
-    /// perms.add(permissions::SPEAK);
-    /// assert!(perms.has(permissions::SPEAK));
-    /// assert!(!perms.has(permissions::BAN_USERS));
+    /// perms.add(Permissions::SPEAK);
+    /// assert!(perms.has(Permissions::SPEAK));
+    /// assert!(!perms.has(Permissions::BAN_USERS));
     ///
     /// // Administrator overrides all
-    /// perms.add(permissions::ADMINISTRATOR);
-    /// assert!(perms.has(permissions::BAN_USERS));
+    /// perms.add(Permissions::ADMINISTRATOR);
+    /// assert!(perms.has(Permissions::BAN_USERS));
     /// ```
-    pub fn has(&self, permission: u64) -> bool {
+    pub fn has(&self, permission: Permissions) -> bool {
         // Administrator permission overrides all others.
-        if self.permissions & permissions::ADMINISTRATOR != 0 {
+        if self.permissions.contains(Permissions::ADMINISTRATOR) {
             return true;
         }
 
         // Check if the specific permission bit is set
-        self.permissions & permission != 0
+        self.permissions.intersects(permission)
     }
 
     /// Checks if the set contains all of the specified permissions.
@@ -174,17 +501,17 @@ impl PermissionSet {
     /// # Examples
     ///
     /// ```
-    /// use fleet_net_common::permission::{PermissionSet, permissions};
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
     ///
     /// let mut perms = PermissionSet::new();
-    /// perms.add(permissions::CONNECT);
-    /// perms.add(permissions::SPEAK);
-    /// perms.add(permissions::LISTEN);
+    /// perms.add(Permissions::CONNECT);
+    /// perms.add(Permissions::SPEAK);
+    /// perms.add(Permissions::LISTEN);
     ///
-    /// assert!(perms.has_all(&[permissions::CONNECT, permissions::SPEAK]));
-    /// assert!(!perms.has_all(&[permissions::CONNECT, permissions::BAN_USERS]));
+    /// assert!(perms.has_all(&[Permissions::CONNECT, Permissions::SPEAK]));
+    /// assert!(!perms.has_all(&[Permissions::CONNECT, Permissions::BAN_USERS]));
     /// ```
-    pub fn has_all(&self, permissions: &[u64]) -> bool {
+    pub fn has_all(&self, permissions: &[Permissions]) -> bool {
         // Check that every permission in the slice is present
         permissions.iter().all(|&p| self.has(p))
     }
@@ -202,100 +529,71 @@ impl PermissionSet {
     /// # Examples
     ///
     /// ```
-    /// use fleet_net_common::permission::{PermissionSet, permissions};
+    /// use fleet_net_common::permission::{PermissionSet, Permissions};
     ///
     /// let mut perms = PermissionSet::new();
-    /// perms.add(permissions::SPEAK);
+    /// perms.add(Permissions::SPEAK);
     ///
-    /// assert!(perms.has_any(&[permissions::SPEAK, permissions::BAN_USERS]));
-    /// assert!(!perms.has_any(&[permissions::MOVE_USERS, permissions::BAN_USERS]));
+    /// assert!(perms.has_any(&[Permissions::SPEAK, Permissions::BAN_USERS]));
+    /// assert!(!perms.has_any(&[Permissions::MOVE_USERS, Permissions::BAN_USERS]));
     /// ```
-    pub fn has_any(&self, permissions: &[u64]) -> bool {
+    pub fn has_any(&self, permissions: &[Permissions]) -> bool {
         // Check if any permission in the slice is present
         permissions.iter().any(|&p| self.has(p))
     }
-}
 
-impl Default for PermissionSet {
-    fn default() -> Self {
-        Self::new()
+    /// Iterates over the individual permission bits set, lowest first.
+    ///
+    /// Unlike [`has`](Self::has), ADMINISTRATOR is not expanded.
+    pub fn iter(&self) -> impl Iterator<Item = Permissions> {
+        self.permissions.iter_bits()
     }
-}
 
-/// Permission bit constants for the Fleet Net system.
-///
-/// Each permission is represented as a single bit in a 64-bit integer,
-/// allowing for efficient permission checking and combination.
-///
-/// # Permission Hierarchy
-///
-/// - ADMINISTRATOR (bit 63) - Overrides all other permissions
-/// - Management permissions - Control server structure (channels, roles)
-/// - Moderation permissions - Control users (kick, ban, mute)
-/// - Basic permissions - Core functionality (connect, speak, listen)
-pub mod permissions {
-    /// Allows connecting to the server.
-    /// This is the most basic permission required for any interaction.
-    pub const CONNECT: u64 = 1 << 0;
-
-    /// Allows transmitting audio in voice channels.
-    /// Users without this permission can still listen but cannot speak.
-    pub const SPEAK: u64 = 1 << 1;
-
-    /// Allows receiving audio in voice channels.
-    /// Users without this permission cannot hear others.
-    pub const LISTEN: u64 = 1 << 2;
-
-    /// Allows moving other users between voice channels.
-    /// This is a moderation permission typically granted to staff.
-    pub const MOVE_USERS: u64 = 1 << 3;
-
-    /// Allows server-muting other users.
-    /// Muted users cannot transmit audio regardless of their SPEAK permission.
-    pub const MUTE_USERS: u64 = 1 << 4;
-
-    /// Allows removing users from the server temporarily.
-    /// Kicked users can rejoin unless also banned.
-    pub const KICK_USERS: u64 = 1 << 5;
-
-    /// Allows permanently banning users from the server.
-    /// Banned users cannot rejoin until unbanned.
-    pub const BAN_USERS: u64 = 1 << 6;
+    /// Names of the permissions set, see [`Permissions::names`].
+    pub fn names(&self) -> Vec<Cow<'static, str>> {
+        self.permissions.names()
+    }
 
-    /// Allows creating, modifying, and deleting channels.
-    /// This includes changing channel permissions and properties.
-    pub const MANAGE_CHANNELS: u64 = 1 << 7;
+    /// Builds a set from permission names, see [`Permissions::from_names`].
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, FleetNetError> {
+        Permissions::from_names(names).map(Self::from)
+    }
+}
 
-    /// Allows creating, modifying, and deleting roles.
-    /// This includes changing role permissions and assignments.
-    pub const MANAGE_ROLES: u64 = 1 << 8;
+impl From<Permissions> for PermissionSet {
+    fn from(permissions: Permissions) -> Self {
+        Self { permissions }
+    }
+}
 
-    /// Master permission that grants all capabilities.
-    /// Users with this permission bypass all permission checks.
-    pub const ADMINISTRATOR: u64 = 1 << 63;
+impl fmt::Display for PermissionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.permissions.fmt(f)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitflags::Flags;
 
     #[test]
     fn test_permission_set_new() {
         let perms = PermissionSet::new();
-        assert_eq!(perms.permissions, 0);
+        assert!(perms.permissions.is_empty());
 
         // Should not have any permissions initially
-        assert!(!perms.has(permissions::CONNECT));
-        assert!(!perms.has(permissions::SPEAK));
+        assert!(!perms.has(Permissions::CONNECT));
+        assert!(!perms.has(Permissions::SPEAK));
     }
 
     #[test]
     fn test_permission_set_from_bits() {
-        let perms = PermissionSet::from_bits(permissions::CONNECT | permissions::SPEAK);
+        let perms = PermissionSet::from(Permissions::CONNECT | Permissions::SPEAK);
 
-        assert!(perms.has(permissions::CONNECT));
-        assert!(perms.has(permissions::SPEAK));
-        assert!(!perms.has(permissions::LISTEN));
+        assert!(perms.has(Permissions::CONNECT));
+        assert!(perms.has(Permissions::SPEAK));
+        assert!(!perms.has(Permissions::LISTEN));
     }
 
     #[test]
@@ -303,72 +601,127 @@ mod tests {
         let mut perms = PermissionSet::new();
 
         // Add permissions
-        perms.add(permissions::CONNECT);
-        perms.add(permissions::SPEAK);
-        perms.add(permissions::LISTEN);
+        perms.add(Permissions::CONNECT);
+        perms.add(Permissions::SPEAK);
+        perms.add(Permissions::LISTEN);
 
-        assert!(perms.has(permissions::CONNECT));
-        assert!(perms.has(permissions::SPEAK));
-        assert!(perms.has(permissions::LISTEN));
+        assert!(perms.has(Permissions::CONNECT));
+        assert!(perms.has(Permissions::SPEAK));
+        assert!(perms.has(Permissions::LISTEN));
 
         // Remove a permission
-        perms.remove(permissions::SPEAK);
-        assert!(perms.has(permissions::CONNECT));
-        assert!(!perms.has(permissions::SPEAK));
-        assert!(perms.has(permissions::LISTEN));
+        perms.remove(Permissions::SPEAK);
+        assert!(perms.has(Permissions::CONNECT));
+        assert!(!perms.has(Permissions::SPEAK));
+        assert!(perms.has(Permissions::LISTEN));
     }
 
     #[test]
     fn test_administrator_overrides_all() {
         let mut perms = PermissionSet::new();
-        perms.add(permissions::ADMINISTRATOR);
+        perms.add(Permissions::ADMINISTRATOR);
 
         // Administrator should have all permissions
-        assert!(perms.has(permissions::CONNECT));
-        assert!(perms.has(permissions::SPEAK));
-        assert!(perms.has(permissions::LISTEN));
-        assert!(perms.has(permissions::MANAGE_CHANNELS));
-        assert!(perms.has(permissions::BAN_USERS));
+        assert!(perms.has(Permissions::CONNECT));
+        assert!(perms.has(Permissions::SPEAK));
+        assert!(perms.has(Permissions::LISTEN));
+        assert!(perms.has(Permissions::MANAGE_CHANNELS));
+        assert!(perms.has(Permissions::BAN_USERS));
     }
 
     #[test]
     fn test_has_all_permissions() {
         let mut perms = PermissionSet::new();
-        perms.add(permissions::CONNECT);
-        perms.add(permissions::SPEAK);
-        perms.add(permissions::LISTEN);
+        perms.add(Permissions::CONNECT);
+        perms.add(Permissions::SPEAK);
+        perms.add(Permissions::LISTEN);
 
         // Should have all specified permissions
-        assert!(perms.has_all(&[permissions::CONNECT, permissions::SPEAK]));
+        assert!(perms.has_all(&[Permissions::CONNECT, Permissions::SPEAK]));
         assert!(perms.has_all(&[
-            permissions::CONNECT,
-            permissions::SPEAK,
-            permissions::LISTEN
+            Permissions::CONNECT,
+            Permissions::SPEAK,
+            Permissions::LISTEN
         ]));
 
         // Should not have all if missing one
         assert!(!perms.has_all(&[
-            permissions::CONNECT,
-            permissions::SPEAK,
-            permissions::MOVE_USERS
+            Permissions::CONNECT,
+            Permissions::SPEAK,
+            Permissions::MOVE_USERS
         ]));
     }
 
     #[test]
     fn test_has_any_permissions() {
         let mut perms = PermissionSet::new();
-        perms.add(permissions::CONNECT);
-        perms.add(permissions::SPEAK);
+        perms.add(Permissions::CONNECT);
+        perms.add(Permissions::SPEAK);
 
         // Should have at least one permission
-        assert!(perms.has_any(&[permissions::CONNECT, permissions::MOVE_USERS]));
-        assert!(perms.has_any(&[permissions::LISTEN, permissions::SPEAK]));
+        assert!(perms.has_any(&[Permissions::CONNECT, Permissions::MOVE_USERS]));
+        assert!(perms.has_any(&[Permissions::LISTEN, Permissions::SPEAK]));
 
         // Should not have any if none match
         assert!(!perms.has_any(&[
-            permissions::LISTEN,
-            permissions::MOVE_USERS,
-            permissions::BAN_USERS
+            Permissions::LISTEN,
+            Permissions::MOVE_USERS,
+            Permissions::BAN_USERS
         ]));
     }
+
+    #[test]
+    fn test_serializes_as_permission_names() {
+        let perms = PermissionSet::from_bits(0b110 | 1 << 40);
+        let json = serde_json::to_string(&perms).unwrap();
+        assert_eq!(json, r#"["speak","listen","bit40"]"#);
+        assert_eq!(serde_json::from_str::<PermissionSet>(&json).unwrap(), perms);
+
+        // Raw bitmasks are still accepted
+        let from_bits: PermissionSet = serde_json::from_str("6").unwrap();
+        assert_eq!(
+            from_bits.permissions(),
+            Permissions::SPEAK | Permissions::LISTEN
+        );
+
+        assert!(serde_json::from_str::<PermissionSet>(r#"["speak","fly"]"#).is_err());
+    }
+
+    #[test]
+    fn test_unknown_bits_are_preserved() {
+        let future = Permissions::from_bits_retain(1 << 40);
+        let perms = Permissions::SPEAK | future;
+
+        // Set operations keep bits this build has no name for
+        assert_eq!(Permissions::from_bits_truncate(perms.bits()), perms);
+        assert_eq!(!(!perms), perms);
+        assert_eq!((perms - Permissions::SPEAK), future);
+        assert_eq!(perms.to_string(), "speak | bit40");
+        assert_eq!(Permissions::empty().to_string(), "none");
+
+        let round_trip: Permissions =
+            serde_json::from_str(&serde_json::to_string(&perms).unwrap()).unwrap();
+        assert_eq!(round_trip, perms);
+    }
+
+    #[test]
+    fn test_named_lists_every_flag() {
+        let named = Permissions::NAMED
+            .iter()
+            .fold(Permissions::empty(), |acc, &(_, flag)| acc | flag);
+        let declared = Permissions::FLAGS
+            .iter()
+            .filter(|flag| flag.is_named())
+            .fold(Permissions::empty(), |acc, flag| acc | *flag.value());
+        assert_eq!(named, declared);
+        assert!(Permissions::NAMED
+            .iter()
+            .all(|&(name, flag)| Permissions::parse_name(name) == Some(flag)));
+
+        let perms = Permissions::WHISPER | Permissions::SUBSCRIBE_RADIO;
+        assert_eq!(
+            serde_json::to_string(&perms).unwrap(),
+            r#"["whisper","subscribe_radio"]"#
+        );
+    }
 }
@@ -0,0 +1,199 @@
+//! Cache of resolved channel permissions.
+//!
+//! Resolving a user's permissions in a channel walks their roles and every
+//! parent channel. The [`PermissionCache`] remembers the result per
+//! `(user, channel)` pair and checks it against generation counters, so a
+//! change to the channel tree or to role definitions invalidates every
+//! cached entry without having to find them.
+
+use crate::channel::ChannelTree;
+use crate::error::FleetNetError;
+use crate::permission::Permissions;
+use crate::role::Role;
+use crate::types::{ChannelId, UserId};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+struct CachedPermissions {
+    tree_generation: u64,
+    role_generation: u64,
+    permissions: Permissions,
+}
+
+/// Resolved permissions per user and channel.
+///
+/// Entries are stale once the [`ChannelTree`] generation moves on, or after
+/// [`roles_changed`](Self::roles_changed). When a single user's roles
+/// change, call [`user_roles_changed`](Self::user_roles_changed) instead.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::channel::{AudioPolicy, Channel, ChannelTree, ChannelType};
+/// use fleet_net_common::permission::Permissions;
+/// use fleet_net_common::permission_cache::PermissionCache;
+/// use fleet_net_common::role::Role;
+/// use fleet_net_common::types::{ChannelId, UserId};
+/// use std::collections::HashMap;
+///
+/// let channel_id = ChannelId::new(1).unwrap();
+/// let mut tree = ChannelTree::new();
+/// tree.insert(Channel {
+///     id: channel_id,
+///     name: "General".to_string(),
+///     description: None,
+///     channel_type: ChannelType::Voice,
+///     role_permissions: HashMap::new(),
+///     position: 0,
+///     parent_id: None,
+///     topic: None,
+///     icon: None,
+///     metadata: HashMap::new(),
+///     radio: None,
+///     audio_policy: AudioPolicy::default(),
+/// })
+/// .unwrap();
+///
+/// let roles = [Role::new("member".to_string(), "Member".to_string())
+///     .with_permissions(Permissions::SPEAK)];
+/// let mut cache = PermissionCache::new();
+/// let user = UserId::new(1).unwrap();
+/// let perms = cache.get_or_compute(user, channel_id, &tree, &roles).unwrap();
+/// assert_eq!(perms, Permissions::SPEAK);
+/// ```
+#[derive(Debug, Default)]
+pub struct PermissionCache {
+    entries: HashMap<(UserId, ChannelId), CachedPermissions>,
+    role_generation: u64,
+}
+
+impl PermissionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The user's permissions in `channel_id`, resolved from `tree` and
+    /// `user_roles` unless a current result is cached.
+    ///
+    /// `user_roles` must be the roles the user held when any cached entry
+    /// was stored; see [`user_roles_changed`](Self::user_roles_changed).
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the channel is not in the tree.
+    pub fn get_or_compute(
+        &mut self,
+        user_id: UserId,
+        channel_id: ChannelId,
+        tree: &ChannelTree,
+        user_roles: &[Role],
+    ) -> Result<Permissions, FleetNetError> {
+        let tree_generation = tree.generation();
+        if let Some(cached) = self.entries.get(&(user_id, channel_id)) {
+            if cached.tree_generation == tree_generation
+                && cached.role_generation == self.role_generation
+            {
+                return Ok(cached.permissions);
+            }
+        }
+
+        let permissions = tree.user_permissions(channel_id, user_roles)?;
+        self.entries.insert(
+            (user_id, channel_id),
+            CachedPermissions {
+                tree_generation,
+                role_generation: self.role_generation,
+                permissions,
+            },
+        );
+        Ok(permissions)
+    }
+
+    /// Invalidates every entry after a role's permissions or priority changed.
+    pub fn roles_changed(&mut self) {
+        self.role_generation += 1;
+    }
+
+    /// Drops a user's entries after they gained or lost a role.
+    pub fn user_roles_changed(&mut self, user_id: UserId) {
+        self.entries.retain(|&(user, _), _| user != user_id);
+    }
+
+    /// Drops a user's entries when they disconnect.
+    pub fn forget_user(&mut self, user_id: UserId) {
+        self.user_roles_changed(user_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of stored entries, including stale ones.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{AudioPolicy, Channel, ChannelPermissions, ChannelType};
+
+    fn channel(id: u16, parent: Option<u16>) -> Channel {
+        Channel {
+            id: ChannelId::new(id).unwrap(),
+            name: format!("Channel {id}"),
+            description: None,
+            channel_type: ChannelType::Voice,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: parent.and_then(ChannelId::new),
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn test_cache_invalidated_by_tree_and_role_changes() {
+        let mut tree = ChannelTree::from_channels([channel(1, None), channel(2, Some(1))]).unwrap();
+        let user = UserId::new(1).unwrap();
+        let child = ChannelId::new(2).unwrap();
+        let mut roles = vec![Role::new("member".to_string(), "Member".to_string())
+            .with_permissions(Permissions::SPEAK | Permissions::LISTEN)];
+
+        let mut cache = PermissionCache::new();
+        let perms = cache.get_or_compute(user, child, &tree, &roles).unwrap();
+        assert_eq!(perms, Permissions::SPEAK | Permissions::LISTEN);
+
+        // A new override bumps the tree generation
+        let mut overridden = channel(2, Some(1));
+        overridden.role_permissions.insert(
+            "member".to_string(),
+            ChannelPermissions {
+                allow: Permissions::empty(),
+                deny: Permissions::SPEAK,
+            },
+        );
+        tree.insert(overridden).unwrap();
+        let perms = cache.get_or_compute(user, child, &tree, &roles).unwrap();
+        assert!(!perms.contains(Permissions::SPEAK));
+
+        // Role edits are only seen once announced
+        roles[0].permissions |= Permissions::WHISPER;
+        let stale = cache.get_or_compute(user, child, &tree, &roles).unwrap();
+        assert!(!stale.contains(Permissions::WHISPER));
+        cache.roles_changed();
+        let perms = cache.get_or_compute(user, child, &tree, &roles).unwrap();
+        assert!(perms.contains(Permissions::WHISPER));
+
+        cache.forget_user(user);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+//! Server-imposed mutes, deafens and bans.
+//!
+//! Moderators restrict a user for a while or indefinitely. Each restriction
+//! records who issued it and why, and the server lifts it once
+//! `expires_at` has passed.
+
+use crate::permission::Permissions;
+use crate::types::UserId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// What a restriction prevents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestrictionKind {
+    /// The user cannot transmit audio.
+    Mute,
+
+    /// The user can neither transmit nor hear audio.
+    Deafen,
+
+    /// The user cannot connect to the server.
+    Ban,
+}
+
+impl RestrictionKind {
+    /// The permission needed to impose or lift this kind of restriction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    /// use fleet_net_common::restriction::RestrictionKind;
+    ///
+    /// assert_eq!(RestrictionKind::Ban.required_permission(), Permissions::BAN_USERS);
+    /// ```
+    pub fn required_permission(self) -> Permissions {
+        match self {
+            RestrictionKind::Mute | RestrictionKind::Deafen => Permissions::MUTE_USERS,
+            RestrictionKind::Ban => Permissions::BAN_USERS,
+        }
+    }
+}
+
+/// A mute, deafen or ban imposed on a user by a moderator.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::{Duration, Utc};
+/// use fleet_net_common::restriction::{RestrictionKind, TimedRestriction};
+/// use fleet_net_common::types::UserId;
+///
+/// let now = Utc::now();
+/// let mute = TimedRestriction {
+///     kind: RestrictionKind::Mute,
+///     expires_at: Some(now + Duration::minutes(10)),
+///     reason: Some("Hot mic".to_string()),
+///     issued_by: UserId::new(1).unwrap(),
+/// };
+///
+/// assert!(!mute.is_expired(now));
+/// assert!(mute.is_expired(now + Duration::minutes(10)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedRestriction {
+    pub kind: RestrictionKind,
+
+    /// When the restriction is lifted; `None` keeps it until a moderator
+    /// lifts it.
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// Explanation shown to the restricted user.
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// The moderator who imposed the restriction.
+    pub issued_by: UserId,
+}
+
+impl TimedRestriction {
+    /// Whether the restriction no longer applies at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Time left at `now`, or `None` if the restriction never expires.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| (expires_at - now).to_std().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restriction_expiry() {
+        let now = Utc::now();
+        let ban = TimedRestriction {
+            kind: RestrictionKind::Ban,
+            expires_at: None,
+            reason: None,
+            issued_by: UserId::new(1).unwrap(),
+        };
+        // Indefinite bans never expire on their own
+        assert!(!ban.is_expired(now + chrono::Duration::days(365)));
+        assert_eq!(ban.remaining(now), None);
+
+        let mute = TimedRestriction {
+            kind: RestrictionKind::Mute,
+            expires_at: Some(now + chrono::Duration::seconds(30)),
+            ..ban
+        };
+        assert_eq!(mute.remaining(now), Some(Duration::from_secs(30)));
+        assert_eq!(
+            mute.remaining(now + chrono::Duration::minutes(1)),
+            Some(Duration::ZERO)
+        );
+
+        let json = serde_json::to_value(&mute).unwrap();
+        assert_eq!(json["kind"], "mute");
+        assert_eq!(
+            serde_json::from_value::<TimedRestriction>(json).unwrap(),
+            mute
+        );
+    }
+}
@@ -2,8 +2,21 @@
 //!
 //! This module provides role-based access control with Discord integration.
 //! Roles can be mapped from Discord roles and have priority-based resolution.
+//!
+//! Every user holds the built-in [`EVERYONE_ROLE_ID`] role, and servers can
+//! assign further roles directly with [`RoleAssignments`], independent of
+//! Discord.
 
+use crate::error::FleetNetError;
+use crate::permission::{PermissionSet, Permissions};
+use crate::types::UserId;
+use crate::validation::Constraint;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+/// Id of the built-in role every user holds.
+pub const EVERYONE_ROLE_ID: &str = "@everyone";
 
 /// Represents a role in the Fleet Net system with associated permissions.
 ///
@@ -21,14 +34,14 @@ use serde::{Deserialize, Serialize};
 ///
 /// ```
 /// use fleet_net_common::role::Role;
-/// use fleet_net_common::permission::permissions;
+/// use fleet_net_common::permission::Permissions;
 ///
 /// let admin_role = Role::new("admin".to_string(), "Administrator".to_string())
-///     .with_permissions(permissions::ADMINISTRATOR)
+///     .with_permissions(Permissions::ADMINISTRATOR)
 ///     .with_priority(1)
 ///     .with_discord_roles(vec!["discord_admin_id".to_string()]);
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Role {
     /// Unique identifier for this role.
     pub id: String,
@@ -36,9 +49,8 @@ pub struct Role {
     /// Human-readable name for the role.
     pub name: String,
 
-    /// Bitmask of permissions granted by this role.
-    /// Uses the same permission constants as PermissionSet.
-    pub permissions: u64,
+    /// Permissions granted by this role.
+    pub permissions: Permissions,
 
     /// List of Discord role IDs that map to this Fleet Net role.
     /// Users with any of these Discord roles will be granted this role.
@@ -68,34 +80,64 @@ impl Role {
     ///
     /// let role = Role::new("moderator".to_string(), "Moderator".to_string());
     /// assert_eq!(role.id, "moderator");
-    /// assert_eq!(role.permissions, 0);
+    /// assert!(role.permissions.is_empty());
     /// ```
     pub fn new(id: String, name: String) -> Self {
         Self {
             id,
             name,
-            permissions: 0,
+            permissions: Permissions::empty(),
             discord_role_ids: Vec::new(),
             priority: 0,
         }
     }
 
+    /// Creates the built-in `@everyone` role granting `permissions`.
+    ///
+    /// It has the lowest possible priority, so any other role a user holds
+    /// takes precedence over it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::permission::Permissions;
+    /// use fleet_net_common::role::Role;
+    ///
+    /// let everyone = Role::everyone(Permissions::CONNECT | Permissions::LISTEN);
+    /// assert!(everyone.is_everyone());
+    /// assert!(Role::new("member".to_string(), "Member".to_string()).can_manage(&everyone));
+    /// ```
+    pub fn everyone(permissions: Permissions) -> Self {
+        Self {
+            id: EVERYONE_ROLE_ID.to_string(),
+            name: "Everyone".to_string(),
+            permissions,
+            discord_role_ids: Vec::new(),
+            priority: u32::MAX,
+        }
+    }
+
+    /// Whether this is the built-in `@everyone` role.
+    pub fn is_everyone(&self) -> bool {
+        self.id == EVERYONE_ROLE_ID
+    }
+
     /// Sets the permissions for this role (builder pattern).
     ///
     /// # Arguments
     ///
-    /// * `permissions` - Bitmask of permissions to grant
+    /// * `permissions` - Permissions to grant
     ///
     /// # Examples
     ///
     /// ```
     /// use fleet_net_common::role::Role;
-    /// use fleet_net_common::permission::permissions;
+    /// use fleet_net_common::permission::Permissions;
     ///
     /// let role = Role::new("mod".to_string(), "Moderator".to_string())
-    ///     .with_permissions(permissions::KICK_USERS | permissions::MUTE_USERS);
+    ///     .with_permissions(Permissions::KICK_USERS | Permissions::MUTE_USERS);
     /// ```
-    pub fn with_permissions(mut self, permissions: u64) -> Self {
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
         self.permissions = permissions;
         self
     }
@@ -222,6 +264,190 @@ impl Role {
         // Check if any of the Discord role IDs match
         self.discord_role_ids.iter().any(|id| role_ids.contains(id))
     }
+
+    /// Checks whether holders of this role may edit, assign or remove `other`.
+    ///
+    /// Only roles of strictly lower priority can be managed, so a moderator
+    /// can never grant themselves or others a role at or above their own.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::role::Role;
+    ///
+    /// let admin = Role::new("admin".to_string(), "Admin".to_string()).with_priority(1);
+    /// let moderator = Role::new("mod".to_string(), "Moderator".to_string()).with_priority(5);
+    ///
+    /// assert!(admin.can_manage(&moderator));
+    /// assert!(!moderator.can_manage(&admin));
+    /// assert!(!moderator.can_manage(&moderator));
+    /// ```
+    pub fn can_manage(&self, other: &Role) -> bool {
+        // Lower values have higher priority
+        self.priority < other.priority
+    }
+}
+
+/// Checks that a user holding `actor_roles` may edit, assign or remove `target`.
+///
+/// The user needs MANAGE_ROLES (or ADMINISTRATOR) from one of their roles,
+/// and their highest priority role must outrank `target`. The server calls
+/// this before applying any role management request.
+///
+/// # Errors
+///
+/// Returns [`FleetNetError::PermissionError`] if either check fails.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::permission::Permissions;
+/// use fleet_net_common::role::{ensure_can_manage, Role};
+///
+/// let moderator = Role::new("mod".to_string(), "Moderator".to_string())
+///     .with_permissions(Permissions::MANAGE_ROLES)
+///     .with_priority(5);
+/// let member = Role::new("member".to_string(), "Member".to_string()).with_priority(10);
+///
+/// assert!(ensure_can_manage(&[moderator.clone()], &member).is_ok());
+/// assert!(ensure_can_manage(&[moderator.clone()], &moderator).is_err());
+/// ```
+pub fn ensure_can_manage(actor_roles: &[Role], target: &Role) -> Result<(), FleetNetError> {
+    let permissions = actor_roles
+        .iter()
+        .fold(Permissions::empty(), |acc, role| acc | role.permissions);
+    if !PermissionSet::from(permissions).has(Permissions::MANAGE_ROLES) {
+        return Err(FleetNetError::PermissionError(Cow::Borrowed(
+            "Managing roles requires the manage_roles permission",
+        )));
+    }
+
+    let outranks = actor_roles
+        .iter()
+        .min_by_key(|role| role.priority)
+        .is_some_and(|highest| highest.can_manage(target));
+    if !outranks {
+        return Err(FleetNetError::PermissionError(Cow::Owned(format!(
+            "Cannot manage role '{}' at or above your own priority",
+            target.id
+        ))));
+    }
+    Ok(())
+}
+
+/// Roles assigned to users directly by the server, keyed by user.
+///
+/// Assignments are independent of Discord; [`RoleAssignments::resolve`]
+/// merges them with the roles a user's Discord roles map to, plus
+/// `@everyone`, ready for permission computation.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::role::{Role, RoleAssignments};
+/// use fleet_net_common::types::UserId;
+/// use fleet_net_common::Permissions;
+///
+/// let roles = [
+///     Role::everyone(Permissions::CONNECT),
+///     Role::new("pilot".to_string(), "Pilot".to_string()).with_priority(10),
+///     Role::new("jtac".to_string(), "JTAC".to_string())
+///         .with_priority(5)
+///         .with_discord_roles(vec!["discord_jtac".to_string()]),
+/// ];
+/// let user_id = UserId::new(1).unwrap();
+/// let mut assignments = RoleAssignments::new();
+/// assignments.assign(user_id, "pilot").unwrap();
+///
+/// let resolved = assignments.resolve(&roles, user_id, &["discord_jtac".to_string()]);
+/// let ids: Vec<_> = resolved.iter().map(|role| role.id.as_str()).collect();
+/// assert_eq!(ids, ["jtac", "pilot", "@everyone"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RoleAssignments {
+    assignments: HashMap<UserId, HashSet<String>>,
+}
+
+impl RoleAssignments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assigns `role_id` to `user_id`, returning whether it was new.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error for `@everyone`, which every user already
+    /// holds.
+    pub fn assign(&mut self, user_id: UserId, role_id: &str) -> Result<bool, FleetNetError> {
+        if role_id == EVERYONE_ROLE_ID {
+            return Err(FleetNetError::invalid_field(
+                "role_id",
+                Constraint::Invalid(Cow::Borrowed("implicit_role")),
+            ));
+        }
+        Ok(self
+            .assignments
+            .entry(user_id)
+            .or_default()
+            .insert(role_id.to_string()))
+    }
+
+    /// Takes `role_id` away from `user_id`, returning whether it was held.
+    pub fn unassign(&mut self, user_id: UserId, role_id: &str) -> bool {
+        let Some(roles) = self.assignments.get_mut(&user_id) else {
+            return false;
+        };
+        let removed = roles.remove(role_id);
+        if roles.is_empty() {
+            self.assignments.remove(&user_id);
+        }
+        removed
+    }
+
+    /// Ids of the roles assigned to `user_id`, excluding `@everyone`.
+    pub fn roles_of(&self, user_id: UserId) -> impl Iterator<Item = &str> {
+        self.assignments
+            .get(&user_id)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Forgets every assignment of a role that was deleted.
+    pub fn remove_role(&mut self, role_id: &str) {
+        self.assignments.retain(|_, roles| {
+            roles.remove(role_id);
+            !roles.is_empty()
+        });
+    }
+
+    /// The roles `user_id` holds out of `roles`: those assigned to them,
+    /// those their `discord_role_ids` map to, and `@everyone` if defined.
+    ///
+    /// The result is sorted highest priority first, as
+    /// [`Channel::compute_user_permissions`](crate::channel::Channel::compute_user_permissions)
+    /// expects, with ties broken by id.
+    pub fn resolve(
+        &self,
+        roles: &[Role],
+        user_id: UserId,
+        discord_role_ids: &[String],
+    ) -> Vec<Role> {
+        let assigned = self.assignments.get(&user_id);
+        let mut held: Vec<Role> = roles
+            .iter()
+            .filter(|role| {
+                role.is_everyone()
+                    || assigned.is_some_and(|assigned| assigned.contains(&role.id))
+                    || role.matches_discord_roles(discord_role_ids)
+            })
+            .cloned()
+            .collect();
+        held.sort_by(|a, b| a.priority.cmp(&b.priority).then_with(|| a.id.cmp(&b.id)));
+        held
+    }
 }
 
 /// Computes the combined permissions for a user based on their Discord roles.
@@ -237,7 +463,7 @@ impl Role {
 ///
 /// # Returns
 ///
-/// Combined permission bitmask from all matching roles
+/// Combined permissions from all matching roles
 ///
 /// # Algorithm
 ///
@@ -245,7 +471,7 @@ impl Role {
 /// 2. Sort by priority (lower value = higher priority)
 /// 3. Combine all permissions using bitwise OR
 #[deprecated(note = "Use Channel::compute_user_permissions for proper priority-based resolution")]
-pub fn compute_permissions(roles: &[Role], user_discord_roles: &[String]) -> u64 {
+pub fn compute_permissions(roles: &[Role], user_discord_roles: &[String]) -> Permissions {
     let mut applicable_roles: Vec<&Role> = roles
         .iter()
         .filter(|role| role.matches_discord_roles(user_discord_roles))
@@ -257,12 +483,13 @@ pub fn compute_permissions(roles: &[Role], user_discord_roles: &[String]) -> u64
     // Combine permissions from all applicable roles using bitwise OR
     applicable_roles
         .iter()
-        .fold(0u64, |acc, role| acc | role.permissions)
+        .fold(Permissions::empty(), |acc, role| acc | role.permissions)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::slice;
 
     #[test]
     fn test_role_creation() {
@@ -270,7 +497,7 @@ mod tests {
 
         assert_eq!(role.id, "admin_role");
         assert_eq!(role.name, "Administrator");
-        assert_eq!(role.permissions, 0);
+        assert!(role.permissions.is_empty());
         assert!(role.discord_role_ids.is_empty());
         assert_eq!(role.priority, 0);
     }
@@ -278,14 +505,14 @@ mod tests {
     #[test]
     fn test_role_builder_pattern() {
         let role = Role::new("mod_role".to_string(), "Moderator".to_string())
-            .with_permissions(0b1111) // Some Permission bits
+            .with_permissions(Permissions::from_bits_retain(0b1111)) // Some Permission bits
             .with_discord_roles(vec![
                 "discord_mod_1".to_string(),
                 "discord_mod_2".to_string(),
             ])
             .with_priority(10);
 
-        assert_eq!(role.permissions, 0b1111);
+        assert_eq!(role.permissions.bits(), 0b1111);
         assert_eq!(role.discord_role_ids.len(), 2);
         assert_eq!(role.priority, 10);
     }
@@ -336,4 +563,71 @@ mod tests {
         // Should not match if empty
         assert!(!role.matches_discord_roles(&[]));
     }
+
+    #[test]
+    fn test_role_hierarchy_prevents_escalation() {
+        let admin = Role::new("admin".to_string(), "Admin".to_string())
+            .with_permissions(Permissions::ADMINISTRATOR)
+            .with_priority(1);
+        let moderator = Role::new("mod".to_string(), "Moderator".to_string())
+            .with_permissions(Permissions::MANAGE_ROLES | Permissions::KICK_USERS)
+            .with_priority(5);
+        let helper = Role::new("helper".to_string(), "Helper".to_string())
+            .with_permissions(Permissions::KICK_USERS)
+            .with_priority(8);
+        let member = Role::new("member".to_string(), "Member".to_string()).with_priority(10);
+
+        assert!(ensure_can_manage(slice::from_ref(&admin), &moderator).is_ok());
+        assert!(ensure_can_manage(slice::from_ref(&moderator), &member).is_ok());
+
+        // Equal or higher priority roles are off limits
+        assert!(ensure_can_manage(slice::from_ref(&moderator), &moderator).is_err());
+        assert!(ensure_can_manage(slice::from_ref(&moderator), &admin).is_err());
+
+        // Outranking is not enough without the permission
+        assert!(ensure_can_manage(slice::from_ref(&helper), &member).is_err());
+
+        // Permission from one role, rank from the highest one
+        assert!(ensure_can_manage(&[helper, moderator], &member).is_ok());
+        assert!(ensure_can_manage(&[], &member).is_err());
+    }
+
+    #[test]
+    fn test_role_assignments_merge_with_discord_roles() {
+        let roles = [
+            Role::everyone(Permissions::CONNECT | Permissions::LISTEN),
+            Role::new("member".to_string(), "Member".to_string())
+                .with_permissions(Permissions::SPEAK)
+                .with_priority(10)
+                .with_discord_roles(vec!["discord_member".to_string()]),
+            Role::new("lead".to_string(), "Flight Lead".to_string()).with_priority(5),
+        ];
+        let alice = UserId::new(1).unwrap();
+        let bob = UserId::new(2).unwrap();
+        let mut assignments = RoleAssignments::new();
+
+        assert!(assignments.assign(alice, "lead").unwrap());
+        assert!(!assignments.assign(alice, "lead").unwrap());
+        assert!(assignments.assign(alice, EVERYONE_ROLE_ID).is_err());
+
+        let ids = |held: Vec<Role>| held.into_iter().map(|role| role.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(assignments.resolve(&roles, alice, &["discord_member".to_string()])),
+            ["lead", "member", EVERYONE_ROLE_ID]
+        );
+        // Users without any roles still hold @everyone
+        assert_eq!(
+            ids(assignments.resolve(&roles, bob, &[])),
+            [EVERYONE_ROLE_ID]
+        );
+
+        let json = serde_json::to_string(&assignments).unwrap();
+        assert_eq!(json, r#"{"1":["lead"]}"#);
+        let parsed: RoleAssignments = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, assignments);
+
+        assignments.remove_role("lead");
+        assert!(!assignments.unassign(alice, "lead"));
+        assert_eq!(assignments.roles_of(alice).count(), 0);
+    }
 }
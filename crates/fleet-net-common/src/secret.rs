@@ -0,0 +1,24 @@
+//! Comparing secrets.
+//!
+//! Tokens and shared secrets are checked with [`constant_time_eq`], whose
+//! running time doesn't depend on where the first mismatch is, so a secret
+//! can't be guessed byte by byte from how long refusals take.
+
+/// Whether `a` and `b` are equal, in time depending only on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"s3cret", b"s3creT"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cre"));
+        assert!(!constant_time_eq(b"", b"s3cret"));
+    }
+}
@@ -3,11 +3,17 @@
 //! This module handles user sessions, tracking connection state,
 //! channel subscriptions, and user activity.
 
+use crate::error::FleetNetError;
 use crate::permission::PermissionSet;
-use crate::types::ChannelId;
+use crate::secret::constant_time_eq;
+use crate::types::{ChannelId, UserId};
 use crate::user::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::collections::HashSet;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Instant;
 
 /// Represents an active user session in the Fleet Net system.
@@ -27,6 +33,7 @@ use std::time::Instant;
 ///
 /// ```no_run
 /// use fleet_net_common::session::{Session, SessionState};
+/// use fleet_net_common::types::UserId;
 /// use fleet_net_common::user::User;
 /// use fleet_net_common::permission::PermissionSet;
 /// use std::net::SocketAddr;
@@ -34,7 +41,7 @@ use std::time::Instant;
 ///
 /// let session = Session {
 ///     id: "session_123".to_string(),
-///     user: User::new(42),
+///     user: User::new(UserId::new(42).unwrap()),
 ///     socket_addr: "127.0.0.1:8080".parse().unwrap(),
 ///     connected_at: Instant::now(),
 ///     last_active: Instant::now(),
@@ -92,8 +99,16 @@ pub struct Session {
 
 /// Represents the current state of a user session.
 ///
-/// Sessions transition through these states during their lifecycle.
-#[derive(Debug, Clone, PartialEq)]
+/// Sessions transition through these states during their lifecycle:
+///
+/// ```text
+/// Authenticating ──> Active <──> Away
+///       │              │          │
+///       └──────────────┴──────────┴──> Disconnecting
+/// ```
+///
+/// Use [`Session::transition`] rather than assigning the state directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SessionState {
     /// Initial state when a connection is established but not yet authenticated.
     Authenticating,
@@ -110,7 +125,120 @@ pub enum SessionState {
     Disconnecting,
 }
 
+impl SessionState {
+    /// Whether a session may move from this state to `to`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::session::SessionState;
+    ///
+    /// assert!(SessionState::Authenticating.can_transition_to(SessionState::Active));
+    /// assert!(!SessionState::Authenticating.can_transition_to(SessionState::Away));
+    /// assert!(!SessionState::Disconnecting.can_transition_to(SessionState::Active));
+    /// ```
+    pub fn can_transition_to(self, to: SessionState) -> bool {
+        use SessionState::{Active, Authenticating, Away, Disconnecting};
+        matches!(
+            (self, to),
+            (Authenticating, Active)
+                | (Active, Away)
+                | (Away, Active)
+                | (Authenticating | Active | Away, Disconnecting)
+        )
+    }
+
+    /// Whether the session has authenticated and is not leaving, so it may
+    /// join channels and send requests.
+    pub fn is_interactive(self) -> bool {
+        matches!(self, SessionState::Active | SessionState::Away)
+    }
+
+    /// Whether the session may send voice packets.
+    ///
+    /// Away sessions are self-muted, so only active sessions transmit.
+    pub fn can_transmit(self) -> bool {
+        self == SessionState::Active
+    }
+}
+
+/// A change of [`SessionState`], published so other components can react,
+/// e.g. by releasing a disconnecting session's channels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTransition {
+    pub session_id: String,
+    pub user_id: UserId,
+    pub from: SessionState,
+    pub to: SessionState,
+}
+
 impl Session {
+    /// Moves the session to `to`, returning the transition to publish.
+    ///
+    /// # Errors
+    ///
+    /// Returns a session state error if the lifecycle does not allow moving
+    /// from the current state to `to`; the state is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fleet_net_common::session::{Session, SessionState};
+    /// # let mut session: Session = todo!();
+    /// let transition = session.transition(SessionState::Active)?;
+    /// assert_eq!(transition.from, SessionState::Authenticating);
+    /// # Ok::<(), fleet_net_common::error::FleetNetError>(())
+    /// ```
+    pub fn transition(&mut self, to: SessionState) -> Result<SessionTransition, FleetNetError> {
+        let from = self.state;
+        if !from.can_transition_to(to) {
+            return Err(FleetNetError::SessionStateError(Cow::Owned(format!(
+                "Session {} cannot go from {from:?} to {to:?}",
+                self.id
+            ))));
+        }
+        self.state = to;
+        Ok(SessionTransition {
+            session_id: self.id.clone(),
+            user_id: self.user.id,
+            from,
+            to,
+        })
+    }
+
+    /// Fails unless the session may join channels and send requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns a session state error while authenticating or disconnecting.
+    pub fn ensure_interactive(&self) -> Result<(), FleetNetError> {
+        if self.state.is_interactive() {
+            Ok(())
+        } else {
+            Err(self.state_error("send requests"))
+        }
+    }
+
+    /// Fails unless the session may send voice packets.
+    ///
+    /// # Errors
+    ///
+    /// Returns a session state error unless the session is active.
+    pub fn ensure_can_transmit(&self) -> Result<(), FleetNetError> {
+        if self.state.can_transmit() {
+            Ok(())
+        } else {
+            Err(self.state_error("transmit"))
+        }
+    }
+
+    fn state_error(&self, action: &str) -> FleetNetError {
+        FleetNetError::SessionStateError(Cow::Owned(format!(
+            "Session {} cannot {action} while {:?}",
+            self.id, self.state
+        )))
+    }
+
     /// Updates the user's last activity timestamp to the current time.
     ///
     /// This should be called whenever the user performs any action,
@@ -160,18 +288,161 @@ impl Session {
 
         dur.as_secs() >= duration
     }
+
+    /// The IP address the user connects from, for tracking and bans.
+    ///
+    /// A dual-stack listener sees IPv4 clients as v4-mapped IPv6 addresses
+    /// such as `::ffff:192.0.2.1`; those are returned as plain IPv4, so the
+    /// same client has one address whichever socket it reached.
+    pub fn ip(&self) -> IpAddr {
+        self.socket_addr.ip().to_canonical()
+    }
+
+    /// Checks if the user receives audio from `channel_id`, either because it is
+    /// their current channel or because they monitor it as a radio.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fleet_net_common::session::Session;
+    /// # use fleet_net_common::types::ChannelId;
+    /// # let mut session: Session = todo!();
+    /// let channel_id = ChannelId::new(7).unwrap();
+    /// session.subscribed_channels.insert(channel_id);
+    /// assert!(session.hears(channel_id));
+    /// ```
+    pub fn hears(&self, channel_id: ChannelId) -> bool {
+        self.current_channel == Some(channel_id) || self.subscribed_channels.contains(&channel_id)
+    }
+
+    /// Subscribed channels in ascending order, as sent to clients.
+    pub fn sorted_subscriptions(&self) -> Vec<ChannelId> {
+        let mut channels: Vec<_> = self.subscribed_channels.iter().copied().collect();
+        channels.sort_unstable();
+        channels
+    }
+
+    /// Captures a serializable snapshot of this session.
+    ///
+    /// `Instant` values are process-local, so the connection and activity
+    /// timestamps are converted to wall-clock time for storage.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fleet_net_common::session::Session;
+    /// # let session: Session = todo!();
+    /// let snapshot = session.snapshot();
+    /// let json = serde_json::to_string(&snapshot).unwrap();
+    /// ```
+    pub fn snapshot(&self) -> SessionSnapshot {
+        SessionSnapshot {
+            id: self.id.clone(),
+            user: self.user.clone(),
+            socket_addr: self.socket_addr,
+            connected_at: instant_to_wall_clock(self.connected_at),
+            last_active: instant_to_wall_clock(self.last_active),
+            state: self.state,
+            current_channel: self.current_channel,
+            subscribed_channels: self.subscribed_channels.clone(),
+            permissions: self.permission.bits(),
+            auth_token_sha256: token_digest(&self.auth_token),
+            client_version: self.client_version.clone(),
+        }
+    }
+}
+
+/// Serializable copy of a [`Session`] used by shared or persistent session stores.
+///
+/// Snapshots let a session survive a server restart or move between nodes in a
+/// cluster. Timestamps are stored as wall-clock UTC times and converted back to
+/// `Instant`s by [`SessionSnapshot::restore`].
+///
+/// Stores are shared infrastructure, so the session's auth token is never
+/// written to them; only its SHA-256 digest is kept, enough to check a token
+/// a reconnecting client presents with [`SessionSnapshot::token_matches`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub id: String,
+    pub user: User,
+    pub socket_addr: SocketAddr,
+    pub connected_at: DateTime<Utc>,
+    pub last_active: DateTime<Utc>,
+    pub state: SessionState,
+    pub current_channel: Option<ChannelId>,
+    pub subscribed_channels: HashSet<ChannelId>,
+    /// Raw permission bitmask, see [`PermissionSet::bits`].
+    pub permissions: u64,
+    /// Hex-encoded SHA-256 digest of the session's auth token.
+    pub auth_token_sha256: String,
+    pub client_version: String,
+}
+
+impl SessionSnapshot {
+    /// Whether `token` is the auth token of the snapshotted session, compared
+    /// in constant time.
+    pub fn token_matches(&self, token: &str) -> bool {
+        constant_time_eq(
+            token_digest(token).as_bytes(),
+            self.auth_token_sha256.as_bytes(),
+        )
+    }
+
+    /// Rebuilds a live [`Session`] from this snapshot.
+    ///
+    /// Timestamps in the future (e.g. from clock skew between nodes) are clamped to now.
+    /// The auth token is not stored, so the restored session has an empty one
+    /// until the client authenticates again.
+    pub fn restore(self) -> Session {
+        Session {
+            id: self.id,
+            user: self.user,
+            socket_addr: self.socket_addr,
+            connected_at: wall_clock_to_instant(self.connected_at),
+            last_active: wall_clock_to_instant(self.last_active),
+            state: self.state,
+            current_channel: self.current_channel,
+            subscribed_channels: self.subscribed_channels,
+            permission: PermissionSet::from_bits(self.permissions),
+            auth_token: String::new(),
+            client_version: self.client_version,
+        }
+    }
+}
+
+fn token_digest(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn instant_to_wall_clock(instant: Instant) -> DateTime<Utc> {
+    let elapsed = Instant::now().saturating_duration_since(instant);
+    Utc::now() - chrono::Duration::from_std(elapsed).unwrap_or_default()
+}
+
+fn wall_clock_to_instant(time: DateTime<Utc>) -> Instant {
+    let now = Instant::now();
+    let elapsed = (Utc::now() - time).to_std().unwrap_or_default();
+    now.checked_sub(elapsed).unwrap_or(now)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::UserId;
     use crate::user::User;
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::net::Ipv4Addr;
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
 
     fn create_test_session() -> Session {
         Session {
             id: "test_session_123".to_string(),
-            user: User::new(1),
+            user: User::new(UserId::new(1).unwrap()),
             socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
             connected_at: Instant::now(),
             last_active: Instant::now(),
@@ -189,7 +460,7 @@ mod tests {
         let session = create_test_session();
 
         assert_eq!(session.id, "test_session_123");
-        assert_eq!(session.user.id, 1);
+        assert_eq!(session.user.id.get(), 1);
         assert_eq!(session.state, SessionState::Active);
         assert!(session.current_channel.is_none());
         assert!(session.subscribed_channels.is_empty());
@@ -226,4 +497,91 @@ mod tests {
         // Should not be idle for duration greater than 10 seconds
         assert!(!session.is_idle(15));
     }
+
+    #[test]
+    fn test_ip_unmaps_v4_mapped_addresses() {
+        let mut session = create_test_session();
+        session.socket_addr = "[::ffff:192.0.2.1]:8080".parse().unwrap();
+        assert_eq!(session.ip(), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+
+        session.socket_addr = "[2001:db8::1]:8080".parse().unwrap();
+        assert_eq!(session.ip(), "2001:db8::1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_hears_current_and_subscribed_channels() {
+        let mut session = create_test_session();
+        session.current_channel = Some(channel(1));
+        session.subscribed_channels.insert(channel(9));
+        session.subscribed_channels.insert(channel(4));
+
+        assert!(session.hears(channel(1)));
+        assert!(session.hears(channel(4)));
+        assert!(!session.hears(channel(2)));
+        assert_eq!(session.sorted_subscriptions(), vec![channel(4), channel(9)]);
+    }
+
+    #[test]
+    fn test_transitions_follow_the_lifecycle() {
+        let mut session = create_test_session();
+        session.state = SessionState::Authenticating;
+        assert!(session.ensure_interactive().is_err());
+        assert!(matches!(
+            session.transition(SessionState::Away),
+            Err(FleetNetError::SessionStateError(_))
+        ));
+        assert_eq!(session.state, SessionState::Authenticating);
+
+        let transition = session.transition(SessionState::Active).unwrap();
+        assert_eq!(transition.from, SessionState::Authenticating);
+        assert_eq!(transition.to, SessionState::Active);
+        assert_eq!(transition.user_id, session.user.id);
+        assert!(session.ensure_can_transmit().is_ok());
+
+        session.transition(SessionState::Away).unwrap();
+        assert!(session.ensure_interactive().is_ok());
+        assert!(session.ensure_can_transmit().is_err());
+
+        session.transition(SessionState::Disconnecting).unwrap();
+        assert!(session.transition(SessionState::Active).is_err());
+        assert!(session.transition(SessionState::Disconnecting).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut session = create_test_session();
+        session.current_channel = Some(channel(7));
+        session.subscribed_channels.insert(channel(7));
+        session.subscribed_channels.insert(channel(9));
+        session.permission = PermissionSet::from_bits(0b101);
+        session.last_active = Instant::now() - std::time::Duration::from_secs(30);
+
+        let json = serde_json::to_string(&session.snapshot()).unwrap();
+        let snapshot: SessionSnapshot = serde_json::from_str(&json).unwrap();
+        let restored = snapshot.restore();
+
+        assert_eq!(restored.id, session.id);
+        assert_eq!(restored.user.id, session.user.id);
+        assert_eq!(restored.socket_addr, session.socket_addr);
+        assert_eq!(restored.state, SessionState::Active);
+        assert_eq!(restored.current_channel, Some(channel(7)));
+        assert_eq!(restored.subscribed_channels, session.subscribed_channels);
+        assert_eq!(restored.permission.bits(), 0b101);
+
+        // Idle time survives the conversion to wall-clock time and back
+        assert!(restored.is_idle(29));
+        assert!(!restored.is_idle(60));
+    }
+
+    #[test]
+    fn test_snapshot_keeps_only_a_digest_of_the_auth_token() {
+        let session = create_test_session();
+        let snapshot = session.snapshot();
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(!json.contains(&session.auth_token));
+        assert!(snapshot.token_matches(&session.auth_token));
+        assert!(!snapshot.token_matches("someone else's token"));
+        assert!(snapshot.restore().auth_token.is_empty());
+    }
 }
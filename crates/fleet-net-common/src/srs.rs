@@ -0,0 +1,315 @@
+//! Interop with the DCS-SRS game export.
+//!
+//! The export script of DCS-SRS, run inside DCS World and mimicked by
+//! radio mods for other games such as Arma, sends the player's cockpit
+//! radios to `127.0.0.1:9084` as one JSON object per UDP datagram, several
+//! times a second. [`SrsExport`] reads that format and
+//! [`SrsExport::tunings`] finds the Fleet Net radio channel on the same net
+//! as each game radio, so a client can follow the cockpit: tuning a radio
+//! in the game retunes it in Fleet Net, and the game's push-to-talk keys
+//! the radio it has selected.
+//!
+//! # Examples
+//!
+//! ```
+//! use fleet_net_common::channel::Modulation;
+//! use fleet_net_common::srs::SrsExport;
+//!
+//! let export = SrsExport::parse(br#"{
+//!     "name": "Viper 1-1", "unit": "F-16C_50", "selected": 1, "ptt": true,
+//!     "radios": [
+//!         { "name": "intercom", "freq": 100, "modulation": 2 },
+//!         { "name": "AN/ARC-164", "freq": 251000000, "modulation": 0, "secFreq": 243000000 }
+//!     ]
+//! }"#).unwrap();
+//!
+//! // The intercom has no Fleet Net counterpart
+//! assert_eq!(export.radios[0].tuning(), None);
+//! let uhf = export.radios[1].tuning().unwrap();
+//! assert_eq!((uhf.frequency_hz, uhf.modulation), (251_000_000, Modulation::Am));
+//! assert_eq!(export.transmitting_radio(), Some(1));
+//! ```
+
+use crate::channel::{ChannelTree, ChannelType, Modulation, RadioChannelConfig};
+use crate::error::FleetNetError;
+use crate::types::ChannelId;
+use serde::{Deserialize, Serialize};
+
+/// Port the DCS-SRS export script sends to.
+pub const SRS_EXPORT_PORT: u16 = 9084;
+
+/// Modulation codes of the export.
+mod modulation {
+    pub const AM: u8 = 0;
+    pub const FM: u8 = 1;
+    pub const DISABLED: u8 = 3;
+}
+
+/// The player's radios as exported by the game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SrsExport {
+    /// Player or callsign.
+    #[serde(default)]
+    pub name: String,
+    /// Airframe or vehicle type, e.g. `F-16C_50`.
+    #[serde(default)]
+    pub unit: String,
+    /// Index of the radio push-to-talk transmits on.
+    #[serde(default)]
+    pub selected: i16,
+    /// Push-to-talk is held in the game.
+    #[serde(default)]
+    pub ptt: bool,
+    /// Cockpit radios; the first one is usually the intercom.
+    #[serde(default)]
+    pub radios: Vec<SrsRadio>,
+    #[serde(default, rename = "latLng")]
+    pub position: Option<SrsPosition>,
+}
+
+/// One cockpit radio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SrsRadio {
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "freq", default)]
+    pub frequency_hz: f64,
+    /// 0 AM, 1 FM, 2 intercom, 3 off, 4 HAVE QUICK, 5 SATCOM, 6 MIDS.
+    #[serde(default = "disabled")]
+    pub modulation: u8,
+    /// Guard receiver frequency; 0 or less when the guard receiver is off.
+    #[serde(rename = "secFreq", default)]
+    pub guard_frequency_hz: f64,
+    /// Simulated encryption is switched on.
+    #[serde(rename = "enc", default)]
+    pub encrypted: bool,
+    /// Key selected for encryption.
+    #[serde(rename = "encKey", default)]
+    pub key: u8,
+}
+
+fn disabled() -> u8 {
+    modulation::DISABLED
+}
+
+/// Where the player is, for games that export it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SrsPosition {
+    pub lat: f64,
+    pub lng: f64,
+    /// Altitude above sea level, in meters.
+    #[serde(default)]
+    pub alt: f64,
+}
+
+/// A game radio matched to a Fleet Net radio channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SrsTuning {
+    /// Index of the radio in [`SrsExport::radios`].
+    pub radio: usize,
+    pub channel_id: ChannelId,
+    pub frequency_hz: u64,
+    /// Matched on the radio's guard receiver rather than its main frequency.
+    pub guard: bool,
+}
+
+impl SrsExport {
+    /// Parses one export datagram.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FleetNetError::JsonError`] if `datagram` is not an export.
+    pub fn parse(datagram: &[u8]) -> Result<Self, FleetNetError> {
+        Ok(serde_json::from_slice(datagram)?)
+    }
+
+    /// The Fleet Net channel for every radio, and every guard receiver,
+    /// tuned to a net that exists in `tree`, in radio order.
+    ///
+    /// Radios set to anything but AM or FM, such as the intercom or
+    /// SATCOM, are left out, as are frequencies no channel is tuned to.
+    pub fn tunings(&self, tree: &ChannelTree) -> Vec<SrsTuning> {
+        let mut tunings = Vec::new();
+        for (index, radio) in self.radios.iter().enumerate() {
+            let receivers = [(radio.tuning(), false), (radio.guard_tuning(), true)];
+            for (tuning, guard) in receivers {
+                let Some(tuning) = tuning else {
+                    continue;
+                };
+                if let Some(channel_id) = channel_on_net(tree, &tuning) {
+                    tunings.push(SrsTuning {
+                        radio: index,
+                        channel_id,
+                        frequency_hz: tuning.frequency_hz,
+                        guard,
+                    });
+                }
+            }
+        }
+        tunings
+    }
+
+    /// Index of the radio being transmitted on, if push-to-talk is held.
+    pub fn transmitting_radio(&self) -> Option<usize> {
+        let selected = usize::try_from(self.selected).ok()?;
+        (self.ptt && selected < self.radios.len()).then_some(selected)
+    }
+}
+
+impl SrsRadio {
+    /// The net the radio's main frequency is on, or `None` if the radio is
+    /// off or in a mode Fleet Net does not model.
+    pub fn tuning(&self) -> Option<RadioChannelConfig> {
+        let modulation = match self.modulation {
+            modulation::AM => Modulation::Am,
+            modulation::FM => Modulation::Fm,
+            _ => return None,
+        };
+        Some(RadioChannelConfig {
+            frequency_hz: to_hz(self.frequency_hz)?,
+            modulation,
+            max_range_m: None,
+            crypto_key_id: self.encrypted.then(|| self.key.to_string()),
+        })
+    }
+
+    /// The net of the guard receiver, which listens in the clear with the
+    /// radio's modulation.
+    pub fn guard_tuning(&self) -> Option<RadioChannelConfig> {
+        let main = self.tuning()?;
+        Some(RadioChannelConfig {
+            frequency_hz: to_hz(self.guard_frequency_hz)?,
+            crypto_key_id: None,
+            ..main
+        })
+    }
+}
+
+/// Whole hertz, or `None` for the export's placeholders of unset frequencies.
+fn to_hz(frequency: f64) -> Option<u64> {
+    (frequency.is_finite() && frequency >= 1.0).then(|| frequency.round() as u64)
+}
+
+/// The lowest-numbered radio channel on the net of `tuning`.
+fn channel_on_net(tree: &ChannelTree, tuning: &RadioChannelConfig) -> Option<ChannelId> {
+    tree.iter()
+        .map(|(_, channel)| channel)
+        .filter(|channel| {
+            channel.channel_type == ChannelType::Radio
+                && channel
+                    .radio
+                    .as_ref()
+                    .is_some_and(|radio| radio.same_net(tuning))
+        })
+        .map(|channel| channel.id)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{AudioPolicy, Channel};
+    use std::collections::HashMap;
+
+    fn radio_channel(
+        id: u16,
+        frequency_hz: u64,
+        modulation: Modulation,
+        key: Option<&str>,
+    ) -> Channel {
+        Channel {
+            id: ChannelId::new(id).unwrap(),
+            name: format!("{frequency_hz} Hz"),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: Some(RadioChannelConfig {
+                frequency_hz,
+                modulation,
+                max_range_m: None,
+                crypto_key_id: key.map(str::to_string),
+            }),
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    fn tree() -> ChannelTree {
+        ChannelTree::from_channels([
+            radio_channel(3, 251_000_000, Modulation::Am, None),
+            radio_channel(2, 251_000_000, Modulation::Am, None),
+            radio_channel(4, 243_000_000, Modulation::Am, None),
+            radio_channel(5, 30_000_000, Modulation::Fm, Some("3")),
+        ])
+        .unwrap()
+    }
+
+    /// An export as DCS-SRS 2.x writes it, trimmed to three radios.
+    const EXPORT: &str = r#"{
+        "name": "Viper 1-1", "unit": "F-16C_50", "unitId": 16778496,
+        "selected": 2, "ptt": false, "control": 0, "seat": 0,
+        "radios": [
+            { "name": "intercom", "freq": 100.0, "modulation": 2, "volume": 1.0,
+              "secFreq": 0, "freqMin": 100, "freqMax": 100, "encKey": 0, "enc": false,
+              "encMode": 0, "freqMode": 0, "guardFreqMode": 0, "volMode": 0,
+              "expansion": false, "channel": -1, "simul": false, "rtMode": 1 },
+            { "name": "AN/ARC-164", "freq": 251000000.0, "modulation": 0, "volume": 0.8,
+              "secFreq": 243000000.0, "encKey": 0, "enc": false },
+            { "name": "AN/ARC-222", "freq": 30000000.4, "modulation": 1, "volume": 1.0,
+              "secFreq": 0, "encKey": 3, "enc": true }
+        ],
+        "latLng": { "lat": 41.93, "lng": 41.86, "alt": 1520.5 },
+        "ambient": { "vol": 1.0, "abType": "f16" }
+    }"#;
+
+    #[test]
+    fn test_export_is_parsed_ignoring_unknown_fields() {
+        let export = SrsExport::parse(EXPORT.as_bytes()).unwrap();
+
+        assert_eq!(export.unit, "F-16C_50");
+        assert_eq!(export.radios.len(), 3);
+        assert_eq!(export.radios[1].guard_frequency_hz, 243_000_000.0);
+        assert!(export.radios[2].encrypted);
+        assert_eq!(export.position.unwrap().alt, 1520.5);
+
+        let minimal = SrsExport::parse(br#"{"radios":[{"freq":1}]}"#).unwrap();
+        assert_eq!(minimal.radios[0].modulation, 3);
+        assert!(SrsExport::parse(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_radios_are_matched_to_channels_on_their_net() {
+        let export = SrsExport::parse(EXPORT.as_bytes()).unwrap();
+
+        let tunings = export.tunings(&tree());
+        let found: Vec<_> = tunings
+            .iter()
+            .map(|tuning| (tuning.radio, tuning.channel_id.get(), tuning.guard))
+            .collect();
+        // The intercom is skipped, and of two channels on a net the first wins
+        assert_eq!(found, [(1, 2, false), (1, 4, true), (2, 5, false)]);
+        assert_eq!(tunings[2].frequency_hz, 30_000_000);
+
+        // Another key is another net
+        let mut other_key = export.clone();
+        other_key.radios[2].key = 4;
+        assert!(other_key.tunings(&tree()).iter().all(|t| t.radio != 2));
+    }
+
+    #[test]
+    fn test_push_to_talk_keys_the_selected_radio() {
+        let mut export = SrsExport::parse(EXPORT.as_bytes()).unwrap();
+        assert_eq!(export.transmitting_radio(), None);
+
+        export.ptt = true;
+        assert_eq!(export.transmitting_radio(), Some(2));
+        export.selected = 7;
+        assert_eq!(export.transmitting_radio(), None);
+        export.selected = -1;
+        assert_eq!(export.transmitting_radio(), None);
+    }
+}
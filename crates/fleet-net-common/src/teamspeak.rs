@@ -0,0 +1,670 @@
+//! Importing the structure of a TeamSpeak 3 server.
+//!
+//! Communities moving from TeamSpeak keep their channel tree and server
+//! groups. The importer reads a ServerQuery transcript: the output of
+//! `channellist`, `servergrouplist` and, for the permissions, of
+//! `servergrouppermlist sgid=<id> -permsid` and `channelpermlist cid=<id>
+//! -permsid`, each below the command that produced it. `serverinfo` names
+//! the default group, which becomes `@everyone`, and `channelgrouplist`
+//! lists channel groups so they can be reported. Anything else in the
+//! transcript, such as `login` or `error` lines, is skipped.
+//!
+//! [`TeamSpeakImport::from_transcript`] turns it into a [`ServerTemplate`]:
+//!
+//! - Channels keep their ids, parents, order, topics and descriptions.
+//!   Spacers become categories.
+//! - Regular server groups become roles named after them, with the
+//!   TeamSpeak permissions that have a Fleet Net counterpart.
+//! - Needed talk and join powers of a channel become overrides allowing or
+//!   denying [`Permissions::SPEAK`] and [`Permissions::CONNECT`] to each
+//!   role, depending on whether its group had the power.
+//!
+//! Everything that has no equivalent, such as channel passwords, channel
+//! groups or permissions like `b_client_ignore_antiflood`, is listed in
+//! [`TeamSpeakImport::unmapped`] for the admin to review.
+//!
+//! # Examples
+//!
+//! ```
+//! use fleet_net_common::teamspeak::TeamSpeakImport;
+//!
+//! let import = TeamSpeakImport::from_transcript(
+//!     "channellist\n\
+//!      cid=1 pid=0 channel_order=0 channel_name=Lobby|cid=2 pid=1 channel_order=0 channel_name=Alpha\\sFlight\n\
+//!      error id=0 msg=ok\n",
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(import.template.channels[1].name, "Alpha Flight");
+//! assert_eq!(import.template.channels[1].parent_id, import.template.channels[0].id.into());
+//! ```
+
+use crate::channel::{AudioPolicy, Channel, ChannelPermissions, ChannelType};
+use crate::error::FleetNetError;
+use crate::limits::{
+    MAX_CHANNEL_DESCRIPTION_LEN, MAX_CHANNEL_NAME_LEN, MAX_CHANNEL_TOPIC_LEN, MAX_ROLE_ID_LEN,
+    MAX_ROLE_NAME_LEN,
+};
+use crate::permission::Permissions;
+use crate::role::{Role, EVERYONE_ROLE_ID};
+use crate::template::ServerTemplate;
+use crate::types::ChannelId;
+use crate::validation::Constraint;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// What TeamSpeak lets every connected user do, before any group permission.
+const BASE_PERMISSIONS: Permissions = Permissions::CONNECT
+    .union(Permissions::SPEAK)
+    .union(Permissions::LISTEN)
+    .union(Permissions::MOVE_SELF);
+
+/// Server group type of groups regular clients are members of; the others
+/// are templates and ServerQuery groups.
+const REGULAR_GROUP: &str = "1";
+
+/// Fleet Net counterparts of TeamSpeak permissions, granted when the value
+/// is positive.
+const PERMISSIONS: &[(&str, Permissions)] = &[
+    (
+        "b_permission_modify_power_ignore",
+        Permissions::ADMINISTRATOR,
+    ),
+    ("b_virtualserver_modify_name", Permissions::MANAGE_SERVER),
+    (
+        "b_virtualserver_modify_welcomemessage",
+        Permissions::MANAGE_SERVER,
+    ),
+    (
+        "b_virtualserver_modify_maxclients",
+        Permissions::MANAGE_SERVER,
+    ),
+    (
+        "b_channel_create_temporary",
+        Permissions::CREATE_TEMP_CHANNELS,
+    ),
+    ("b_channel_create_permanent", Permissions::MANAGE_CHANNELS),
+    (
+        "b_channel_create_semi_permanent",
+        Permissions::MANAGE_CHANNELS,
+    ),
+    ("b_channel_modify_name", Permissions::MANAGE_CHANNELS),
+    ("b_channel_delete_permanent", Permissions::MANAGE_CHANNELS),
+    (
+        "b_virtualserver_servergroup_create",
+        Permissions::MANAGE_ROLES,
+    ),
+    ("i_group_member_add_power", Permissions::MANAGE_ROLES),
+    ("i_client_move_power", Permissions::MOVE_USERS),
+    ("i_client_kick_from_server_power", Permissions::KICK_USERS),
+    ("i_client_kick_from_channel_power", Permissions::KICK_USERS),
+    ("b_client_ban_create", Permissions::BAN_USERS),
+    ("i_client_ban_power", Permissions::BAN_USERS),
+    (
+        "b_client_is_priority_speaker",
+        Permissions::PRIORITY_SPEAKER,
+    ),
+    (
+        "b_client_channel_textmessage_send",
+        Permissions::SEND_MESSAGES,
+    ),
+    ("i_client_whisper_power", Permissions::WHISPER),
+    ("b_virtualserver_log_view", Permissions::VIEW_AUDIT_LOG),
+];
+
+/// Permissions about the group itself rather than what members may do, and
+/// the powers channel overrides are worked out from.
+const GROUP_SETTINGS: &[&str] = &[
+    "b_group_is_permanent",
+    "i_group_auto_update_type",
+    "i_group_auto_update_max_value",
+    "i_group_sort_id",
+    "i_group_show_name_in_tree",
+    "i_group_needed_modify_power",
+    "i_group_needed_member_add_power",
+    "i_group_needed_member_remove_power",
+    "i_icon_id",
+    "i_client_talk_power",
+    "i_channel_join_power",
+];
+
+/// Needed powers of a channel and the power of a group they are checked
+/// against, with the permission they decide.
+const CHANNEL_POWERS: &[(&str, &str, Permissions)] = &[
+    (
+        "i_channel_needed_talk_power",
+        "i_client_talk_power",
+        Permissions::SPEAK,
+    ),
+    (
+        "i_channel_needed_join_power",
+        "i_channel_join_power",
+        Permissions::CONNECT,
+    ),
+];
+
+/// One record of ServerQuery output.
+type Record = HashMap<String, String>;
+
+/// A TeamSpeak server converted to a template.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TeamSpeakImport {
+    pub template: ServerTemplate,
+    /// What could not be carried over, one sentence each.
+    pub unmapped: Vec<String>,
+}
+
+/// The transcript sorted by command.
+#[derive(Default)]
+struct Transcript {
+    channels: Vec<Record>,
+    server_groups: Vec<Record>,
+    channel_groups: Vec<Record>,
+    /// Permissions by server group id.
+    group_permissions: BTreeMap<String, Vec<Record>>,
+    /// Permissions by channel id.
+    channel_permissions: BTreeMap<String, Vec<Record>>,
+    default_group: Option<String>,
+}
+
+/// The command whose output follows.
+enum Section {
+    Channels,
+    ServerGroups,
+    ChannelGroups,
+    GroupPermissions(String),
+    ChannelPermissions(String),
+    ServerInfo,
+    Other,
+}
+
+impl TeamSpeakImport {
+    /// Converts a ServerQuery transcript, see the module documentation.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the transcript has no channel list.
+    pub fn from_transcript(transcript: &str) -> Result<Self, FleetNetError> {
+        let transcript = Transcript::parse(transcript);
+        if transcript.channels.is_empty() {
+            return Err(FleetNetError::invalid_field(
+                "channellist",
+                Constraint::NotFound,
+            ));
+        }
+
+        let mut import = Self::default();
+        let powers = import.convert_groups(&transcript);
+        import.convert_channels(&transcript, &powers);
+        for group in &transcript.channel_groups {
+            import.note(format!(
+                "Channel group {:?} has no equivalent; assign roles instead",
+                field(group, "name")
+            ));
+        }
+        import.unmapped.sort();
+        import.unmapped.dedup();
+        Ok(import)
+    }
+
+    /// Adds a role for every regular server group and returns the powers
+    /// of each by role id.
+    fn convert_groups(&mut self, transcript: &Transcript) -> HashMap<String, HashMap<String, i64>> {
+        let mut powers = HashMap::new();
+        for group in &transcript.server_groups {
+            let sgid = field(group, "sgid");
+            let name = field(group, "name");
+            if field(group, "type") != REGULAR_GROUP {
+                self.note(format!(
+                    "Server group {name:?} is a template or ServerQuery group and was skipped"
+                ));
+                continue;
+            }
+
+            let mut role = if transcript.default_group.as_deref() == Some(sgid) {
+                Role::everyone(BASE_PERMISSIONS)
+            } else {
+                let id = self.role_id(name, sgid);
+                Role {
+                    priority: field(group, "sortid").parse().unwrap_or(0),
+                    ..Role::new(id, truncate(name, MAX_ROLE_NAME_LEN))
+                }
+            };
+            let mut group_powers = HashMap::new();
+            for permission in transcript.group_permissions.get(sgid).into_iter().flatten() {
+                let Some((permsid, value)) = self.permission(permission, name) else {
+                    continue;
+                };
+                if let Some(&(_, granted)) = PERMISSIONS.iter().find(|(id, _)| *id == permsid) {
+                    if value > 0 {
+                        role.permissions |= granted;
+                    }
+                } else if GROUP_SETTINGS.contains(&permsid)
+                    || permsid.starts_with("i_needed_modify_power_")
+                {
+                    group_powers.insert(permsid.to_string(), value);
+                } else if value > 0 {
+                    self.note(format!(
+                        "Permission {permsid} of server group {name:?} has no equivalent"
+                    ));
+                }
+            }
+            powers.insert(role.id.clone(), group_powers);
+            self.template.roles.push(role);
+        }
+        powers
+    }
+
+    fn convert_channels(
+        &mut self,
+        transcript: &Transcript,
+        powers: &HashMap<String, HashMap<String, i64>>,
+    ) {
+        let positions = positions(&transcript.channels);
+        for record in &transcript.channels {
+            let cid = field(record, "cid");
+            let name = field(record, "channel_name");
+            let Some(id) = cid.parse().ok().and_then(ChannelId::new) else {
+                self.note(format!(
+                    "Channel {name:?} was skipped, its id {cid} is out of range"
+                ));
+                continue;
+            };
+            let parent_id = match field(record, "pid") {
+                "0" | "" => None,
+                pid => pid.parse().ok().and_then(ChannelId::new),
+            };
+            let (channel_type, name) = match spacer_title(name) {
+                Some(title) => (ChannelType::Category, title),
+                None => (ChannelType::Voice, name),
+            };
+            if field(record, "channel_flag_password") == "1" {
+                self.note(format!(
+                    "Channel {name:?} has a password, which was not carried over"
+                ));
+            }
+
+            let mut needed: Vec<(&str, i64)> = transcript
+                .channel_permissions
+                .get(cid)
+                .into_iter()
+                .flatten()
+                .filter_map(|permission| self.permission(permission, name))
+                .collect();
+            // `channellist -voice` lists the needed talk power as a property
+            if let Ok(power) = field(record, "channel_needed_talk_power").parse() {
+                needed.push(("i_channel_needed_talk_power", power));
+            }
+            let mut role_permissions = HashMap::new();
+            for (permsid, value) in needed {
+                let Some(&(_, power, permission)) =
+                    CHANNEL_POWERS.iter().find(|(id, ..)| *id == permsid)
+                else {
+                    self.note(format!(
+                        "Permission {permsid} of channel {name:?} has no equivalent"
+                    ));
+                    continue;
+                };
+                if value <= 0 {
+                    continue;
+                }
+                for role in &self.template.roles {
+                    let has = powers
+                        .get(&role.id)
+                        .and_then(|powers| powers.get(power))
+                        .copied()
+                        .unwrap_or(0);
+                    let entry: &mut ChannelPermissions = role_permissions
+                        .entry(role.id.clone())
+                        .or_insert_with(|| ChannelPermissions {
+                            allow: Permissions::empty(),
+                            deny: Permissions::empty(),
+                        });
+                    if has >= value {
+                        entry.allow |= permission;
+                    } else {
+                        entry.deny |= permission;
+                    }
+                }
+            }
+
+            self.template.channels.push(Channel {
+                id,
+                name: truncate(name, MAX_CHANNEL_NAME_LEN),
+                description: non_empty(field(record, "channel_description"))
+                    .map(|text| truncate(text, MAX_CHANNEL_DESCRIPTION_LEN)),
+                channel_type,
+                role_permissions,
+                position: positions.get(cid).copied().unwrap_or(0),
+                parent_id,
+                topic: non_empty(field(record, "channel_topic"))
+                    .map(|text| truncate(text, MAX_CHANNEL_TOPIC_LEN)),
+                icon: None,
+                metadata: HashMap::new(),
+                radio: None,
+                audio_policy: AudioPolicy::default(),
+            });
+        }
+    }
+
+    /// The id and value of a permission record, noting records listed by
+    /// numeric id, which cannot be told apart without a TeamSpeak server.
+    fn permission<'a>(&mut self, record: &'a Record, owner: &str) -> Option<(&'a str, i64)> {
+        let Some(permsid) = record.get("permsid") else {
+            self.note(format!(
+                "Permissions of {owner:?} are listed by number; list them with -permsid"
+            ));
+            return None;
+        };
+        Some((permsid, field(record, "permvalue").parse().unwrap_or(0)))
+    }
+
+    /// A role id from the group name, unique within the template.
+    fn role_id(&self, name: &str, sgid: &str) -> String {
+        let mut id = String::new();
+        for c in name.chars().flat_map(char::to_lowercase) {
+            if c.is_alphanumeric() {
+                id.push(c);
+            } else if !id.is_empty() && !id.ends_with('-') {
+                id.push('-');
+            }
+        }
+        let id = truncate(id.trim_end_matches('-'), MAX_ROLE_ID_LEN);
+        let taken = |id: &str| {
+            id == EVERYONE_ROLE_ID || self.template.roles.iter().any(|role| role.id == id)
+        };
+        if id.is_empty() || taken(&id) {
+            format!("group-{sgid}")
+        } else {
+            id
+        }
+    }
+
+    fn note(&mut self, note: String) {
+        self.unmapped.push(note);
+    }
+}
+
+impl Transcript {
+    fn parse(text: &str) -> Self {
+        let mut transcript = Self::default();
+        let mut section = Section::Other;
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let first = line.split(' ').next().unwrap_or_default();
+            if !first.contains('=') {
+                section = Section::from_command(line);
+                continue;
+            }
+
+            let records = line.split('|').map(parse_record);
+            match &section {
+                Section::Channels => transcript.channels.extend(records),
+                Section::ServerGroups => transcript.server_groups.extend(records),
+                Section::ChannelGroups => transcript.channel_groups.extend(records),
+                Section::GroupPermissions(sgid) => transcript
+                    .group_permissions
+                    .entry(sgid.clone())
+                    .or_default()
+                    .extend(records),
+                Section::ChannelPermissions(cid) => transcript
+                    .channel_permissions
+                    .entry(cid.clone())
+                    .or_default()
+                    .extend(records),
+                Section::ServerInfo => {
+                    for record in records {
+                        if let Some(sgid) = record.get("virtualserver_default_server_group") {
+                            transcript.default_group = Some(sgid.clone());
+                        }
+                    }
+                }
+                Section::Other => {}
+            }
+        }
+        transcript
+    }
+}
+
+impl Section {
+    fn from_command(line: &str) -> Self {
+        let mut words = line.split(' ');
+        let command = words.next().unwrap_or_default();
+        let argument = |key: &str| {
+            line.split(' ')
+                .find_map(|word| word.strip_prefix(key)?.strip_prefix('='))
+                .map(str::to_string)
+        };
+        match command {
+            "channellist" => Self::Channels,
+            "servergrouplist" => Self::ServerGroups,
+            "channelgrouplist" => Self::ChannelGroups,
+            "serverinfo" => Self::ServerInfo,
+            "servergrouppermlist" => argument("sgid").map_or(Self::Other, Self::GroupPermissions),
+            "channelpermlist" => argument("cid").map_or(Self::Other, Self::ChannelPermissions),
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Parses `key=value` pairs separated by spaces; keys without a value are
+/// kept with an empty one.
+fn parse_record(text: &str) -> Record {
+    text.split(' ')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (key.to_string(), unescape(value)),
+            None => (pair.to_string(), String::new()),
+        })
+        .collect()
+}
+
+/// Undoes ServerQuery escaping.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('p') => unescaped.push('|'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('a' | 'b' | 'f' | 'v') | None => {}
+            Some(other) => unescaped.push(other),
+        }
+    }
+    unescaped
+}
+
+fn field<'a>(record: &'a Record, key: &str) -> &'a str {
+    record.get(key).map_or("", String::as_str)
+}
+
+fn non_empty(text: &str) -> Option<&str> {
+    let text = text.trim();
+    (!text.is_empty()).then_some(text)
+}
+
+fn truncate(text: &str, max_len: usize) -> String {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+/// The title of a spacer such as `[cspacer0]Flights`, or its raw name if the
+/// title is empty.
+fn spacer_title(name: &str) -> Option<&str> {
+    let rest = name.strip_prefix('[')?;
+    let (tag, title) = rest.split_once(']')?;
+    let kind = tag.trim_start_matches(['c', 'l', 'r', '*']);
+    kind.starts_with("spacer")
+        .then(|| non_empty(title).unwrap_or(name))
+}
+
+/// Position of each channel among its siblings.
+///
+/// TeamSpeak stores the order as the id of the channel each one follows,
+/// `0` for the first. Channels a broken chain does not reach go last, in
+/// id order.
+fn positions(channels: &[Record]) -> HashMap<&str, u32> {
+    let mut siblings: BTreeMap<&str, Vec<&Record>> = BTreeMap::new();
+    for channel in channels {
+        siblings
+            .entry(field(channel, "pid"))
+            .or_default()
+            .push(channel);
+    }
+
+    let mut positions = HashMap::new();
+    for children in siblings.values() {
+        let after: HashMap<&str, &str> = children
+            .iter()
+            .map(|channel| (field(channel, "channel_order"), field(channel, "cid")))
+            .collect();
+        let mut ordered = Vec::new();
+        let mut seen = BTreeSet::new();
+        let mut previous = "0";
+        while let Some(&cid) = after.get(previous) {
+            if !seen.insert(cid) {
+                break;
+            }
+            ordered.push(cid);
+            previous = cid;
+        }
+        let mut rest: Vec<&str> = children
+            .iter()
+            .map(|channel| field(channel, "cid"))
+            .filter(|cid| !seen.contains(cid))
+            .collect();
+        rest.sort_by_key(|cid| cid.parse::<u64>().unwrap_or(u64::MAX));
+        ordered.extend(rest);
+        for (position, cid) in ordered.into_iter().enumerate() {
+            positions.insert(cid, position as u32);
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ServerLimits;
+    use crate::validation::Validate;
+
+    /// A transcript of a small milsim server, as a ServerQuery session logs it.
+    const TRANSCRIPT: &str = r"
+TS3
+Welcome to the TeamSpeak 3 ServerQuery interface.
+login serveradmin secret
+error id=0 msg=ok
+use sid=1
+error id=0 msg=ok
+serverinfo
+virtualserver_name=JTF\s42 virtualserver_default_server_group=8 virtualserver_maxclients=64
+error id=0 msg=ok
+channellist -topic -flags
+cid=1 pid=0 channel_order=0 channel_name=Lobby channel_topic=Welcome\p\sread\sthe\srules channel_flag_password=0|cid=5 pid=0 channel_order=3 channel_name=[cspacer0]Operations channel_topic channel_flag_password=0|cid=3 pid=0 channel_order=1 channel_name=Command channel_topic channel_flag_password=1|cid=6 pid=5 channel_order=0 channel_name=Alpha\sFlight channel_topic channel_flag_password=0
+error id=0 msg=ok
+servergrouplist
+sgid=1 name=Guest\sServer\sQuery type=2 iconid=0 savedb=0 sortid=0|sgid=6 name=Server\sAdmin type=1 iconid=300 savedb=1 sortid=10|sgid=8 name=Guest type=1 iconid=0 savedb=1 sortid=0|sgid=9 name=Pilot type=1 iconid=0 savedb=1 sortid=20
+error id=0 msg=ok
+servergrouppermlist sgid=6 -permsid
+sgid=6 permsid=b_permission_modify_power_ignore permvalue=1 permnegated=0 permskip=0|permsid=b_channel_create_permanent permvalue=1 permnegated=0 permskip=0|permsid=i_client_talk_power permvalue=75 permnegated=0 permskip=0|permsid=b_client_ignore_antiflood permvalue=1 permnegated=0 permskip=0|permsid=i_group_sort_id permvalue=10 permnegated=0 permskip=0
+error id=0 msg=ok
+servergrouppermlist sgid=9 -permsid
+sgid=9 permsid=i_client_talk_power permvalue=50 permnegated=0 permskip=0|permsid=b_client_channel_textmessage_send permvalue=1 permnegated=0 permskip=0
+error id=0 msg=ok
+servergrouppermlist sgid=8
+sgid=8 permid=8470 permvalue=1 permnegated=0 permskip=0
+error id=0 msg=ok
+channelpermlist cid=6 -permsid
+cid=6 permsid=i_channel_needed_talk_power permvalue=50 permnegated=0 permskip=0|permsid=i_channel_needed_subscribe_power permvalue=10 permnegated=0 permskip=0
+error id=0 msg=ok
+channelgrouplist
+cgid=5 name=Channel\sAdmin type=1 iconid=100 savedb=1
+error id=0 msg=ok
+quit
+";
+
+    #[test]
+    fn test_transcript_becomes_a_valid_template() {
+        let import = TeamSpeakImport::from_transcript(TRANSCRIPT).unwrap();
+        let template = &import.template;
+        template.validate(&ServerLimits::default()).unwrap();
+
+        let channels: Vec<_> = template
+            .channels
+            .iter()
+            .map(|c| {
+                (
+                    c.id.get(),
+                    c.name.as_str(),
+                    c.position,
+                    c.channel_type.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            channels,
+            [
+                (1, "Lobby", 0, ChannelType::Voice),
+                (5, "Operations", 2, ChannelType::Category),
+                (3, "Command", 1, ChannelType::Voice),
+                (6, "Alpha Flight", 0, ChannelType::Voice),
+            ]
+        );
+        assert_eq!(
+            template.channels[0].topic.as_deref(),
+            Some("Welcome| read the rules")
+        );
+        assert_eq!(template.channels[3].parent_id, ChannelId::new(5));
+
+        let roles: Vec<_> = template.roles.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(roles, ["server-admin", EVERYONE_ROLE_ID, "pilot"]);
+        assert!(template.roles[0]
+            .permissions
+            .contains(Permissions::ADMINISTRATOR));
+        assert_eq!(template.roles[1].permissions, BASE_PERMISSIONS);
+        assert_eq!(template.roles[2].permissions, Permissions::SEND_MESSAGES);
+        assert_eq!(template.roles[2].priority, 20);
+
+        // Only groups with the needed talk power may speak in Alpha Flight
+        let overrides = &template.channels[3].role_permissions;
+        assert_eq!(overrides["pilot"].allow, Permissions::SPEAK);
+        assert_eq!(overrides["server-admin"].allow, Permissions::SPEAK);
+        assert_eq!(overrides[EVERYONE_ROLE_ID].deny, Permissions::SPEAK);
+
+        assert_eq!(
+            import.unmapped,
+            [
+                "Channel \"Command\" has a password, which was not carried over",
+                "Channel group \"Channel Admin\" has no equivalent; assign roles instead",
+                "Permission b_client_ignore_antiflood of server group \"Server Admin\" has no equivalent",
+                "Permission i_channel_needed_subscribe_power of channel \"Alpha Flight\" has no equivalent",
+                "Permissions of \"Guest\" are listed by number; list them with -permsid",
+                "Server group \"Guest Server Query\" is a template or ServerQuery group and was skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_values_are_unescaped() {
+        assert_eq!(unescape(r"Alpha\s\p\sBravo\/1\\2"), r"Alpha | Bravo/1\2");
+        assert_eq!(unescape(r"trailing\"), "trailing");
+        assert_eq!(spacer_title("[spacer1]---"), Some("---"));
+        assert_eq!(spacer_title("[*spacer2]"), Some("[*spacer2]"));
+        assert_eq!(spacer_title("[Alpha]"), None);
+    }
+
+    #[test]
+    fn test_transcripts_without_channels_are_rejected() {
+        let result =
+            TeamSpeakImport::from_transcript("servergrouplist\nsgid=6 name=Admin type=1\n");
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+    }
+}
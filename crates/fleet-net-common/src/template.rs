@@ -0,0 +1,334 @@
+//! Channel and role setups for export and import.
+//!
+//! A [`ServerTemplate`] holds everything a community sets up by hand: the
+//! channel tree, each channel's role permission overrides and the role
+//! definitions. Servers export their template so it can be kept under
+//! version control, and import one to replace their setup in one step.
+//! Users, role assignments and channel membership are not part of it.
+//!
+//! Importing starts with [`ServerTemplate::diff`] against the current
+//! setup, so a dry run can show what would change before anything does.
+//!
+//! # Examples
+//!
+//! ```
+//! use fleet_net_common::limits::ServerLimits;
+//! use fleet_net_common::permission::Permissions;
+//! use fleet_net_common::role::Role;
+//! use fleet_net_common::template::ServerTemplate;
+//! use fleet_net_common::validation::Validate;
+//!
+//! let current = ServerTemplate::default();
+//! let template = ServerTemplate {
+//!     channels: Vec::new(),
+//!     roles: vec![Role::new("pilot".to_string(), "Pilot".to_string())
+//!         .with_permissions(Permissions::SPEAK)],
+//! };
+//! template.validate(&ServerLimits::default()).unwrap();
+//!
+//! let changes = template.diff(&current);
+//! assert_eq!(changes.roles_added, ["pilot"]);
+//! assert!(changes.channels_removed.is_empty());
+//! ```
+
+use crate::channel::Channel;
+use crate::limits::{ServerLimits, MAX_ROLES, MAX_ROLE_ID_LEN, MAX_ROLE_NAME_LEN};
+use crate::role::{Role, EVERYONE_ROLE_ID};
+use crate::types::ChannelId;
+use crate::validation::{Constraint, FieldErrors, Validate};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+/// The channel tree and roles of a server.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerTemplate {
+    /// Every channel, with its role permission overrides.
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    /// Role definitions. Leaving out `@everyone` keeps the server's own.
+    #[serde(default)]
+    pub roles: Vec<Role>,
+}
+
+/// What importing a template changes, by channel and role id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateChanges {
+    #[serde(default)]
+    pub channels_added: Vec<ChannelId>,
+    #[serde(default)]
+    pub channels_updated: Vec<ChannelId>,
+    #[serde(default)]
+    pub channels_removed: Vec<ChannelId>,
+    #[serde(default)]
+    pub roles_added: Vec<String>,
+    #[serde(default)]
+    pub roles_updated: Vec<String>,
+    #[serde(default)]
+    pub roles_removed: Vec<String>,
+}
+
+impl TemplateChanges {
+    pub fn is_empty(&self) -> bool {
+        self.channels_added.is_empty()
+            && self.channels_updated.is_empty()
+            && self.channels_removed.is_empty()
+            && self.roles_added.is_empty()
+            && self.roles_updated.is_empty()
+            && self.roles_removed.is_empty()
+    }
+}
+
+impl ServerTemplate {
+    /// What replacing `current` with this template changes, each list
+    /// sorted by id.
+    ///
+    /// `@everyone` is only ever updated: it is kept when the template
+    /// leaves it out.
+    pub fn diff(&self, current: &ServerTemplate) -> TemplateChanges {
+        let mut changes = TemplateChanges::default();
+
+        let existing: HashMap<_, _> = current.channels.iter().map(|c| (c.id, c)).collect();
+        for channel in &self.channels {
+            match existing.get(&channel.id) {
+                None => changes.channels_added.push(channel.id),
+                Some(&old) if old != channel => changes.channels_updated.push(channel.id),
+                Some(_) => {}
+            }
+        }
+        let kept: HashSet<_> = self.channels.iter().map(|c| c.id).collect();
+        changes.channels_removed = existing
+            .keys()
+            .filter(|id| !kept.contains(id))
+            .copied()
+            .collect();
+
+        let existing: HashMap<_, _> = current.roles.iter().map(|r| (r.id.as_str(), r)).collect();
+        for role in &self.roles {
+            match existing.get(role.id.as_str()) {
+                None => changes.roles_added.push(role.id.clone()),
+                Some(&old) if old != role => changes.roles_updated.push(role.id.clone()),
+                Some(_) => {}
+            }
+        }
+        let kept: HashSet<_> = self.roles.iter().map(|r| r.id.as_str()).collect();
+        changes.roles_removed = existing
+            .keys()
+            .filter(|&&id| id != EVERYONE_ROLE_ID && !kept.contains(id))
+            .map(|id| id.to_string())
+            .collect();
+
+        changes.channels_added.sort();
+        changes.channels_updated.sort();
+        changes.channels_removed.sort();
+        changes.roles_added.sort();
+        changes.roles_updated.sort();
+        changes.roles_removed.sort();
+        changes
+    }
+}
+
+/// Checks every channel and role, that ids are unique, that parents exist
+/// without forming cycles and that overrides name roles of the template.
+impl Validate for ServerTemplate {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        if self.channels.len() > limits.max_channels as usize {
+            errors.add(
+                "channels",
+                Constraint::TooLong(limits.max_channels as usize),
+            );
+        }
+        if self.roles.len() > MAX_ROLES {
+            errors.add("roles", Constraint::TooLong(MAX_ROLES));
+        }
+
+        let mut role_ids = HashSet::new();
+        for (index, role) in self.roles.iter().enumerate() {
+            let field = |name: &str| format!("roles[{index}].{name}");
+            errors.check_length(field("id"), &role.id, 1, MAX_ROLE_ID_LEN);
+            errors.check_length(field("name"), role.name.trim(), 1, MAX_ROLE_NAME_LEN);
+            if !role_ids.insert(role.id.as_str()) {
+                errors.add(field("id"), Constraint::Duplicate);
+            }
+        }
+        // Overrides for @everyone apply whether or not the template defines it
+        role_ids.insert(EVERYONE_ROLE_ID);
+
+        let mut channels = HashMap::new();
+        for (index, channel) in self.channels.iter().enumerate() {
+            let prefix = format!("channels[{index}]");
+            errors.check_nested(&prefix, channel, limits);
+            if channels.insert(channel.id, channel).is_some() {
+                errors.add(format!("{prefix}.id"), Constraint::Duplicate);
+            }
+            let mut unknown: Vec<_> = channel
+                .role_permissions
+                .keys()
+                .filter(|role_id| !role_ids.contains(role_id.as_str()))
+                .collect();
+            unknown.sort();
+            for role_id in unknown {
+                errors.add(
+                    format!("{prefix}.role_permissions.{role_id}"),
+                    Constraint::NotFound,
+                );
+            }
+        }
+
+        for (index, channel) in self.channels.iter().enumerate() {
+            if let Some(constraint) = parent_problem(&channels, channel) {
+                errors.add(format!("channels[{index}].parent_id"), constraint);
+            }
+        }
+    }
+}
+
+/// Why the parent links of `channel` are broken, if they are.
+fn parent_problem(
+    channels: &HashMap<ChannelId, &Channel>,
+    channel: &Channel,
+) -> Option<Constraint> {
+    let mut next = channel.parent_id;
+    // Bounded by the tree size, as the links may form a cycle elsewhere
+    for _ in 0..=channels.len() {
+        let ancestor = next?;
+        if ancestor == channel.id {
+            return Some(Constraint::Invalid(Cow::Borrowed("cycle")));
+        }
+        next = match channels.get(&ancestor) {
+            Some(parent) => parent.parent_id,
+            None => return Some(Constraint::NotFound),
+        };
+    }
+    Some(Constraint::Invalid(Cow::Borrowed("cycle")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arbitrary;
+    use crate::channel::{AudioPolicy, ChannelPermissions, ChannelType};
+    use crate::permission::Permissions;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn channel(id: u16, parent: Option<u16>) -> Channel {
+        Channel {
+            id: ChannelId::new(id).unwrap(),
+            name: format!("Channel {id}"),
+            description: None,
+            channel_type: ChannelType::Voice,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: parent.map(|parent| ChannelId::new(parent).unwrap()),
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    fn role(id: &str) -> Role {
+        Role::new(id.to_string(), id.to_uppercase()).with_permissions(Permissions::SPEAK)
+    }
+
+    fn errors(template: &ServerTemplate) -> Vec<String> {
+        let mut errors = FieldErrors::new();
+        template.check(&mut errors, &ServerLimits::default());
+        errors.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_template_structure_is_validated() {
+        let mut overridden = channel(3, Some(1));
+        overridden.role_permissions.insert(
+            "pilot".to_string(),
+            ChannelPermissions {
+                allow: Permissions::SPEAK,
+                deny: Permissions::empty(),
+            },
+        );
+        let mut template = ServerTemplate {
+            channels: vec![channel(1, None), channel(2, Some(1)), overridden],
+            roles: vec![role("pilot"), Role::everyone(Permissions::CONNECT)],
+        };
+        assert!(errors(&template).is_empty());
+
+        template.channels[0].parent_id = ChannelId::new(3);
+        template.channels.push(channel(5, Some(9)));
+        template.channels.push(channel(2, Some(1)));
+        template.channels[2].role_permissions.insert(
+            "atc".to_string(),
+            ChannelPermissions {
+                allow: Permissions::SPEAK,
+                deny: Permissions::empty(),
+            },
+        );
+        template.roles.push(role("pilot"));
+        assert_eq!(
+            errors(&template),
+            [
+                "roles[2].id: duplicate",
+                "channels[2].role_permissions.atc: not_found",
+                "channels[4].id: duplicate",
+                "channels[0].parent_id: cycle",
+                "channels[1].parent_id: cycle",
+                "channels[2].parent_id: cycle",
+                "channels[3].parent_id: not_found",
+                "channels[4].parent_id: cycle",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_lists_changes_by_id() {
+        let current = ServerTemplate {
+            channels: vec![channel(1, None), channel(2, Some(1)), channel(3, None)],
+            roles: vec![
+                Role::everyone(Permissions::CONNECT),
+                role("pilot"),
+                role("atc"),
+            ],
+        };
+        assert!(current.diff(&current).is_empty());
+
+        let mut renamed = channel(2, Some(1));
+        renamed.name = "Tower".to_string();
+        let template = ServerTemplate {
+            channels: vec![channel(4, None), renamed, channel(1, None)],
+            roles: vec![role("pilot").with_priority(5), role("awacs")],
+        };
+        assert_eq!(
+            template.diff(&current),
+            TemplateChanges {
+                channels_added: vec![ChannelId::new(4).unwrap()],
+                channels_updated: vec![ChannelId::new(2).unwrap()],
+                channels_removed: vec![ChannelId::new(3).unwrap()],
+                roles_added: vec!["awacs".to_string()],
+                roles_updated: vec!["pilot".to_string()],
+                // @everyone stays although the template leaves it out
+                roles_removed: vec!["atc".to_string()],
+            }
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_templates_round_trip(
+            mut channels in vec(arbitrary::channel(), 0..8),
+            mut roles in vec(arbitrary::role(), 0..4),
+        ) {
+            // Ids within a template are unique
+            let mut seen = HashSet::new();
+            channels.retain(|channel| seen.insert(channel.id));
+            let mut seen = HashSet::new();
+            roles.retain(|role| seen.insert(role.id.clone()));
+            let template = ServerTemplate { channels, roles };
+            let json = serde_json::to_string(&template).unwrap();
+            let parsed: ServerTemplate = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(&parsed, &template);
+            prop_assert!(parsed.diff(&template).is_empty());
+        }
+    }
+}
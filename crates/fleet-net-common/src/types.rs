@@ -1,40 +1,160 @@
 //! Core type definitions for Fleet Net.
 //!
-//! This module contains fundamental type aliases used throughout the Fleet Net system.
-//! These types provide semantic meaning and consistent sizing for network identifiers.
-
-/// Unique identifier for users in the Fleet Net system.
-///
-/// Using a 16-bit unsigned integer provides:
-/// - Fast lookup performance in collections
-/// - Compact network packet size
-/// - Support for up to 65,535 concurrent users per server
-///
-/// # Examples
-///
-/// ```
-/// use fleet_net_common::types::UserId;
-///
-/// let user_id: UserId = 42;
-/// assert_eq!(user_id, 42u16);
-/// ```
-pub type UserId = u16;
-
-/// Unique identifier for channels in the Fleet Net system.
-///
-/// Using a 16-bit unsigned integer provides:
-/// - Fast lookup performance in channel collections
-/// - Compact representation in network packets
-/// - Support for up to 65,535 channels per server
-///
-/// Channels can be voice channels, radio channels, or categories.
-///
-/// # Examples
-///
-/// ```
-/// use fleet_net_common::types::ChannelId;
-///
-/// let voice_channel: ChannelId = 1;
-/// let category_channel: ChannelId = 100;
-/// ```
-pub type ChannelId = u16;
+//! This module contains the identifier types used throughout the Fleet Net system.
+//! Each identifier is a distinct type so a user can never be passed where a
+//! channel is expected, and zero is reserved as "no id" so it can never name
+//! a real user, channel or group.
+
+use crate::error::FleetNetError;
+use crate::validation::Constraint;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::num::NonZeroU16;
+use std::str::FromStr;
+
+macro_rules! id_type {
+    ($(#[$meta:meta])* $name:ident, $field:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(NonZeroU16);
+
+        impl $name {
+            /// Wraps `id`, or `None` if it is zero.
+            pub const fn new(id: u16) -> Option<Self> {
+                match NonZeroU16::new(id) {
+                    Some(id) => Some(Self(id)),
+                    None => None,
+                }
+            }
+
+            /// The raw id, as sent on the wire.
+            pub const fn get(self) -> u16 {
+                self.0.get()
+            }
+        }
+
+        impl From<NonZeroU16> for $name {
+            fn from(id: NonZeroU16) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<$name> for u16 {
+            fn from(id: $name) -> Self {
+                id.get()
+            }
+        }
+
+        impl TryFrom<u16> for $name {
+            type Error = FleetNetError;
+
+            fn try_from(id: u16) -> Result<Self, Self::Error> {
+                Self::new(id).ok_or_else(|| {
+                    FleetNetError::invalid_field(
+                        $field,
+                        Constraint::OutOfRange {
+                            min: 1,
+                            max: u16::MAX.into(),
+                        },
+                    )
+                })
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = FleetNetError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let id: u16 = s.parse().map_err(|_| {
+                    FleetNetError::invalid_field($field, Constraint::InvalidFormat)
+                })?;
+                Self::try_from(id)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Unique identifier for users in the Fleet Net system.
+    ///
+    /// A non-zero 16-bit unsigned integer, which provides:
+    /// - Fast lookup performance in collections
+    /// - Compact network packet size
+    /// - Support for up to 65,535 concurrent users per server
+    ///
+    /// Serialized as a plain number.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::types::UserId;
+    ///
+    /// let user_id = UserId::new(42).unwrap();
+    /// assert_eq!(user_id.get(), 42);
+    /// assert!(UserId::new(0).is_none());
+    /// ```
+    UserId,
+    "user_id"
+);
+
+id_type!(
+    /// Unique identifier for channels in the Fleet Net system.
+    ///
+    /// A non-zero 16-bit unsigned integer, which provides:
+    /// - Fast lookup performance in channel collections
+    /// - Compact representation in network packets
+    /// - Support for up to 65,535 channels per server
+    ///
+    /// Channels can be voice channels, radio channels, or categories.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::types::ChannelId;
+    ///
+    /// let voice_channel = ChannelId::new(1).unwrap();
+    /// let category_channel: ChannelId = "100".parse().unwrap();
+    /// assert!(voice_channel < category_channel);
+    /// ```
+    ChannelId,
+    "channel_id"
+);
+
+id_type!(
+    /// Unique identifier for groups, such as fireteams, on a server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::types::GroupId;
+    ///
+    /// let group_id: GroupId = "3".parse().unwrap();
+    /// assert_eq!(group_id.get(), 3);
+    /// ```
+    GroupId,
+    "group_id"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_serialize_as_plain_numbers() {
+        let user_id = UserId::new(7).unwrap();
+        assert_eq!(serde_json::to_string(&user_id).unwrap(), "7");
+        assert_eq!(serde_json::from_str::<UserId>("7").unwrap(), user_id);
+
+        // Zero never names a real user or channel
+        assert!(serde_json::from_str::<UserId>("0").is_err());
+        assert!(ChannelId::try_from(0).is_err());
+        assert!("0".parse::<ChannelId>().is_err());
+    }
+}
@@ -3,7 +3,10 @@
 //! This module provides user representation with Discord integration,
 //! supporting both Discord-authenticated and standalone users.
 
+use crate::error::FleetNetError;
+use crate::limits::ServerLimits;
 use crate::types::UserId;
+use crate::validation::{Constraint, FieldErrors, Validate};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
@@ -21,6 +24,7 @@ use std::collections::HashSet;
 /// # Examples
 ///
 /// ```
+/// use fleet_net_common::types::UserId;
 /// use fleet_net_common::user::{User, DiscordUser};
 ///
 /// // Create a user with Discord authentication
@@ -31,7 +35,7 @@ use std::collections::HashSet;
 ///     avatar: Some("avatar_hash".to_string()),
 /// };
 ///
-/// let user = User::new_with_discord(42, discord_user);
+/// let user = User::new_with_discord(UserId::new(42).unwrap(), discord_user);
 /// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -58,6 +62,58 @@ pub struct User {
     /// Last time the user was active.
     /// Updated when user connects or performs actions.
     pub last_seen: chrono::DateTime<chrono::Utc>,
+
+    /// Status the user chose to show to others.
+    #[serde(default)]
+    pub presence: Presence,
+
+    /// Name shown on this server instead of the Discord username, e.g. a
+    /// callsign.
+    #[serde(default)]
+    pub nickname: Option<String>,
+}
+
+pub use crate::limits::{MAX_GAME_LEN, MAX_NICKNAME_LEN};
+
+/// Status a user chooses to show to others.
+///
+/// Unlike [`SessionState::Away`](crate::session::SessionState::Away), which
+/// follows the user's mute and deafen state, presence is set explicitly and
+/// does not affect audio.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::user::Presence;
+///
+/// let presence = Presence::InGame { game: "DCS World".to_string() };
+/// let json = serde_json::to_string(&presence).unwrap();
+/// assert_eq!(json, r#"{"status":"in_game","game":"DCS World"}"#);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Presence {
+    #[default]
+    Online,
+
+    /// Stepped away from the computer.
+    Away,
+
+    /// Connected but not to be disturbed, e.g. by whispers.
+    DoNotDisturb,
+
+    /// Playing the named game.
+    InGame { game: String },
+}
+
+/// Checks that the game name of [`Presence::InGame`] is not empty or too
+/// long.
+impl Validate for Presence {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        if let Presence::InGame { game } = self {
+            errors.check_length("presence.game", game.trim(), 1, limits.max_game_len);
+        }
+    }
 }
 
 /// Discord user information obtained through OAuth.
@@ -86,10 +142,88 @@ pub struct DiscordUser {
     pub discriminator: Option<String>,
 
     /// Avatar hash from Discord.
-    /// Can be used to construct avatar URLs.
+    /// Can be used to construct avatar URLs, see [`DiscordUser::avatar_url`].
     pub avatar: Option<String>,
 }
 
+/// Base URL of Discord's CDN.
+pub const DISCORD_CDN_URL: &str = "https://cdn.discordapp.com";
+
+/// Avatar size requested when the caller has no preference, in pixels.
+pub const DEFAULT_AVATAR_SIZE: u16 = 128;
+
+impl DiscordUser {
+    /// Whether the custom avatar is animated; Discord prefixes the hashes of
+    /// animated avatars with `a_`.
+    pub fn has_animated_avatar(&self) -> bool {
+        self.avatar
+            .as_deref()
+            .is_some_and(|hash| hash.starts_with("a_"))
+    }
+
+    /// URL of the user's avatar, or of Discord's default avatar for users
+    /// without one.
+    ///
+    /// Animated avatars are served as GIFs. `size` is rounded up to a power
+    /// of two between 16 and 4096, the sizes the CDN serves.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::user::DiscordUser;
+    ///
+    /// let mut user = DiscordUser {
+    ///     id: "80351110224678912".to_string(),
+    ///     username: "nelly".to_string(),
+    ///     discriminator: None,
+    ///     avatar: Some("a_1269e74af4df7417b13759eae50c83dc".to_string()),
+    /// };
+    /// assert_eq!(
+    ///     user.avatar_url(100),
+    ///     "https://cdn.discordapp.com/avatars/80351110224678912/a_1269e74af4df7417b13759eae50c83dc.gif?size=128"
+    /// );
+    ///
+    /// user.avatar = None;
+    /// assert_eq!(user.avatar_url(128), "https://cdn.discordapp.com/embed/avatars/5.png");
+    /// ```
+    pub fn avatar_url(&self, size: u16) -> String {
+        match &self.avatar {
+            Some(hash) => {
+                let extension = if self.has_animated_avatar() {
+                    "gif"
+                } else {
+                    "png"
+                };
+                let size = size.clamp(16, 4096).next_power_of_two();
+                format!(
+                    "{DISCORD_CDN_URL}/avatars/{}/{hash}.{extension}?size={size}",
+                    self.id
+                )
+            }
+            None => self.default_avatar_url(),
+        }
+    }
+
+    /// URL of the default avatar Discord shows for this user.
+    ///
+    /// Legacy usernames pick one of five defaults by discriminator; users on
+    /// the new username system, whose discriminator is absent or `0`, pick
+    /// one of six by account id.
+    pub fn default_avatar_url(&self) -> String {
+        let legacy = self
+            .discriminator
+            .as_deref()
+            .and_then(|discriminator| discriminator.parse::<u64>().ok())
+            .filter(|&discriminator| discriminator != 0);
+        let index = match legacy {
+            Some(discriminator) => discriminator % 5,
+            // Ids that do not parse fall back to the first default
+            None => (self.id.parse::<u64>().unwrap_or(0) >> 22) % 6,
+        };
+        format!("{DISCORD_CDN_URL}/embed/avatars/{index}.png")
+    }
+}
+
 impl User {
     /// Creates a new User with the given Id and default values
     pub fn new(id: UserId) -> Self {
@@ -101,6 +235,8 @@ impl User {
             local_roles: HashSet::new(),
             created_at: now,
             last_seen: now,
+            presence: Presence::Online,
+            nickname: None,
         }
     }
 
@@ -117,6 +253,7 @@ impl User {
     /// # Examples
     ///
     /// ```
+    /// use fleet_net_common::types::UserId;
     /// use fleet_net_common::user::{User, DiscordUser};
     ///
     /// let discord_user = DiscordUser {
@@ -126,9 +263,9 @@ impl User {
     ///     avatar: None,
     /// };
     ///
-    /// let user = User::new_with_discord(42, discord_user);
+    /// let user = User::new_with_discord(UserId::new(42).unwrap(), discord_user);
     /// assert!(user.discord_user.is_some());
-    /// assert_eq!(user.id, 42);
+    /// assert_eq!(user.id.get(), 42);
     /// ```
     pub fn new_with_discord(id: UserId, discord_user: DiscordUser) -> Self {
         let now = chrono::Utc::now();
@@ -139,6 +276,61 @@ impl User {
             local_roles: HashSet::new(),
             created_at: now,
             last_seen: now,
+            presence: Presence::Online,
+            nickname: None,
+        }
+    }
+
+    /// URL of the user's Discord avatar at [`DEFAULT_AVATAR_SIZE`], or `None`
+    /// for users who did not sign in with Discord.
+    pub fn avatar_url(&self) -> Option<String> {
+        self.discord_user
+            .as_ref()
+            .map(|discord_user| discord_user.avatar_url(DEFAULT_AVATAR_SIZE))
+    }
+
+    /// The name other users see: the nickname, the Discord username, or
+    /// `User <id>` for users with neither.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::types::UserId;
+    /// use fleet_net_common::user::User;
+    ///
+    /// let mut user = User::new(UserId::new(7).unwrap());
+    /// assert_eq!(user.display_name(), "User 7");
+    ///
+    /// user.nickname = Some("Viper 1-1".to_string());
+    /// assert_eq!(user.display_name(), "Viper 1-1");
+    /// ```
+    pub fn display_name(&self) -> String {
+        match (&self.nickname, &self.discord_user) {
+            (Some(nickname), _) => nickname.clone(),
+            (None, Some(discord_user)) => discord_user.username.clone(),
+            (None, None) => format!("User {}", self.id),
+        }
+    }
+
+    /// Checks a requested nickname: 1 to `limits.max_nickname_len` bytes,
+    /// with no surrounding whitespace or control characters.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error for the `nickname` field.
+    pub fn validate_nickname(nickname: &str, limits: &ServerLimits) -> Result<(), FleetNetError> {
+        let mut errors = FieldErrors::new();
+        Self::check_nickname(&mut errors, nickname, limits);
+        errors.into_result()
+    }
+
+    /// Records any problem with a requested nickname in `errors`, see
+    /// [`User::validate_nickname`].
+    pub fn check_nickname(errors: &mut FieldErrors, nickname: &str, limits: &ServerLimits) {
+        if nickname.is_empty() || nickname.len() > limits.max_nickname_len {
+            errors.check_length("nickname", nickname, 1, limits.max_nickname_len);
+        } else if nickname.trim() != nickname || nickname.chars().any(char::is_control) {
+            errors.add("nickname", Constraint::InvalidFormat);
         }
     }
 }
@@ -149,9 +341,9 @@ mod tests {
 
     #[test]
     fn test_user_creation() {
-        let user = User::new(1);
+        let user = User::new(UserId::new(1).unwrap());
 
-        assert_eq!(user.id, 1);
+        assert_eq!(user.id.get(), 1);
         assert!(user.discord_user.is_none());
         assert!(user.guild_roles.is_empty());
         assert!(user.local_roles.is_empty());
@@ -166,9 +358,9 @@ mod tests {
             avatar: Some("AvatarHash".to_string()),
         };
 
-        let user = User::new_with_discord(42, discord_user.clone());
+        let user = User::new_with_discord(UserId::new(42).unwrap(), discord_user.clone());
 
-        assert_eq!(user.id, 42);
+        assert_eq!(user.id.get(), 42);
         assert!(user.discord_user.is_some());
 
         let discord = user.discord_user.as_ref().unwrap();
@@ -178,6 +370,82 @@ mod tests {
         assert_eq!(discord.avatar, Some("AvatarHash".to_string()));
     }
 
+    #[test]
+    fn test_presence_defaults_and_validation() {
+        // Users stored before presence existed come back online
+        let mut json = serde_json::to_value(User::new(UserId::new(1).unwrap())).unwrap();
+        json.as_object_mut().unwrap().remove("presence");
+        let user: User = serde_json::from_value(json).unwrap();
+        assert_eq!(user.presence, Presence::Online);
+
+        let json = serde_json::to_string(&Presence::DoNotDisturb).unwrap();
+        assert_eq!(json, r#"{"status":"do_not_disturb"}"#);
+
+        assert!(Presence::InGame {
+            game: "Arma 3".to_string()
+        }
+        .validate(&ServerLimits::default())
+        .is_ok());
+        let err = Presence::InGame {
+            game: " ".to_string(),
+        }
+        .validate(&ServerLimits::default())
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: presence.game: too_short(1)"
+        );
+    }
+
+    #[test]
+    fn test_nickname_validation() {
+        assert!(User::validate_nickname("Viper 1-1", &ServerLimits::default()).is_ok());
+        for invalid in ["", " Viper", "Viper\n", &"x".repeat(MAX_NICKNAME_LEN + 1)] {
+            assert!(
+                matches!(
+                    User::validate_nickname(invalid, &ServerLimits::default()),
+                    Err(FleetNetError::ValidationError(_))
+                ),
+                "{invalid:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_avatar_urls() {
+        let mut user = DiscordUser {
+            id: "987654321".to_string(),
+            username: "SampleUser".to_string(),
+            discriminator: Some("1337".to_string()),
+            avatar: Some("8342729096ea3675442027381ff50dfe".to_string()),
+        };
+        assert!(!user.has_animated_avatar());
+        assert_eq!(
+            user.avatar_url(4096),
+            "https://cdn.discordapp.com/avatars/987654321/8342729096ea3675442027381ff50dfe.png?size=4096"
+        );
+        // Sizes the CDN does not serve are rounded up or clamped
+        assert!(user.avatar_url(3).ends_with("?size=16"));
+        assert!(user.avatar_url(u16::MAX).ends_with("?size=4096"));
+
+        // Legacy users pick a default by discriminator
+        user.avatar = None;
+        assert_eq!(
+            user.avatar_url(DEFAULT_AVATAR_SIZE),
+            "https://cdn.discordapp.com/embed/avatars/2.png"
+        );
+
+        // Migrated users have a discriminator of "0" and pick one by id
+        user.id = "80351110224678912".to_string();
+        user.discriminator = Some("0".to_string());
+        assert!(user.default_avatar_url().ends_with("/embed/avatars/5.png"));
+
+        let mut local = User::new(UserId::new(1).unwrap());
+        assert_eq!(local.avatar_url(), None);
+        local.discord_user = Some(user);
+        assert!(local.avatar_url().is_some());
+    }
+
     #[test]
     fn test_user_serialization() {
         let mut local_roles = HashSet::new();
@@ -187,7 +455,7 @@ mod tests {
         let mut guild_roles = ["member".to_string(), "vip".to_string()];
 
         let mut user = User::new_with_discord(
-            100,
+            UserId::new(100).unwrap(),
             DiscordUser {
                 id: "987654321".to_string(),
                 username: "SampleUser".to_string(),
@@ -0,0 +1,246 @@
+//! Field-level validation errors.
+//!
+//! A [`FleetNetError::ValidationError`] lists every offending field together
+//! with the constraint it broke, e.g. `name: too_long(100)`, so a UI can
+//! highlight the right input instead of showing a single sentence.
+//!
+//! Types checked beyond what the type system guarantees implement
+//! [`Validate`], so a connection can check every inbound message the same
+//! way before it is dispatched.
+
+use crate::error::FleetNetError;
+use crate::limits::ServerLimits;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+
+/// A rule a field value broke.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Constraint {
+    /// The field is missing or empty.
+    Required,
+
+    /// Shorter than the given minimum length.
+    TooShort(usize),
+
+    /// Longer than the given maximum length, in bytes or items.
+    TooLong(usize),
+
+    /// Outside the inclusive range.
+    OutOfRange { min: i64, max: i64 },
+
+    /// Not in the expected format, e.g. an unparsable version.
+    InvalidFormat,
+
+    /// Refers to something that does not exist.
+    NotFound,
+
+    /// Repeats a value that must be unique.
+    Duplicate,
+
+    /// Any other rule, explained in words.
+    Invalid(Cow<'static, str>),
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::Required => f.write_str("required"),
+            Constraint::TooShort(min) => write!(f, "too_short({min})"),
+            Constraint::TooLong(max) => write!(f, "too_long({max})"),
+            Constraint::OutOfRange { min, max } => write!(f, "out_of_range({min}..={max})"),
+            Constraint::InvalidFormat => f.write_str("invalid_format"),
+            Constraint::NotFound => f.write_str("not_found"),
+            Constraint::Duplicate => f.write_str("duplicate"),
+            Constraint::Invalid(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// A single offending field.
+///
+/// Nested fields are written as paths, e.g. `channels[2].name`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: Cow<'static, str>,
+    pub constraint: Constraint,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.constraint)
+    }
+}
+
+/// Every field that failed validation.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::validation::{Constraint, FieldErrors};
+///
+/// let mut errors = FieldErrors::new();
+/// errors.check_length("name", "", 1, 100);
+/// errors.add("parent_id", Constraint::NotFound);
+///
+/// let err = errors.into_result().unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "Validation error: name: too_short(1); parent_id: not_found"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Errors for a single field.
+    pub fn single(field: impl Into<Cow<'static, str>>, constraint: Constraint) -> Self {
+        let mut errors = Self::new();
+        errors.add(field, constraint);
+        errors
+    }
+
+    pub fn add(&mut self, field: impl Into<Cow<'static, str>>, constraint: Constraint) {
+        self.0.push(FieldError {
+            field: field.into(),
+            constraint,
+        });
+    }
+
+    /// Records an error if `value` is not between `min` and `max` bytes long.
+    pub fn check_length(
+        &mut self,
+        field: impl Into<Cow<'static, str>>,
+        value: &str,
+        min: usize,
+        max: usize,
+    ) {
+        if value.len() < min {
+            self.add(field, Constraint::TooShort(min));
+        } else if value.len() > max {
+            self.add(field, Constraint::TooLong(max));
+        }
+    }
+
+    /// Adds the errors of a nested value, prefixing their fields with `prefix`.
+    pub fn nest(&mut self, prefix: &str, errors: FieldErrors) {
+        for error in errors.0 {
+            self.add(format!("{prefix}.{}", error.field), error.constraint);
+        }
+    }
+
+    /// Checks a nested value, prefixing its fields with `prefix`.
+    pub fn check_nested(&mut self, prefix: &str, value: &impl Validate, limits: &ServerLimits) {
+        let mut nested = FieldErrors::new();
+        value.check(&mut nested, limits);
+        self.nest(prefix, nested);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FieldError> {
+        self.0.iter()
+    }
+
+    /// `Ok` if nothing failed, otherwise a [`FleetNetError::ValidationError`].
+    pub fn into_result(self) -> Result<(), FleetNetError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(FleetNetError::ValidationError(self))
+        }
+    }
+}
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            error.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<FieldError> for FieldErrors {
+    fn from(error: FieldError) -> Self {
+        Self(vec![error])
+    }
+}
+
+/// A value whose content is checked against a server's limits.
+///
+/// Implementors only record problems in [`Validate::check`]; callers use
+/// [`Validate::validate`] to get a single error listing all of them.
+///
+/// # Examples
+///
+/// ```
+/// use fleet_net_common::limits::ServerLimits;
+/// use fleet_net_common::user::Presence;
+/// use fleet_net_common::validation::Validate;
+///
+/// let presence = Presence::InGame {
+///     game: String::new(),
+/// };
+/// let err = presence.validate(&ServerLimits::default()).unwrap_err();
+/// assert_eq!(err.to_string(), "Validation error: presence.game: too_short(1)");
+/// ```
+pub trait Validate {
+    /// Records every problem with `self` in `errors`.
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits);
+
+    /// Checks `self` against `limits`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error listing every offending field.
+    fn validate(&self, limits: &ServerLimits) -> Result<(), FleetNetError> {
+        let mut errors = FieldErrors::new();
+        self.check(&mut errors, limits);
+        errors.into_result()
+    }
+}
+
+impl<T: Validate> Validate for Option<T> {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        if let Some(value) = self {
+            value.check(errors, limits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_errors_serialize_for_uis() {
+        let mut errors = FieldErrors::single("name", Constraint::TooLong(100));
+        errors.nest(
+            "channels[2]",
+            FieldErrors::single("parent_id", Constraint::NotFound),
+        );
+
+        let json = serde_json::to_string(&errors).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"field":"name","constraint":{"too_long":100}},{"field":"channels[2].parent_id","constraint":"not_found"}]"#
+        );
+        assert_eq!(serde_json::from_str::<FieldErrors>(&json).unwrap(), errors);
+        assert_eq!(
+            errors.to_string(),
+            "name: too_long(100); channels[2].parent_id: not_found"
+        );
+    }
+}
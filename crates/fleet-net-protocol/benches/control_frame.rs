@@ -0,0 +1,26 @@
+//! Control frame encoding and decoding, from a bare ping up to a
+//! `channel_joined` listing a busy channel.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fleet_net_protocol::connection::{decode_frame, encode_frame};
+use fleet_net_protocol::message::ControlMessage;
+use fleet_test_support::bench::control_frames;
+
+fn bench_control_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("control_frame");
+    for (name, frame) in control_frames() {
+        let (message, _) = decode_frame::<ControlMessage>(&frame).unwrap().unwrap();
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("encode", name), &message, |b, message| {
+            b.iter(|| encode_frame(black_box(message)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("decode", name), &frame, |b, frame| {
+            b.iter(|| decode_frame::<ControlMessage>(black_box(frame)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_control_frames);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+//! Per-packet work on the voice path: signing and checking the HMAC prefix,
+//! and encoding and decoding `AudioPacket`s, for 20 ms frames at common
+//! Opus bitrates.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use fleet_test_support::bench::{hmac_key_bytes, payload, PAYLOAD_SIZES};
+
+fn header() -> PacketHeader {
+    PacketHeader {
+        channel_id: ChannelId::new(12).unwrap(),
+        user_id: UserId::new(345).unwrap(),
+        sequence: 6_789,
+        timestamp: 135_780,
+        signal_strength: 255,
+        frame_duration: 20,
+        dtx: false,
+        frames: 1,
+        padded: false,
+        audio_length: 0,
+        hmac_prefix: 0,
+    }
+}
+
+fn bench_hmac(c: &mut Criterion) {
+    let key = HmacKey::from_bytes(&hmac_key_bytes());
+    let mut group = c.benchmark_group("packet_hmac");
+    for size in PAYLOAD_SIZES {
+        let audio = payload(size);
+        let mut signed = header();
+        signed.sign(&key, &audio);
+        group.throughput(Throughput::Bytes((PacketHeader::SIZE + size) as u64));
+
+        group.bench_with_input(BenchmarkId::new("sign", size), &audio, |b, audio| {
+            let mut header = header();
+            b.iter(|| header.sign(&key, black_box(audio)))
+        });
+        group.bench_with_input(BenchmarkId::new("verify", size), &audio, |b, audio| {
+            b.iter(|| assert!(signed.validate_hmac(&key, black_box(audio))))
+        });
+    }
+    group.finish();
+}
+
+fn bench_codec(c: &mut Criterion) {
+    let key = HmacKey::from_bytes(&hmac_key_bytes());
+    let mut group = c.benchmark_group("audio_packet");
+    for size in PAYLOAD_SIZES {
+        let packet = AudioPacket::new_signed(header(), payload(size), &key);
+        let bytes = packet.to_bytes();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("encode", size), &packet, |b, packet| {
+            b.iter(|| black_box(packet).to_bytes())
+        });
+        group.bench_with_input(BenchmarkId::new("decode", size), &bytes, |b, bytes| {
+            b.iter(|| AudioPacket::from_bytes(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_hmac, bench_codec);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+#![no_main]
+
+use fleet_net_protocol::packet::AudioPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything that parses must serialize back to the same bytes, less
+    // any padding
+    if let Ok(packet) = AudioPacket::from_bytes(data) {
+        let bytes = packet.to_bytes();
+        if bytes.len() == data.len() {
+            assert_eq!(&bytes[..], data);
+        } else {
+            assert_eq!(AudioPacket::from_bytes(&bytes).as_ref(), Ok(&packet));
+            let padded = packet.to_padded_bytes(64);
+            assert_eq!(AudioPacket::from_bytes(&padded).as_ref(), Ok(&packet));
+        }
+        // Batches split into as many frames as they announce, or not at all
+        let frames = usize::from(packet.header.frames);
+        if let Ok(split) = packet.into_frames() {
+            assert_eq!(split.len(), frames);
+        }
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::validation::Validate;
+use fleet_net_protocol::connection::decode_frame;
+use fleet_net_protocol::message::ControlMessage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Decode every frame in the input, as a connection reading it would
+    let mut rest = data;
+    while let Ok(Some((message, used))) = decode_frame::<ControlMessage>(rest) {
+        assert!((4..=rest.len()).contains(&used));
+        let _ = message.validate(&ServerLimits::default());
+        rest = &rest[used..];
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use fleet_net_protocol::packet::PacketHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = data;
+    if let Ok(header) = PacketHeader::read_from(&mut buf) {
+        // A header takes exactly its size and writes back unchanged
+        assert_eq!(buf.len(), data.len() - PacketHeader::SIZE);
+        let mut written = Vec::with_capacity(PacketHeader::SIZE);
+        header.write_to(&mut written);
+        assert_eq!(written, data[..PacketHeader::SIZE]);
+    }
+});
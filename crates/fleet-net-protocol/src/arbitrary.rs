@@ -0,0 +1,426 @@
+//! Proptest strategies for protocol messages and voice packets.
+//!
+//! Only available with the `proptest` feature, which also enables the
+//! strategies for the shared types in `fleet_net_common::arbitrary`. As
+//! there, generated messages pass validation under the default limits.
+
+use crate::hmac::HmacKey;
+use crate::message::{ControlMessage, ReportReason};
+use crate::packet::{
+    AudioPacket, PacketHeader, SpeakerPosition, MAX_AUDIO_LENGTH, MAX_BATCH_FRAMES,
+    MAX_PADDING_BLOCK, MAX_RETRANSMIT_SEQUENCES, MIN_PADDING_BLOCK,
+};
+use crate::resume::ResumeToken;
+use fleet_net_common::arbitrary::{
+    channel_id, channel_info, display_name, group, group_id, presence, reception_quality,
+    restriction_kind, template, timed_restriction, transmit_mode, user_id, ROLE_IDS,
+};
+use fleet_net_common::error::FleetNetErrorCode;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::template::TemplateChanges;
+use fleet_net_common::validation::FieldErrors;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::select;
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+
+pub fn packet_header() -> impl Strategy<Value = PacketHeader> {
+    (
+        (channel_id(), user_id()),
+        any::<u16>(),
+        any::<u32>(),
+        any::<u8>(),
+        prop_oneof![Just(10u8), Just(20), Just(40), Just(60)],
+        any::<bool>(),
+        any::<bool>(),
+        1..=MAX_BATCH_FRAMES,
+        0..=MAX_AUDIO_LENGTH,
+        any::<u16>(),
+    )
+        .prop_map(
+            |(
+                (channel_id, user_id),
+                sequence,
+                timestamp,
+                signal_strength,
+                frame_duration,
+                dtx,
+                padded,
+                frames,
+                audio_length,
+                hmac_prefix,
+            )| PacketHeader {
+                channel_id,
+                user_id,
+                sequence,
+                timestamp,
+                signal_strength,
+                frame_duration,
+                dtx,
+                padded,
+                frames,
+                audio_length,
+                hmac_prefix,
+            },
+        )
+}
+
+pub fn speaker_position() -> impl Strategy<Value = SpeakerPosition> {
+    let coordinate = -1.0e7f32..1.0e7;
+    (
+        coordinate.clone(),
+        coordinate.clone(),
+        coordinate,
+        0.0f32..360.0,
+        -90.0f32..=90.0,
+    )
+        .prop_map(|(x, y, z, yaw, pitch)| SpeakerPosition {
+            x,
+            y,
+            z,
+            yaw,
+            pitch,
+        })
+}
+
+/// A packet whose header's `audio_length` matches its payload, unpadded as
+/// parsed packets are.
+pub fn audio_packet() -> impl Strategy<Value = AudioPacket> {
+    (
+        packet_header(),
+        vec(any::<u8>(), 0..512),
+        option::of(speaker_position()),
+    )
+        .prop_map(|(mut header, opus_payload, position)| {
+            header.audio_length = opus_payload.len() as u16;
+            header.padded = false;
+            AudioPacket {
+                header,
+                opus_payload,
+                position,
+            }
+        })
+}
+
+pub fn resume_token() -> impl Strategy<Value = ResumeToken> {
+    "[A-Za-z0-9_-]{32}".prop_map(ResumeToken::from)
+}
+
+pub fn hmac_key() -> impl Strategy<Value = HmacKey> {
+    any::<[u8; 32]>().prop_map(|key| HmacKey::from_bytes(&key))
+}
+
+/// An address without the flow and scope ids text forms leave out.
+pub fn socket_addr() -> impl Strategy<Value = SocketAddr> {
+    (any::<IpAddr>(), any::<u16>()).prop_map(|(ip, port)| SocketAddr::new(ip, port))
+}
+
+/// A semantic version such as `1.4.0`.
+pub fn version() -> impl Strategy<Value = Cow<'static, str>> {
+    (0..100u32, 0..100u32, 0..100u32)
+        .prop_map(|(major, minor, patch)| Cow::Owned(format!("{major}.{minor}.{patch}")))
+}
+
+pub fn report_reason() -> impl Strategy<Value = ReportReason> {
+    prop_oneof![
+        Just(ReportReason::Harassment),
+        Just(ReportReason::Spam),
+        Just(ReportReason::OffensiveAudio),
+        Just(ReportReason::Impersonation),
+        Just(ReportReason::Other),
+    ]
+}
+
+/// Any control message, client request or server event alike.
+pub fn control_message() -> impl Strategy<Value = ControlMessage> {
+    let channels = || vec(channel_id(), 0..8);
+    prop_oneof![
+        (
+            "[A-Za-z0-9._-]{1,128}",
+            version(),
+            option::of(resume_token())
+        )
+            .prop_map(|(token, client_version, resume_token)| {
+                ControlMessage::Authenticate {
+                    token,
+                    client_version,
+                    resume_token,
+                }
+            }),
+        (
+            any::<bool>(),
+            option::of(user_id()),
+            option::of(".{0,64}"),
+            option::of(resume_token()),
+            option::of(version()),
+            option::of(hmac_key()),
+            option::of(socket_addr()),
+        )
+            .prop_map(
+                |(
+                    success,
+                    user_id,
+                    error,
+                    resume_token,
+                    min_client_version,
+                    udp_key,
+                    voice_relay,
+                )| {
+                    ControlMessage::AuthResponse {
+                        success,
+                        user_id,
+                        error: error.map(Cow::Owned),
+                        resume_token,
+                        min_client_version,
+                        udp_key,
+                        voice_relay,
+                    }
+                }
+            ),
+        (option::of(channel_id()), channels()).prop_map(
+            |(current_channel, subscribed_channels)| ControlMessage::SessionResumed {
+                current_channel,
+                subscribed_channels,
+            }
+        ),
+        channel_id().prop_map(|channel_id| ControlMessage::JoinChannel { channel_id }),
+        channel_id().prop_map(|channel_id| ControlMessage::LeaveChannel { channel_id }),
+        (channel_id(), vec(user_id(), 0..16), vec(user_id(), 0..4)).prop_map(
+            |(channel_id, users, spectators)| ControlMessage::ChannelJoined {
+                channel_id,
+                users,
+                spectators,
+            }
+        ),
+        channel_id().prop_map(|channel_id| ControlMessage::ChannelLeft { channel_id }),
+        channel_id().prop_map(|channel_id| ControlMessage::SubscribeChannel { channel_id }),
+        channel_id().prop_map(|channel_id| ControlMessage::UnsubscribeChannel { channel_id }),
+        (channels(), channels()).prop_map(|(subscribed_channels, retransmit_channels)| {
+            ControlMessage::SubscriptionsChanged {
+                subscribed_channels,
+                retransmit_channels,
+            }
+        }),
+        (
+            user_id(),
+            display_name(),
+            option::of(channel_id()),
+            option::of(display_name()),
+            option::of("https://cdn\\.discordapp\\.com/avatars/[0-9]{18}/[a-f0-9]{32}\\.png"),
+        )
+            .prop_map(|(user_id, username, channel_id, nickname, avatar_url)| {
+                ControlMessage::UserJoined {
+                    user_id,
+                    username,
+                    channel_id,
+                    nickname,
+                    avatar_url,
+                }
+            }),
+        user_id().prop_map(|user_id| ControlMessage::UserLeft { user_id }),
+        (
+            user_id(),
+            option::of(channel_id()),
+            option::of(channel_id())
+        )
+            .prop_map(|(user_id, from_channel, to_channel)| {
+                ControlMessage::UserChangedChannel {
+                    user_id,
+                    from_channel,
+                    to_channel,
+                }
+            }),
+        (any::<bool>(), any::<bool>()).prop_map(|(self_muted, self_deafened)| {
+            ControlMessage::UserStateChange {
+                self_muted,
+                self_deafened,
+            }
+        }),
+        transmit_mode().prop_map(|mode| ControlMessage::SetTransmitMode { mode }),
+        channels().prop_map(|channel_ids| ControlMessage::SetTransmitTargets { channel_ids }),
+        channels().prop_map(|channel_ids| ControlMessage::TransmitTargetsChanged { channel_ids }),
+        (user_id(), channel_id(), any::<bool>()).prop_map(|(user_id, channel_id, speaking)| {
+            ControlMessage::UserSpeaking {
+                user_id,
+                channel_id,
+                speaking,
+            }
+        }),
+        (channel_id(), user_id())
+            .prop_map(|(channel_id, by)| ControlMessage::TransmissionSteppedOn { channel_id, by }),
+        (user_id(), channel_id(), reception_quality()).prop_map(
+            |(user_id, channel_id, quality)| ControlMessage::ReceptionReport {
+                user_id,
+                channel_id,
+                quality,
+            }
+        ),
+        (
+            channel_id(),
+            reception_quality(),
+            any::<u32>(),
+            any::<u32>()
+        )
+            .prop_map(|(channel_id, quality, min_bitrate, max_bitrate)| {
+                ControlMessage::ReceptionFeedback {
+                    channel_id,
+                    quality,
+                    min_bitrate,
+                    max_bitrate,
+                }
+            }),
+        (
+            user_id(),
+            channel_id(),
+            vec(any::<u16>(), 1..=MAX_RETRANSMIT_SEQUENCES)
+        )
+            .prop_map(|(user_id, channel_id, sequences)| {
+                ControlMessage::RetransmitRequest {
+                    user_id,
+                    channel_id,
+                    sequences,
+                }
+            }),
+        audio_packet().prop_map(|packet| ControlMessage::VoiceTunnel {
+            packet: packet.to_bytes().to_vec(),
+        }),
+        (user_id(), any::<[bool; 4]>()).prop_map(|(user_id, [a, b, c, d])| {
+            ControlMessage::UserStateChanged {
+                user_id,
+                self_muted: a,
+                self_deafened: b,
+                server_muted: c,
+                server_deafened: d,
+            }
+        }),
+        presence().prop_map(|presence| ControlMessage::SetPresence { presence }),
+        (user_id(), presence())
+            .prop_map(|(user_id, presence)| ControlMessage::PresenceChanged { user_id, presence }),
+        option::of(display_name()).prop_map(|nickname| ControlMessage::SetNickname { nickname }),
+        (user_id(), option::of(display_name()))
+            .prop_map(|(user_id, nickname)| ControlMessage::NicknameChanged { user_id, nickname }),
+        (channel_id(), channel_info()).prop_map(|(channel_id, (topic, icon, metadata))| {
+            ControlMessage::UpdateChannelInfo {
+                channel_id,
+                topic,
+                icon,
+                metadata,
+            }
+        }),
+        (channel_id(), channel_info()).prop_map(|(channel_id, (topic, icon, metadata))| {
+            ControlMessage::ChannelInfoChanged {
+                channel_id,
+                topic,
+                icon,
+                metadata,
+            }
+        }),
+        display_name().prop_map(|name| ControlMessage::CreateGroup { name }),
+        group_id().prop_map(|group_id| ControlMessage::JoinGroup { group_id }),
+        Just(ControlMessage::LeaveGroup),
+        group().prop_map(|group| ControlMessage::GroupChanged { group }),
+        group_id().prop_map(|group_id| ControlMessage::GroupDisbanded { group_id }),
+        (
+            (display_name(), version()),
+            (any::<u32>(), any::<u32>()),
+            (
+                option::of("[a-z]{2}-[a-z]{4}"),
+                option::of(any::<u16>()),
+                option::of(any::<u16>()),
+            ),
+            option::of(1..=u32::MAX),
+            (
+                option::of(".{1,128}"),
+                option::of(MIN_PADDING_BLOCK..=MAX_PADDING_BLOCK),
+            ),
+        )
+            .prop_map(
+                |(
+                    (name, version),
+                    (user_count, channel_count),
+                    (region, ping_port, voice_port),
+                    max_users,
+                    (motd, voice_padding),
+                )| {
+                    ControlMessage::ServerInfo {
+                        name,
+                        version,
+                        user_count,
+                        channel_count,
+                        region,
+                        ping_port,
+                        voice_port,
+                        max_users,
+                        limits: Some(ServerLimits {
+                            max_users,
+                            ..ServerLimits::default()
+                        }),
+                        motd,
+                        voice_padding,
+                    }
+                }
+            ),
+        (select(FleetNetErrorCode::ALL), ".{0,64}").prop_map(|(code, message)| {
+            ControlMessage::Error {
+                code,
+                message,
+                fields: FieldErrors::new(),
+            }
+        }),
+        (user_id(), report_reason(), option::of(".{0,128}")).prop_map(
+            |(target, reason, context)| ControlMessage::ReportUser {
+                target,
+                reason,
+                context,
+            }
+        ),
+        any::<u64>().prop_map(|report_id| ControlMessage::ReportSubmitted { report_id }),
+        (
+            user_id(),
+            restriction_kind(),
+            option::of(1..=31_536_000u64),
+            option::of(".{0,64}"),
+        )
+            .prop_map(|(target, kind, duration_secs, reason)| {
+                ControlMessage::RestrictUser {
+                    target,
+                    kind,
+                    duration_secs,
+                    reason,
+                }
+            }),
+        (user_id(), restriction_kind())
+            .prop_map(|(target, kind)| ControlMessage::LiftRestriction { target, kind }),
+        (user_id(), timed_restriction()).prop_map(|(user_id, restriction)| {
+            ControlMessage::UserRestricted {
+                user_id,
+                restriction,
+            }
+        }),
+        (user_id(), restriction_kind())
+            .prop_map(|(user_id, kind)| ControlMessage::RestrictionLifted { user_id, kind }),
+        Just(ControlMessage::ExportTemplate),
+        template().prop_map(|template| ControlMessage::TemplateExported { template }),
+        (template(), any::<bool>())
+            .prop_map(|(template, dry_run)| ControlMessage::ImportTemplate { template, dry_run }),
+        (
+            vec(channel_id(), 0..4),
+            vec(select(&ROLE_IDS[..]).prop_map(str::to_string), 0..4),
+            any::<bool>()
+        )
+            .prop_map(|(channels_added, roles_removed, dry_run)| {
+                ControlMessage::TemplateImported {
+                    changes: TemplateChanges {
+                        channels_added,
+                        roles_removed,
+                        ..TemplateChanges::default()
+                    },
+                    dry_run,
+                }
+            }),
+        Just(ControlMessage::Ping),
+        Just(ControlMessage::Pong),
+    ]
+}
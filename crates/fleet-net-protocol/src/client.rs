@@ -0,0 +1,1509 @@
+//! Client side of the control connection, with automatic reconnection.
+//!
+//! [`ServerConnection`] owns a background task that connects, authenticates
+//! and then relays control messages in both directions. When the connection
+//! drops (a read or write fails, or the server stops answering heartbeats)
+//! the task retries with jittered exponential backoff and re-authenticates
+//! with the last [`ResumeToken`], so the server can restore the session's
+//! channels. Every transition is published as a [`ConnectionState`].
+//!
+//! A server reachable at several endpoints, such as its IPv6 and IPv4
+//! addresses or a fallback port, is connected to at whichever answers first,
+//! see [`TlsServerConnector::with_fallbacks`]. Networks that block UDP
+//! send voice through the control connection instead, see [`VoiceTransport`].
+
+use crate::connection::Connection;
+use crate::dual_stack;
+use crate::hmac::HmacKey;
+use crate::message::ControlMessage;
+use crate::packet::AudioPacket;
+use crate::ping;
+use crate::resume::ResumeToken;
+use bytes::BytesMut;
+use fleet_net_common::error::{FleetNetError, FleetNetErrorCode};
+use fleet_net_common::types::UserId;
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+
+/// Opens the transport for a control connection.
+///
+/// Failures reported as [`FleetNetError::EncryptionError`] are treated as
+/// permanent and end the connection instead of being retried.
+pub trait Connector: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    fn connect(&self) -> impl Future<Output = Result<Self::Stream, FleetNetError>> + Send;
+}
+
+/// Probes sent to decide whether voice can use UDP.
+const VOICE_PROBE_SAMPLES: u32 = 2;
+
+/// Connects over TCP and performs the TLS handshake.
+///
+/// Clones share the address last connected to.
+#[derive(Clone)]
+pub struct TlsServerConnector {
+    /// `host:port` pairs, in order of preference.
+    endpoints: Vec<String>,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    connected: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl TlsServerConnector {
+    /// `address` is a `host:port` pair; the host is also the name verified
+    /// against the server certificate.
+    pub fn new(
+        address: impl Into<String>,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self, FleetNetError> {
+        let address = address.into();
+        let host = address
+            .rsplit_once(':')
+            .map_or(address.as_str(), |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let server_name = ServerName::try_from(host.to_string()).map_err(|_| {
+            FleetNetError::NetworkError(Cow::Owned(format!("Invalid server address {address}")))
+        })?;
+
+        Ok(Self {
+            endpoints: vec![address],
+            server_name,
+            config,
+            connected: Arc::default(),
+        })
+    }
+
+    /// Also tries `endpoints` (`host:port` pairs, e.g. the server's other
+    /// addresses or ports) on every connection. All of them are resolved and
+    /// raced Happy Eyeballs style, see [`dual_stack::connect_tcp`]; the
+    /// certificate is still verified against the first address's host.
+    pub fn with_fallbacks<S: Into<String>>(
+        mut self,
+        endpoints: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.endpoints.extend(endpoints.into_iter().map(Into::into));
+        self
+    }
+
+    /// Peer address of the last connection made, e.g. to probe its voice
+    /// path with [`VoiceTransport::probe`].
+    pub fn connected_address(&self) -> Option<SocketAddr> {
+        *self.connected.lock().unwrap()
+    }
+
+    /// Every address the endpoints resolve to, failing only if none does.
+    async fn resolve(&self) -> Result<Vec<SocketAddr>, FleetNetError> {
+        let mut addresses = Vec::new();
+        let mut last_error = None;
+        for endpoint in &self.endpoints {
+            match lookup_host(endpoint.as_str()).await {
+                Ok(resolved) => addresses.extend(resolved),
+                Err(e) => {
+                    debug!("Failed to resolve {endpoint}: {e}");
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if addresses.is_empty() => Err(e.into()),
+            _ => Ok(addresses),
+        }
+    }
+}
+
+impl Connector for TlsServerConnector {
+    type Stream = TlsStream<TcpStream>;
+
+    async fn connect(&self) -> Result<Self::Stream, FleetNetError> {
+        let addresses = self.resolve().await?;
+        let stream =
+            dual_stack::connect_tcp(&addresses, dual_stack::CONNECTION_ATTEMPT_DELAY).await?;
+        stream.set_nodelay(true)?;
+        *self.connected.lock().unwrap() = Some(stream.peer_addr()?);
+        TlsConnector::from(self.config.clone())
+            .connect(self.server_name.clone(), stream)
+            .await
+            .map_err(|e| {
+                let certificate_error = e
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+                    .filter(|tls| matches!(tls, rustls::Error::InvalidCertificate(_)));
+                match certificate_error {
+                    Some(tls) => FleetNetError::EncryptionError(Cow::Owned(format!(
+                        "Server certificate rejected: {tls}"
+                    ))),
+                    None => e.into(),
+                }
+            })
+    }
+}
+
+/// How voice packets reach the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceTransport {
+    /// Datagrams to the voice port.
+    #[default]
+    Udp,
+    /// [`ControlMessage::VoiceTunnel`] messages on the control connection,
+    /// for networks that block UDP. Costs latency when packets are lost, as
+    /// TCP waits for them.
+    Tunnel,
+}
+
+impl VoiceTransport {
+    /// Probes the server's ping responder at `ping_address` over UDP,
+    /// choosing [`VoiceTransport::Tunnel`] if it does not answer within
+    /// `timeout`.
+    pub async fn probe(ping_address: SocketAddr, timeout: Duration) -> Self {
+        match ping::measure_rtt(ping_address, VOICE_PROBE_SAMPLES, timeout).await {
+            Ok(_) => Self::Udp,
+            Err(e) => {
+                info!("UDP to {ping_address} looks blocked, tunneling voice: {e}");
+                Self::Tunnel
+            }
+        }
+    }
+
+    /// Serializes `packet` for this transport, padded to a multiple of
+    /// `padding` bytes when set. Voice is tunneled when UDP looked blocked,
+    /// and also while there is no `udp_address` to send it to.
+    pub fn prepare(
+        self,
+        packet: &AudioPacket,
+        padding: Option<u16>,
+        udp_address: Option<SocketAddr>,
+    ) -> OutgoingVoice {
+        let datagram = match padding {
+            Some(block) => packet.to_padded_bytes(block),
+            None => packet.to_bytes(),
+        };
+        match (self, udp_address) {
+            (Self::Udp, Some(address)) => OutgoingVoice::Datagram(address, datagram),
+            _ => OutgoingVoice::Tunneled(ControlMessage::VoiceTunnel {
+                packet: datagram.to_vec(),
+            }),
+        }
+    }
+}
+
+/// A voice packet serialized for the transport it goes out on, see
+/// [`VoiceTransport::prepare`].
+#[derive(Debug)]
+pub enum OutgoingVoice {
+    /// A datagram for the voice port or relay at the address.
+    Datagram(SocketAddr, BytesMut),
+    /// A message for the control connection.
+    Tunneled(ControlMessage),
+}
+
+/// Backoff and liveness settings for a [`ServerConnection`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first retry.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Growth factor applied to the delay after each failed attempt.
+    pub multiplier: f64,
+    /// Fraction of the delay randomized, from 0.0 (none) to 1.0.
+    pub jitter: f64,
+    /// Gives up after this many consecutive failures; retries forever when `None`.
+    pub max_attempts: Option<u32>,
+    pub heartbeat_interval: Duration,
+    /// The connection is considered lost after this long without any message.
+    pub heartbeat_timeout: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.25,
+            max_attempts: None,
+            heartbeat_interval: Duration::from_secs(5),
+            heartbeat_timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before retry number `attempt` (starting at 1).
+    ///
+    /// `unit` is a random value in `[0, 1)` that spreads the delay over
+    /// `±jitter/2`, so clients dropped together do not reconnect in lockstep.
+    pub fn delay(&self, attempt: u32, unit: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(32) as i32;
+        let base = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let scale = 1.0 - jitter / 2.0 + jitter * unit.clamp(0.0, 1.0);
+        Duration::from_secs_f64((base * scale).min(self.max_delay.as_secs_f64()))
+    }
+}
+
+/// Connection lifecycle as shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected {
+        user_id: Option<UserId>,
+        /// Whether the previous session was resumed rather than started fresh.
+        resumed: bool,
+    },
+    Reconnecting {
+        attempt: u32,
+        retry_in_ms: u64,
+    },
+    Disconnected {
+        reason: Option<String>,
+        /// Code of the error that ended the connection, for showing `reason`
+        /// in the user's language; `None` when the connection was closed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<FleetNetErrorCode>,
+        /// Set when the server refused this client as too old; connecting
+        /// again will only work after updating to at least this version.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_client_version: Option<String>,
+    },
+}
+
+/// Health of the control connection, for diagnostics.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConnectionStats {
+    /// Round trip of the last answered heartbeat.
+    pub rtt_ms: Option<f64>,
+    /// Round trip smoothed the way TCP does (RFC 6298), steadier for display.
+    pub smoothed_rtt_ms: Option<f64>,
+    /// Sessions established after a failed or lost connection.
+    pub reconnects: u32,
+    pub last_error: Option<String>,
+    /// Unix time of `last_error`, in seconds.
+    pub last_error_at: Option<u64>,
+}
+
+impl ConnectionStats {
+    fn record_rtt(&mut self, rtt: Duration) {
+        let rtt_ms = rtt.as_secs_f64() * 1000.0;
+        self.rtt_ms = Some(rtt_ms);
+        self.smoothed_rtt_ms = Some(match self.smoothed_rtt_ms {
+            Some(smoothed) => smoothed * 0.875 + rtt_ms * 0.125,
+            None => rtt_ms,
+        });
+    }
+
+    fn record_error(&mut self, error: &FleetNetError) {
+        self.last_error = Some(error.to_string());
+        self.last_error_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|elapsed| elapsed.as_secs());
+    }
+}
+
+/// Credentials presented on every (re)connection.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub token: String,
+    pub client_version: Cow<'static, str>,
+}
+
+/// Why a live session ended.
+enum SessionEnd {
+    /// Transport failure; worth retrying.
+    Lost(FleetNetError),
+    /// The server refused us; retrying would fail the same way.
+    Rejected(FleetNetError),
+    /// The server refused us for being older than `min_client_version`.
+    Outdated {
+        error: FleetNetError,
+        min_client_version: String,
+    },
+    /// The handle was dropped.
+    Closed,
+}
+
+/// Handle to a control connection that reconnects on its own.
+///
+/// Dropping the handle closes the connection.
+pub struct ServerConnection {
+    /// Taken on [`ServerConnection::disconnect`] to let the task wind down.
+    outbound: Option<mpsc::UnboundedSender<ControlMessage>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    stats: Arc<Mutex<ConnectionStats>>,
+    udp_key: Arc<Mutex<Option<HmacKey>>>,
+    voice_relay: Arc<Mutex<Option<SocketAddr>>>,
+    task: JoinHandle<()>,
+}
+
+impl ServerConnection {
+    /// Starts connecting in the background. Messages from the server other
+    /// than heartbeats are delivered to `inbound`.
+    pub fn spawn<C: Connector>(
+        connector: C,
+        credentials: Credentials,
+        policy: ReconnectPolicy,
+        inbound: mpsc::UnboundedSender<ControlMessage>,
+    ) -> Self {
+        let (outbound, outbound_rx) = mpsc::unbounded_channel();
+        let state = Arc::new(watch::Sender::new(ConnectionState::Connecting));
+        let stats = Arc::new(Mutex::new(ConnectionStats::default()));
+        let udp_key = Arc::new(Mutex::new(None));
+        let voice_relay = Arc::new(Mutex::new(None));
+        let task = tokio::spawn(run(
+            connector,
+            credentials,
+            policy,
+            Shared {
+                state: state.clone(),
+                stats: stats.clone(),
+                udp_key: udp_key.clone(),
+                voice_relay: voice_relay.clone(),
+            },
+            outbound_rx,
+            inbound,
+        ));
+
+        Self {
+            outbound: Some(outbound),
+            state,
+            stats,
+            udp_key,
+            voice_relay,
+            task,
+        }
+    }
+
+    /// Queues a message; messages sent while reconnecting go out once the
+    /// session is re-established.
+    pub fn send(&self, message: ControlMessage) -> Result<(), FleetNetError> {
+        self.outbound
+            .as_ref()
+            .and_then(|outbound| outbound.send(message).ok())
+            .ok_or(FleetNetError::NetworkError(Cow::Borrowed(
+                "Connection is closed",
+            )))
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state.borrow().clone()
+    }
+
+    pub fn subscribe_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Key the current session signs its voice packets with; `None` until
+    /// the server issued one.
+    pub fn udp_key(&self) -> Option<HmacKey> {
+        self.udp_key.lock().unwrap().clone()
+    }
+
+    /// Relay the current session sends its voice to; `None` to send it to
+    /// the server's voice port.
+    pub fn voice_relay(&self) -> Option<SocketAddr> {
+        *self.voice_relay.lock().unwrap()
+    }
+
+    pub fn close(self) {
+        // Drop does the work.
+    }
+
+    /// Sends any queued messages, closes the connection cleanly and waits up
+    /// to `timeout` for it to finish. Unlike [`ServerConnection::close`] the
+    /// server sees an orderly shutdown rather than a dropped connection.
+    pub async fn disconnect(mut self, timeout: Duration) {
+        self.outbound.take();
+        if tokio::time::timeout(timeout, &mut self.task).await.is_err() {
+            debug!("Connection did not close within {timeout:?}");
+        }
+    }
+}
+
+impl Drop for ServerConnection {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.state.send_replace(ConnectionState::Disconnected {
+            reason: None,
+            code: None,
+            min_client_version: None,
+        });
+    }
+}
+
+/// State the connection task publishes to its handle.
+struct Shared {
+    state: Arc<watch::Sender<ConnectionState>>,
+    stats: Arc<Mutex<ConnectionStats>>,
+    udp_key: Arc<Mutex<Option<HmacKey>>>,
+    voice_relay: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+async fn run<C: Connector>(
+    connector: C,
+    credentials: Credentials,
+    policy: ReconnectPolicy,
+    shared: Shared,
+    mut outbound: mpsc::UnboundedReceiver<ControlMessage>,
+    inbound: mpsc::UnboundedSender<ControlMessage>,
+) {
+    let mut resume_token: Option<ResumeToken> = None;
+    let mut attempt = 0u32;
+    let state = &shared.state;
+
+    loop {
+        let end = session(
+            &connector,
+            &credentials,
+            &policy,
+            &mut resume_token,
+            &shared,
+            &mut outbound,
+            &inbound,
+            &mut attempt,
+        )
+        .await;
+
+        if let SessionEnd::Lost(error)
+        | SessionEnd::Rejected(error)
+        | SessionEnd::Outdated { error, .. } = &end
+        {
+            shared.stats.lock().unwrap().record_error(error);
+        }
+        let error = match end {
+            SessionEnd::Lost(error) => error,
+            SessionEnd::Rejected(error) => {
+                warn!("Server rejected the connection: {error}");
+                state.send_replace(ConnectionState::Disconnected {
+                    reason: Some(error.to_string()),
+                    code: Some(error.code()),
+                    min_client_version: None,
+                });
+                return;
+            }
+            SessionEnd::Outdated {
+                error,
+                min_client_version,
+            } => {
+                warn!("Server requires client version {min_client_version} or newer: {error}");
+                state.send_replace(ConnectionState::Disconnected {
+                    reason: Some(error.to_string()),
+                    code: Some(error.code()),
+                    min_client_version: Some(min_client_version),
+                });
+                return;
+            }
+            SessionEnd::Closed => return,
+        };
+
+        attempt += 1;
+        if policy.max_attempts.is_some_and(|max| attempt > max) {
+            state.send_replace(ConnectionState::Disconnected {
+                reason: Some(error.to_string()),
+                code: Some(error.code()),
+                min_client_version: None,
+            });
+            return;
+        }
+
+        let delay = policy.delay(attempt, random_unit());
+        info!("Connection lost ({error}), retrying in {delay:?}");
+        state.send_replace(ConnectionState::Reconnecting {
+            attempt,
+            retry_in_ms: delay.as_millis() as u64,
+        });
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Connects, authenticates and relays messages until the connection ends.
+#[allow(clippy::too_many_arguments)]
+async fn session<C: Connector>(
+    connector: &C,
+    credentials: &Credentials,
+    policy: &ReconnectPolicy,
+    resume_token: &mut Option<ResumeToken>,
+    shared: &Shared,
+    outbound: &mut mpsc::UnboundedReceiver<ControlMessage>,
+    inbound: &mpsc::UnboundedSender<ControlMessage>,
+    attempt: &mut u32,
+) -> SessionEnd {
+    let stream = match connector.connect().await {
+        Ok(stream) => stream,
+        // An untrusted certificate stays untrusted until the user intervenes.
+        Err(e @ FleetNetError::EncryptionError(_)) => return SessionEnd::Rejected(e),
+        Err(e) => return SessionEnd::Lost(e),
+    };
+    let mut conn = Connection::new(stream);
+
+    let resuming = resume_token.is_some();
+    let authenticate = ControlMessage::Authenticate {
+        token: credentials.token.clone(),
+        client_version: credentials.client_version.clone(),
+        resume_token: resume_token.clone(),
+    };
+    if let Err(e) = conn.write_message(&authenticate).await {
+        return SessionEnd::Lost(e);
+    }
+
+    // The server may announce itself before answering the authentication.
+    let user_id = loop {
+        let message =
+            match tokio::time::timeout(policy.heartbeat_timeout, conn.read_message()).await {
+                Ok(Ok(message)) => message,
+                Ok(Err(e)) => return SessionEnd::Lost(e),
+                Err(_) => {
+                    return SessionEnd::Lost(FleetNetError::NetworkError(Cow::Borrowed(
+                        "Timed out waiting for authentication",
+                    )))
+                }
+            };
+        match message {
+            ControlMessage::AuthResponse {
+                success: true,
+                user_id,
+                resume_token: token,
+                udp_key,
+                voice_relay,
+                ..
+            } => {
+                *resume_token = token;
+                *shared.udp_key.lock().unwrap() = udp_key;
+                *shared.voice_relay.lock().unwrap() = voice_relay;
+                break user_id;
+            }
+            ControlMessage::AuthResponse {
+                error,
+                min_client_version,
+                ..
+            } => {
+                let error = FleetNetError::AuthError(
+                    error.unwrap_or(Cow::Borrowed("Authentication failed")),
+                );
+                if let Some(min_client_version) = min_client_version {
+                    return SessionEnd::Outdated {
+                        error,
+                        min_client_version: min_client_version.into_owned(),
+                    };
+                }
+                // An expired resume token is no reason to give up; start fresh.
+                if resume_token.take().is_some() {
+                    return SessionEnd::Lost(FleetNetError::AuthError(Cow::Borrowed(
+                        "Session could not be resumed",
+                    )));
+                }
+                return SessionEnd::Rejected(error);
+            }
+            other => {
+                if inbound.send(other).is_err() {
+                    debug!("Inbound receiver dropped");
+                }
+            }
+        }
+    };
+
+    if *attempt > 0 {
+        shared.stats.lock().unwrap().reconnects += 1;
+    }
+    *attempt = 0;
+    shared.state.send_replace(ConnectionState::Connected {
+        user_id,
+        resumed: resuming,
+    });
+
+    let (mut reader, mut writer) = conn.into_split();
+    let (received_tx, mut received) = mpsc::unbounded_channel();
+    // Reads are not cancel-safe, so they run on their own task.
+    let reader_task = tokio::spawn(async move {
+        loop {
+            let result = reader.read_message().await;
+            let failed = result.is_err();
+            if received_tx.send(result).is_err() || failed {
+                break;
+            }
+        }
+    });
+    let _reader_guard = AbortOnDrop(reader_task);
+
+    let mut heartbeat = tokio::time::interval(policy.heartbeat_interval);
+    heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_received = Instant::now();
+    let mut ping_sent: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            message = outbound.recv() => {
+                // Queued messages are drained before the sender is seen closed.
+                let Some(message) = message else {
+                    if let Err(e) = writer.shutdown().await {
+                        debug!("Failed to close connection cleanly: {e}");
+                    }
+                    return SessionEnd::Closed;
+                };
+                if let Err(e) = writer.write_message(&message).await {
+                    return SessionEnd::Lost(e);
+                }
+            }
+            result = received.recv() => {
+                let message = match result {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => return SessionEnd::Lost(e),
+                    None => {
+                        return SessionEnd::Lost(FleetNetError::NetworkError(Cow::Borrowed(
+                            "Connection closed by server",
+                        )))
+                    }
+                };
+                last_received = Instant::now();
+                match message {
+                    ControlMessage::Pong => {
+                        if let Some(sent) = ping_sent.take() {
+                            shared.stats.lock().unwrap().record_rtt(sent.elapsed());
+                        }
+                    }
+                    ControlMessage::Ping => {
+                        if let Err(e) = writer.write_message(&ControlMessage::Pong).await {
+                            return SessionEnd::Lost(e);
+                        }
+                    }
+                    other => {
+                        if inbound.send(other).is_err() {
+                            debug!("Inbound receiver dropped");
+                        }
+                    }
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_received.elapsed() >= policy.heartbeat_timeout {
+                    return SessionEnd::Lost(FleetNetError::NetworkError(Cow::Borrowed(
+                        "Server stopped answering heartbeats",
+                    )));
+                }
+                if let Err(e) = writer.write_message(&ControlMessage::Ping).await {
+                    return SessionEnd::Lost(e);
+                }
+                ping_sent = Some(Instant::now());
+            }
+        }
+    }
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Uniform in `[0, 1)`; jitter only needs to differ between clients.
+fn random_unit() -> f64 {
+    let bits = RandomState::new().hash_one(Instant::now());
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketHeader;
+    use crate::test_helpers::{assert_is_server_info, create_test_server_info, ScriptedPeer};
+    use fleet_net_common::types::ChannelId;
+    use fleet_test_support::chaos::{ChaosController, ChaosStream, LinkCondition, LinkHandle};
+    use fleet_test_support::net::{bind_ephemeral, mock_connection_pair_default};
+    use fleet_test_support::recording::{Direction, Recording, RecordingStream};
+    use fleet_test_support::time::{advance, advance_in_steps, with_default_timeout};
+    use fleet_test_support::tls::{
+        client_config_from_bundle, create_tls_acceptor, server_config_from_bundle,
+    };
+    use fleet_test_support::{
+        generate_expired_certs, generate_not_yet_valid_certs, generate_oversized_chain,
+        generate_test_certs, generate_wrong_hostname_certs, init_crypto_once, TestCertBundle,
+    };
+    use std::net::SocketAddr;
+    use tokio::io::DuplexStream;
+    use tokio::net::TcpListener;
+    use tokio::net::UdpSocket;
+
+    struct TcpConnector(SocketAddr);
+
+    impl Connector for TcpConnector {
+        type Stream = TcpStream;
+
+        async fn connect(&self) -> Result<TcpStream, FleetNetError> {
+            Ok(TcpStream::connect(self.0).await?)
+        }
+    }
+
+    /// Connects over a chaos link, which refuses new connections while it
+    /// is down.
+    struct ChaosConnector(SocketAddr, LinkHandle);
+
+    impl Connector for ChaosConnector {
+        type Stream = ChaosStream<TcpStream>;
+
+        async fn connect(&self) -> Result<Self::Stream, FleetNetError> {
+            if !matches!(self.1.condition(), LinkCondition::Up) {
+                return Err(FleetNetError::NetworkError(Cow::Borrowed(
+                    "Server unreachable",
+                )));
+            }
+            let stream = TcpStream::connect(self.0).await?;
+            Ok(ChaosStream::new(stream, self.1.clone()))
+        }
+    }
+
+    /// Connects over TCP, recording the client's side of each connection.
+    struct RecordingConnector(SocketAddr, mpsc::UnboundedSender<Recording>);
+
+    impl Connector for RecordingConnector {
+        type Stream = RecordingStream<TcpStream>;
+
+        async fn connect(&self) -> Result<Self::Stream, FleetNetError> {
+            let stream = RecordingStream::new(TcpStream::connect(self.0).await?);
+            let _ = self.1.send(stream.recording());
+            Ok(stream)
+        }
+    }
+
+    /// Hands out one end of an in-memory stream, once.
+    struct DuplexConnector(Mutex<Option<DuplexStream>>);
+
+    impl Connector for DuplexConnector {
+        type Stream = DuplexStream;
+
+        async fn connect(&self) -> Result<Self::Stream, FleetNetError> {
+            self.0
+                .lock()
+                .unwrap()
+                .take()
+                .ok_or(FleetNetError::NetworkError(Cow::Borrowed(
+                    "Server unreachable",
+                )))
+        }
+    }
+
+    fn fast_policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            heartbeat_interval: Duration::from_millis(20),
+            heartbeat_timeout: Duration::from_millis(100),
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    fn credentials() -> Credentials {
+        Credentials {
+            token: "discord_token".to_string(),
+            client_version: Cow::Borrowed("1.0.0"),
+        }
+    }
+
+    /// The voice key issued along with the resume token `issue`.
+    fn session_key(issue: &str) -> HmacKey {
+        let mut key = [0u8; 32];
+        key[..issue.len()].copy_from_slice(issue.as_bytes());
+        HmacKey::from_bytes(&key)
+    }
+
+    /// Accepts one client, checks its resume token and authenticates it.
+    async fn accept_and_authenticate(
+        listener: &TcpListener,
+        expected_resume: Option<&str>,
+        issue: &str,
+    ) -> Connection<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = Connection::new(stream);
+        match conn.read_message().await.unwrap() {
+            ControlMessage::Authenticate { resume_token, .. } => {
+                assert_eq!(resume_token.as_ref().map(|t| t.as_str()), expected_resume);
+            }
+            other => panic!("Expected Authenticate, got {other:?}"),
+        }
+        conn.write_message(&ControlMessage::AuthResponse {
+            success: true,
+            user_id: Some(UserId::new(7).unwrap()),
+            error: None,
+            resume_token: Some(ResumeToken::from(issue.to_string())),
+            min_client_version: None,
+            udp_key: Some(session_key(issue)),
+            voice_relay: None,
+        })
+        .await
+        .unwrap();
+        conn
+    }
+
+    async fn wait_for_state(
+        states: &mut watch::Receiver<ConnectionState>,
+        matches: impl Fn(&ConnectionState) -> bool,
+    ) -> ConnectionState {
+        with_default_timeout(states.wait_for(|state| matches(state)))
+            .await
+            .expect("Timed out waiting for connection state")
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_backoff_grows_to_cap_with_bounded_jitter() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            jitter: 0.5,
+            ..ReconnectPolicy::default()
+        };
+
+        assert_eq!(policy.delay(1, 0.5), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, 0.5), Duration::from_millis(200));
+        assert_eq!(policy.delay(4, 0.5), Duration::from_millis(800));
+        assert_eq!(policy.delay(20, 0.5), Duration::from_secs(2));
+
+        // Jitter spreads the delay by ±25% but never past the cap
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(150));
+        assert_eq!(policy.delay(2, 1.0), Duration::from_millis(250));
+        assert_eq!(policy.delay(20, 1.0), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_and_resumes_after_connection_loss() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, mut inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        let first = accept_and_authenticate(&listener, None, "token-1").await;
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { resumed: false, .. })
+        })
+        .await;
+
+        // Server goes away; the client backs off and presents its resume token
+        drop(first);
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Reconnecting { attempt: 1, .. })
+        })
+        .await;
+        let mut second = accept_and_authenticate(&listener, Some("token-1"), "token-2").await;
+        let state = wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { .. })
+        })
+        .await;
+        assert_eq!(
+            state,
+            ConnectionState::Connected {
+                user_id: Some(UserId::new(7).unwrap()),
+                resumed: true
+            }
+        );
+        let stats = connection.stats();
+        assert_eq!(stats.reconnects, 1);
+        assert!(stats.last_error.is_some());
+        // Voice is signed with the key of the new session
+        assert_eq!(connection.udp_key(), Some(session_key("token-2")));
+
+        // Traffic flows both ways on the new connection
+        connection
+            .send(ControlMessage::JoinChannel {
+                channel_id: ChannelId::new(3).unwrap(),
+            })
+            .unwrap();
+        loop {
+            match second.read_message().await.unwrap() {
+                ControlMessage::JoinChannel { channel_id } => {
+                    assert_eq!(channel_id, ChannelId::new(3).unwrap());
+                    break;
+                }
+                ControlMessage::Ping => {}
+                other => panic!("Unexpected message {other:?}"),
+            }
+        }
+        second
+            .write_message(&ControlMessage::ChannelLeft {
+                channel_id: ChannelId::new(3).unwrap(),
+            })
+            .await
+            .unwrap();
+        let received = with_default_timeout(inbound_rx.recv()).await.unwrap();
+        assert!(matches!(
+            received,
+            Some(ControlMessage::ChannelLeft { channel_id }) if channel_id.get() == 3
+        ));
+
+        connection.close();
+        assert_eq!(
+            *states.borrow(),
+            ConnectionState::Disconnected {
+                reason: None,
+                code: None,
+                min_client_version: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_silent_server_triggers_reconnect() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        // Keep the socket open but never answer pings
+        let _silent = accept_and_authenticate(&listener, None, "token-1").await;
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Reconnecting { .. })
+        })
+        .await;
+        let _second = accept_and_authenticate(&listener, Some("token-1"), "token-2").await;
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { resumed: true, .. })
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_resumes_after_network_partition() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let mut chaos = ChaosController::new();
+        let connector = ChaosConnector(addr, chaos.link("client-a"));
+        let connection = ServerConnection::spawn(connector, credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        let _first = accept_and_authenticate(&listener, None, "token-1").await;
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { resumed: false, .. })
+        })
+        .await;
+
+        // Cut the client off for longer than the heartbeat timeout
+        chaos.partition("client-a", Duration::ZERO, Duration::from_millis(300));
+        let script = chaos.run();
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Reconnecting { .. })
+        })
+        .await;
+
+        // Once the link heals the session resumes
+        let _second = accept_and_authenticate(&listener, Some("token-1"), "token-2").await;
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { resumed: true, .. })
+        })
+        .await;
+        script.await.unwrap();
+        assert_eq!(connection.stats().reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_credentials_do_not_retry() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = Connection::new(stream);
+        conn.read_message().await.unwrap();
+        conn.write_message(&ControlMessage::AuthResponse {
+            success: false,
+            user_id: None,
+            error: Some(Cow::Borrowed("Invalid token")),
+            resume_token: None,
+            min_client_version: None,
+            udp_key: None,
+            voice_relay: None,
+        })
+        .await
+        .unwrap();
+
+        let state = wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Disconnected { .. })
+        })
+        .await;
+        match state {
+            ConnectionState::Disconnected {
+                reason: Some(reason),
+                code: Some(code),
+                min_client_version: None,
+            } => {
+                assert_eq!(code, FleetNetErrorCode::AuthFailed);
+                assert!(reason.contains("Invalid token"))
+            }
+            other => panic!("Expected a disconnect reason, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outdated_client_learns_required_version() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        ScriptedPeer::new()
+            .expect("Authenticate from 1.0.0", |m| {
+                matches!(m, ControlMessage::Authenticate { client_version, .. } if client_version == "1.0.0")
+            })
+            .send(ControlMessage::AuthResponse {
+                success: false,
+                user_id: None,
+                error: Some(Cow::Borrowed("Client is too old")),
+                resume_token: None,
+                min_client_version: Some(Cow::Borrowed("1.2.0")),
+                udp_key: None,
+                voice_relay: None,
+            })
+            // An outdated client must not try again on the same connection
+            .expect_closed()
+            .play(&mut Connection::new(stream))
+            .await;
+
+        let state = wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Disconnected { .. })
+        })
+        .await;
+        assert!(matches!(
+            state,
+            ConnectionState::Disconnected {
+                min_client_version: Some(version),
+                ..
+            } if version == "1.2.0"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_authenticates_before_sending_queued_messages() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (recordings, mut recordings_rx) = mpsc::unbounded_channel();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection = ServerConnection::spawn(
+            RecordingConnector(addr, recordings),
+            credentials(),
+            fast_policy(),
+            inbound,
+        );
+        let channel_id = ChannelId::new(3).unwrap();
+        connection
+            .send(ControlMessage::JoinChannel { channel_id })
+            .unwrap();
+
+        let mut conn = accept_and_authenticate(&listener, None, "token-1").await;
+        loop {
+            match with_default_timeout(conn.read_message())
+                .await
+                .unwrap()
+                .unwrap()
+            {
+                ControlMessage::JoinChannel { .. } => break,
+                ControlMessage::Ping => conn.write_message(&ControlMessage::Pong).await.unwrap(),
+                other => panic!("Expected JoinChannel, got {other:?}"),
+            }
+        }
+
+        let recording = recordings_rx.recv().await.unwrap();
+        recording.assert_before::<ControlMessage>(
+            Direction::Written,
+            ("Authenticate", |m| {
+                matches!(m, ControlMessage::Authenticate { .. })
+            }),
+            ("JoinChannel", |m| {
+                matches!(m, ControlMessage::JoinChannel { .. })
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_info_may_precede_auth_response() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, mut inbound_rx) = mpsc::unbounded_channel();
+        let connection = ServerConnection::spawn(
+            TcpConnector(addr),
+            credentials(),
+            ReconnectPolicy::default(),
+            inbound,
+        );
+        let mut states = connection.subscribe_state();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let received = ScriptedPeer::new()
+            .expect("Authenticate", |m| {
+                matches!(
+                    m,
+                    ControlMessage::Authenticate {
+                        resume_token: None,
+                        ..
+                    }
+                )
+            })
+            .send(create_test_server_info("Fleet Net", "0.1.0"))
+            .wait(Duration::from_millis(30))
+            .send(ControlMessage::AuthResponse {
+                success: true,
+                user_id: Some(UserId::new(7).unwrap()),
+                error: None,
+                resume_token: None,
+                min_client_version: None,
+                udp_key: None,
+                voice_relay: None,
+            })
+            // The first heartbeat goes out as soon as the session starts
+            .expect_message(ControlMessage::Ping)
+            .send(ControlMessage::Pong)
+            .send(ControlMessage::Ping)
+            .expect_message(ControlMessage::Pong)
+            .play(&mut Connection::new(stream))
+            .await;
+        assert_eq!(received.len(), 3);
+
+        assert_is_server_info(&inbound_rx.recv().await.unwrap(), "Fleet Net");
+        let state = wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { .. })
+        })
+        .await;
+        assert_eq!(
+            state,
+            ConnectionState::Connected {
+                user_id: Some(UserId::new(7).unwrap()),
+                resumed: false,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_timeout_follows_paused_time() {
+        let (client, server) = mock_connection_pair_default();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection = ServerConnection::spawn(
+            DuplexConnector(Mutex::new(Some(client))),
+            credentials(),
+            ReconnectPolicy::default(),
+            inbound,
+        );
+
+        let mut server = Connection::new(server);
+        ScriptedPeer::new()
+            .expect("Authenticate", |m| {
+                matches!(m, ControlMessage::Authenticate { .. })
+            })
+            .send(ControlMessage::AuthResponse {
+                success: true,
+                user_id: Some(UserId::new(7).unwrap()),
+                error: None,
+                resume_token: None,
+                min_client_version: None,
+                udp_key: None,
+                voice_relay: None,
+            })
+            .expect_message(ControlMessage::Ping)
+            .play(&mut server)
+            .await;
+
+        // Unanswered heartbeats go out every 5s, but 15s of silence are
+        // needed to give up on the server
+        advance_in_steps(Duration::from_secs(10), Duration::from_secs(5)).await;
+        assert!(matches!(
+            connection.state(),
+            ConnectionState::Connected { .. }
+        ));
+        advance(Duration::from_secs(5)).await;
+        assert!(matches!(
+            connection.state(),
+            ConnectionState::Reconnecting { attempt: 1, .. }
+        ));
+        assert!(connection
+            .stats()
+            .last_error
+            .is_some_and(|e| e.contains("stopped answering heartbeats")));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_measure_round_trip() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), fast_policy(), inbound);
+
+        let mut conn = accept_and_authenticate(&listener, None, "token-1").await;
+        let server = tokio::spawn(async move {
+            while let Ok(message) = conn.read_message().await {
+                if matches!(message, ControlMessage::Ping)
+                    && conn.write_message(&ControlMessage::Pong).await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let measured = fleet_test_support::wait_until(
+            Duration::from_secs(2),
+            Duration::from_millis(10),
+            || connection.stats().smoothed_rtt_ms.is_some(),
+        )
+        .await;
+        assert!(measured);
+        let stats = connection.stats();
+        assert!(stats.rtt_ms.unwrap() < 1000.0);
+        assert_eq!(stats.reconnects, 0);
+        assert_eq!(stats.last_error, None);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_flushes_queued_messages() {
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        let mut conn = accept_and_authenticate(&listener, None, "token-1").await;
+        wait_for_state(&mut states, |s| {
+            matches!(s, ConnectionState::Connected { .. })
+        })
+        .await;
+
+        connection
+            .send(ControlMessage::LeaveChannel {
+                channel_id: ChannelId::new(3).unwrap(),
+            })
+            .unwrap();
+        connection.disconnect(Duration::from_secs(2)).await;
+        assert_eq!(
+            *states.borrow(),
+            ConnectionState::Disconnected {
+                reason: None,
+                code: None,
+                min_client_version: None,
+            }
+        );
+
+        // The queued message arrives, then the stream ends
+        let mut left = false;
+        loop {
+            match conn.read_message().await {
+                Ok(ControlMessage::LeaveChannel { channel_id }) if channel_id.get() == 3 => {
+                    left = true
+                }
+                Ok(ControlMessage::Ping) => {}
+                Ok(other) => panic!("Unexpected message {other:?}"),
+                Err(_) => break,
+            }
+        }
+        assert!(left);
+    }
+
+    struct UntrustedConnector;
+
+    impl Connector for UntrustedConnector {
+        type Stream = TcpStream;
+
+        async fn connect(&self) -> Result<TcpStream, FleetNetError> {
+            Err(FleetNetError::EncryptionError(Cow::Borrowed(
+                "Server certificate rejected",
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_certificate_errors_do_not_retry() {
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let connection =
+            ServerConnection::spawn(UntrustedConnector, credentials(), fast_policy(), inbound);
+        let mut states = connection.subscribe_state();
+
+        let state =
+            wait_for_state(&mut states, |s| !matches!(s, ConnectionState::Connecting)).await;
+        assert!(matches!(
+            state,
+            ConnectionState::Disconnected {
+                reason: Some(_),
+                ..
+            }
+        ));
+    }
+
+    /// The error from connecting to a server presenting `server`'s
+    /// certificates while trusting only `ca`.
+    async fn tls_connect_error(ca: &TestCertBundle, server: &TestCertBundle) -> FleetNetError {
+        init_crypto_once();
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let acceptor = create_tls_acceptor(server_config_from_bundle(server));
+        let _server = AbortOnDrop(tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        }));
+        let connector = TlsServerConnector::new(
+            format!("localhost:{}", addr.port()),
+            client_config_from_bundle(ca),
+        )
+        .unwrap();
+        connector.connect().await.err().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connector_falls_back_to_other_endpoints() {
+        init_crypto_once();
+        let bundle = generate_test_certs("localhost");
+        let (listener, live) = bind_ephemeral().await.unwrap();
+        let dead = bind_ephemeral().await.unwrap().1;
+        let acceptor = create_tls_acceptor(server_config_from_bundle(&bundle));
+        let _server = AbortOnDrop(tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _stream = acceptor.accept(stream).await.unwrap();
+            std::future::pending::<()>().await;
+        }));
+
+        // The certificate is checked against the first endpoint's name
+        let connector = TlsServerConnector::new(
+            format!("localhost:{}", dead.port()),
+            client_config_from_bundle(&bundle),
+        )
+        .unwrap()
+        .with_fallbacks(["unresolvable.invalid:1".to_string(), live.to_string()]);
+        assert_eq!(connector.connected_address(), None);
+        connector.connect().await.unwrap();
+        assert_eq!(connector.clone().connected_address(), Some(live));
+    }
+
+    #[tokio::test]
+    async fn test_voice_falls_back_to_the_tunnel_without_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let _responder = AbortOnDrop(tokio::spawn(async move {
+            let _ = ping::serve_ping(socket, || (0, 0)).await;
+        }));
+        assert_eq!(
+            VoiceTransport::probe(address, Duration::from_millis(500)).await,
+            VoiceTransport::Udp
+        );
+
+        // Nothing answers on a socket that drops every probe
+        let blocked = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        assert_eq!(
+            VoiceTransport::probe(blocked.local_addr().unwrap(), Duration::from_millis(50)).await,
+            VoiceTransport::Tunnel
+        );
+    }
+
+    #[test]
+    fn test_voice_is_tunneled_while_udp_is_blocked_or_has_nowhere_to_go() {
+        let key = HmacKey::from_bytes(&[7; 32]);
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(9).unwrap(),
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            padded: false,
+            frames: 1,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let packet = AudioPacket::new_signed(header, vec![1, 2, 3], &key);
+        let voice_port: SocketAddr = "192.0.2.1:7001".parse().unwrap();
+
+        let OutgoingVoice::Datagram(address, datagram) =
+            VoiceTransport::Udp.prepare(&packet, Some(64), Some(voice_port))
+        else {
+            panic!("Voice should go over UDP");
+        };
+        assert_eq!(address, voice_port);
+        assert_eq!(datagram.len(), 64);
+        assert_eq!(AudioPacket::from_bytes(&datagram).unwrap(), packet);
+
+        for (transport, udp_address) in [
+            (VoiceTransport::Tunnel, Some(voice_port)),
+            (VoiceTransport::Udp, None),
+        ] {
+            let OutgoingVoice::Tunneled(ControlMessage::VoiceTunnel { packet: tunneled }) =
+                transport.prepare(&packet, None, udp_address)
+            else {
+                panic!("Voice should be tunneled over {transport:?} to {udp_address:?}");
+            };
+            assert_eq!(tunneled, packet.to_bytes().to_vec());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_certificates_are_explained() {
+        let (ca, server) = generate_expired_certs("localhost");
+        let expired = tls_connect_error(&ca, &server).await;
+        let (ca, server) = generate_not_yet_valid_certs("localhost");
+        let not_yet_valid = tls_connect_error(&ca, &server).await;
+        let (ca, server) = generate_wrong_hostname_certs("fleet.test");
+        let wrong_name = tls_connect_error(&ca, &server).await;
+        let chain = generate_oversized_chain("localhost");
+        let too_long = tls_connect_error(&chain.root, &chain.server).await;
+
+        for (error, reason) in [
+            (expired, "certificate expired"),
+            (not_yet_valid, "certificate not valid yet"),
+            (wrong_name, "not valid for name \"localhost\""),
+            (too_long, "MaximumPathDepthExceeded"),
+        ] {
+            assert_eq!(error.code(), FleetNetErrorCode::EncryptionError);
+            assert!(
+                error.to_string().contains(reason),
+                "Expected {reason:?} in {error}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        // Nothing listens on this port once the listener is dropped
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        drop(listener);
+
+        let (inbound, _inbound_rx) = mpsc::unbounded_channel();
+        let policy = ReconnectPolicy {
+            max_attempts: Some(2),
+            ..fast_policy()
+        };
+        let connection =
+            ServerConnection::spawn(TcpConnector(addr), credentials(), policy, inbound);
+        let mut states = connection.subscribe_state();
+
+        wait_for_state(&mut states, |s| {
+            matches!(
+                s,
+                ConnectionState::Disconnected {
+                    reason: Some(_),
+                    ..
+                }
+            )
+        })
+        .await;
+    }
+}
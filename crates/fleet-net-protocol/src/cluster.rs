@@ -0,0 +1,172 @@
+//! Internal protocol spoken between a coordinator node and its voice relays.
+//!
+//! Relays are stateless UDP forwarders: everything they know about sessions and
+//! channel membership is pushed to them by the coordinator over a TLS connection
+//! using the same length-prefixed framing as the client control channel.
+
+use crate::hmac::HmacKey;
+use fleet_net_common::audio::TransmitMode;
+use fleet_net_common::channel::Channel;
+use fleet_net_common::types::{ChannelId, UserId};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::net::SocketAddr;
+
+/// A single forwarding target for a channel on a relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RelaySubscriber {
+    /// User the voice address belongs to.
+    pub user_id: UserId,
+    /// UDP address the user's voice socket is reachable at.
+    pub address: SocketAddr,
+}
+
+/// What a relay needs to check a user's voice the way the coordinator
+/// does, and to sign what it forwards to them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelaySender {
+    /// Session key the user signs their voice packets with.
+    pub udp_key: HmacKey,
+    /// Whether the user's voice may go out, as they are active and not
+    /// server muted.
+    pub may_transmit: bool,
+    /// Whether the user lacks the permission to speak, and only spectates
+    /// in channels in spectator mode.
+    pub listen_only: bool,
+    pub transmit_mode: TransmitMode,
+    /// Rank for preemption, lower values first; `None` ranks last.
+    pub transmit_priority: Option<u32>,
+    /// Channels the user transmits on at once when simulcasting.
+    pub transmit_targets: Vec<ChannelId>,
+    /// Whether the user gets their voice over the control connection, so
+    /// the relay passes it to the coordinator instead of sending it.
+    pub tunneled: bool,
+}
+
+// Messages exchanged between coordinator and relay nodes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterMessage {
+    // Relay -> Coordinator
+    /// First message on a relay connection. The coordinator rejects relays
+    /// that do not present the cluster's shared secret.
+    RegisterRelay {
+        relay_id: String,
+        voice_address: SocketAddr,
+        capacity: u32,
+        cluster_secret: String,
+    },
+    RelayStats {
+        relay_id: String,
+        active_users: u32,
+        packets_forwarded: u64,
+    },
+    /// A user's voice is now received from `address`, as their signed
+    /// packets reaching the relay show.
+    VoiceAddress {
+        user_id: UserId,
+        address: SocketAddr,
+    },
+    /// A packet for a user whose voice is tunneled, for the coordinator to
+    /// pass on over their control connection.
+    TunneledVoice {
+        user_id: UserId,
+        packet: Vec<u8>,
+    },
+
+    // Coordinator -> Relay
+    RelayAccepted {
+        relay_id: String,
+    },
+    RelayRejected {
+        reason: Cow<'static, str>,
+    },
+    /// Replaces the full subscriber list the relay holds for a channel.
+    RouteUpdate {
+        channel_id: ChannelId,
+        subscribers: Vec<RelaySubscriber>,
+    },
+    RouteRemoved {
+        channel_id: ChannelId,
+    },
+    /// Replaces every channel the relay checks voice against.
+    ChannelsUpdated {
+        channels: Vec<Channel>,
+    },
+    /// Replaces what the relay knows about a connected user.
+    SenderUpdate {
+        user_id: UserId,
+        sender: RelaySender,
+    },
+    /// The user disconnected; their voice is no longer forwarded.
+    SenderRemoved {
+        user_id: UserId,
+    },
+
+    Heartbeat,
+    HeartbeatAck,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use fleet_test_support::connected_tcp_pair;
+
+    #[test]
+    fn test_cluster_message_serialization() {
+        let msg = ClusterMessage::RouteUpdate {
+            channel_id: ChannelId::new(7).unwrap(),
+            subscribers: vec![RelaySubscriber {
+                user_id: UserId::new(3).unwrap(),
+                address: "127.0.0.1:9000".parse().unwrap(),
+            }],
+        };
+
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"route_update\""));
+
+        let parsed: ClusterMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_sender_update_carries_the_session_key() {
+        let msg = ClusterMessage::SenderUpdate {
+            user_id: UserId::new(3).unwrap(),
+            sender: RelaySender {
+                udp_key: HmacKey::from_bytes(&[0x5a; 32]),
+                may_transmit: true,
+                listen_only: false,
+                transmit_mode: TransmitMode::PushToTalk,
+                transmit_priority: Some(10),
+                transmit_targets: vec![ChannelId::new(2).unwrap()],
+                tunneled: false,
+            },
+        };
+
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"sender_update\""));
+
+        let parsed: ClusterMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_messages_share_connection_framing() {
+        let (coordinator_stream, relay_stream) = connected_tcp_pair().await.unwrap();
+        let mut coordinator = Connection::new(coordinator_stream);
+        let mut relay = Connection::new(relay_stream);
+
+        let register = ClusterMessage::RegisterRelay {
+            relay_id: "relay-eu-1".to_string(),
+            voice_address: "10.0.0.5:7001".parse().unwrap(),
+            capacity: 250,
+            cluster_secret: "s3cret".to_string(),
+        };
+        relay.write_frame(&register).await.unwrap();
+
+        let received: ClusterMessage = coordinator.read_frame().await.unwrap();
+        assert_eq!(received, register);
+    }
+}
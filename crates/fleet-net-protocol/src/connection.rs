@@ -1,7 +1,11 @@
 use crate::message::ControlMessage;
 use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::{ServerLimits, MAX_CONTROL_MESSAGE_LEN};
+use fleet_net_common::validation::Validate;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::borrow::Cow;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 
 pub struct Connection<S>
 where
@@ -19,43 +23,210 @@ where
     }
 
     pub async fn write_message(&mut self, message: &ControlMessage) -> Result<(), FleetNetError> {
-        // Serialize the message to JSON
-        let json = serde_json::to_string(message)?;
+        self.write_frame(message).await
+    }
+
+    pub async fn read_message(&mut self) -> Result<ControlMessage, FleetNetError> {
+        self.read_frame().await
+    }
+
+    /// Reads a message and checks it against `limits` before handing it on.
+    ///
+    /// A message that fails validation is returned as an error, but the
+    /// frame has been consumed, so the connection can still be used to
+    /// report it to the peer.
+    pub async fn read_validated_message(
+        &mut self,
+        limits: &ServerLimits,
+    ) -> Result<ControlMessage, FleetNetError> {
+        self.read_validated_frame(limits).await
+    }
 
-        // Write the length of the message first
-        let length = json.len() as u32;
-        self.stream.write_all(&length.to_be_bytes()).await?;
+    /// Reads a frame like [`Connection::read_frame`] and validates it.
+    pub async fn read_validated_frame<T: DeserializeOwned + Validate>(
+        &mut self,
+        limits: &ServerLimits,
+    ) -> Result<T, FleetNetError> {
+        let frame: T = self.read_frame().await?;
+        frame.validate(limits)?;
+        Ok(frame)
+    }
+
+    /// Write any serializable frame using the length-prefixed JSON framing.
+    ///
+    /// This is what `write_message` uses for `ControlMessage`; it is exposed so that
+    /// other message families (e.g. the internal cluster protocol) share the same framing.
+    pub async fn write_frame<T: Serialize>(&mut self, frame: &T) -> Result<(), FleetNetError> {
+        write_frame_to(&mut self.stream, frame).await
+    }
 
-        // Then write the actual message
-        self.stream.write_all(json.as_bytes()).await?;
+    /// Read a length-prefixed JSON frame and deserialize it into `T`.
+    pub async fn read_frame<T: DeserializeOwned>(&mut self) -> Result<T, FleetNetError> {
+        read_frame_from(&mut self.stream).await
+    }
 
-        Ok(())
+    /// Split the connection into independently owned reader and writer halves.
+    ///
+    /// Useful when one task consumes inbound frames while another pushes outbound ones.
+    pub fn into_split(
+        self,
+    ) -> (
+        ConnectionReader<ReadHalf<S>>,
+        ConnectionWriter<WriteHalf<S>>,
+    ) {
+        let (reader, writer) = tokio::io::split(self.stream);
+        (
+            ConnectionReader { stream: reader },
+            ConnectionWriter { stream: writer },
+        )
     }
+}
 
+/// Read half of a split [`Connection`].
+pub struct ConnectionReader<R>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    stream: R,
+}
+
+impl<R> ConnectionReader<R>
+where
+    R: AsyncRead + Unpin + Send,
+{
     pub async fn read_message(&mut self) -> Result<ControlMessage, FleetNetError> {
-        // First read the length of the incoming message
-        let mut length_bytes = [0u8; 4];
-        self.stream.read_exact(&mut length_bytes).await?;
-
-        // Convert bytes to u32
-        let length = u32::from_be_bytes(length_bytes);
-
-        // Read the actual message data
-        let mut buffer = vec![0u8; length as usize];
-        self.stream.read_exact(&mut buffer).await?;
-
-        // Test if the length matches the buffer size
-        if buffer.len() != length as usize {
-            return Err(FleetNetError::PacketError(Cow::Borrowed(
-                "Received message length does not match expected length",
-            )));
-        }
+        self.read_frame().await
+    }
+
+    pub async fn read_frame<T: DeserializeOwned>(&mut self) -> Result<T, FleetNetError> {
+        read_frame_from(&mut self.stream).await
+    }
+
+    /// See [`Connection::read_validated_message`].
+    pub async fn read_validated_message(
+        &mut self,
+        limits: &ServerLimits,
+    ) -> Result<ControlMessage, FleetNetError> {
+        self.read_validated_frame(limits).await
+    }
+
+    /// See [`Connection::read_validated_frame`].
+    pub async fn read_validated_frame<T: DeserializeOwned + Validate>(
+        &mut self,
+        limits: &ServerLimits,
+    ) -> Result<T, FleetNetError> {
+        let frame: T = self.read_frame().await?;
+        frame.validate(limits)?;
+        Ok(frame)
+    }
+}
+
+/// Write half of a split [`Connection`].
+pub struct ConnectionWriter<W>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    stream: W,
+}
+
+impl<W> ConnectionWriter<W>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    pub async fn write_message(&mut self, message: &ControlMessage) -> Result<(), FleetNetError> {
+        self.write_frame(message).await
+    }
+
+    pub async fn write_frame<T: Serialize>(&mut self, frame: &T) -> Result<(), FleetNetError> {
+        write_frame_to(&mut self.stream, frame).await
+    }
+
+    /// Flushes and closes the write side, e.g. sending a TLS close_notify.
+    pub async fn shutdown(&mut self) -> Result<(), FleetNetError> {
+        Ok(self.stream.shutdown().await?)
+    }
+}
+
+async fn write_frame_to<W, T>(stream: &mut W, frame: &T) -> Result<(), FleetNetError>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    stream.write_all(&encode_frame(frame)?).await?;
+    Ok(())
+}
+
+async fn read_frame_from<R, T>(stream: &mut R) -> Result<T, FleetNetError>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    // First read the length of the incoming message
+    let mut length_bytes = [0u8; 4];
+    stream.read_exact(&mut length_bytes).await?;
+
+    let length = frame_length(length_bytes)?;
+
+    // Read the actual message data
+    let mut buffer = vec![0u8; length];
+    stream.read_exact(&mut buffer).await?;
+
+    // Test if the length matches the buffer size
+    if buffer.len() != length {
+        return Err(FleetNetError::PacketError(Cow::Borrowed(
+            "Received message length does not match expected length",
+        )));
+    }
+
+    // Deserialize the JSON frame
+    let frame: T = serde_json::from_slice(&buffer)?;
 
-        // Deserialize the JSON message
-        let message: ControlMessage = serde_json::from_slice(&buffer)?;
+    Ok(frame)
+}
+
+/// Decodes the frame at the start of `data`, for input that is already in
+/// memory rather than on a stream.
+///
+/// Returns the frame and the number of bytes it took, or `None` if `data`
+/// ends before the frame does. Frames are checked exactly as
+/// [`Connection::read_frame`] checks them.
+pub fn decode_frame<T: DeserializeOwned>(data: &[u8]) -> Result<Option<(T, usize)>, FleetNetError> {
+    let Some((length_bytes, rest)) = data.split_first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let length = frame_length(*length_bytes)?;
+    let Some(body) = rest.get(..length) else {
+        return Ok(None);
+    };
+    Ok(Some((serde_json::from_slice(body)?, 4 + length)))
+}
+
+/// Encodes `frame` as JSON behind its big-endian length prefix, exactly as
+/// [`Connection::write_frame`] sends it.
+pub fn encode_frame<T: Serialize>(frame: &T) -> Result<Vec<u8>, FleetNetError> {
+    // Serialize after a placeholder prefix, then fill the length in
+    let mut buf = vec![0u8; 4];
+    serde_json::to_writer(&mut buf, frame)?;
+    let length = buf.len() - 4;
+    if length > MAX_CONTROL_MESSAGE_LEN {
+        return Err(oversized());
+    }
+    buf[..4].copy_from_slice(&(length as u32).to_be_bytes());
+    Ok(buf)
+}
 
-        Ok(message)
+/// The body length announced by a frame's prefix.
+fn frame_length(prefix: [u8; 4]) -> Result<usize, FleetNetError> {
+    let length = u32::from_be_bytes(prefix) as usize;
+    // Refuse before allocating, so a bogus length cannot exhaust memory
+    if length > MAX_CONTROL_MESSAGE_LEN {
+        return Err(oversized());
     }
+    Ok(length)
+}
+
+fn oversized() -> FleetNetError {
+    FleetNetError::PacketError(Cow::Borrowed("Message exceeds the maximum size"))
 }
 
 #[cfg(test)]
@@ -81,6 +252,13 @@ mod tests {
             version: Cow::Borrowed("1.0.0"),
             user_count: 0,
             channel_count: 0,
+            region: None,
+            ping_port: None,
+            voice_port: None,
+            max_users: None,
+            limits: None,
+            motd: None,
+            voice_padding: None,
         };
 
         // Use a task to avoid deadlock
@@ -98,6 +276,7 @@ mod tests {
                 version,
                 user_count,
                 channel_count,
+                ..
             } => {
                 assert_eq!(name, "TestServer");
                 assert_eq!(version, Cow::Borrowed("1.0.0"));
@@ -109,6 +288,107 @@ mod tests {
 
         server_task.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_split_connection_reads_and_writes_independently() {
+        let (server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+
+        let (mut server_reader, mut server_writer) = Connection::new(server_stream).into_split();
+        let mut client_connection = Connection::new(client_stream);
+
+        // Writer half can push while the reader half is owned elsewhere
+        server_writer
+            .write_message(&ControlMessage::Ping)
+            .await
+            .unwrap();
+        assert!(matches!(
+            client_connection.read_message().await.unwrap(),
+            ControlMessage::Ping
+        ));
+
+        client_connection
+            .write_message(&ControlMessage::Pong)
+            .await
+            .unwrap();
+        assert!(matches!(
+            server_reader.read_message().await.unwrap(),
+            ControlMessage::Pong
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_messages_are_refused_on_read() {
+        let (server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+        let mut server_connection = Connection::new(server_stream);
+        let mut client_connection = Connection::new(client_stream);
+        let limits = ServerLimits::default();
+
+        client_connection
+            .write_message(&ControlMessage::SetNickname {
+                nickname: Some(" Viper".to_string()),
+            })
+            .await
+            .unwrap();
+        client_connection
+            .write_message(&ControlMessage::Ping)
+            .await
+            .unwrap();
+
+        let err = server_connection
+            .read_validated_message(&limits)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: nickname: invalid_format"
+        );
+
+        // The bad frame was consumed, so the next one reads normally
+        assert!(matches!(
+            server_connection.read_validated_message(&limits).await,
+            Ok(ControlMessage::Ping)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frames_are_refused() {
+        let (mut server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+        let mut client_connection = Connection::new(client_stream);
+
+        // Only the length prefix is sent; it must be refused without waiting for the body
+        let length = (MAX_CONTROL_MESSAGE_LEN as u32 + 1).to_be_bytes();
+        server_stream.write_all(&length).await.unwrap();
+        assert!(matches!(
+            client_connection.read_message().await,
+            Err(FleetNetError::PacketError(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_frame_from_memory() {
+        let json = br#"{"type":"join_channel","channel_id":3}"#;
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json);
+        data.extend_from_slice(b"next");
+
+        let (message, used) = decode_frame::<ControlMessage>(&data).unwrap().unwrap();
+        assert!(
+            matches!(message, ControlMessage::JoinChannel { channel_id } if channel_id.get() == 3)
+        );
+        assert_eq!(used, 4 + json.len());
+
+        // Partial frames wait for more data; bad ones fail
+        assert!(decode_frame::<ControlMessage>(&data[..10])
+            .unwrap()
+            .is_none());
+        assert!(decode_frame::<ControlMessage>(&[0, 0]).unwrap().is_none());
+        assert!(decode_frame::<ControlMessage>(&u32::MAX.to_be_bytes()).is_err());
+        assert!(decode_frame::<ControlMessage>(&[0, 0, 0, 2, b'{', b'x']).is_err());
+
+        // Encoding gives back the same frame
+        let encoded = encode_frame(&message).unwrap();
+        assert_eq!(encoded, &data[..used]);
+    }
 }
 
 #[cfg(test)]
@@ -174,6 +454,13 @@ mod tls_tests {
                 version: Cow::Borrowed("1.0.0"),
                 user_count: 42,
                 channel_count: 5,
+                region: None,
+                ping_port: None,
+                voice_port: None,
+                max_users: None,
+                limits: None,
+                motd: None,
+                voice_padding: None,
             };
             conn.write_message(&msg).await.unwrap();
         });
@@ -193,6 +480,7 @@ mod tls_tests {
                 version,
                 user_count,
                 channel_count,
+                ..
             } => {
                 assert_eq!(name, "TLSTestServer");
                 assert_eq!(version, Cow::Borrowed("1.0.0"));
@@ -265,6 +553,13 @@ mod tls_tests {
                 version: Cow::Borrowed("1.0.0"),
                 user_count: 1,
                 channel_count: 1,
+                region: None,
+                ping_port: None,
+                voice_port: None,
+                max_users: None,
+                limits: None,
+                motd: None,
+                voice_padding: None,
             };
             conn.write_message(&msg).await.unwrap();
         });
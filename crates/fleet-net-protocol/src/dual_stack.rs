@@ -0,0 +1,261 @@
+//! IPv6 and dual-stack sockets.
+//!
+//! Binding the IPv6 unspecified address `[::]` with [`bind_tcp`] or
+//! [`bind_udp`] gives a dual-stack socket that takes IPv4 peers too,
+//! whatever the host's `IPV6_V6ONLY` default. Those peers then show up as
+//! v4-mapped addresses such as `::ffff:192.0.2.1`, while the same client
+//! reached over an IPv4 socket is plain `192.0.2.1`. Addresses are stored
+//! and compared in [`canonical`] form, and turned back into one a socket
+//! can send to with [`reachable_from`].
+//!
+//! Clients reaching a server by several addresses race them with
+//! [`connect_tcp`], Happy Eyeballs style (RFC 8305), so a broken IPv6 route
+//! or a dead fallback costs a fraction of a second rather than a timeout.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use tokio::task::JoinSet;
+use tracing::debug;
+
+/// Pending connections a listener queues, as tokio's own `bind` does.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Head start each connection attempt gets before the next one is raced
+/// against it, as RFC 8305 recommends.
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// `address` with a v4-mapped IPv6 address as the plain IPv4 one.
+pub fn canonical(address: SocketAddr) -> SocketAddr {
+    SocketAddr::new(address.ip().to_canonical(), address.port())
+}
+
+/// `target` as a socket bound to `local` can send to it: IPv4 targets of
+/// an IPv6 socket are v4-mapped, as only dual-stack sockets reach them.
+pub fn reachable_from(local: SocketAddr, target: SocketAddr) -> SocketAddr {
+    match (local.ip(), target.ip()) {
+        (IpAddr::V6(_), IpAddr::V4(ip)) => {
+            SocketAddr::new(ip.to_ipv6_mapped().into(), target.port())
+        }
+        _ => target,
+    }
+}
+
+/// Binds a TCP listener to the first address `address` resolves to,
+/// dual-stack if that is `[::]`.
+pub async fn bind_tcp(address: impl ToSocketAddrs) -> io::Result<TcpListener> {
+    let address = resolve(address).await?;
+    if !is_dual_stack(address) {
+        return TcpListener::bind(address).await;
+    }
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a UDP socket to the first address `address` resolves to,
+/// dual-stack if that is `[::]`.
+pub async fn bind_udp(address: impl ToSocketAddrs) -> io::Result<UdpSocket> {
+    let address = resolve(address).await?;
+    if !is_dual_stack(address) {
+        return UdpSocket::bind(address).await;
+    }
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// `addresses` in the order [`connect_tcp`] tries them: without duplicates,
+/// alternating between address families starting with the first address's,
+/// each family in its own order.
+pub fn interleave(addresses: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut unique: Vec<SocketAddr> = Vec::with_capacity(addresses.len());
+    for &address in addresses {
+        if !unique.contains(&address) {
+            unique.push(address);
+        }
+    }
+    let Some(first) = unique.first() else {
+        return unique;
+    };
+    let preferred_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = unique
+        .into_iter()
+        .partition(|address| address.is_ipv6() == preferred_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop());
+        ordered.extend(other.pop());
+    }
+    ordered
+}
+
+/// Connects to whichever of `addresses` answers first.
+///
+/// Attempts start in [`interleave`]d order, each `attempt_delay` after the
+/// previous one or as soon as it fails, and keep running side by side; the
+/// first to connect wins and the rest are dropped. Fails with the last
+/// error once every attempt has.
+pub async fn connect_tcp(
+    addresses: &[SocketAddr],
+    attempt_delay: Duration,
+) -> io::Result<TcpStream> {
+    let mut queue = interleave(addresses).into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to");
+    loop {
+        if let Some(address) = queue.next() {
+            attempts.spawn(async move { (address, TcpStream::connect(address).await) });
+        }
+        let finished = if queue.len() > 0 {
+            match tokio::time::timeout(attempt_delay, attempts.join_next()).await {
+                Ok(finished) => finished,
+                // Slow, not failed: race the next address against it
+                Err(_) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+        match finished {
+            Some(Ok((_, Ok(stream)))) => return Ok(stream),
+            Some(Ok((address, Err(e)))) => {
+                debug!("Connecting to {address} failed: {e}");
+                last_error = e;
+            }
+            Some(Err(e)) => last_error = io::Error::other(e),
+            None => return Err(last_error),
+        }
+    }
+}
+
+async fn resolve(address: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Address resolved to nothing"))
+}
+
+fn is_dual_stack(address: SocketAddr) -> bool {
+    matches!(address.ip(), IpAddr::V6(ip) if ip.is_unspecified())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_v4_mapped_addresses_are_stored_as_ipv4() {
+        let mapped: SocketAddr = "[::ffff:192.0.2.1]:5000".parse().unwrap();
+        let plain: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:5000".parse().unwrap();
+        assert_eq!(canonical(mapped), plain);
+        assert_eq!(canonical(plain), plain);
+        assert_eq!(canonical(v6), v6);
+
+        let dual: SocketAddr = "[::]:7000".parse().unwrap();
+        let v4: SocketAddr = "0.0.0.0:7000".parse().unwrap();
+        assert_eq!(reachable_from(dual, plain), mapped);
+        assert_eq!(reachable_from(dual, v6), v6);
+        assert_eq!(reachable_from(v4, plain), plain);
+    }
+
+    #[test]
+    fn test_interleave_alternates_families() {
+        let addresses: Vec<SocketAddr> = [
+            "[2001:db8::1]:7000",
+            "[2001:db8::2]:7000",
+            "[2001:db8::3]:7000",
+            "192.0.2.1:7000",
+            "[2001:db8::1]:7000",
+            "192.0.2.2:7000",
+        ]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+        let ordered: Vec<String> = interleave(&addresses)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            ordered,
+            [
+                "[2001:db8::1]:7000",
+                "192.0.2.1:7000",
+                "[2001:db8::2]:7000",
+                "192.0.2.2:7000",
+                "[2001:db8::3]:7000",
+            ]
+        );
+        assert!(interleave(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_tcp_falls_back_past_dead_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+        let dead = {
+            let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            closed.local_addr().unwrap()
+        };
+
+        let stream = connect_tcp(&[dead, live], CONNECTION_ATTEMPT_DELAY)
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live);
+
+        assert!(connect_tcp(&[dead], CONNECTION_ATTEMPT_DELAY)
+            .await
+            .is_err());
+        assert!(connect_tcp(&[], CONNECTION_ATTEMPT_DELAY).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unspecified_ipv6_takes_both_families() {
+        // Skip on hosts without IPv6
+        let Ok(socket) = bind_udp("[::]:0").await else {
+            return;
+        };
+        let port = socket.local_addr().unwrap().port();
+        for peer in ["127.0.0.1:0", "[::1]:0"] {
+            let client = UdpSocket::bind(peer).await.unwrap();
+            let server = SocketAddr::new(client.local_addr().unwrap().ip(), port);
+            client.send_to(b"ping", server).await.unwrap();
+
+            let mut buf = [0u8; 8];
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"ping");
+            assert_eq!(canonical(from), client.local_addr().unwrap());
+            let reply = reachable_from(socket.local_addr().unwrap(), canonical(from));
+            socket.send_to(b"pong", reply).await.unwrap();
+            let len = client.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"pong");
+        }
+
+        let listener = bind_tcp("[::]:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        for host in ["127.0.0.1", "::1"] {
+            let address = SocketAddr::new(host.parse().unwrap(), port);
+            let (client, accepted) = tokio::join!(TcpStream::connect(address), listener.accept());
+            let (mut server, from) = accepted.unwrap();
+            let mut client = client.unwrap();
+            assert_eq!(canonical(from).ip(), address.ip());
+            client.write_all(b"hi").await.unwrap();
+            let mut buf = [0u8; 2];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hi");
+        }
+    }
+}
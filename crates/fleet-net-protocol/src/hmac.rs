@@ -1,8 +1,15 @@
+use fleet_net_common::error::FleetNetError;
 use hmac::{Hmac, Mac};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sha2::Sha256;
+use std::borrow::Cow;
+use std::fmt;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// A session's key for signing voice packets, sent to the client hex-encoded
+/// in its [`ControlMessage::AuthResponse`](crate::message::ControlMessage::AuthResponse).
+#[derive(Clone, PartialEq, Eq)]
 pub struct HmacKey {
     key: [u8; 32], // HMAC key must be 32 bytes for SHA-256
 }
@@ -21,6 +28,47 @@ impl HmacKey {
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.key
     }
+
+    /// Generates a key from 256 bits of cryptographically secure randomness.
+    pub fn generate() -> Result<HmacKey, FleetNetError> {
+        let mut key = [0u8; 32];
+        rustls::crypto::ring::default_provider()
+            .secure_random
+            .fill(&mut key)
+            .map_err(|_| {
+                FleetNetError::EncryptionError(Cow::Borrowed("Failed to generate session key"))
+            })?;
+        Ok(HmacKey::new(&key))
+    }
+}
+
+impl Serialize for HmacKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = self.key.iter().map(|b| format!("{b:02x}")).collect();
+        serializer.serialize_str(&hex)
+    }
+}
+
+impl<'de> Deserialize<'de> for HmacKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let mut key = [0u8; 32];
+        if hex.len() != key.len() * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(de::Error::custom("expected 64 hex digits"));
+        }
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(de::Error::custom)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(de::Error::custom)?;
+        }
+        Ok(HmacKey::new(&key))
+    }
+}
+
+// Keys are credentials; keep them out of logs.
+impl fmt::Debug for HmacKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HmacKey(..)")
+    }
 }
 
 pub fn generate_hmac(key: &HmacKey, data: &[u8]) -> Vec<u8> {
@@ -67,6 +115,18 @@ mod tests {
         assert_eq!(hmac_key.as_bytes(), key_bytes);
     }
 
+    #[test]
+    fn test_hmac_key_round_trips_as_hex() {
+        let key = HmacKey::generate().unwrap();
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(json.len(), 64 + 2);
+        assert_eq!(serde_json::from_str::<HmacKey>(&json).unwrap(), key);
+        assert_ne!(HmacKey::generate().unwrap(), key);
+
+        assert!(serde_json::from_str::<HmacKey>("\"abc\"").is_err());
+        assert!(serde_json::from_str::<HmacKey>(&format!("\"{}\"", "zz".repeat(32))).is_err());
+    }
+
     #[test]
     fn test_generate_hmac() {
         let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
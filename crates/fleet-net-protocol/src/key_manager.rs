@@ -19,7 +19,7 @@ impl KeyManager {
 
         // Mix all inputs together
         hasher.update(server_secret);
-        hasher.update(user_id.to_be_bytes());
+        hasher.update(user_id.get().to_be_bytes());
         hasher.update(session_nonce);
 
         // Get the hash result
@@ -62,11 +62,12 @@ mod tests {
     use super::*;
     use crate::hmac::extract_hmac_prefix;
     use crate::message::{ControlMessage, FramedMessage};
+    use fleet_net_common::types::ChannelId;
 
     #[test]
     fn test_generate_session_key() {
         // Test generating a cryptographically secure random session key for a user
-        let user_id: UserId = 42;
+        let user_id = UserId::new(42).unwrap();
         let server_secret = b"super_secret_server_key_32b!!!!!";
         let session_nonce = b"unique_session_nonce_value";
 
@@ -99,7 +100,7 @@ mod tests {
     #[test]
     fn test_tcp_message_flow_with_hmac() {
         // Simulate server generating a session key for a user
-        let user_id: UserId = 1001;
+        let user_id = UserId::new(1001).unwrap();
         let server_secret = b"super_secret_server_key_32b!!!!!";
         let session_nonce = b"unique_session_nonce_value_10011";
 
@@ -109,7 +110,9 @@ mod tests {
         let keys = KeyManager::derive_protocol_keys(&session_key);
 
         // Client sends a TCP control message
-        let msg = ControlMessage::JoinChannel { channel_id: 42 };
+        let msg = ControlMessage::JoinChannel {
+            channel_id: ChannelId::new(42).unwrap(),
+        };
         let framed = FramedMessage::new(&msg, &keys.tcp_key);
 
         // Server receives and validates the message
@@ -118,7 +121,7 @@ mod tests {
         // Should get the original message back
         match decoded {
             ControlMessage::JoinChannel { channel_id } => {
-                assert_eq!(channel_id, 42)
+                assert_eq!(channel_id, ChannelId::new(42).unwrap())
             }
             _ => panic!("Unexpected message type"),
         }
@@ -128,7 +131,7 @@ mod tests {
     fn test_udp_packet_flow_with_hmac() {
         // Generate session and protocol keys
         let session_key = KeyManager::generate_session_key(
-            2002,
+            UserId::new(2002).unwrap(),
             b"another_secret_server_key_32b!",
             b"session_nonce_2002",
         );
@@ -136,12 +139,15 @@ mod tests {
 
         // Create audio packet header
         let mut header = crate::packet::PacketHeader {
-            channel_id: 5,
-            user_id: 10,
+            channel_id: ChannelId::new(5).unwrap(),
+            user_id: UserId::new(10).unwrap(),
             sequence: 100,
             timestamp: 123456,
             signal_strength: 255,
             frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
             audio_length: 128,
             hmac_prefix: 0, // Will be set after HMAC calculation
         };
@@ -151,8 +157,8 @@ mod tests {
 
         // Calculate HMAC for the packet
         let mut packet_bytes = Vec::new();
-        packet_bytes.extend_from_slice(&header.channel_id.to_be_bytes());
-        packet_bytes.extend_from_slice(&header.user_id.to_be_bytes());
+        packet_bytes.extend_from_slice(&header.channel_id.get().to_be_bytes());
+        packet_bytes.extend_from_slice(&header.user_id.get().to_be_bytes());
         packet_bytes.extend_from_slice(&header.sequence.to_be_bytes());
         packet_bytes.extend_from_slice(&header.timestamp.to_be_bytes());
         packet_bytes.push(header.signal_strength);
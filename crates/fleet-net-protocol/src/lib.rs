@@ -1,10 +1,18 @@
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
+pub mod client;
+pub mod cluster;
 pub mod connection;
+pub mod dual_stack;
 pub mod hmac;
 pub mod key_manager;
 pub mod message;
 pub mod packet;
+pub mod ping;
+pub mod qos;
+pub mod resume;
 pub mod tls;
 pub mod version;
 
-#[cfg(feature = "test-helpers")]
+#[cfg(any(test, feature = "test-helpers"))]
 pub mod test_helpers;
@@ -1,8 +1,27 @@
 use crate::hmac::{generate_hmac, validate_hmac, HmacKey};
-use fleet_net_common::error::FleetNetError;
-use fleet_net_common::types::{ChannelId, UserId};
+use crate::packet::{
+    MAX_PADDING_BLOCK, MAX_RETRANSMIT_SEQUENCES, MAX_TUNNELED_PACKET_LEN, MIN_PADDING_BLOCK,
+};
+use crate::resume::ResumeToken;
+use fleet_net_common::audio::{AudioStateChange, ReceptionQuality, TransmitMode};
+use fleet_net_common::channel::Channel;
+use fleet_net_common::error::{FleetNetError, FleetNetErrorCode};
+use fleet_net_common::group::Group;
+use fleet_net_common::i18n;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::restriction::{RestrictionKind, TimedRestriction};
+use fleet_net_common::template::{ServerTemplate, TemplateChanges};
+use fleet_net_common::types::{ChannelId, GroupId, UserId};
+use fleet_net_common::user::{Presence, User};
+use fleet_net_common::validation::{Constraint, FieldErrors, Validate};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+pub use fleet_net_common::limits::{
+    MAX_MOTD_LEN, MAX_REGION_LEN, MAX_SERVER_NAME_LEN, MAX_TOKEN_LEN,
+};
 
 // Message frame with HMAC for integrity
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,11 +62,34 @@ pub enum ControlMessage {
     Authenticate {
         token: String,
         client_version: Cow<'static, str>,
+        /// Token from a previous session to restore its channel membership.
+        #[serde(default)]
+        resume_token: Option<ResumeToken>,
     },
     AuthResponse {
         success: bool,
         user_id: Option<UserId>,
         error: Option<Cow<'static, str>>,
+        /// Token to present when reconnecting after a dropped connection.
+        #[serde(default)]
+        resume_token: Option<ResumeToken>,
+        /// Oldest client version the server accepts, sent when refusing a
+        /// client that is too old.
+        #[serde(default)]
+        min_client_version: Option<Cow<'static, str>>,
+        /// Key the session signs its voice packets with, and the server the
+        /// packets it forwards to the session.
+        #[serde(default)]
+        udp_key: Option<HmacKey>,
+        /// Relay the session sends its voice to instead of the server's
+        /// voice port, in clustered deployments.
+        #[serde(default)]
+        voice_relay: Option<SocketAddr>,
+    },
+    /// Sent after a successful resume, before any channel events.
+    SessionResumed {
+        current_channel: Option<ChannelId>,
+        subscribed_channels: Vec<ChannelId>,
     },
     JoinChannel {
         channel_id: ChannelId,
@@ -58,14 +100,43 @@ pub enum ControlMessage {
     ChannelJoined {
         channel_id: ChannelId,
         users: Vec<UserId>,
+        /// Those of `users` who only listen, in a channel in
+        /// [spectator mode](fleet_net_common::channel::AudioPolicy::spectator_mode).
+        #[serde(default)]
+        spectators: Vec<UserId>,
     },
     ChannelLeft {
         channel_id: ChannelId,
     },
+    /// Starts receiving audio from a radio channel without leaving the current
+    /// channel. Several radio channels can be monitored at once.
+    SubscribeChannel {
+        channel_id: ChannelId,
+    },
+    UnsubscribeChannel {
+        channel_id: ChannelId,
+    },
+    /// Acknowledges a subscription change with the full set now monitored.
+    SubscriptionsChanged {
+        subscribed_channels: Vec<ChannelId>,
+        /// Those of `subscribed_channels` whose audio policy allows
+        /// [retransmission](fleet_net_common::channel::AudioPolicy::retransmit),
+        /// for the client to ask for lost packets with
+        /// [`ControlMessage::RetransmitRequest`].
+        #[serde(default)]
+        retransmit_channels: Vec<ChannelId>,
+    },
     UserJoined {
         user_id: UserId,
         username: String,
         channel_id: Option<ChannelId>,
+        /// Server nickname, shown instead of `username` when set.
+        #[serde(default)]
+        nickname: Option<String>,
+        /// Discord CDN URL of the user's avatar, if they signed in with
+        /// Discord.
+        #[serde(default)]
+        avatar_url: Option<String>,
     },
     UserLeft {
         user_id: UserId,
@@ -75,31 +146,449 @@ pub enum ControlMessage {
         from_channel: Option<ChannelId>,
         to_channel: Option<ChannelId>,
     },
+    /// Sets the sender's own mute and deafen state.
+    UserStateChange {
+        self_muted: bool,
+        self_deafened: bool,
+    },
+    /// Tells the server how the sender decides when to transmit, so channels
+    /// that forbid voice activation can refuse it.
+    SetTransmitMode {
+        mode: TransmitMode,
+    },
+    /// Declares the channels the sender transmits on while keying several
+    /// radios at once. Packets sent on any of them are forwarded to all of
+    /// them; an empty list transmits on each packet's own channel only.
+    SetTransmitTargets {
+        channel_ids: Vec<ChannelId>,
+    },
+    /// Acknowledges [`ControlMessage::SetTransmitTargets`] with the targets
+    /// now in effect.
+    TransmitTargetsChanged {
+        channel_ids: Vec<ChannelId>,
+    },
+    /// Broadcast as a user starts or stops transmitting on a channel, as
+    /// seen by the server, so clients not hearing the channel can still
+    /// show activity on it.
+    UserSpeaking {
+        user_id: UserId,
+        channel_id: ChannelId,
+        speaking: bool,
+    },
+    /// Tells a sender their transmission on a channel with
+    /// [preemption](fleet_net_common::channel::AudioPolicy::preemption) is
+    /// cut off, as `by` outranks them and is on the air.
+    TransmissionSteppedOn {
+        channel_id: ChannelId,
+        by: UserId,
+    },
+    /// Reports how well the sender hears `user_id` on `channel_id`, sent
+    /// periodically while receiving their audio.
+    ReceptionReport {
+        user_id: UserId,
+        channel_id: ChannelId,
+        quality: ReceptionQuality,
+    },
+    /// Tells a speaker the worst reception their listeners on `channel_id`
+    /// recently reported, and the bitrates the audio policies of the
+    /// channels they transmit on allow, to adapt their encoder to.
+    ReceptionFeedback {
+        channel_id: ChannelId,
+        quality: ReceptionQuality,
+        min_bitrate: u32,
+        max_bitrate: u32,
+    },
+    /// Asks for packets from `user_id` on `channel_id` that never arrived to
+    /// be sent again, on channels that allow
+    /// [retransmission](fleet_net_common::channel::AudioPolicy::retransmit).
+    /// Each packet is sent again once at most, over the voice socket.
+    RetransmitRequest {
+        user_id: UserId,
+        channel_id: ChannelId,
+        sequences: Vec<u16>,
+    },
+    /// A voice packet sent over the control connection rather than the
+    /// voice socket, both ways, by clients on networks that block UDP; see
+    /// [`crate::client::VoiceTransport`]. `packet` holds the datagram
+    /// exactly as it would be sent over UDP.
+    VoiceTunnel {
+        packet: Vec<u8>,
+    },
+    /// Broadcast after a user's effective mute or deafen state changes,
+    /// whether by their own choice or a moderator's.
+    UserStateChanged {
+        user_id: UserId,
+        self_muted: bool,
+        self_deafened: bool,
+        #[serde(default)]
+        server_muted: bool,
+        #[serde(default)]
+        server_deafened: bool,
+    },
+    /// Sets the sender's presence, shown to other users.
+    SetPresence {
+        presence: Presence,
+    },
+    /// Broadcast after a user changes their presence.
+    PresenceChanged {
+        user_id: UserId,
+        presence: Presence,
+    },
+    /// Sets the sender's nickname on this server; `None` clears it.
+    SetNickname {
+        nickname: Option<String>,
+    },
+    /// Broadcast after a user's nickname is set or cleared.
+    NicknameChanged {
+        user_id: UserId,
+        nickname: Option<String>,
+    },
+    /// Replaces a channel's topic, icon and metadata.
+    UpdateChannelInfo {
+        channel_id: ChannelId,
+        topic: Option<String>,
+        icon: Option<String>,
+        #[serde(default)]
+        metadata: HashMap<String, String>,
+    },
+    /// Broadcast after a channel's topic, icon or metadata changes.
+    ChannelInfoChanged {
+        channel_id: ChannelId,
+        topic: Option<String>,
+        icon: Option<String>,
+        #[serde(default)]
+        metadata: HashMap<String, String>,
+    },
+    /// Creates a group led by the sender, who must not be in one already.
+    CreateGroup {
+        name: String,
+    },
+    /// Adds the sender to a group, if they are not in one already.
+    JoinGroup {
+        group_id: GroupId,
+    },
+    /// Removes the sender from their group.
+    LeaveGroup,
+    /// Broadcast after a group is created or its members or leader change.
+    GroupChanged {
+        group: Group,
+    },
+    /// Broadcast after the last member leaves a group.
+    GroupDisbanded {
+        group_id: GroupId,
+    },
     // Server State
     ServerInfo {
         name: String,
         version: Cow<'static, str>,
         user_count: u32,
         channel_count: u32,
+        /// Deployment region, e.g. `eu-west`.
+        #[serde(default)]
+        region: Option<String>,
+        /// UDP port answering latency probes on the same host, see [`crate::ping`].
+        #[serde(default)]
+        ping_port: Option<u16>,
+        /// UDP port voice datagrams go to on the same host.
+        #[serde(default)]
+        voice_port: Option<u16>,
+        #[serde(default)]
+        max_users: Option<u32>,
+        /// Limits the server enforces, so clients can check input before
+        /// sending it. Servers predating limits omit them.
+        #[serde(default)]
+        limits: Option<ServerLimits>,
+        /// Message of the day, already rendered from the operator's template.
+        #[serde(default)]
+        motd: Option<String>,
+        /// Block size voice packets are padded to, see
+        /// [`crate::packet::pad_datagram`]; unpadded when `None`.
+        #[serde(default)]
+        voice_padding: Option<u16>,
     },
     Error {
-        code: Cow<'static, str>,
+        code: FleetNetErrorCode,
+        /// Human-readable detail; clients should branch on `code` instead.
         message: String,
+        /// The offending fields of a rejected request, for highlighting in
+        /// forms.
+        #[serde(default, skip_serializing_if = "FieldErrors::is_empty")]
+        fields: FieldErrors,
+    },
+
+    // Moderation
+    ReportUser {
+        target: UserId,
+        reason: ReportReason,
+        /// Free-form description from the reporter.
+        context: Option<String>,
+    },
+    ReportSubmitted {
+        report_id: u64,
+    },
+    /// Mutes, deafens or bans `target` for `duration_secs`, or until lifted.
+    RestrictUser {
+        target: UserId,
+        kind: RestrictionKind,
+        #[serde(default)]
+        duration_secs: Option<u64>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    LiftRestriction {
+        target: UserId,
+        kind: RestrictionKind,
+    },
+    /// Broadcast after a user is restricted.
+    UserRestricted {
+        user_id: UserId,
+        restriction: TimedRestriction,
+    },
+    /// Broadcast after a restriction is lifted or expires.
+    RestrictionLifted {
+        user_id: UserId,
+        kind: RestrictionKind,
+    },
+
+    // Administration
+    /// Asks for the server's channel tree and roles.
+    ExportTemplate,
+    TemplateExported {
+        template: ServerTemplate,
+    },
+    /// Replaces the server's channel tree and roles with `template`, or with
+    /// `dry_run` only reports what that would change.
+    ImportTemplate {
+        template: ServerTemplate,
+        #[serde(default)]
+        dry_run: bool,
+    },
+    TemplateImported {
+        changes: TemplateChanges,
+        dry_run: bool,
     },
 
     Ping,
     Pong,
 }
 
+impl From<&FleetNetError> for ControlMessage {
+    /// The [`ControlMessage::Error`] reporting `error` to the other side.
+    fn from(error: &FleetNetError) -> Self {
+        let fields = match error {
+            FleetNetError::ValidationError(fields) => fields.clone(),
+            _ => FieldErrors::new(),
+        };
+        ControlMessage::Error {
+            code: error.code(),
+            message: error.to_string(),
+            fields,
+        }
+    }
+}
+
+impl From<AudioStateChange> for ControlMessage {
+    /// The [`ControlMessage::UserStateChanged`] announcing `change`.
+    fn from(change: AudioStateChange) -> Self {
+        ControlMessage::UserStateChanged {
+            user_id: change.user_id,
+            self_muted: change.self_muted,
+            self_deafened: change.self_deafened,
+            server_muted: change.server_muted,
+            server_deafened: change.server_deafened,
+        }
+    }
+}
+
+/// Checks the fields of a client request that the type system cannot.
+///
+/// Messages without such fields are always valid.
+impl Validate for ControlMessage {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        match self {
+            ControlMessage::Authenticate {
+                token,
+                client_version,
+                ..
+            } => {
+                if token.is_empty() {
+                    errors.add("token", Constraint::Required);
+                } else if token.len() > limits.max_token_len {
+                    errors.add("token", Constraint::TooLong(limits.max_token_len));
+                }
+                if semver::Version::parse(client_version).is_err() {
+                    errors.add("client_version", Constraint::InvalidFormat);
+                }
+            }
+            ControlMessage::SetPresence { presence } => presence.check(errors, limits),
+            ControlMessage::SetNickname {
+                nickname: Some(nickname),
+            } => User::check_nickname(errors, nickname, limits),
+            ControlMessage::UpdateChannelInfo {
+                topic,
+                icon,
+                metadata,
+                ..
+            } => Channel::check_info(errors, topic, icon, metadata, limits),
+            ControlMessage::CreateGroup { name } => Group::check_name(errors, name),
+            ControlMessage::RetransmitRequest { sequences, .. } => {
+                if sequences.is_empty() {
+                    errors.add("sequences", Constraint::Required);
+                } else if sequences.len() > MAX_RETRANSMIT_SEQUENCES {
+                    errors.add("sequences", Constraint::TooLong(MAX_RETRANSMIT_SEQUENCES));
+                }
+            }
+            ControlMessage::VoiceTunnel { packet } => {
+                if packet.is_empty() {
+                    errors.add("packet", Constraint::Required);
+                } else if packet.len() > MAX_TUNNELED_PACKET_LEN {
+                    errors.add("packet", Constraint::TooLong(MAX_TUNNELED_PACKET_LEN));
+                }
+            }
+            ControlMessage::ImportTemplate { template, .. } => {
+                errors.check_nested("template", template, limits)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Harassment,
+    Spam,
+    /// Slurs, loud noises, or other offensive audio.
+    OffensiveAudio,
+    Impersonation,
+    Other,
+}
+
+/// Publicly visible server state, sent as [`ControlMessage::ServerInfo`] and
+/// served by the HTTP status endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub name: String,
+    pub version: Cow<'static, str>,
+    pub region: Option<String>,
+    pub ping_port: Option<u16>,
+    /// Port of the voice socket, once bound.
+    #[serde(default)]
+    pub voice_port: Option<u16>,
+    pub user_count: u32,
+    pub channel_count: u32,
+    pub limits: ServerLimits,
+    /// Message of the day template, see [`ServerStatus::motd`].
+    #[serde(default)]
+    pub motd: Option<String>,
+    /// Block size voice packets are padded to; unpadded when `None`.
+    #[serde(default)]
+    pub voice_padding: Option<u16>,
+}
+
+/// Checks the operator-configured fields before they are published, with
+/// the server name against `limits`.
+impl Validate for ServerStatus {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        errors.check_length("name", self.name.trim(), 1, limits.max_server_name_len);
+        if semver::Version::parse(&self.version).is_err() {
+            errors.add("version", Constraint::InvalidFormat);
+        }
+        if let Some(region) = &self.region {
+            errors.check_length("region", region, 1, MAX_REGION_LEN);
+        }
+        if let Some(motd) = &self.motd {
+            errors.check_length("motd", motd, 1, MAX_MOTD_LEN);
+        }
+        if let Some(block) = self.voice_padding {
+            if !(MIN_PADDING_BLOCK..=MAX_PADDING_BLOCK).contains(&block) {
+                errors.add(
+                    "voice_padding",
+                    Constraint::OutOfRange {
+                        min: MIN_PADDING_BLOCK.into(),
+                        max: MAX_PADDING_BLOCK.into(),
+                    },
+                );
+            }
+        }
+        if self.limits.max_users == Some(0) {
+            errors.add(
+                "limits.max_users",
+                Constraint::OutOfRange {
+                    min: 1,
+                    max: u32::MAX.into(),
+                },
+            );
+        }
+    }
+}
+
+impl ServerStatus {
+    /// The message of the day with `{ $server }` and `{ $users }` filled in
+    /// from the current status.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fleet_net_common::limits::ServerLimits;
+    /// use fleet_net_protocol::message::ServerStatus;
+    ///
+    /// let status = ServerStatus {
+    ///     name: "JTF-2".to_string(),
+    ///     version: "0.1.0".into(),
+    ///     region: None,
+    ///     ping_port: None,
+    ///     voice_port: None,
+    ///     user_count: 12,
+    ///     channel_count: 4,
+    ///     limits: ServerLimits::default(),
+    ///     motd: Some("{ $server }: { $users } on net. Op starts 1900Z.".to_string()),
+    ///     voice_padding: None,
+    /// };
+    /// assert_eq!(status.motd().unwrap(), "JTF-2: 12 on net. Op starts 1900Z.");
+    /// ```
+    pub fn motd(&self) -> Option<String> {
+        let users = self.user_count.to_string();
+        self.motd
+            .as_deref()
+            .map(|template| i18n::render(template, &[("server", &self.name), ("users", &users)]))
+    }
+
+    pub fn server_info(&self) -> ControlMessage {
+        ControlMessage::ServerInfo {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            user_count: self.user_count,
+            channel_count: self.channel_count,
+            region: self.region.clone(),
+            ping_port: self.ping_port,
+            voice_port: self.voice_port,
+            max_users: self.limits.max_users,
+            limits: Some(self.limits),
+            motd: self.motd(),
+            voice_padding: self.voice_padding,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arbitrary;
+    use chrono::{TimeZone, Utc};
+    use fleet_net_common::audio::UserAudioState;
+    use fleet_net_common::permission::Permissions;
+    use fleet_net_common::role::Role;
+    use fleet_test_support::golden::assert_golden;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
 
     #[test]
     fn test_message_serialization() {
         let msg = ControlMessage::Authenticate {
             token: "discord_token_123".to_string(),
             client_version: Cow::Borrowed("1.0.0"),
+            resume_token: None,
         };
 
         // Serialize to JSON
@@ -114,6 +603,7 @@ mod tests {
             ControlMessage::Authenticate {
                 token,
                 client_version,
+                ..
             } => {
                 assert_eq!(token, "discord_token_123");
                 assert_eq!(client_version, Cow::Borrowed("1.0.0"));
@@ -125,7 +615,9 @@ mod tests {
     #[test]
     fn test_message_with_hmac() {
         // Create a test message.
-        let msg = ControlMessage::JoinChannel { channel_id: 42 };
+        let msg = ControlMessage::JoinChannel {
+            channel_id: ChannelId::new(42).unwrap(),
+        };
 
         // Create a session key
         let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
@@ -144,9 +636,396 @@ mod tests {
         let parsed: ControlMessage = serde_json::from_slice(&framed.payload).unwrap();
         match parsed {
             ControlMessage::JoinChannel { channel_id } => {
-                assert_eq!(channel_id, 42);
+                assert_eq!(channel_id, ChannelId::new(42).unwrap());
             }
             _ => todo!(),
         }
     }
+
+    #[test]
+    fn test_server_info_without_region_fields() {
+        // Servers predating region support omit the new fields
+        let json = r#"{"type":"server_info","name":"Old","version":"0.1.0","user_count":2,"channel_count":1}"#;
+
+        match serde_json::from_str::<ControlMessage>(json).unwrap() {
+            ControlMessage::ServerInfo {
+                region,
+                ping_port,
+                voice_port,
+                max_users,
+                limits,
+                ..
+            } => {
+                assert_eq!(region, None);
+                assert_eq!(ping_port, None);
+                assert_eq!(voice_port, None);
+                assert_eq!(max_users, None);
+                assert_eq!(limits, None);
+            }
+            other => panic!("Expected ServerInfo, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_authenticate_validation_lists_fields() {
+        let msg = ControlMessage::Authenticate {
+            token: String::new(),
+            client_version: Cow::Borrowed("latest"),
+            resume_token: None,
+        };
+        let limits = ServerLimits::default();
+        let err = msg.validate(&limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: token: required; client_version: invalid_format"
+        );
+        assert!(ControlMessage::Ping.validate(&limits).is_ok());
+
+        // Servers can tighten the defaults
+        let strict = ServerLimits {
+            max_nickname_len: 4,
+            ..limits
+        };
+        let nickname = ControlMessage::SetNickname {
+            nickname: Some("Viper".to_string()),
+        };
+        assert!(nickname.validate(&limits).is_ok());
+        assert!(nickname.validate(&strict).is_err());
+    }
+
+    #[test]
+    fn test_audio_state_changes_become_broadcasts() {
+        let mut state = UserAudioState::new(UserId::new(7).unwrap());
+        let change = state
+            .apply_restriction(RestrictionKind::Deafen, true)
+            .unwrap();
+        let json = serde_json::to_value(ControlMessage::from(change)).unwrap();
+        assert_eq!(json["type"], "user_state_changed");
+        assert_eq!(json["server_muted"], true);
+        assert_eq!(json["self_muted"], false);
+
+        // Older servers only sent the self state
+        let json =
+            r#"{"type":"user_state_changed","user_id":7,"self_muted":true,"self_deafened":false}"#;
+        assert!(matches!(
+            serde_json::from_str::<ControlMessage>(json).unwrap(),
+            ControlMessage::UserStateChanged {
+                server_muted: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_error_message_carries_code() {
+        let error = FleetNetError::PermissionError(Cow::Borrowed("Missing permission to speak"));
+        let json = serde_json::to_string(&ControlMessage::from(&error)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"error","code":"NO_PERMISSION","message":"Permission error: Missing permission to speak"}"#
+        );
+
+        let error = FleetNetError::invalid_field("token", Constraint::Required);
+        let json = serde_json::to_string(&ControlMessage::from(&error)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"error","code":"INVALID_REQUEST","message":"Validation error: token: required","fields":[{"field":"token","constraint":"required"}]}"#
+        );
+
+        let json = r#"{"type":"error","code":"CHANNEL_FULL","message":"Channel is full"}"#;
+        assert!(matches!(
+            serde_json::from_str::<ControlMessage>(json).unwrap(),
+            ControlMessage::Error {
+                code: FleetNetErrorCode::ChannelFull,
+                ..
+            }
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_messages_round_trip_and_validate(message in arbitrary::control_message()) {
+            let json = serde_json::to_value(&message).unwrap();
+            let parsed: ControlMessage = serde_json::from_value(json.clone()).unwrap();
+            prop_assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+            prop_assert!(message.validate(&ServerLimits::default()).is_ok());
+        }
+    }
+
+    /// One message of every variant, with optional fields set so their
+    /// encoding is covered too. Maps hold at most one entry to keep the
+    /// encoding deterministic.
+    fn wire_samples() -> Vec<ControlMessage> {
+        let user = |id| UserId::new(id).unwrap();
+        let channel = |id| ChannelId::new(id).unwrap();
+        let group_id = GroupId::new(4).unwrap();
+        let token = || Some(ResumeToken::from("k3Jx9Qw2".to_string()));
+        let metadata = HashMap::from([("grid".to_string(), "CA 123 456".to_string())]);
+        let presence = Presence::InGame {
+            game: "Arma 3".to_string(),
+        };
+        vec![
+            ControlMessage::Authenticate {
+                token: "discord_token".to_string(),
+                client_version: Cow::Borrowed("1.2.0"),
+                resume_token: token(),
+            },
+            ControlMessage::AuthResponse {
+                success: false,
+                user_id: Some(user(7)),
+                error: Some(Cow::Borrowed("Client is too old")),
+                resume_token: token(),
+                min_client_version: Some(Cow::Borrowed("1.3.0")),
+                udp_key: Some(HmacKey::from_bytes(&[0xab; 32])),
+                voice_relay: Some("10.0.0.5:7001".parse().unwrap()),
+            },
+            ControlMessage::SessionResumed {
+                current_channel: Some(channel(2)),
+                subscribed_channels: vec![channel(3), channel(5)],
+            },
+            ControlMessage::JoinChannel {
+                channel_id: channel(2),
+            },
+            ControlMessage::LeaveChannel {
+                channel_id: channel(2),
+            },
+            ControlMessage::ChannelJoined {
+                channel_id: channel(2),
+                users: vec![user(7), user(8)],
+                spectators: vec![user(8)],
+            },
+            ControlMessage::ChannelLeft {
+                channel_id: channel(2),
+            },
+            ControlMessage::SubscribeChannel {
+                channel_id: channel(3),
+            },
+            ControlMessage::UnsubscribeChannel {
+                channel_id: channel(3),
+            },
+            ControlMessage::SubscriptionsChanged {
+                subscribed_channels: vec![channel(3), channel(5)],
+                retransmit_channels: vec![channel(5)],
+            },
+            ControlMessage::UserJoined {
+                user_id: user(8),
+                username: "pilot".to_string(),
+                channel_id: Some(channel(2)),
+                nickname: Some("Viper 1-1".to_string()),
+                avatar_url: Some("https://cdn.discordapp.com/avatars/1/a.png".to_string()),
+            },
+            ControlMessage::UserLeft { user_id: user(8) },
+            ControlMessage::UserChangedChannel {
+                user_id: user(8),
+                from_channel: Some(channel(2)),
+                to_channel: None,
+            },
+            ControlMessage::UserStateChange {
+                self_muted: true,
+                self_deafened: false,
+            },
+            ControlMessage::SetTransmitMode {
+                mode: TransmitMode::VoiceActivity,
+            },
+            ControlMessage::SetTransmitTargets {
+                channel_ids: vec![channel(3), channel(5)],
+            },
+            ControlMessage::TransmitTargetsChanged {
+                channel_ids: vec![channel(3), channel(5)],
+            },
+            ControlMessage::UserSpeaking {
+                user_id: user(8),
+                channel_id: channel(3),
+                speaking: true,
+            },
+            ControlMessage::TransmissionSteppedOn {
+                channel_id: channel(3),
+                by: user(7),
+            },
+            ControlMessage::ReceptionReport {
+                user_id: user(7),
+                channel_id: channel(3),
+                quality: ReceptionQuality {
+                    loss_percent: 12,
+                    jitter_ms: 35,
+                },
+            },
+            ControlMessage::ReceptionFeedback {
+                channel_id: channel(3),
+                quality: ReceptionQuality {
+                    loss_percent: 12,
+                    jitter_ms: 35,
+                },
+                min_bitrate: 6_000,
+                max_bitrate: 32_000,
+            },
+            ControlMessage::RetransmitRequest {
+                user_id: user(7),
+                channel_id: channel(5),
+                sequences: vec![65_535, 0, 2],
+            },
+            ControlMessage::VoiceTunnel {
+                packet: vec![
+                    0, 5, 0, 7, 0, 3, 0, 0, 0, 80, 200, 20, 0, 3, 0xAB, 0xCD, 1, 2, 3,
+                ],
+            },
+            ControlMessage::UserStateChanged {
+                user_id: user(8),
+                self_muted: true,
+                self_deafened: false,
+                server_muted: false,
+                server_deafened: true,
+            },
+            ControlMessage::SetPresence {
+                presence: presence.clone(),
+            },
+            ControlMessage::PresenceChanged {
+                user_id: user(8),
+                presence,
+            },
+            ControlMessage::SetNickname {
+                nickname: Some("Viper 1-1".to_string()),
+            },
+            ControlMessage::NicknameChanged {
+                user_id: user(8),
+                nickname: None,
+            },
+            ControlMessage::UpdateChannelInfo {
+                channel_id: channel(2),
+                topic: Some("Tasking".to_string()),
+                icon: Some("radio-tower".to_string()),
+                metadata: metadata.clone(),
+            },
+            ControlMessage::ChannelInfoChanged {
+                channel_id: channel(2),
+                topic: None,
+                icon: None,
+                metadata,
+            },
+            ControlMessage::CreateGroup {
+                name: "Fireteam A".to_string(),
+            },
+            ControlMessage::JoinGroup { group_id },
+            ControlMessage::LeaveGroup,
+            ControlMessage::GroupChanged {
+                group: Group {
+                    id: group_id,
+                    name: "Fireteam A".to_string(),
+                    leader: user(7),
+                    members: vec![user(7), user(8)],
+                },
+            },
+            ControlMessage::GroupDisbanded { group_id },
+            ControlMessage::ServerInfo {
+                name: "Fleet Net".to_string(),
+                version: Cow::Borrowed("0.1.0"),
+                user_count: 12,
+                channel_count: 4,
+                region: Some("eu-west".to_string()),
+                ping_port: Some(7878),
+                voice_port: Some(7879),
+                max_users: Some(100),
+                limits: Some(ServerLimits::default()),
+                motd: Some("Welcome".to_string()),
+                voice_padding: Some(256),
+            },
+            ControlMessage::Error {
+                code: FleetNetErrorCode::InvalidRequest,
+                message: "Invalid request".to_string(),
+                fields: FieldErrors::single("token", Constraint::Required),
+            },
+            ControlMessage::ReportUser {
+                target: user(8),
+                reason: ReportReason::Spam,
+                context: Some("Soundboard".to_string()),
+            },
+            ControlMessage::ReportSubmitted { report_id: 42 },
+            ControlMessage::RestrictUser {
+                target: user(8),
+                kind: RestrictionKind::Mute,
+                duration_secs: Some(600),
+                reason: Some("Hot mic".to_string()),
+            },
+            ControlMessage::LiftRestriction {
+                target: user(8),
+                kind: RestrictionKind::Mute,
+            },
+            ControlMessage::UserRestricted {
+                user_id: user(8),
+                restriction: TimedRestriction {
+                    kind: RestrictionKind::Ban,
+                    expires_at: Some(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap()),
+                    reason: Some("Griefing".to_string()),
+                    issued_by: user(7),
+                },
+            },
+            ControlMessage::RestrictionLifted {
+                user_id: user(8),
+                kind: RestrictionKind::Ban,
+            },
+            ControlMessage::ExportTemplate,
+            ControlMessage::TemplateExported {
+                template: ServerTemplate {
+                    channels: Vec::new(),
+                    roles: vec![Role::everyone(Permissions::CONNECT)],
+                },
+            },
+            ControlMessage::ImportTemplate {
+                template: ServerTemplate {
+                    channels: Vec::new(),
+                    roles: vec![Role::new("pilot".to_string(), "Pilot".to_string())
+                        .with_permissions(Permissions::SPEAK)
+                        .with_priority(10)],
+                },
+                dry_run: true,
+            },
+            ControlMessage::TemplateImported {
+                changes: TemplateChanges {
+                    channels_removed: vec![channel(2)],
+                    roles_added: vec!["pilot".to_string()],
+                    ..TemplateChanges::default()
+                },
+                dry_run: true,
+            },
+            ControlMessage::Ping,
+            ControlMessage::Pong,
+        ]
+    }
+
+    /// Tags of every variant, read from serde's refusal of an unknown one so
+    /// a new variant cannot be left out of the snapshot.
+    fn all_variant_tags() -> BTreeSet<String> {
+        let err = serde_json::from_str::<ControlMessage>(r#"{"type":"?"}"#)
+            .unwrap_err()
+            .to_string();
+        let (_, expected) = err.split_once("expected one of ").unwrap();
+        // Tags are quoted in backticks: `ping`, `pong` at line 1 column 11
+        expected
+            .split('`')
+            .skip(1)
+            .step_by(2)
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_wire_format_matches_golden_file() {
+        let samples = wire_samples();
+        let mut encoded = String::new();
+        let mut tags = BTreeSet::new();
+        for message in &samples {
+            let json = serde_json::to_string(message).unwrap();
+            let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+            tags.insert(value["type"].as_str().unwrap().to_string());
+            encoded.push_str(&json);
+            encoded.push('\n');
+        }
+        assert_eq!(tags, all_variant_tags(), "Every variant needs a sample");
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/golden/control_messages.jsonl");
+        assert_golden(path, &encoded);
+        for line in std::fs::read_to_string(path).unwrap().lines() {
+            serde_json::from_str::<ControlMessage>(line).unwrap();
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use crate::hmac::{extract_hmac_prefix, HmacKey};
 use bytes::{Buf, BufMut, BytesMut};
 use fleet_net_common::types::{ChannelId, UserId};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use thiserror::Error;
 
@@ -12,6 +13,14 @@ pub enum PacketError {
     InvalidLength { expected: usize, actual: usize },
     #[error("Invalid packet header")]
     InvalidFormat,
+    #[error("Packet header has a zero user or channel id")]
+    ZeroId,
+    #[error("Packet position is not a finite number")]
+    InvalidPosition,
+    #[error("Batched frame lengths do not match the packet's audio")]
+    InvalidBatch,
+    #[error("Packet padding is longer than the packet")]
+    InvalidPadding,
 }
 
 impl From<PacketError> for fleet_net_common::error::FleetNetError {
@@ -37,27 +46,75 @@ pub struct PacketHeader {
     /// Signal strength of the sender 0 - 255 (byte 10).
     pub signal_strength: u8,
 
-    /// Frame duration in ms (byte 11).
+    /// Frame duration in ms (byte 11, low 6 bits).
     pub frame_duration: u8,
 
-    /// Audio data length in bytes (bytes 12-13).
+    /// Discontinuous transmission: the sender is keyed but silent, and
+    /// skips packets until [`DTX_REFRESH_MS`] has passed or it speaks
+    /// again (byte 11, top bit).
+    pub dtx: bool,
+
+    /// Padding follows the audio and position, hiding the packet's real
+    /// size from traffic analysis, see [`pad_datagram`] (byte 11, second
+    /// bit).
+    pub padded: bool,
+
+    /// Consecutive Opus frames batched in the packet, 1 unless it is a
+    /// batch, see [`AudioPacket::new_batch_signed`] (bytes 12-13, top 4
+    /// bits, less one).
+    pub frames: u8,
+
+    /// Audio data length in bytes (bytes 12-13, low 12 bits).
     pub audio_length: u16,
 
     /// HMAC prefix - first 16 bits of HMAC-SHA256 (bytes 14-15).
     pub hmac_prefix: u16,
 }
 
+/// Longest a sender in discontinuous transmission goes without a packet,
+/// in milliseconds. Kept under the server's 500ms transmission gap so a
+/// pause doesn't end the transmission.
+pub const DTX_REFRESH_MS: u32 = 400;
+
+/// Most lost packets one `retransmit_request` may ask for.
+pub const MAX_RETRANSMIT_SEQUENCES: usize = 16;
+
+/// Top bit of the frame duration byte, set on DTX packets.
+const DTX_FLAG: u8 = 0x80;
+
+/// Second bit of the frame duration byte, set on padded packets.
+const PADDED_FLAG: u8 = 0x40;
+
+/// Smallest and largest block size voice packets may be padded to, in
+/// bytes; the largest still fits a datagram in a typical MTU.
+pub const MIN_PADDING_BLOCK: u16 = 32;
+pub const MAX_PADDING_BLOCK: u16 = 1200;
+
+/// Most Opus frames one packet may batch.
+pub const MAX_BATCH_FRAMES: u8 = 16;
+
+/// Longest audio a packet may carry, in bytes; the top 4 bits of its
+/// length field count batched frames.
+pub const MAX_AUDIO_LENGTH: u16 = 0x0FFF;
+
+/// Largest voice packet a `voice_tunnel` message may carry: the most audio,
+/// a speaker position and the most padding.
+pub const MAX_TUNNELED_PACKET_LEN: usize = PacketHeader::SIZE
+    + MAX_AUDIO_LENGTH as usize
+    + SpeakerPosition::SIZE
+    + MAX_PADDING_BLOCK as usize;
+
 impl PacketHeader {
     pub const SIZE: usize = 16; // Total size of the header in bytes
 
     pub fn write_to<B: BufMut>(&self, buf: &mut B) {
-        buf.put_u16(self.channel_id);
-        buf.put_u16(self.user_id);
+        buf.put_u16(self.channel_id.get());
+        buf.put_u16(self.user_id.get());
         buf.put_u16(self.sequence);
         buf.put_u32(self.timestamp);
         buf.put_u8(self.signal_strength);
-        buf.put_u8(self.frame_duration);
-        buf.put_u16(self.audio_length);
+        buf.put_u8(self.duration_byte());
+        buf.put_u16(self.length_field());
         buf.put_u16(self.hmac_prefix);
     }
 
@@ -66,87 +123,394 @@ impl PacketHeader {
             return Err(PacketError::TooShort);
         }
 
+        let channel_id = ChannelId::new(buf.get_u16()).ok_or(PacketError::ZeroId)?;
+        let user_id = UserId::new(buf.get_u16()).ok_or(PacketError::ZeroId)?;
+        let sequence = buf.get_u16();
+        let timestamp = buf.get_u32();
+        let signal_strength = buf.get_u8();
+        let duration = buf.get_u8();
+        let length = buf.get_u16();
         Ok(PacketHeader {
-            channel_id: buf.get_u16(),
-            user_id: buf.get_u16(),
-            sequence: buf.get_u16(),
-            timestamp: buf.get_u32(),
-            signal_strength: buf.get_u8(),
-            frame_duration: buf.get_u8(),
-            audio_length: buf.get_u16(),
+            channel_id,
+            user_id,
+            sequence,
+            timestamp,
+            signal_strength,
+            frame_duration: duration & !(DTX_FLAG | PADDED_FLAG),
+            dtx: duration & DTX_FLAG != 0,
+            padded: duration & PADDED_FLAG != 0,
+            frames: (length >> 12) as u8 + 1,
+            audio_length: length & MAX_AUDIO_LENGTH,
             hmac_prefix: buf.get_u16(),
         })
     }
 
+    /// Byte 11 on the wire: the frame duration, with the DTX and padding
+    /// flags on top.
+    fn duration_byte(&self) -> u8 {
+        let mut duration = self.frame_duration;
+        if self.dtx {
+            duration |= DTX_FLAG;
+        }
+        if self.padded {
+            duration |= PADDED_FLAG;
+        }
+        duration
+    }
+
+    /// Bytes 12-13 on the wire: the audio length, with the number of
+    /// batched frames less one on top.
+    fn length_field(&self) -> u16 {
+        u16::from(self.frames.saturating_sub(1)) << 12 | self.audio_length & MAX_AUDIO_LENGTH
+    }
+
+    /// Media time the packet covers, in milliseconds.
+    pub fn duration_ms(&self) -> u32 {
+        u32::from(self.frame_duration) * u32::from(self.frames.max(1))
+    }
+
+    /// Checks the prefix of a packet without a position; see
+    /// [`AudioPacket::validate_hmac`] for packets that may carry one.
     pub fn validate_hmac(&self, key: &HmacKey, audio_data: &[u8]) -> bool {
+        // Compare with the stored prefix
+        self.hmac_prefix == self.compute_hmac_prefix(key, audio_data, None)
+    }
+
+    /// Sets `audio_length` and `hmac_prefix` for `audio_data` under `key`.
+    pub fn sign(&mut self, key: &HmacKey, audio_data: &[u8]) {
+        self.audio_length = audio_data.len() as u16;
+        self.hmac_prefix = self.compute_hmac_prefix(key, audio_data, None);
+    }
+
+    /// Sets `hmac_prefix` for `body`, the audio and any position following
+    /// it, under `key`; for relays handing on a header they rewrote.
+    pub fn sign_body(&mut self, key: &HmacKey, body: &[u8]) {
+        self.hmac_prefix = self.compute_hmac_prefix(key, body, None);
+    }
+
+    /// The bytes after the header without their padding, if the packet is
+    /// padded; `None` if the padding length doesn't fit in `body`.
+    pub fn unpadded<'a>(&self, body: &'a [u8]) -> Option<&'a [u8]> {
+        if !self.padded {
+            return Some(body);
+        }
+        let trailer = body.len().checked_sub(2)?;
+        let padding = usize::from(u16::from_be_bytes([body[trailer], body[trailer + 1]]));
+        if padding < 2 {
+            return None;
+        }
+        body.get(..body.len().checked_sub(padding)?)
+    }
+
+    /// Whether `len` bytes after the header are this packet's audio, alone
+    /// or followed by a [`SpeakerPosition`], once unpadded.
+    pub fn fits_body(&self, len: usize) -> bool {
+        let audio_length = self.audio_length as usize;
+        len == audio_length || len == audio_length + SpeakerPosition::SIZE
+    }
+
+    /// The header forwarded alone, in place of a packet on an encrypted
+    /// net, to listeners on its frequency without the key. Clients play
+    /// such packets as noise for `frame_duration`, see
+    /// [`AudioPacket::is_scrambled`].
+    pub fn scrambled(&self) -> PacketHeader {
+        PacketHeader {
+            audio_length: 0,
+            hmac_prefix: 0,
+            ..*self
+        }
+    }
+
+    fn compute_hmac_prefix(
+        &self,
+        key: &HmacKey,
+        audio_data: &[u8],
+        position: Option<&SpeakerPosition>,
+    ) -> u16 {
         // Reconstruct the header bytes without the HMAC prefix & audio data
-        let mut packet_data = Vec::new();
+        let mut packet_data =
+            Vec::with_capacity(Self::SIZE - 2 + audio_data.len() + SpeakerPosition::SIZE);
 
         // Add header fields (excluding hmac_prefix)
-        packet_data.extend_from_slice(&self.channel_id.to_be_bytes());
-        packet_data.extend_from_slice(&self.user_id.to_be_bytes());
+        packet_data.extend_from_slice(&self.channel_id.get().to_be_bytes());
+        packet_data.extend_from_slice(&self.user_id.get().to_be_bytes());
         packet_data.extend_from_slice(&self.sequence.to_be_bytes());
         packet_data.extend_from_slice(&self.timestamp.to_be_bytes());
         packet_data.push(self.signal_strength);
-        packet_data.push(self.frame_duration);
-        packet_data.extend_from_slice(&self.audio_length.to_be_bytes());
+        // Padding is left out, so relays can strip or add it without the
+        // sender's key
+        packet_data.push(self.duration_byte() & !PADDED_FLAG);
+        packet_data.extend_from_slice(&self.length_field().to_be_bytes());
 
-        // Add the audio data
+        // Add the audio data, and the position if the packet carries one
         packet_data.extend_from_slice(audio_data);
+        if let Some(position) = position {
+            position.write_to(&mut packet_data);
+        }
 
         // Generate HMAC for the entire packet (header + audio)
         let full_hmac = crate::hmac::generate_hmac(key, &packet_data);
-        let calculated_prefix = extract_hmac_prefix(&full_hmac);
+        extract_hmac_prefix(&full_hmac)
+    }
+}
 
-        // Compare with the stored prefix
-        self.hmac_prefix == calculated_prefix
+/// Pads an unpadded `datagram` to the next multiple of `block` bytes, with
+/// zeros ending in the big-endian `u16` padding length, and flags its header
+/// as padded. Every voice packet then has one of a few sizes, whatever its
+/// audio, so packet sizes don't give away who is speaking or the codec
+/// settings in use.
+pub fn pad_datagram<B: BufMut + AsMut<[u8]>>(datagram: &mut B, block: u16) {
+    let len = datagram.as_mut().len();
+    let Some(duration) = datagram.as_mut().get_mut(11) else {
+        return;
+    };
+    *duration |= PADDED_FLAG;
+    let block = usize::from(block.clamp(MIN_PADDING_BLOCK, MAX_PADDING_BLOCK));
+    let padding = (len + 2).next_multiple_of(block) - len;
+    datagram.put_bytes(0, padding - 2);
+    datagram.put_u16(padding as u16);
+}
+
+/// Where the speaker is in the game world, sent by clients fed by game
+/// telemetry after the Opus payload of their packets.
+///
+/// Coordinates are in meters in the game's own frame, with `x` east, `y`
+/// north and `z` up. Yaw is the heading in degrees clockwise from north,
+/// pitch the degrees above the horizon.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct SpeakerPosition {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+}
+
+impl SpeakerPosition {
+    pub const SIZE: usize = 20; // Five big-endian f32s
+
+    pub fn write_to<B: BufMut>(&self, buf: &mut B) {
+        for value in [self.x, self.y, self.z, self.yaw, self.pitch] {
+            buf.put_f32(value);
+        }
+    }
+
+    pub fn read_from<B: Buf>(buf: &mut B) -> Result<Self, PacketError> {
+        if buf.remaining() < Self::SIZE {
+            return Err(PacketError::TooShort);
+        }
+
+        let position = SpeakerPosition {
+            x: buf.get_f32(),
+            y: buf.get_f32(),
+            z: buf.get_f32(),
+            yaw: buf.get_f32(),
+            pitch: buf.get_f32(),
+        };
+        if !position.is_finite() {
+            return Err(PacketError::InvalidPosition);
+        }
+        Ok(position)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        [self.x, self.y, self.z, self.yaw, self.pitch]
+            .iter()
+            .all(|value| value.is_finite())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AudioPacket {
     pub header: PacketHeader,
     pub opus_payload: Vec<u8>,
+    /// Speaker position following the payload, when the sender has one.
+    pub position: Option<SpeakerPosition>,
 }
 
 impl AudioPacket {
     /// Serialize back to bytes for the network transmission.
     pub fn to_bytes(&self) -> BytesMut {
         // create a buffer with enough space for the header and payload
-        let mut buf = BytesMut::with_capacity(PacketHeader::SIZE + self.opus_payload.len());
+        let mut buf = BytesMut::with_capacity(
+            PacketHeader::SIZE + self.opus_payload.len() + SpeakerPosition::SIZE,
+        );
 
         // Write the header first
         self.header.write_to(&mut buf);
 
-        // Then write the opus payload
+        // Then write the opus payload, and the position if known
         buf.put_slice(&self.opus_payload);
+        if let Some(position) = &self.position {
+            position.write_to(&mut buf);
+        }
 
         // return the buffer
         buf
     }
 
-    /// Parse packet from network bytes
+    /// Serialize for the network, padded to a multiple of `block` bytes, see
+    /// [`pad_datagram`].
+    pub fn to_padded_bytes(&self, block: u16) -> BytesMut {
+        let mut buf = self.to_bytes();
+        pad_datagram(&mut buf, block);
+        buf
+    }
+
+    /// Whether the packet stands in for encrypted traffic the receiver has
+    /// no key for, see [`PacketHeader::scrambled`].
+    pub fn is_scrambled(&self) -> bool {
+        self.opus_payload.is_empty() && !self.header.dtx
+    }
+
+    /// A packet marking `frame_duration` ms of silence in discontinuous
+    /// transmission, carrying no audio.
+    pub fn new_dtx(mut header: PacketHeader, key: &HmacKey) -> Self {
+        header.dtx = true;
+        Self::new_signed(header, Vec::new(), key)
+    }
+
+    /// Builds a packet batching consecutive Opus `frames`, the first one at
+    /// the sequence number and timestamp of `header`, to save the per-packet
+    /// overhead on low-bandwidth links.
+    ///
+    /// The audio starts with an extension header of one big-endian `u16`
+    /// length per frame, followed by the frames back to back. Receivers
+    /// split it with [`AudioPacket::into_frames`].
+    pub fn new_batch_signed(
+        mut header: PacketHeader,
+        frames: &[Vec<u8>],
+        position: Option<SpeakerPosition>,
+        key: &HmacKey,
+    ) -> Result<Self, PacketError> {
+        if frames.is_empty() || frames.len() > usize::from(MAX_BATCH_FRAMES) {
+            return Err(PacketError::InvalidBatch);
+        }
+        let mut payload =
+            Vec::with_capacity(frames.len() * 2 + frames.iter().map(Vec::len).sum::<usize>());
+        for frame in frames {
+            payload.put_u16(u16::try_from(frame.len()).map_err(|_| PacketError::InvalidBatch)?);
+        }
+        for frame in frames {
+            payload.extend_from_slice(frame);
+        }
+        if payload.len() > usize::from(MAX_AUDIO_LENGTH) {
+            return Err(PacketError::InvalidBatch);
+        }
+        header.frames = frames.len() as u8;
+        Ok(Self::new_signed_at(header, payload, position, key))
+    }
+
+    /// The frames of a batch as packets of their own, with consecutive
+    /// sequence numbers and timestamps; a packet that is no batch alone.
+    /// Check the HMAC before splitting, as the frames' prefixes are not
+    /// valid on their own.
+    pub fn into_frames(self) -> Result<Vec<AudioPacket>, PacketError> {
+        let count = usize::from(self.header.frames);
+        if count <= 1 {
+            return Ok(vec![self]);
+        }
+        let frame_header = |index: usize, audio_length: u16| PacketHeader {
+            sequence: self.header.sequence.wrapping_add(index as u16),
+            timestamp: self
+                .header
+                .timestamp
+                .wrapping_add(index as u32 * u32::from(self.header.frame_duration)),
+            frames: 1,
+            audio_length,
+            ..self.header
+        };
+        let packet = |index: usize, opus_payload: Vec<u8>| AudioPacket {
+            header: frame_header(index, opus_payload.len() as u16),
+            opus_payload,
+            position: self.position,
+        };
+        if self.is_scrambled() {
+            return Ok((0..count).map(|index| packet(index, Vec::new())).collect());
+        }
+
+        let mut buf = self.opus_payload.as_slice();
+        if buf.len() < count * 2 {
+            return Err(PacketError::InvalidBatch);
+        }
+        let lengths: Vec<usize> = (0..count).map(|_| usize::from(buf.get_u16())).collect();
+        if lengths.iter().sum::<usize>() != buf.len() {
+            return Err(PacketError::InvalidBatch);
+        }
+        Ok(lengths
+            .into_iter()
+            .enumerate()
+            .map(|(index, len)| {
+                let (frame, rest) = buf.split_at(len);
+                buf = rest;
+                packet(index, frame.to_vec())
+            })
+            .collect())
+    }
+
+    /// Builds a packet for `opus_payload`, signing the header with the session UDP key.
+    pub fn new_signed(header: PacketHeader, opus_payload: Vec<u8>, key: &HmacKey) -> Self {
+        Self::new_signed_at(header, opus_payload, None, key)
+    }
+
+    /// Builds a packet for `opus_payload` sent from `position`, signing the
+    /// header over both.
+    pub fn new_signed_at(
+        mut header: PacketHeader,
+        opus_payload: Vec<u8>,
+        position: Option<SpeakerPosition>,
+        key: &HmacKey,
+    ) -> Self {
+        header.audio_length = opus_payload.len() as u16;
+        header.hmac_prefix = header.compute_hmac_prefix(key, &opus_payload, position.as_ref());
+        AudioPacket {
+            header,
+            opus_payload,
+            position,
+        }
+    }
+
+    /// Checks the header's HMAC prefix against the payload and position.
+    pub fn validate_hmac(&self, key: &HmacKey) -> bool {
+        let expected =
+            self.header
+                .compute_hmac_prefix(key, &self.opus_payload, self.position.as_ref());
+        self.header.hmac_prefix == expected
+    }
+
+    /// Parse packet from network bytes, stripping any padding.
     pub fn from_bytes(data: &[u8]) -> Result<Self, PacketError> {
-        let mut buf = bytes::Bytes::copy_from_slice(data);
+        let mut buf = data;
 
-        // Parse the header
-        let header = PacketHeader::read_from(&mut buf)?;
+        // Parse the header, then drop the padding
+        let mut header = PacketHeader::read_from(&mut buf)?;
+        let body = header.unpadded(buf).ok_or(PacketError::InvalidPadding)?;
+        header.padded = false;
+        let mut buf = bytes::Bytes::copy_from_slice(body);
 
         // Verify payload length
-        if buf.remaining() != header.audio_length as usize {
+        if !header.fits_body(buf.remaining()) {
             return Err(PacketError::InvalidLength {
                 expected: header.audio_length as usize,
                 actual: buf.remaining(),
             });
         }
 
-        // Extract the opus payload
-        let opus_payload = buf.to_vec();
+        // Extract the opus payload, then the position if one follows
+        let opus_payload = buf.split_to(header.audio_length as usize).to_vec();
+        let position = if buf.has_remaining() {
+            Some(SpeakerPosition::read_from(&mut buf)?)
+        } else {
+            None
+        };
 
         // Return the constructed AudioPacket
         Ok(AudioPacket {
             header,
             opus_payload,
+            position,
         })
     }
 }
@@ -154,17 +518,24 @@ impl AudioPacket {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arbitrary;
     use crate::hmac::{extract_hmac_prefix, generate_hmac};
+    use fleet_test_support::golden::{assert_golden, hex_dump};
+    use fleet_test_support::udp::{corrupt_hmac, truncate_header};
+    use proptest::prelude::*;
 
     #[test]
     fn test_packet_round_trip() {
         let header = PacketHeader {
-            channel_id: 0x1234,
-            user_id: 0x5678,
+            channel_id: ChannelId::new(0x1234).unwrap(),
+            user_id: UserId::new(0x5678).unwrap(),
             sequence: 0x9ABC,
             timestamp: 0xDEADBEEF,
             signal_strength: 200,
             frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
             audio_length: 10,
             hmac_prefix: 0xCAFE,
         };
@@ -173,6 +544,7 @@ mod tests {
         let packet = AudioPacket {
             header,
             opus_payload: payload,
+            position: None,
         };
 
         // Serialize to bytes
@@ -187,16 +559,29 @@ mod tests {
         assert_eq!(parsed_packet.opus_payload, packet.opus_payload);
     }
 
+    #[test]
+    fn test_packet_with_zero_id_is_rejected() {
+        let mut bytes = [0u8; PacketHeader::SIZE];
+        bytes[1] = 1; // Channel 1, user 0
+        assert_eq!(
+            PacketHeader::read_from(&mut &bytes[..]),
+            Err(PacketError::ZeroId)
+        );
+    }
+
     #[test]
     fn test_packet_hmac_validation() {
         // Create a test packet header
         let header = PacketHeader {
-            channel_id: 1,
-            user_id: 42,
+            channel_id: ChannelId::new(1).unwrap(),
+            user_id: UserId::new(42).unwrap(),
             sequence: 1234,
             timestamp: 5000,
             signal_strength: 255,
             frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
             audio_length: 256,
             hmac_prefix: 0, // Will be calculated
         };
@@ -207,8 +592,8 @@ mod tests {
 
         // Serialize header without HMAC prefix
         let mut header_bytes = Vec::new();
-        header_bytes.extend_from_slice(&header.channel_id.to_be_bytes());
-        header_bytes.extend_from_slice(&header.user_id.to_be_bytes());
+        header_bytes.extend_from_slice(&header.channel_id.get().to_be_bytes());
+        header_bytes.extend_from_slice(&header.user_id.get().to_be_bytes());
         header_bytes.extend_from_slice(&header.sequence.to_be_bytes());
         header_bytes.extend_from_slice(&header.timestamp.to_be_bytes());
         header_bytes.push(header.signal_strength);
@@ -233,4 +618,299 @@ mod tests {
         // Verify we can validate it
         assert!(verified_header.validate_hmac(&key, &audio_data));
     }
+
+    #[test]
+    fn test_new_signed_packet_validates() {
+        let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(7).unwrap(),
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+
+        let packet = AudioPacket::new_signed(header, vec![0x55; 40], &key);
+        assert_eq!(packet.header.audio_length, 40);
+
+        let parsed = AudioPacket::from_bytes(&packet.to_bytes()).unwrap();
+        assert!(parsed.header.validate_hmac(&key, &parsed.opus_payload));
+
+        // Tampering with any header field breaks the signature
+        let tampered = PacketHeader {
+            sequence: 2,
+            ..parsed.header
+        };
+        assert!(!tampered.validate_hmac(&key, &parsed.opus_payload));
+
+        // As does a forged prefix, while a cut-off header never parses
+        let bytes = packet.to_bytes();
+        let forged = AudioPacket::from_bytes(&corrupt_hmac(&bytes)).unwrap();
+        assert!(!forged.header.validate_hmac(&key, &forged.opus_payload));
+        assert_eq!(
+            AudioPacket::from_bytes(&truncate_header(&bytes, 12)),
+            Err(PacketError::TooShort)
+        );
+    }
+
+    #[test]
+    fn test_position_follows_payload_and_is_signed() {
+        let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(7).unwrap(),
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let position = SpeakerPosition {
+            x: -1250.5,
+            y: 88_000.0,
+            z: 1520.0,
+            yaw: 270.0,
+            pitch: -5.0,
+        };
+
+        let packet = AudioPacket::new_signed_at(header, vec![0x55; 40], Some(position), &key);
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), PacketHeader::SIZE + 40 + SpeakerPosition::SIZE);
+
+        let parsed = AudioPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, packet);
+        assert!(parsed.validate_hmac(&key));
+
+        // Moving the speaker breaks the signature, as does dropping the position
+        let moved = AudioPacket {
+            position: Some(SpeakerPosition { x: 0.0, ..position }),
+            ..parsed.clone()
+        };
+        assert!(!moved.validate_hmac(&key));
+        assert!(!parsed.header.validate_hmac(&key, &parsed.opus_payload));
+
+        // Anything but a whole position after the payload is refused
+        assert!(matches!(
+            AudioPacket::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(PacketError::InvalidLength { .. })
+        ));
+        let mut not_a_number = bytes.to_vec();
+        not_a_number[PacketHeader::SIZE + 40..][..4].copy_from_slice(&f32::NAN.to_be_bytes());
+        assert_eq!(
+            AudioPacket::from_bytes(&not_a_number),
+            Err(PacketError::InvalidPosition)
+        );
+    }
+
+    #[test]
+    fn test_padding_hides_the_packet_size_and_keeps_the_signature() {
+        let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(7).unwrap(),
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let quiet = AudioPacket::new_signed(header, vec![0x11; 30], &key);
+        let loud = AudioPacket::new_signed_at(
+            header,
+            vec![0x22; 120],
+            Some(SpeakerPosition::default()),
+            &key,
+        );
+        let dtx = AudioPacket::new_dtx(header, &key);
+
+        // Whatever they carry, all three take one block
+        for packet in [&quiet, &loud, &dtx] {
+            let bytes = packet.to_padded_bytes(256);
+            assert_eq!(bytes.len(), 256);
+            let parsed = AudioPacket::from_bytes(&bytes).unwrap();
+            assert_eq!(&parsed, packet);
+            assert!(parsed.validate_hmac(&key));
+        }
+        // Larger packets round up to the next block, even when they fill one
+        assert_eq!(loud.to_padded_bytes(64).len(), 192);
+        assert_eq!(quiet.to_padded_bytes(46).len(), 92);
+
+        let mut bytes = quiet.to_padded_bytes(64).to_vec();
+        let len = bytes.len();
+        bytes[len - 2..].copy_from_slice(&(len as u16).to_be_bytes());
+        assert_eq!(
+            AudioPacket::from_bytes(&bytes),
+            Err(PacketError::InvalidPadding)
+        );
+    }
+
+    #[test]
+    fn test_scrambled_packets_carry_no_audio() {
+        let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(7).unwrap(),
+            sequence: 9,
+            timestamp: 180,
+            signal_strength: 140,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let packet = AudioPacket::new_signed(header, vec![0x55; 40], &key);
+        assert!(!packet.is_scrambled());
+
+        let mut bytes = BytesMut::new();
+        packet.header.scrambled().write_to(&mut bytes);
+        let scrambled = AudioPacket::from_bytes(&bytes).unwrap();
+        assert!(scrambled.is_scrambled());
+        assert_eq!(scrambled.header.sequence, 9);
+        assert_eq!(scrambled.header.frame_duration, 20);
+        assert_eq!(scrambled.position, None);
+    }
+
+    #[test]
+    fn test_dtx_flag_rides_on_the_frame_duration() {
+        let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(7).unwrap(),
+            sequence: 12,
+            timestamp: 240,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let packet = AudioPacket::new_dtx(header, &key);
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), PacketHeader::SIZE);
+        assert_eq!(bytes[11], 0x80 | 20);
+
+        let parsed = AudioPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, packet);
+        assert!(parsed.header.dtx);
+        assert_eq!(parsed.header.frame_duration, 20);
+        assert!(!parsed.is_scrambled());
+        assert!(parsed.validate_hmac(&key));
+
+        // The flag is signed like the rest of the header
+        let spoken = PacketHeader {
+            dtx: false,
+            ..parsed.header
+        };
+        assert!(!spoken.validate_hmac(&key, &parsed.opus_payload));
+    }
+
+    #[test]
+    fn test_batched_frames_split_into_consecutive_packets() {
+        let key = HmacKey::from_bytes(b"test_session_key_32_bytes_long!!");
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(7).unwrap(),
+            sequence: u16::MAX,
+            timestamp: 1_000,
+            signal_strength: 90,
+            frame_duration: 60,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let frames = vec![vec![1; 40], vec![2; 38], vec![3; 41]];
+        let position = SpeakerPosition::default();
+        let batch = AudioPacket::new_batch_signed(header, &frames, Some(position), &key).unwrap();
+        let bytes = batch.to_bytes();
+        // Three frames less one on top of the length, which counts the
+        // extension header of their lengths
+        assert_eq!(&bytes[12..14], &(0x2000u16 | 125).to_be_bytes());
+        assert_eq!(
+            bytes.len(),
+            PacketHeader::SIZE + 125 + SpeakerPosition::SIZE
+        );
+
+        let parsed = AudioPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, batch);
+        assert_eq!(parsed.header.duration_ms(), 180);
+        assert!(parsed.validate_hmac(&key));
+
+        let split = parsed.into_frames().unwrap();
+        let sequences: Vec<_> = split.iter().map(|frame| frame.header.sequence).collect();
+        assert_eq!(sequences, [u16::MAX, 0, 1]);
+        for (index, frame) in split.iter().enumerate() {
+            assert_eq!(frame.opus_payload, frames[index]);
+            assert_eq!(frame.header.timestamp, 1_000 + index as u32 * 60);
+            assert_eq!(frame.header.frames, 1);
+            assert_eq!(frame.position, Some(position));
+        }
+
+        // Lengths that don't add up, and batches too large to carry
+        let mut lying = batch.clone();
+        lying.opus_payload.pop();
+        assert_eq!(lying.into_frames(), Err(PacketError::InvalidBatch));
+        let mut scrambled = Vec::new();
+        batch.header.scrambled().write_to(&mut scrambled);
+        let scrambled = AudioPacket::from_bytes(&scrambled).unwrap();
+        assert_eq!(scrambled.into_frames().unwrap().len(), 3);
+        assert!(AudioPacket::new_batch_signed(header, &[], None, &key).is_err());
+        assert!(
+            AudioPacket::new_batch_signed(header, &vec![vec![0; 300]; 16], None, &key).is_err()
+        );
+    }
+
+    #[test]
+    fn test_packet_layout_matches_golden_file() {
+        // Every field distinct, so swapped or resized fields show up
+        let header = PacketHeader {
+            channel_id: ChannelId::new(0x0102).unwrap(),
+            user_id: UserId::new(0x0304).unwrap(),
+            sequence: 0x0506,
+            timestamp: 0x0708_090A,
+            signal_strength: 0x0B,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let key = HmacKey::from_bytes(b"Golden_Packet_Key_32_Bytes_Long!");
+        let packet = AudioPacket::new_signed(header, vec![0xF8, 0xFF, 0xFE, 0x01], &key);
+
+        assert_golden(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/golden/audio_packet.hex"),
+            &hex_dump(&packet.to_bytes()),
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_packets_round_trip(packet in arbitrary::audio_packet()) {
+            let parsed = AudioPacket::from_bytes(&packet.to_bytes()).unwrap();
+            prop_assert_eq!(parsed.header, packet.header);
+            prop_assert_eq!(parsed.opus_payload, packet.opus_payload);
+            prop_assert_eq!(parsed.position, packet.position);
+        }
+    }
 }
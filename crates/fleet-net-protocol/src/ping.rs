@@ -0,0 +1,306 @@
+//! Unauthenticated UDP latency probe.
+//!
+//! Servers answer small ping datagrams with the nonce and their current load,
+//! letting clients rank the instances of a multi-region deployment by
+//! round-trip time before opening a control connection.
+
+use crate::packet::PacketError;
+use bytes::{Buf, BufMut};
+use fleet_net_common::error::FleetNetError;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+use tracing::debug;
+
+const REQUEST_MAGIC: &[u8; 4] = b"FNPQ";
+const RESPONSE_MAGIC: &[u8; 4] = b"FNPR";
+
+/// A latency probe.
+///
+/// Requests are zero-padded to the size of a response so the probe can never
+/// be used to amplify traffic towards a spoofed source address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingRequest {
+    pub nonce: u64,
+}
+
+impl PingRequest {
+    pub const SIZE: usize = PingResponse::SIZE;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        let mut out = &mut buf[..];
+        out.put_slice(REQUEST_MAGIC);
+        out.put_u64(self.nonce);
+        // The rest stays zeroed as padding
+        buf
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self, PacketError> {
+        if data.len() != Self::SIZE {
+            return Err(PacketError::InvalidLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+        if &data[..4] != REQUEST_MAGIC {
+            return Err(PacketError::InvalidFormat);
+        }
+        data.advance(4);
+        Ok(Self {
+            nonce: data.get_u64(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingResponse {
+    pub nonce: u64,
+    pub user_count: u32,
+    /// Maximum concurrent users, or 0 when the server has no limit.
+    pub max_users: u32,
+}
+
+impl PingResponse {
+    pub const SIZE: usize = 20;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        let mut out = &mut buf[..];
+        out.put_slice(RESPONSE_MAGIC);
+        out.put_u64(self.nonce);
+        out.put_u32(self.user_count);
+        out.put_u32(self.max_users);
+        buf
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self, PacketError> {
+        if data.len() != Self::SIZE {
+            return Err(PacketError::InvalidLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+        if &data[..4] != RESPONSE_MAGIC {
+            return Err(PacketError::InvalidFormat);
+        }
+        data.advance(4);
+        Ok(Self {
+            nonce: data.get_u64(),
+            user_count: data.get_u32(),
+            max_users: data.get_u32(),
+        })
+    }
+}
+
+/// Latency measured to a single server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PingResult {
+    pub address: SocketAddr,
+    /// Median round-trip time of the answered probes.
+    pub rtt: Duration,
+    pub user_count: u32,
+    pub max_users: u32,
+    pub samples_received: u32,
+}
+
+impl PingResult {
+    pub fn is_full(&self) -> bool {
+        self.max_users != 0 && self.user_count >= self.max_users
+    }
+}
+
+/// Answers ping requests on `socket` until receiving fails.
+///
+/// `load` is consulted for every request and returns `(user_count, max_users)`.
+/// Failing to answer one client does not stop the responder.
+pub async fn serve_ping<F>(socket: UdpSocket, load: F) -> Result<(), FleetNetError>
+where
+    F: Fn() -> (u32, u32),
+{
+    let mut buf = [0u8; PingRequest::SIZE + 1];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        // Ignore anything that is not a well-formed request; never amplify.
+        let Ok(request) = PingRequest::decode(&buf[..len]) else {
+            continue;
+        };
+
+        let (user_count, max_users) = load();
+        let response = PingResponse {
+            nonce: request.nonce,
+            user_count,
+            max_users,
+        };
+        if let Err(e) = socket.send_to(&response.encode(), from).await {
+            debug!("Failed to answer ping from {from}: {e}");
+        }
+    }
+}
+
+/// Sends `samples` sequential probes to `address` and reports the median RTT.
+pub async fn measure_rtt(
+    address: SocketAddr,
+    samples: u32,
+    timeout: Duration,
+) -> Result<PingResult, FleetNetError> {
+    let bind_address: SocketAddr = if address.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind_address).await?;
+    socket.connect(address).await?;
+
+    let mut rtts = Vec::with_capacity(samples as usize);
+    let mut last_response = None;
+    let mut buf = [0u8; PingResponse::SIZE + 1];
+
+    for nonce in 0..u64::from(samples) {
+        let sent_at = Instant::now();
+        socket.send(&PingRequest { nonce }.encode()).await?;
+
+        let deadline = sent_at + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Ok(received) = tokio::time::timeout(remaining, socket.recv(&mut buf)).await else {
+                break; // Lost probe
+            };
+            // Late answers to earlier probes carry a stale nonce; keep waiting.
+            match PingResponse::decode(&buf[..received?]) {
+                Ok(response) if response.nonce == nonce => {
+                    rtts.push(sent_at.elapsed());
+                    last_response = Some(response);
+                    break;
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    let Some(response) = last_response else {
+        return Err(FleetNetError::NetworkError(Cow::Owned(format!(
+            "No ping response from {address}"
+        ))));
+    };
+
+    rtts.sort();
+    Ok(PingResult {
+        address,
+        rtt: rtts[rtts.len() / 2],
+        user_count: response.user_count,
+        max_users: response.max_users,
+        samples_received: rtts.len() as u32,
+    })
+}
+
+/// Pings all `addresses` concurrently and returns the reachable ones, nearest first.
+///
+/// Servers that are full are ordered after every server with free capacity.
+pub async fn rank_by_latency(
+    addresses: &[SocketAddr],
+    samples: u32,
+    timeout: Duration,
+) -> Vec<PingResult> {
+    let mut probes = JoinSet::new();
+    for &address in addresses {
+        probes.spawn(measure_rtt(address, samples, timeout));
+    }
+
+    let mut results = Vec::with_capacity(addresses.len());
+    while let Some(probe) = probes.join_next().await {
+        if let Ok(Ok(result)) = probe {
+            results.push(result);
+        }
+    }
+
+    results.sort_by_key(|result| (result.is_full(), result.rtt));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn spawn_responder(user_count: u32, max_users: u32) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(serve_ping(socket, move || (user_count, max_users)));
+        addr
+    }
+
+    #[test]
+    fn test_ping_encoding_round_trip() {
+        let request = PingRequest { nonce: 0xDEAD_BEEF };
+        assert_eq!(PingRequest::decode(&request.encode()), Ok(request));
+
+        let response = PingResponse {
+            nonce: 7,
+            user_count: 12,
+            max_users: 100,
+        };
+        assert_eq!(PingResponse::decode(&response.encode()), Ok(response));
+
+        // A response is not a valid request
+        assert_eq!(
+            PingRequest::decode(&response.encode()),
+            Err(PacketError::InvalidFormat)
+        );
+
+        // Requests are never smaller than the response they ask for
+        assert!(request.encode().len() >= PingResponse::SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_short_requests_are_not_answered() {
+        let addr = spawn_responder(1, 10).await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(addr).await.unwrap();
+
+        let request = PingRequest { nonce: 1 }.encode();
+        socket.send(&request[..12]).await.unwrap();
+
+        let mut buf = [0u8; PingResponse::SIZE + 1];
+        let reply = tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf)).await;
+        assert!(reply.is_err());
+
+        // A padded request from the same socket is still answered
+        socket.send(&request).await.unwrap();
+        let len = tokio::time::timeout(Duration::from_secs(1), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(PingResponse::decode(&buf[..len]).unwrap().nonce, 1);
+    }
+
+    #[tokio::test]
+    async fn test_measure_rtt_reports_server_load() {
+        let addr = spawn_responder(3, 50).await;
+
+        let result = measure_rtt(addr, 3, Duration::from_millis(500))
+            .await
+            .unwrap();
+        assert_eq!(result.address, addr);
+        assert_eq!(result.samples_received, 3);
+        assert_eq!((result.user_count, result.max_users), (3, 50));
+    }
+
+    #[tokio::test]
+    async fn test_rank_by_latency_skips_unreachable_and_full_last() {
+        let open = spawn_responder(1, 10).await;
+        let full = spawn_responder(10, 10).await;
+        // Nothing answers on this socket's address once it is dropped
+        let silent = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let ranked = rank_by_latency(&[full, silent, open], 2, Duration::from_millis(200)).await;
+        let order: Vec<_> = ranked.iter().map(|result| result.address).collect();
+        assert_eq!(order, vec![open, full]);
+    }
+}
@@ -0,0 +1,192 @@
+//! DSCP marking for voice and control sockets.
+//!
+//! Marks outgoing voice datagrams as Expedited Forwarding and control traffic
+//! as signaling so QoS-aware routers prioritize them on congested links.
+//!
+//! Platform notes: Linux, macOS and the BSDs honor `IP_TOS`/`IPV6_TCLASS`
+//! directly. Windows accepts `IP_TOS` but ignores it unless the host has a QoS
+//! policy for the executable, so marking there is best effort.
+
+use crate::dual_stack;
+use fleet_net_common::error::FleetNetError;
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use std::borrow::Cow;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+/// Expedited Forwarding (RFC 3246), the standard class for interactive voice.
+pub const DSCP_EF: u8 = 46;
+
+/// Class Selector 3, recommended for call signaling (RFC 4594).
+pub const DSCP_CS3: u8 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QosConfig {
+    /// DSCP applied to UDP voice sockets; `None` leaves the OS default.
+    pub voice_dscp: Option<u8>,
+    /// DSCP applied to TCP control connections; `None` leaves the OS default.
+    pub control_dscp: Option<u8>,
+}
+
+impl Default for QosConfig {
+    fn default() -> Self {
+        Self {
+            voice_dscp: Some(DSCP_EF),
+            control_dscp: Some(DSCP_CS3),
+        }
+    }
+}
+
+impl QosConfig {
+    /// Leaves all sockets unmarked.
+    pub fn disabled() -> Self {
+        Self {
+            voice_dscp: None,
+            control_dscp: None,
+        }
+    }
+
+    pub fn apply_voice<S: AsSocketRef>(&self, socket: &S) -> Result<(), FleetNetError> {
+        match self.voice_dscp {
+            Some(dscp) => set_dscp(socket, dscp),
+            None => Ok(()),
+        }
+    }
+
+    pub fn apply_control<S: AsSocketRef>(&self, socket: &S) -> Result<(), FleetNetError> {
+        match self.control_dscp {
+            Some(dscp) => set_dscp(socket, dscp),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Binds a UDP voice socket with the configured voice marking applied,
+/// dual-stack on `[::]`, see [`crate::dual_stack`].
+pub async fn bind_voice_socket(
+    address: SocketAddr,
+    qos: &QosConfig,
+) -> Result<UdpSocket, FleetNetError> {
+    let socket = dual_stack::bind_udp(address).await?;
+    qos.apply_voice(&socket)?;
+    Ok(socket)
+}
+
+/// Sockets whose options can be set through [`SockRef`].
+#[cfg(unix)]
+pub trait AsSocketRef: std::os::fd::AsFd {}
+#[cfg(unix)]
+impl<T: std::os::fd::AsFd> AsSocketRef for T {}
+
+/// Sockets whose options can be set through [`SockRef`].
+#[cfg(windows)]
+pub trait AsSocketRef: std::os::windows::io::AsSocket {}
+#[cfg(windows)]
+impl<T: std::os::windows::io::AsSocket> AsSocketRef for T {}
+
+/// Sets the DSCP code point (0-63) on a TCP or UDP socket.
+pub fn set_dscp<S: AsSocketRef>(socket: &S, dscp: u8) -> Result<(), FleetNetError> {
+    if dscp > 63 {
+        return Err(FleetNetError::NetworkError(Cow::Owned(format!(
+            "Invalid DSCP value {dscp}, expected 0-63"
+        ))));
+    }
+
+    let socket = SockRef::from(socket);
+    // DSCP occupies the upper six bits of the TOS / traffic class byte.
+    let tos = u32::from(dscp) << 2;
+
+    let is_ipv6 = socket
+        .local_addr()?
+        .as_socket()
+        .is_some_and(|addr| addr.is_ipv6());
+    let result = if is_ipv6 {
+        set_traffic_class_v6(&socket, tos)
+    } else {
+        socket.set_tos_v4(tos)
+    };
+
+    result.map_err(|e| {
+        FleetNetError::NetworkError(Cow::Owned(format!("Failed to set DSCP {dscp}: {e}")))
+    })
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+))]
+fn set_traffic_class_v6(socket: &SockRef<'_>, tclass: u32) -> io::Result<()> {
+    socket.set_tclass_v6(tclass)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+)))]
+fn set_traffic_class_v6(_socket: &SockRef<'_>, _tclass: u32) -> io::Result<()> {
+    // No IPV6_TCLASS support; rely on OS QoS policy instead.
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use fleet_test_support::connected_tcp_pair;
+
+    #[tokio::test]
+    async fn test_voice_socket_marked_expedited_forwarding() {
+        let socket = bind_voice_socket("127.0.0.1:0".parse().unwrap(), &QosConfig::default())
+            .await
+            .unwrap();
+
+        let tos = SockRef::from(&socket).tos_v4().unwrap();
+        assert_eq!(tos, u32::from(DSCP_EF) << 2);
+    }
+
+    #[tokio::test]
+    async fn test_control_stream_marking_and_disabled_config() {
+        let (stream, _peer) = connected_tcp_pair().await.unwrap();
+
+        QosConfig::disabled().apply_control(&stream).unwrap();
+        assert_eq!(SockRef::from(&stream).tos_v4().unwrap(), 0);
+
+        QosConfig::default().apply_control(&stream).unwrap();
+        assert_eq!(
+            SockRef::from(&stream).tos_v4().unwrap(),
+            u32::from(DSCP_CS3) << 2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_socket_sets_traffic_class() {
+        // Skip on hosts without IPv6 loopback
+        let Ok(socket) = UdpSocket::bind("[::1]:0").await else {
+            return;
+        };
+
+        set_dscp(&socket, DSCP_EF).unwrap();
+        assert_eq!(
+            SockRef::from(&socket).tclass_v6().unwrap(),
+            u32::from(DSCP_EF) << 2
+        );
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_dscp() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(matches!(
+            set_dscp(&socket, 64),
+            Err(FleetNetError::NetworkError(_))
+        ));
+    }
+}
@@ -0,0 +1,72 @@
+//! Session resume tokens.
+//!
+//! The server hands out an opaque token after authentication. A client that
+//! loses its connection (or whose server restarts) presents the token when it
+//! re-authenticates and is placed back into its previous channels.
+
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::secret::constant_time_eq;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ResumeToken(String);
+
+impl ResumeToken {
+    const BYTES: usize = 32;
+
+    /// Generates a token from 256 bits of cryptographically secure randomness.
+    pub fn generate() -> Result<Self, FleetNetError> {
+        let mut bytes = [0u8; Self::BYTES];
+        rustls::crypto::ring::default_provider()
+            .secure_random
+            .fill(&mut bytes)
+            .map_err(|_| {
+                FleetNetError::EncryptionError(Cow::Borrowed("Failed to generate resume token"))
+            })?;
+
+        Ok(Self(bytes.iter().map(|b| format!("{b:02x}")).collect()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Compares tokens in constant time.
+    pub fn matches(&self, other: &str) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.as_bytes())
+    }
+}
+
+impl From<String> for ResumeToken {
+    fn from(token: String) -> Self {
+        Self(token)
+    }
+}
+
+// Tokens are credentials; keep them out of logs.
+impl fmt::Debug for ResumeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResumeToken(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_tokens_are_unique_and_comparable() {
+        let a = ResumeToken::generate().unwrap();
+        let b = ResumeToken::generate().unwrap();
+
+        assert_eq!(a.as_str().len(), 64);
+        assert_ne!(a, b);
+        assert!(a.matches(a.as_str()));
+        assert!(!a.matches(b.as_str()));
+        assert!(!a.matches(&a.as_str()[..63]));
+        assert_eq!(format!("{a:?}"), "ResumeToken(..)");
+    }
+}
@@ -1,9 +1,16 @@
-//! Test helper functions for protocol messages
+//! Test helper functions for protocol messages and voice packets
 //!
 //! This module is only available when the `test-helpers` feature is enabled.
 
+use crate::connection::Connection;
+use crate::hmac::HmacKey;
 use crate::message::ControlMessage;
+use crate::packet::{AudioPacket, PacketHeader};
+use fleet_net_common::types::{ChannelId, UserId};
 use std::borrow::Cow;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 
 /// Assert that a message is a ServerInfo with the expected name.
 pub fn assert_is_server_info(msg: &ControlMessage, expected_name: &str) {
@@ -23,6 +30,7 @@ pub fn assert_server_info(msg: &ControlMessage) -> (&str, &str, u32, u32) {
             version,
             user_count,
             channel_count,
+            ..
         } => (name.as_str(), version.as_ref(), *user_count, *channel_count),
         other => panic!("Expected ServerInfo, got {other:?}"),
     }
@@ -53,6 +61,7 @@ pub fn create_test_authenticate(token: &str, client_version: &'static str) -> Co
     ControlMessage::Authenticate {
         token: token.to_string(),
         client_version: Cow::Borrowed(client_version),
+        resume_token: None,
     }
 }
 
@@ -63,5 +72,339 @@ pub fn create_test_server_info(name: &str, version: &'static str) -> ControlMess
         version: Cow::Borrowed(version),
         user_count: 0,
         channel_count: 0,
+        region: None,
+        ping_port: None,
+        voice_port: None,
+        max_users: None,
+        limits: None,
+        motd: None,
+        voice_padding: None,
+    }
+}
+
+/// How long a [`ScriptedPeer`] waits for each expected message.
+pub const SCRIPT_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Step {
+    Send(ControlMessage),
+    Wait(Duration),
+    Expect {
+        description: String,
+        matches: Box<dyn Fn(&ControlMessage) -> bool + Send>,
+    },
+    ExpectClosed,
+}
+
+/// The far end of a connection, playing back a script of control messages.
+///
+/// Scripts send messages, pause between them and check what the other side
+/// sends back, so regression tests can pin down handshake ordering exactly:
+///
+/// ```ignore
+/// ScriptedPeer::new()
+///     .expect("Authenticate", |m| matches!(m, ControlMessage::Authenticate { .. }))
+///     .wait(Duration::from_millis(50))
+///     .send(create_test_server_info("Fleet Net", "0.1.0"))
+///     .expect_closed()
+///     .play(&mut conn)
+///     .await;
+/// ```
+///
+/// Playback panics at the first step that does not go as scripted, naming
+/// the step and what arrived instead.
+pub struct ScriptedPeer {
+    steps: Vec<Step>,
+    step_timeout: Duration,
+}
+
+impl Default for ScriptedPeer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedPeer {
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            step_timeout: SCRIPT_STEP_TIMEOUT,
+        }
+    }
+
+    /// A script sending recorded messages, each after its gap.
+    pub fn from_recording(recording: impl IntoIterator<Item = (Duration, ControlMessage)>) -> Self {
+        recording
+            .into_iter()
+            .fold(Self::new(), |peer, (gap, message)| {
+                peer.wait(gap).send(message)
+            })
+    }
+
+    /// How long to wait for each expected message, [`SCRIPT_STEP_TIMEOUT`]
+    /// by default.
+    pub fn step_timeout(mut self, timeout: Duration) -> Self {
+        self.step_timeout = timeout;
+        self
+    }
+
+    pub fn send(mut self, message: ControlMessage) -> Self {
+        self.steps.push(Step::Send(message));
+        self
+    }
+
+    pub fn wait(mut self, gap: Duration) -> Self {
+        if !gap.is_zero() {
+            self.steps.push(Step::Wait(gap));
+        }
+        self
+    }
+
+    /// Expects the next message to satisfy `matches`.
+    pub fn expect(
+        mut self,
+        description: impl Into<String>,
+        matches: impl Fn(&ControlMessage) -> bool + Send + 'static,
+    ) -> Self {
+        self.steps.push(Step::Expect {
+            description: description.into(),
+            matches: Box::new(matches),
+        });
+        self
+    }
+
+    /// Expects the next message to be `expected`, compared by its encoding.
+    pub fn expect_message(self, expected: ControlMessage) -> Self {
+        let description = format!("{expected:?}");
+        let expected = serde_json::to_value(&expected).expect("Message encodes");
+        self.expect(description, move |message| {
+            serde_json::to_value(message).is_ok_and(|actual| actual == expected)
+        })
+    }
+
+    /// Expects the other side to close the connection.
+    pub fn expect_closed(mut self) -> Self {
+        self.steps.push(Step::ExpectClosed);
+        self
+    }
+
+    /// Plays the script over `conn` and returns the messages it expected.
+    pub async fn play<S>(self, conn: &mut Connection<S>) -> Vec<ControlMessage>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut received = Vec::new();
+        for (index, step) in self.steps.into_iter().enumerate() {
+            match step {
+                Step::Send(message) => {
+                    if let Err(e) = conn.write_message(&message).await {
+                        panic!("Step {index}: failed to send {message:?}: {e}");
+                    }
+                }
+                Step::Wait(gap) => tokio::time::sleep(gap).await,
+                Step::Expect {
+                    description,
+                    matches,
+                } => {
+                    let message =
+                        match tokio::time::timeout(self.step_timeout, conn.read_message()).await {
+                            Ok(Ok(message)) => message,
+                            Ok(Err(e)) => {
+                                panic!("Step {index}: expected {description}, got error {e}")
+                            }
+                            Err(_) => panic!("Step {index}: timed out waiting for {description}"),
+                        };
+                    assert!(
+                        matches(&message),
+                        "Step {index}: expected {description}, got {message:?}"
+                    );
+                    received.push(message);
+                }
+                Step::ExpectClosed => {
+                    match tokio::time::timeout(self.step_timeout, conn.read_message()).await {
+                        Ok(Err(_)) => {}
+                        Ok(Ok(message)) => {
+                            panic!(
+                                "Step {index}: expected the connection to close, got {message:?}"
+                            )
+                        }
+                        Err(_) => panic!("Step {index}: connection still open"),
+                    }
+                }
+            }
+        }
+        received
+    }
+}
+
+/// The Opus table-of-contents byte for a mono frame of `frame_duration_ms`:
+/// CELT fullband up to 20 ms, SILK wideband for 40 and 60 ms.
+pub fn opus_toc(frame_duration_ms: u8) -> u8 {
+    let config = match frame_duration_ms {
+        0..=2 => 28,
+        3..=5 => 29,
+        6..=10 => 30,
+        11..=20 => 31,
+        21..=40 => 10,
+        _ => 11,
+    };
+    config << 3
+}
+
+/// A deterministic stand-in for an encoded Opus frame.
+///
+/// It starts with a real TOC byte so anything peeking at the frame
+/// configuration sees something sensible, and the rest is noise derived from
+/// `seed`, 40 to 119 bytes of it, like a voice frame at common bitrates. It
+/// does not decode; tests that need audio out of it need a real encoder.
+pub fn synthetic_opus_payload(seed: u64, frame_duration_ms: u8) -> Vec<u8> {
+    // SplitMix64, so nearby seeds still give unrelated payloads
+    let mut state = seed;
+    let mut next = move || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    let len = 40 + (next() % 80) as usize;
+    let mut payload = Vec::with_capacity(len);
+    payload.push(opus_toc(frame_duration_ms));
+    while payload.len() < len {
+        let bytes = next().to_le_bytes();
+        let take = bytes.len().min(len - payload.len());
+        payload.extend_from_slice(&bytes[..take]);
+    }
+    payload
+}
+
+/// Builds signed voice packets with synthetic payloads.
+///
+/// The same ids and sequence always give the same packet, so routing and
+/// jitter buffer tests can compare what comes out against a fresh copy.
+/// Timestamps advance by one frame per sequence number.
+pub struct SyntheticAudio<'a> {
+    key: &'a HmacKey,
+    frame_duration: u8,
+    signal_strength: u8,
+}
+
+impl<'a> SyntheticAudio<'a> {
+    /// 20 ms frames at full signal strength, signed with `key`.
+    pub fn new(key: &'a HmacKey) -> Self {
+        Self {
+            key,
+            frame_duration: 20,
+            signal_strength: u8::MAX,
+        }
+    }
+
+    pub fn frame_duration(mut self, ms: u8) -> Self {
+        self.frame_duration = ms;
+        self
+    }
+
+    pub fn signal_strength(mut self, strength: u8) -> Self {
+        self.signal_strength = strength;
+        self
+    }
+
+    pub fn packet(&self, channel_id: ChannelId, user_id: UserId, sequence: u16) -> AudioPacket {
+        let seed = (u64::from(channel_id.get()) << 32)
+            | (u64::from(user_id.get()) << 16)
+            | u64::from(sequence);
+        let header = PacketHeader {
+            channel_id,
+            user_id,
+            sequence,
+            timestamp: u32::from(sequence) * u32::from(self.frame_duration),
+            signal_strength: self.signal_strength,
+            frame_duration: self.frame_duration,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        AudioPacket::new_signed(
+            header,
+            synthetic_opus_payload(seed, self.frame_duration),
+            self.key,
+        )
+    }
+
+    /// Every user in `users` talking in every channel in `channels`, in the
+    /// order packets would be sent: all of one sequence number before the
+    /// next.
+    ///
+    /// `sequences` is taken as given, so chaining `65534..=65535` with
+    /// `0..2` covers wraparound.
+    ///
+    /// # Panics
+    ///
+    /// If either id range includes 0, which is not a valid id.
+    pub fn stream(
+        &self,
+        channels: RangeInclusive<u16>,
+        users: RangeInclusive<u16>,
+        sequences: impl IntoIterator<Item = u16>,
+    ) -> Vec<AudioPacket> {
+        let channels: Vec<ChannelId> = channels
+            .map(|id| ChannelId::new(id).expect("Channel ids start at 1"))
+            .collect();
+        let users: Vec<UserId> = users
+            .map(|id| UserId::new(id).expect("User ids start at 1"))
+            .collect();
+
+        let mut packets = Vec::new();
+        for sequence in sequences {
+            for &channel_id in &channels {
+                for &user_id in &users {
+                    packets.push(self.packet(channel_id, user_id, sequence));
+                }
+            }
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_stream_is_deterministic_and_signed() {
+        let key = HmacKey::from_bytes(b"Synthetic_Audio_Key_32_Bytes!!!!");
+        let audio = SyntheticAudio::new(&key);
+        let stream = audio.stream(1..=2, 5..=7, (65534..=65535).chain(0..2));
+
+        assert_eq!(stream.len(), 2 * 3 * 4);
+        assert_eq!(
+            stream,
+            audio.stream(1..=2, 5..=7, (65534..=65535).chain(0..2))
+        );
+        let sequences: Vec<u16> = stream
+            .iter()
+            .step_by(6)
+            .map(|p| p.header.sequence)
+            .collect();
+        assert_eq!(sequences, [65534, 65535, 0, 1]);
+
+        for packet in &stream {
+            assert!(packet.header.validate_hmac(&key, &packet.opus_payload));
+            assert_eq!(packet.opus_payload[0], opus_toc(20));
+            assert!((40..120).contains(&packet.opus_payload.len()));
+            assert_eq!(
+                &AudioPacket::from_bytes(&packet.to_bytes()).unwrap(),
+                packet
+            );
+        }
+        // Different speakers never send the same frame
+        assert_ne!(stream[0].opus_payload, stream[1].opus_payload);
+
+        let other = HmacKey::from_bytes(b"Another_Audio_Key_32_Bytes_Long!");
+        assert!(!stream[0]
+            .header
+            .validate_hmac(&other, &stream[0].opus_payload));
     }
 }
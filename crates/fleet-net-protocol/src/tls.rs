@@ -1,10 +1,19 @@
 use fleet_net_common::error::FleetNetError;
-use rustls::pki_types::PrivateKeyDer;
-use rustls::{ClientConfig, ServerConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::BufReader;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{debug, warn};
 
 pub struct TlsConfig {
     pub server_config: Option<Arc<ServerConfig>>,
@@ -31,6 +40,34 @@ impl TlsConfig {
         })
     }
 
+    /// Creates a server config whose certificate is served by a hot-reloadable resolver.
+    pub fn new_server_with_resolver(resolver: Arc<CertResolver>) -> Self {
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+
+        Self {
+            server_config: Some(Arc::new(config)),
+            client_config: None,
+        }
+    }
+
+    /// Like [`new_server_with_resolver`](Self::new_server_with_resolver), but
+    /// also advertises the ACME TLS-ALPN-01 protocol so the resolver can answer
+    /// validation handshakes. Only for servers using that challenge; Fleet Net
+    /// clients do not negotiate ALPN.
+    pub fn new_server_with_acme_resolver(resolver: Arc<CertResolver>) -> Self {
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+        config.alpn_protocols = vec![ACME_TLS_ALPN_PROTOCOL.to_vec()];
+
+        Self {
+            server_config: Some(Arc::new(config)),
+            client_config: None,
+        }
+    }
+
     pub fn new_client(ca_cert_path: &Path) -> Result<Self, FleetNetError> {
         let ca_certs = Self::load_certs(ca_cert_path)?;
 
@@ -53,17 +90,62 @@ impl TlsConfig {
         })
     }
 
-    fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, FleetNetError> {
-        use rustls_pemfile::{ec_private_keys, pkcs8_private_keys, rsa_private_keys};
+    /// Creates a client config that trusts the platform's root certificates,
+    /// for servers with certificates issued by a public CA.
+    ///
+    /// Falls back to the bundled Mozilla roots when the platform store is
+    /// empty or unreadable, e.g. in minimal containers.
+    pub fn new_client_with_system_roots() -> Self {
+        let native = rustls_native_certs::load_native_certs();
+        for error in &native.errors {
+            warn!("Failed to load platform root certificates: {error}");
+        }
 
-        let file = std::fs::File::open(path).map_err(|e| {
+        let mut root_store = rustls::RootCertStore::empty();
+        let (added, ignored) = root_store.add_parsable_certificates(native.certs);
+        if ignored > 0 {
+            debug!("Ignored {ignored} unparsable platform root certificates");
+        }
+        if added == 0 {
+            root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        let config = ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+
+        Self {
+            server_config: None,
+            client_config: Some(Arc::new(config)),
+        }
+    }
+
+    /// Creates a client config that pins the server certificate instead of
+    /// validating it against a CA, see [`FingerprintVerifier`].
+    pub fn new_client_pinned(verifier: Arc<FingerprintVerifier>) -> Self {
+        let config = ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth();
+
+        Self {
+            server_config: None,
+            client_config: Some(Arc::new(config)),
+        }
+    }
+
+    fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, FleetNetError> {
+        let pem = std::fs::read(path).map_err(|e| {
             FleetNetError::FileSystemError(Cow::Owned(format!("Failed to open key file: {e}")))
         })?;
+        Self::parse_private_key(&pem)
+    }
 
-        let mut reader = BufReader::new(file);
+    fn parse_private_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, FleetNetError> {
+        use rustls_pemfile::{ec_private_keys, pkcs8_private_keys, rsa_private_keys};
 
         // Try PKCS8 first
-        let pkcs8_keys = pkcs8_private_keys(&mut reader)
+        let pkcs8_keys = pkcs8_private_keys(&mut BufReader::new(pem))
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| {
                 FleetNetError::EncryptionError(Cow::Owned(format!(
@@ -74,13 +156,8 @@ impl TlsConfig {
             return Ok(PrivateKeyDer::Pkcs8(pkcs8_keys.into_iter().next().unwrap()));
         }
 
-        // Reset reader and try RSA keys
-        let file = std::fs::File::open(path).map_err(|e| {
-            FleetNetError::FileSystemError(Cow::Owned(format!("Failed to open key file: {e}")))
-        })?;
-        let mut reader = BufReader::new(file);
-
-        let rsa_keys = rsa_private_keys(&mut reader)
+        // Then RSA keys
+        let rsa_keys = rsa_private_keys(&mut BufReader::new(pem))
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| {
                 FleetNetError::EncryptionError(Cow::Owned(format!(
@@ -92,11 +169,7 @@ impl TlsConfig {
         }
 
         // Try EC keys as last resort
-        let file = std::fs::File::open(path).map_err(|e| {
-            FleetNetError::FileSystemError(Cow::Owned(format!("Failed to open key file: {e}")))
-        })?;
-        let mut reader = BufReader::new(file);
-        let ec_keys = ec_private_keys(&mut reader)
+        let ec_keys = ec_private_keys(&mut BufReader::new(pem))
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| {
                 FleetNetError::EncryptionError(Cow::Owned(format!(
@@ -112,15 +185,15 @@ impl TlsConfig {
         )))
     }
 
-    fn load_certs(
-        path: &Path,
-    ) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>, FleetNetError> {
-        let file = std::fs::File::open(path).map_err(|e| {
+    fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, FleetNetError> {
+        let pem = std::fs::read(path).map_err(|e| {
             FleetNetError::FileSystemError(Cow::Owned(format!("Failed to open file: {e}")))
         })?;
+        Self::parse_certs(&pem)
+    }
 
-        let mut reader = BufReader::new(file);
-        let certs = rustls_pemfile::certs(&mut reader)
+    fn parse_certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, FleetNetError> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(pem))
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| {
                 FleetNetError::EncryptionError(Cow::Owned(format!(
@@ -137,14 +210,222 @@ impl TlsConfig {
     }
 }
 
+/// ALPN protocol identifier used by the ACME TLS-ALPN-01 challenge (RFC 8737).
+pub const ACME_TLS_ALPN_PROTOCOL: &[u8] = b"acme-tls/1";
+
+/// Server certificate resolver whose certificate can be swapped at runtime.
+///
+/// Connections already established keep the certificate they negotiated;
+/// new handshakes pick up the replacement immediately. The resolver also
+/// answers TLS-ALPN-01 validation handshakes with per-domain challenge
+/// certificates while an ACME order is in progress.
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_files(cert_path: &Path, key_path: &Path) -> Result<Self, FleetNetError> {
+        let resolver = Self::new();
+        resolver.reload(cert_path, key_path)?;
+        Ok(resolver)
+    }
+
+    /// Re-reads the certificate chain and key from disk and starts serving them.
+    pub fn reload(&self, cert_path: &Path, key_path: &Path) -> Result<(), FleetNetError> {
+        let certs = TlsConfig::load_certs(cert_path)?;
+        let key = TlsConfig::load_private_key(key_path)?;
+        self.set_certificate(certs, key)
+    }
+
+    /// Replaces the served certificate with a PEM encoded chain and key.
+    pub fn set_certificate_pem(
+        &self,
+        cert_pem: &[u8],
+        key_pem: &[u8],
+    ) -> Result<(), FleetNetError> {
+        let certs = TlsConfig::parse_certs(cert_pem)?;
+        let key = TlsConfig::parse_private_key(key_pem)?;
+        self.set_certificate(certs, key)
+    }
+
+    pub fn set_certificate(
+        &self,
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<(), FleetNetError> {
+        let certified = Self::certified_key(certs, &key)?;
+        *self.current.write().unwrap() = Some(Arc::new(certified));
+        Ok(())
+    }
+
+    pub fn has_certificate(&self) -> bool {
+        self.current.read().unwrap().is_some()
+    }
+
+    /// Serves `cert` to TLS-ALPN-01 validation handshakes for `domain`.
+    pub fn set_challenge(
+        &self,
+        domain: &str,
+        cert: CertificateDer<'static>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<(), FleetNetError> {
+        let certified = Self::certified_key(vec![cert], &key)?;
+        self.challenges
+            .write()
+            .unwrap()
+            .insert(domain.to_ascii_lowercase(), Arc::new(certified));
+        Ok(())
+    }
+
+    pub fn remove_challenge(&self, domain: &str) {
+        self.challenges
+            .write()
+            .unwrap()
+            .remove(&domain.to_ascii_lowercase());
+    }
+
+    fn certified_key(
+        certs: Vec<CertificateDer<'static>>,
+        key: &PrivateKeyDer<'static>,
+    ) -> Result<CertifiedKey, FleetNetError> {
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(key).map_err(|e| {
+            FleetNetError::EncryptionError(Cow::Owned(format!("Unsupported private key: {e}")))
+        })?;
+        Ok(CertifiedKey::new(certs, signing_key))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let is_acme_challenge = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN_PROTOCOL));
+
+        if is_acme_challenge {
+            let domain = client_hello.server_name()?.to_ascii_lowercase();
+            return self.challenges.read().unwrap().get(&domain).cloned();
+        }
+
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// SHA-256 fingerprint of a DER encoded certificate, as lowercase hex.
+pub fn certificate_fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Trust-on-first-use verification for self-signed servers.
+///
+/// Instead of a CA chain, the server's certificate is pinned by fingerprint.
+/// With no pinned fingerprint any certificate is accepted; otherwise it must
+/// match exactly. Either way the fingerprint that was presented is recorded
+/// so the caller can pin it or ask the user about a change. Handshake
+/// signatures are still verified, so the server must hold the pinned key.
+#[derive(Debug)]
+pub struct FingerprintVerifier {
+    pinned: Mutex<Option<String>>,
+    presented: Mutex<Option<String>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl FingerprintVerifier {
+    pub fn new(pinned: Option<String>) -> Self {
+        Self {
+            pinned: Mutex::new(pinned.map(|fingerprint| fingerprint.to_ascii_lowercase())),
+            presented: Mutex::new(None),
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+
+    pub fn pinned(&self) -> Option<String> {
+        self.pinned.lock().unwrap().clone()
+    }
+
+    /// Requires `fingerprint` from now on, e.g. once a first-use certificate
+    /// has been trusted, so reconnects cannot be intercepted.
+    pub fn pin(&self, fingerprint: &str) {
+        *self.pinned.lock().unwrap() = Some(fingerprint.to_ascii_lowercase());
+    }
+
+    /// Fingerprint of the certificate seen in the most recent handshake.
+    pub fn presented(&self) -> Option<String> {
+        self.presented.lock().unwrap().clone()
+    }
+
+    /// Whether the last handshake failed because the certificate changed.
+    pub fn is_mismatch(&self) -> bool {
+        match (self.pinned(), self.presented()) {
+            (Some(pinned), Some(presented)) => pinned != presented,
+            _ => false,
+        }
+    }
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = certificate_fingerprint(end_entity);
+        *self.presented.lock().unwrap() = Some(fingerprint.clone());
+
+        match self.pinned() {
+            Some(pinned) if pinned != fingerprint => Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
 #[cfg(test)]
 mod tls_config_tests {
-    use crate::tls::TlsConfig;
+    use crate::tls::{CertResolver, FingerprintVerifier, TlsConfig, ACME_TLS_ALPN_PROTOCOL};
     use fleet_net_common::error::FleetNetError;
-    use fleet_test_support::{generate_test_certs, init_crypto_once};
+    use fleet_test_support::{
+        connected_tcp_pair, generate_test_certs, init_crypto_once, TestCertBundle,
+    };
+    use rustls::pki_types::ServerName;
     use std::fs;
     use std::sync::Arc;
     use tempfile::TempDir;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
 
     #[test]
     fn test_load_server_certificates() {
@@ -286,4 +567,143 @@ mod tls_config_tests {
 
         assert_eq!(Arc::strong_count(&server_config), 1);
     }
+
+    async fn handshake_succeeds(acceptor: &TlsAcceptor, trusted: &TestCertBundle) -> bool {
+        let (server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+        let client = TlsConfig::new_client(&trusted.cert_path).unwrap();
+        let connector = TlsConnector::from(client.client_config.unwrap());
+        let domain = ServerName::try_from("localhost").unwrap();
+
+        let (server, client) = tokio::join!(
+            acceptor.accept(server_stream),
+            connector.connect(domain, client_stream)
+        );
+        server.is_ok() && client.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_cert_resolver_hot_reload() {
+        init_crypto_once();
+
+        let original = generate_test_certs("localhost");
+        let renewed = generate_test_certs("localhost");
+
+        let resolver =
+            Arc::new(CertResolver::from_files(&original.cert_path, &original.key_path).unwrap());
+        let tls_config = TlsConfig::new_server_with_resolver(resolver.clone());
+        let acceptor = TlsAcceptor::from(tls_config.server_config.unwrap());
+
+        assert!(handshake_succeeds(&acceptor, &original).await);
+
+        // New handshakes use the reloaded certificate without rebuilding the acceptor
+        resolver
+            .reload(&renewed.cert_path, &renewed.key_path)
+            .unwrap();
+        assert!(handshake_succeeds(&acceptor, &renewed).await);
+        assert!(!handshake_succeeds(&acceptor, &original).await);
+    }
+
+    #[test]
+    fn test_acme_alpn_only_advertised_when_requested() {
+        init_crypto_once();
+        let resolver = Arc::new(CertResolver::new());
+
+        let plain = TlsConfig::new_server_with_resolver(resolver.clone());
+        assert!(plain.server_config.unwrap().alpn_protocols.is_empty());
+
+        let acme = TlsConfig::new_server_with_acme_resolver(resolver);
+        assert_eq!(
+            acme.server_config.unwrap().alpn_protocols,
+            vec![ACME_TLS_ALPN_PROTOCOL.to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_system_roots_reject_self_signed_server() {
+        init_crypto_once();
+
+        let bundle = generate_test_certs("localhost");
+        let acceptor = TlsAcceptor::from(
+            TlsConfig::new_server(&bundle.cert_path, &bundle.key_path)
+                .unwrap()
+                .server_config
+                .unwrap(),
+        );
+
+        let client = TlsConfig::new_client_with_system_roots();
+        assert!(client.server_config.is_none());
+        let connector = TlsConnector::from(client.client_config.unwrap());
+        let (server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+        let domain = ServerName::try_from("localhost").unwrap();
+        let (_, client) = tokio::join!(
+            acceptor.accept(server_stream),
+            connector.connect(domain, client_stream)
+        );
+
+        // Self-signed setups still need an explicit CA or a pinned fingerprint
+        assert!(client.is_err());
+    }
+
+    async fn pinned_handshake_succeeds(
+        acceptor: &TlsAcceptor,
+        verifier: Arc<FingerprintVerifier>,
+    ) -> bool {
+        let (server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+        let client = TlsConfig::new_client_pinned(verifier);
+        let connector = TlsConnector::from(client.client_config.unwrap());
+        let domain = ServerName::try_from("localhost").unwrap();
+
+        let (server, client) = tokio::join!(
+            acceptor.accept(server_stream),
+            connector.connect(domain, client_stream)
+        );
+        server.is_ok() && client.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_verifier_trusts_on_first_use() {
+        init_crypto_once();
+
+        let original = generate_test_certs("localhost");
+        let replaced = generate_test_certs("localhost");
+        let resolver =
+            Arc::new(CertResolver::from_files(&original.cert_path, &original.key_path).unwrap());
+        let acceptor = TlsAcceptor::from(
+            TlsConfig::new_server_with_resolver(resolver.clone())
+                .server_config
+                .unwrap(),
+        );
+
+        // First contact accepts any certificate and reports its fingerprint
+        let first_use = Arc::new(FingerprintVerifier::new(None));
+        assert!(pinned_handshake_succeeds(&acceptor, first_use.clone()).await);
+        let fingerprint = first_use.presented().unwrap();
+        assert_eq!(fingerprint.len(), 64);
+        assert!(!first_use.is_mismatch());
+        first_use.pin(&fingerprint);
+        assert_eq!(first_use.pinned(), Some(fingerprint.clone()));
+
+        // Pinned fingerprints are accepted regardless of case
+        let pinned = Arc::new(FingerprintVerifier::new(Some(fingerprint.to_uppercase())));
+        assert!(pinned_handshake_succeeds(&acceptor, pinned).await);
+
+        // A changed certificate is refused and its fingerprint reported
+        resolver
+            .reload(&replaced.cert_path, &replaced.key_path)
+            .unwrap();
+        let pinned = Arc::new(FingerprintVerifier::new(Some(fingerprint.clone())));
+        assert!(!pinned_handshake_succeeds(&acceptor, pinned.clone()).await);
+        assert!(pinned.is_mismatch());
+        assert_ne!(pinned.presented().unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn test_cert_resolver_rejects_invalid_pem() {
+        init_crypto_once();
+
+        let resolver = CertResolver::new();
+        let result = resolver.set_certificate_pem(b"not a cert", b"not a key");
+        assert!(matches!(result, Err(FleetNetError::EncryptionError(_))));
+        assert!(!resolver.has_certificate());
+    }
 }
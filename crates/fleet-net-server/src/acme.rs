@@ -0,0 +1,507 @@
+//! Automatic certificate provisioning over ACME (RFC 8555).
+//!
+//! [`AcmeManager`] obtains a certificate for the configured domain, caches it
+//! together with the ACME account credentials, and renews it ahead of expiry.
+//! Every new certificate is pushed into the server's [`CertResolver`], so new
+//! TLS handshakes pick it up without a restart.
+//!
+//! Both HTTP-01 (serve [`AcmeManager::http01_router`] on port 80) and
+//! TLS-ALPN-01 (answered by the resolver on the control port) are supported.
+//! [`AcmeManager::server`] builds a server that only advertises the
+//! `acme-tls/1` protocol when TLS-ALPN-01 is in use.
+//!
+//! The operator must agree to the CA's terms of service explicitly through
+//! [`AcmeConfig::terms_of_service_agreed`]. The account credentials and
+//! the certificate are written atomically and readable only by the owner.
+//! The certificate is kept in one file with its private key, so a crash
+//! while renewing can't leave a new certificate next to the old key.
+
+use crate::server::{Server, ServerConfig};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use dashmap::DashMap;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_protocol::tls::CertResolver;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus, RetryPolicy,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Delay before retrying after a failed order.
+const RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait for the ACME server to validate challenges and issue.
+const ORDER_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcmeChallenge {
+    /// Token served over plain HTTP on port 80.
+    Http01,
+    /// Self-signed challenge certificate served over TLS on port 443.
+    TlsAlpn01,
+}
+
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    /// Domain the certificate is issued for.
+    pub domain: String,
+    /// Contact URIs for the ACME account, e.g. `mailto:ops@example.com`.
+    pub contact: Vec<String>,
+    pub directory_url: String,
+    pub challenge: AcmeChallenge,
+    /// Directory holding the account credentials and the certificate with
+    /// its key.
+    pub cache_dir: PathBuf,
+    /// Renew once the certificate is closer than this to expiry.
+    pub renew_before: Duration,
+    /// Whether the operator agrees to the CA's terms of service. No account
+    /// is registered until this is set.
+    pub terms_of_service_agreed: bool,
+}
+
+impl AcmeConfig {
+    /// Configuration for the Let's Encrypt production directory.
+    pub fn lets_encrypt(
+        domain: impl Into<String>,
+        contact: Vec<String>,
+        cache_dir: PathBuf,
+    ) -> Self {
+        Self {
+            domain: domain.into(),
+            contact,
+            directory_url: instant_acme::LetsEncrypt::Production.url().to_string(),
+            challenge: AcmeChallenge::TlsAlpn01,
+            cache_dir,
+            renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+            terms_of_service_agreed: false,
+        }
+    }
+
+    fn account_path(&self) -> PathBuf {
+        self.cache_dir.join("account.json")
+    }
+
+    /// The certificate chain followed by its private key, in PEM.
+    fn bundle_path(&self) -> PathBuf {
+        self.cache_dir.join(format!("{}.pem", self.domain))
+    }
+}
+
+fn acme_error(err: instant_acme::Error) -> FleetNetError {
+    FleetNetError::EncryptionError(Cow::Owned(format!("ACME error: {err}")))
+}
+
+fn rcgen_error(err: rcgen::Error) -> FleetNetError {
+    FleetNetError::EncryptionError(Cow::Owned(format!(
+        "Failed to generate ACME challenge certificate: {err}"
+    )))
+}
+
+pub struct AcmeManager {
+    config: AcmeConfig,
+    resolver: Arc<CertResolver>,
+    /// HTTP-01 token -> key authorization.
+    http_tokens: Arc<DashMap<String, String>>,
+}
+
+impl AcmeManager {
+    pub fn new(config: AcmeConfig, resolver: Arc<CertResolver>) -> Self {
+        Self {
+            config,
+            resolver,
+            http_tokens: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Builds a server serving the managed certificate, answering TLS-ALPN-01
+    /// validation handshakes on the control port when that challenge is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configured store can't be opened.
+    pub fn server(&self, config: ServerConfig) -> Result<Server, FleetNetError> {
+        match self.config.challenge {
+            AcmeChallenge::Http01 => Server::with_cert_resolver(config, self.resolver.clone()),
+            AcmeChallenge::TlsAlpn01 => Server::with_acme_resolver(config, self.resolver.clone()),
+        }
+    }
+
+    /// Router answering HTTP-01 challenges under `/.well-known/acme-challenge/`.
+    pub fn http01_router(&self) -> Router {
+        Router::new()
+            .route("/.well-known/acme-challenge/{token}", get(http01_challenge))
+            .with_state(self.http_tokens.clone())
+    }
+
+    /// Loads a previously issued certificate from the cache into the resolver.
+    ///
+    /// Returns `false` when nothing is cached yet.
+    pub fn load_cached(&self) -> Result<bool, FleetNetError> {
+        let path = self.config.bundle_path();
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        self.resolver.reload(&path, &path)?;
+        Ok(true)
+    }
+
+    /// Time until the cached certificate should be renewed; zero if it is
+    /// missing, unreadable, or already inside the renewal window.
+    pub fn time_until_renewal(&self) -> Duration {
+        let Some(not_after) = std::fs::read(self.config.bundle_path())
+            .ok()
+            .and_then(|pem| certificate_expiry(&pem))
+        else {
+            return Duration::ZERO;
+        };
+
+        let renew_at = not_after
+            .checked_sub(self.config.renew_before)
+            .unwrap_or(UNIX_EPOCH);
+        renew_at
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Keeps the certificate current for the lifetime of the server.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            let wait = self.time_until_renewal();
+            if !wait.is_zero() {
+                info!(
+                    "Next ACME renewal for {} in {}h",
+                    self.config.domain,
+                    wait.as_secs() / 3600
+                );
+                tokio::time::sleep(wait).await;
+                continue;
+            }
+
+            match self.obtain().await {
+                Ok(()) => info!("Installed new certificate for {}", self.config.domain),
+                Err(e) => {
+                    warn!("ACME order for {} failed: {e}", self.config.domain);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Runs a full ACME order and installs the resulting certificate.
+    pub async fn obtain(&self) -> Result<(), FleetNetError> {
+        let account = self.account().await?;
+        let identifiers = [Identifier::Dns(self.config.domain.clone())];
+        let mut order = account
+            .new_order(&NewOrder::new(&identifiers))
+            .await
+            .map_err(acme_error)?;
+
+        let mut pending_tokens = Vec::new();
+        let result = async {
+            let mut authorizations = order.authorizations();
+            while let Some(authorization) = authorizations.next().await {
+                let mut authorization = authorization.map_err(acme_error)?;
+                match authorization.status {
+                    AuthorizationStatus::Pending => {}
+                    AuthorizationStatus::Valid => continue,
+                    status => {
+                        return Err(FleetNetError::EncryptionError(Cow::Owned(format!(
+                            "ACME authorization is {status:?}"
+                        ))))
+                    }
+                }
+
+                let challenge_type = match self.config.challenge {
+                    AcmeChallenge::Http01 => ChallengeType::Http01,
+                    AcmeChallenge::TlsAlpn01 => ChallengeType::TlsAlpn01,
+                };
+                let mut challenge = authorization.challenge(challenge_type).ok_or(
+                    FleetNetError::EncryptionError(Cow::Borrowed(
+                        "ACME server did not offer the configured challenge type",
+                    )),
+                )?;
+
+                let key_authorization = challenge.key_authorization();
+                match self.config.challenge {
+                    AcmeChallenge::Http01 => {
+                        self.http_tokens.insert(
+                            challenge.token.clone(),
+                            key_authorization.as_str().to_string(),
+                        );
+                        pending_tokens.push(challenge.token.clone());
+                    }
+                    AcmeChallenge::TlsAlpn01 => {
+                        let (cert, key) = tls_alpn01_certificate(
+                            &self.config.domain,
+                            key_authorization.digest().as_ref(),
+                        )?;
+                        self.resolver
+                            .set_challenge(&self.config.domain, cert, key)?;
+                    }
+                }
+
+                challenge.set_ready().await.map_err(acme_error)?;
+            }
+
+            let retries = RetryPolicy::new().timeout(ORDER_TIMEOUT);
+            let status = order.poll_ready(&retries).await.map_err(acme_error)?;
+            if status != OrderStatus::Ready {
+                return Err(FleetNetError::EncryptionError(Cow::Owned(format!(
+                    "ACME order ended in state {status:?}"
+                ))));
+            }
+
+            let key_pem = order.finalize().await.map_err(acme_error)?;
+            let cert_pem = order.poll_certificate(&retries).await.map_err(acme_error)?;
+            Ok((cert_pem, key_pem))
+        }
+        .await;
+
+        // Challenge responses are only needed while the order is validated
+        for token in pending_tokens {
+            self.http_tokens.remove(&token);
+        }
+        self.resolver.remove_challenge(&self.config.domain);
+
+        let (cert_pem, key_pem) = result?;
+        self.resolver
+            .set_certificate_pem(cert_pem.as_bytes(), key_pem.as_bytes())?;
+
+        std::fs::create_dir_all(&self.config.cache_dir)?;
+        write_private(&self.config.bundle_path(), &bundle_pem(&cert_pem, &key_pem))?;
+        Ok(())
+    }
+
+    /// Restores the cached ACME account, registering a new one on first use.
+    async fn account(&self) -> Result<Account, FleetNetError> {
+        let path = self.config.account_path();
+
+        if let Ok(json) = std::fs::read(&path) {
+            let credentials: AccountCredentials = serde_json::from_slice(&json)?;
+            return Account::builder()
+                .map_err(acme_error)?
+                .from_credentials(credentials)
+                .await
+                .map_err(acme_error);
+        }
+
+        if !self.config.terms_of_service_agreed {
+            return Err(FleetNetError::EncryptionError(Cow::Borrowed(
+                "The ACME terms of service must be agreed to before registering an account",
+            )));
+        }
+
+        let builder = Account::builder().map_err(acme_error)?;
+        let contact: Vec<&str> = self.config.contact.iter().map(String::as_str).collect();
+        let (account, credentials) = builder
+            .create(
+                &NewAccount {
+                    contact: &contact,
+                    terms_of_service_agreed: true,
+                    only_return_existing: false,
+                },
+                self.config.directory_url.clone(),
+                None,
+            )
+            .await
+            .map_err(acme_error)?;
+
+        std::fs::create_dir_all(&self.config.cache_dir)?;
+        write_private(&path, &serde_json::to_vec(&credentials)?)?;
+        Ok(account)
+    }
+}
+
+/// Replaces `path` with `contents` in one step, readable and writable by
+/// the owner only.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), FleetNetError> {
+    let tmp_path = path.with_extension("tmp");
+    // A leftover from an earlier crash may have looser permissions
+    let _ = std::fs::remove_file(&tmp_path);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+async fn http01_challenge(
+    State(tokens): State<Arc<DashMap<String, String>>>,
+    UrlPath(token): UrlPath<String>,
+) -> Result<String, StatusCode> {
+    tokens
+        .get(&token)
+        .map(|key_authorization| key_authorization.clone())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Builds the self-signed certificate carrying the `acmeIdentifier` extension
+/// required by TLS-ALPN-01 (RFC 8737).
+fn tls_alpn01_certificate(
+    domain: &str,
+    key_authorization_digest: &[u8],
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>), FleetNetError> {
+    let mut params =
+        rcgen::CertificateParams::new(vec![domain.to_string()]).map_err(rcgen_error)?;
+    params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(
+        key_authorization_digest,
+    )];
+
+    let key_pair = rcgen::KeyPair::generate().map_err(rcgen_error)?;
+    let cert = params.self_signed(&key_pair).map_err(rcgen_error)?;
+    let key = PrivatePkcs8KeyDer::from(key_pair.serialize_der());
+    Ok((cert.der().clone(), key.into()))
+}
+
+/// The certificate chain followed by its key, as cached. The chain comes
+/// first for [`certificate_expiry`].
+fn bundle_pem(cert_pem: &str, key_pem: &str) -> Vec<u8> {
+    let mut bundle = cert_pem.as_bytes().to_vec();
+    if !bundle.ends_with(b"\n") {
+        bundle.push(b'\n');
+    }
+    bundle.extend_from_slice(key_pem.as_bytes());
+    bundle
+}
+
+/// Reads the `notAfter` time of the first certificate in a PEM chain.
+fn certificate_expiry(pem: &[u8]) -> Option<SystemTime> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(pem).ok()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&pem.contents).ok()?;
+    let not_after = u64::try_from(cert.validity().not_after.timestamp()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(not_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_test_support::init_crypto_once;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    fn test_manager(cache_dir: &TempDir) -> AcmeManager {
+        let mut config = AcmeConfig::lets_encrypt(
+            "voice.example.com",
+            vec!["mailto:ops@example.com".to_string()],
+            cache_dir.path().to_path_buf(),
+        );
+        config.challenge = AcmeChallenge::Http01;
+        AcmeManager::new(config, Arc::new(CertResolver::new()))
+    }
+
+    fn write_cached_cert(manager: &AcmeManager, valid_for_days: u64) {
+        let mut params =
+            rcgen::CertificateParams::new(vec![manager.config.domain.clone()]).unwrap();
+        params.not_after = x509_parser::time::ASN1Time::now().to_datetime()
+            + Duration::from_secs(valid_for_days * 86_400);
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+
+        let bundle = bundle_pem(&cert.pem(), &key_pair.serialize_pem());
+        std::fs::write(manager.config.bundle_path(), bundle).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http01_router_serves_key_authorization() {
+        let dir = TempDir::new().unwrap();
+        let manager = test_manager(&dir);
+        manager
+            .http_tokens
+            .insert("token-1".to_string(), "token-1.thumbprint".to_string());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = manager.http01_router();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let url = format!("http://{addr}/.well-known/acme-challenge");
+        let response = reqwest::get(format!("{url}/token-1")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "token-1.thumbprint");
+
+        let response = reqwest::get(format!("{url}/unknown")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_renewal_schedule_from_cached_certificate() {
+        init_crypto_once();
+        let dir = TempDir::new().unwrap();
+        let manager = test_manager(&dir);
+
+        // Nothing cached: order immediately
+        assert!(!manager.load_cached().unwrap());
+        assert_eq!(manager.time_until_renewal(), Duration::ZERO);
+
+        // 60 days left with a 30 day window: renew in roughly 30 days
+        write_cached_cert(&manager, 60);
+        assert!(manager.load_cached().unwrap());
+        assert!(manager.resolver.has_certificate());
+        let wait = manager.time_until_renewal();
+        assert!(
+            wait > Duration::from_secs(29 * 86_400) && wait <= Duration::from_secs(30 * 86_400)
+        );
+
+        // Inside the renewal window: order immediately
+        write_cached_cert(&manager, 10);
+        assert_eq!(manager.time_until_renewal(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_account_requires_agreed_terms_of_service() {
+        let dir = TempDir::new().unwrap();
+        let manager = test_manager(&dir);
+        assert!(!manager.config.terms_of_service_agreed);
+
+        let result = manager.account().await;
+        assert!(matches!(result, Err(FleetNetError::EncryptionError(_))));
+        assert!(!manager.config.account_path().exists());
+    }
+
+    #[test]
+    fn test_private_files_are_replaced_owner_only() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("voice.example.com.key");
+        std::fs::write(&path, "old").unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!path.with_extension("tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_tls_alpn01_certificate_is_installable() {
+        init_crypto_once();
+        let resolver = CertResolver::new();
+        let (cert, key) = tls_alpn01_certificate("voice.example.com", &[7u8; 32]).unwrap();
+        resolver
+            .set_challenge("voice.example.com", cert, key)
+            .unwrap();
+        resolver.remove_challenge("voice.example.com");
+    }
+}
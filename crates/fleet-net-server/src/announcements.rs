@@ -0,0 +1,639 @@
+//! Spoken server announcements.
+//!
+//! An [`Announcer`] turns text such as "Server restarting in 5 minutes"
+//! into speech with a [`SpeechEngine`] and plays it into chosen channels
+//! as a user of its own, so clients hear it like any other speaker.
+//! Announcements are made through the admin API, optionally after a delay,
+//! or repeat on a [`ScheduledAnnouncement`] schedule. One plays at a time;
+//! the next waits for it to finish.
+//!
+//! The server never encodes audio, so engines hand over 48 kHz Opus
+//! packets. [`CommandEngine`] runs an external program, such as a TTS
+//! engine piped into `opusenc`, which reads the text on its standard input
+//! and writes Ogg Opus to its standard output.
+//!
+//! Announcements go through the voice router: the announcer listens to its
+//! channels while it speaks and transmits on all of them at once, so the
+//! channels' audio policies, radio nets and the realism scenario apply as
+//! they would to a member's transmission.
+
+use crate::rtp::opus_packet_duration_us;
+use crate::subscriptions::SubscriptionRegistry;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::validation::{Constraint, FieldErrors, Validate};
+use fleet_net_protocol::cluster::RelaySubscriber;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Longest announcement text, in bytes.
+pub const MAX_ANNOUNCEMENT_LEN: usize = 500;
+
+/// Channels a single announcement is played into.
+pub const MAX_ANNOUNCEMENT_CHANNELS: usize = 64;
+
+/// Longest delay the admin API accepts before playing an announcement.
+pub const MAX_ANNOUNCEMENT_DELAY_SECS: u32 = 24 * 60 * 60;
+
+/// Turns text into speech.
+pub trait SpeechEngine: Send + Sync + 'static {
+    /// The spoken `text` as 48 kHz Opus packets, in playing order.
+    fn synthesize(
+        &self,
+        text: &str,
+    ) -> impl Future<Output = Result<Vec<Vec<u8>>, FleetNetError>> + Send;
+}
+
+/// Speaks through an external program, e.g. `sh -c "piper --model
+/// voice.onnx --output_raw | opusenc --raw --raw-rate 22050 - -"`.
+///
+/// The program gets the text on its standard input and must write Ogg Opus
+/// to its standard output before exiting successfully.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandEngine {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl SpeechEngine for CommandEngine {
+    async fn synthesize(&self, text: &str) -> Result<Vec<Vec<u8>>, FleetNetError> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("Piped stdin");
+        let text = text.to_string();
+        // Written alongside reading, so a chatty engine can't deadlock on a full pipe
+        let writer = tokio::spawn(async move {
+            stdin.write_all(text.as_bytes()).await?;
+            stdin.shutdown().await
+        });
+        let output = child.wait_with_output().await?;
+        writer
+            .await
+            .map_err(|e| speech_error(format!("Failed to write the text: {e}")))??;
+        if !output.status.success() {
+            return Err(speech_error(format!(
+                "{} exited with {}",
+                self.program, output.status
+            )));
+        }
+        ogg_opus_packets(&output.stdout)
+    }
+}
+
+fn speech_error(message: String) -> FleetNetError {
+    FleetNetError::AudioError(Cow::Owned(message))
+}
+
+/// The audio packets of an Ogg Opus stream (RFC 7845), leaving out its
+/// identification and comment headers.
+pub fn ogg_opus_packets(mut data: &[u8]) -> Result<Vec<Vec<u8>>, FleetNetError> {
+    let invalid = || speech_error("Speech is not a valid Ogg stream".to_string());
+    let mut packets = Vec::new();
+    let mut partial = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 27 || &data[..4] != b"OggS" {
+            return Err(invalid());
+        }
+        let segments = usize::from(data[26]);
+        let lacing = data.get(27..27 + segments).ok_or_else(invalid)?;
+        let body_len: usize = lacing.iter().map(|&len| usize::from(len)).sum();
+        let mut body = data
+            .get(27 + segments..27 + segments + body_len)
+            .ok_or_else(invalid)?;
+        for &len in lacing {
+            partial.extend_from_slice(&body[..usize::from(len)]);
+            body = &body[usize::from(len)..];
+            // A full segment continues into the next one
+            if len < 255 {
+                packets.push(std::mem::take(&mut partial));
+            }
+        }
+        data = &data[27 + segments + body_len..];
+    }
+
+    if packets
+        .first()
+        .is_none_or(|head| !head.starts_with(b"OpusHead"))
+        || packets
+            .get(1)
+            .is_none_or(|tags| !tags.starts_with(b"OpusTags"))
+    {
+        return Err(speech_error("Speech is not Ogg Opus".to_string()));
+    }
+    Ok(packets.split_off(2))
+}
+
+/// Text to speak into channels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub text: String,
+    pub channel_ids: Vec<ChannelId>,
+}
+
+impl Validate for Announcement {
+    fn check(&self, errors: &mut FieldErrors, _limits: &ServerLimits) {
+        errors.check_length("text", self.text.trim(), 1, MAX_ANNOUNCEMENT_LEN);
+        if self.channel_ids.is_empty() {
+            errors.add("channel_ids", Constraint::Required);
+        } else if self.channel_ids.len() > MAX_ANNOUNCEMENT_CHANNELS {
+            errors.add(
+                "channel_ids",
+                Constraint::TooLong(MAX_ANNOUNCEMENT_CHANNELS),
+            );
+        }
+        let mut seen = HashSet::new();
+        if !self.channel_ids.iter().all(|id| seen.insert(*id)) {
+            errors.add("channel_ids", Constraint::Duplicate);
+        }
+    }
+}
+
+/// An announcement repeated every `every_secs`, first after one period.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledAnnouncement {
+    #[serde(flatten)]
+    pub announcement: Announcement,
+    pub every_secs: u64,
+}
+
+/// Settings of the server's [`Announcer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnouncementsConfig {
+    pub engine: CommandEngine,
+    /// User id announcements are spoken as. It must not clash with ids of
+    /// real users.
+    pub user_id: UserId,
+    /// Address the announcer's socket binds to; listeners see voice come
+    /// from it.
+    pub bind_ip: IpAddr,
+    pub schedule: Vec<ScheduledAnnouncement>,
+}
+
+impl AnnouncementsConfig {
+    pub fn new(engine: CommandEngine, user_id: UserId) -> Self {
+        Self {
+            engine,
+            user_id,
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            schedule: Vec::new(),
+        }
+    }
+}
+
+/// Where the next announcement continues the announcer's packet stream.
+struct Playback {
+    sequence: u16,
+}
+
+/// Speaks announcements into channels.
+pub struct Announcer<E> {
+    engine: E,
+    user_id: UserId,
+    socket: UdpSocket,
+    subscriptions: Arc<SubscriptionRegistry>,
+    /// Held while an announcement plays, so they don't talk over each other.
+    playback: Mutex<Playback>,
+    started: Instant,
+}
+
+impl<E: SpeechEngine> Announcer<E> {
+    /// Binds the socket announcements are sent from.
+    pub async fn bind(
+        engine: E,
+        user_id: UserId,
+        bind_ip: IpAddr,
+        subscriptions: Arc<SubscriptionRegistry>,
+    ) -> Result<Self, FleetNetError> {
+        Ok(Self {
+            engine,
+            user_id,
+            socket: dual_stack::bind_udp((bind_ip, 0)).await?,
+            subscriptions,
+            playback: Mutex::new(Playback { sequence: 0 }),
+            started: Instant::now(),
+        })
+    }
+
+    /// Socket announcements are sent from, e.g. to mark it for QoS.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Speaks `announcement`, returning once it has been played in real
+    /// time, and returns how many packets were forwarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the announcement is invalid, the engine fails,
+    /// or a channel's audio policy refuses the announcement.
+    pub async fn announce(&self, announcement: &Announcement) -> Result<usize, FleetNetError> {
+        announcement.validate(&ServerLimits::default())?;
+        let packets = self.engine.synthesize(&announcement.text).await?;
+        let mut playback = self.playback.lock().await;
+        info!(
+            "Announcing {:?} in {} channels",
+            announcement.text,
+            announcement.channel_ids.len()
+        );
+
+        let address = self.socket.local_addr()?;
+        for &channel_id in &announcement.channel_ids {
+            self.subscriptions.add_listener(
+                channel_id,
+                RelaySubscriber {
+                    user_id: self.user_id,
+                    address,
+                },
+            );
+        }
+        self.subscriptions
+            .set_transmit_targets(self.user_id, announcement.channel_ids.clone());
+        let played = self
+            .play(&mut playback, announcement.channel_ids[0], packets)
+            .await;
+        self.subscriptions.remove_user(self.user_id);
+        played
+    }
+
+    /// Sends `packets` on `channel_id`, each when the one before has played.
+    async fn play(
+        &self,
+        playback: &mut Playback,
+        channel_id: ChannelId,
+        packets: Vec<Vec<u8>>,
+    ) -> Result<usize, FleetNetError> {
+        let start = tokio::time::Instant::now();
+        let mut elapsed = Duration::ZERO;
+        let mut forwarded = 0;
+        for opus in packets {
+            let Some(frame_duration) = opus_packet_duration_us(&opus)
+                .filter(|us| us % 1_000 == 0)
+                .and_then(|us| u8::try_from(us / 1_000).ok())
+                .filter(|&ms| ms > 0)
+            else {
+                continue;
+            };
+            let header = PacketHeader {
+                channel_id,
+                user_id: self.user_id,
+                sequence: playback.sequence,
+                timestamp: (self.started.elapsed() + elapsed).as_millis() as u32,
+                signal_strength: u8::MAX,
+                frame_duration,
+                dtx: false,
+                frames: 1,
+                padded: false,
+                audio_length: opus.len() as u16,
+                // Receivers don't check it
+                hmac_prefix: 0,
+            };
+            let packet = AudioPacket {
+                header,
+                opus_payload: opus,
+                position: None,
+            };
+            tokio::time::sleep_until(start + elapsed).await;
+            forwarded += self
+                .subscriptions
+                .forward_packet(&self.socket, &packet.to_bytes(), self.socket.local_addr()?)
+                .await?;
+            playback.sequence = playback.sequence.wrapping_add(1);
+            elapsed += Duration::from_millis(frame_duration.into());
+        }
+        Ok(forwarded)
+    }
+
+    /// Plays `announcement` in the background, after `delay`, logging
+    /// failures.
+    pub fn spawn_announce(
+        self: &Arc<Self>,
+        announcement: Announcement,
+        delay: Duration,
+    ) -> JoinHandle<()> {
+        let announcer = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(e) = announcer.announce(&announcement).await {
+                warn!("Announcement {:?} failed: {e}", announcement.text);
+            }
+        })
+    }
+
+    /// Repeats each of `schedule` for the life of the server.
+    pub fn spawn_schedule(self: &Arc<Self>, schedule: &[ScheduledAnnouncement]) {
+        for scheduled in schedule.iter().filter(|s| s.every_secs > 0) {
+            let announcer = self.clone();
+            let scheduled = scheduled.clone();
+            tokio::spawn(async move {
+                let period = Duration::from_secs(scheduled.every_secs);
+                let mut interval =
+                    tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = announcer.announce(&scheduled.announcement).await {
+                        warn!(
+                            "Scheduled announcement {:?} failed: {e}",
+                            scheduled.announcement.text
+                        );
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// An announcement requested through the admin API.
+#[derive(Debug, Clone, Deserialize)]
+struct AnnouncementRequest {
+    #[serde(flatten)]
+    announcement: Announcement,
+    #[serde(default)]
+    delay_secs: u32,
+}
+
+struct AdminState<E> {
+    announcer: Arc<Announcer<E>>,
+    token: Arc<str>,
+}
+
+impl<E> Clone for AdminState<E> {
+    fn clone(&self) -> Self {
+        Self {
+            announcer: self.announcer.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+/// Builds the announcement admin API:
+///
+/// - `POST /admin/announcements` with the text, channel ids and an optional
+///   `delay_secs`, answering `202 Accepted` once the announcement is queued
+pub fn admin_router<E: SpeechEngine>(announcer: Arc<Announcer<E>>, admin_token: &str) -> Router {
+    Router::new()
+        .route("/admin/announcements", post(post_announcement::<E>))
+        .with_state(AdminState {
+            announcer,
+            token: admin_token.into(),
+        })
+}
+
+async fn post_announcement<E: SpeechEngine>(
+    State(state): State<AdminState<E>>,
+    headers: HeaderMap,
+    Json(request): Json<AnnouncementRequest>,
+) -> Result<StatusCode, Response> {
+    crate::reports::authorize(&state.token, &headers).map_err(IntoResponse::into_response)?;
+    let mut errors = FieldErrors::new();
+    request
+        .announcement
+        .check(&mut errors, &ServerLimits::default());
+    if request.delay_secs > MAX_ANNOUNCEMENT_DELAY_SECS {
+        errors.add(
+            "delay_secs",
+            Constraint::OutOfRange {
+                min: 0,
+                max: MAX_ANNOUNCEMENT_DELAY_SECS.into(),
+            },
+        );
+    }
+    if !errors.is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response());
+    }
+    state.announcer.spawn_announce(
+        request.announcement,
+        Duration::from_secs(request.delay_secs.into()),
+    );
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_test_support::wait_until;
+
+    /// A CELT packet of one 20 ms frame.
+    fn opus_frame(fill: u8) -> Vec<u8> {
+        vec![0xF8, fill, fill]
+    }
+
+    /// Speaks every text as the same three frames.
+    struct FixedEngine;
+
+    impl SpeechEngine for FixedEngine {
+        async fn synthesize(&self, _text: &str) -> Result<Vec<Vec<u8>>, FleetNetError> {
+            Ok((1..=3).map(opus_frame).collect())
+        }
+    }
+
+    fn ogg_page(packets: &[&[u8]]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        let mut body = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+            body.extend_from_slice(packet);
+        }
+        let mut page = b"OggS".to_vec();
+        page.resize(26, 0);
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        page.extend(body);
+        page
+    }
+
+    fn announcement(text: &str, channel_ids: &[u16]) -> Announcement {
+        Announcement {
+            text: text.to_string(),
+            channel_ids: channel_ids
+                .iter()
+                .map(|&id| ChannelId::new(id).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_ogg_opus_packets_skip_the_headers() {
+        let long = vec![0xF8; 300];
+        let mut stream = ogg_page(&[b"OpusHead\x01\x01"]);
+        stream.extend(ogg_page(&[b"OpusTags", &opus_frame(1)]));
+        stream.extend(ogg_page(&[&long, &opus_frame(2)]));
+
+        assert_eq!(
+            ogg_opus_packets(&stream).unwrap(),
+            [opus_frame(1), long, opus_frame(2)]
+        );
+        assert!(ogg_opus_packets(&ogg_page(&[b"OpusHead", b"OpusTags"]))
+            .unwrap()
+            .is_empty());
+        assert!(ogg_opus_packets(b"RIFF....WAVE").is_err());
+        assert!(ogg_opus_packets(&ogg_page(&[b"FLAC"])).is_err());
+    }
+
+    #[test]
+    fn test_announcements_are_validated() {
+        let errors =
+            |announcement: &Announcement| match announcement.validate(&ServerLimits::default()) {
+                Err(FleetNetError::ValidationError(errors)) => {
+                    errors.iter().map(ToString::to_string).collect::<Vec<_>>()
+                }
+                other => panic!("expected validation errors, got {other:?}"),
+            };
+        assert!(announcement("Server restarting in 5 minutes", &[1, 2])
+            .validate(&ServerLimits::default())
+            .is_ok());
+        assert_eq!(
+            errors(&announcement("  ", &[])),
+            ["text: too_short(1)", "channel_ids: required"]
+        );
+        assert_eq!(
+            errors(&announcement("Again", &[3, 3])),
+            ["channel_ids: duplicate"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announcements_play_into_every_channel() {
+        let subscriptions = Arc::new(SubscriptionRegistry::new());
+        let announcer_id = UserId::new(0xDFFF).unwrap();
+        let announcer = Announcer::bind(
+            FixedEngine,
+            announcer_id,
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            subscriptions.clone(),
+        )
+        .await
+        .unwrap();
+
+        let mut listeners = Vec::new();
+        for id in 1..=2 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            subscriptions.add_listener(
+                ChannelId::new(id).unwrap(),
+                RelaySubscriber {
+                    user_id: UserId::new(id).unwrap(),
+                    address: socket.local_addr().unwrap(),
+                },
+            );
+            listeners.push(socket);
+        }
+
+        let started = Instant::now();
+        let forwarded = announcer
+            .announce(&announcement("Server restarting in 5 minutes", &[1, 2]))
+            .await
+            .unwrap();
+        assert_eq!(forwarded, 6);
+        // Played in real time, the last frame going out after 40 ms
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        for (id, socket) in (1..=2).zip(&listeners) {
+            let mut buf = [0u8; 64];
+            for fill in 1..=3 {
+                let len = socket.recv(&mut buf).await.unwrap();
+                let packet = AudioPacket::from_bytes(&buf[..len]).unwrap();
+                assert_eq!(packet.header.channel_id, ChannelId::new(id).unwrap());
+                assert_eq!(packet.header.user_id, announcer_id);
+                assert_eq!(packet.header.sequence, u16::from(fill) - 1);
+                assert_eq!(packet.opus_payload, opus_frame(fill));
+            }
+        }
+        // The announcer leaves the channels once done
+        assert!(subscriptions
+            .listeners(ChannelId::new(1).unwrap())
+            .iter()
+            .all(|listener| listener.user_id != announcer_id));
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_queues_announcements() {
+        let subscriptions = Arc::new(SubscriptionRegistry::new());
+        let announcer = Arc::new(
+            Announcer::bind(
+                FixedEngine,
+                UserId::new(0xDFFF).unwrap(),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                subscriptions.clone(),
+            )
+            .await
+            .unwrap(),
+        );
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        subscriptions.add_listener(
+            ChannelId::new(4).unwrap(),
+            RelaySubscriber {
+                user_id: UserId::new(1).unwrap(),
+                address: listener.local_addr().unwrap(),
+            },
+        );
+
+        let router = admin_router(announcer, "s3cret");
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(tcp, router).await.unwrap() });
+        let url = format!("http://{address}/admin/announcements");
+        let client = reqwest::Client::new();
+        let request = serde_json::json!({"text": "Comms check", "channel_ids": [4]});
+
+        let unauthorized = client.post(&url).json(&request).send().await.unwrap();
+        assert_eq!(unauthorized.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let invalid = serde_json::json!({"text": "", "channel_ids": [4], "delay_secs": 100000});
+        let refused = client
+            .post(&url)
+            .bearer_auth("s3cret")
+            .json(&invalid)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(refused.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let errors: FieldErrors = refused.json().await.unwrap();
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["text: too_short(1)", "delay_secs: out_of_range(0..=86400)"]
+        );
+
+        let queued = client
+            .post(&url)
+            .bearer_auth("s3cret")
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(queued.status(), reqwest::StatusCode::ACCEPTED);
+        let mut buf = [0u8; 64];
+        let heard = tokio::time::timeout(Duration::from_secs(5), listener.recv(&mut buf)).await;
+        assert!(heard.is_ok());
+        let played = wait_until(Duration::from_secs(5), Duration::from_millis(10), || {
+            subscriptions.packets_forwarded() == 3
+        })
+        .await;
+        assert!(played);
+    }
+}
@@ -0,0 +1,92 @@
+//! Access tokens clients authenticate with.
+//!
+//! Users sign in with Discord through the login service, which hands the
+//! client an HS256 JWT signed with the secret it shares with this server.
+//! The token's `sub` claim is the user's Fleet Net id and it expires after
+//! [`TOKEN_LIFETIME`], after which the client signs in again.
+
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::types::UserId;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long an issued token is accepted.
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    /// The user id, as JWT subjects are strings.
+    sub: String,
+    /// Unix time the token expires at, in seconds.
+    exp: u64,
+}
+
+/// Issues and verifies access tokens with a shared secret.
+pub struct TokenVerifier {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+}
+
+impl TokenVerifier {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+
+    /// Signs a token for `user_id`, as the login service does.
+    pub fn issue(&self, user_id: UserId) -> Result<String, FleetNetError> {
+        let expires = SystemTime::now() + TOKEN_LIFETIME;
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp: expires
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding).map_err(|e| {
+            FleetNetError::EncryptionError(Cow::Owned(format!("Failed to sign token: {e}")))
+        })
+    }
+
+    /// The user `token` was issued to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an authentication error if the token is malformed, expired
+    /// or not signed with this server's secret.
+    pub fn verify(&self, token: &str) -> Result<UserId, FleetNetError> {
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding, &self.validation)
+            .map_err(|e| FleetNetError::AuthError(Cow::Owned(format!("Invalid token: {e}"))))?
+            .claims;
+        claims
+            .sub
+            .parse()
+            .ok()
+            .and_then(UserId::new)
+            .ok_or(FleetNetError::AuthError(Cow::Borrowed(
+                "Token is not issued to a user",
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_verify_only_with_their_secret() {
+        let verifier = TokenVerifier::new(b"shared-secret");
+        let user_id = UserId::new(42).unwrap();
+        let token = verifier.issue(user_id).unwrap();
+
+        assert_eq!(verifier.verify(&token).unwrap(), user_id);
+        assert!(TokenVerifier::new(b"other-secret").verify(&token).is_err());
+        assert!(verifier.verify("not-a-token").is_err());
+    }
+}
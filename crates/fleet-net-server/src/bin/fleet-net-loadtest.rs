@@ -0,0 +1,54 @@
+//! Load generator for the server, see the `loadtest` module.
+//!
+//! ```text
+//! fleet-net-loadtest [--clients 200] [--per-channel 10] [--pps 50] [--duration 10]
+//! ```
+
+use fleet_net_server::loadtest::{self, LoadTestConfig};
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::{error, info};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    fleet_net_common::logging::init_tracing();
+
+    let config = match parse_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    info!(?config, "Starting load test");
+    match loadtest::run(&config).await {
+        Ok(report) => {
+            info!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Load test failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<LoadTestConfig, String> {
+    let mut config = LoadTestConfig::default();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {flag}"))?;
+        let number: u64 = value
+            .parse()
+            .map_err(|_| format!("{flag} expects a number, got {value:?}"))?;
+        match flag.as_str() {
+            "--clients" => config.clients = number as usize,
+            "--per-channel" => config.clients_per_channel = number as usize,
+            "--pps" => config.packets_per_second = number as u32,
+            "--duration" => config.duration = Duration::from_secs(number),
+            _ => return Err(format!("Unknown option {flag}")),
+        }
+    }
+    Ok(config)
+}
@@ -0,0 +1,75 @@
+//! Converts a TeamSpeak 3 ServerQuery transcript into a Fleet Net template,
+//! see `fleet_net_common::teamspeak` for what the transcript holds.
+//!
+//! ```text
+//! fleet-net-ts3-import <transcript.txt> [--json] > template.yaml
+//! ```
+//!
+//! The template is written to stdout as YAML, or JSON with `--json`, and
+//! everything that could not be mapped is logged. Check it against the
+//! server with `PUT /admin/template?format=yaml&dry_run=true` before
+//! importing it for real.
+
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::teamspeak::TeamSpeakImport;
+use fleet_net_common::validation::Validate;
+use std::io::Write;
+use std::process::ExitCode;
+use tracing::{error, info, warn};
+
+fn main() -> ExitCode {
+    // Logs go to stderr, stdout is the template
+    tracing_subscriber::fmt()
+        .with_env_filter("fleet_net=info")
+        .with_writer(std::io::stderr)
+        .init();
+
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut path = None;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+            _ if path.is_some() => return Err(format!("Unexpected argument {arg}")),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.ok_or("Usage: fleet-net-ts3-import <transcript.txt> [--json]")?;
+
+    let transcript =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+    let import = TeamSpeakImport::from_transcript(&transcript)
+        .map_err(|e| format!("Failed to convert {path}: {e}"))?;
+    for note in &import.unmapped {
+        warn!("{note}");
+    }
+    if let Err(e) = import.template.validate(&ServerLimits::default()) {
+        warn!("The template will need changes before it can be imported: {e}");
+    }
+    info!(
+        channels = import.template.channels.len(),
+        roles = import.template.roles.len(),
+        unmapped = import.unmapped.len(),
+        "Converted {path}"
+    );
+
+    let output = if json {
+        serde_json::to_string_pretty(&import.template).map_err(|e| e.to_string())?
+    } else {
+        serde_yaml::to_string(&import.template).map_err(|e| e.to_string())?
+    };
+    std::io::stdout()
+        .lock()
+        .write_all(output.as_bytes())
+        .map_err(|e| format!("Failed to write the template: {e}"))
+}
@@ -0,0 +1,193 @@
+//! Channel topics, icons and metadata.
+//!
+//! Users holding [`Permissions::MANAGE_CHANNELS`] replace a channel's topic,
+//! icon and metadata with [`ControlMessage::UpdateChannelInfo`]. The channel
+//! is saved to its [`ChannelStore`] and each change is published as a
+//! [`ControlMessage::ChannelInfoChanged`] for connections to broadcast.
+//!
+//! Metadata is opaque to the server, so game integrations can keep things
+//! like ATIS frequencies or map grids on the channel they belong to.
+
+use crate::store::{ChannelBackend, ChannelStore, InMemoryChannelStore};
+use fleet_net_common::channel::Channel;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::session::Session;
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::ControlMessage;
+use std::borrow::Cow;
+use tokio::sync::broadcast;
+
+/// Channel changes buffered for slow subscribers.
+const CHANGE_BUFFER: usize = 64;
+
+pub struct ChannelRegistry<S = ChannelBackend> {
+    store: S,
+    changes: broadcast::Sender<ControlMessage>,
+    limits: ServerLimits,
+}
+
+impl ChannelRegistry {
+    /// A registry keeping channels in memory.
+    pub fn in_memory() -> Self {
+        Self::new(ChannelBackend::InMemory(InMemoryChannelStore::new()))
+    }
+}
+
+impl<S: ChannelStore> ChannelRegistry<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            changes: broadcast::channel(CHANGE_BUFFER).0,
+            limits: ServerLimits::default(),
+        }
+    }
+
+    /// Checks requests against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Receives every channel change from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ControlMessage> {
+        self.changes.subscribe()
+    }
+
+    /// Handles a channel info update from `session` and returns the change,
+    /// which is also published to subscribers.
+    pub async fn apply(
+        &self,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<ControlMessage, FleetNetError> {
+        session.ensure_interactive()?;
+        let ControlMessage::UpdateChannelInfo {
+            channel_id,
+            topic,
+            icon,
+            metadata,
+        } = message
+        else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected update_channel_info")),
+            ));
+        };
+        if !session.permission.has(Permissions::MANAGE_CHANNELS) {
+            return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                "Missing permission to manage channels",
+            )));
+        }
+        Channel::validate_info(topic, icon, metadata, &self.limits)?;
+
+        let mut channel = self
+            .store
+            .load(*channel_id)
+            .await?
+            .ok_or_else(|| FleetNetError::invalid_field("channel_id", Constraint::NotFound))?;
+        channel.topic = topic.clone();
+        channel.icon = icon.clone();
+        channel.metadata = metadata.clone();
+        self.store.save(channel).await?;
+        session.update_activity();
+
+        let change = ControlMessage::ChannelInfoChanged {
+            channel_id: *channel_id,
+            topic: topic.clone(),
+            icon: icon.clone(),
+            metadata: metadata.clone(),
+        };
+        // Nobody listening is fine; the store is still up to date.
+        let _ = self.changes.send(change.clone());
+        Ok(change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::{AudioPolicy, ChannelType};
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::types::{ChannelId, UserId};
+    use fleet_net_common::user::User;
+    use std::collections::HashMap;
+    use std::time::Instant;
+
+    fn session(permissions: Permissions) -> Session {
+        Session {
+            id: "session".to_string(),
+            user: User::new(UserId::new(1).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    fn update(channel_id: u16, topic: &str) -> ControlMessage {
+        ControlMessage::UpdateChannelInfo {
+            channel_id: ChannelId::new(channel_id).unwrap(),
+            topic: Some(topic.to_string()),
+            icon: Some("tower".to_string()),
+            metadata: HashMap::from([("atis".to_string(), "121.500".to_string())]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channel_info_updates_are_saved_and_broadcast() {
+        let registry = ChannelRegistry::in_memory();
+        let channel_id = ChannelId::new(1).unwrap();
+        registry
+            .store()
+            .save(Channel {
+                id: channel_id,
+                name: "Tower".to_string(),
+                description: None,
+                channel_type: ChannelType::Radio,
+                role_permissions: HashMap::new(),
+                position: 0,
+                parent_id: None,
+                topic: None,
+                icon: None,
+                metadata: HashMap::new(),
+                radio: None,
+                audio_policy: AudioPolicy::default(),
+            })
+            .await
+            .unwrap();
+        let mut changes = registry.subscribe();
+
+        let mut member = session(Permissions::CONNECT);
+        let result = registry.apply(&mut member, &update(1, "Runway 27")).await;
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+
+        let mut admin = session(Permissions::MANAGE_CHANNELS);
+        registry
+            .apply(&mut admin, &update(1, "Runway 27"))
+            .await
+            .unwrap();
+        let saved = registry.store().load(channel_id).await.unwrap().unwrap();
+        assert_eq!(saved.topic.as_deref(), Some("Runway 27"));
+        assert_eq!(saved.metadata["atis"], "121.500");
+        assert!(matches!(
+            changes.try_recv(),
+            Ok(ControlMessage::ChannelInfoChanged { topic: Some(topic), .. }) if topic == "Runway 27"
+        ));
+
+        // Unknown channels are reported against the channel id
+        let result = registry.apply(&mut admin, &update(2, "Runway 09")).await;
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+    }
+}
@@ -0,0 +1,1369 @@
+//! Horizontal scaling with a voice relay tier.
+//!
+//! In clustered deployments a single coordinator keeps all session and control
+//! state, while any number of stateless relay nodes forward UDP audio. The
+//! coordinator pushes channel routing tables to relays over TLS using the
+//! [`ClusterMessage`] protocol, and relays report their load back so new users
+//! can be placed on the least busy relay.
+//!
+//! Each client is [assigned](Coordinator::assign_relay) a relay as it
+//! authenticates and told its address in the `AuthResponse`. Relays check
+//! voice like the coordinator does: the coordinator pushes them every active
+//! user's session key, whether they may transmit and their transmit
+//! settings, as well as the channels with their audio policies, and relays
+//! forward through their own [`SubscriptionRegistry`]. Packets are only
+//! forwarded when signed with the sender's session key, and every listener
+//! gets the header signed with theirs. Relays report the voice addresses
+//! they learn back to the coordinator, and pass on voice for listeners who
+//! tunnel it over their control connection.
+//!
+//! Relays send voice straight to listeners, wherever those send their own,
+//! so they need to be reachable from the clients' networks. Preemption,
+//! transmission events and speaking indicators are tracked by whichever node
+//! the sender's voice reaches, and a mute takes effect on relays with the
+//! next sync, within [`ROUTE_SYNC_INTERVAL`].
+//!
+//! [`Server::start`](crate::server::Server::start) sets a node up for its
+//! [`ClusterMode`]: a coordinator accepts relays on its relay address with
+//! its own certificate and keeps them in sync with the channel listeners,
+//! while a relay registers with the coordinator and only forwards voice.
+//!
+//! The cluster protocol is JSON in the same length-prefixed frames as the
+//! client control protocol rather than gRPC. It reuses the TLS setup, framing
+//! and size limits the server already has, and keeps a protobuf toolchain and
+//! a second RPC stack out of the build for a handful of message types.
+
+use crate::subscriptions::SubscriptionRegistry;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use fleet_net_common::channel::{Channel, ChannelTree};
+use fleet_net_common::error::{FleetNetError, FleetNetErrorCode};
+use fleet_net_common::logging::ViolationLog;
+use fleet_net_common::secret::constant_time_eq;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::cluster::{ClusterMessage, RelaySender, RelaySubscriber};
+use fleet_net_protocol::connection::Connection;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use fleet_net_protocol::tls::TlsConfig;
+use rustls::pki_types::ServerName;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, info, warn};
+
+/// How often a coordinator pushes changed channel listeners to its relays.
+pub const ROUTE_SYNC_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a relay reports its load to the coordinator.
+pub const RELAY_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reports buffered on their way between relays and the coordinator,
+/// tunneled voice included.
+const REPORT_BUFFER: usize = 1024;
+
+/// How this server instance participates in a cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ClusterMode {
+    /// Single node handling both control and voice traffic.
+    #[default]
+    Standalone,
+
+    /// Holds sessions and control state, and accepts relay registrations.
+    Coordinator {
+        /// Address relays connect to for the internal TLS protocol.
+        relay_bind_address: String,
+        /// Shared secret every relay must present when registering.
+        cluster_secret: String,
+    },
+
+    /// Stateless voice forwarder fed by a coordinator.
+    Relay(RelayConfig),
+}
+
+/// Settings for a node running in relay mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayConfig {
+    /// Unique name of this relay within the cluster.
+    pub relay_id: String,
+    /// Address of the coordinator's relay listener.
+    pub coordinator_address: String,
+    /// Hostname presented in the coordinator's TLS certificate.
+    pub coordinator_hostname: String,
+    /// CA certificate the coordinator's certificate is checked against; the
+    /// system roots when `None`.
+    pub coordinator_ca_path: Option<PathBuf>,
+    /// UDP address this relay receives voice on, advertised to the
+    /// coordinator as bound, so it should not be a wildcard address.
+    pub voice_bind_address: String,
+    /// Maximum number of users this relay should be assigned.
+    pub capacity: u32,
+    /// Shared secret presented to the coordinator when registering.
+    pub cluster_secret: String,
+}
+
+/// Coordinator-side view of a registered relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayInfo {
+    pub relay_id: String,
+    pub voice_address: SocketAddr,
+    pub capacity: u32,
+    pub active_users: u32,
+}
+
+impl RelayInfo {
+    /// Fraction of the relay's capacity currently in use.
+    pub fn load(&self) -> f64 {
+        if self.capacity == 0 {
+            return 1.0;
+        }
+        f64::from(self.active_users) / f64::from(self.capacity)
+    }
+
+    pub fn has_capacity(&self) -> bool {
+        self.active_users < self.capacity
+    }
+}
+
+/// Aborts a helper task when the owning future completes or is dropped.
+pub(crate) struct AbortOnDrop(pub(crate) JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+struct RelayHandle {
+    info: RelayInfo,
+    outbound: mpsc::UnboundedSender<ClusterMessage>,
+}
+
+/// Tracks relays and distributes routing tables to them.
+///
+/// Relay connections carry routing tables with every user's voice address
+/// and session keys, so only relays presenting the cluster secret are
+/// registered.
+pub struct Coordinator {
+    relays: DashMap<String, RelayHandle>,
+    /// Routes last published, sent whole to relays as they register.
+    routes: DashMap<ChannelId, Vec<RelaySubscriber>>,
+    /// Senders last published, likewise.
+    senders: DashMap<UserId, RelaySender>,
+    /// Channels last published, likewise.
+    channels: Mutex<Vec<Channel>>,
+    /// Voice addresses and tunneled voice relays report for their users.
+    reports: broadcast::Sender<ClusterMessage>,
+    violations: ViolationLog<String>,
+    cluster_secret: String,
+}
+
+impl Coordinator {
+    pub fn new(cluster_secret: impl Into<String>) -> Self {
+        Self {
+            relays: DashMap::new(),
+            routes: DashMap::new(),
+            senders: DashMap::new(),
+            channels: Mutex::new(Vec::new()),
+            reports: broadcast::channel(REPORT_BUFFER).0,
+            violations: ViolationLog::default(),
+            cluster_secret: cluster_secret.into(),
+        }
+    }
+
+    pub fn relay_count(&self) -> usize {
+        self.relays.len()
+    }
+
+    pub fn relay(&self, relay_id: &str) -> Option<RelayInfo> {
+        self.relays.get(relay_id).map(|handle| handle.info.clone())
+    }
+
+    /// Picks the least loaded relay that still has room for another user.
+    pub fn select_relay(&self) -> Option<RelayInfo> {
+        self.relays
+            .iter()
+            .map(|handle| handle.info.clone())
+            .filter(RelayInfo::has_capacity)
+            .min_by(|a, b| a.load().total_cmp(&b.load()))
+    }
+
+    /// Picks the relay a user who just authenticated sends their voice to,
+    /// and counts them towards its load until it next reports its stats.
+    /// `None` when no relay has room, for the user to send it here.
+    pub fn assign_relay(&self) -> Option<SocketAddr> {
+        let selected = self.select_relay()?;
+        let mut handle = self.relays.get_mut(&selected.relay_id)?;
+        handle.info.active_users += 1;
+        Some(handle.info.voice_address)
+    }
+
+    /// Receives every [`ClusterMessage::VoiceAddress`] and
+    /// [`ClusterMessage::TunneledVoice`] relays send from now on.
+    pub fn subscribe_reports(&self) -> broadcast::Receiver<ClusterMessage> {
+        self.reports.subscribe()
+    }
+
+    /// Pushes the full subscriber list for a channel to every relay.
+    pub fn publish_routes(&self, channel_id: ChannelId, subscribers: Vec<RelaySubscriber>) {
+        self.routes.insert(channel_id, subscribers.clone());
+        self.broadcast(ClusterMessage::RouteUpdate {
+            channel_id,
+            subscribers,
+        });
+    }
+
+    /// Tells every relay to stop forwarding for a channel.
+    pub fn remove_routes(&self, channel_id: ChannelId) {
+        self.routes.remove(&channel_id);
+        self.broadcast(ClusterMessage::RouteRemoved { channel_id });
+    }
+
+    /// Publishes the channels whose listeners differ from `routes` since the
+    /// last call, and removes those no longer listened to.
+    pub fn sync_routes(&self, mut routes: HashMap<ChannelId, Vec<RelaySubscriber>>) {
+        let stale: Vec<ChannelId> = self
+            .routes
+            .iter()
+            .filter(|entry| !routes.contains_key(entry.key()))
+            .map(|entry| *entry.key())
+            .collect();
+        for channel_id in stale {
+            self.remove_routes(channel_id);
+        }
+        for (channel_id, mut subscribers) in routes.drain() {
+            subscribers.sort_unstable_by_key(|subscriber| subscriber.user_id);
+            let unchanged = self
+                .routes
+                .get(&channel_id)
+                .is_some_and(|published| *published == subscribers);
+            if !unchanged {
+                self.publish_routes(channel_id, subscribers);
+            }
+        }
+    }
+
+    /// Publishes the senders that differ from `senders` since the last
+    /// call, and removes those no longer connected.
+    pub fn sync_senders(&self, mut senders: HashMap<UserId, RelaySender>) {
+        let stale: Vec<UserId> = self
+            .senders
+            .iter()
+            .filter(|entry| !senders.contains_key(entry.key()))
+            .map(|entry| *entry.key())
+            .collect();
+        for user_id in stale {
+            self.senders.remove(&user_id);
+            self.broadcast(ClusterMessage::SenderRemoved { user_id });
+        }
+        for (user_id, sender) in senders.drain() {
+            let unchanged = self
+                .senders
+                .get(&user_id)
+                .is_some_and(|published| *published == sender);
+            if !unchanged {
+                self.publish_sender(user_id, sender);
+            }
+        }
+    }
+
+    /// Pushes what relays need to forward `user_id`'s voice to every
+    /// relay, e.g. before the user is told which relay to use.
+    pub fn publish_sender(&self, user_id: UserId, sender: RelaySender) {
+        self.senders.insert(user_id, sender.clone());
+        self.broadcast(ClusterMessage::SenderUpdate { user_id, sender });
+    }
+
+    /// Publishes `channels` if they differ from the last call.
+    pub fn sync_channels(&self, mut channels: Vec<Channel>) {
+        channels.sort_unstable_by_key(|channel| channel.id);
+        let mut published = self.channels.lock().unwrap();
+        if *published != channels {
+            *published = channels.clone();
+            drop(published);
+            self.broadcast(ClusterMessage::ChannelsUpdated { channels });
+        }
+    }
+
+    fn broadcast(&self, message: ClusterMessage) {
+        for handle in self.relays.iter() {
+            // A closed channel means the relay is disconnecting; its handler cleans up.
+            let _ = handle.outbound.send(message.clone());
+        }
+    }
+
+    /// Compares in constant time so the secret cannot be guessed byte by byte.
+    fn secret_matches(&self, presented: &str) -> bool {
+        constant_time_eq(presented.as_bytes(), self.cluster_secret.as_bytes())
+    }
+
+    /// Accepts relay connections over TLS until the listener fails.
+    pub async fn serve_relays(
+        self: Arc<Self>,
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+    ) -> Result<(), FleetNetError> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let coordinator = self.clone();
+            let acceptor = acceptor.clone();
+
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(tls_stream) => {
+                        if let Err(e) = coordinator.handle_relay(Connection::new(tls_stream)).await
+                        {
+                            warn!("Relay connection from {addr} ended: {e}");
+                        }
+                    }
+                    Err(e) => warn!("Relay TLS handshake from {addr} failed: {e}"),
+                }
+            });
+        }
+    }
+
+    /// Runs the coordinator side of a single relay connection.
+    ///
+    /// The relay must register first. Afterwards routing updates are pushed to it
+    /// while its stats and heartbeats are consumed. The relay is removed from the
+    /// registry when the connection ends.
+    pub async fn handle_relay<S>(&self, mut conn: Connection<S>) -> Result<(), FleetNetError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (relay_id, voice_address, capacity) = match conn.read_frame().await? {
+            ClusterMessage::RegisterRelay {
+                relay_id,
+                voice_address,
+                capacity,
+                cluster_secret,
+            } => {
+                if !self.secret_matches(&cluster_secret) {
+                    conn.write_frame(&ClusterMessage::RelayRejected {
+                        reason: Cow::Borrowed("Invalid cluster secret"),
+                    })
+                    .await?;
+                    return Err(FleetNetError::AuthError(Cow::Owned(format!(
+                        "Relay {relay_id} presented an invalid cluster secret"
+                    ))));
+                }
+                (relay_id, voice_address, capacity)
+            }
+            _ => {
+                return Err(FleetNetError::NetworkError(Cow::Borrowed(
+                    "Relay must register before sending other messages",
+                )))
+            }
+        };
+
+        // Claiming the id through the entry keeps two relays registering the
+        // same id at once from both being accepted.
+        let (outbound, mut outbound_rx) = mpsc::unbounded_channel();
+        let registered = match self.relays.entry(relay_id.clone()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(RelayHandle {
+                    info: RelayInfo {
+                        relay_id: relay_id.clone(),
+                        voice_address,
+                        capacity,
+                        active_users: 0,
+                    },
+                    outbound: outbound.clone(),
+                });
+                true
+            }
+        };
+        if !registered {
+            conn.write_frame(&ClusterMessage::RelayRejected {
+                reason: Cow::Borrowed("Relay id already registered"),
+            })
+            .await?;
+            return Err(FleetNetError::NetworkError(Cow::Owned(format!(
+                "Duplicate relay id {relay_id}"
+            ))));
+        }
+        info!("Relay {relay_id} registered at {voice_address} (capacity {capacity})");
+
+        let (mut reader, mut writer) = conn.into_split();
+        let _ = outbound.send(ClusterMessage::RelayAccepted {
+            relay_id: relay_id.clone(),
+        });
+        let channels = self.channels.lock().unwrap().clone();
+        let _ = outbound.send(ClusterMessage::ChannelsUpdated { channels });
+        for entry in self.senders.iter() {
+            let _ = outbound.send(ClusterMessage::SenderUpdate {
+                user_id: *entry.key(),
+                sender: entry.value().clone(),
+            });
+        }
+        for entry in self.routes.iter() {
+            let _ = outbound.send(ClusterMessage::RouteUpdate {
+                channel_id: *entry.key(),
+                subscribers: entry.value().clone(),
+            });
+        }
+
+        let _writer_task = AbortOnDrop(tokio::spawn(async move {
+            while let Some(message) = outbound_rx.recv().await {
+                if let Err(e) = writer.write_frame(&message).await {
+                    debug!("Stopped writing to relay: {e}");
+                    break;
+                }
+            }
+        }));
+
+        let result = loop {
+            match reader.read_frame::<ClusterMessage>().await {
+                Ok(ClusterMessage::RelayStats { active_users, .. }) => {
+                    if let Some(mut handle) = self.relays.get_mut(&relay_id) {
+                        handle.info.active_users = active_users;
+                    }
+                }
+                Ok(ClusterMessage::Heartbeat) => {
+                    let _ = outbound.send(ClusterMessage::HeartbeatAck);
+                }
+                Ok(
+                    report @ (ClusterMessage::VoiceAddress { .. }
+                    | ClusterMessage::TunneledVoice { .. }),
+                ) => {
+                    // Nobody listening is fine
+                    let _ = self.reports.send(report);
+                }
+                Ok(other) => {
+                    self.violations.record(
+                        &relay_id,
+                        FleetNetErrorCode::InvalidRequest.as_str(),
+                        &format_args!("unexpected {other:?}"),
+                    );
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.relays.remove(&relay_id);
+        info!("Relay {relay_id} disconnected");
+        result
+    }
+}
+
+/// Stateless UDP forwarder driven by routing tables from the coordinator.
+pub struct RelayNode {
+    relay_id: String,
+    subscriptions: Arc<SubscriptionRegistry>,
+    /// Connected users, as last pushed by the coordinator.
+    senders: DashMap<UserId, RelaySender>,
+    /// Voice addresses of the users sending their voice here.
+    clients: DashMap<UserId, SocketAddr>,
+    /// Passes voice for each tunneled user on to the coordinator.
+    tunnels: DashMap<UserId, AbortOnDrop>,
+    /// Messages for the coordinator about the relay's users.
+    reports: broadcast::Sender<ClusterMessage>,
+    violations: ViolationLog<SocketAddr>,
+}
+
+impl RelayNode {
+    pub fn new(relay_id: impl Into<String>) -> Self {
+        Self {
+            relay_id: relay_id.into(),
+            subscriptions: Arc::new(SubscriptionRegistry::new()),
+            senders: DashMap::new(),
+            clients: DashMap::new(),
+            tunnels: DashMap::new(),
+            reports: broadcast::channel(REPORT_BUFFER).0,
+            violations: ViolationLog::default(),
+        }
+    }
+
+    /// Forwards through `subscriptions`, e.g. to pad and model radio
+    /// reception as configured for the server.
+    pub fn with_subscriptions(mut self, subscriptions: Arc<SubscriptionRegistry>) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
+    pub fn relay_id(&self) -> &str {
+        &self.relay_id
+    }
+
+    pub fn packets_forwarded(&self) -> u64 {
+        self.subscriptions.packets_forwarded()
+    }
+
+    /// Number of users the coordinator told this relay about.
+    pub fn sender_count(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Everyone the relay forwards voice on `channel_id` to.
+    pub fn listeners(&self, channel_id: ChannelId) -> Vec<RelaySubscriber> {
+        self.subscriptions.listeners(channel_id)
+    }
+
+    /// Number of users sending their voice to this relay.
+    pub fn active_users(&self) -> u32 {
+        self.clients.len() as u32
+    }
+
+    /// Applies a routing message from the coordinator.
+    pub fn apply(&self, message: &ClusterMessage) {
+        match message {
+            ClusterMessage::RouteUpdate {
+                channel_id,
+                subscribers,
+            } => {
+                // Users sending their voice here are reachable where it comes
+                // from, which the coordinator may not have heard yet
+                let subscribers = subscribers
+                    .iter()
+                    .map(|subscriber| RelaySubscriber {
+                        address: self
+                            .clients
+                            .get(&subscriber.user_id)
+                            .map_or(subscriber.address, |address| *address),
+                        ..*subscriber
+                    })
+                    .collect();
+                self.subscriptions
+                    .replace_listeners(*channel_id, subscribers);
+            }
+            ClusterMessage::RouteRemoved { channel_id } => {
+                self.subscriptions
+                    .replace_listeners(*channel_id, Vec::new());
+            }
+            ClusterMessage::ChannelsUpdated { channels } => {
+                match ChannelTree::from_channels(channels.iter().cloned()) {
+                    Ok(tree) => self.subscriptions.update_channels(&tree),
+                    Err(e) => warn!("Ignoring channels from the coordinator: {e}"),
+                }
+            }
+            ClusterMessage::SenderUpdate { user_id, sender } => {
+                self.subscriptions.apply_relay_sender(*user_id, sender);
+                self.set_tunneled(*user_id, sender.tunneled);
+                self.senders.insert(*user_id, sender.clone());
+            }
+            ClusterMessage::SenderRemoved { user_id } => {
+                self.senders.remove(user_id);
+                self.clients.remove(user_id);
+                self.tunnels.remove(user_id);
+                self.subscriptions.remove_user(*user_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Passes the voice `user_id` hears on to the coordinator while they
+    /// tunnel it, and sends it to their voice address otherwise.
+    fn set_tunneled(&self, user_id: UserId, tunneled: bool) {
+        if !tunneled {
+            if self.tunnels.remove(&user_id).is_some() {
+                self.subscriptions.close_tunnel(user_id);
+            }
+            return;
+        }
+        if self.tunnels.contains_key(&user_id) {
+            return;
+        }
+        let mut tunnel = self.subscriptions.open_tunnel(user_id);
+        let reports = self.reports.clone();
+        let task = tokio::spawn(async move {
+            while let Some(message) = tunnel.recv().await {
+                if let ControlMessage::VoiceTunnel { packet } = message {
+                    // Nobody listening is fine: the coordinator is reconnecting
+                    let _ = reports.send(ClusterMessage::TunneledVoice { user_id, packet });
+                }
+            }
+        });
+        self.tunnels.insert(user_id, AbortOnDrop(task));
+    }
+
+    /// Determines where a packet should be forwarded.
+    ///
+    /// Packets are only forwarded when the source address belongs to a subscriber
+    /// of the channel with the same user id as the header, so a relay never
+    /// amplifies traffic from addresses the coordinator does not know about.
+    /// Addresses match whether or not they are v4-mapped, see
+    /// [`dual_stack::canonical`].
+    pub fn forward_targets(&self, header: &PacketHeader, source: SocketAddr) -> Vec<SocketAddr> {
+        self.subscriptions.forward_targets(header, source)
+    }
+
+    /// Forwards one datagram, returning the number of subscribers it was sent to.
+    ///
+    /// The packet must be signed with its sender's session key, who must be
+    /// allowed to transmit; it is then checked against the audio policies
+    /// of its channels like on the coordinator.
+    pub async fn forward_packet(
+        &self,
+        socket: &UdpSocket,
+        datagram: &[u8],
+        source: SocketAddr,
+    ) -> Result<usize, FleetNetError> {
+        let packet = AudioPacket::from_bytes(datagram)?;
+        let header = packet.header;
+        let Some(may_transmit) = self
+            .senders
+            .get(&header.user_id)
+            .filter(|sender| packet.validate_hmac(&sender.udp_key))
+            .map(|sender| sender.may_transmit)
+        else {
+            return Err(FleetNetError::PacketError(Cow::Borrowed(
+                "Packet HMAC does not match",
+            )));
+        };
+        self.learn_voice_address(header.user_id, source);
+        if (header.audio_length == 0 && !header.dtx) || !may_transmit {
+            return Ok(0);
+        }
+        self.subscriptions
+            .forward_packet(socket, datagram, source)
+            .await
+    }
+
+    /// Learns `user_id`'s voice address from a packet sent from `source`,
+    /// and reports it to the coordinator.
+    fn learn_voice_address(&self, user_id: UserId, source: SocketAddr) {
+        let source = dual_stack::canonical(source);
+        if self.clients.insert(user_id, source) != Some(source) {
+            self.subscriptions.set_voice_address(user_id, source);
+            // Nobody listening is fine: the coordinator is reconnecting
+            let _ = self.reports.send(ClusterMessage::VoiceAddress {
+                user_id,
+                address: source,
+            });
+        }
+    }
+
+    /// Connects to the coordinator of `config` over TLS and registers as
+    /// receiving voice on `voice_address`.
+    pub async fn connect(
+        &self,
+        config: &RelayConfig,
+        voice_address: SocketAddr,
+    ) -> Result<Connection<TlsStream<TcpStream>>, FleetNetError> {
+        let tls = match &config.coordinator_ca_path {
+            Some(ca_path) => TlsConfig::new_client(ca_path)?,
+            None => TlsConfig::new_client_with_system_roots(),
+        };
+        let client_config =
+            tls.client_config
+                .ok_or(FleetNetError::EncryptionError(Cow::Borrowed(
+                    "Missing TLS client config",
+                )))?;
+        let domain = ServerName::try_from(config.coordinator_hostname.clone()).map_err(|e| {
+            FleetNetError::EncryptionError(Cow::Owned(format!("Invalid coordinator hostname: {e}")))
+        })?;
+        let stream = TcpStream::connect(&config.coordinator_address).await?;
+        let stream = TlsConnector::from(client_config)
+            .connect(domain, stream)
+            .await?;
+
+        let mut conn = Connection::new(stream);
+        self.register(
+            &mut conn,
+            voice_address,
+            config.capacity,
+            &config.cluster_secret,
+        )
+        .await?;
+        Ok(conn)
+    }
+
+    /// Registers with the coordinator and waits for acceptance.
+    pub async fn register<S>(
+        &self,
+        conn: &mut Connection<S>,
+        voice_address: SocketAddr,
+        capacity: u32,
+        cluster_secret: &str,
+    ) -> Result<(), FleetNetError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        conn.write_frame(&ClusterMessage::RegisterRelay {
+            relay_id: self.relay_id.clone(),
+            voice_address,
+            capacity,
+            cluster_secret: cluster_secret.to_string(),
+        })
+        .await?;
+
+        match conn.read_frame().await? {
+            ClusterMessage::RelayAccepted { .. } => Ok(()),
+            ClusterMessage::RelayRejected { reason } => Err(FleetNetError::NetworkError(
+                Cow::Owned(format!("Coordinator rejected relay: {reason}")),
+            )),
+            _ => Err(FleetNetError::NetworkError(Cow::Borrowed(
+                "Unexpected response to relay registration",
+            ))),
+        }
+    }
+
+    /// Consumes routing updates, and reports stats periodically and what
+    /// the relay learns about its users as it happens to the coordinator.
+    pub async fn run_control<S>(
+        self: Arc<Self>,
+        conn: Connection<S>,
+        stats_interval: Duration,
+    ) -> Result<(), FleetNetError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut reader, mut writer) = conn.into_split();
+
+        let relay = self.clone();
+        let mut reports = self.reports.subscribe();
+        let _writer_task = AbortOnDrop(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(stats_interval);
+            loop {
+                let message = tokio::select! {
+                    _ = interval.tick() => ClusterMessage::RelayStats {
+                        relay_id: relay.relay_id.clone(),
+                        active_users: relay.active_users(),
+                        packets_forwarded: relay.packets_forwarded(),
+                    },
+                    report = reports.recv() => match report {
+                        Ok(report) => report,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Coordinator missed {skipped} relay reports");
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                };
+                if writer.write_frame(&message).await.is_err() {
+                    break;
+                }
+            }
+        }));
+
+        loop {
+            let message = reader.read_frame::<ClusterMessage>().await?;
+            self.apply(&message);
+        }
+    }
+
+    /// Receives voice datagrams and forwards them until the socket fails.
+    pub async fn run_voice(self: Arc<Self>, socket: UdpSocket) -> Result<(), FleetNetError> {
+        let mut buf = vec![0u8; 65_535];
+        loop {
+            let (len, source) = socket.recv_from(&mut buf).await?;
+            match self.forward_packet(&socket, &buf[..len], source).await {
+                Ok(_) => {}
+                Err(e @ FleetNetError::NetworkError(_)) => {
+                    debug!("Failed to forward packet from {source}: {e}");
+                }
+                Err(e) => {
+                    self.violations.record(&source, e.code().as_str(), &e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_channel;
+    use fleet_net_common::audio::TransmitMode;
+    use fleet_net_protocol::hmac::HmacKey;
+    use fleet_test_support::udp::{no_packet_within, send_truncated_header};
+    use fleet_test_support::{
+        connected_tcp_pair, recv_packet, recv_packet_from, send_packet, wait_until,
+    };
+
+    const SECRET: &str = "cluster-secret";
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    fn relay_info(relay_id: &str, capacity: u32, active_users: u32) -> RelayInfo {
+        RelayInfo {
+            relay_id: relay_id.to_string(),
+            voice_address: "127.0.0.1:7000".parse().unwrap(),
+            capacity,
+            active_users,
+        }
+    }
+
+    fn key(user_id: UserId) -> HmacKey {
+        HmacKey::from_bytes(&[user_id.get() as u8; 32])
+    }
+
+    fn relay_sender(user_id: UserId) -> RelaySender {
+        RelaySender {
+            udp_key: key(user_id),
+            may_transmit: true,
+            listen_only: false,
+            transmit_mode: TransmitMode::PushToTalk,
+            transmit_priority: None,
+            transmit_targets: Vec::new(),
+            tunneled: false,
+        }
+    }
+
+    fn sender_update(user_id: UserId) -> ClusterMessage {
+        ClusterMessage::SenderUpdate {
+            user_id,
+            sender: relay_sender(user_id),
+        }
+    }
+
+    fn signed_packet(channel_id: ChannelId, user_id: UserId, payload: &[u8]) -> AudioPacket {
+        AudioPacket::new_signed(
+            test_header(channel_id, user_id, 0),
+            payload.to_vec(),
+            &key(user_id),
+        )
+    }
+
+    fn test_header(channel_id: ChannelId, user_id: UserId, audio_length: u16) -> PacketHeader {
+        PacketHeader {
+            channel_id,
+            user_id,
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length,
+            hmac_prefix: 0,
+        }
+    }
+
+    #[test]
+    fn test_select_relay_prefers_least_loaded_with_capacity() {
+        let coordinator = Coordinator::new(SECRET);
+        for info in [
+            relay_info("busy", 100, 90),
+            relay_info("quiet", 100, 10),
+            relay_info("full", 10, 10),
+        ] {
+            let (outbound, _rx) = mpsc::unbounded_channel();
+            coordinator
+                .relays
+                .insert(info.relay_id.clone(), RelayHandle { info, outbound });
+        }
+
+        let selected = coordinator.select_relay().unwrap();
+        assert_eq!(selected.relay_id, "quiet");
+    }
+
+    #[test]
+    fn test_assigned_users_count_towards_relay_load() {
+        let coordinator = Coordinator::new(SECRET);
+        for info in [relay_info("a", 2, 0), relay_info("b", 2, 1)] {
+            let (outbound, _rx) = mpsc::unbounded_channel();
+            coordinator
+                .relays
+                .insert(info.relay_id.clone(), RelayHandle { info, outbound });
+        }
+
+        // Each assignment loads the relay it picked, until all are full
+        assert!(coordinator.assign_relay().is_some());
+        assert_eq!(coordinator.relay("a").unwrap().active_users, 1);
+        assert!(coordinator.assign_relay().is_some());
+        assert!(coordinator.assign_relay().is_some());
+        assert!(coordinator.assign_relay().is_none());
+    }
+
+    #[test]
+    fn test_only_changed_senders_are_published() {
+        let coordinator = Coordinator::new(SECRET);
+        let (outbound, mut rx) = mpsc::unbounded_channel();
+        coordinator.relays.insert(
+            "relay-1".to_string(),
+            RelayHandle {
+                info: relay_info("relay-1", 10, 0),
+                outbound,
+            },
+        );
+        let mut alice = relay_sender(user(1));
+
+        coordinator.sync_senders(HashMap::from([(user(1), alice.clone())]));
+        coordinator.sync_senders(HashMap::from([(user(1), alice.clone())]));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ClusterMessage::SenderUpdate { .. })
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // A mute goes out, and a disconnect removes the sender
+        alice.may_transmit = false;
+        coordinator.sync_senders(HashMap::from([(user(1), alice.clone())]));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ClusterMessage::SenderUpdate {
+                user_id: user(1),
+                sender: alice
+            }
+        );
+        coordinator.sync_senders(HashMap::new());
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ClusterMessage::SenderRemoved { user_id: user(1) }
+        );
+    }
+
+    #[test]
+    fn test_select_relay_none_when_all_full() {
+        let coordinator = Coordinator::new(SECRET);
+        let (outbound, _rx) = mpsc::unbounded_channel();
+        coordinator.relays.insert(
+            "full".to_string(),
+            RelayHandle {
+                info: relay_info("full", 5, 5),
+                outbound,
+            },
+        );
+
+        assert!(coordinator.select_relay().is_none());
+    }
+
+    #[test]
+    fn test_relay_forwards_only_from_known_senders() {
+        let relay = RelayNode::new("relay-1");
+        let alice: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let bob: SocketAddr = "127.0.0.1:5002".parse().unwrap();
+        let carol: SocketAddr = "127.0.0.1:5003".parse().unwrap();
+
+        relay.apply(&ClusterMessage::RouteUpdate {
+            channel_id: channel(1),
+            subscribers: vec![
+                RelaySubscriber {
+                    user_id: user(1),
+                    address: alice,
+                },
+                RelaySubscriber {
+                    user_id: user(2),
+                    address: bob,
+                },
+                RelaySubscriber {
+                    user_id: user(3),
+                    address: carol,
+                },
+            ],
+        });
+
+        // Known sender reaches everyone but themselves
+        let targets = relay.forward_targets(&test_header(channel(1), user(1), 0), alice);
+        assert_eq!(targets, vec![bob, carol]);
+
+        // Spoofed user id from another subscriber's address is dropped
+        assert!(relay
+            .forward_targets(&test_header(channel(1), user(1), 0), bob)
+            .is_empty());
+
+        // Unknown channel is dropped
+        assert!(relay
+            .forward_targets(&test_header(channel(9), user(1), 0), alice)
+            .is_empty());
+
+        relay.apply(&ClusterMessage::RouteRemoved {
+            channel_id: channel(1),
+        });
+        assert!(relay
+            .forward_targets(&test_header(channel(1), user(1), 0), alice)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_relay_registers_and_receives_routes() {
+        let (coordinator_stream, relay_stream) = connected_tcp_pair().await.unwrap();
+        let coordinator = Arc::new(Coordinator::new(SECRET));
+
+        let coordinator_task = {
+            let coordinator = coordinator.clone();
+            tokio::spawn(async move {
+                coordinator
+                    .handle_relay(Connection::new(coordinator_stream))
+                    .await
+            })
+        };
+
+        let relay = Arc::new(RelayNode::new("relay-1"));
+        let mut relay_conn = Connection::new(relay_stream);
+        relay
+            .register(
+                &mut relay_conn,
+                "127.0.0.1:7100".parse().unwrap(),
+                50,
+                SECRET,
+            )
+            .await
+            .expect("Relay registration should succeed");
+
+        let control_task = tokio::spawn(
+            relay
+                .clone()
+                .run_control(relay_conn, Duration::from_millis(10)),
+        );
+
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                coordinator.relay_count() == 1
+            })
+            .await
+        );
+
+        let speaker: SocketAddr = "127.0.0.1:5004".parse().unwrap();
+        let listener: SocketAddr = "127.0.0.1:5005".parse().unwrap();
+        coordinator.publish_routes(
+            channel(3),
+            vec![
+                RelaySubscriber {
+                    user_id: user(4),
+                    address: speaker,
+                },
+                RelaySubscriber {
+                    user_id: user(5),
+                    address: listener,
+                },
+            ],
+        );
+
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                relay.forward_targets(&test_header(channel(3), user(4), 0), speaker)
+                    == vec![listener]
+            })
+            .await
+        );
+
+        // Stats reported by the relay replace the load assignments added
+        assert_eq!(
+            coordinator.assign_relay(),
+            Some("127.0.0.1:7100".parse().unwrap())
+        );
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                coordinator
+                    .relay("relay-1")
+                    .is_some_and(|info| info.active_users == 0)
+            })
+            .await
+        );
+
+        // Dropping the relay connection removes it from the registry
+        control_task.abort();
+        let _ = control_task.await;
+        let _ = coordinator_task.await;
+        assert_eq!(coordinator.relay_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_relay_id_rejected() {
+        let coordinator = Arc::new(Coordinator::new(SECRET));
+
+        let (first_coordinator_stream, first_relay_stream) = connected_tcp_pair().await.unwrap();
+        let first_task = {
+            let coordinator = coordinator.clone();
+            tokio::spawn(async move {
+                coordinator
+                    .handle_relay(Connection::new(first_coordinator_stream))
+                    .await
+            })
+        };
+        let mut first_conn = Connection::new(first_relay_stream);
+        RelayNode::new("relay-1")
+            .register(
+                &mut first_conn,
+                "127.0.0.1:7100".parse().unwrap(),
+                10,
+                SECRET,
+            )
+            .await
+            .unwrap();
+
+        let (second_coordinator_stream, second_relay_stream) = connected_tcp_pair().await.unwrap();
+        let second_task = {
+            let coordinator = coordinator.clone();
+            tokio::spawn(async move {
+                coordinator
+                    .handle_relay(Connection::new(second_coordinator_stream))
+                    .await
+            })
+        };
+        let mut second_conn = Connection::new(second_relay_stream);
+        let result = RelayNode::new("relay-1")
+            .register(
+                &mut second_conn,
+                "127.0.0.1:7101".parse().unwrap(),
+                10,
+                SECRET,
+            )
+            .await;
+
+        assert!(result.is_err());
+        assert!(second_task.await.unwrap().is_err());
+        assert_eq!(coordinator.relay_count(), 1);
+
+        drop(first_conn);
+        let _ = first_task.await;
+    }
+
+    #[tokio::test]
+    async fn test_relay_with_wrong_secret_rejected() {
+        let coordinator = Arc::new(Coordinator::new(SECRET));
+        let (coordinator_stream, relay_stream) = connected_tcp_pair().await.unwrap();
+        let task = {
+            let coordinator = coordinator.clone();
+            tokio::spawn(async move {
+                coordinator
+                    .handle_relay(Connection::new(coordinator_stream))
+                    .await
+            })
+        };
+
+        let mut conn = Connection::new(relay_stream);
+        let result = RelayNode::new("intruder")
+            .register(&mut conn, "127.0.0.1:7100".parse().unwrap(), 10, "guess")
+            .await;
+
+        assert!(result.is_err());
+        assert!(matches!(
+            task.await.unwrap(),
+            Err(FleetNetError::AuthError(_))
+        ));
+        assert_eq!(coordinator.relay_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_relay_forwards_udp_datagrams() {
+        let relay_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let relay = RelayNode::new("relay-1");
+        relay.apply(&sender_update(user(1)));
+        relay.apply(&sender_update(user(2)));
+        relay.apply(&ClusterMessage::RouteUpdate {
+            channel_id: channel(2),
+            subscribers: vec![
+                RelaySubscriber {
+                    user_id: user(1),
+                    address: sender.local_addr().unwrap(),
+                },
+                RelaySubscriber {
+                    user_id: user(2),
+                    address: receiver.local_addr().unwrap(),
+                },
+            ],
+        });
+
+        let bytes = signed_packet(channel(2), user(1), &[1, 2, 3, 4]).to_bytes();
+        let forwarded = relay
+            .forward_packet(&relay_socket, &bytes, sender.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(forwarded, 1);
+        assert_eq!(relay.packets_forwarded(), 1);
+        assert_eq!(relay.active_users(), 1);
+
+        // The receiver gets the header signed with their own key
+        let (bytes, from) = recv_packet_from(&receiver, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(from, relay_socket.local_addr().unwrap());
+        let packet = AudioPacket::from_bytes(&bytes).unwrap();
+        assert!(packet.validate_hmac(&key(user(2))));
+        assert_eq!(packet.header.user_id, user(1));
+        assert_eq!(packet.opus_payload, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_relay_checks_voice_like_the_coordinator() {
+        let relay_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let source = sender_socket.local_addr().unwrap();
+
+        let relay = RelayNode::new("relay-1");
+        relay.apply(&sender_update(user(1)));
+        relay.apply(&sender_update(user(2)));
+        relay.apply(&ClusterMessage::RouteUpdate {
+            channel_id: channel(2),
+            subscribers: vec![
+                RelaySubscriber {
+                    user_id: user(1),
+                    address: source,
+                },
+                RelaySubscriber {
+                    user_id: user(2),
+                    address: receiver.local_addr().unwrap(),
+                },
+            ],
+        });
+        let packet = signed_packet(channel(2), user(1), b"opus").to_bytes();
+
+        // Packets signed with another key, or from unknown users, are refused
+        let forged = AudioPacket::new_signed(
+            test_header(channel(2), user(1), 0),
+            b"opus".to_vec(),
+            &key(user(2)),
+        );
+        assert!(relay
+            .forward_packet(&relay_socket, &forged.to_bytes(), source)
+            .await
+            .is_err());
+        let stranger = signed_packet(channel(2), user(9), b"opus");
+        assert!(relay
+            .forward_packet(&relay_socket, &stranger.to_bytes(), source)
+            .await
+            .is_err());
+
+        // Server muted users are dropped
+        let mut muted = relay_sender(user(1));
+        muted.may_transmit = false;
+        relay.apply(&ClusterMessage::SenderUpdate {
+            user_id: user(1),
+            sender: muted,
+        });
+        assert_eq!(
+            relay
+                .forward_packet(&relay_socket, &packet, source)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Audio policies of the channels are enforced
+        relay.apply(&sender_update(user(1)));
+        let mut ptt_only = test_channel(2);
+        ptt_only.audio_policy.force_ptt = true;
+        relay.apply(&ClusterMessage::ChannelsUpdated {
+            channels: vec![ptt_only],
+        });
+        let mut vad = relay_sender(user(1));
+        vad.transmit_mode = TransmitMode::VoiceActivity;
+        relay.apply(&ClusterMessage::SenderUpdate {
+            user_id: user(1),
+            sender: vad,
+        });
+        assert!(matches!(
+            relay.forward_packet(&relay_socket, &packet, source).await,
+            Err(FleetNetError::PermissionError(_))
+        ));
+        assert!(no_packet_within(&receiver, Duration::from_millis(50)).await);
+
+        // Disconnected users are forgotten
+        relay.apply(&sender_update(user(1)));
+        relay.apply(&ClusterMessage::SenderRemoved { user_id: user(1) });
+        assert!(relay
+            .forward_packet(&relay_socket, &packet, source)
+            .await
+            .is_err());
+        assert_eq!(relay.sender_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_relay_passes_tunneled_voice_to_the_coordinator() {
+        let relay_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let source = sender_socket.local_addr().unwrap();
+
+        let relay = RelayNode::new("relay-1");
+        let mut reports = relay.reports.subscribe();
+        relay.apply(&sender_update(user(1)));
+        let mut tunneled = relay_sender(user(2));
+        tunneled.tunneled = true;
+        relay.apply(&ClusterMessage::SenderUpdate {
+            user_id: user(2),
+            sender: tunneled,
+        });
+        relay.apply(&ClusterMessage::RouteUpdate {
+            channel_id: channel(2),
+            subscribers: vec![
+                RelaySubscriber {
+                    user_id: user(1),
+                    address: "127.0.0.1:5001".parse().unwrap(),
+                },
+                RelaySubscriber {
+                    user_id: user(2),
+                    address: "127.0.0.1:5002".parse().unwrap(),
+                },
+            ],
+        });
+
+        let packet = signed_packet(channel(2), user(1), b"opus").to_bytes();
+        relay
+            .forward_packet(&relay_socket, &packet, source)
+            .await
+            .unwrap();
+
+        // The sender's address is reported, then the voice for the tunnel
+        assert_eq!(
+            reports.recv().await.unwrap(),
+            ClusterMessage::VoiceAddress {
+                user_id: user(1),
+                address: source,
+            }
+        );
+        let ClusterMessage::TunneledVoice { user_id, packet } = reports.recv().await.unwrap()
+        else {
+            panic!("Expected tunneled voice");
+        };
+        assert_eq!(user_id, user(2));
+        let packet = AudioPacket::from_bytes(&packet).unwrap();
+        assert!(packet.validate_hmac(&key(user(2))));
+        assert_eq!(packet.opus_payload, b"opus");
+    }
+
+    #[tokio::test]
+    async fn test_relay_drops_truncated_datagrams() {
+        let relay_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay_socket.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.connect(relay_addr).await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let relay = Arc::new(RelayNode::new("relay-1"));
+        relay.apply(&sender_update(user(1)));
+        relay.apply(&sender_update(user(2)));
+        relay.apply(&ClusterMessage::RouteUpdate {
+            channel_id: channel(2),
+            subscribers: vec![
+                RelaySubscriber {
+                    user_id: user(1),
+                    address: sender.local_addr().unwrap(),
+                },
+                RelaySubscriber {
+                    user_id: user(2),
+                    address: receiver.local_addr().unwrap(),
+                },
+            ],
+        });
+        let voice = tokio::spawn(relay.clone().run_voice(relay_socket));
+
+        let packet = signed_packet(channel(2), user(1), &[1, 2, 3, 4]).to_bytes();
+        send_truncated_header(&sender, &packet, 10).await.unwrap();
+        assert!(no_packet_within(&receiver, Duration::from_millis(50)).await);
+
+        // The relay keeps serving after a bad datagram
+        send_packet(&sender, &packet).await.unwrap();
+        let forwarded = recv_packet(&receiver, Duration::from_secs(1))
+            .await
+            .unwrap();
+        let forwarded = AudioPacket::from_bytes(&forwarded).unwrap();
+        assert_eq!(forwarded.opus_payload, vec![1, 2, 3, 4]);
+        assert_eq!(relay.packets_forwarded(), 1);
+        voice.abort();
+    }
+}
@@ -0,0 +1,1051 @@
+//! Bridge to a Discord voice channel.
+//!
+//! Mixed communities can keep members who won't install the client in the
+//! net: [`DiscordBridge`] joins a Discord voice channel as a bot and relays
+//! audio both ways with one Fleet Net channel. Every Discord member who
+//! talks becomes a Fleet Net speaker of their own, with a user id from
+//! [`DiscordConfig::user_ids`], while the channel's native voice is sent to
+//! Discord as the bot, one speaker at a time as an RTP export in
+//! [`RtpExportMode::ActiveSpeaker`] mode would.
+//!
+//! Both sides carry 48 kHz Opus, so audio is never decoded: RTP timestamps
+//! become Fleet Net timestamps and back, and the Opus frames pass through
+//! unchanged. Voice to and from Discord is encrypted with
+//! `aead_aes256_gcm_rtpsize`. The bridge does not take part in Discord's
+//! end-to-end encryption (DAVE), so channels that require it refuse the
+//! bot when it connects.
+//!
+//! Setting up the call takes two websockets: the main gateway, where the
+//! bot logs in and asks to join the channel, and the voice gateway of the
+//! voice server Discord assigns, which hands out the bot's SSRC and key and
+//! reports which SSRC belongs to which member.
+
+use crate::cluster::AbortOnDrop;
+use crate::rtp::{opus_packet_duration_us, Packetizer, RtpExportMode, TICKS_PER_MS};
+use crate::subscriptions::SubscriptionRegistry;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::cluster::RelaySubscriber;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::key_manager::KeyManager;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+/// Discord's main gateway, API version 10.
+const GATEWAY_URL: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
+
+/// Voice gateway version, the first to acknowledge sequence numbers in
+/// heartbeats.
+const VOICE_GATEWAY_VERSION: u8 = 8;
+
+/// `GUILDS | GUILD_VOICE_STATES`, enough to join voice channels.
+const INTENTS: u64 = 1 << 0 | 1 << 7;
+
+/// Transport encryption the bridge implements, one every voice server offers.
+const ENCRYPTION_MODE: &str = "aead_aes256_gcm_rtpsize";
+
+/// RTP payload type Discord uses for Opus.
+const DISCORD_PAYLOAD_TYPE: u8 = 120;
+
+/// Time allowed from logging in until the voice transport is ready.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Size of an IP discovery request and response.
+const IP_DISCOVERY_LEN: usize = 74;
+
+/// Bytes of the GCM tag and of the nonce counter trailing each packet.
+const TAG_LEN: usize = 16;
+const NONCE_LEN: usize = 4;
+
+/// Main gateway opcodes.
+mod op {
+    pub const DISPATCH: u8 = 0;
+    pub const HEARTBEAT: u8 = 1;
+    pub const IDENTIFY: u8 = 2;
+    pub const VOICE_STATE_UPDATE: u8 = 4;
+    pub const HELLO: u8 = 10;
+}
+
+/// Voice gateway opcodes.
+mod voice_op {
+    pub const IDENTIFY: u8 = 0;
+    pub const SELECT_PROTOCOL: u8 = 1;
+    pub const READY: u8 = 2;
+    pub const HEARTBEAT: u8 = 3;
+    pub const SESSION_DESCRIPTION: u8 = 4;
+    pub const SPEAKING: u8 = 5;
+    pub const HELLO: u8 = 8;
+    pub const CLIENT_DISCONNECT: u8 = 13;
+}
+
+/// Settings of a [`DiscordBridge`].
+#[derive(Debug, Clone)]
+pub struct DiscordConfig {
+    /// Token of the bot account, which needs the Connect and Speak
+    /// permissions in the Discord channel.
+    pub bot_token: String,
+    /// Discord server (guild) of the voice channel.
+    pub guild_id: u64,
+    /// Discord voice channel to bridge.
+    pub discord_channel_id: u64,
+    /// Fleet Net channel to bridge.
+    pub channel_id: ChannelId,
+    /// Fleet Net voice socket that Discord speakers transmit to.
+    pub voice_address: SocketAddr,
+    /// Address the bridge's voice sockets bind to; the router must reach it.
+    pub voice_bind_ip: IpAddr,
+    /// Secret the Discord speakers' packet signing keys are derived from.
+    pub secret: Vec<u8>,
+    /// User id the bridge hears the Fleet Net channel as.
+    pub listener_id: UserId,
+    /// Fleet Net user ids handed out to Discord members while they talk.
+    /// They must not clash with ids of native users or `listener_id`.
+    pub user_ids: RangeInclusive<u16>,
+    pub gateway_url: String,
+}
+
+impl DiscordConfig {
+    /// A bridge on the same host as the voice router at `voice_address`,
+    /// using the user ids just below the Mumble gateway's.
+    pub fn new(
+        bot_token: impl Into<String>,
+        guild_id: u64,
+        discord_channel_id: u64,
+        channel_id: ChannelId,
+        voice_address: SocketAddr,
+        secret: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self {
+            bot_token: bot_token.into(),
+            guild_id,
+            discord_channel_id,
+            channel_id,
+            voice_address,
+            voice_bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            secret: secret.into(),
+            listener_id: UserId::new(0xEFFF).expect("Non-zero id"),
+            user_ids: 0xE000..=0xEFFE,
+            gateway_url: GATEWAY_URL.to_string(),
+        }
+    }
+}
+
+/// A message on either gateway.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct GatewayPayload {
+    op: u8,
+    #[serde(default)]
+    d: Value,
+    /// Sequence number of main gateway dispatches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    s: Option<u64>,
+    /// Event name of main gateway dispatches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    t: Option<String>,
+    /// Sequence number of voice gateway messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+}
+
+impl GatewayPayload {
+    fn new(op: u8, d: Value) -> Self {
+        Self {
+            op,
+            d,
+            ..Default::default()
+        }
+    }
+
+    /// A Discord id, which the gateways send as a string.
+    fn snowflake(&self, field: &str) -> Option<u64> {
+        self.d.get(field)?.as_str()?.parse().ok()
+    }
+}
+
+fn main_heartbeat(seq: Option<u64>) -> GatewayPayload {
+    GatewayPayload::new(op::HEARTBEAT, json!(seq))
+}
+
+fn voice_heartbeat(seq: Option<u64>) -> GatewayPayload {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64);
+    GatewayPayload::new(
+        voice_op::HEARTBEAT,
+        json!({ "t": nonce, "seq_ack": seq.map_or(-1, |seq| seq as i64) }),
+    )
+}
+
+fn discord_error(message: impl Into<Cow<'static, str>>) -> FleetNetError {
+    FleetNetError::NetworkError(message.into())
+}
+
+/// An open gateway websocket, heartbeated by a task of its own.
+struct Gateway {
+    outgoing: mpsc::UnboundedSender<GatewayPayload>,
+    incoming: mpsc::UnboundedReceiver<GatewayPayload>,
+    _task: AbortOnDrop,
+}
+
+impl Gateway {
+    /// Connects to `url` and starts heartbeating at the interval given by
+    /// its hello message.
+    async fn open(
+        url: &str,
+        hello: u8,
+        heartbeat: fn(Option<u64>) -> GatewayPayload,
+    ) -> Result<Self, FleetNetError> {
+        let (mut ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(|e| discord_error(format!("Failed to connect to {url}: {e}")))?;
+        let interval = loop {
+            let Some(message) = ws.next().await else {
+                return Err(discord_error("Discord gateway closed before hello"));
+            };
+            let message = message.map_err(|e| discord_error(e.to_string()))?;
+            let Ok(payload) = parse_message(&message) else {
+                continue;
+            };
+            if payload.op == hello {
+                let millis = payload.d["heartbeat_interval"].as_f64().unwrap_or(0.0);
+                break Duration::from_millis(millis as u64).max(Duration::from_secs(1));
+            }
+        };
+
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<GatewayPayload>();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            let mut seq = None;
+            let ended = loop {
+                let payload = tokio::select! {
+                    _ = ticks.tick() => heartbeat(seq),
+                    Some(payload) = outgoing_rx.recv() => payload,
+                    message = ws.next() => match message {
+                        Some(Ok(Message::Close(frame))) => {
+                            break frame.map_or_else(String::new, |frame| {
+                                format!("{} {}", frame.code, frame.reason)
+                            });
+                        }
+                        Some(Ok(message)) => {
+                            if let Ok(payload) = parse_message(&message) {
+                                seq = payload.s.or(payload.seq).or(seq);
+                                // Nobody listening after setup is fine
+                                let _ = incoming_tx.send(payload);
+                            }
+                            continue;
+                        }
+                        Some(Err(e)) => break e.to_string(),
+                        None => break String::new(),
+                    },
+                };
+                let text = serde_json::to_string(&payload).expect("Payloads serialize");
+                if let Err(e) = ws.send(Message::text(text)).await {
+                    break e.to_string();
+                }
+            };
+            debug!("Discord gateway closed: {ended}");
+        });
+
+        Ok(Self {
+            outgoing,
+            incoming,
+            _task: AbortOnDrop(task),
+        })
+    }
+
+    fn send(&self, op: u8, d: Value) {
+        let _ = self.outgoing.send(GatewayPayload::new(op, d));
+    }
+
+    /// Waits for the first message `pick` accepts.
+    async fn expect<T>(
+        &mut self,
+        mut pick: impl FnMut(&GatewayPayload) -> Option<T>,
+    ) -> Result<T, FleetNetError> {
+        while let Some(payload) = self.incoming.recv().await {
+            if let Some(found) = pick(&payload) {
+                return Ok(found);
+            }
+        }
+        Err(discord_error("Discord gateway closed during setup"))
+    }
+}
+
+/// Text frames as payloads; DAVE's binary frames and anything else are
+/// not understood.
+fn parse_message(message: &Message) -> Result<GatewayPayload, FleetNetError> {
+    match message {
+        Message::Text(text) => serde_json::from_str(text.as_str())
+            .map_err(|e| discord_error(format!("Malformed Discord payload: {e}"))),
+        _ => Err(discord_error("Not a text frame")),
+    }
+}
+
+/// Where and as whom the bot connects for voice.
+#[derive(Debug)]
+struct VoiceServer {
+    endpoint: String,
+    token: String,
+    session_id: String,
+    user_id: u64,
+}
+
+/// Encrypts and decrypts voice with `aead_aes256_gcm_rtpsize`: the RTP
+/// header is authenticated but left readable, and a 32-bit nonce counter
+/// trails each packet.
+struct VoiceCipher {
+    cipher: Aes256Gcm,
+    nonce: AtomicU32,
+}
+
+/// Voice received from a Discord member.
+#[derive(Debug, PartialEq, Eq)]
+struct DiscordVoice {
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    opus: Vec<u8>,
+}
+
+impl VoiceCipher {
+    fn new(secret_key: &[u8]) -> Option<Self> {
+        Some(Self {
+            cipher: Aes256Gcm::new_from_slice(secret_key).ok()?,
+            nonce: AtomicU32::new(0),
+        })
+    }
+
+    fn nonce(counter: [u8; NONCE_LEN]) -> Nonce<aes_gcm::aead::consts::U12> {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_LEN].copy_from_slice(&counter);
+        nonce.into()
+    }
+
+    /// Encrypts a plain RTP packet with a 12-byte header.
+    fn seal(&self, rtp: &[u8]) -> Option<Vec<u8>> {
+        let (header, payload) = rtp.split_at_checked(12)?;
+        let counter = self.nonce.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+        let sealed = self
+            .cipher
+            .encrypt(
+                &Self::nonce(counter),
+                Payload {
+                    msg: payload,
+                    aad: header,
+                },
+            )
+            .ok()?;
+        let mut packet = Vec::with_capacity(rtp.len() + TAG_LEN + NONCE_LEN);
+        packet.extend_from_slice(header);
+        packet.extend_from_slice(&sealed);
+        packet.extend_from_slice(&counter);
+        Some(packet)
+    }
+
+    /// Decrypts an Opus packet, dropping the header extension Discord
+    /// clients send. Returns `None` for RTCP, other payloads and packets
+    /// that fail to authenticate.
+    fn open(&self, packet: &[u8]) -> Option<DiscordVoice> {
+        let (&first, _) = packet.split_first()?;
+        if first >> 6 != 2 || packet.get(1)? & 0x7F != DISCORD_PAYLOAD_TYPE {
+            return None;
+        }
+        let csrcs = usize::from(first & 0x0F) * 4;
+        let extension = first & 0x10 != 0;
+        // Only the extension's 4-byte header is sent in the clear
+        let header_len = 12 + csrcs + if extension { 4 } else { 0 };
+        let body_end = packet.len().checked_sub(NONCE_LEN)?;
+        if body_end < header_len + TAG_LEN {
+            return None;
+        }
+        let counter: [u8; NONCE_LEN] = packet[body_end..].try_into().ok()?;
+        let mut plain = self
+            .cipher
+            .decrypt(
+                &Self::nonce(counter),
+                Payload {
+                    msg: &packet[header_len..body_end],
+                    aad: &packet[..header_len],
+                },
+            )
+            .ok()?;
+        if extension {
+            let words = u16::from_be_bytes([packet[header_len - 2], packet[header_len - 1]]);
+            let skip = usize::from(words) * 4;
+            if skip > plain.len() {
+                return None;
+            }
+            plain.drain(..skip);
+        }
+        Some(DiscordVoice {
+            ssrc: u32::from_be_bytes(packet[8..12].try_into().ok()?),
+            sequence: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes(packet[4..8].try_into().ok()?),
+            opus: plain,
+        })
+    }
+}
+
+/// Asks the voice server which address it sees `ssrc` sending from.
+fn ip_discovery_request(ssrc: u32) -> [u8; IP_DISCOVERY_LEN] {
+    let mut request = [0u8; IP_DISCOVERY_LEN];
+    request[..2].copy_from_slice(&1u16.to_be_bytes());
+    request[2..4].copy_from_slice(&70u16.to_be_bytes());
+    request[4..8].copy_from_slice(&ssrc.to_be_bytes());
+    request
+}
+
+/// The external address in an IP discovery response.
+fn parse_ip_discovery(response: &[u8]) -> Option<SocketAddr> {
+    if response.len() != IP_DISCOVERY_LEN || response[..2] != 2u16.to_be_bytes() {
+        return None;
+    }
+    let address = &response[8..72];
+    let end = address.iter().position(|&b| b == 0)?;
+    let ip = std::str::from_utf8(&address[..end]).ok()?.parse().ok()?;
+    let port = u16::from_be_bytes([response[72], response[73]]);
+    Some(SocketAddr::new(ip, port))
+}
+
+/// The bot's voice transport, ready to send and receive.
+struct VoiceLink {
+    /// Connected to the voice server.
+    socket: UdpSocket,
+    ssrc: u32,
+    cipher: VoiceCipher,
+}
+
+/// A Fleet Net user id registered with the router, unregistered on drop.
+struct Subscribed {
+    subscriptions: Arc<SubscriptionRegistry>,
+    user_id: UserId,
+}
+
+impl Subscribed {
+    fn listen(
+        subscriptions: &Arc<SubscriptionRegistry>,
+        channel_id: ChannelId,
+        subscriber: RelaySubscriber,
+    ) -> Self {
+        subscriptions.add_listener(channel_id, subscriber);
+        Self {
+            subscriptions: subscriptions.clone(),
+            user_id: subscriber.user_id,
+        }
+    }
+}
+
+impl Drop for Subscribed {
+    fn drop(&mut self) {
+        self.subscriptions.remove_user(self.user_id);
+    }
+}
+
+/// A Discord member talking into Fleet Net.
+struct Speaker {
+    user_id: UserId,
+    /// Never read; the router sends the channel's voice here too.
+    socket: UdpSocket,
+    key: HmacKey,
+    _subscribed: Subscribed,
+}
+
+/// Who is talking on the Discord side.
+#[derive(Default)]
+struct Members {
+    /// Discord user of each SSRC heard about.
+    ssrcs: HashMap<u32, u64>,
+    speakers: HashMap<u64, Speaker>,
+}
+
+/// Relays voice between a Discord voice channel and a Fleet Net channel.
+pub struct DiscordBridge {
+    config: DiscordConfig,
+    subscriptions: Arc<SubscriptionRegistry>,
+    speakers_added: AtomicU64,
+}
+
+impl DiscordBridge {
+    pub fn new(config: DiscordConfig, subscriptions: Arc<SubscriptionRegistry>) -> Self {
+        Self {
+            config,
+            subscriptions,
+            speakers_added: AtomicU64::new(0),
+        }
+    }
+
+    /// Joins the Discord channel and relays voice until either gateway
+    /// disconnects. Call again to reconnect.
+    pub async fn run(self: Arc<Self>) -> Result<(), FleetNetError> {
+        let (_main, server) = tokio::time::timeout(SETUP_TIMEOUT, self.join_voice_channel())
+            .await
+            .map_err(|_| discord_error("Timed out joining the Discord voice channel"))??;
+        let (voice, link) = tokio::time::timeout(SETUP_TIMEOUT, self.connect_voice(&server))
+            .await
+            .map_err(|_| discord_error("Timed out connecting to Discord voice"))??;
+        info!(
+            "Bridging Discord channel {} with channel {}",
+            self.config.discord_channel_id, self.config.channel_id
+        );
+        self.relay(link, voice.incoming).await
+    }
+
+    /// Logs in on the main gateway and asks to join the voice channel.
+    ///
+    /// The returned gateway must stay open for the bot to stay in the call.
+    async fn join_voice_channel(&self) -> Result<(Gateway, VoiceServer), FleetNetError> {
+        let mut gateway =
+            Gateway::open(&self.config.gateway_url, op::HELLO, main_heartbeat).await?;
+        gateway.send(
+            op::IDENTIFY,
+            json!({
+                "token": self.config.bot_token,
+                "intents": INTENTS,
+                "properties": { "os": std::env::consts::OS, "browser": "fleet-net", "device": "fleet-net" },
+            }),
+        );
+        let user_id = gateway
+            .expect(|payload| match payload.t.as_deref() {
+                Some("READY") => payload.d["user"]["id"].as_str()?.parse().ok(),
+                _ => None,
+            })
+            .await?;
+        gateway.send(
+            op::VOICE_STATE_UPDATE,
+            json!({
+                "guild_id": self.config.guild_id.to_string(),
+                "channel_id": self.config.discord_channel_id.to_string(),
+                "self_mute": false,
+                "self_deaf": false,
+            }),
+        );
+
+        let (mut session_id, mut server) = (None, None);
+        while session_id.is_none() || server.is_none() {
+            let payload = gateway.expect(|payload| Some(payload.clone())).await?;
+            if payload.op != op::DISPATCH {
+                continue;
+            }
+            match payload.t.as_deref() {
+                Some("VOICE_STATE_UPDATE") if payload.snowflake("user_id") == Some(user_id) => {
+                    session_id = payload.d["session_id"].as_str().map(str::to_string);
+                }
+                Some("VOICE_SERVER_UPDATE") => {
+                    let endpoint = payload.d["endpoint"].as_str().map(str::to_string);
+                    let token = payload.d["token"].as_str().map(str::to_string);
+                    server = endpoint.zip(token);
+                }
+                _ => {}
+            }
+        }
+        let (endpoint, token) = server.expect("Loop ends once set");
+        // Dispatches are no longer needed; the gateway drops them from now on
+        gateway.incoming = mpsc::unbounded_channel().1;
+        Ok((
+            gateway,
+            VoiceServer {
+                endpoint,
+                token,
+                session_id: session_id.expect("Loop ends once set"),
+                user_id,
+            },
+        ))
+    }
+
+    /// Sets up the encrypted voice transport with the assigned voice server.
+    async fn connect_voice(
+        &self,
+        server: &VoiceServer,
+    ) -> Result<(Gateway, VoiceLink), FleetNetError> {
+        let endpoint = server.endpoint.trim_start_matches("wss://");
+        let url = format!("wss://{endpoint}/?v={VOICE_GATEWAY_VERSION}");
+        let mut gateway = Gateway::open(&url, voice_op::HELLO, voice_heartbeat).await?;
+        gateway.send(
+            voice_op::IDENTIFY,
+            json!({
+                "server_id": self.config.guild_id.to_string(),
+                "user_id": server.user_id.to_string(),
+                "session_id": server.session_id,
+                "token": server.token,
+                "max_dave_protocol_version": 0,
+            }),
+        );
+        let ready = gateway
+            .expect(|payload| (payload.op == voice_op::READY).then(|| payload.d.clone()))
+            .await?;
+        let (Some(ssrc), Some(ip), Some(port)) = (
+            ready["ssrc"]
+                .as_u64()
+                .and_then(|ssrc| u32::try_from(ssrc).ok()),
+            ready["ip"].as_str(),
+            ready["port"]
+                .as_u64()
+                .and_then(|port| u16::try_from(port).ok()),
+        ) else {
+            return Err(discord_error("Malformed Discord voice ready"));
+        };
+        let offered = ready["modes"]
+            .as_array()
+            .is_some_and(|modes| modes.iter().any(|mode| mode == ENCRYPTION_MODE));
+        if !offered {
+            return Err(discord_error(format!(
+                "Discord voice server does not offer {ENCRYPTION_MODE}"
+            )));
+        }
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.connect((ip, port)).await?;
+        socket.send(&ip_discovery_request(ssrc)).await?;
+        let mut response = [0u8; IP_DISCOVERY_LEN];
+        let len = socket.recv(&mut response).await?;
+        let external = parse_ip_discovery(&response[..len])
+            .ok_or_else(|| discord_error("Malformed Discord IP discovery response"))?;
+
+        gateway.send(
+            voice_op::SELECT_PROTOCOL,
+            json!({
+                "protocol": "udp",
+                "data": {
+                    "address": external.ip().to_string(),
+                    "port": external.port(),
+                    "mode": ENCRYPTION_MODE,
+                },
+            }),
+        );
+        let secret_key: Vec<u8> = gateway
+            .expect(|payload| {
+                (payload.op == voice_op::SESSION_DESCRIPTION)
+                    .then(|| serde_json::from_value(payload.d["secret_key"].clone()).ok())
+                    .flatten()
+            })
+            .await?;
+        let cipher = VoiceCipher::new(&secret_key)
+            .ok_or_else(|| discord_error("Discord sent an unusable voice key"))?;
+
+        // Discord drops voice from a client that has not said it speaks
+        gateway.send(
+            voice_op::SPEAKING,
+            json!({ "speaking": 1, "delay": 0, "ssrc": ssrc }),
+        );
+        Ok((
+            gateway,
+            VoiceLink {
+                socket,
+                ssrc,
+                cipher,
+            },
+        ))
+    }
+
+    /// Relays voice over an established link until the voice gateway's
+    /// `events` end or the link fails.
+    async fn relay(
+        self: Arc<Self>,
+        link: VoiceLink,
+        mut events: mpsc::UnboundedReceiver<GatewayPayload>,
+    ) -> Result<(), FleetNetError> {
+        let listener = dual_stack::bind_udp((self.config.voice_bind_ip, 0)).await?;
+        let _listening = Subscribed::listen(
+            &self.subscriptions,
+            self.config.channel_id,
+            RelaySubscriber {
+                user_id: self.config.listener_id,
+                address: listener.local_addr()?,
+            },
+        );
+        let link = Arc::new(link);
+        let _outbound = AbortOnDrop(tokio::spawn(
+            self.clone().relay_to_discord(listener, link.clone()),
+        ));
+
+        let mut members = Members::default();
+        let mut buf = vec![0u8; 65_535];
+        loop {
+            tokio::select! {
+                // Speaking events first, so the voice they announce is attributed
+                biased;
+                event = events.recv() => match event {
+                    Some(event) => members.update(&event),
+                    None => return Ok(()),
+                },
+                received = link.socket.recv(&mut buf) => {
+                    let len = received?;
+                    self.relay_from_discord(&mut members, &link, &buf[..len]).await?;
+                }
+            }
+        }
+    }
+
+    /// Passes voice from a Discord member on to the Fleet Net channel.
+    async fn relay_from_discord(
+        &self,
+        members: &mut Members,
+        link: &VoiceLink,
+        data: &[u8],
+    ) -> Result<(), FleetNetError> {
+        let Some(voice) = link.cipher.open(data) else {
+            return Ok(());
+        };
+        // Voice arriving before its speaking event cannot be attributed
+        let Some(&member) = members.ssrcs.get(&voice.ssrc) else {
+            return Ok(());
+        };
+        let Some(frame_duration) = opus_packet_duration_us(&voice.opus)
+            .filter(|us| us % 1_000 == 0)
+            .and_then(|us| u8::try_from(us / 1_000).ok())
+            .filter(|&ms| ms > 0)
+        else {
+            return Ok(());
+        };
+
+        if !members.speakers.contains_key(&member) {
+            let Some(speaker) = self.add_speaker(members).await? else {
+                warn!("No Fleet Net user id left for Discord member {member}");
+                return Ok(());
+            };
+            info!(
+                "Discord member {member} speaks as Fleet Net user {}",
+                speaker.user_id
+            );
+            members.speakers.insert(member, speaker);
+        }
+        let speaker = &members.speakers[&member];
+
+        let header = PacketHeader {
+            channel_id: self.config.channel_id,
+            user_id: speaker.user_id,
+            sequence: voice.sequence,
+            timestamp: voice.timestamp / TICKS_PER_MS,
+            signal_strength: u8::MAX,
+            frame_duration,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let packet = AudioPacket::new_signed(header, voice.opus, &speaker.key);
+        let router =
+            dual_stack::reachable_from(speaker.socket.local_addr()?, self.config.voice_address);
+        speaker.socket.send_to(&packet.to_bytes(), router).await?;
+        Ok(())
+    }
+
+    /// Registers a Fleet Net user for a Discord member, or `None` if all
+    /// ids are taken.
+    async fn add_speaker(&self, members: &Members) -> Result<Option<Speaker>, FleetNetError> {
+        let Some(user_id) = self
+            .config
+            .user_ids
+            .clone()
+            .filter_map(UserId::new)
+            .find(|id| members.speakers.values().all(|s| s.user_id != *id))
+        else {
+            return Ok(None);
+        };
+        let socket = dual_stack::bind_udp((self.config.voice_bind_ip, 0)).await?;
+        let nonce = self
+            .speakers_added
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes();
+        let key = KeyManager::generate_session_key(user_id, &self.config.secret, &nonce);
+        let subscribed = Subscribed::listen(
+            &self.subscriptions,
+            self.config.channel_id,
+            RelaySubscriber {
+                user_id,
+                address: socket.local_addr()?,
+            },
+        );
+        Ok(Some(Speaker {
+            user_id,
+            socket,
+            key,
+            _subscribed: subscribed,
+        }))
+    }
+
+    /// Sends the Fleet Net channel's voice to Discord as the bot, one
+    /// speaker at a time.
+    async fn relay_to_discord(self: Arc<Self>, listener: UdpSocket, link: Arc<VoiceLink>) {
+        let mut packetizer = Packetizer::new(
+            RtpExportMode::ActiveSpeaker,
+            DISCORD_PAYLOAD_TYPE,
+            link.ssrc,
+            Instant::now(),
+        );
+        let mut buf = vec![0u8; 65_535];
+        while let Ok((len, _)) = listener.recv_from(&mut buf).await {
+            let Ok(packet) = AudioPacket::from_bytes(&buf[..len]) else {
+                continue;
+            };
+            // Discord members already hear each other
+            if self.config.user_ids.contains(&packet.header.user_id.get()) {
+                continue;
+            }
+            let Ok(frames) = packet.into_frames() else {
+                continue;
+            };
+            for frame in &frames {
+                let Some(rtp) = packetizer.packetize(frame, Instant::now()) else {
+                    continue;
+                };
+                if let Some(sealed) = link.cipher.seal(&rtp) {
+                    if let Err(e) = link.socket.send(&sealed).await {
+                        warn!("Failed to send voice to Discord: {e}");
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Members {
+    /// Tracks SSRCs and departures reported by the voice gateway.
+    fn update(&mut self, event: &GatewayPayload) {
+        match event.op {
+            voice_op::SPEAKING => {
+                let ssrc = event.d["ssrc"].as_u64().and_then(|s| u32::try_from(s).ok());
+                if let (Some(ssrc), Some(member)) = (ssrc, event.snowflake("user_id")) {
+                    self.ssrcs.insert(ssrc, member);
+                }
+            }
+            voice_op::CLIENT_DISCONNECT => {
+                if let Some(member) = event.snowflake("user_id") {
+                    self.ssrcs.retain(|_, known| *known != member);
+                    self.speakers.remove(&member);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtp::RtpHeader;
+    use fleet_net_protocol::test_helpers::synthetic_opus_payload;
+    use fleet_test_support::{recv_packet, wait_until};
+
+    const KEY: [u8; 32] = [9; 32];
+
+    fn discord_packet(ssrc: u32, sequence: u16, timestamp: u32, opus: &[u8]) -> Vec<u8> {
+        RtpHeader {
+            marker: false,
+            payload_type: DISCORD_PAYLOAD_TYPE,
+            sequence,
+            timestamp,
+            ssrc,
+        }
+        .packet(opus)
+    }
+
+    #[test]
+    fn test_ip_discovery_reads_the_external_address() {
+        let request = ip_discovery_request(0x0102_0304);
+        assert_eq!(request[..8], [0, 1, 0, 70, 1, 2, 3, 4]);
+
+        let mut response = request;
+        response[..2].copy_from_slice(&2u16.to_be_bytes());
+        response[8..8 + 11].copy_from_slice(b"203.0.113.9");
+        response[72..].copy_from_slice(&50_004u16.to_be_bytes());
+        assert_eq!(
+            parse_ip_discovery(&response),
+            Some("203.0.113.9:50004".parse().unwrap())
+        );
+        assert_eq!(parse_ip_discovery(&request), None);
+    }
+
+    #[test]
+    fn test_voice_encryption_round_trips_and_skips_header_extensions() {
+        let cipher = VoiceCipher::new(&KEY).unwrap();
+        let opus = synthetic_opus_payload(3, 20);
+
+        let sealed = cipher.seal(&discord_packet(42, 7, 960, &opus)).unwrap();
+        assert_eq!(sealed.len(), 12 + opus.len() + TAG_LEN + NONCE_LEN);
+        assert_eq!(sealed[sealed.len() - NONCE_LEN..], [0, 0, 0, 0]);
+        let voice = cipher.open(&sealed).unwrap();
+        assert_eq!((voice.ssrc, voice.sequence, voice.timestamp), (42, 7, 960));
+        assert_eq!(voice.opus, opus);
+
+        // Discord clients add a one-word extension, encrypted but for its header
+        let mut plain = discord_packet(42, 8, 1_920, &[]);
+        plain[0] |= 0x10;
+        plain.extend_from_slice(&[0xBE, 0xDE, 0, 1]);
+        let mut body = vec![0x10, 0xFF, 0, 0];
+        body.extend_from_slice(&opus);
+        let counter = 5u32.to_be_bytes();
+        let mut packet = plain.clone();
+        packet.extend(
+            cipher
+                .cipher
+                .encrypt(
+                    &VoiceCipher::nonce(counter),
+                    Payload {
+                        msg: &body,
+                        aad: &plain,
+                    },
+                )
+                .unwrap(),
+        );
+        packet.extend_from_slice(&counter);
+        assert_eq!(cipher.open(&packet).unwrap().opus, opus);
+
+        let mut tampered = sealed.clone();
+        tampered[3] ^= 1;
+        assert_eq!(cipher.open(&tampered), None);
+    }
+
+    #[test]
+    fn test_speaking_events_attribute_ssrcs_until_members_leave() {
+        let mut members = Members::default();
+        members.update(&GatewayPayload::new(
+            voice_op::SPEAKING,
+            json!({ "user_id": "80351110224678912", "ssrc": 42, "speaking": 1 }),
+        ));
+        assert_eq!(members.ssrcs.get(&42), Some(&80_351_110_224_678_912));
+
+        members.update(&GatewayPayload::new(
+            voice_op::CLIENT_DISCONNECT,
+            json!({ "user_id": "80351110224678912" }),
+        ));
+        assert!(members.ssrcs.is_empty());
+
+        let payload: GatewayPayload =
+            serde_json::from_str(r#"{"op":0,"d":{},"s":3,"t":"READY"}"#).unwrap();
+        assert_eq!(payload.s, Some(3));
+        assert_eq!(
+            serde_json::to_value(main_heartbeat(Some(3))).unwrap(),
+            json!({ "op": 1, "d": 3 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_voice_flows_between_discord_and_fleet_net() {
+        let subscriptions = Arc::new(SubscriptionRegistry::new());
+        let router = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        tokio::spawn({
+            let router = router.clone();
+            let subscriptions = subscriptions.clone();
+            async move {
+                let mut buf = vec![0u8; 1_500];
+                while let Ok((len, source)) = router.recv_from(&mut buf).await {
+                    let _ = subscriptions
+                        .forward_packet(&router, &buf[..len], source)
+                        .await;
+                }
+            }
+        });
+        let channel_id = ChannelId::new(2).unwrap();
+        let config = DiscordConfig::new(
+            "token",
+            1,
+            2,
+            channel_id,
+            router.local_addr().unwrap(),
+            b"secret".to_vec(),
+        );
+        let bridge = Arc::new(DiscordBridge::new(config, subscriptions.clone()));
+
+        // The test plays Discord's voice server
+        let discord = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(discord.local_addr().unwrap()).await.unwrap();
+        discord.connect(socket.local_addr().unwrap()).await.unwrap();
+        let link = VoiceLink {
+            socket,
+            ssrc: 1_000,
+            cipher: VoiceCipher::new(&KEY).unwrap(),
+        };
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let relay = tokio::spawn(bridge.clone().relay(link, events_rx));
+        let listening = wait_until(Duration::from_secs(5), Duration::from_millis(10), || {
+            !subscriptions.listeners(channel_id).is_empty()
+        })
+        .await;
+        assert!(listening);
+
+        let native = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let native_id = UserId::new(7).unwrap();
+        subscriptions.add_listener(
+            channel_id,
+            RelaySubscriber {
+                user_id: native_id,
+                address: native.local_addr().unwrap(),
+            },
+        );
+
+        // A Discord member talks once their SSRC is known
+        events
+            .send(GatewayPayload::new(
+                voice_op::SPEAKING,
+                json!({ "user_id": "555", "ssrc": 42, "speaking": 1 }),
+            ))
+            .unwrap();
+        let member_cipher = VoiceCipher::new(&KEY).unwrap();
+        let opus = synthetic_opus_payload(1, 20);
+        let spoken = member_cipher
+            .seal(&discord_packet(42, 9, 48_000, &opus))
+            .unwrap();
+        discord.send(&spoken).await.unwrap();
+
+        let received = recv_packet(&native, Duration::from_secs(5)).await.unwrap();
+        let packet = AudioPacket::from_bytes(&received).unwrap();
+        assert_eq!(packet.header.user_id.get(), 0xE000);
+        assert_eq!(packet.header.sequence, 9);
+        assert_eq!(packet.header.timestamp, 1_000);
+        assert_eq!(packet.header.frame_duration, 20);
+        assert_eq!(packet.opus_payload, opus);
+
+        // The native user talks back and Discord hears it from the bot
+        let reply = AudioPacket {
+            header: PacketHeader {
+                user_id: native_id,
+                ..packet.header
+            },
+            opus_payload: opus.clone(),
+            position: None,
+        };
+        native
+            .send_to(&reply.to_bytes(), router.local_addr().unwrap())
+            .await
+            .unwrap();
+        let heard = recv_packet(&discord, Duration::from_secs(5)).await.unwrap();
+        let voice = member_cipher.open(&heard).unwrap();
+        assert_eq!(voice.ssrc, 1_000);
+        assert_eq!(voice.opus, opus);
+
+        // Leaving unregisters the member's Fleet Net user
+        events
+            .send(GatewayPayload::new(
+                voice_op::CLIENT_DISCONNECT,
+                json!({ "user_id": "555" }),
+            ))
+            .unwrap();
+        drop(events);
+        relay.await.unwrap().unwrap();
+        assert!(subscriptions
+            .listeners(channel_id)
+            .iter()
+            .all(|s| s.user_id == native_id));
+    }
+}
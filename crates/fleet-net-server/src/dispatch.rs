@@ -0,0 +1,1258 @@
+//! Control connections and voice of native clients.
+//!
+//! The [`Dispatcher`] greets each connection with `ServerInfo` and waits
+//! [`AUTH_TIMEOUT`] for its `Authenticate`, whose access token is checked
+//! with the [`TokenVerifier`]. The session then becomes active and every
+//! request is handed to the registry owning it, with the answer sent back
+//! and failures reported as a [`ControlMessage::Error`] without dropping the
+//! connection. What the registries publish, e.g. presence, nickname, group
+//! and restriction changes, is relayed to every client.
+//!
+//! A client presenting a resume token is put back into the channels it was
+//! in and told so with a [`ControlMessage::SessionResumed`]. Every change
+//! of a session's channels is recorded in the [`SessionJournal`] under a
+//! fresh token, so that works after a server restart too.
+//!
+//! Voice datagrams of all clients arrive on one UDP socket and go out
+//! through the [`SubscriptionRegistry`]. Each session gets its own key in
+//! the `AuthResponse`, and packets whose HMAC doesn't check out under the
+//! sender's key are dropped, tunneled ones included. A client's voice
+//! address is learned from its packets; until then voice for it goes to the
+//! address of the control connection. A bare header without audio only
+//! registers the address, for clients that listen before they speak.
+//! Packets of server muted users are dropped.
+//!
+//! On a coordinator each client is assigned a relay to send its voice to,
+//! if one has room, and the dispatcher supplies what relays need to check
+//! it, taking on the voice addresses they learn in turn.
+
+use crate::auth::TokenVerifier;
+use crate::channels::ChannelRegistry;
+use crate::cluster::{AbortOnDrop, Coordinator};
+use crate::groups::GroupRegistry;
+use crate::journal::{JournalRecord, SessionJournal};
+use crate::nicknames::NicknameRegistry;
+use crate::presence::PresenceRegistry;
+use crate::reception::ReceptionRegistry;
+use crate::reports::{ReportQueue, SpeakerEvent};
+use crate::restrictions::RestrictionRegistry;
+use crate::roles::RoleRegistry;
+use crate::server::Server;
+use crate::sessions::{self, SessionLifecycle};
+use crate::store::{ChannelStore, SessionBackend, SessionStore};
+use crate::subscriptions::{SubscriptionRegistry, TransmissionEvent};
+use crate::templates::TemplateManager;
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use fleet_net_common::audio::UserAudioState;
+use fleet_net_common::channel::{ChannelTree, ChannelType};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::logging::ViolationLog;
+use fleet_net_common::permission::{PermissionSet, Permissions};
+use fleet_net_common::restriction::RestrictionKind;
+use fleet_net_common::session::{Session, SessionState};
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::user::User;
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::cluster::{ClusterMessage, RelaySender, RelaySubscriber};
+use fleet_net_protocol::connection::Connection;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::AudioPacket;
+use fleet_net_protocol::resume::ResumeToken;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, warn};
+
+/// How long a client has to authenticate after connecting.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connected client, from the moment its access token checked out.
+struct Client {
+    state: SessionState,
+    current_channel: Option<ChannelId>,
+    /// The `UserJoined` announcing the client, for newly connected ones;
+    /// `None` until the session is active, and broadcasts reach it.
+    joined: Option<ControlMessage>,
+    voice_address: SocketAddr,
+    audio: UserAudioState,
+    /// Key the client signs its voice packets with.
+    udp_key: HmacKey,
+    /// Messages for the client's connection.
+    outbound: mpsc::UnboundedSender<ControlMessage>,
+}
+
+/// What one connection keeps between requests.
+struct ClientConnection {
+    session: Session,
+    resume_token: Option<ResumeToken>,
+    /// Relays tunneled voice once the client tunnels its own.
+    tunnel: Option<AbortOnDrop>,
+    outbound: mpsc::UnboundedSender<ControlMessage>,
+}
+
+/// Serves control connections and the voice socket of a [`Server`].
+pub struct Dispatcher {
+    limits: ServerLimits,
+    tokens: Option<TokenVerifier>,
+    voice: Arc<UdpSocket>,
+    journal: Option<Arc<SessionJournal>>,
+    session_store: Arc<SessionBackend>,
+    channels: Arc<ChannelRegistry>,
+    roles: Arc<RoleRegistry>,
+    subscriptions: Arc<SubscriptionRegistry>,
+    reception: Arc<ReceptionRegistry>,
+    presence: Arc<PresenceRegistry>,
+    nicknames: Arc<NicknameRegistry>,
+    groups: Arc<GroupRegistry>,
+    restrictions: Arc<RestrictionRegistry>,
+    reports: Arc<ReportQueue>,
+    templates: Arc<TemplateManager>,
+    sessions: Arc<SessionLifecycle>,
+    coordinator: Option<Arc<Coordinator>>,
+    clients: DashMap<UserId, Client>,
+    violations: ViolationLog<SocketAddr>,
+}
+
+impl Dispatcher {
+    /// A dispatcher for the registries of `server`, receiving voice on
+    /// `voice`. Without `tokens` every client is refused.
+    pub fn new(server: &Server, voice: Arc<UdpSocket>, tokens: Option<TokenVerifier>) -> Self {
+        Self {
+            limits: server.status().limits,
+            tokens,
+            voice,
+            journal: server.journal().cloned(),
+            session_store: server.session_store().clone(),
+            channels: server.channels().clone(),
+            roles: server.roles().clone(),
+            subscriptions: server.subscriptions().clone(),
+            reception: server.reception().clone(),
+            presence: server.presence().clone(),
+            nicknames: server.nicknames().clone(),
+            groups: server.groups().clone(),
+            restrictions: server.restrictions().clone(),
+            reports: server.reports().clone(),
+            templates: server.templates().clone(),
+            sessions: server.sessions().clone(),
+            coordinator: server.coordinator().cloned(),
+            clients: DashMap::new(),
+            violations: ViolationLog::default(),
+        }
+    }
+
+    /// Connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Starts relaying registry changes and transmission events to clients,
+    /// for the life of the server.
+    pub async fn spawn_relays(self: &Arc<Self>) -> Result<(), FleetNetError> {
+        self.refresh_channels().await?;
+        for changes in [
+            self.channels.subscribe(),
+            self.presence.subscribe(),
+            self.nicknames.subscribe(),
+            self.groups.subscribe(),
+            self.restrictions.subscribe(),
+            self.subscriptions.subscribe_speaking(),
+        ] {
+            let dispatcher = self.clone();
+            tokio::spawn(async move { dispatcher.relay_changes(changes).await });
+        }
+        let dispatcher = self.clone();
+        let transmissions = self.subscriptions.subscribe_transmissions();
+        tokio::spawn(async move { dispatcher.relay_transmissions(transmissions).await });
+        if let Some(coordinator) = &self.coordinator {
+            let dispatcher = self.clone();
+            let reports = coordinator.subscribe_reports();
+            tokio::spawn(async move { dispatcher.relay_reports(reports).await });
+        }
+        Ok(())
+    }
+
+    /// Takes on what relays report about their users: where their voice
+    /// comes from, and the voice those tunneling it hear.
+    async fn relay_reports(&self, mut reports: broadcast::Receiver<ClusterMessage>) {
+        loop {
+            match reports.recv().await {
+                Ok(ClusterMessage::VoiceAddress { user_id, address }) => {
+                    self.learn_voice_address(user_id, address);
+                }
+                Ok(ClusterMessage::TunneledVoice { user_id, packet }) => {
+                    self.subscriptions.deliver_tunneled(user_id, packet);
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Missed {skipped} relay reports");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// What relays need to check the voice of every active client.
+    pub fn relay_senders(&self) -> HashMap<UserId, RelaySender> {
+        self.clients
+            .iter()
+            .filter_map(|client| {
+                let may_transmit =
+                    client.state.can_transmit() && !client.audio.effective().server_muted;
+                let user_id = *client.key();
+                self.subscriptions
+                    .relay_sender(user_id, may_transmit)
+                    .map(|sender| (user_id, sender))
+            })
+            .collect()
+    }
+
+    async fn relay_changes(&self, mut changes: broadcast::Receiver<ControlMessage>) {
+        loop {
+            match changes.recv().await {
+                Ok(change) => {
+                    self.apply_restriction(&change);
+                    self.broadcast(change);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Clients missed {skipped} changes");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Tells senders they are stepped on, and keeps finished transmissions
+    /// for abuse reports.
+    async fn relay_transmissions(&self, mut events: broadcast::Receiver<TransmissionEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Some((user_id, notice)) = event.stepped_on_notice() {
+                        self.send_to(user_id, notice);
+                    }
+                    if let TransmissionEvent::Stopped {
+                        user_id,
+                        channel_id,
+                        duration,
+                    } = event
+                    {
+                        let ended_at_ms = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64;
+                        let duration_ms = duration.as_millis() as u64;
+                        self.reports.history().record(SpeakerEvent {
+                            user_id,
+                            channel_id,
+                            started_at_ms: ended_at_ms.saturating_sub(duration_ms),
+                            duration_ms: duration_ms.try_into().unwrap_or(u32::MAX),
+                        });
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Missed {skipped} transmission events");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Mirrors server mutes and deafens into the audio state of connected
+    /// users, announcing the effective change.
+    fn apply_restriction(&self, change: &ControlMessage) {
+        let (user_id, kind, active) = match change {
+            ControlMessage::UserRestricted {
+                user_id,
+                restriction,
+            } => (*user_id, restriction.kind, true),
+            ControlMessage::RestrictionLifted { user_id, kind } => (*user_id, *kind, false),
+            _ => return,
+        };
+        let change = self
+            .clients
+            .get_mut(&user_id)
+            .and_then(|mut client| client.audio.apply_restriction(kind, active));
+        if let Some(change) = change {
+            self.broadcast(change.into());
+        }
+    }
+
+    fn broadcast(&self, message: ControlMessage) {
+        for client in self.clients.iter().filter(|client| client.joined.is_some()) {
+            // A closed channel means the connection is ending; it cleans up.
+            let _ = client.outbound.send(message.clone());
+        }
+    }
+
+    fn send_to(&self, user_id: UserId, message: ControlMessage) {
+        if let Some(client) = self.clients.get(&user_id) {
+            let _ = client.outbound.send(message);
+        }
+    }
+
+    /// Reloads the channel tree into voice routing, e.g. after a template
+    /// import.
+    async fn refresh_channels(&self) -> Result<(), FleetNetError> {
+        let tree = ChannelTree::from_channels(self.channels.store().list().await?)?;
+        self.subscriptions.update_channels(&tree);
+        Ok(())
+    }
+
+    /// Runs one control connection from `peer` until it closes, greeting
+    /// the client with `server_info`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client does not authenticate in time, is
+    /// refused, or the connection fails.
+    pub async fn handle<S>(
+        self: Arc<Self>,
+        mut conn: Connection<S>,
+        peer: SocketAddr,
+        server_info: ControlMessage,
+    ) -> Result<(), FleetNetError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        conn.write_message(&server_info).await?;
+        let message = tokio::time::timeout(AUTH_TIMEOUT, conn.read_validated_message(&self.limits))
+            .await
+            .map_err(|_| {
+                FleetNetError::NetworkError(Cow::Borrowed("Client did not authenticate"))
+            })?;
+        let (outbound, mut outbound_rx) = mpsc::unbounded_channel();
+        let (mut session, resumed) = match message {
+            Ok(message) => match self.authenticate(&message, peer, &outbound).await {
+                Ok(authenticated) => authenticated,
+                Err(e) => {
+                    conn.write_message(&refusal(&e)).await?;
+                    return Err(e);
+                }
+            },
+            Err(e) => {
+                conn.write_message(&refusal(&e)).await?;
+                return Err(e);
+            }
+        };
+        let user_id = session.user.id;
+
+        let (mut reader, mut writer) = conn.into_split();
+        let _writer_task = AbortOnDrop(tokio::spawn(async move {
+            while let Some(message) = outbound_rx.recv().await {
+                if let Err(e) = writer.write_message(&message).await {
+                    debug!("Stopped writing to client: {e}");
+                    break;
+                }
+            }
+        }));
+        let mut client = ClientConnection {
+            session,
+            resume_token: None,
+            tunnel: None,
+            outbound: outbound.clone(),
+        };
+        if let Err(e) = self.activate(&mut client, peer, resumed.is_some()).await {
+            self.clients.remove(&user_id);
+            return Err(e);
+        }
+        info!("User {user_id} connected from {peer}");
+
+        let result = loop {
+            let message = match reader.read_validated_message(&self.limits).await {
+                Ok(message) => message,
+                // The frame was read, so the connection is still usable
+                Err(e @ (FleetNetError::ValidationError(_) | FleetNetError::JsonError(_))) => {
+                    let _ = outbound.send(ControlMessage::from(&e));
+                    continue;
+                }
+                Err(e) => break e,
+            };
+            if let Err(e) = self.dispatch(&mut client, message).await {
+                let _ = outbound.send(ControlMessage::from(&e));
+            }
+            self.update_client(&client.session);
+        };
+
+        self.clients.remove(&user_id);
+        if let Err(e) = self.session_store.remove(&client.session.id).await {
+            warn!(
+                "Failed to remove session {} from the store: {e}",
+                client.session.id
+            );
+        }
+        session = client.session;
+        let _ = self
+            .sessions
+            .transition(&mut session, SessionState::Disconnecting);
+        self.broadcast(ControlMessage::UserLeft { user_id });
+        info!("User {user_id} disconnected: {result}");
+        Ok(())
+    }
+
+    /// Checks the `Authenticate` a connection starts with and builds its
+    /// session, with the journaled state it resumes, if any. The user is
+    /// registered as a client sending to `outbound` right away, so a second
+    /// connection of theirs is refused.
+    async fn authenticate(
+        &self,
+        message: &ControlMessage,
+        peer: SocketAddr,
+        outbound: &mpsc::UnboundedSender<ControlMessage>,
+    ) -> Result<(Session, Option<JournalRecord>), FleetNetError> {
+        let ControlMessage::Authenticate {
+            token,
+            client_version,
+            resume_token,
+        } = message
+        else {
+            return Err(FleetNetError::AuthError(Cow::Borrowed(
+                "Authenticate before sending other messages",
+            )));
+        };
+        let user_id = self
+            .tokens
+            .as_ref()
+            .ok_or(FleetNetError::AuthError(Cow::Borrowed(
+                "Authentication is not configured",
+            )))?
+            .verify(token)?;
+        if self
+            .restrictions
+            .active(user_id, RestrictionKind::Ban, Utc::now())
+            .is_some()
+        {
+            return Err(FleetNetError::AuthError(Cow::Borrowed(
+                "Banned from this server",
+            )));
+        }
+        let user = User::new(user_id);
+        let permission = self.server_permissions(&user);
+        if !permission.has(Permissions::CONNECT) {
+            return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                "Missing permission to connect",
+            )));
+        }
+
+        let udp_key = HmacKey::generate()?;
+        match self.clients.entry(user_id) {
+            Entry::Occupied(_) => {
+                return Err(FleetNetError::AuthError(Cow::Borrowed(
+                    "Already connected from elsewhere",
+                )))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Client {
+                    state: SessionState::Authenticating,
+                    current_channel: None,
+                    joined: None,
+                    voice_address: dual_stack::canonical(peer),
+                    audio: UserAudioState::new(user_id),
+                    udp_key,
+                    outbound: outbound.clone(),
+                });
+            }
+        }
+        let session = self
+            .open_session(user, permission, token, client_version, resume_token, peer)
+            .await
+            .inspect_err(|_| {
+                self.clients.remove(&user_id);
+            })?;
+        Ok(session)
+    }
+
+    /// The session of `user`, resuming the one of `resume_token` if given.
+    async fn open_session(
+        &self,
+        user: User,
+        permission: PermissionSet,
+        token: &str,
+        client_version: &str,
+        resume_token: &Option<ResumeToken>,
+        peer: SocketAddr,
+    ) -> Result<(Session, Option<JournalRecord>), FleetNetError> {
+        let user_id = user.id;
+        let resumed = match (resume_token, &self.journal) {
+            (None, _) => None,
+            (Some(resume_token), Some(journal)) => journal.resume(resume_token, user_id).await?,
+            (Some(_), None) => None,
+        };
+        if resume_token.is_some() && resumed.is_none() {
+            return Err(FleetNetError::AuthError(Cow::Borrowed(
+                "Session could not be resumed",
+            )));
+        }
+
+        let now = Instant::now();
+        let id = match &resumed {
+            Some(record) => record.session_id.clone(),
+            None => format!(
+                "{user_id}-{:x}",
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos()
+            ),
+        };
+        let mut session = Session {
+            id,
+            user,
+            socket_addr: peer,
+            connected_at: now,
+            last_active: now,
+            state: SessionState::Authenticating,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission,
+            auth_token: token.to_string(),
+            client_version: client_version.to_string(),
+        };
+        self.nicknames.restore(&mut session).await?;
+        if let Some(record) = &resumed {
+            for &channel_id in &record.subscribed_channels {
+                if self.channels.store().load(channel_id).await?.is_some() {
+                    session.subscribed_channels.insert(channel_id);
+                }
+            }
+            session.current_channel = record.current_channel;
+        }
+        Ok((session, resumed))
+    }
+
+    /// Makes an authenticated session active, answers its `Authenticate`
+    /// and brings the client up to date.
+    async fn activate(
+        &self,
+        client: &mut ClientConnection,
+        peer: SocketAddr,
+        resumed: bool,
+    ) -> Result<(), FleetNetError> {
+        let user_id = client.session.user.id;
+        self.sessions
+            .transition(&mut client.session, SessionState::Active)?;
+        self.subscriptions
+            .set_transmit_priority(user_id, self.roles.transmit_priority(&client.session.user));
+
+        let mut audio = UserAudioState::new(user_id);
+        for kind in [RestrictionKind::Mute, RestrictionKind::Deafen] {
+            let active = self
+                .restrictions
+                .active(user_id, kind, Utc::now())
+                .is_some();
+            audio.apply_restriction(kind, active);
+        }
+        let udp_key = self
+            .clients
+            .get_mut(&user_id)
+            .map(|mut reserved| {
+                reserved.state = client.session.state;
+                reserved.audio = audio;
+                reserved.udp_key.clone()
+            })
+            .ok_or(FleetNetError::SessionStateError(Cow::Borrowed(
+                "Session ended while authenticating",
+            )))?;
+        self.subscriptions.set_session_key(user_id, udp_key.clone());
+        let voice_address = self.voice_address(user_id, dual_stack::canonical(peer));
+        for &channel_id in &client.session.subscribed_channels {
+            self.subscriptions.add_listener(
+                channel_id,
+                RelaySubscriber {
+                    user_id,
+                    address: voice_address,
+                },
+            );
+        }
+        // The journaled channel may since have become off limits
+        let current_channel = client.session.current_channel.take();
+        if let Some(channel_id) = current_channel {
+            if let Err(e) = self
+                .change_channel(&mut client.session, voice_address, Some(channel_id))
+                .await
+            {
+                debug!("User {user_id} could not rejoin channel {channel_id}: {e}");
+            }
+        }
+
+        if let Some(journal) = &self.journal {
+            client.resume_token = Some(ResumeToken::generate()?);
+            self.record(journal, client).await;
+        }
+        self.save_session(&client.session).await;
+        // Relays learn the session before its voice can reach them
+        let voice_relay = self.coordinator.as_ref().and_then(|coordinator| {
+            let sender = self
+                .subscriptions
+                .relay_sender(user_id, self.may_transmit(user_id))?;
+            coordinator.publish_sender(user_id, sender);
+            coordinator.assign_relay()
+        });
+        let _ = client.outbound.send(ControlMessage::AuthResponse {
+            success: true,
+            user_id: Some(user_id),
+            error: None,
+            resume_token: client.resume_token.clone(),
+            min_client_version: None,
+            udp_key: Some(udp_key),
+            voice_relay,
+        });
+        if resumed {
+            let _ = client.outbound.send(ControlMessage::SessionResumed {
+                current_channel: client.session.current_channel,
+                subscribed_channels: client.session.sorted_subscriptions(),
+            });
+        }
+
+        let mut snapshot: Vec<ControlMessage> = self
+            .clients
+            .iter()
+            .filter_map(|client| client.joined.clone())
+            .collect();
+        snapshot.extend(self.presence.snapshot());
+        snapshot.extend(self.groups.snapshot());
+        let joined = sessions::user_joined(&client.session);
+        if let Some(mut reserved) = self.clients.get_mut(&user_id) {
+            reserved.current_channel = client.session.current_channel;
+            reserved.joined = Some(joined.clone());
+        }
+        for message in snapshot {
+            let _ = client.outbound.send(message);
+        }
+        self.broadcast(joined);
+        Ok(())
+    }
+
+    /// Keeps the shared view of a connected session current.
+    fn update_client(&self, session: &Session) {
+        if let Some(mut client) = self.clients.get_mut(&session.user.id) {
+            client.state = session.state;
+            client.current_channel = session.current_channel;
+            if client.joined.is_some() {
+                client.joined = Some(sessions::user_joined(session));
+            }
+        }
+    }
+
+    /// What `user`'s roles let them do outside any channel.
+    fn server_permissions(&self, user: &User) -> PermissionSet {
+        self.roles
+            .user_roles(user)
+            .iter()
+            .fold(Permissions::empty(), |permissions, role| {
+                permissions | role.permissions
+            })
+            .into()
+    }
+
+    fn voice_address(&self, user_id: UserId, fallback: SocketAddr) -> SocketAddr {
+        self.clients
+            .get(&user_id)
+            .map_or(fallback, |client| client.voice_address)
+    }
+
+    /// Journals a session's channels so it can be resumed.
+    async fn record(&self, journal: &SessionJournal, client: &ClientConnection) {
+        let Some(resume_token) = &client.resume_token else {
+            return;
+        };
+        let record = JournalRecord::from_session(&client.session, resume_token.clone());
+        // The session goes on; it just cannot be resumed as it is now
+        if let Err(e) = journal.record(record).await {
+            warn!("Failed to journal session {}: {e}", client.session.id);
+        }
+    }
+
+    /// Saves a session's snapshot to the store, where other nodes and
+    /// tools can see it.
+    async fn save_session(&self, session: &Session) {
+        // The session goes on; the store just shows it as it was
+        if let Err(e) = self.session_store.save(session.snapshot()).await {
+            warn!("Failed to store session {}: {e}", session.id);
+        }
+    }
+
+    /// Handles one request of an active session.
+    async fn dispatch(
+        &self,
+        client: &mut ClientConnection,
+        message: ControlMessage,
+    ) -> Result<(), FleetNetError> {
+        let session = &mut client.session;
+        let user_id = session.user.id;
+        let voice_address = self.voice_address(user_id, session.socket_addr);
+        let reply = match &message {
+            ControlMessage::JoinChannel { channel_id } => {
+                self.change_channel(session, voice_address, Some(*channel_id))
+                    .await?;
+                Some(self.channel_joined(user_id, *channel_id))
+            }
+            ControlMessage::LeaveChannel { channel_id } => {
+                if session.current_channel != Some(*channel_id) {
+                    return Err(FleetNetError::invalid_field(
+                        "channel_id",
+                        Constraint::Invalid(Cow::Borrowed("not_in_channel")),
+                    ));
+                }
+                self.change_channel(session, voice_address, None).await?;
+                Some(ControlMessage::ChannelLeft {
+                    channel_id: *channel_id,
+                })
+            }
+            ControlMessage::SubscribeChannel { channel_id } => {
+                self.ensure_channel(*channel_id).await?;
+                Some(self.subscriptions.apply(session, voice_address, &message)?)
+            }
+            ControlMessage::UnsubscribeChannel { .. }
+            | ControlMessage::SetTransmitTargets { .. } => {
+                Some(self.subscriptions.apply(session, voice_address, &message)?)
+            }
+            ControlMessage::SetTransmitMode { mode } => {
+                session.ensure_interactive()?;
+                self.subscriptions.set_transmit_mode(user_id, *mode);
+                None
+            }
+            ControlMessage::UserStateChange {
+                self_muted,
+                self_deafened,
+            } => {
+                session.ensure_interactive()?;
+                let change = self.clients.get_mut(&user_id).and_then(|mut client| {
+                    client.audio.set_self_state(*self_muted, *self_deafened)
+                });
+                if let Some(change) = change {
+                    self.broadcast(change.into());
+                }
+                None
+            }
+            ControlMessage::ReceptionReport { .. } => {
+                if let Some((speaker, feedback)) = self.reception.apply(session, &message)? {
+                    self.send_to(speaker, feedback);
+                }
+                None
+            }
+            ControlMessage::RetransmitRequest { .. } => {
+                self.subscriptions
+                    .retransmit(&self.voice, session, &message)
+                    .await?;
+                None
+            }
+            ControlMessage::VoiceTunnel { .. } => {
+                if client.tunnel.is_none() {
+                    let mut tunneled = self.subscriptions.open_tunnel(user_id);
+                    let outbound = client.outbound.clone();
+                    client.tunnel = Some(AbortOnDrop(tokio::spawn(async move {
+                        while let Some(message) = tunneled.recv().await {
+                            if outbound.send(message).is_err() {
+                                break;
+                            }
+                        }
+                    })));
+                }
+                if !self.authentic(user_id, &message) {
+                    return Err(FleetNetError::PacketError(Cow::Borrowed(
+                        "Packet HMAC does not match",
+                    )));
+                }
+                if !self.may_transmit(user_id) {
+                    return Ok(());
+                }
+                self.subscriptions
+                    .forward_tunneled(&self.voice, session, voice_address, &message)
+                    .await?;
+                None
+            }
+            ControlMessage::SetPresence { .. } => {
+                self.presence.apply(session, &message)?;
+                None
+            }
+            ControlMessage::SetNickname { .. } => {
+                self.nicknames.apply(session, &message).await?;
+                None
+            }
+            ControlMessage::UpdateChannelInfo { .. } => {
+                self.channels.apply(session, &message).await?;
+                None
+            }
+            ControlMessage::CreateGroup { .. }
+            | ControlMessage::JoinGroup { .. }
+            | ControlMessage::LeaveGroup => {
+                self.groups.apply(session, &message)?;
+                None
+            }
+            ControlMessage::ReportUser { .. } => {
+                session.ensure_interactive()?;
+                let report_id = self
+                    .reports
+                    .submit(user_id, session.current_channel, &message)?;
+                Some(ControlMessage::ReportSubmitted { report_id })
+            }
+            ControlMessage::RestrictUser { .. } | ControlMessage::LiftRestriction { .. } => {
+                self.restrictions.apply(session, &message, Utc::now())?;
+                None
+            }
+            ControlMessage::ExportTemplate | ControlMessage::ImportTemplate { .. } => {
+                let reply = self.templates.apply(session, &message).await?;
+                if matches!(
+                    reply,
+                    ControlMessage::TemplateImported { dry_run: false, .. }
+                ) {
+                    self.refresh_channels().await?;
+                }
+                Some(reply)
+            }
+            ControlMessage::Ping => Some(ControlMessage::Pong),
+            _ => {
+                return Err(FleetNetError::invalid_field(
+                    "type",
+                    Constraint::Invalid(Cow::Borrowed("not_a_client_request")),
+                ))
+            }
+        };
+
+        if matches!(
+            message,
+            ControlMessage::JoinChannel { .. }
+                | ControlMessage::LeaveChannel { .. }
+                | ControlMessage::SubscribeChannel { .. }
+                | ControlMessage::UnsubscribeChannel { .. }
+        ) {
+            if let Some(journal) = &self.journal {
+                self.record(journal, client).await;
+            }
+            self.save_session(&client.session).await;
+        }
+        if let Some(reply) = reply {
+            let _ = client.outbound.send(reply);
+        }
+        Ok(())
+    }
+
+    async fn ensure_channel(&self, channel_id: ChannelId) -> Result<(), FleetNetError> {
+        match self.channels.store().load(channel_id).await? {
+            Some(_) => Ok(()),
+            None => Err(FleetNetError::invalid_field(
+                "channel_id",
+                Constraint::NotFound,
+            )),
+        }
+    }
+
+    /// Moves `session` into `to`, or out of its channel when `None`, and
+    /// announces the move.
+    async fn change_channel(
+        &self,
+        session: &mut Session,
+        voice_address: SocketAddr,
+        to: Option<ChannelId>,
+    ) -> Result<(), FleetNetError> {
+        session.ensure_interactive()?;
+        let user_id = session.user.id;
+        let permission = match to {
+            Some(channel_id) => {
+                let tree = ChannelTree::from_channels(self.channels.store().list().await?)?;
+                let channel = tree.get(channel_id).ok_or_else(|| {
+                    FleetNetError::invalid_field("channel_id", Constraint::NotFound)
+                })?;
+                if channel.channel_type == ChannelType::Category {
+                    return Err(FleetNetError::invalid_field(
+                        "channel_id",
+                        Constraint::Invalid(Cow::Borrowed("not_a_voice_channel")),
+                    ));
+                }
+                let permissions = self
+                    .roles
+                    .permissions_in(&session.user, &tree, channel_id)?;
+                if !PermissionSet::from(permissions).has(Permissions::CONNECT) {
+                    return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                        "Missing permission to join the channel",
+                    )));
+                }
+                permissions.into()
+            }
+            None => self.server_permissions(&session.user),
+        };
+
+        let from = session.current_channel;
+        if let Some(old) = from.filter(|old| !session.subscribed_channels.contains(old)) {
+            self.subscriptions.remove_listener(old, user_id);
+        }
+        if let Some(channel_id) = to {
+            self.subscriptions.add_listener(
+                channel_id,
+                RelaySubscriber {
+                    user_id,
+                    address: voice_address,
+                },
+            );
+        }
+        session.current_channel = to;
+        session.permission = permission;
+        session.update_activity();
+        self.update_client(session);
+        self.broadcast(ControlMessage::UserChangedChannel {
+            user_id,
+            from_channel: from,
+            to_channel: to,
+        });
+        Ok(())
+    }
+
+    /// The `ChannelJoined` telling `user_id` who else is in `channel_id`.
+    fn channel_joined(&self, user_id: UserId, channel_id: ChannelId) -> ControlMessage {
+        let mut users: Vec<UserId> = self
+            .clients
+            .iter()
+            .filter(|client| client.current_channel == Some(channel_id))
+            .map(|client| *client.key())
+            .collect();
+        if !users.contains(&user_id) {
+            users.push(user_id);
+        }
+        users.sort_unstable();
+        ControlMessage::ChannelJoined {
+            channel_id,
+            users,
+            spectators: self.subscriptions.spectators(channel_id),
+        }
+    }
+
+    /// Whether voice from `user_id` may go out, as they are active and not
+    /// server muted.
+    fn may_transmit(&self, user_id: UserId) -> bool {
+        self.clients.get(&user_id).is_some_and(|client| {
+            client.state.can_transmit() && !client.audio.effective().server_muted
+        })
+    }
+
+    /// Whether `packet` comes from the user in its header, as its HMAC
+    /// checks out under their session key.
+    fn signed_by_sender(&self, packet: &AudioPacket) -> bool {
+        self.clients
+            .get(&packet.header.user_id)
+            .is_some_and(|client| packet.validate_hmac(&client.udp_key))
+    }
+
+    /// Whether the packet of a [`ControlMessage::VoiceTunnel`] from
+    /// `user_id` is theirs and signed with their session key.
+    fn authentic(&self, user_id: UserId, message: &ControlMessage) -> bool {
+        let ControlMessage::VoiceTunnel { packet } = message else {
+            return false;
+        };
+        AudioPacket::from_bytes(packet)
+            .is_ok_and(|packet| packet.header.user_id == user_id && self.signed_by_sender(&packet))
+    }
+
+    /// Learns `user_id`'s voice address from a packet sent from `source`.
+    fn learn_voice_address(&self, user_id: UserId, source: SocketAddr) {
+        let source = dual_stack::canonical(source);
+        let Some(mut client) = self.clients.get_mut(&user_id) else {
+            return;
+        };
+        if client.voice_address != source {
+            client.voice_address = source;
+            drop(client);
+            self.subscriptions.set_voice_address(user_id, source);
+        }
+    }
+
+    /// Receives voice datagrams and forwards them until the socket fails.
+    pub async fn run_voice(self: Arc<Self>) -> Result<(), FleetNetError> {
+        let mut buf = vec![0u8; 65_535];
+        loop {
+            let (len, source) = self.voice.recv_from(&mut buf).await?;
+            let datagram = &buf[..len];
+            let packet = match AudioPacket::from_bytes(datagram) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    let e = FleetNetError::from(e);
+                    self.violations.record(&source, e.code().as_str(), &e);
+                    continue;
+                }
+            };
+            if !self.signed_by_sender(&packet) {
+                let e = FleetNetError::PacketError(Cow::Borrowed("Packet HMAC does not match"));
+                self.violations.record(&source, e.code().as_str(), &e);
+                continue;
+            }
+            let header = packet.header;
+            self.learn_voice_address(header.user_id, source);
+            if (header.audio_length == 0 && !header.dtx) || !self.may_transmit(header.user_id) {
+                continue;
+            }
+            match self
+                .subscriptions
+                .forward_packet(&self.voice, datagram, source)
+                .await
+            {
+                Ok(_) => {}
+                Err(e @ FleetNetError::NetworkError(_)) => {
+                    debug!("Failed to forward packet from {source}: {e}");
+                }
+                Err(e) => {
+                    self.violations.record(&source, e.code().as_str(), &e);
+                }
+            }
+        }
+    }
+}
+
+/// The `AuthResponse` refusing a client for `error`.
+fn refusal(error: &FleetNetError) -> ControlMessage {
+    ControlMessage::AuthResponse {
+        success: false,
+        user_id: None,
+        error: Some(Cow::Owned(error.to_string())),
+        resume_token: None,
+        min_client_version: None,
+        udp_key: None,
+        voice_relay: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_channel, TestClient, TestCluster, TEST_JWT_SECRET};
+    use fleet_net_common::user::Presence;
+    use fleet_net_protocol::packet::PacketHeader;
+
+    async fn authenticate(client: &mut TestClient, token: String) -> ControlMessage {
+        client
+            .send(&ControlMessage::Authenticate {
+                token,
+                client_version: Cow::Borrowed("1.0.0"),
+                resume_token: None,
+            })
+            .await;
+        client
+            .recv_until(|message| matches!(message, ControlMessage::AuthResponse { .. }))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_clients_authenticate_join_and_hear_each_other() {
+        let (cluster, mut clients) = TestCluster::start(3).await;
+        let server = cluster.server();
+        server
+            .channels()
+            .store()
+            .save(test_channel(1))
+            .await
+            .unwrap();
+        let tokens = TokenVerifier::new(TEST_JWT_SECRET.as_bytes());
+        let (alice, bob) = (UserId::new(1).unwrap(), UserId::new(2).unwrap());
+
+        // Tokens signed with another secret are refused
+        let forged = TokenVerifier::new(b"forged").issue(alice).unwrap();
+        let refused = authenticate(&mut clients[2], forged).await;
+        assert!(matches!(
+            refused,
+            ControlMessage::AuthResponse { success: false, .. }
+        ));
+
+        for (client, user_id) in clients.iter_mut().zip([alice, bob]) {
+            client.authenticate(user_id).await;
+        }
+        // The same user cannot sign in twice
+        let mut duplicate = cluster.connect().await;
+        let refused = authenticate(&mut duplicate, tokens.issue(alice).unwrap()).await;
+        assert!(matches!(
+            refused,
+            ControlMessage::AuthResponse { success: false, .. }
+        ));
+
+        // Requests reach their registries and changes reach everyone
+        let presence = Presence::Away;
+        clients[0]
+            .send(&ControlMessage::SetPresence {
+                presence: presence.clone(),
+            })
+            .await;
+        let changed = clients[1]
+            .recv_until(|message| matches!(message, ControlMessage::PresenceChanged { .. }))
+            .await;
+        assert!(matches!(
+            changed,
+            ControlMessage::PresenceChanged { user_id, presence: p, .. } if user_id == alice && p == presence
+        ));
+
+        // Failed requests are answered without dropping the connection
+        clients[0]
+            .send(&ControlMessage::JoinChannel {
+                channel_id: ChannelId::new(9).unwrap(),
+            })
+            .await;
+        clients[0]
+            .recv_until(|message| matches!(message, ControlMessage::Error { .. }))
+            .await;
+        clients[0].send(&ControlMessage::Ping).await;
+        clients[0]
+            .recv_until(|message| matches!(message, ControlMessage::Pong))
+            .await;
+
+        let channel_id = ChannelId::new(1).unwrap();
+        for client in &mut clients[..2] {
+            client.join_channel(channel_id).await;
+        }
+        // Packets not signed with the sender's session key are dropped
+        let forger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let forged = AudioPacket::new_signed(
+            PacketHeader {
+                channel_id,
+                user_id: alice,
+                sequence: 1,
+                timestamp: 0,
+                signal_strength: 255,
+                frame_duration: 20,
+                dtx: false,
+                padded: false,
+                frames: 1,
+                audio_length: 0,
+                hmac_prefix: 0,
+            },
+            vec![6, 6, 6],
+            &HmacKey::from_bytes(&[6; 32]),
+        );
+        forger
+            .send_to(&forged.to_bytes(), cluster.voice_addr())
+            .await
+            .unwrap();
+        clients[0].transmit(channel_id, &[1, 2, 3]).await;
+        let forwarded = clients[1].recv_voice().await;
+        assert_eq!(forwarded.header.user_id, alice);
+        assert_eq!(forwarded.opus_payload, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_kept_in_the_store_while_connected() {
+        let (cluster, mut clients) = TestCluster::start(1).await;
+        let server = cluster.server();
+        server
+            .channels()
+            .store()
+            .save(test_channel(1))
+            .await
+            .unwrap();
+        let (user_id, channel_id) = (UserId::new(3).unwrap(), ChannelId::new(1).unwrap());
+
+        clients[0].authenticate(user_id).await;
+        clients[0].join_channel(channel_id).await;
+        let snapshots = server.session_store().list().await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].user.id, user_id);
+        assert_eq!(snapshots[0].current_channel, Some(channel_id));
+
+        drop(clients.remove(0));
+        fleet_test_support::time::with_default_timeout(async {
+            while !server.session_store().list().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Session was not removed on disconnect");
+    }
+
+    #[tokio::test]
+    async fn test_sessions_resume_after_a_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = || crate::server::ServerConfig {
+            journal_path: Some(dir.path().join("sessions.journal")),
+            ..crate::testing::test_config()
+        };
+        let tokens = TokenVerifier::new(TEST_JWT_SECRET.as_bytes());
+        let user_id = UserId::new(5).unwrap();
+        let (lobby, tower) = (ChannelId::new(1).unwrap(), ChannelId::new(2).unwrap());
+
+        let resume_token = {
+            let (cluster, mut clients) = TestCluster::start_with(config(), 1).await;
+            for id in [1, 2] {
+                let store = cluster.server().channels().store();
+                store.save(test_channel(id)).await.unwrap();
+            }
+            let client = &mut clients[0];
+            let ControlMessage::AuthResponse {
+                resume_token: Some(resume_token),
+                ..
+            } = authenticate(client, tokens.issue(user_id).unwrap()).await
+            else {
+                panic!("Expected a resume token");
+            };
+            client
+                .send(&ControlMessage::JoinChannel { channel_id: lobby })
+                .await;
+            client
+                .send(&ControlMessage::SubscribeChannel { channel_id: tower })
+                .await;
+            client
+                .recv_until(|message| {
+                    matches!(message, ControlMessage::SubscriptionsChanged { .. })
+                })
+                .await;
+            resume_token
+        };
+
+        let (cluster, mut clients) = TestCluster::start_with(config(), 2).await;
+        for id in [1, 2] {
+            let store = cluster.server().channels().store();
+            store.save(test_channel(id)).await.unwrap();
+        }
+        let resume = ControlMessage::Authenticate {
+            token: tokens.issue(user_id).unwrap(),
+            client_version: Cow::Borrowed("1.0.0"),
+            resume_token: Some(resume_token),
+        };
+        clients[0].send(&resume).await;
+        assert!(matches!(
+            clients[0].recv().await,
+            ControlMessage::AuthResponse {
+                success: true,
+                resume_token: Some(_),
+                ..
+            }
+        ));
+        match clients[0].recv().await {
+            ControlMessage::SessionResumed {
+                current_channel,
+                subscribed_channels,
+            } => {
+                assert_eq!(current_channel, Some(lobby));
+                assert_eq!(subscribed_channels, vec![tower]);
+            }
+            other => panic!("Expected SessionResumed, got {other:?}"),
+        }
+        let subscriptions = cluster.server().subscriptions();
+        for channel_id in [lobby, tower] {
+            let listeners = subscriptions.listeners(channel_id);
+            assert!(listeners.iter().any(|listener| listener.user_id == user_id));
+        }
+
+        // Resume tokens are single use
+        drop(clients.remove(0));
+        let refused = loop {
+            clients[0].send(&resume).await;
+            let response = clients[0]
+                .recv_until(|message| matches!(message, ControlMessage::AuthResponse { .. }))
+                .await;
+            // Wait out the first connection's cleanup
+            if !matches!(&response, ControlMessage::AuthResponse { error: Some(e), .. } if e.contains("Already connected"))
+            {
+                break response;
+            }
+            clients[0] = cluster.connect().await;
+        };
+        assert!(matches!(
+            refused,
+            ControlMessage::AuthResponse { success: false, .. }
+        ));
+    }
+}
@@ -0,0 +1,586 @@
+//! Publishing server events to MQTT or NATS.
+//!
+//! Ops teams wire Fleet Net into their automation, such as a Node-RED flow
+//! lighting an on-air sign or a bot logging moderation, by consuming events
+//! from the broker they already run instead of polling the admin API. An
+//! [`EventPublisher`] follows the server's registries and publishes each
+//! [`ServerEvent`] as JSON on its own topic below a prefix, e.g.
+//! `fleet-net/transmission_started` on MQTT or
+//! `fleet-net.transmission_started` on NATS:
+//!
+//! ```json
+//! {"event":"transmission_started","user_id":7,"channel_id":2,"at_ms":1760000000000}
+//! ```
+//!
+//! Publishing is best effort: an event the broker does not take is logged
+//! and dropped, and voice is never held up by it. Brokers are behind the
+//! `mqtt` and `nats` features.
+
+use crate::server::Server;
+use crate::subscriptions::TransmissionEvent;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::restriction::{RestrictionKind, TimedRestriction};
+use fleet_net_common::session::{SessionState, SessionTransition};
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::ControlMessage;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Default MQTT broker port.
+const MQTT_PORT: u16 = 1883;
+
+/// Default NATS server port.
+const NATS_PORT: u16 = 4222;
+
+/// Topic prefix used unless configured otherwise.
+pub const DEFAULT_TOPIC_PREFIX: &str = "fleet-net";
+
+/// Where events are published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventsConfig {
+    /// Broker address, `mqtt://host[:port]` or `nats://host[:port]`.
+    pub url: String,
+    pub topic_prefix: String,
+}
+
+impl EventsConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            topic_prefix: DEFAULT_TOPIC_PREFIX.to_string(),
+        }
+    }
+}
+
+/// Something that happened on the server, as published.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    UserConnected {
+        user_id: UserId,
+        session_id: String,
+    },
+    UserDisconnected {
+        user_id: UserId,
+        session_id: String,
+    },
+    TransmissionStarted {
+        user_id: UserId,
+        channel_id: ChannelId,
+    },
+    TransmissionStopped {
+        user_id: UserId,
+        channel_id: ChannelId,
+        duration_ms: u64,
+    },
+    TransmissionSteppedOn {
+        user_id: UserId,
+        channel_id: ChannelId,
+        by: UserId,
+    },
+    UserRestricted {
+        user_id: UserId,
+        restriction: TimedRestriction,
+    },
+    RestrictionLifted {
+        user_id: UserId,
+        kind: RestrictionKind,
+    },
+}
+
+/// An event with the time it was published.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    #[serde(flatten)]
+    pub event: ServerEvent,
+    /// Unix time in milliseconds.
+    pub at_ms: u64,
+}
+
+impl ServerEvent {
+    /// Name of the event, and last segment of its topic.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UserConnected { .. } => "user_connected",
+            Self::UserDisconnected { .. } => "user_disconnected",
+            Self::TransmissionStarted { .. } => "transmission_started",
+            Self::TransmissionStopped { .. } => "transmission_stopped",
+            Self::TransmissionSteppedOn { .. } => "transmission_stepped_on",
+            Self::UserRestricted { .. } => "user_restricted",
+            Self::RestrictionLifted { .. } => "restriction_lifted",
+        }
+    }
+
+    /// The event for a session transition worth publishing.
+    pub fn from_transition(transition: &SessionTransition) -> Option<Self> {
+        let user_id = transition.user_id;
+        let session_id = transition.session_id.clone();
+        match (transition.from, transition.to) {
+            (SessionState::Authenticating, SessionState::Active) => Some(Self::UserConnected {
+                user_id,
+                session_id,
+            }),
+            (_, SessionState::Disconnecting) => Some(Self::UserDisconnected {
+                user_id,
+                session_id,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The event for a restriction change broadcast by the server.
+    pub fn from_restriction(change: &ControlMessage) -> Option<Self> {
+        match change {
+            ControlMessage::UserRestricted {
+                user_id,
+                restriction,
+            } => Some(Self::UserRestricted {
+                user_id: *user_id,
+                restriction: restriction.clone(),
+            }),
+            ControlMessage::RestrictionLifted { user_id, kind } => Some(Self::RestrictionLifted {
+                user_id: *user_id,
+                kind: *kind,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransmissionEvent> for ServerEvent {
+    fn from(event: TransmissionEvent) -> Self {
+        match event {
+            TransmissionEvent::Started {
+                user_id,
+                channel_id,
+            } => Self::TransmissionStarted {
+                user_id,
+                channel_id,
+            },
+            TransmissionEvent::Stopped {
+                user_id,
+                channel_id,
+                duration,
+            } => Self::TransmissionStopped {
+                user_id,
+                channel_id,
+                duration_ms: duration.as_millis() as u64,
+            },
+            TransmissionEvent::SteppedOn {
+                user_id,
+                channel_id,
+                by,
+            } => Self::TransmissionSteppedOn {
+                user_id,
+                channel_id,
+                by,
+            },
+        }
+    }
+}
+
+/// A broker connection events are published through.
+pub trait EventSink: Send + Sync + 'static {
+    /// Separates the prefix from the event name in topics.
+    const SEPARATOR: char;
+
+    fn publish(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+    ) -> impl Future<Output = Result<(), FleetNetError>> + Send;
+}
+
+/// Publishes the events of a server to an [`EventSink`].
+pub struct EventPublisher<S> {
+    sink: S,
+    topic_prefix: String,
+}
+
+impl<S: EventSink> EventPublisher<S> {
+    pub fn new(sink: S, topic_prefix: impl Into<String>) -> Self {
+        Self {
+            sink,
+            topic_prefix: topic_prefix.into(),
+        }
+    }
+
+    pub fn topic(&self, event: &ServerEvent) -> String {
+        format!("{}{}{}", self.topic_prefix, S::SEPARATOR, event.name())
+    }
+
+    /// Publishes `event`, logging rather than returning failures.
+    pub async fn publish(&self, event: ServerEvent) {
+        let topic = self.topic(&event);
+        let record = EventRecord {
+            event,
+            at_ms: unix_millis(),
+        };
+        let payload = match serde_json::to_vec(&record) {
+            Ok(payload) => payload,
+            Err(e) => return warn!("Failed to encode {topic} event: {e}"),
+        };
+        if let Err(e) = self.sink.publish(topic.clone(), payload).await {
+            warn!("Failed to publish {topic} event: {e}");
+        }
+    }
+
+    /// Publishes the events of `server` until it shuts down.
+    pub fn spawn(self, server: &Server) -> JoinHandle<()> {
+        let mut sessions = server.sessions().subscribe();
+        let mut transmissions = server.subscriptions().subscribe_transmissions();
+        let mut restrictions = server.restrictions().subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    transition = sessions.recv() => {
+                        transition.map(|transition| ServerEvent::from_transition(&transition))
+                    }
+                    event = transmissions.recv() => event.map(|event| Some(event.into())),
+                    change = restrictions.recv() => {
+                        change.map(|change| ServerEvent::from_restriction(&change))
+                    }
+                };
+                match event {
+                    Ok(Some(event)) => self.publish(event).await,
+                    Ok(None) => {}
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Dropped {count} events the broker could not keep up with");
+                    }
+                    // The server is gone
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Splits `mqtt://host:port` style URLs, defaulting the port.
+fn host_and_port<'a>(
+    url: &'a str,
+    scheme: &str,
+    default_port: u16,
+) -> Result<(&'a str, u16), FleetNetError> {
+    let invalid = || FleetNetError::invalid_field("url", Constraint::Invalid(Cow::Borrowed("url")));
+    let address = url
+        .strip_prefix(scheme)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .ok_or_else(invalid)?
+        .trim_end_matches('/');
+    match address.rsplit_once(':') {
+        Some((host, port)) => Ok((host, port.parse().map_err(|_| invalid())?)),
+        None if !address.is_empty() => Ok((address, default_port)),
+        None => Err(invalid()),
+    }
+}
+
+/// Connects to the broker in `config` and publishes the events of `server`
+/// through it.
+///
+/// # Errors
+///
+/// Returns a validation error if the URL is malformed or names a broker
+/// this build does not support.
+pub async fn spawn(
+    config: &EventsConfig,
+    server: &Server,
+) -> Result<JoinHandle<()>, FleetNetError> {
+    let scheme = config.url.split("://").next().unwrap_or_default();
+    let prefix = &config.topic_prefix;
+    let publisher = match scheme {
+        "mqtt" => spawn_mqtt(
+            host_and_port(&config.url, scheme, MQTT_PORT)?,
+            prefix,
+            server,
+        ),
+        "nats" => {
+            spawn_nats(
+                host_and_port(&config.url, scheme, NATS_PORT)?,
+                prefix,
+                server,
+            )
+            .await
+        }
+        _ => Err(unsupported(scheme)),
+    }?;
+    info!("Publishing server events to {}", config.url);
+    Ok(publisher)
+}
+
+fn unsupported(scheme: &str) -> FleetNetError {
+    FleetNetError::invalid_field(
+        "url",
+        Constraint::Invalid(Cow::Owned(format!("unsupported_broker({scheme})"))),
+    )
+}
+
+#[cfg(feature = "mqtt")]
+fn spawn_mqtt(
+    (host, port): (&str, u16),
+    prefix: &str,
+    server: &Server,
+) -> Result<JoinHandle<()>, FleetNetError> {
+    Ok(EventPublisher::new(mqtt::MqttSink::connect(host, port), prefix).spawn(server))
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn spawn_mqtt(_: (&str, u16), _: &str, _: &Server) -> Result<JoinHandle<()>, FleetNetError> {
+    Err(unsupported("mqtt"))
+}
+
+#[cfg(feature = "nats")]
+async fn spawn_nats(
+    (host, port): (&str, u16),
+    prefix: &str,
+    server: &Server,
+) -> Result<JoinHandle<()>, FleetNetError> {
+    let sink = nats::NatsSink::connect(host, port).await?;
+    Ok(EventPublisher::new(sink, prefix).spawn(server))
+}
+
+#[cfg(not(feature = "nats"))]
+async fn spawn_nats(_: (&str, u16), _: &str, _: &Server) -> Result<JoinHandle<()>, FleetNetError> {
+    Err(unsupported("nats"))
+}
+
+#[cfg(feature = "mqtt")]
+mod mqtt {
+    use super::EventSink;
+    use fleet_net_common::error::FleetNetError;
+    use rumqttc::{AsyncClient, MqttOptions, QoS};
+    use std::borrow::Cow;
+    use std::time::Duration;
+    use tracing::warn;
+
+    /// Publishes waiting to be sent before publishing blocks.
+    const QUEUE_CAPACITY: usize = 256;
+
+    pub struct MqttSink {
+        client: AsyncClient,
+    }
+
+    impl MqttSink {
+        /// Connects in the background, reconnecting whenever the broker is
+        /// lost.
+        pub fn connect(host: &str, port: u16) -> Self {
+            let client_id = format!("fleet-net-server-{}", std::process::id());
+            let mut options = MqttOptions::new(client_id, host, port);
+            options.set_keep_alive(Duration::from_secs(30));
+            let (client, mut event_loop) = AsyncClient::new(options, QUEUE_CAPACITY);
+            tokio::spawn(async move {
+                loop {
+                    if let Err(e) = event_loop.poll().await {
+                        warn!("MQTT connection failed, retrying: {e}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            });
+            Self { client }
+        }
+    }
+
+    impl EventSink for MqttSink {
+        const SEPARATOR: char = '/';
+
+        async fn publish(&self, topic: String, payload: Vec<u8>) -> Result<(), FleetNetError> {
+            self.client
+                .publish(topic, QoS::AtLeastOnce, false, payload)
+                .await
+                .map_err(|e| FleetNetError::NetworkError(Cow::Owned(e.to_string())))
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use super::EventSink;
+    use fleet_net_common::error::FleetNetError;
+    use std::borrow::Cow;
+
+    pub struct NatsSink {
+        client: async_nats::Client,
+    }
+
+    impl NatsSink {
+        /// Connects to `url`, retrying in the background if the server is
+        /// not up yet.
+        pub async fn connect(host: &str, port: u16) -> Result<Self, FleetNetError> {
+            let client = async_nats::ConnectOptions::new()
+                .retry_on_initial_connect()
+                .connect(format!("nats://{host}:{port}"))
+                .await
+                .map_err(|e| FleetNetError::NetworkError(Cow::Owned(e.to_string())))?;
+            Ok(Self { client })
+        }
+    }
+
+    impl EventSink for NatsSink {
+        const SEPARATOR: char = '.';
+
+        async fn publish(&self, topic: String, payload: Vec<u8>) -> Result<(), FleetNetError> {
+            self.client
+                .publish(topic, payload.into())
+                .await
+                .map_err(|e| FleetNetError::NetworkError(Cow::Owned(e.to_string())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_config;
+    use chrono::Utc;
+    use fleet_net_common::permission::{PermissionSet, Permissions};
+    use fleet_net_common::session::Session;
+    use fleet_net_common::user::User;
+    use fleet_net_protocol::packet::PacketHeader;
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc;
+
+    struct ChannelSink(mpsc::UnboundedSender<(String, Vec<u8>)>);
+
+    impl EventSink for ChannelSink {
+        const SEPARATOR: char = '/';
+
+        async fn publish(&self, topic: String, payload: Vec<u8>) -> Result<(), FleetNetError> {
+            let _ = self.0.send((topic, payload));
+            Ok(())
+        }
+    }
+
+    fn session(id: u16, permissions: Permissions) -> Session {
+        Session {
+            id: format!("session_{id}"),
+            user: User::new(UserId::new(id).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Authenticating,
+            current_channel: None,
+            subscribed_channels: HashSet::new(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    async fn next(
+        published: &mut mpsc::UnboundedReceiver<(String, Vec<u8>)>,
+    ) -> (String, ServerEvent) {
+        let (topic, payload) = published.recv().await.unwrap();
+        let record: EventRecord = serde_json::from_slice(&payload).unwrap();
+        assert!(record.at_ms > 0);
+        (topic, record.event)
+    }
+
+    #[tokio::test]
+    async fn test_server_events_are_published_by_topic() {
+        let server = Server::new(test_config()).unwrap();
+        let (sink, mut published) = mpsc::unbounded_channel();
+        let _publisher = EventPublisher::new(ChannelSink(sink), "ops/voice").spawn(&server);
+
+        let mut moderator = session(1, Permissions::MUTE_USERS);
+        server
+            .sessions()
+            .transition(&mut moderator, SessionState::Active)
+            .unwrap();
+        assert_eq!(
+            next(&mut published).await,
+            (
+                "ops/voice/user_connected".to_string(),
+                ServerEvent::UserConnected {
+                    user_id: moderator.user.id,
+                    session_id: "session_1".to_string(),
+                }
+            )
+        );
+
+        let header = PacketHeader {
+            channel_id: ChannelId::new(3).unwrap(),
+            user_id: UserId::new(2).unwrap(),
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 80,
+            hmac_prefix: 0,
+        };
+        let start = Instant::now();
+        server.subscriptions().check_policy(&header, start).unwrap();
+        server
+            .subscriptions()
+            .end_idle_transmissions(start + Duration::from_secs(1));
+        let (topic, _) = next(&mut published).await;
+        assert_eq!(topic, "ops/voice/transmission_started");
+        assert_eq!(
+            next(&mut published).await.1,
+            ServerEvent::TransmissionStopped {
+                user_id: header.user_id,
+                channel_id: header.channel_id,
+                duration_ms: 0,
+            }
+        );
+
+        server
+            .restrictions()
+            .apply(
+                &moderator,
+                &ControlMessage::RestrictUser {
+                    target: header.user_id,
+                    kind: RestrictionKind::Mute,
+                    duration_secs: Some(60),
+                    reason: None,
+                },
+                Utc::now(),
+            )
+            .unwrap();
+        let (topic, event) = next(&mut published).await;
+        assert_eq!(topic, "ops/voice/user_restricted");
+        assert!(
+            matches!(event, ServerEvent::UserRestricted { user_id, .. } if user_id == header.user_id)
+        );
+
+        server
+            .sessions()
+            .transition(&mut moderator, SessionState::Disconnecting)
+            .unwrap();
+        assert_eq!(next(&mut published).await.0, "ops/voice/user_disconnected");
+    }
+
+    #[tokio::test]
+    async fn test_broker_urls_are_checked() {
+        let server = Server::new(test_config()).unwrap();
+        let result = spawn(&EventsConfig::new("amqp://broker.local"), &server).await;
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+
+        assert_eq!(
+            host_and_port("mqtt://broker.local:8883", "mqtt", 1883).unwrap(),
+            ("broker.local", 8883)
+        );
+        assert_eq!(
+            host_and_port("mqtt://broker.local/", "mqtt", 1883).unwrap(),
+            ("broker.local", 1883)
+        );
+        assert!(host_and_port("mqtt://", "mqtt", 1883).is_err());
+        assert!(host_and_port("mqtt://broker:port", "mqtt", 1883).is_err());
+        assert!(host_and_port("nats://broker", "mqtt", 1883).is_err());
+    }
+}
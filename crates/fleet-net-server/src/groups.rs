@@ -0,0 +1,293 @@
+//! Groups such as fireteams.
+//!
+//! Users holding [`Permissions::WHISPER`] create groups with
+//! [`ControlMessage::CreateGroup`] and join them with
+//! [`ControlMessage::JoinGroup`]; anyone can leave with
+//! [`ControlMessage::LeaveGroup`]. The registry knows every group's members,
+//! so it answers who a user's whispers reach, and publishes each change as a
+//! [`ControlMessage::GroupChanged`] or [`ControlMessage::GroupDisbanded`] for
+//! connections to broadcast.
+
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::group::Group;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::session::Session;
+use fleet_net_common::types::{GroupId, UserId};
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::ControlMessage;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Group changes buffered for slow subscribers.
+const CHANGE_BUFFER: usize = 64;
+
+pub struct GroupRegistry {
+    /// Behind one lock so a user can never end up in two groups.
+    groups: Mutex<HashMap<GroupId, Group>>,
+    changes: broadcast::Sender<ControlMessage>,
+    limits: ServerLimits,
+}
+
+impl GroupRegistry {
+    pub fn new() -> Self {
+        Self {
+            groups: Mutex::new(HashMap::new()),
+            changes: broadcast::channel(CHANGE_BUFFER).0,
+            limits: ServerLimits::default(),
+        }
+    }
+
+    /// Checks requests against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Receives every group change from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ControlMessage> {
+        self.changes.subscribe()
+    }
+
+    /// Handles a create, join or leave request from `session` and returns
+    /// the change, which is also published to subscribers.
+    pub fn apply(
+        &self,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<ControlMessage, FleetNetError> {
+        session.ensure_interactive()?;
+        let user_id = session.user.id;
+        let mut groups = self.groups.lock().unwrap();
+        let change = match message {
+            ControlMessage::CreateGroup { name } => {
+                Self::check_can_group(session)?;
+                Group::validate_name(name)?;
+                Self::ensure_ungrouped(&groups, user_id)?;
+                let group_id = (1..=u16::MAX)
+                    .filter_map(GroupId::new)
+                    .find(|id| !groups.contains_key(id))
+                    .ok_or_else(|| {
+                        FleetNetError::invalid_field(
+                            "group_id",
+                            Constraint::Invalid(Cow::Borrowed("no_free_ids")),
+                        )
+                    })?;
+                let group = Group::new(group_id, name.clone(), user_id);
+                groups.insert(group_id, group.clone());
+                ControlMessage::GroupChanged { group }
+            }
+            ControlMessage::JoinGroup { group_id } => {
+                Self::check_can_group(session)?;
+                Self::ensure_ungrouped(&groups, user_id)?;
+                let group = groups.get_mut(group_id).ok_or_else(|| {
+                    FleetNetError::invalid_field("group_id", Constraint::NotFound)
+                })?;
+                if group.is_full(&self.limits) {
+                    return Err(FleetNetError::invalid_field(
+                        "group_id",
+                        Constraint::Invalid(Cow::Owned(format!(
+                            "group_full({})",
+                            self.limits.max_group_size
+                        ))),
+                    ));
+                }
+                group.add_member(user_id);
+                ControlMessage::GroupChanged {
+                    group: group.clone(),
+                }
+            }
+            ControlMessage::LeaveGroup => Self::leave(&mut groups, user_id).ok_or_else(|| {
+                FleetNetError::invalid_field(
+                    "group_id",
+                    Constraint::Invalid(Cow::Borrowed("not_in_group")),
+                )
+            })?,
+            _ => {
+                return Err(FleetNetError::invalid_field(
+                    "type",
+                    Constraint::Invalid(Cow::Borrowed(
+                        "expected create_group, join_group or leave_group",
+                    )),
+                ))
+            }
+        };
+        drop(groups);
+
+        session.update_activity();
+        // Nobody listening is fine; the registry is still up to date.
+        let _ = self.changes.send(change.clone());
+        Ok(change)
+    }
+
+    /// The group `user_id` is in, if any.
+    pub fn group_of(&self, user_id: UserId) -> Option<Group> {
+        self.groups
+            .lock()
+            .unwrap()
+            .values()
+            .find(|group| group.is_member(user_id))
+            .cloned()
+    }
+
+    /// The other members of `user_id`'s group, who hear their group whispers.
+    pub fn whisper_targets(&self, user_id: UserId) -> Vec<UserId> {
+        self.group_of(user_id)
+            .map(|group| group.others(user_id).collect())
+            .unwrap_or_default()
+    }
+
+    /// A [`ControlMessage::GroupChanged`] for every group, to bring a newly
+    /// connected client up to date.
+    pub fn snapshot(&self) -> Vec<ControlMessage> {
+        let mut groups: Vec<Group> = self.groups.lock().unwrap().values().cloned().collect();
+        groups.sort_by_key(|group| group.id);
+        groups
+            .into_iter()
+            .map(|group| ControlMessage::GroupChanged { group })
+            .collect()
+    }
+
+    /// Takes a disconnected user out of their group, publishing the change.
+    pub fn remove_user(&self, user_id: UserId) {
+        let change = Self::leave(&mut self.groups.lock().unwrap(), user_id);
+        if let Some(change) = change {
+            let _ = self.changes.send(change);
+        }
+    }
+
+    /// Removes `user_id` from their group, disbanding it once empty, and
+    /// returns the change; `None` if they were in no group.
+    fn leave(groups: &mut HashMap<GroupId, Group>, user_id: UserId) -> Option<ControlMessage> {
+        let group = groups.values_mut().find(|group| group.is_member(user_id))?;
+        group.remove_member(user_id);
+        if group.is_empty() {
+            let group_id = group.id;
+            groups.remove(&group_id);
+            Some(ControlMessage::GroupDisbanded { group_id })
+        } else {
+            Some(ControlMessage::GroupChanged {
+                group: group.clone(),
+            })
+        }
+    }
+
+    fn check_can_group(session: &Session) -> Result<(), FleetNetError> {
+        if session.permission.has(Permissions::WHISPER) {
+            Ok(())
+        } else {
+            Err(FleetNetError::PermissionError(Cow::Borrowed(
+                "Missing permission to form groups",
+            )))
+        }
+    }
+
+    fn ensure_ungrouped(
+        groups: &HashMap<GroupId, Group>,
+        user_id: UserId,
+    ) -> Result<(), FleetNetError> {
+        if groups.values().any(|group| group.is_member(user_id)) {
+            return Err(FleetNetError::invalid_field(
+                "group_id",
+                Constraint::Invalid(Cow::Borrowed("already_in_group")),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for GroupRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::user::User;
+    use std::time::Instant;
+
+    fn session(user_id: u16, permissions: Permissions) -> Session {
+        Session {
+            id: format!("session_{user_id}"),
+            user: User::new(UserId::new(user_id).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    fn create(name: &str) -> ControlMessage {
+        ControlMessage::CreateGroup {
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_groups_form_and_disband() {
+        let registry = GroupRegistry::new().with_limits(ServerLimits {
+            max_group_size: 2,
+            ..ServerLimits::default()
+        });
+        let mut changes = registry.subscribe();
+        let mut lead = session(1, Permissions::WHISPER);
+        let mut wingman = session(2, Permissions::WHISPER);
+        let mut late = session(3, Permissions::WHISPER);
+
+        let ControlMessage::GroupChanged { group } =
+            registry.apply(&mut lead, &create("Viper")).unwrap()
+        else {
+            panic!("expected group_changed");
+        };
+        let join = ControlMessage::JoinGroup { group_id: group.id };
+        registry.apply(&mut wingman, &join).unwrap();
+        assert_eq!(registry.whisper_targets(lead.user.id), [wingman.user.id]);
+
+        // One group at a time, and no more members than the limit
+        assert!(registry.apply(&mut lead, &create("Cobra")).is_err());
+        assert!(registry.apply(&mut late, &join).is_err());
+
+        // The lead passes to the remaining member, then the group disbands
+        let left = registry
+            .apply(&mut lead, &ControlMessage::LeaveGroup)
+            .unwrap();
+        assert!(matches!(
+            left,
+            ControlMessage::GroupChanged { group } if group.leader == wingman.user.id
+        ));
+        registry.remove_user(wingman.user.id);
+        assert!(registry.group_of(wingman.user.id).is_none());
+
+        let published: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok()).collect();
+        assert_eq!(published.len(), 4);
+        assert!(matches!(
+            published[3],
+            ControlMessage::GroupDisbanded { group_id } if group_id == group.id
+        ));
+    }
+
+    #[test]
+    fn test_forming_groups_requires_whisper_permission() {
+        let registry = GroupRegistry::new();
+        let result = registry.apply(&mut session(1, Permissions::CONNECT), &create("Viper"));
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+
+        // Leaving needs no permission, but does need a group
+        let result = registry.apply(
+            &mut session(1, Permissions::CONNECT),
+            &ControlMessage::LeaveGroup,
+        );
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+    }
+}
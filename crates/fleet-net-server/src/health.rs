@@ -0,0 +1,473 @@
+//! Health reporting and systemd supervision.
+//!
+//! Exposes an HTTP `/healthz` endpoint describing the state of the control
+//! listener, the database, and the Discord API, a `/status` endpoint with the
+//! public server status used for region selection, and implements the subset
+//! of the `sd_notify` protocol needed for `Type=notify` units with `WatchdogSec=`.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_protocol::message::ServerStatus;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Timeout applied to each individual dependency check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Outcome of a single health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    /// The dependency is not configured on this server.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CheckResult {
+    fn ok() -> Self {
+        Self {
+            status: CheckStatus::Ok,
+            detail: None,
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Failed,
+            detail: Some(detail.into()),
+        }
+    }
+
+    fn skipped() -> Self {
+        Self {
+            status: CheckStatus::Skipped,
+            detail: None,
+        }
+    }
+
+    fn is_failed(&self) -> bool {
+        self.status == CheckStatus::Failed
+    }
+}
+
+/// Overall server health.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Healthy,
+    /// Serving voice, but an external dependency (Discord) is unreachable.
+    Degraded,
+    /// The listener is down or the database is unreachable.
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub listener: CheckResult,
+    pub database: CheckResult,
+    pub discord: CheckResult,
+}
+
+/// Shared state inspected by the health endpoint.
+pub struct HealthState {
+    listener_up: AtomicBool,
+    database: Option<SqlitePool>,
+    discord_api_url: Option<String>,
+    http: reqwest::Client,
+    status: RwLock<Option<ServerStatus>>,
+}
+
+impl HealthState {
+    pub fn new(database: Option<SqlitePool>, discord_api_url: Option<String>) -> Self {
+        Self {
+            listener_up: AtomicBool::new(false),
+            database,
+            discord_api_url,
+            http: reqwest::Client::new(),
+            status: RwLock::new(None),
+        }
+    }
+
+    /// Publishes the server status served on `/status` and in ping responses.
+    pub fn set_status(&self, status: ServerStatus) {
+        *self.status.write().unwrap() = Some(status);
+    }
+
+    pub fn status(&self) -> Option<ServerStatus> {
+        self.status.read().unwrap().clone()
+    }
+
+    /// Records whether the control listener is accepting connections.
+    pub fn set_listener_up(&self, up: bool) {
+        self.listener_up.store(up, Ordering::SeqCst);
+    }
+
+    pub async fn check(&self) -> HealthReport {
+        let listener = self.check_listener();
+        let database = self.check_database().await;
+        let discord = match &self.discord_api_url {
+            Some(url) => match self.http.get(url).timeout(CHECK_TIMEOUT).send().await {
+                // Any non-5xx answer means the API is reachable from this host.
+                Ok(response) if !response.status().is_server_error() => CheckResult::ok(),
+                Ok(response) => CheckResult::failed(format!("HTTP {}", response.status())),
+                Err(e) => CheckResult::failed(e.to_string()),
+            },
+            None => CheckResult::skipped(),
+        };
+
+        let status = if listener.is_failed() || database.is_failed() {
+            HealthStatus::Unhealthy
+        } else if discord.is_failed() {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        };
+
+        HealthReport {
+            status,
+            listener,
+            database,
+            discord,
+        }
+    }
+
+    /// Whether the server is serving, i.e. would not be reported unhealthy.
+    /// Unlike [`check`](Self::check) this leaves out Discord, which a
+    /// restart would not bring back.
+    pub async fn is_serving(&self) -> bool {
+        !self.check_listener().is_failed() && !self.check_database().await.is_failed()
+    }
+
+    fn check_listener(&self) -> CheckResult {
+        if self.listener_up.load(Ordering::SeqCst) {
+            CheckResult::ok()
+        } else {
+            CheckResult::failed("Control listener is not bound")
+        }
+    }
+
+    async fn check_database(&self) -> CheckResult {
+        match &self.database {
+            Some(pool) => {
+                match tokio::time::timeout(CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(pool))
+                    .await
+                {
+                    Ok(Ok(_)) => CheckResult::ok(),
+                    Ok(Err(e)) => CheckResult::failed(e.to_string()),
+                    Err(_) => CheckResult::failed("Database check timed out"),
+                }
+            }
+            None => CheckResult::skipped(),
+        }
+    }
+}
+
+/// Builds the router serving `/healthz` and `/status`.
+pub fn router(state: Arc<HealthState>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/status", get(status))
+        .with_state(state)
+}
+
+async fn status(State(state): State<Arc<HealthState>>) -> Result<Json<ServerStatus>, StatusCode> {
+    state
+        .status()
+        .map(Json)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+async fn healthz(State(state): State<Arc<HealthState>>) -> (StatusCode, Json<HealthReport>) {
+    let report = state.check().await;
+    let code = match report.status {
+        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+    };
+    (code, Json(report))
+}
+
+/// Serves the HTTP endpoints in `router` until the listener fails.
+pub async fn serve(listener: TcpListener, router: Router) -> Result<(), FleetNetError> {
+    info!("HTTP endpoint listening on {}", listener.local_addr()?);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+/// Minimal `sd_notify` client.
+///
+/// All functions are no-ops returning `Ok(false)` when the process was not
+/// started by systemd with `NOTIFY_SOCKET` set, or on non-Unix platforms.
+pub mod systemd {
+    use super::HealthState;
+    use std::io;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Sends a raw state string such as `READY=1` to the service manager.
+    pub fn notify(state: &str) -> io::Result<bool> {
+        match std::env::var_os("NOTIFY_SOCKET") {
+            Some(socket) => send(&socket.to_string_lossy(), state).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Tells systemd the server has finished starting up.
+    pub fn notify_ready() -> io::Result<bool> {
+        notify("READY=1")
+    }
+
+    /// Tells systemd the server is shutting down.
+    pub fn notify_stopping() -> io::Result<bool> {
+        notify("STOPPING=1")
+    }
+
+    /// Resets the systemd watchdog timer.
+    pub fn notify_watchdog() -> io::Result<bool> {
+        notify("WATCHDOG=1")
+    }
+
+    /// Returns the watchdog timeout configured for this process, if any.
+    pub fn watchdog_interval() -> Option<Duration> {
+        // WATCHDOG_PID, when present, restricts the watchdog to a single process.
+        if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok() != Some(std::process::id()) {
+                return None;
+            }
+        }
+
+        let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        (usec > 0).then(|| Duration::from_micros(usec))
+    }
+
+    /// Pings the watchdog at half the configured interval while `health`
+    /// says the server is serving, until the task is dropped. Pings stop
+    /// while it isn't, so systemd restarts a server that stopped serving.
+    pub fn spawn_watchdog(health: Arc<HealthState>) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = watchdog_interval()?;
+        Some(tokio::spawn(watchdog(
+            health,
+            interval / 2,
+            notify_watchdog,
+        )))
+    }
+
+    pub(crate) async fn watchdog(
+        health: Arc<HealthState>,
+        period: Duration,
+        ping: impl Fn() -> io::Result<bool>,
+    ) {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if !health.is_serving().await {
+                tracing::warn!("Withholding the systemd watchdog ping while unhealthy");
+                continue;
+            }
+            if let Err(e) = ping() {
+                tracing::warn!("Failed to ping systemd watchdog: {e}");
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn send(socket: &str, state: &str) -> io::Result<()> {
+        use std::os::unix::net::UnixDatagram;
+
+        let datagram = UnixDatagram::unbound()?;
+
+        // A leading '@' denotes a Linux abstract namespace socket.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = socket.strip_prefix('@') {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+
+        datagram.send_to(state.as_bytes(), socket)?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn send(_socket: &str, _state: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs but otherwise ignores notification failures; supervision is best effort.
+pub fn notify_ready() {
+    match systemd::notify_ready() {
+        Ok(true) => info!("Notified systemd of readiness"),
+        Ok(false) => {}
+        Err(e) => warn!("Failed to notify systemd of readiness: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::limits::ServerLimits;
+
+    async fn serve_health(state: Arc<HealthState>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, router(state)));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_until_listener_is_up() {
+        let state = Arc::new(HealthState::new(None, None));
+
+        let report = state.check().await;
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert_eq!(report.listener.status, CheckStatus::Failed);
+        assert_eq!(report.database.status, CheckStatus::Skipped);
+        assert_eq!(report.discord.status, CheckStatus::Skipped);
+
+        state.set_listener_up(true);
+        assert_eq!(state.check().await.status, HealthStatus::Healthy);
+    }
+
+    #[tokio::test]
+    async fn test_database_check_uses_pool() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let state = HealthState::new(Some(pool.clone()), None);
+        state.set_listener_up(true);
+
+        assert_eq!(state.check().await.database.status, CheckStatus::Ok);
+
+        pool.close().await;
+        let report = state.check().await;
+        assert_eq!(report.database.status, CheckStatus::Failed);
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_discord_degrades_health() {
+        // Bind and immediately drop a listener to get a port nothing listens on.
+        let closed_port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let state = HealthState::new(None, Some(format!("http://127.0.0.1:{closed_port}/")));
+        state.set_listener_up(true);
+
+        let report = state.check().await;
+        assert_eq!(report.discord.status, CheckStatus::Failed);
+        assert_eq!(report.status, HealthStatus::Degraded);
+    }
+
+    #[tokio::test]
+    async fn test_healthz_endpoint_status_codes() {
+        let state = Arc::new(HealthState::new(None, None));
+        let addr = serve_health(state.clone()).await;
+        let url = format!("http://{addr}/healthz");
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        state.set_listener_up(true);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["listener"]["status"], "ok");
+        assert_eq!(body["database"]["status"], "skipped");
+    }
+
+    #[tokio::test]
+    async fn test_status_endpoint() {
+        let state = Arc::new(HealthState::new(None, None));
+        let addr = serve_health(state.clone()).await;
+        let url = format!("http://{addr}/status");
+
+        // Not published until the server has started
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        let published = ServerStatus {
+            name: "Fleet Net EU".to_string(),
+            version: "0.1.0".into(),
+            region: Some("eu-west".to_string()),
+            ping_port: Some(7002),
+            voice_port: Some(7001),
+            user_count: 4,
+            channel_count: 2,
+            limits: ServerLimits {
+                max_users: Some(100),
+                ..ServerLimits::default()
+            },
+            motd: None,
+            voice_padding: None,
+        };
+        state.set_status(published.clone());
+
+        let status: ServerStatus = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        assert_eq!(status, published);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sd_notify_sends_state_datagram() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        systemd::send(path.to_str().unwrap(), "READY=1").unwrap();
+
+        let mut buf = [0u8; 32];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_is_pinged_only_while_serving() {
+        use std::sync::atomic::AtomicUsize;
+
+        let state = Arc::new(HealthState::new(None, None));
+        let pings = Arc::new(AtomicUsize::new(0));
+        let period = Duration::from_secs(5);
+        tokio::spawn(systemd::watchdog(state.clone(), period, {
+            let pings = pings.clone();
+            move || {
+                pings.fetch_add(1, Ordering::SeqCst);
+                Ok(true)
+            }
+        }));
+
+        tokio::time::sleep(period * 3).await;
+        assert_eq!(pings.load(Ordering::SeqCst), 0);
+
+        state.set_listener_up(true);
+        tokio::time::sleep(period * 2).await;
+        assert!(pings.load(Ordering::SeqCst) >= 2);
+
+        state.set_listener_up(false);
+        let before = pings.load(Ordering::SeqCst);
+        tokio::time::sleep(period * 2).await;
+        assert_eq!(pings.load(Ordering::SeqCst), before);
+    }
+}
@@ -0,0 +1,357 @@
+//! Crash-safe journal of active sessions.
+//!
+//! Every change to a session's channel membership is appended to the journal
+//! as a JSON line and flushed to disk before the call returns. After a crash
+//! or restart the journal is replayed, so clients reconnecting with their
+//! resume token are placed back into the channels they were in.
+//!
+//! The journal is compacted on open; a torn final line left by a crash
+//! mid-write is ignored.
+
+use dashmap::DashMap;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::session::Session;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::resume::ResumeToken;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// What is needed to restore a session after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub session_id: String,
+    pub user_id: UserId,
+    pub resume_token: ResumeToken,
+    pub current_channel: Option<ChannelId>,
+    pub subscribed_channels: Vec<ChannelId>,
+    /// Unix time of the last update, in seconds.
+    pub updated_at: u64,
+}
+
+impl JournalRecord {
+    pub fn from_session(session: &Session, resume_token: ResumeToken) -> Self {
+        let mut subscribed_channels: Vec<_> = session.subscribed_channels.iter().copied().collect();
+        subscribed_channels.sort_unstable();
+
+        Self {
+            session_id: session.id.clone(),
+            user_id: session.user.id,
+            resume_token,
+            current_channel: session.current_channel,
+            subscribed_channels,
+            updated_at: unix_now(),
+        }
+    }
+
+    fn is_expired(&self, resume_window: Duration) -> bool {
+        unix_now().saturating_sub(self.updated_at) > resume_window.as_secs()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalEntry {
+    Upsert { record: JournalRecord },
+    Remove { session_id: String },
+}
+
+pub struct SessionJournal {
+    path: PathBuf,
+    file: Mutex<File>,
+    records: DashMap<String, JournalRecord>,
+    resume_window: Duration,
+}
+
+impl SessionJournal {
+    /// Opens the journal at `path`, replaying and compacting any existing entries.
+    ///
+    /// Sessions last updated more than `resume_window` ago are discarded.
+    pub async fn open(
+        path: impl Into<PathBuf>,
+        resume_window: Duration,
+    ) -> Result<Self, FleetNetError> {
+        let path = path.into();
+        let records = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => replay(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let records: DashMap<_, _> = records
+            .into_iter()
+            .filter(|(_, record)| !record.is_expired(resume_window))
+            .collect();
+        let file = write_snapshot(&path, records.iter().map(|entry| entry.value().clone())).await?;
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            records,
+            resume_window,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Durably records the current state of a session.
+    pub async fn record(&self, record: JournalRecord) -> Result<(), FleetNetError> {
+        // The file lock is held until the in-memory record is updated, so a
+        // concurrent compaction either sees this record or runs before the
+        // line is appended.
+        let mut file = self.file.lock().await;
+        append(
+            &mut file,
+            &JournalEntry::Upsert {
+                record: record.clone(),
+            },
+        )
+        .await?;
+        self.records.insert(record.session_id.clone(), record);
+        Ok(())
+    }
+
+    /// Forgets a session that ended cleanly; it can no longer be resumed.
+    pub async fn remove(&self, session_id: &str) -> Result<(), FleetNetError> {
+        let mut file = self.file.lock().await;
+        if self.records.remove(session_id).is_some() {
+            append(
+                &mut file,
+                &JournalEntry::Remove {
+                    session_id: session_id.to_string(),
+                },
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Claims the session matching `token` for `user_id`.
+    ///
+    /// The record is removed so a token can only be used once; the resumed
+    /// session should be recorded again under a fresh token.
+    pub async fn resume(
+        &self,
+        token: &ResumeToken,
+        user_id: UserId,
+    ) -> Result<Option<JournalRecord>, FleetNetError> {
+        let mut file = self.file.lock().await;
+        let session_id = self.records.iter().find_map(|entry| {
+            let record = entry.value();
+            (record.user_id == user_id
+                && record.resume_token.matches(token.as_str())
+                && !record.is_expired(self.resume_window))
+            .then(|| record.session_id.clone())
+        });
+
+        // Claims are serialized by the file lock, so concurrent claims of one
+        // token cannot both succeed.
+        let Some((session_id, record)) = session_id.and_then(|id| self.records.remove(&id)) else {
+            return Ok(None);
+        };
+        append(&mut file, &JournalEntry::Remove { session_id }).await?;
+        Ok(Some(record))
+    }
+
+    /// Rewrites the journal so it only contains live sessions.
+    pub async fn compact(&self) -> Result<(), FleetNetError> {
+        let mut file = self.file.lock().await;
+        self.records
+            .retain(|_, record| !record.is_expired(self.resume_window));
+        *file = write_snapshot(
+            &self.path,
+            self.records.iter().map(|entry| entry.value().clone()),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Appends one entry; callers hold the file lock for the whole update.
+async fn append(file: &mut File, entry: &JournalEntry) -> Result<(), FleetNetError> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    file.write_all(&line).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+fn replay(contents: &str) -> HashMap<String, JournalRecord> {
+    let mut records = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        match serde_json::from_str::<JournalEntry>(line) {
+            Ok(JournalEntry::Upsert { record }) => {
+                records.insert(record.session_id.clone(), record);
+            }
+            Ok(JournalEntry::Remove { session_id }) => {
+                records.remove(&session_id);
+            }
+            // A crash mid-append leaves a partial last line; skip it.
+            Err(e) => warn!(
+                "Skipping unreadable session journal line {}: {e}",
+                index + 1
+            ),
+        }
+    }
+    records
+}
+
+/// Atomically replaces the journal with one upsert per record and returns
+/// the new file opened for appending.
+async fn write_snapshot(
+    path: &Path,
+    records: impl Iterator<Item = JournalRecord>,
+) -> Result<File, FleetNetError> {
+    let mut contents = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut contents, &JournalEntry::Upsert { record })?;
+        contents.push(b'\n');
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path).await?;
+    tmp.write_all(&contents).await?;
+    tmp.sync_all().await?;
+    drop(tmp);
+    tokio::fs::rename(&tmp_path, path).await?;
+
+    Ok(OpenOptions::new().append(true).open(path).await?)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    const WINDOW: Duration = Duration::from_secs(300);
+
+    fn test_record(session_id: &str, user_id: UserId, channel_id: ChannelId) -> JournalRecord {
+        JournalRecord {
+            session_id: session_id.to_string(),
+            user_id,
+            resume_token: ResumeToken::generate().unwrap(),
+            current_channel: Some(channel_id),
+            subscribed_channels: vec![channel_id, channel(9)],
+            updated_at: unix_now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_restart() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sessions.journal");
+
+        let record = test_record("s1", user(7), channel(3));
+        let token = record.resume_token.clone();
+        {
+            let journal = SessionJournal::open(&path, WINDOW).await.unwrap();
+            journal
+                .record(test_record("s1", user(7), channel(1)))
+                .await
+                .unwrap();
+            // Later update wins on replay
+            journal.record(record.clone()).await.unwrap();
+            journal
+                .record(test_record("s2", user(8), channel(2)))
+                .await
+                .unwrap();
+            journal.remove("s2").await.unwrap();
+            // Dropped without any shutdown, as in a crash
+        }
+
+        let journal = SessionJournal::open(&path, WINDOW).await.unwrap();
+        assert_eq!(journal.len(), 1);
+
+        // Wrong user or token does not resume
+        assert!(journal.resume(&token, user(8)).await.unwrap().is_none());
+        let wrong = ResumeToken::generate().unwrap();
+        assert!(journal.resume(&wrong, user(7)).await.unwrap().is_none());
+
+        let resumed = journal.resume(&token, user(7)).await.unwrap().unwrap();
+        assert_eq!(resumed.current_channel, Some(channel(3)));
+        assert_eq!(resumed.subscribed_channels, vec![channel(3), channel(9)]);
+
+        // Tokens are single use
+        assert!(journal.resume(&token, user(7)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_torn_write_and_expired_records_are_dropped() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sessions.journal");
+
+        let mut stale = test_record("old", user(1), channel(1));
+        stale.updated_at -= WINDOW.as_secs() + 1;
+        let live = test_record("live", user(2), channel(2));
+
+        let mut contents = String::new();
+        for record in [stale, live] {
+            contents.push_str(&serde_json::to_string(&JournalEntry::Upsert { record }).unwrap());
+            contents.push('\n');
+        }
+        contents.push_str(r#"{"op":"upsert","record":{"session_id":"#);
+        std::fs::write(&path, contents).unwrap();
+
+        let journal = SessionJournal::open(&path, WINDOW).await.unwrap();
+        assert_eq!(journal.len(), 1);
+
+        // Compaction on open leaves only the live session on disk
+        let on_disk = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(on_disk.lines().count(), 1);
+        assert!(on_disk.contains("\"live\""));
+    }
+
+    #[tokio::test]
+    async fn test_records_survive_concurrent_compaction() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sessions.journal");
+        let journal = std::sync::Arc::new(SessionJournal::open(&path, WINDOW).await.unwrap());
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for id in 1..=50u16 {
+            let recorder = journal.clone();
+            tasks.spawn(async move {
+                let record = test_record(&format!("s{id}"), user(id), channel(1));
+                recorder.record(record).await.unwrap();
+            });
+            let compactor = journal.clone();
+            tasks.spawn(async move { compactor.compact().await.unwrap() });
+        }
+        while tasks.join_next().await.is_some() {}
+        drop(journal);
+
+        let journal = SessionJournal::open(&path, WINDOW).await.unwrap();
+        assert_eq!(journal.len(), 50);
+    }
+}
@@ -0,0 +1,37 @@
+//! Fleet Net server: control connections, voice routing and the services
+//! around them, run by the `fleet-net-server` binary and driven by the
+//! `fleet-net-loadtest` one.
+
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod announcements;
+pub mod auth;
+pub mod channels;
+pub mod cluster;
+#[cfg(any(test, feature = "discord"))]
+pub mod discord;
+pub mod dispatch;
+pub mod events;
+pub mod groups;
+pub mod health;
+pub mod journal;
+#[cfg(any(test, feature = "loadtest"))]
+pub mod loadtest;
+#[cfg(any(test, feature = "mumble"))]
+pub mod mumble;
+pub mod nicknames;
+pub mod presence;
+pub mod propagation;
+pub mod realism;
+pub mod reception;
+pub mod reports;
+pub mod restrictions;
+pub mod roles;
+pub mod rtp;
+pub mod server;
+pub mod sessions;
+pub mod store;
+pub mod subscriptions;
+pub mod templates;
+#[cfg(test)]
+pub mod testing;
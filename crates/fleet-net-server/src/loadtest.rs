@@ -0,0 +1,497 @@
+//! Capacity testing for the server.
+//!
+//! [`run`] boots a [`Server`] on loopback and connects a crowd of simulated
+//! clients to it the way real ones connect: over TLS, authenticated with a
+//! signed token, each joining its channel and registering its voice
+//! address. Clients are grouped into channels and each sends signed voice
+//! frames at a steady rate, which the server fans out to the rest of its
+//! channel. The [`LoadReport`] compares what arrived with what should have,
+//! so a regression in the control or forwarding path shows up as lower
+//! throughput, loss or more CPU for the same load.
+//!
+//! Run it with the `fleet-net-loadtest` binary, built with the `loadtest`
+//! feature. Clients and server share one process, so the CPU figure covers
+//! both; compare reports from the same machine and settings only.
+
+use crate::auth::TokenVerifier;
+use crate::cluster::ClusterMode;
+use crate::server::{SecurityConfig, Server, ServerConfig};
+use crate::store::{ChannelStore, StoreConfig};
+use fleet_net_common::channel::{AudioPolicy, Channel, ChannelType};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::connection::Connection;
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use fleet_net_protocol::qos::QosConfig;
+use fleet_net_protocol::tls::TlsConfig;
+use rustls::pki_types::ServerName;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinSet;
+use tokio_rustls::TlsConnector;
+use tracing::warn;
+
+/// Time allowed after the last packet is sent for forwarded copies to
+/// arrive before they count as lost.
+const DRAIN_TIME: Duration = Duration::from_millis(250);
+
+/// Hostname of the generated certificate the clients check.
+const HOSTNAME: &str = "localhost";
+
+/// Size of each simulated Opus frame, about 32 kbps at 20 ms frames.
+const FRAME_BYTES: usize = 80;
+
+/// Shape of the simulated load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadTestConfig {
+    pub clients: usize,
+    /// Clients sharing each channel; every packet reaches the others.
+    pub clients_per_channel: usize,
+    /// Voice frames each client sends per second; 50 is 20 ms frames.
+    pub packets_per_second: u32,
+    pub duration: Duration,
+}
+
+impl Default for LoadTestConfig {
+    fn default() -> Self {
+        Self {
+            clients: 200,
+            clients_per_channel: 10,
+            packets_per_second: 50,
+            duration: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What a load test measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    pub clients: usize,
+    /// Time from the first packet sent to the end of the drain period.
+    pub elapsed: Duration,
+    pub packets_sent: u64,
+    /// Copies the server sent on, by its own count.
+    pub packets_forwarded: u64,
+    /// Copies that would arrive on a lossless network.
+    pub packets_expected: u64,
+    pub packets_received: u64,
+    /// CPU time the process spent, where the platform reports it.
+    pub cpu_time: Option<Duration>,
+}
+
+impl LoadReport {
+    /// Fraction of expected copies that never arrived.
+    pub fn loss(&self) -> f64 {
+        if self.packets_expected == 0 {
+            return 0.0;
+        }
+        1.0 - self.packets_received as f64 / self.packets_expected as f64
+    }
+
+    /// Copies received per second.
+    pub fn throughput_pps(&self) -> f64 {
+        self.packets_received as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// CPU time as a share of one core over the test.
+    pub fn cpu_percent(&self) -> Option<f64> {
+        self.cpu_time
+            .map(|cpu| cpu.as_secs_f64() / self.elapsed.as_secs_f64() * 100.0)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} clients: sent {} packets, received {}/{} copies ({:.2}% loss) at {:.0} pps",
+            self.clients,
+            self.packets_sent,
+            self.packets_received,
+            self.packets_expected,
+            self.loss() * 100.0,
+            self.throughput_pps(),
+        )?;
+        match self.cpu_percent() {
+            Some(cpu) => write!(f, ", {cpu:.1}% CPU"),
+            None => write!(f, ", CPU not measured"),
+        }
+    }
+}
+
+/// Runs the load described by `config` against a fresh server.
+///
+/// # Errors
+///
+/// Returns a validation error naming the unusable setting in `config`, or
+/// the error of a server that could not be started or a client it refused.
+pub async fn run(config: &LoadTestConfig) -> Result<LoadReport, FleetNetError> {
+    if config.clients == 0 || config.clients > usize::from(u16::MAX) {
+        return Err(out_of_range("clients", 1, u16::MAX.into()));
+    }
+    if config.clients_per_channel < 2 {
+        return Err(out_of_range("clients_per_channel", 2, u16::MAX.into()));
+    }
+    if config.packets_per_second == 0 {
+        return Err(out_of_range("packets_per_second", 1, u32::MAX.into()));
+    }
+
+    // Whoever installs the provider first wins; any other call fails harmlessly
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let certs = tempfile::TempDir::new()?;
+    let (cert_path, key_path) = (certs.path().join("cert.pem"), certs.path().join("key.pem"));
+    let cert = rcgen::generate_simple_self_signed(vec![HOSTNAME.to_string()]).map_err(|e| {
+        FleetNetError::EncryptionError(Cow::Owned(format!("Failed to generate certificate: {e}")))
+    })?;
+    std::fs::write(&cert_path, cert.cert.pem())?;
+    std::fs::write(&key_path, cert.key_pair.serialize_pem())?;
+    let jwt_secret = random_secret()?;
+
+    let mut server = Server::new(ServerConfig {
+        name: "Fleet Net Load Test".to_string(),
+        bind_address: "127.0.0.1:0".to_string(),
+        tls_cert_path: Some(cert_path.clone()),
+        tls_key_path: Some(key_path),
+        cluster: ClusterMode::Standalone,
+        health_bind_address: None,
+        admin_token: None,
+        region: None,
+        ping_bind_address: None,
+        voice_bind_address: "127.0.0.1:0".to_string(),
+        jwt_secret: Some(jwt_secret.clone()),
+        limits: ServerLimits::default(),
+        qos: QosConfig::default(),
+        journal_path: None,
+        motd: None,
+        rtp_exports: Vec::new(),
+        events: None,
+        propagation: None,
+        announcements: None,
+        security: SecurityConfig::default(),
+        store: StoreConfig::default(),
+    })?;
+    let addr = server.start().await?;
+    let voice = SocketAddr::new(
+        addr.ip(),
+        server
+            .status()
+            .voice_port
+            .ok_or(FleetNetError::NetworkError(Cow::Borrowed(
+                "Voice socket not bound",
+            )))?,
+    );
+    for channel_id in channel_ids(config).collect::<BTreeSet<_>>() {
+        server.channels().store().save(channel(channel_id)).await?;
+    }
+    let server = Arc::new(server);
+    let mut tasks = JoinSet::new();
+    tasks.spawn({
+        let server = server.clone();
+        async move {
+            if let Err(e) = server.run().await {
+                warn!("Server stopped: {e}");
+            }
+        }
+    });
+
+    let tls =
+        TlsConfig::new_client(&cert_path)?
+            .client_config
+            .ok_or(FleetNetError::EncryptionError(Cow::Borrowed(
+                "Missing TLS client config",
+            )))?;
+    let connector = TlsConnector::from(tls);
+    let tokens = TokenVerifier::new(jwt_secret.as_bytes());
+    // Ids count from 1, as 0 is reserved
+    let mut clients = Vec::with_capacity(config.clients);
+    for (index, channel_id) in (0..config.clients).zip(channel_ids(config)) {
+        let user_id = UserId::new(index as u16 + 1).expect("Index below u16::MAX");
+        let (socket, key) = connect(
+            &connector, addr, voice, &tokens, user_id, channel_id, &mut tasks,
+        )
+        .await?;
+        clients.push((user_id, channel_id, Arc::new(socket), key));
+    }
+
+    let received = Arc::new(AtomicU64::new(0));
+    for (_, _, socket, _) in &clients {
+        tasks.spawn(count_received(socket.clone(), received.clone()));
+    }
+
+    let mut packets_expected = 0;
+    let cpu_before = process_cpu_time();
+    let started = Instant::now();
+    let packets = (config.duration.as_secs_f64() * f64::from(config.packets_per_second)) as u64;
+    let mut senders = JoinSet::new();
+    for members in clients.chunks(config.clients_per_channel) {
+        for (user_id, channel_id, socket, key) in members {
+            senders.spawn(send_voice(
+                socket.clone(),
+                voice,
+                *user_id,
+                *channel_id,
+                key.clone(),
+                config.packets_per_second,
+                packets,
+            ));
+        }
+        packets_expected += packets * members.len() as u64 * (members.len() as u64 - 1);
+    }
+
+    while let Some(result) = senders.join_next().await {
+        result.map_err(|e| {
+            FleetNetError::NetworkError(Cow::Owned(format!("Sender failed: {e}")))
+        })??;
+    }
+    tokio::time::sleep(DRAIN_TIME).await;
+    let elapsed = started.elapsed();
+    let cpu_time = process_cpu_time()
+        .zip(cpu_before)
+        .map(|(after, before)| after.saturating_sub(before));
+    tasks.abort_all();
+
+    Ok(LoadReport {
+        clients: config.clients,
+        elapsed,
+        packets_sent: packets * config.clients as u64,
+        packets_forwarded: server.subscriptions().packets_forwarded(),
+        packets_expected,
+        packets_received: received.load(Ordering::Relaxed),
+        cpu_time,
+    })
+}
+
+/// Connects `user_id` over TLS, signs in, joins `channel_id` and registers
+/// the returned voice socket, returned with the session key to sign voice
+/// with. Messages from the server are drained in `tasks` for as long as the
+/// test runs.
+async fn connect(
+    connector: &TlsConnector,
+    addr: SocketAddr,
+    voice: SocketAddr,
+    tokens: &TokenVerifier,
+    user_id: UserId,
+    channel_id: ChannelId,
+    tasks: &mut JoinSet<()>,
+) -> Result<(UdpSocket, HmacKey), FleetNetError> {
+    let stream = TcpStream::connect(addr).await?;
+    let domain = ServerName::try_from(HOSTNAME).expect("Valid hostname");
+    let mut conn = Connection::new(connector.connect(domain, stream).await?);
+    conn.read_message().await?;
+
+    conn.write_message(&ControlMessage::Authenticate {
+        token: tokens.issue(user_id)?,
+        client_version: Cow::Borrowed(env!("CARGO_PKG_VERSION")),
+        resume_token: None,
+    })
+    .await?;
+    conn.write_message(&ControlMessage::JoinChannel { channel_id })
+        .await?;
+    let mut key = None;
+    loop {
+        match conn.read_message().await? {
+            ControlMessage::AuthResponse {
+                success: true,
+                udp_key,
+                ..
+            } => key = udp_key,
+            ControlMessage::AuthResponse {
+                success: false,
+                error,
+                ..
+            } => {
+                return Err(FleetNetError::AuthError(Cow::Owned(format!(
+                    "User {user_id} was refused: {}",
+                    error.unwrap_or_default()
+                ))))
+            }
+            ControlMessage::Error { message, .. } => {
+                return Err(FleetNetError::NetworkError(Cow::Owned(format!(
+                    "User {user_id} could not join channel {channel_id}: {message}"
+                ))))
+            }
+            ControlMessage::ChannelJoined { .. } => break,
+            _ => {}
+        }
+    }
+    tasks.spawn(async move { while conn.read_message().await.is_ok() {} });
+    let key = key.ok_or(FleetNetError::AuthError(Cow::Borrowed(
+        "Server issued no session key",
+    )))?;
+
+    // A bare header registers the address voice is sent to
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let registration = AudioPacket::new_signed(header(channel_id, user_id, 0), Vec::new(), &key);
+    socket.send_to(&registration.to_bytes(), voice).await?;
+    Ok((socket, key))
+}
+
+/// A voice channel anyone may join.
+fn channel(id: ChannelId) -> Channel {
+    Channel {
+        id,
+        name: format!("Load {id}"),
+        description: None,
+        channel_type: ChannelType::Voice,
+        role_permissions: HashMap::new(),
+        position: 0,
+        parent_id: None,
+        topic: None,
+        icon: None,
+        metadata: HashMap::new(),
+        radio: None,
+        audio_policy: AudioPolicy::default(),
+    }
+}
+
+fn header(channel_id: ChannelId, user_id: UserId, sequence: u16) -> PacketHeader {
+    PacketHeader {
+        channel_id,
+        user_id,
+        sequence,
+        timestamp: u32::from(sequence) * 20,
+        signal_strength: u8::MAX,
+        frame_duration: 20,
+        dtx: false,
+        frames: 1,
+        padded: false,
+        audio_length: 0,
+        hmac_prefix: 0,
+    }
+}
+
+/// A secret only this run knows, so nothing else on the host can sign in.
+fn random_secret() -> Result<String, FleetNetError> {
+    let mut bytes = [0u8; 32];
+    rustls::crypto::ring::default_provider()
+        .secure_random
+        .fill(&mut bytes)
+        .map_err(|_| {
+            FleetNetError::EncryptionError(Cow::Borrowed("Failed to generate a secret"))
+        })?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+fn out_of_range(field: &'static str, min: i64, max: i64) -> FleetNetError {
+    FleetNetError::invalid_field(field, Constraint::OutOfRange { min, max })
+}
+
+/// The channel of each client in turn, `clients_per_channel` at a time.
+fn channel_ids(config: &LoadTestConfig) -> impl Iterator<Item = ChannelId> + '_ {
+    (0..config.clients).map(|index| {
+        let channel = (index / config.clients_per_channel) as u16 + 1;
+        ChannelId::new(channel).expect("Channel ids start at 1")
+    })
+}
+
+/// Sends `packets` voice frames at `packets_per_second`, signed with `key`.
+async fn send_voice(
+    socket: Arc<UdpSocket>,
+    server: SocketAddr,
+    user_id: UserId,
+    channel_id: ChannelId,
+    key: HmacKey,
+    packets_per_second: u32,
+    packets: u64,
+) -> Result<(), FleetNetError> {
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / packets_per_second);
+    for sequence in 0..packets {
+        interval.tick().await;
+        let sequence = sequence as u16;
+        let payload = vec![sequence as u8; FRAME_BYTES];
+        let packet = AudioPacket::new_signed(header(channel_id, user_id, sequence), payload, &key);
+        socket.send_to(&packet.to_bytes(), server).await?;
+    }
+    Ok(())
+}
+
+async fn count_received(socket: Arc<UdpSocket>, received: Arc<AtomicU64>) {
+    let mut buf = [0u8; 1_500];
+    while socket.recv_from(&mut buf).await.is_ok() {
+        received.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// User and system CPU time of this process so far, on Linux.
+fn process_cpu_time() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // Fields after the parenthesized command name, which may hold spaces
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    // utime and stime are fields 14 and 15 of the whole line, in clock ticks
+    let user: u64 = fields.get(11)?.parse().ok()?;
+    let system: u64 = fields.get(12)?.parse().ok()?;
+    Some(Duration::from_millis(
+        (user + system) * 1_000 / clock_ticks_per_sec()?,
+    ))
+}
+
+/// Kernel clock ticks per second, the unit of CPU times in `/proc`.
+#[cfg(unix)]
+fn clock_ticks_per_sec() -> Option<u64> {
+    // SAFETY: sysconf only reads a system constant
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    u64::try_from(ticks).ok().filter(|&ticks| ticks > 0)
+}
+
+#[cfg(not(unix))]
+fn clock_ticks_per_sec() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_is_fanned_out_to_each_channel() {
+        let config = LoadTestConfig {
+            clients: 7,
+            clients_per_channel: 3,
+            packets_per_second: 50,
+            duration: Duration::from_millis(200),
+        };
+        let report = run(&config).await.unwrap();
+
+        assert_eq!(report.packets_sent, 7 * 10);
+        // Two full channels of 3 and one of 1, who has nobody to reach
+        assert_eq!(report.packets_expected, 10 * (3 * 2 + 3 * 2));
+        assert_eq!(report.packets_forwarded, report.packets_expected);
+        assert!(report.loss() < 0.05, "{report}");
+        if cfg!(target_os = "linux") {
+            assert!(report.cpu_percent().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unusable_configs_are_refused() {
+        for config in [
+            LoadTestConfig {
+                clients: 0,
+                ..LoadTestConfig::default()
+            },
+            LoadTestConfig {
+                clients_per_channel: 1,
+                ..LoadTestConfig::default()
+            },
+            LoadTestConfig {
+                packets_per_second: 0,
+                ..LoadTestConfig::default()
+            },
+        ] {
+            assert!(matches!(
+                run(&config).await,
+                Err(FleetNetError::ValidationError(_))
+            ));
+        }
+    }
+}
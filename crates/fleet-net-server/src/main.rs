@@ -1,7 +1,128 @@
-pub mod server;
+//! Runs a standalone server until interrupted.
+//!
+//! ```text
+//! fleet-net-server --bind [::]:7000 --voice-bind [::]:7001 --cert server.crt --key server.key
+//!     [--name "Fleet Net"] [--region eu-west] [--health-bind 127.0.0.1:8080]
+//!     [--ping-bind [::]:7002] [--journal sessions.journal]
+//! ```
+//!
+//! Secrets are read from the environment rather than the command line, where
+//! other users could see them: `FLEET_NET_JWT_SECRET` signs client access
+//! tokens, and every client is refused without it, and `FLEET_NET_ADMIN_TOKEN`
+//! enables the admin API on the health address. Everything else takes its
+//! default; clustering, event publishing and the other optional features are
+//! only available to programs building a [`ServerConfig`] themselves.
+//!
+//! Under systemd, run it as a `Type=notify` unit: readiness is signalled once
+//! the listeners are up, and with `WatchdogSec=` set the watchdog is pinged
+//! only while the health check passes.
+
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_protocol::qos::QosConfig;
+use fleet_net_server::cluster::ClusterMode;
+use fleet_net_server::health;
+use fleet_net_server::server::{SecurityConfig, Server, ServerConfig};
+use fleet_net_server::store::StoreConfig;
+use std::process::ExitCode;
+use tracing::{error, info, warn};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // Initialize tracing for logging
     fleet_net_common::logging::init_tracing();
+    // rustls needs its crypto provider chosen before the first TLS config
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let config = match parse_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut server = match Server::new(config) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to create server: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = server.start().await {
+        error!("Failed to start server: {e}");
+        return ExitCode::FAILURE;
+    }
+
+    let code = tokio::select! {
+        result = server.run() => {
+            if let Err(e) = result {
+                error!("Server stopped: {e}");
+            }
+            ExitCode::FAILURE
+        }
+        _ = tokio::signal::ctrl_c() => {
+            info!("Shutting down");
+            ExitCode::SUCCESS
+        }
+    };
+    if let Err(e) = health::systemd::notify_stopping() {
+        warn!("Failed to notify systemd of shutdown: {e}");
+    }
+    code
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<ServerConfig, String> {
+    let mut config = ServerConfig {
+        name: "Fleet Net".to_string(),
+        bind_address: String::new(),
+        tls_cert_path: None,
+        tls_key_path: None,
+        cluster: ClusterMode::Standalone,
+        health_bind_address: None,
+        admin_token: std::env::var("FLEET_NET_ADMIN_TOKEN").ok(),
+        region: None,
+        ping_bind_address: None,
+        voice_bind_address: String::new(),
+        jwt_secret: std::env::var("FLEET_NET_JWT_SECRET").ok(),
+        limits: ServerLimits::default(),
+        qos: QosConfig::default(),
+        journal_path: None,
+        motd: None,
+        rtp_exports: Vec::new(),
+        events: None,
+        propagation: None,
+        announcements: None,
+        security: SecurityConfig::default(),
+        store: StoreConfig::default(),
+    };
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {flag}"))?;
+        match flag.as_str() {
+            "--name" => config.name = value,
+            "--bind" => config.bind_address = value,
+            "--voice-bind" => config.voice_bind_address = value,
+            "--cert" => config.tls_cert_path = Some(value.into()),
+            "--key" => config.tls_key_path = Some(value.into()),
+            "--region" => config.region = Some(value),
+            "--health-bind" => config.health_bind_address = Some(value),
+            "--ping-bind" => config.ping_bind_address = Some(value),
+            "--journal" => config.journal_path = Some(value.into()),
+            _ => return Err(format!("Unknown option {flag}")),
+        }
+    }
+    for (flag, missing) in [
+        ("--bind", config.bind_address.is_empty()),
+        ("--voice-bind", config.voice_bind_address.is_empty()),
+        ("--cert", config.tls_cert_path.is_none()),
+        ("--key", config.tls_key_path.is_none()),
+    ] {
+        if missing {
+            return Err(format!("Missing required option {flag}"));
+        }
+    }
+    if config.jwt_secret.is_none() {
+        warn!("FLEET_NET_JWT_SECRET is not set; every client will be refused");
+    }
+    Ok(config)
 }
@@ -0,0 +1,1420 @@
+//! Gateway for Mumble clients.
+//!
+//! Communities moving off Murmur can keep their Mumble clients while they
+//! switch over: [`MumbleGateway`] speaks the Mumble client protocol on its
+//! own TLS port and bridges each Mumble user into Fleet Net as a user of its
+//! own. Fleet Net channels are shown as Mumble channels under a root named
+//! after the server, and joining one makes the Mumble user a listener of it
+//! in the [`SubscriptionRegistry`], so voice flows both ways through the
+//! normal router.
+//!
+//! Both sides carry 48 kHz mono Opus, so audio is never decoded. Voice is
+//! translated between Mumble's tunnelled voice packets and signed
+//! [`AudioPacket`]s: the frame duration is read from the Opus packet itself
+//! and Mumble's 10 ms sequence numbers become Fleet Net timestamps and back.
+//! Clients still on CELT or Speex are refused, as are whispers, text
+//! messages and channel editing.
+//!
+//! The gateway announces itself as Mumble 1.4, so clients use the tunnelled
+//! legacy voice format over TCP and never set up UDP.
+
+use crate::channels::ChannelRegistry;
+use crate::cluster::AbortOnDrop;
+use crate::nicknames::NicknameRegistry;
+use crate::rtp::opus_packet_duration_us;
+use crate::server::{Server, DEFAULT_EVERYONE_PERMISSIONS};
+use crate::store::{ChannelStore, NicknameStore};
+use crate::subscriptions::SubscriptionRegistry;
+use dashmap::DashMap;
+use fleet_net_common::channel::{Channel, ChannelType};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::user::User;
+use fleet_net_protocol::cluster::RelaySubscriber;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::key_manager::KeyManager;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use prost::Message as _;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+/// Mumble version the gateway announces, 1.4.0. Later versions would make
+/// clients switch to the protobuf voice format.
+const MUMBLE_VERSION: u32 = 1 << 16 | 4 << 8;
+
+/// Largest control message accepted from a client. Murmur allows more, but
+/// only for avatars and comments, which the gateway ignores.
+const MAX_MESSAGE_LEN: usize = 128 * 1024;
+
+/// How long a client may take to authenticate after connecting.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mumble's root channel, which every other channel hangs below.
+const ROOT_CHANNEL: u32 = 0;
+
+/// Mumble counts voice in 10 ms frames.
+const MUMBLE_FRAME_MS: u32 = 10;
+
+/// Mumble's id for the CELT 0.7 codec, announced because clients expect one.
+const CELT_ALPHA_VERSION: i32 = -2_147_483_637;
+
+/// Mumble permission bits granted in channels, see `ChannelACL.h`.
+mod acl {
+    pub const TRAVERSE: u64 = 0x2;
+    pub const ENTER: u64 = 0x4;
+    pub const SPEAK: u64 = 0x8;
+    pub const LISTEN: u64 = 0x800;
+}
+
+/// The subset of `Mumble.proto` the gateway reads and writes.
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Version {
+        #[prost(uint32, optional, tag = "1")]
+        pub version_v1: Option<u32>,
+        #[prost(string, optional, tag = "2")]
+        pub release: Option<String>,
+        #[prost(string, optional, tag = "3")]
+        pub os: Option<String>,
+        #[prost(string, optional, tag = "4")]
+        pub os_version: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Authenticate {
+        #[prost(string, optional, tag = "1")]
+        pub username: Option<String>,
+        #[prost(string, optional, tag = "2")]
+        pub password: Option<String>,
+        #[prost(string, repeated, tag = "3")]
+        pub tokens: Vec<String>,
+        #[prost(int32, repeated, packed = "false", tag = "4")]
+        pub celt_versions: Vec<i32>,
+        #[prost(bool, optional, tag = "5")]
+        pub opus: Option<bool>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Ping {
+        #[prost(uint64, optional, tag = "1")]
+        pub timestamp: Option<u64>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum RejectType {
+        None = 0,
+        WrongVersion = 1,
+        InvalidUsername = 2,
+        WrongUserPw = 3,
+        WrongServerPw = 4,
+        UsernameInUse = 5,
+        ServerFull = 6,
+        NoCertificate = 7,
+        AuthenticatorFail = 8,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Reject {
+        #[prost(enumeration = "RejectType", optional, tag = "1")]
+        pub r#type: Option<i32>,
+        #[prost(string, optional, tag = "2")]
+        pub reason: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ServerSync {
+        #[prost(uint32, optional, tag = "1")]
+        pub session: Option<u32>,
+        #[prost(uint32, optional, tag = "2")]
+        pub max_bandwidth: Option<u32>,
+        #[prost(string, optional, tag = "3")]
+        pub welcome_text: Option<String>,
+        #[prost(uint64, optional, tag = "4")]
+        pub permissions: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChannelState {
+        #[prost(uint32, optional, tag = "1")]
+        pub channel_id: Option<u32>,
+        #[prost(uint32, optional, tag = "2")]
+        pub parent: Option<u32>,
+        #[prost(string, optional, tag = "3")]
+        pub name: Option<String>,
+        #[prost(string, optional, tag = "5")]
+        pub description: Option<String>,
+        #[prost(int32, optional, tag = "9")]
+        pub position: Option<i32>,
+        #[prost(bool, optional, tag = "12")]
+        pub is_enter_restricted: Option<bool>,
+        #[prost(bool, optional, tag = "13")]
+        pub can_enter: Option<bool>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UserRemove {
+        #[prost(uint32, optional, tag = "1")]
+        pub session: Option<u32>,
+        #[prost(string, optional, tag = "3")]
+        pub reason: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UserState {
+        #[prost(uint32, optional, tag = "1")]
+        pub session: Option<u32>,
+        #[prost(uint32, optional, tag = "2")]
+        pub actor: Option<u32>,
+        #[prost(string, optional, tag = "3")]
+        pub name: Option<String>,
+        #[prost(uint32, optional, tag = "5")]
+        pub channel_id: Option<u32>,
+        #[prost(bool, optional, tag = "9")]
+        pub self_mute: Option<bool>,
+        #[prost(bool, optional, tag = "10")]
+        pub self_deaf: Option<bool>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum DenyType {
+        Text = 0,
+        Permission = 1,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PermissionDenied {
+        #[prost(uint32, optional, tag = "2")]
+        pub channel_id: Option<u32>,
+        #[prost(uint32, optional, tag = "3")]
+        pub session: Option<u32>,
+        #[prost(string, optional, tag = "4")]
+        pub reason: Option<String>,
+        #[prost(enumeration = "DenyType", optional, tag = "5")]
+        pub r#type: Option<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CodecVersion {
+        #[prost(int32, required, tag = "1")]
+        pub alpha: i32,
+        #[prost(int32, required, tag = "2")]
+        pub beta: i32,
+        #[prost(bool, required, tag = "3")]
+        pub prefer_alpha: bool,
+        #[prost(bool, optional, tag = "4")]
+        pub opus: Option<bool>,
+    }
+}
+
+/// A message of the Mumble control protocol.
+///
+/// Types the gateway has no use for are kept as [`MumbleMessage::Other`] so
+/// they can be skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MumbleMessage {
+    Version(proto::Version),
+    /// A voice packet tunnelled over the control connection.
+    UdpTunnel(Vec<u8>),
+    Authenticate(proto::Authenticate),
+    Ping(proto::Ping),
+    Reject(proto::Reject),
+    ServerSync(proto::ServerSync),
+    ChannelState(proto::ChannelState),
+    UserRemove(proto::UserRemove),
+    UserState(proto::UserState),
+    PermissionDenied(proto::PermissionDenied),
+    CodecVersion(proto::CodecVersion),
+    Other {
+        kind: u16,
+        payload: Vec<u8>,
+    },
+}
+
+impl MumbleMessage {
+    fn kind(&self) -> u16 {
+        match self {
+            Self::Version(_) => 0,
+            Self::UdpTunnel(_) => 1,
+            Self::Authenticate(_) => 2,
+            Self::Ping(_) => 3,
+            Self::Reject(_) => 4,
+            Self::ServerSync(_) => 5,
+            Self::ChannelState(_) => 7,
+            Self::UserRemove(_) => 8,
+            Self::UserState(_) => 9,
+            Self::PermissionDenied(_) => 12,
+            Self::CodecVersion(_) => 21,
+            Self::Other { kind, .. } => *kind,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Self::Version(m) => m.encode_to_vec(),
+            Self::UdpTunnel(voice) => voice.clone(),
+            Self::Authenticate(m) => m.encode_to_vec(),
+            Self::Ping(m) => m.encode_to_vec(),
+            Self::Reject(m) => m.encode_to_vec(),
+            Self::ServerSync(m) => m.encode_to_vec(),
+            Self::ChannelState(m) => m.encode_to_vec(),
+            Self::UserRemove(m) => m.encode_to_vec(),
+            Self::UserState(m) => m.encode_to_vec(),
+            Self::PermissionDenied(m) => m.encode_to_vec(),
+            Self::CodecVersion(m) => m.encode_to_vec(),
+            Self::Other { payload, .. } => payload.clone(),
+        }
+    }
+
+    /// The message behind its 2-byte type and 4-byte length prefix.
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = self.payload();
+        let mut frame = Vec::with_capacity(6 + payload.len());
+        frame.extend_from_slice(&self.kind().to_be_bytes());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    /// Decodes the payload of a message of type `kind`.
+    pub fn decode(kind: u16, payload: Vec<u8>) -> Result<Self, FleetNetError> {
+        let message = match kind {
+            0 => Self::Version(proto::Version::decode(&*payload).map_err(malformed)?),
+            1 => Self::UdpTunnel(payload),
+            2 => Self::Authenticate(proto::Authenticate::decode(&*payload).map_err(malformed)?),
+            3 => Self::Ping(proto::Ping::decode(&*payload).map_err(malformed)?),
+            4 => Self::Reject(proto::Reject::decode(&*payload).map_err(malformed)?),
+            5 => Self::ServerSync(proto::ServerSync::decode(&*payload).map_err(malformed)?),
+            7 => Self::ChannelState(proto::ChannelState::decode(&*payload).map_err(malformed)?),
+            8 => Self::UserRemove(proto::UserRemove::decode(&*payload).map_err(malformed)?),
+            9 => Self::UserState(proto::UserState::decode(&*payload).map_err(malformed)?),
+            12 => Self::PermissionDenied(
+                proto::PermissionDenied::decode(&*payload).map_err(malformed)?,
+            ),
+            21 => Self::CodecVersion(proto::CodecVersion::decode(&*payload).map_err(malformed)?),
+            kind => Self::Other { kind, payload },
+        };
+        Ok(message)
+    }
+
+    /// Reads the next message from a Mumble control stream.
+    pub async fn read_from<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self, FleetNetError> {
+        let mut prefix = [0u8; 6];
+        reader.read_exact(&mut prefix).await?;
+        let kind = u16::from_be_bytes([prefix[0], prefix[1]]);
+        let length = u32::from_be_bytes([prefix[2], prefix[3], prefix[4], prefix[5]]) as usize;
+        // Refuse before allocating, so a bogus length cannot exhaust memory
+        if length > MAX_MESSAGE_LEN {
+            return Err(FleetNetError::PacketError(Cow::Borrowed(
+                "Mumble message exceeds the maximum size",
+            )));
+        }
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload).await?;
+        Self::decode(kind, payload)
+    }
+
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), FleetNetError> {
+        writer.write_all(&self.encode()).await?;
+        Ok(())
+    }
+}
+
+fn malformed(e: prost::DecodeError) -> FleetNetError {
+    FleetNetError::PacketError(Cow::Owned(format!("Malformed Mumble message: {e}")))
+}
+
+/// Appends `value` in Mumble's variable-length integer encoding.
+pub fn put_varint(buf: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0x7F => buf.push(value as u8),
+        0x80..=0x3FFF => buf.extend_from_slice(&[0x80 | (value >> 8) as u8, value as u8]),
+        0x4000..=0x1F_FFFF => {
+            buf.extend_from_slice(&[0xC0 | (value >> 16) as u8, (value >> 8) as u8, value as u8])
+        }
+        0x20_0000..=0xFFF_FFFF => buf.extend_from_slice(&[
+            0xE0 | (value >> 24) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ]),
+        0x1000_0000..=0xFFFF_FFFF => {
+            buf.push(0xF0);
+            buf.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(0xF4);
+            buf.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+/// Reads a Mumble variable-length integer off the front of `buf`.
+///
+/// Returns `None` if `buf` ends early or holds a negative number, which no
+/// field the gateway reads may contain.
+pub fn take_varint(buf: &mut &[u8]) -> Option<u64> {
+    let (&first, rest) = buf.split_first()?;
+    let (extra, value) = match first {
+        0x00..=0x7F => (0, u64::from(first)),
+        0x80..=0xBF => (1, u64::from(first & 0x3F)),
+        0xC0..=0xDF => (2, u64::from(first & 0x1F)),
+        0xE0..=0xEF => (3, u64::from(first & 0x0F)),
+        0xF0..=0xF3 => (4, 0),
+        0xF4..=0xF7 => (8, 0),
+        _ => return None,
+    };
+    let bytes = rest.get(..extra)?;
+    let value = bytes
+        .iter()
+        .fold(value, |value, &byte| value << 8 | u64::from(byte));
+    *buf = &rest[extra..];
+    Some(value)
+}
+
+/// Type of a legacy voice packet, the top three bits of its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VoiceKind {
+    Ping,
+    Opus,
+    /// CELT or Speex, which would need real transcoding.
+    Unsupported,
+}
+
+impl VoiceKind {
+    const PING: u8 = 1;
+    const OPUS: u8 = 4;
+
+    fn of(header: u8) -> Self {
+        match header >> 5 {
+            Self::PING => Self::Ping,
+            Self::OPUS => Self::Opus,
+            _ => Self::Unsupported,
+        }
+    }
+}
+
+/// An Opus voice packet in Mumble's legacy format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MumbleVoice {
+    /// 0 for normal talking, 1-30 for whisper targets.
+    pub target: u8,
+    /// Position of the first frame, in 10 ms frames.
+    pub sequence: u64,
+    pub opus: Vec<u8>,
+    /// Set on the last packet of a transmission.
+    pub terminator: bool,
+}
+
+impl MumbleVoice {
+    /// Bit of the Opus length field marking the last packet.
+    const TERMINATOR: u64 = 0x2000;
+
+    /// Parses a voice packet sent by a client, which carries no session.
+    ///
+    /// Returns `None` for anything but Opus voice, including voice pings.
+    pub fn from_client(data: &[u8]) -> Option<Self> {
+        let (&header, mut rest) = data.split_first()?;
+        if VoiceKind::of(header) != VoiceKind::Opus {
+            return None;
+        }
+        let sequence = take_varint(&mut rest)?;
+        let length = take_varint(&mut rest)?;
+        let size = (length & !Self::TERMINATOR) as usize;
+        // Positional audio may follow the frame; it is dropped
+        let opus = rest.get(..size)?.to_vec();
+        Some(Self {
+            target: header & 0x1F,
+            sequence,
+            opus,
+            terminator: length & Self::TERMINATOR != 0,
+        })
+    }
+
+    /// Encodes the packet as the server relays it, spoken by `session`.
+    pub fn to_client(&self, session: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8 + self.opus.len());
+        data.push(VoiceKind::OPUS << 5 | self.target);
+        put_varint(&mut data, session.into());
+        put_varint(&mut data, self.sequence);
+        let terminator = if self.terminator { Self::TERMINATOR } else { 0 };
+        put_varint(&mut data, self.opus.len() as u64 | terminator);
+        data.extend_from_slice(&self.opus);
+        data
+    }
+}
+
+/// The timestamp and frame duration of a Mumble voice packet in Fleet Net's
+/// terms.
+///
+/// Returns `None` if the Opus packet is unreadable or its duration does not
+/// fit Fleet Net's whole-millisecond frames.
+fn to_fleet_timing(voice: &MumbleVoice) -> Option<(u32, u8)> {
+    let duration_us = opus_packet_duration_us(&voice.opus)?;
+    if duration_us == 0 || duration_us % 1_000 != 0 {
+        return None;
+    }
+    let frame_duration = u8::try_from(duration_us / 1_000).ok()?;
+    let timestamp = (voice.sequence as u32).wrapping_mul(MUMBLE_FRAME_MS);
+    Some((timestamp, frame_duration))
+}
+
+/// Converts a Fleet Net packet into a Mumble voice packet.
+fn to_mumble_voice(packet: AudioPacket) -> MumbleVoice {
+    MumbleVoice {
+        target: 0,
+        sequence: u64::from(packet.header.timestamp / MUMBLE_FRAME_MS),
+        opus: packet.opus_payload,
+        terminator: false,
+    }
+}
+
+/// Mumble permission bits for what `permissions` allows in a channel.
+fn mumble_permissions(permissions: Permissions) -> u64 {
+    let mut bits = acl::TRAVERSE;
+    if permissions.contains(Permissions::CONNECT) {
+        bits |= acl::ENTER;
+    }
+    if permissions.contains(Permissions::SPEAK) {
+        bits |= acl::SPEAK;
+    }
+    if permissions.contains(Permissions::LISTEN) {
+        bits |= acl::LISTEN;
+    }
+    bits
+}
+
+/// Settings of a [`MumbleGateway`].
+#[derive(Debug, Clone)]
+pub struct MumbleConfig {
+    /// Fleet Net voice socket that bridged users transmit to.
+    pub voice_address: SocketAddr,
+    /// Address the per-user voice sockets bind to; the router must reach it.
+    pub voice_bind_ip: IpAddr,
+    /// Secret the bridged users' packet signing keys are derived from.
+    pub secret: Vec<u8>,
+    /// Fleet Net user ids handed out to Mumble users, one per connection.
+    /// They must not clash with ids of native users.
+    pub user_ids: RangeInclusive<u16>,
+    /// Name of the root channel.
+    pub root_name: String,
+    /// Password Mumble users must give; none when `None`.
+    pub password: Option<String>,
+    /// Shown by clients after connecting.
+    pub welcome_text: Option<String>,
+    /// Bitrate limit clients are told about, in bits per second.
+    pub max_bandwidth: u32,
+    /// What bridged users may do, as native users holding only `@everyone`.
+    pub permissions: Permissions,
+}
+
+impl MumbleConfig {
+    /// A gateway on the same host as the voice router at `voice_address`,
+    /// using the top 4096 user ids.
+    pub fn new(voice_address: SocketAddr, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            voice_address,
+            voice_bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            secret: secret.into(),
+            user_ids: 0xF000..=u16::MAX,
+            root_name: "Fleet Net".to_string(),
+            password: None,
+            welcome_text: None,
+            max_bandwidth: 72_000,
+            permissions: DEFAULT_EVERYONE_PERMISSIONS,
+        }
+    }
+}
+
+/// A connected Mumble user.
+struct BridgedUser {
+    name: String,
+    channel_id: Option<ChannelId>,
+    self_mute: bool,
+    self_deaf: bool,
+    /// Messages for this user's client.
+    outbound: mpsc::UnboundedSender<MumbleMessage>,
+}
+
+impl BridgedUser {
+    fn state(&self, user_id: UserId) -> proto::UserState {
+        proto::UserState {
+            session: Some(user_id.get().into()),
+            name: Some(self.name.clone()),
+            channel_id: Some(self.channel_id.map_or(ROOT_CHANNEL, |id| id.get().into())),
+            self_mute: Some(self.self_mute),
+            self_deaf: Some(self.self_deaf),
+            ..Default::default()
+        }
+    }
+}
+
+/// Bridges Mumble clients into the server's channels.
+pub struct MumbleGateway {
+    config: MumbleConfig,
+    limits: ServerLimits,
+    channels: Arc<ChannelRegistry>,
+    subscriptions: Arc<SubscriptionRegistry>,
+    nicknames: Arc<NicknameRegistry>,
+    users: DashMap<UserId, BridgedUser>,
+    connections: AtomicU64,
+}
+
+impl MumbleGateway {
+    /// A gateway into the channels and voice routing of `server`.
+    pub fn new(config: MumbleConfig, server: &Server) -> Self {
+        Self {
+            config,
+            limits: server.status().limits,
+            channels: server.channels().clone(),
+            subscriptions: server.subscriptions().clone(),
+            nicknames: server.nicknames().clone(),
+            users: DashMap::new(),
+            connections: AtomicU64::new(0),
+        }
+    }
+
+    /// Connected Mumble users.
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Accepts Mumble clients over TLS until the listener fails.
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+    ) -> Result<(), FleetNetError> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let gateway = self.clone();
+            let acceptor = acceptor.clone();
+
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(tls_stream) => {
+                        if let Err(e) = gateway.handle(tls_stream).await {
+                            debug!("Mumble connection from {addr} ended: {e}");
+                        }
+                    }
+                    Err(e) => warn!("Mumble TLS handshake from {addr} failed: {e}"),
+                }
+            });
+        }
+    }
+
+    /// Runs one Mumble client connection until it closes.
+    ///
+    /// The client is refused with a `Reject` if it cannot decode Opus, gives
+    /// the wrong password, picks a name that is taken or invalid, or no user
+    /// id is left for it.
+    pub async fn handle<S>(self: Arc<Self>, stream: S) -> Result<(), FleetNetError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        MumbleMessage::Version(proto::Version {
+            version_v1: Some(MUMBLE_VERSION),
+            release: Some(format!("Fleet Net {}", env!("CARGO_PKG_VERSION"))),
+            ..Default::default()
+        })
+        .write_to(&mut writer)
+        .await?;
+
+        let auth = tokio::time::timeout(AUTH_TIMEOUT, read_authenticate(&mut reader))
+            .await
+            .map_err(|_| {
+                FleetNetError::NetworkError(Cow::Borrowed("Mumble client did not authenticate"))
+            })??;
+        let (outbound, mut outbound_rx) = mpsc::unbounded_channel();
+        let user_id = match self.admit(&auth, outbound.clone()) {
+            Ok(user_id) => user_id,
+            Err(reject) => {
+                MumbleMessage::Reject(reject).write_to(&mut writer).await?;
+                return Ok(());
+            }
+        };
+        let _user = RemoveOnDrop {
+            gateway: &self,
+            user_id,
+        };
+
+        let voice = Arc::new(dual_stack::bind_udp((self.config.voice_bind_ip, 0)).await?);
+        let nonce = self
+            .connections
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes();
+        let key = KeyManager::generate_session_key(user_id, &self.config.secret, &nonce);
+        info!("Mumble user {user_id} connected as {:?}", auth.username);
+
+        self.send_welcome(user_id, &outbound).await?;
+        let _writer_task = AbortOnDrop(tokio::spawn(async move {
+            while let Some(message) = outbound_rx.recv().await {
+                if let Err(e) = message.write_to(&mut writer).await {
+                    debug!("Stopped writing to Mumble client: {e}");
+                    break;
+                }
+            }
+        }));
+        let _voice_task = AbortOnDrop(tokio::spawn(
+            self.clone().relay_voice(user_id, voice.clone()),
+        ));
+        let _channel_task = AbortOnDrop(tokio::spawn(relay_channel_changes(
+            self.channels.subscribe(),
+            outbound.clone(),
+        )));
+
+        let mut sequence = 0u16;
+        let voice_address = voice.local_addr()?;
+        let router = dual_stack::reachable_from(voice_address, self.config.voice_address);
+        loop {
+            match MumbleMessage::read_from(&mut reader).await? {
+                MumbleMessage::Ping(ping) => {
+                    let _ = outbound.send(MumbleMessage::Ping(ping));
+                }
+                MumbleMessage::UserState(state) => {
+                    self.update_user(user_id, voice_address, state).await?;
+                }
+                MumbleMessage::UdpTunnel(data) => {
+                    if VoiceKind::of(data.first().copied().unwrap_or_default()) == VoiceKind::Ping {
+                        let _ = outbound.send(MumbleMessage::UdpTunnel(data));
+                    } else if let Some(packet) =
+                        self.to_fleet_packet(user_id, &data, sequence, &key)
+                    {
+                        sequence = sequence.wrapping_add(1);
+                        voice.send_to(&packet.to_bytes(), router).await?;
+                    }
+                }
+                other => debug!("Ignoring Mumble message of type {}", other.kind()),
+            }
+        }
+    }
+
+    /// Reserves a user id for an authenticating client.
+    fn admit(
+        &self,
+        auth: &proto::Authenticate,
+        outbound: mpsc::UnboundedSender<MumbleMessage>,
+    ) -> Result<UserId, proto::Reject> {
+        if auth.opus != Some(true) {
+            return Err(reject(
+                proto::RejectType::WrongVersion,
+                "Fleet Net voice needs a client with Opus support",
+            ));
+        }
+        if self.config.password.is_some() && auth.password != self.config.password {
+            return Err(reject(
+                proto::RejectType::WrongServerPw,
+                "Wrong server password",
+            ));
+        }
+        let name = auth.username.clone().unwrap_or_default();
+        if User::validate_nickname(&name, &self.limits).is_err() {
+            return Err(reject(
+                proto::RejectType::InvalidUsername,
+                "Invalid username",
+            ));
+        }
+        if self.users.iter().any(|user| user.name == name) {
+            return Err(reject(
+                proto::RejectType::UsernameInUse,
+                "Username already in use",
+            ));
+        }
+
+        // Checking and inserting are not atomic, so the entry is claimed
+        // under the map's lock
+        for id in self.config.user_ids.clone() {
+            let Some(user_id) = UserId::new(id) else {
+                continue;
+            };
+            if let dashmap::Entry::Vacant(entry) = self.users.entry(user_id) {
+                entry.insert(BridgedUser {
+                    name,
+                    channel_id: None,
+                    self_mute: false,
+                    self_deaf: false,
+                    outbound,
+                });
+                return Ok(user_id);
+            }
+        }
+        Err(reject(
+            proto::RejectType::ServerFull,
+            "No room for more Mumble users",
+        ))
+    }
+
+    /// Sends the channel tree and everyone connected to a newly admitted
+    /// user, and announces the user to everyone else.
+    async fn send_welcome(
+        &self,
+        user_id: UserId,
+        outbound: &mpsc::UnboundedSender<MumbleMessage>,
+    ) -> Result<(), FleetNetError> {
+        let send = |message| {
+            let _ = outbound.send(message);
+        };
+        send(MumbleMessage::CodecVersion(proto::CodecVersion {
+            alpha: CELT_ALPHA_VERSION,
+            beta: 0,
+            prefer_alpha: true,
+            opus: Some(true),
+        }));
+        send(MumbleMessage::ChannelState(proto::ChannelState {
+            channel_id: Some(ROOT_CHANNEL),
+            name: Some(self.config.root_name.clone()),
+            ..Default::default()
+        }));
+        for channel in parents_first(self.channels.store().list().await?) {
+            send(MumbleMessage::ChannelState(channel_state(&channel)));
+        }
+
+        let own_state = self
+            .users
+            .get(&user_id)
+            .map(|user| user.state(user_id))
+            .expect("Admitted users stay registered while connected");
+        for user in self.users.iter() {
+            if *user.key() != user_id {
+                send(MumbleMessage::UserState(user.state(*user.key())));
+                let _ = user
+                    .outbound
+                    .send(MumbleMessage::UserState(own_state.clone()));
+            }
+        }
+        send(MumbleMessage::UserState(own_state));
+        send(MumbleMessage::ServerSync(proto::ServerSync {
+            session: Some(user_id.get().into()),
+            max_bandwidth: Some(self.config.max_bandwidth),
+            welcome_text: self.config.welcome_text.clone(),
+            permissions: Some(mumble_permissions(self.config.permissions)),
+        }));
+        Ok(())
+    }
+
+    /// Applies a client's change to its own state: moving channel, or
+    /// muting or deafening itself.
+    async fn update_user(
+        &self,
+        user_id: UserId,
+        voice_address: SocketAddr,
+        state: proto::UserState,
+    ) -> Result<(), FleetNetError> {
+        let session = u32::from(user_id.get());
+        if state.session.is_some_and(|target| target != session) {
+            self.deny(user_id, None, "Mumble users can only change themselves");
+            return Ok(());
+        }
+
+        let mut new_channel = None;
+        if let Some(channel) = state.channel_id {
+            if channel == ROOT_CHANNEL {
+                new_channel = Some(None);
+            } else {
+                let channel = u16::try_from(channel).ok().and_then(ChannelId::new);
+                let joinable = match channel {
+                    Some(channel_id) => self
+                        .channels
+                        .store()
+                        .load(channel_id)
+                        .await?
+                        .is_some_and(|channel| channel.channel_type != ChannelType::Category),
+                    None => false,
+                };
+                if !joinable {
+                    self.deny(user_id, state.channel_id, "Not a voice channel");
+                    return Ok(());
+                }
+                if !self.config.permissions.contains(Permissions::CONNECT) {
+                    self.deny(user_id, state.channel_id, "Missing permission to join");
+                    return Ok(());
+                }
+                new_channel = Some(channel);
+            }
+        }
+
+        let Some(mut user) = self.users.get_mut(&user_id) else {
+            return Ok(());
+        };
+        if let Some(channel_id) = new_channel {
+            if let Some(old) = user.channel_id {
+                self.subscriptions.remove_listener(old, user_id);
+            }
+            if let Some(channel_id) = channel_id {
+                self.subscriptions.add_listener(
+                    channel_id,
+                    RelaySubscriber {
+                        user_id,
+                        address: voice_address,
+                    },
+                );
+            }
+            user.channel_id = channel_id;
+        }
+        user.self_mute = state.self_mute.unwrap_or(user.self_mute);
+        user.self_deaf = state.self_deaf.unwrap_or(user.self_deaf);
+        let change = MumbleMessage::UserState(user.state(user_id));
+        drop(user);
+        self.broadcast(change);
+        Ok(())
+    }
+
+    /// Builds the Fleet Net packet for voice from a bridged user, or `None`
+    /// if it cannot or may not be sent.
+    fn to_fleet_packet(
+        &self,
+        user_id: UserId,
+        data: &[u8],
+        sequence: u16,
+        key: &HmacKey,
+    ) -> Option<AudioPacket> {
+        let voice = MumbleVoice::from_client(data)?;
+        let user = self.users.get(&user_id)?;
+        let channel_id = user.channel_id?;
+        if voice.target != 0
+            || user.self_mute
+            || !self.config.permissions.contains(Permissions::SPEAK)
+        {
+            return None;
+        }
+        let (timestamp, frame_duration) = to_fleet_timing(&voice)?;
+        let header = PacketHeader {
+            channel_id,
+            user_id,
+            sequence,
+            timestamp,
+            signal_strength: u8::MAX,
+            frame_duration,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        Some(AudioPacket::new_signed(header, voice.opus, key))
+    }
+
+    /// Passes voice routed to a bridged user on to its client, announcing
+    /// native speakers the client has not seen yet.
+    async fn relay_voice(self: Arc<Self>, user_id: UserId, voice: Arc<UdpSocket>) {
+        let mut announced = HashSet::new();
+        let mut buf = vec![0u8; 65_535];
+        while let Ok((len, _)) = voice.recv_from(&mut buf).await {
+            let Ok(packet) = AudioPacket::from_bytes(&buf[..len]) else {
+                continue;
+            };
+            // Mumble has no DTX packets; clients play the gap as silence
+            if packet.header.dtx {
+                continue;
+            }
+            let speaker = packet.header.user_id;
+            let Some(outbound) = self
+                .users
+                .get(&user_id)
+                .filter(|user| !user.self_deaf)
+                .map(|user| user.outbound.clone())
+            else {
+                continue;
+            };
+            if !self.users.contains_key(&speaker) && announced.insert(speaker) {
+                let _ = outbound.send(MumbleMessage::UserState(
+                    self.native_user_state(speaker, packet.header.channel_id)
+                        .await,
+                ));
+            }
+            let Ok(frames) = packet.into_frames() else {
+                continue;
+            };
+            for frame in frames {
+                let voice = to_mumble_voice(frame).to_client(speaker.get().into());
+                let _ = outbound.send(MumbleMessage::UdpTunnel(voice));
+            }
+        }
+    }
+
+    /// How a native Fleet Net speaker is shown to Mumble clients.
+    async fn native_user_state(&self, user_id: UserId, channel_id: ChannelId) -> proto::UserState {
+        let name = match self.nicknames.store().load(user_id).await {
+            Ok(Some(nickname)) => nickname,
+            _ => format!("Fleet Net {user_id}"),
+        };
+        proto::UserState {
+            session: Some(user_id.get().into()),
+            name: Some(name),
+            channel_id: Some(channel_id.get().into()),
+            ..Default::default()
+        }
+    }
+
+    fn deny(&self, user_id: UserId, channel_id: Option<u32>, reason: &str) {
+        if let Some(user) = self.users.get(&user_id) {
+            let _ = user
+                .outbound
+                .send(MumbleMessage::PermissionDenied(proto::PermissionDenied {
+                    channel_id,
+                    session: Some(user_id.get().into()),
+                    reason: Some(reason.to_string()),
+                    r#type: Some(proto::DenyType::Permission.into()),
+                }));
+        }
+    }
+
+    fn broadcast(&self, message: MumbleMessage) {
+        for user in self.users.iter() {
+            // A closed channel means the user is disconnecting; its handler cleans up.
+            let _ = user.outbound.send(message.clone());
+        }
+    }
+
+    fn remove_user(&self, user_id: UserId) {
+        if self.users.remove(&user_id).is_some() {
+            self.subscriptions.remove_user(user_id);
+            self.broadcast(MumbleMessage::UserRemove(proto::UserRemove {
+                session: Some(user_id.get().into()),
+                reason: None,
+            }));
+            info!("Mumble user {user_id} disconnected");
+        }
+    }
+}
+
+/// Forgets a bridged user however its connection ends.
+struct RemoveOnDrop<'a> {
+    gateway: &'a MumbleGateway,
+    user_id: UserId,
+}
+
+impl Drop for RemoveOnDrop<'_> {
+    fn drop(&mut self) {
+        self.gateway.remove_user(self.user_id);
+    }
+}
+
+/// Shows channel topic changes as Mumble channel descriptions.
+async fn relay_channel_changes(
+    mut changes: broadcast::Receiver<ControlMessage>,
+    outbound: mpsc::UnboundedSender<MumbleMessage>,
+) {
+    loop {
+        match changes.recv().await {
+            Ok(ControlMessage::ChannelInfoChanged {
+                channel_id, topic, ..
+            }) => {
+                let state = proto::ChannelState {
+                    channel_id: Some(channel_id.get().into()),
+                    description: Some(topic.unwrap_or_default()),
+                    ..Default::default()
+                };
+                if outbound.send(MumbleMessage::ChannelState(state)).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Mumble gateway missed {skipped} channel changes");
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Reads until the client authenticates, skipping its version and pings.
+async fn read_authenticate<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<proto::Authenticate, FleetNetError> {
+    loop {
+        if let MumbleMessage::Authenticate(auth) = MumbleMessage::read_from(reader).await? {
+            return Ok(auth);
+        }
+    }
+}
+
+fn reject(kind: proto::RejectType, reason: &str) -> proto::Reject {
+    proto::Reject {
+        r#type: Some(kind.into()),
+        reason: Some(reason.to_string()),
+    }
+}
+
+fn channel_state(channel: &Channel) -> proto::ChannelState {
+    proto::ChannelState {
+        channel_id: Some(channel.id.get().into()),
+        parent: Some(channel.parent_id.map_or(ROOT_CHANNEL, |id| id.get().into())),
+        name: Some(channel.name.clone()),
+        description: channel
+            .topic
+            .clone()
+            .or_else(|| channel.description.clone()),
+        position: Some(i32::try_from(channel.position).unwrap_or(i32::MAX)),
+        is_enter_restricted: Some(channel.channel_type == ChannelType::Category),
+        can_enter: Some(channel.channel_type != ChannelType::Category),
+    }
+}
+
+/// `channels` ordered so every parent comes before its children, as Mumble
+/// clients need; channels whose parent is missing are left out.
+fn parents_first(mut channels: Vec<Channel>) -> Vec<Channel> {
+    channels.sort_by_key(|channel| (channel.position, channel.id));
+    let mut ordered: Vec<Channel> = Vec::with_capacity(channels.len());
+    let mut placed = HashSet::new();
+    loop {
+        let before = ordered.len();
+        channels.retain(|channel| {
+            let ready = channel
+                .parent_id
+                .is_none_or(|parent| placed.contains(&parent));
+            if ready {
+                placed.insert(channel.id);
+                ordered.push(channel.clone());
+            }
+            !ready
+        });
+        if channels.is_empty() || ordered.len() == before {
+            return ordered;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_config;
+    use fleet_net_common::channel::AudioPolicy;
+    use fleet_net_protocol::test_helpers::synthetic_opus_payload;
+    use fleet_test_support::time::with_default_timeout;
+    use fleet_test_support::{recv_packet, wait_until};
+    use std::collections::HashMap;
+    use tokio::io::DuplexStream;
+
+    fn channel(id: u16, name: &str, channel_type: ChannelType) -> Channel {
+        Channel {
+            id: ChannelId::new(id).unwrap(),
+            name: name.to_string(),
+            description: None,
+            channel_type,
+            role_permissions: HashMap::new(),
+            position: u32::from(id),
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    /// A server with a category holding one voice channel, its voice router
+    /// and a gateway into it.
+    async fn gateway() -> (Arc<Server>, Arc<MumbleGateway>, Arc<UdpSocket>) {
+        let server = Arc::new(Server::new(test_config()).unwrap());
+        let store = server.channels().store();
+        store
+            .save(channel(1, "Fleet", ChannelType::Category))
+            .await
+            .unwrap();
+        let mut ops = channel(2, "Ops", ChannelType::Voice);
+        ops.parent_id = ChannelId::new(1);
+        store.save(ops).await.unwrap();
+
+        let router = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        tokio::spawn({
+            let router = router.clone();
+            let subscriptions = server.subscriptions().clone();
+            async move {
+                let mut buf = vec![0u8; 1_500];
+                while let Ok((len, source)) = router.recv_from(&mut buf).await {
+                    let _ = subscriptions
+                        .forward_packet(&router, &buf[..len], source)
+                        .await;
+                }
+            }
+        });
+
+        let config = MumbleConfig::new(router.local_addr().unwrap(), b"secret".to_vec());
+        let gateway = Arc::new(MumbleGateway::new(config, &server));
+        (server, gateway, router)
+    }
+
+    fn authenticate(name: &str, opus: bool) -> MumbleMessage {
+        MumbleMessage::Authenticate(proto::Authenticate {
+            username: Some(name.to_string()),
+            opus: Some(opus),
+            ..Default::default()
+        })
+    }
+
+    /// A Mumble client connected to `gateway`.
+    async fn connect(gateway: &Arc<MumbleGateway>, auth: MumbleMessage) -> DuplexStream {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(gateway.clone().handle(server));
+        auth.write_to(&mut client).await.unwrap();
+        client
+    }
+
+    async fn recv(client: &mut DuplexStream) -> MumbleMessage {
+        with_default_timeout(MumbleMessage::read_from(client))
+            .await
+            .expect("Timed out waiting for a Mumble message")
+            .unwrap()
+    }
+
+    async fn recv_until<T>(
+        client: &mut DuplexStream,
+        pick: impl Fn(MumbleMessage) -> Option<T>,
+    ) -> T {
+        loop {
+            if let Some(found) = pick(recv(client).await) {
+                return found;
+            }
+        }
+    }
+
+    async fn join(client: &mut DuplexStream, session: u32, channel_id: u32) {
+        MumbleMessage::UserState(proto::UserState {
+            channel_id: Some(channel_id),
+            ..Default::default()
+        })
+        .write_to(client)
+        .await
+        .unwrap();
+        recv_until(client, |message| match message {
+            MumbleMessage::UserState(state)
+                if state.session == Some(session) && state.channel_id == Some(channel_id) =>
+            {
+                Some(())
+            }
+            _ => None,
+        })
+        .await;
+    }
+
+    async fn session_of(client: &mut DuplexStream) -> u32 {
+        recv_until(client, |message| match message {
+            MumbleMessage::ServerSync(sync) => sync.session,
+            _ => None,
+        })
+        .await
+    }
+
+    #[test]
+    fn test_varints_match_mumble_encoding() {
+        for (value, encoded) in [
+            (0x05, vec![0x05]),
+            (0x1234, vec![0x92, 0x34]),
+            (0x12_3456, vec![0xD2, 0x34, 0x56]),
+            (0x0123_4567, vec![0xE1, 0x23, 0x45, 0x67]),
+            (0x8000_0000, vec![0xF0, 0x80, 0, 0, 0]),
+            (
+                u64::MAX,
+                vec![0xF4, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+        ] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, value);
+            assert_eq!(buf, encoded, "{value:#x}");
+            let mut rest = &buf[..];
+            assert_eq!(take_varint(&mut rest), Some(value));
+            assert!(rest.is_empty());
+        }
+        assert_eq!(take_varint(&mut &[0x92][..]), None);
+        assert_eq!(take_varint(&mut &[0xFC][..]), None);
+    }
+
+    #[test]
+    fn test_voice_is_repacketized_with_its_timing() {
+        let opus = synthetic_opus_payload(7, 20);
+        let mut data = vec![VoiceKind::OPUS << 5];
+        put_varint(&mut data, 300);
+        put_varint(&mut data, opus.len() as u64 | MumbleVoice::TERMINATOR);
+        data.extend_from_slice(&opus);
+        // Positional audio trails the frame
+        data.extend_from_slice(&[0; 12]);
+
+        let voice = MumbleVoice::from_client(&data).unwrap();
+        assert!(voice.terminator);
+        assert_eq!(voice.opus, opus);
+        assert_eq!(to_fleet_timing(&voice), Some((3_000, 20)));
+
+        let relayed = voice.to_client(42);
+        assert_eq!(&relayed[..2], &[VoiceKind::OPUS << 5, 42]);
+
+        // Two 10 ms CELT frames in one packet, and 2.5 ms frames that do not
+        // add up to whole milliseconds
+        assert_eq!(opus_packet_duration_us(&[30 << 3 | 1, 0, 0]), Some(20_000));
+        assert_eq!(opus_packet_duration_us(&[16 << 3 | 3, 3]), Some(7_500));
+        assert_eq!(opus_packet_duration_us(&[]), None);
+        let odd = MumbleVoice {
+            opus: vec![16 << 3 | 3, 3],
+            ..voice
+        };
+        assert_eq!(to_fleet_timing(&odd), None);
+
+        // CELT voice and pings are not Opus voice
+        assert!(MumbleVoice::from_client(&[0, 1, 1, 0]).is_none());
+        assert!(MumbleVoice::from_client(&[VoiceKind::PING << 5, 1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_clients_without_opus_or_with_taken_names_are_rejected() {
+        let (_server, gateway, _router) = gateway().await;
+        let rejected = |message| match message {
+            MumbleMessage::Reject(reject) => reject.r#type,
+            _ => None,
+        };
+
+        let mut celt = connect(&gateway, authenticate("Legacy", false)).await;
+        assert_eq!(
+            recv_until(&mut celt, rejected).await,
+            proto::RejectType::WrongVersion as i32
+        );
+
+        let mut first = connect(&gateway, authenticate("Viper 1-1", true)).await;
+        session_of(&mut first).await;
+        let mut second = connect(&gateway, authenticate("Viper 1-1", true)).await;
+        assert_eq!(
+            recv_until(&mut second, rejected).await,
+            proto::RejectType::UsernameInUse as i32
+        );
+        assert_eq!(gateway.user_count(), 1);
+
+        drop(first);
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                gateway.user_count() == 0
+            })
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_voice_flows_between_mumble_and_fleet_net_users() {
+        let (server, gateway, router) = gateway().await;
+
+        let mut alpha = connect(&gateway, authenticate("Alpha", true)).await;
+        let channels: Vec<proto::ChannelState> = {
+            let mut channels = Vec::new();
+            loop {
+                match recv(&mut alpha).await {
+                    MumbleMessage::ChannelState(state) => channels.push(state),
+                    MumbleMessage::ServerSync(_) => break channels,
+                    _ => {}
+                }
+            }
+        };
+        let names: Vec<_> = channels.iter().filter_map(|c| c.name.as_deref()).collect();
+        assert_eq!(names, ["Fleet Net", "Fleet", "Ops"]);
+        assert_eq!(channels[2].parent, Some(1));
+
+        let mut bravo = connect(&gateway, authenticate("Bravo", true)).await;
+        let bravo_session = session_of(&mut bravo).await;
+        let alpha_session = u32::from(*gateway.config.user_ids.start());
+
+        // Categories cannot be joined
+        MumbleMessage::UserState(proto::UserState {
+            channel_id: Some(1),
+            ..Default::default()
+        })
+        .write_to(&mut alpha)
+        .await
+        .unwrap();
+        recv_until(&mut alpha, |message| match message {
+            MumbleMessage::PermissionDenied(denied) => denied.channel_id,
+            _ => None,
+        })
+        .await;
+
+        join(&mut alpha, alpha_session, 2).await;
+        join(&mut bravo, bravo_session, 2).await;
+
+        // A native user listening on the same channel
+        let native = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let native_id = UserId::new(7).unwrap();
+        server.subscriptions().add_listener(
+            ChannelId::new(2).unwrap(),
+            RelaySubscriber {
+                user_id: native_id,
+                address: native.local_addr().unwrap(),
+            },
+        );
+
+        let opus = synthetic_opus_payload(1, 20);
+        let spoken = MumbleVoice {
+            target: 0,
+            sequence: 50,
+            opus: opus.clone(),
+            terminator: false,
+        };
+        let mut data = vec![VoiceKind::OPUS << 5];
+        put_varint(&mut data, spoken.sequence);
+        put_varint(&mut data, opus.len() as u64);
+        data.extend_from_slice(&opus);
+        MumbleMessage::UdpTunnel(data)
+            .write_to(&mut alpha)
+            .await
+            .unwrap();
+
+        let heard = recv_until(&mut bravo, |message| match message {
+            MumbleMessage::UdpTunnel(data) => Some(data),
+            _ => None,
+        })
+        .await;
+        assert_eq!(heard, spoken.to_client(alpha_session));
+
+        let received = recv_packet(&native, Duration::from_secs(5)).await.unwrap();
+        let packet = AudioPacket::from_bytes(&received).unwrap();
+        assert_eq!(packet.header.user_id.get(), alpha_session as u16);
+        assert_eq!(packet.header.frame_duration, 20);
+        assert_eq!(packet.header.timestamp, 500);
+        assert_eq!(packet.opus_payload, opus);
+
+        // The native user talks back and is introduced first
+        let reply = AudioPacket {
+            header: PacketHeader {
+                user_id: native_id,
+                timestamp: 1_000,
+                ..packet.header
+            },
+            opus_payload: opus.clone(),
+            position: None,
+        };
+        native
+            .send_to(&reply.to_bytes(), router.local_addr().unwrap())
+            .await
+            .unwrap();
+        let introduced = recv_until(&mut bravo, |message| match message {
+            MumbleMessage::UserState(state) if state.session == Some(7) => Some(state),
+            _ => None,
+        })
+        .await;
+        assert_eq!(introduced.name.as_deref(), Some("Fleet Net 7"));
+        let heard = recv_until(&mut bravo, |message| match message {
+            MumbleMessage::UdpTunnel(data) => Some(data),
+            _ => None,
+        })
+        .await;
+        let expected = MumbleVoice {
+            sequence: 100,
+            ..spoken
+        };
+        assert_eq!(heard, expected.to_client(7));
+    }
+}
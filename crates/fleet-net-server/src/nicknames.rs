@@ -0,0 +1,201 @@
+//! Per-server nicknames.
+//!
+//! Users holding [`Permissions::CHANGE_NICKNAME`] set a nickname with
+//! [`ControlMessage::SetNickname`]. Nicknames are kept in a [`NicknameStore`]
+//! so they survive reconnects, and each change is published as a
+//! [`ControlMessage::NicknameChanged`] for connections to broadcast.
+//!
+//! Servers can require nicknames to match a pattern, e.g. so a milsim unit
+//! only accepts callsigns like `Viper 1-1`.
+
+use crate::store::{InMemoryNicknameStore, NicknameBackend, NicknameStore};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::session::Session;
+use fleet_net_common::user::User;
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::ControlMessage;
+use regex::Regex;
+use std::borrow::Cow;
+use tokio::sync::broadcast;
+
+/// Nickname changes buffered for slow subscribers.
+const CHANGE_BUFFER: usize = 64;
+
+pub struct NicknameRegistry<S = NicknameBackend> {
+    store: S,
+    pattern: Option<Regex>,
+    changes: broadcast::Sender<ControlMessage>,
+    limits: ServerLimits,
+}
+
+impl NicknameRegistry {
+    /// A registry keeping nicknames in memory, accepting any valid nickname.
+    pub fn in_memory() -> Self {
+        Self::new(NicknameBackend::InMemory(InMemoryNicknameStore::new()))
+    }
+}
+
+impl<S: NicknameStore> NicknameRegistry<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            pattern: None,
+            changes: broadcast::channel(CHANGE_BUFFER).0,
+            limits: ServerLimits::default(),
+        }
+    }
+
+    /// Checks requests against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Only accepts nicknames matching `pattern` in full.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if `pattern` is not a valid regular
+    /// expression.
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self, FleetNetError> {
+        let anchored = Regex::new(&format!("^(?:{pattern})$")).map_err(|_| {
+            FleetNetError::invalid_field("nickname_pattern", Constraint::InvalidFormat)
+        })?;
+        self.pattern = Some(anchored);
+        Ok(self)
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Receives every nickname change from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ControlMessage> {
+        self.changes.subscribe()
+    }
+
+    /// Handles a nickname request from `session` and returns the change,
+    /// which is also published to subscribers.
+    pub async fn apply(
+        &self,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<ControlMessage, FleetNetError> {
+        session.ensure_interactive()?;
+        let ControlMessage::SetNickname { nickname } = message else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected set_nickname")),
+            ));
+        };
+        if !session.permission.has(Permissions::CHANGE_NICKNAME) {
+            return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                "Missing permission to change nickname",
+            )));
+        }
+        if let Some(nickname) = nickname {
+            self.check(nickname)?;
+        }
+
+        let user_id = session.user.id;
+        self.store.save(user_id, nickname.clone()).await?;
+        session.user.nickname = nickname.clone();
+        session.update_activity();
+
+        let change = ControlMessage::NicknameChanged {
+            user_id,
+            nickname: nickname.clone(),
+        };
+        // Nobody listening is fine; the store is still up to date.
+        let _ = self.changes.send(change.clone());
+        Ok(change)
+    }
+
+    /// Gives a newly authenticated session its stored nickname.
+    pub async fn restore(&self, session: &mut Session) -> Result<(), FleetNetError> {
+        session.user.nickname = self.store.load(session.user.id).await?;
+        Ok(())
+    }
+
+    fn check(&self, nickname: &str) -> Result<(), FleetNetError> {
+        User::validate_nickname(nickname, &self.limits)?;
+        if self
+            .pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.is_match(nickname))
+        {
+            return Err(FleetNetError::invalid_field(
+                "nickname",
+                Constraint::Invalid(Cow::Borrowed("does_not_match_pattern")),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::types::UserId;
+    use std::time::Instant;
+
+    fn session(permissions: Permissions) -> Session {
+        Session {
+            id: "session".to_string(),
+            user: User::new(UserId::new(1).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    fn set(nickname: Option<&str>) -> ControlMessage {
+        ControlMessage::SetNickname {
+            nickname: nickname.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nicknames_follow_pattern_and_persist() {
+        let registry = NicknameRegistry::in_memory()
+            .with_pattern(r"[A-Z][a-z]+ \d-\d")
+            .unwrap();
+        let mut changes = registry.subscribe();
+        let mut alice = session(Permissions::CHANGE_NICKNAME);
+
+        let result = registry.apply(&mut alice, &set(Some("alice"))).await;
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+        registry
+            .apply(&mut alice, &set(Some("Viper 1-1")))
+            .await
+            .unwrap();
+        assert_eq!(alice.user.display_name(), "Viper 1-1");
+        assert!(matches!(
+            changes.try_recv(),
+            Ok(ControlMessage::NicknameChanged { nickname: Some(nickname), .. }) if nickname == "Viper 1-1"
+        ));
+
+        // A reconnecting user gets their nickname back
+        let mut reconnected = session(Permissions::empty());
+        registry.restore(&mut reconnected).await.unwrap();
+        assert_eq!(reconnected.user.nickname.as_deref(), Some("Viper 1-1"));
+
+        // Without the permission the nickname cannot be changed, even cleared
+        let result = registry.apply(&mut reconnected, &set(None)).await;
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+
+        registry.apply(&mut alice, &set(None)).await.unwrap();
+        registry.restore(&mut reconnected).await.unwrap();
+        assert_eq!(reconnected.user.nickname, None);
+    }
+}
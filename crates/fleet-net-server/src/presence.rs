@@ -0,0 +1,179 @@
+//! User presence.
+//!
+//! Users set their presence with [`ControlMessage::SetPresence`]. The registry
+//! remembers the presence of every connected user, so newcomers can be told,
+//! and publishes each change as a [`ControlMessage::PresenceChanged`] for
+//! connections to broadcast.
+
+use dashmap::DashMap;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::session::Session;
+use fleet_net_common::types::UserId;
+use fleet_net_common::user::Presence;
+use fleet_net_common::validation::{Constraint, Validate};
+use fleet_net_protocol::message::ControlMessage;
+use std::borrow::Cow;
+use tokio::sync::broadcast;
+
+/// Presence changes buffered for slow subscribers.
+const CHANGE_BUFFER: usize = 256;
+
+pub struct PresenceRegistry {
+    presences: DashMap<UserId, Presence>,
+    changes: broadcast::Sender<ControlMessage>,
+    limits: ServerLimits,
+}
+
+impl PresenceRegistry {
+    pub fn new() -> Self {
+        Self {
+            presences: DashMap::new(),
+            changes: broadcast::channel(CHANGE_BUFFER).0,
+            limits: ServerLimits::default(),
+        }
+    }
+
+    /// Checks requests against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Receives every presence change from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ControlMessage> {
+        self.changes.subscribe()
+    }
+
+    /// Handles a presence update from `session` and returns the change,
+    /// which is also published to subscribers.
+    pub fn apply(
+        &self,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<ControlMessage, FleetNetError> {
+        session.ensure_interactive()?;
+        let ControlMessage::SetPresence { presence } = message else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected set_presence")),
+            ));
+        };
+        presence.validate(&self.limits)?;
+
+        let user_id = session.user.id;
+        session.user.presence = presence.clone();
+        session.update_activity();
+        self.presences.insert(user_id, presence.clone());
+
+        let change = ControlMessage::PresenceChanged {
+            user_id,
+            presence: presence.clone(),
+        };
+        // Nobody listening is fine; the registry is still up to date.
+        let _ = self.changes.send(change.clone());
+        Ok(change)
+    }
+
+    /// The presence of `user_id`; users who never set one are online.
+    pub fn get(&self, user_id: UserId) -> Presence {
+        self.presences
+            .get(&user_id)
+            .map(|presence| presence.clone())
+            .unwrap_or_default()
+    }
+
+    /// A [`ControlMessage::PresenceChanged`] for every user not online, to
+    /// bring a newly connected client up to date.
+    pub fn snapshot(&self) -> Vec<ControlMessage> {
+        self.presences
+            .iter()
+            .filter(|entry| *entry.value() != Presence::Online)
+            .map(|entry| ControlMessage::PresenceChanged {
+                user_id: *entry.key(),
+                presence: entry.value().clone(),
+            })
+            .collect()
+    }
+
+    /// Forgets a disconnected user.
+    pub fn remove_user(&self, user_id: UserId) {
+        self.presences.remove(&user_id);
+    }
+}
+
+impl Default for PresenceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::user::User;
+    use std::time::Instant;
+
+    fn session(state: SessionState) -> Session {
+        Session {
+            id: "session".to_string(),
+            user: User::new(UserId::new(1).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::new(),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_presence_changes_are_broadcast() {
+        let registry = PresenceRegistry::new();
+        let mut changes = registry.subscribe();
+        let mut alice = session(SessionState::Active);
+        let in_game = Presence::InGame {
+            game: "DCS World".to_string(),
+        };
+
+        registry
+            .apply(
+                &mut alice,
+                &ControlMessage::SetPresence {
+                    presence: in_game.clone(),
+                },
+            )
+            .unwrap();
+        assert_eq!(alice.user.presence, in_game);
+        assert_eq!(registry.get(alice.user.id), in_game);
+        assert_eq!(registry.snapshot().len(), 1);
+        assert!(matches!(
+            changes.try_recv(),
+            Ok(ControlMessage::PresenceChanged { presence, .. }) if presence == in_game
+        ));
+
+        // Unauthenticated sessions and empty game names are refused
+        let mut pending = session(SessionState::Authenticating);
+        let away = ControlMessage::SetPresence {
+            presence: Presence::Away,
+        };
+        assert!(registry.apply(&mut pending, &away).is_err());
+        let unnamed = ControlMessage::SetPresence {
+            presence: Presence::InGame {
+                game: String::new(),
+            },
+        };
+        assert!(matches!(
+            registry.apply(&mut alice, &unnamed),
+            Err(FleetNetError::ValidationError(_))
+        ));
+
+        registry.remove_user(alice.user.id);
+        assert_eq!(registry.get(alice.user.id), Presence::Online);
+    }
+}
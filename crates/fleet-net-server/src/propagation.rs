@@ -0,0 +1,231 @@
+//! Radio propagation, deciding the signal strength each receiver gets.
+//!
+//! Clients fed by game telemetry put the speaker's [`SpeakerPosition`] in
+//! their packets. With propagation configured, the server uses the last
+//! position of the sender and of each receiver to compute the strength
+//! that receiver hears a transmission at, instead of forwarding the byte
+//! the sender picked. How far a signal carries depends on the kind of
+//! radio, see [`RadioTypes`]:
+//!
+//! - [`PropagationModel::LineOfSight`] carries to the radio horizon, which
+//!   grows with the height of both antennas; the default for VHF and UHF
+//! - [`PropagationModel::Skywave`] carries a short way as ground wave and
+//!   then, past a skip zone hearing nothing, far off the ionosphere; the
+//!   default for HF
+//! - [`PropagationModel::Unlimited`] carries everywhere; the default for
+//!   satellite and quantum radios
+//!
+//! A channel's `max_range_m` caps every model.
+
+use fleet_net_common::channel::{RadioChannelConfig, RadioTypes};
+use fleet_net_protocol::packet::SpeakerPosition;
+use serde::{Deserialize, Serialize};
+
+/// Radio horizon, in meters, of an antenna one meter up, over standard
+/// atmospheric refraction.
+const HORIZON_M_PER_SQRT_M: f32 = 4_120.0;
+
+/// How far and how well a kind of radio carries.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum PropagationModel {
+    /// Fades out towards the combined radio horizon of both antennas and
+    /// is lost beyond it. Antennas are as high as the speaker's `z`, and
+    /// never lower than `min_antenna_height_m`.
+    LineOfSight { min_antenna_height_m: f32 },
+    /// Fades out across `ground_wave_m`, is lost until `skip_distance_m`
+    /// and then heard at `skywave_strength` up to `max_range_m`.
+    Skywave {
+        ground_wave_m: f32,
+        skip_distance_m: f32,
+        max_range_m: f32,
+        skywave_strength: u8,
+    },
+    /// Full strength at any distance.
+    Unlimited,
+}
+
+impl PropagationModel {
+    /// Strength of a transmission from `from`, as heard at `to`.
+    pub fn signal_strength(&self, from: &SpeakerPosition, to: &SpeakerPosition) -> u8 {
+        match *self {
+            PropagationModel::LineOfSight {
+                min_antenna_height_m,
+            } => {
+                let height = |position: &SpeakerPosition| position.z.max(min_antenna_height_m);
+                let horizon_m = HORIZON_M_PER_SQRT_M * (height(from).sqrt() + height(to).sqrt());
+                fade(distance(from, to), horizon_m)
+            }
+            PropagationModel::Skywave {
+                ground_wave_m,
+                skip_distance_m,
+                max_range_m,
+                skywave_strength,
+            } => {
+                let distance_m = (to.x - from.x).hypot(to.y - from.y);
+                if distance_m <= ground_wave_m {
+                    fade(distance_m, ground_wave_m)
+                } else if distance_m >= skip_distance_m && distance_m <= max_range_m {
+                    skywave_strength
+                } else {
+                    0
+                }
+            }
+            PropagationModel::Unlimited => u8::MAX,
+        }
+    }
+}
+
+/// The model used for each kind of radio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PropagationConfig {
+    pub hf: PropagationModel,
+    pub vhf: PropagationModel,
+    pub uhf: PropagationModel,
+    pub satellite: PropagationModel,
+    pub quantum: PropagationModel,
+}
+
+impl PropagationConfig {
+    pub fn model(&self, radio_type: RadioTypes) -> &PropagationModel {
+        match radio_type {
+            RadioTypes::Hf => &self.hf,
+            RadioTypes::Vhf => &self.vhf,
+            RadioTypes::Uhf => &self.uhf,
+            RadioTypes::Satellite => &self.satellite,
+            RadioTypes::Quantum => &self.quantum,
+        }
+    }
+
+    /// Strength of a transmission on `tuning` from `from`, as heard at `to`;
+    /// 0 if it does not carry that far.
+    pub fn signal_strength(
+        &self,
+        tuning: &RadioChannelConfig,
+        from: &SpeakerPosition,
+        to: &SpeakerPosition,
+    ) -> u8 {
+        if let Some(max_range_m) = tuning.max_range_m {
+            if distance(from, to) > max_range_m as f32 {
+                return 0;
+            }
+        }
+        self.model(tuning.radio_type()).signal_strength(from, to)
+    }
+}
+
+impl Default for PropagationConfig {
+    fn default() -> Self {
+        let line_of_sight = PropagationModel::LineOfSight {
+            min_antenna_height_m: 2.0,
+        };
+        Self {
+            hf: PropagationModel::Skywave {
+                ground_wave_m: 50_000.0,
+                skip_distance_m: 300_000.0,
+                max_range_m: 3_000_000.0,
+                skywave_strength: 120,
+            },
+            vhf: line_of_sight,
+            uhf: line_of_sight,
+            satellite: PropagationModel::Unlimited,
+            quantum: PropagationModel::Unlimited,
+        }
+    }
+}
+
+fn distance(from: &SpeakerPosition, to: &SpeakerPosition) -> f32 {
+    (to.x - from.x).hypot(to.y - from.y).hypot(to.z - from.z)
+}
+
+/// Full strength next to the sender, falling off to a trace at `range_m`
+/// and nothing beyond.
+fn fade(distance_m: f32, range_m: f32) -> u8 {
+    if distance_m > range_m {
+        return 0;
+    }
+    let left = 1.0 - (distance_m / range_m).powi(2);
+    // A zero range only carries to the sender's own spot
+    (f32::from(u8::MAX) * left).round().max(1.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::Modulation;
+
+    fn at(x: f32, z: f32) -> SpeakerPosition {
+        SpeakerPosition {
+            x,
+            z,
+            ..SpeakerPosition::default()
+        }
+    }
+
+    fn tuning(frequency_hz: u64) -> RadioChannelConfig {
+        RadioChannelConfig {
+            frequency_hz,
+            modulation: Modulation::Am,
+            max_range_m: None,
+            crypto_key_id: None,
+        }
+    }
+
+    #[test]
+    fn test_line_of_sight_reaches_further_from_higher_up() {
+        let config = PropagationConfig::default();
+        let uhf = tuning(305_000_000);
+        let ground = at(0.0, 0.0);
+
+        assert_eq!(config.signal_strength(&uhf, &ground, &ground), 255);
+        // Two soldiers see each other's antennas up to about 11.7 km away
+        let near = config.signal_strength(&uhf, &ground, &at(5_000.0, 0.0));
+        assert!(near > 150 && near < 255, "{near}");
+        assert_eq!(config.signal_strength(&uhf, &ground, &at(12_000.0, 0.0)), 0);
+        // An aircraft at 5 km stays above the horizon for almost 300 km
+        let aircraft = at(250_000.0, 5_000.0);
+        assert!(config.signal_strength(&uhf, &ground, &aircraft) > 0);
+
+        let mut capped = uhf.clone();
+        capped.max_range_m = Some(100_000);
+        assert_eq!(config.signal_strength(&capped, &ground, &aircraft), 0);
+    }
+
+    #[test]
+    fn test_skywave_skips_over_the_middle_distance() {
+        let config = PropagationConfig::default();
+        let hf = tuning(8_992_000);
+        let strength = |x| config.signal_strength(&hf, &at(0.0, 0.0), &at(x, 0.0));
+
+        assert!(strength(10_000.0) > 200);
+        assert_eq!(strength(100_000.0), 0);
+        assert_eq!(strength(1_000_000.0), 120);
+        assert_eq!(strength(5_000_000.0), 0);
+
+        // Satellite links don't care about distance
+        let satcom = tuning(7_250_000_000);
+        assert_eq!(
+            config.signal_strength(&satcom, &at(0.0, 0.0), &at(9_000_000.0, 0.0)),
+            255
+        );
+    }
+
+    #[test]
+    fn test_models_are_configured_per_radio_type() {
+        let config: PropagationConfig = serde_json::from_str(
+            r#"{"hf": {"model": "unlimited"},
+                "vhf": {"model": "line_of_sight", "min_antenna_height_m": 10.0}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.hf, PropagationModel::Unlimited);
+        assert_eq!(
+            config.model(RadioTypes::Vhf),
+            &PropagationModel::LineOfSight {
+                min_antenna_height_m: 10.0
+            }
+        );
+        // Unset types keep their default
+        assert_eq!(config.uhf, PropagationConfig::default().uhf);
+    }
+}
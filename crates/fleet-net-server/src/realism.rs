@@ -0,0 +1,711 @@
+//! Simulated frequency hopping, jamming and interference on radio nets.
+//!
+//! A [`Scenario`] loaded through the admin API decides how well each
+//! transmission on a tuned radio channel gets through:
+//!
+//! - a [`HopSet`] makes the nets on its frequency hop across several others,
+//!   moving every `dwell_ms` from the moment the scenario was loaded
+//! - a [`Jammer`] covers a band, optionally only within a zone of the game
+//!   world, and either weakens or blocks what it hits
+//! - [`Interference`] weakens everything in a band, e.g. HF on a bad day
+//!
+//! Transmissions are judged on the frequency they are on at the moment,
+//! so a jammer on one frequency of a hop set only hits part of a hopping
+//! net's traffic. Signal losses add up and are taken off the signal
+//! strength each receiver gets; receivers left with nothing don't get the
+//! packet, and nobody does when it is hit by a blocking jammer. Zones are matched against the
+//! position the sender's game telemetry puts in the packet, so speakers
+//! without telemetry are only hit by jammers without a zone.
+//!
+//! With no scenario loaded, which is the default, packets pass untouched.
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use dashmap::DashMap;
+use fleet_net_common::channel::ChannelTree;
+use fleet_net_common::clock::{self, Clock};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::{ServerLimits, MAX_RADIO_FREQUENCY_HZ};
+use fleet_net_common::types::ChannelId;
+use fleet_net_common::validation::{Constraint, FieldErrors, Validate};
+use fleet_net_protocol::packet::{PacketHeader, SpeakerPosition};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Hop sets, jammers and interference entries, each, in one scenario.
+pub const MAX_SCENARIO_ENTRIES: usize = 64;
+
+/// Frequencies a single hop set moves across.
+pub const MAX_HOPS: usize = 256;
+
+/// Longest name of a scenario entry, in bytes.
+pub const MAX_ENTRY_NAME_LEN: usize = 64;
+
+/// Frequencies from `from_hz` to `to_hz`, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrequencyBand {
+    pub from_hz: u64,
+    pub to_hz: u64,
+}
+
+impl FrequencyBand {
+    pub fn contains(&self, frequency_hz: u64) -> bool {
+        (self.from_hz..=self.to_hz).contains(&frequency_hz)
+    }
+}
+
+/// Frequencies the nets tuned to `frequency_hz` hop across.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HopSet {
+    pub name: String,
+    pub frequency_hz: u64,
+    pub hops_hz: Vec<u64>,
+    /// Time spent on each hop before moving to the next.
+    pub dwell_ms: u32,
+}
+
+/// A circle of the game world, in the horizontal frame of
+/// [`SpeakerPosition`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub x: f32,
+    pub y: f32,
+    pub radius_m: f32,
+}
+
+impl Zone {
+    pub fn contains(&self, position: &SpeakerPosition) -> bool {
+        (position.x - self.x).hypot(position.y - self.y) <= self.radius_m
+    }
+}
+
+/// What a jammer does to the transmissions it hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "effect", rename_all = "snake_case")]
+pub enum JamEffect {
+    /// Takes `signal_loss` off the signal strength.
+    Degrade { signal_loss: u8 },
+    /// Drops the transmission.
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Jammer {
+    pub name: String,
+    pub band: FrequencyBand,
+    /// Where senders are jammed; everywhere when `None`.
+    #[serde(default)]
+    pub zone: Option<Zone>,
+    #[serde(flatten)]
+    pub effect: JamEffect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interference {
+    pub name: String,
+    pub band: FrequencyBand,
+    pub signal_loss: u8,
+}
+
+/// Everything the realism engine simulates, replaced as a whole.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub hop_sets: Vec<HopSet>,
+    #[serde(default)]
+    pub jammers: Vec<Jammer>,
+    #[serde(default)]
+    pub interference: Vec<Interference>,
+}
+
+impl Scenario {
+    pub fn is_empty(&self) -> bool {
+        self.hop_sets.is_empty() && self.jammers.is_empty() && self.interference.is_empty()
+    }
+}
+
+/// How a transmission gets through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reception {
+    Clear,
+    /// Forwarded with this much taken off its signal strength.
+    Weakened(u8),
+    Blocked,
+}
+
+/// Judges voice packets on radio nets against the loaded scenario.
+pub struct RadioRealism {
+    scenario: RwLock<Option<(Scenario, Instant)>>,
+    /// Frequency of every tuned radio channel.
+    frequencies: DashMap<ChannelId, u64>,
+    limits: ServerLimits,
+    clock: Arc<dyn Clock>,
+}
+
+impl RadioRealism {
+    pub fn new() -> Self {
+        Self {
+            scenario: RwLock::new(None),
+            frequencies: DashMap::new(),
+            limits: ServerLimits::default(),
+            clock: clock::system(),
+        }
+    }
+
+    /// Checks scenarios against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Times hops with `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Retunes after channels in `tree` changed.
+    pub fn update_channels(&self, tree: &ChannelTree) {
+        self.frequencies.clear();
+        for (_, channel) in tree.iter() {
+            if let Some(radio) = &channel.radio {
+                self.frequencies.insert(channel.id, radio.frequency_hz);
+            }
+        }
+    }
+
+    /// The loaded scenario, empty if there is none.
+    pub fn scenario(&self) -> Scenario {
+        self.scenario
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(scenario, _)| scenario.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the scenario, restarting hop sets; an empty one turns the
+    /// simulation off.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error listing every offending field.
+    pub fn set_scenario(&self, scenario: Scenario) -> Result<(), FleetNetError> {
+        scenario.validate(&self.limits)?;
+        info!(
+            hop_sets = scenario.hop_sets.len(),
+            jammers = scenario.jammers.len(),
+            interference = scenario.interference.len(),
+            "Loaded radio realism scenario"
+        );
+        *self.scenario.write().unwrap() =
+            (!scenario.is_empty()).then(|| (scenario, self.clock.now()));
+        Ok(())
+    }
+
+    /// How the packet with `header`, sent from `position`, gets through at
+    /// `now`.
+    pub fn reception(
+        &self,
+        header: &PacketHeader,
+        position: Option<&SpeakerPosition>,
+        now: Instant,
+    ) -> Reception {
+        let scenario = self.scenario.read().unwrap();
+        let Some((scenario, loaded)) = scenario.as_ref() else {
+            return Reception::Clear;
+        };
+        let Some(tuned_hz) = self.frequencies.get(&header.channel_id).map(|f| *f) else {
+            return Reception::Clear;
+        };
+
+        let elapsed = now.saturating_duration_since(*loaded);
+        let frequency_hz = scenario
+            .hop_sets
+            .iter()
+            .find(|hop_set| hop_set.frequency_hz == tuned_hz)
+            .map_or(tuned_hz, |hop_set| current_hop(hop_set, elapsed));
+
+        let mut signal_loss = 0u8;
+        for jammer in &scenario.jammers {
+            let in_zone = match &jammer.zone {
+                Some(zone) => position.is_some_and(|position| zone.contains(position)),
+                None => true,
+            };
+            if !in_zone || !jammer.band.contains(frequency_hz) {
+                continue;
+            }
+            match jammer.effect {
+                JamEffect::Block => return Reception::Blocked,
+                JamEffect::Degrade { signal_loss: loss } => {
+                    signal_loss = signal_loss.saturating_add(loss);
+                }
+            }
+        }
+        for interference in &scenario.interference {
+            if interference.band.contains(frequency_hz) {
+                signal_loss = signal_loss.saturating_add(interference.signal_loss);
+            }
+        }
+
+        match signal_loss {
+            0 => Reception::Clear,
+            loss => Reception::Weakened(loss),
+        }
+    }
+}
+
+impl Default for RadioRealism {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The frequency `hop_set` is on `elapsed` after it started.
+fn current_hop(hop_set: &HopSet, elapsed: Duration) -> u64 {
+    let hop = elapsed.as_millis() / u128::from(hop_set.dwell_ms.max(1));
+    hop_set.hops_hz[(hop % hop_set.hops_hz.len() as u128) as usize]
+}
+
+impl Validate for FrequencyBand {
+    fn check(&self, errors: &mut FieldErrors, _limits: &ServerLimits) {
+        for (field, frequency_hz) in [("from_hz", self.from_hz), ("to_hz", self.to_hz)] {
+            check_frequency(errors, field, frequency_hz);
+        }
+        if self.from_hz > self.to_hz {
+            errors.add("from_hz", Constraint::Invalid(Cow::Borrowed("above_to_hz")));
+        }
+    }
+}
+
+impl Validate for Zone {
+    fn check(&self, errors: &mut FieldErrors, _limits: &ServerLimits) {
+        if !self.x.is_finite() {
+            errors.add("x", Constraint::InvalidFormat);
+        }
+        if !self.y.is_finite() {
+            errors.add("y", Constraint::InvalidFormat);
+        }
+        if !(self.radius_m.is_finite() && self.radius_m > 0.0) {
+            errors.add(
+                "radius_m",
+                Constraint::Invalid(Cow::Borrowed("not_positive")),
+            );
+        }
+    }
+}
+
+/// Checks entry counts and names, that every band and zone is sound, that
+/// hop sets hop and that no two of them share a frequency.
+impl Validate for Scenario {
+    fn check(&self, errors: &mut FieldErrors, limits: &ServerLimits) {
+        for (field, len) in [
+            ("hop_sets", self.hop_sets.len()),
+            ("jammers", self.jammers.len()),
+            ("interference", self.interference.len()),
+        ] {
+            if len > MAX_SCENARIO_ENTRIES {
+                errors.add(field, Constraint::TooLong(MAX_SCENARIO_ENTRIES));
+            }
+        }
+
+        let mut hopping = HashSet::new();
+        for (index, hop_set) in self.hop_sets.iter().enumerate() {
+            let field = |name: &str| format!("hop_sets[{index}].{name}");
+            errors.check_length(field("name"), hop_set.name.trim(), 1, MAX_ENTRY_NAME_LEN);
+            check_frequency(errors, field("frequency_hz"), hop_set.frequency_hz);
+            if !hopping.insert(hop_set.frequency_hz) {
+                errors.add(field("frequency_hz"), Constraint::Duplicate);
+            }
+            if hop_set.hops_hz.is_empty() {
+                errors.add(field("hops_hz"), Constraint::Required);
+            } else if hop_set.hops_hz.len() > MAX_HOPS {
+                errors.add(field("hops_hz"), Constraint::TooLong(MAX_HOPS));
+            }
+            for (hop, &frequency_hz) in hop_set.hops_hz.iter().enumerate() {
+                check_frequency(errors, field(&format!("hops_hz[{hop}]")), frequency_hz);
+            }
+            if hop_set.dwell_ms == 0 {
+                errors.add(field("dwell_ms"), Constraint::TooShort(1));
+            }
+        }
+
+        for (index, jammer) in self.jammers.iter().enumerate() {
+            let prefix = format!("jammers[{index}]");
+            errors.check_length(
+                format!("{prefix}.name"),
+                jammer.name.trim(),
+                1,
+                MAX_ENTRY_NAME_LEN,
+            );
+            errors.check_nested(&format!("{prefix}.band"), &jammer.band, limits);
+            errors.check_nested(&format!("{prefix}.zone"), &jammer.zone, limits);
+        }
+
+        for (index, interference) in self.interference.iter().enumerate() {
+            let prefix = format!("interference[{index}]");
+            errors.check_length(
+                format!("{prefix}.name"),
+                interference.name.trim(),
+                1,
+                MAX_ENTRY_NAME_LEN,
+            );
+            errors.check_nested(&format!("{prefix}.band"), &interference.band, limits);
+        }
+    }
+}
+
+fn check_frequency(errors: &mut FieldErrors, field: impl Into<Cow<'static, str>>, hz: u64) {
+    if !(1..=MAX_RADIO_FREQUENCY_HZ).contains(&hz) {
+        errors.add(
+            field,
+            Constraint::OutOfRange {
+                min: 1,
+                max: MAX_RADIO_FREQUENCY_HZ as i64,
+            },
+        );
+    }
+}
+
+#[derive(Clone)]
+struct AdminState {
+    realism: Arc<RadioRealism>,
+    token: Arc<str>,
+}
+
+/// Builds the scenario admin API:
+///
+/// - `GET /admin/realism`
+/// - `PUT /admin/realism` with the scenario, replacing the loaded one
+/// - `DELETE /admin/realism`, turning the simulation off
+///
+/// Scenarios that do not validate are rejected with `422 Unprocessable
+/// Entity` and the field errors as JSON. Every request must carry
+/// `Authorization: Bearer <admin_token>`.
+pub fn admin_router(realism: Arc<RadioRealism>, admin_token: &str) -> Router {
+    Router::new()
+        .route(
+            "/admin/realism",
+            get(get_scenario).put(put_scenario).delete(clear_scenario),
+        )
+        .with_state(AdminState {
+            realism,
+            token: admin_token.into(),
+        })
+}
+
+async fn get_scenario(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+) -> Result<Json<Scenario>, StatusCode> {
+    crate::reports::authorize(&state.token, &headers)?;
+    Ok(Json(state.realism.scenario()))
+}
+
+async fn put_scenario(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Json(scenario): Json<Scenario>,
+) -> Result<Json<Scenario>, Response> {
+    crate::reports::authorize(&state.token, &headers).map_err(IntoResponse::into_response)?;
+    match state.realism.set_scenario(scenario) {
+        Ok(()) => Ok(Json(state.realism.scenario())),
+        Err(FleetNetError::ValidationError(errors)) => {
+            Err((StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response())
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()),
+    }
+}
+
+async fn clear_scenario(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    crate::reports::authorize(&state.token, &headers)?;
+    // An empty scenario always validates
+    let _ = state.realism.set_scenario(Scenario::default());
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::{
+        AudioPolicy, Channel, ChannelType, Modulation, RadioChannelConfig,
+    };
+    use fleet_net_common::clock::ManualClock;
+    use fleet_net_common::types::UserId;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    const VHF_HZ: u64 = 30_000_000;
+    const UHF_HZ: u64 = 251_000_000;
+
+    fn radio_channel(id: u16, frequency_hz: u64) -> Channel {
+        Channel {
+            id: ChannelId::new(id).unwrap(),
+            name: format!("Net {id}"),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: Some(RadioChannelConfig {
+                frequency_hz,
+                modulation: Modulation::Fm,
+                max_range_m: None,
+                crypto_key_id: None,
+            }),
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    fn header(channel_id: u16) -> PacketHeader {
+        PacketHeader {
+            channel_id: ChannelId::new(channel_id).unwrap(),
+            user_id: UserId::new(1).unwrap(),
+            sequence: 0,
+            timestamp: 0,
+            signal_strength: 200,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        }
+    }
+
+    fn band(from_hz: u64, to_hz: u64) -> FrequencyBand {
+        FrequencyBand { from_hz, to_hz }
+    }
+
+    fn realism(clock: &ManualClock) -> RadioRealism {
+        let realism = RadioRealism::new().with_clock(clock.shared());
+        let tree =
+            ChannelTree::from_channels(vec![radio_channel(1, VHF_HZ), radio_channel(2, UHF_HZ)])
+                .unwrap();
+        realism.update_channels(&tree);
+        realism
+    }
+
+    #[test]
+    fn test_jammers_and_interference_weaken_or_block() {
+        let clock = ManualClock::new();
+        let realism = realism(&clock);
+        let now = clock.now();
+        assert_eq!(realism.reception(&header(1), None, now), Reception::Clear);
+
+        let zone = Zone {
+            x: 0.0,
+            y: 0.0,
+            radius_m: 1_000.0,
+        };
+        realism
+            .set_scenario(Scenario {
+                hop_sets: Vec::new(),
+                jammers: vec![
+                    Jammer {
+                        name: "Barrage".to_string(),
+                        band: band(VHF_HZ, VHF_HZ),
+                        zone: None,
+                        effect: JamEffect::Degrade { signal_loss: 50 },
+                    },
+                    Jammer {
+                        name: "SAM site".to_string(),
+                        band: band(225_000_000, 400_000_000),
+                        zone: Some(zone),
+                        effect: JamEffect::Block,
+                    },
+                ],
+                interference: vec![Interference {
+                    name: "Static".to_string(),
+                    band: band(1, 100_000_000),
+                    signal_loss: 100,
+                }],
+            })
+            .unwrap();
+
+        let inside = SpeakerPosition {
+            x: 300.0,
+            y: -400.0,
+            ..SpeakerPosition::default()
+        };
+        let outside = SpeakerPosition {
+            x: 2_000.0,
+            ..inside
+        };
+        assert_eq!(
+            realism.reception(&header(1), None, now),
+            Reception::Weakened(150)
+        );
+        assert_eq!(
+            realism.reception(&header(2), Some(&inside), now),
+            Reception::Blocked
+        );
+        assert_eq!(
+            realism.reception(&header(2), Some(&outside), now),
+            Reception::Clear
+        );
+        // Without telemetry nothing places the sender in the zone
+        assert_eq!(realism.reception(&header(2), None, now), Reception::Clear);
+    }
+
+    #[test]
+    fn test_hopping_nets_are_only_jammed_on_jammed_hops() {
+        let clock = ManualClock::new();
+        let realism = realism(&clock);
+        realism
+            .set_scenario(Scenario {
+                hop_sets: vec![HopSet {
+                    name: "SINCGARS".to_string(),
+                    frequency_hz: VHF_HZ,
+                    hops_hz: vec![40_000_000, 50_000_000, 60_000_000],
+                    dwell_ms: 10,
+                }],
+                jammers: vec![Jammer {
+                    name: "Spot".to_string(),
+                    band: band(45_000_000, 55_000_000),
+                    zone: None,
+                    effect: JamEffect::Block,
+                }],
+                interference: Vec::new(),
+            })
+            .unwrap();
+
+        let receptions: Vec<_> = (0..6)
+            .map(|_| {
+                let reception = realism.reception(&header(1), None, clock.now());
+                clock.advance(Duration::from_millis(10));
+                reception
+            })
+            .collect();
+        use Reception::{Blocked, Clear};
+        assert_eq!(receptions, [Clear, Blocked, Clear, Clear, Blocked, Clear]);
+    }
+
+    #[test]
+    fn test_scenarios_are_validated() {
+        let realism = RadioRealism::new();
+        let hop_set = HopSet {
+            name: "Net".to_string(),
+            frequency_hz: VHF_HZ,
+            hops_hz: Vec::new(),
+            dwell_ms: 0,
+        };
+        let scenario = Scenario {
+            hop_sets: vec![hop_set.clone(), hop_set],
+            jammers: vec![Jammer {
+                name: " ".to_string(),
+                band: band(UHF_HZ, VHF_HZ),
+                zone: Some(Zone {
+                    x: f32::NAN,
+                    y: 0.0,
+                    radius_m: 0.0,
+                }),
+                effect: JamEffect::Block,
+            }],
+            interference: Vec::new(),
+        };
+
+        let Err(FleetNetError::ValidationError(errors)) = realism.set_scenario(scenario) else {
+            panic!("scenario should be refused");
+        };
+        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            [
+                "hop_sets[0].hops_hz: required",
+                "hop_sets[0].dwell_ms: too_short(1)",
+                "hop_sets[1].frequency_hz: duplicate",
+                "hop_sets[1].hops_hz: required",
+                "hop_sets[1].dwell_ms: too_short(1)",
+                "jammers[0].name: too_short(1)",
+                "jammers[0].band.from_hz: above_to_hz",
+                "jammers[0].zone.x: invalid_format",
+                "jammers[0].zone.radius_m: not_positive",
+            ]
+        );
+        assert!(realism.scenario().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_replaces_the_scenario() {
+        let realism = Arc::new(RadioRealism::new());
+        let router = admin_router(realism.clone(), "s3cret");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let url = format!("http://{address}/admin/realism");
+        let client = reqwest::Client::new();
+
+        let scenario = serde_json::json!({
+            "jammers": [{
+                "name": "Barrage",
+                "band": {"from_hz": 30000000, "to_hz": 88000000},
+                "effect": "degrade",
+                "signal_loss": 80
+            }]
+        });
+        let unauthorized = client.put(&url).json(&scenario).send().await.unwrap();
+        assert_eq!(unauthorized.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let loaded = client
+            .put(&url)
+            .bearer_auth("s3cret")
+            .json(&scenario)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(loaded.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            realism.scenario().jammers[0].effect,
+            JamEffect::Degrade { signal_loss: 80 }
+        );
+
+        let invalid = serde_json::json!({"hop_sets": [{
+            "name": "Net", "frequency_hz": 0, "hops_hz": [40000000], "dwell_ms": 10
+        }]});
+        let refused = client
+            .put(&url)
+            .bearer_auth("s3cret")
+            .json(&invalid)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(refused.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let errors: FieldErrors = refused.json().await.unwrap();
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["hop_sets[0].frequency_hz: out_of_range(1..=300000000000)"]
+        );
+
+        let cleared = client
+            .delete(&url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(cleared.status(), reqwest::StatusCode::NO_CONTENT);
+        let current: Scenario = client
+            .get(&url)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(current.is_empty());
+    }
+}
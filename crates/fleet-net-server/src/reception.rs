@@ -0,0 +1,310 @@
+//! Reception feedback for adaptive bitrate.
+//!
+//! Listeners send a [`ControlMessage::ReceptionReport`] every few seconds for
+//! each speaker they hear, with the packet loss and jitter their jitter
+//! buffer measured. The registry keeps the latest report from every listener
+//! and answers with a [`ControlMessage::ReceptionFeedback`] for the speaker,
+//! carrying the worst recent reception and the bitrates the audio policies
+//! of their channels allow. Their client then lowers its bitrate and adds
+//! forward error correction while listeners lose packets, and raises it
+//! again once they stop, without leaving the range the server enforces.
+//!
+//! Speakers are sent feedback at most once per [`FEEDBACK_INTERVAL`], however
+//! many listeners report.
+
+use crate::subscriptions::SubscriptionRegistry;
+use dashmap::DashMap;
+use fleet_net_common::audio::ReceptionQuality;
+use fleet_net_common::clock::{self, Clock};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::session::Session;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::ControlMessage;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a listener's report counts towards the feedback.
+pub const REPORT_TTL: Duration = Duration::from_secs(10);
+
+/// Least time between two feedback messages to the same speaker.
+pub const FEEDBACK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+struct Report {
+    quality: ReceptionQuality,
+    received: Instant,
+}
+
+pub struct ReceptionRegistry {
+    subscriptions: Arc<SubscriptionRegistry>,
+    clock: Arc<dyn Clock>,
+    /// Latest report from each listener, by speaker and channel.
+    reports: DashMap<(UserId, ChannelId), HashMap<UserId, Report>>,
+    /// When each speaker was last sent feedback.
+    last_feedback: DashMap<UserId, Instant>,
+}
+
+impl ReceptionRegistry {
+    /// Caps feedback with the audio policies known to `subscriptions`.
+    pub fn new(subscriptions: Arc<SubscriptionRegistry>) -> Self {
+        Self {
+            subscriptions,
+            clock: clock::system(),
+            reports: DashMap::new(),
+            last_feedback: DashMap::new(),
+        }
+    }
+
+    /// Ages reports and paces feedback with `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Handles a reception report from `session` and returns the feedback
+    /// to send to the speaker it is about, if some is due.
+    pub fn apply(
+        &self,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<Option<(UserId, ControlMessage)>, FleetNetError> {
+        session.ensure_interactive()?;
+        let ControlMessage::ReceptionReport {
+            user_id: speaker,
+            channel_id,
+            quality,
+        } = message
+        else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected reception_report")),
+            ));
+        };
+        if quality.loss_percent > 100 {
+            return Err(FleetNetError::invalid_field(
+                "quality.loss_percent",
+                Constraint::OutOfRange { min: 0, max: 100 },
+            ));
+        }
+        let listener = session.user.id;
+        if *speaker == listener {
+            return Err(FleetNetError::invalid_field(
+                "user_id",
+                Constraint::Invalid(Cow::Borrowed("own_reception")),
+            ));
+        }
+        if !session.subscribed_channels.contains(channel_id)
+            && session.current_channel != Some(*channel_id)
+        {
+            return Err(FleetNetError::invalid_field(
+                "channel_id",
+                Constraint::Invalid(Cow::Borrowed("not_listening")),
+            ));
+        }
+        session.update_activity();
+
+        let now = self.clock.now();
+        let worst = {
+            let mut reports = self.reports.entry((*speaker, *channel_id)).or_default();
+            reports.insert(
+                listener,
+                Report {
+                    quality: *quality,
+                    received: now,
+                },
+            );
+            reports.retain(|_, report| now.duration_since(report.received) <= REPORT_TTL);
+            reports
+                .values()
+                .fold(ReceptionQuality::default(), |worst, report| {
+                    ReceptionQuality {
+                        loss_percent: worst.loss_percent.max(report.quality.loss_percent),
+                        jitter_ms: worst.jitter_ms.max(report.quality.jitter_ms),
+                    }
+                })
+        };
+
+        if self
+            .last_feedback
+            .get(speaker)
+            .is_some_and(|sent| now.duration_since(*sent) < FEEDBACK_INTERVAL)
+        {
+            return Ok(None);
+        }
+        self.last_feedback.insert(*speaker, now);
+        let (min_bitrate, max_bitrate) = self.subscriptions.bitrate_range(*speaker, *channel_id);
+        Ok(Some((
+            *speaker,
+            ControlMessage::ReceptionFeedback {
+                channel_id: *channel_id,
+                quality: worst,
+                min_bitrate,
+                max_bitrate,
+            },
+        )))
+    }
+
+    /// Forgets a disconnected user's reports, and those about them.
+    pub fn remove_user(&self, user_id: UserId) {
+        self.reports.retain(|(speaker, _), reports| {
+            reports.remove(&user_id);
+            *speaker != user_id && !reports.is_empty()
+        });
+        self.last_feedback.remove(&user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::{AudioPolicy, Channel, ChannelTree, ChannelType};
+    use fleet_net_common::clock::ManualClock;
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::user::User;
+    use std::collections::HashSet;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    fn listener(id: u16, channel_id: ChannelId) -> Session {
+        Session {
+            id: format!("session-{id}"),
+            user: User::new(user(id)),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: HashSet::from([channel_id]),
+            permission: PermissionSet::new(),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    fn report(speaker: u16, loss_percent: u8, jitter_ms: u16) -> ControlMessage {
+        ControlMessage::ReceptionReport {
+            user_id: user(speaker),
+            channel_id: channel(1),
+            quality: ReceptionQuality {
+                loss_percent,
+                jitter_ms,
+            },
+        }
+    }
+
+    fn registry(clock: &ManualClock) -> ReceptionRegistry {
+        let mut tree = ChannelTree::new();
+        tree.insert(Channel {
+            id: channel(1),
+            name: "Guard".to_string(),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy {
+                min_bitrate: 12_000,
+                max_bitrate: 24_000,
+                ..AudioPolicy::default()
+            },
+        })
+        .unwrap();
+        let subscriptions = SubscriptionRegistry::new();
+        subscriptions.update_channels(&tree);
+        ReceptionRegistry::new(Arc::new(subscriptions)).with_clock(Arc::new(clock.clone()))
+    }
+
+    fn feedback(
+        update: Option<(UserId, ControlMessage)>,
+    ) -> Option<(UserId, ReceptionQuality, u32, u32)> {
+        update.map(|(speaker, message)| match message {
+            ControlMessage::ReceptionFeedback {
+                quality,
+                min_bitrate,
+                max_bitrate,
+                ..
+            } => (speaker, quality, min_bitrate, max_bitrate),
+            other => panic!("unexpected feedback {other:?}"),
+        })
+    }
+
+    #[test]
+    fn test_speakers_get_the_worst_reception_within_the_policy() {
+        let clock = ManualClock::new();
+        let registry = registry(&clock);
+        let mut near = listener(2, channel(1));
+        let mut far = listener(3, channel(1));
+
+        let first = registry.apply(&mut near, &report(1, 2, 10)).unwrap();
+        assert_eq!(
+            feedback(first),
+            Some((
+                user(1),
+                ReceptionQuality {
+                    loss_percent: 2,
+                    jitter_ms: 10
+                },
+                12_000,
+                24_000
+            ))
+        );
+
+        // Reports in between are folded into the next feedback
+        assert!(registry
+            .apply(&mut far, &report(1, 15, 40))
+            .unwrap()
+            .is_none());
+        clock.advance(FEEDBACK_INTERVAL);
+        let (_, quality, ..) = feedback(registry.apply(&mut near, &report(1, 1, 5)).unwrap())
+            .expect("feedback is due");
+        assert_eq!(quality.loss_percent, 15);
+        assert_eq!(quality.jitter_ms, 40);
+
+        // Once the far listener stops reporting, its loss no longer counts
+        clock.advance(REPORT_TTL);
+        let (_, quality, ..) = feedback(registry.apply(&mut near, &report(1, 1, 5)).unwrap())
+            .expect("feedback is due");
+        assert_eq!(quality.loss_percent, 1);
+    }
+
+    #[test]
+    fn test_reports_must_be_about_someone_else_heard_on_the_channel() {
+        let clock = ManualClock::new();
+        let registry = registry(&clock);
+        let mut session = listener(2, channel(1));
+
+        for message in [
+            report(2, 0, 0),
+            report(1, 101, 0),
+            ControlMessage::ReceptionReport {
+                user_id: user(1),
+                channel_id: channel(9),
+                quality: ReceptionQuality::default(),
+            },
+            ControlMessage::Ping,
+        ] {
+            assert!(
+                registry.apply(&mut session, &message).is_err(),
+                "{message:?}"
+            );
+        }
+
+        registry.apply(&mut session, &report(1, 3, 0)).unwrap();
+        registry.remove_user(user(2));
+        assert!(registry.reports.is_empty());
+    }
+}
@@ -0,0 +1,575 @@
+//! Abuse reports and the admin triage queue.
+//!
+//! When a user files a [`ControlMessage::ReportUser`], the server attaches the
+//! speaker metadata for the reporter's channel from the last few seconds: who
+//! transmitted, when, and for how long. No audio is ever retained.
+//!
+//! Admins review and resolve reports through the HTTP API built by
+//! [`admin_router`], authenticated with a static bearer token.
+//!
+//! Each reporter may file [`MAX_REPORTS_PER_WINDOW`] reports per
+//! [`REPORTER_RATE_WINDOW`], and the queue holds at most [`MAX_REPORTS`];
+//! once full, the oldest resolved report makes room, and new reports are
+//! refused while every stored report is still open.
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dashmap::DashMap;
+use fleet_net_common::clock::{self, Clock};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::secret::constant_time_eq;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::{ControlMessage, ReportReason};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How much speaker history is attached to a report.
+pub const DEFAULT_REPORT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Most reports kept in the queue, open and resolved together.
+pub const MAX_REPORTS: usize = 10_000;
+
+/// Reports one user may file per [`REPORTER_RATE_WINDOW`].
+pub const MAX_REPORTS_PER_WINDOW: usize = 5;
+
+pub const REPORTER_RATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// A single voice transmission, without any audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpeakerEvent {
+    pub user_id: UserId,
+    pub channel_id: ChannelId,
+    /// Unix time the transmission started, in milliseconds.
+    pub started_at_ms: u64,
+    pub duration_ms: u32,
+}
+
+impl SpeakerEvent {
+    fn ended_at_ms(&self) -> u64 {
+        self.started_at_ms + u64::from(self.duration_ms)
+    }
+}
+
+/// Rolling window of recent transmissions across all channels.
+///
+/// Events are evicted by when they were recorded according to the clock,
+/// never by their own timestamps, so a skewed timestamp cannot pin an event
+/// in memory.
+pub struct SpeakerHistory {
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    events: Mutex<VecDeque<(Instant, SpeakerEvent)>>,
+}
+
+impl SpeakerHistory {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            clock: clock::system(),
+            events: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Ages events with `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Records a finished transmission, evicting anything outside the window.
+    pub fn record(&self, event: SpeakerEvent) {
+        let now = self.clock.now();
+        let mut events = self.events.lock().unwrap();
+        events.push_back((now, event));
+        self.evict(&mut events, now);
+    }
+
+    /// Transmissions in `channel_id` that ended within the window before `now_ms`.
+    pub fn recent(&self, channel_id: ChannelId, now_ms: u64) -> Vec<SpeakerEvent> {
+        let cutoff = now_ms.saturating_sub(self.window_ms());
+        let mut events = self.events.lock().unwrap();
+        self.evict(&mut events, self.clock.now());
+        events
+            .iter()
+            .map(|(_, event)| event)
+            .filter(|event| event.channel_id == channel_id && event.ended_at_ms() >= cutoff)
+            .copied()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn evict(&self, events: &mut VecDeque<(Instant, SpeakerEvent)>, now: Instant) {
+        while events
+            .front()
+            .is_some_and(|(recorded_at, _)| now.duration_since(*recorded_at) > self.window)
+        {
+            events.pop_front();
+        }
+    }
+
+    fn window_ms(&self) -> u64 {
+        self.window.as_millis() as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    Resolved {
+        resolution: String,
+        resolved_at_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbuseReport {
+    pub id: u64,
+    pub reporter: UserId,
+    pub target: UserId,
+    pub reason: ReportReason,
+    pub context: Option<String>,
+    /// Channel the reporter was in when filing the report.
+    pub channel_id: Option<ChannelId>,
+    pub created_at_ms: u64,
+    pub speaker_activity: Vec<SpeakerEvent>,
+    pub status: ReportStatus,
+}
+
+/// Maximum length of the reporter's free-form context.
+const MAX_CONTEXT_LEN: usize = 1000;
+
+pub struct ReportQueue {
+    next_id: AtomicU64,
+    reports: DashMap<u64, AbuseReport>,
+    history: Arc<SpeakerHistory>,
+    clock: Arc<dyn Clock>,
+    max_reports: usize,
+    /// When each reporter's recent reports were filed, oldest first.
+    filed: DashMap<UserId, VecDeque<Instant>>,
+    /// Serializes making room so concurrent reports cannot overfill the queue.
+    admission: Mutex<()>,
+}
+
+impl ReportQueue {
+    pub fn new(history: Arc<SpeakerHistory>) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            reports: DashMap::new(),
+            history,
+            clock: clock::system(),
+            max_reports: MAX_REPORTS,
+            filed: DashMap::new(),
+            admission: Mutex::new(()),
+        }
+    }
+
+    /// Rate limits reporters with `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Keeps at most `max_reports` instead of [`MAX_REPORTS`].
+    pub fn with_max_reports(mut self, max_reports: usize) -> Self {
+        self.max_reports = max_reports;
+        self
+    }
+
+    pub fn history(&self) -> &Arc<SpeakerHistory> {
+        &self.history
+    }
+
+    /// Files a report from a [`ControlMessage::ReportUser`] and returns its id.
+    pub fn submit(
+        &self,
+        reporter: UserId,
+        channel_id: Option<ChannelId>,
+        message: &ControlMessage,
+    ) -> Result<u64, FleetNetError> {
+        let ControlMessage::ReportUser {
+            target,
+            reason,
+            context,
+        } = message
+        else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected report_user")),
+            ));
+        };
+
+        if *target == reporter {
+            return Err(FleetNetError::invalid_field(
+                "target",
+                Constraint::Invalid(Cow::Borrowed("self")),
+            ));
+        }
+        if context
+            .as_ref()
+            .is_some_and(|context| context.len() > MAX_CONTEXT_LEN)
+        {
+            return Err(FleetNetError::invalid_field(
+                "context",
+                Constraint::TooLong(MAX_CONTEXT_LEN),
+            ));
+        }
+
+        let _admission = self.admission.lock().unwrap();
+        self.make_room()?;
+        self.check_rate(reporter)?;
+
+        let now = unix_millis();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let speaker_activity = channel_id
+            .map(|channel_id| self.history.recent(channel_id, now))
+            .unwrap_or_default();
+
+        self.reports.insert(
+            id,
+            AbuseReport {
+                id,
+                reporter,
+                target: *target,
+                reason: *reason,
+                context: context.clone(),
+                channel_id,
+                created_at_ms: now,
+                speaker_activity,
+                status: ReportStatus::Open,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Evicts the oldest resolved report when the queue is full, or refuses
+    /// the new one if every report is still open.
+    fn make_room(&self) -> Result<(), FleetNetError> {
+        if self.reports.len() < self.max_reports {
+            return Ok(());
+        }
+        let oldest_resolved = self
+            .reports
+            .iter()
+            .filter(|entry| entry.status != ReportStatus::Open)
+            .map(|entry| entry.id)
+            .min();
+        match oldest_resolved {
+            Some(id) => {
+                self.reports.remove(&id);
+                Ok(())
+            }
+            None => Err(FleetNetError::RateLimited(Cow::Borrowed(
+                "The report queue is full",
+            ))),
+        }
+    }
+
+    /// Counts a report against `reporter`'s allowance for the window.
+    fn check_rate(&self, reporter: UserId) -> Result<(), FleetNetError> {
+        let now = self.clock.now();
+        let mut filed = self.filed.entry(reporter).or_default();
+        while filed
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > REPORTER_RATE_WINDOW)
+        {
+            filed.pop_front();
+        }
+        if filed.len() >= MAX_REPORTS_PER_WINDOW {
+            return Err(FleetNetError::RateLimited(Cow::Borrowed(
+                "Too many reports filed recently",
+            )));
+        }
+        filed.push_back(now);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u64) -> Option<AbuseReport> {
+        self.reports.get(&id).map(|entry| entry.clone())
+    }
+
+    /// Reports ordered oldest first, optionally only those still open.
+    pub fn list(&self, open_only: bool) -> Vec<AbuseReport> {
+        let mut reports: Vec<_> = self
+            .reports
+            .iter()
+            .filter(|entry| !open_only || entry.status == ReportStatus::Open)
+            .map(|entry| entry.clone())
+            .collect();
+        reports.sort_by_key(|report| report.id);
+        reports
+    }
+
+    pub fn resolve(&self, id: u64, resolution: String) -> Result<AbuseReport, FleetNetError> {
+        let mut report = self
+            .reports
+            .get_mut(&id)
+            .ok_or(FleetNetError::invalid_field("id", Constraint::NotFound))?;
+
+        report.status = ReportStatus::Resolved {
+            resolution,
+            resolved_at_ms: unix_millis(),
+        };
+        Ok(report.clone())
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Clone)]
+struct AdminState {
+    queue: Arc<ReportQueue>,
+    token: Arc<str>,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    open: bool,
+}
+
+#[derive(Deserialize)]
+struct ResolveRequest {
+    resolution: String,
+}
+
+/// Builds the admin API:
+///
+/// - `GET /admin/reports[?open=true]`
+/// - `GET /admin/reports/{id}`
+/// - `POST /admin/reports/{id}/resolve` with `{"resolution": "..."}`
+///
+/// Every request must carry `Authorization: Bearer <admin_token>`.
+pub fn admin_router(queue: Arc<ReportQueue>, admin_token: &str) -> Router {
+    Router::new()
+        .route("/admin/reports", get(list_reports))
+        .route("/admin/reports/{id}", get(get_report))
+        .route("/admin/reports/{id}/resolve", post(resolve_report))
+        .with_state(AdminState {
+            queue,
+            token: admin_token.into(),
+        })
+}
+
+/// Checks the bearer token of an admin API request against `token`.
+pub(crate) fn authorize(token: &str, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let presented = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    // An empty token would let in requests without one
+    if !token.is_empty() && constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+async fn list_reports(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<AbuseReport>>, StatusCode> {
+    authorize(&state.token, &headers)?;
+    Ok(Json(state.queue.list(query.open)))
+}
+
+async fn get_report(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Json<AbuseReport>, StatusCode> {
+    authorize(&state.token, &headers)?;
+    state.queue.get(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn resolve_report(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+    Json(request): Json<ResolveRequest>,
+) -> Result<Json<AbuseReport>, StatusCode> {
+    authorize(&state.token, &headers)?;
+    state
+        .queue
+        .resolve(id, request.resolution)
+        .map(Json)
+        .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::clock::ManualClock;
+    use fleet_net_common::error::FleetNetErrorCode;
+    use tokio::net::TcpListener;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    fn report(target: UserId) -> ControlMessage {
+        ControlMessage::ReportUser {
+            target,
+            reason: ReportReason::OffensiveAudio,
+            context: Some("Shouting in ops".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_report_captures_recent_speakers_in_channel() {
+        let history = Arc::new(SpeakerHistory::new(Duration::from_secs(30)));
+        let now = unix_millis();
+        let event = |user_id, channel_id, ago_ms: u64| SpeakerEvent {
+            user_id,
+            channel_id,
+            started_at_ms: now - ago_ms,
+            duration_ms: 500,
+        };
+        history.record(event(user(9), channel(1), 120_000)); // Outside the window
+        history.record(event(user(5), channel(1), 10_000));
+        history.record(event(user(6), channel(2), 5_000)); // Other channel
+        history.record(event(user(5), channel(1), 2_000));
+
+        let queue = ReportQueue::new(history);
+        let id = queue
+            .submit(user(2), Some(channel(1)), &report(user(5)))
+            .unwrap();
+        let filed = queue.get(id).unwrap();
+
+        assert_eq!(filed.target, user(5));
+        assert_eq!(filed.status, ReportStatus::Open);
+        let speakers: Vec<_> = filed.speaker_activity.iter().map(|e| e.user_id).collect();
+        assert_eq!(speakers, vec![user(5), user(5)]);
+
+        assert!(matches!(
+            queue.submit(user(5), Some(channel(1)), &report(user(5))),
+            Err(FleetNetError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_history_evicts_by_clock_not_event_time() {
+        let clock = ManualClock::new();
+        let history = SpeakerHistory::new(Duration::from_secs(30)).with_clock(clock.shared());
+        // A timestamp far in the future must not keep the event around
+        history.record(SpeakerEvent {
+            user_id: user(1),
+            channel_id: channel(1),
+            started_at_ms: u64::MAX / 2,
+            duration_ms: 500,
+        });
+        assert_eq!(history.len(), 1);
+
+        clock.advance(Duration::from_secs(31));
+        assert!(history.recent(channel(1), unix_millis()).is_empty());
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_reports_are_rate_limited_and_capped() {
+        let clock = ManualClock::new();
+        let history = Arc::new(SpeakerHistory::new(DEFAULT_REPORT_WINDOW));
+        let queue = ReportQueue::new(history)
+            .with_clock(clock.shared())
+            .with_max_reports(MAX_REPORTS_PER_WINDOW + 1);
+
+        for _ in 0..MAX_REPORTS_PER_WINDOW {
+            queue.submit(user(1), None, &report(user(2))).unwrap();
+        }
+        let limited = queue.submit(user(1), None, &report(user(2))).unwrap_err();
+        assert_eq!(limited.code(), FleetNetErrorCode::RateLimited);
+
+        // Another reporter fills the last slot, after which the queue is full
+        let last = queue.submit(user(3), None, &report(user(2))).unwrap();
+        let full = queue.submit(user(4), None, &report(user(2))).unwrap_err();
+        assert_eq!(full.code(), FleetNetErrorCode::RateLimited);
+
+        // Resolving a report lets the next one evict it
+        queue.resolve(1, "Handled".to_string()).unwrap();
+        queue.submit(user(4), None, &report(user(2))).unwrap();
+        assert!(queue.get(1).is_none());
+        assert!(queue.get(last).is_some());
+
+        // The allowance comes back once the window has passed
+        queue.resolve(2, "Handled".to_string()).unwrap();
+        clock.advance(REPORTER_RATE_WINDOW + Duration::from_secs(1));
+        queue.submit(user(1), None, &report(user(2))).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_lists_and_resolves_reports() {
+        let queue = Arc::new(ReportQueue::new(Arc::new(SpeakerHistory::new(
+            DEFAULT_REPORT_WINDOW,
+        ))));
+        let id = queue.submit(user(1), None, &report(user(4))).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = admin_router(queue.clone(), "s3cret");
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = reqwest::Client::new();
+        let base = format!("http://{addr}/admin/reports");
+
+        let response = client.get(&base).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let open: Vec<AbuseReport> = client
+            .get(format!("{base}?open=true"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(open.len(), 1);
+
+        let resolved: AbuseReport = client
+            .post(format!("{base}/{id}/resolve"))
+            .bearer_auth("s3cret")
+            .json(&serde_json::json!({ "resolution": "Warned user" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(matches!(resolved.status, ReportStatus::Resolved { .. }));
+        assert!(queue.list(true).is_empty());
+
+        let response = client
+            .get(format!("{base}/999"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
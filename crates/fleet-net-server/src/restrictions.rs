@@ -0,0 +1,341 @@
+//! Timed mutes, deafens and bans.
+//!
+//! Moderators restrict users with [`ControlMessage::RestrictUser`]. The
+//! registry keeps every active restriction, lifts them as they expire and
+//! publishes each change as a [`ControlMessage::UserRestricted`] or
+//! [`ControlMessage::RestrictionLifted`] for connections to broadcast.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::DashMap;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::restriction::{RestrictionKind, TimedRestriction};
+use fleet_net_common::session::Session;
+use fleet_net_common::types::UserId;
+use fleet_net_common::validation::Constraint;
+use fleet_net_protocol::message::ControlMessage;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// How often expired restrictions are looked for.
+pub const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Restriction changes buffered for slow subscribers.
+const CHANGE_BUFFER: usize = 64;
+
+pub struct RestrictionRegistry {
+    active: DashMap<UserId, Vec<TimedRestriction>>,
+    changes: broadcast::Sender<ControlMessage>,
+    limits: ServerLimits,
+}
+
+impl RestrictionRegistry {
+    pub fn new() -> Self {
+        Self {
+            active: DashMap::new(),
+            changes: broadcast::channel(CHANGE_BUFFER).0,
+            limits: ServerLimits::default(),
+        }
+    }
+
+    /// Checks requests against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Receives every restriction imposed, lifted or expired from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ControlMessage> {
+        self.changes.subscribe()
+    }
+
+    /// Handles a restrict or lift request from `issuer` and returns the
+    /// state change, which is also published to subscribers.
+    pub fn apply(
+        &self,
+        issuer: &Session,
+        message: &ControlMessage,
+        now: DateTime<Utc>,
+    ) -> Result<ControlMessage, FleetNetError> {
+        issuer.ensure_interactive()?;
+        let change = match message {
+            ControlMessage::RestrictUser {
+                target,
+                kind,
+                duration_secs,
+                reason,
+            } => {
+                Self::check_issuer(issuer, *target, *kind)?;
+                if reason
+                    .as_ref()
+                    .is_some_and(|reason| reason.len() > self.limits.max_reason_len)
+                {
+                    return Err(FleetNetError::invalid_field(
+                        "reason",
+                        Constraint::TooLong(self.limits.max_reason_len),
+                    ));
+                }
+                let expires_at = match duration_secs {
+                    Some(secs) => Some(
+                        i64::try_from(*secs)
+                            .ok()
+                            .and_then(TimeDelta::try_seconds)
+                            .and_then(|duration| now.checked_add_signed(duration))
+                            .ok_or(FleetNetError::invalid_field(
+                                "duration_secs",
+                                Constraint::Invalid(Cow::Borrowed("too_far_in_future")),
+                            ))?,
+                    ),
+                    None => None,
+                };
+
+                let restriction = TimedRestriction {
+                    kind: *kind,
+                    expires_at,
+                    reason: reason.clone(),
+                    issued_by: issuer.user.id,
+                };
+                // A new restriction replaces an earlier one of the same kind.
+                let mut restrictions = self.active.entry(*target).or_default();
+                restrictions.retain(|existing| existing.kind != *kind);
+                restrictions.push(restriction.clone());
+
+                ControlMessage::UserRestricted {
+                    user_id: *target,
+                    restriction,
+                }
+            }
+            ControlMessage::LiftRestriction { target, kind } => {
+                Self::check_issuer(issuer, *target, *kind)?;
+                if !self.remove(*target, |existing| existing.kind == *kind) {
+                    return Err(FleetNetError::invalid_field("kind", Constraint::NotFound));
+                }
+                ControlMessage::RestrictionLifted {
+                    user_id: *target,
+                    kind: *kind,
+                }
+            }
+            _ => {
+                return Err(FleetNetError::invalid_field(
+                    "type",
+                    Constraint::Invalid(Cow::Borrowed(
+                        "expected restrict_user or lift_restriction",
+                    )),
+                ))
+            }
+        };
+
+        // Nobody listening is fine; the registry is still up to date.
+        let _ = self.changes.send(change.clone());
+        Ok(change)
+    }
+
+    /// The unexpired restriction of `kind` on `user_id`, if any, e.g. to
+    /// refuse a banned user at login.
+    pub fn active(
+        &self,
+        user_id: UserId,
+        kind: RestrictionKind,
+        now: DateTime<Utc>,
+    ) -> Option<TimedRestriction> {
+        self.active.get(&user_id)?.iter().find_map(|restriction| {
+            (restriction.kind == kind && !restriction.is_expired(now)).then(|| restriction.clone())
+        })
+    }
+
+    /// Every restriction on `user_id`, including ones about to be lifted.
+    pub fn restrictions(&self, user_id: UserId) -> Vec<TimedRestriction> {
+        self.active
+            .get(&user_id)
+            .map(|restrictions| restrictions.clone())
+            .unwrap_or_default()
+    }
+
+    /// Lifts every restriction expired at `now`, publishing and returning
+    /// a [`ControlMessage::RestrictionLifted`] for each.
+    pub fn lift_expired(&self, now: DateTime<Utc>) -> Vec<ControlMessage> {
+        let mut lifted = Vec::new();
+        self.active.retain(|&user_id, restrictions| {
+            restrictions.retain(|restriction| {
+                let expired = restriction.is_expired(now);
+                if expired {
+                    lifted.push(ControlMessage::RestrictionLifted {
+                        user_id,
+                        kind: restriction.kind,
+                    });
+                }
+                !expired
+            });
+            !restrictions.is_empty()
+        });
+
+        for change in &lifted {
+            let _ = self.changes.send(change.clone());
+        }
+        lifted
+    }
+
+    /// Lifts expired restrictions every [`EXPIRY_SWEEP_INTERVAL`].
+    pub fn spawn_expiry(self: &Arc<Self>) -> JoinHandle<()> {
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                registry.lift_expired(Utc::now());
+            }
+        })
+    }
+
+    fn check_issuer(
+        issuer: &Session,
+        target: UserId,
+        kind: RestrictionKind,
+    ) -> Result<(), FleetNetError> {
+        if !issuer.permission.has(kind.required_permission()) {
+            return Err(FleetNetError::PermissionError(Cow::Owned(format!(
+                "Missing permission to {kind:?} users"
+            ))));
+        }
+        if target == issuer.user.id {
+            return Err(FleetNetError::invalid_field(
+                "target",
+                Constraint::Invalid(Cow::Borrowed("self")),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Removes the restrictions on `user_id` matching `predicate`, returning
+    /// whether any were removed.
+    fn remove(&self, user_id: UserId, predicate: impl Fn(&TimedRestriction) -> bool) -> bool {
+        let removed = match self.active.get_mut(&user_id) {
+            Some(mut restrictions) => {
+                let before = restrictions.len();
+                restrictions.retain(|restriction| !predicate(restriction));
+                restrictions.len() != before
+            }
+            None => false,
+        };
+        self.active
+            .remove_if(&user_id, |_, restrictions| restrictions.is_empty());
+        removed
+    }
+}
+
+impl Default for RestrictionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::permission::{PermissionSet, Permissions};
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::user::User;
+    use std::time::Instant;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn moderator(permissions: Permissions) -> Session {
+        Session {
+            id: "moderator".to_string(),
+            user: User::new(user(1)),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    fn restrict(kind: RestrictionKind, duration_secs: Option<u64>) -> ControlMessage {
+        ControlMessage::RestrictUser {
+            target: user(2),
+            kind,
+            duration_secs,
+            reason: Some("Hot mic".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_expired_restrictions_are_lifted_and_broadcast() {
+        let registry = RestrictionRegistry::new();
+        let mut changes = registry.subscribe();
+        let issuer = moderator(Permissions::MUTE_USERS | Permissions::BAN_USERS);
+        let now = Utc::now();
+
+        registry
+            .apply(&issuer, &restrict(RestrictionKind::Mute, Some(60)), now)
+            .unwrap();
+        registry
+            .apply(&issuer, &restrict(RestrictionKind::Ban, None), now)
+            .unwrap();
+        assert!(matches!(
+            changes.try_recv(),
+            Ok(ControlMessage::UserRestricted { user_id, .. }) if user_id == user(2)
+        ));
+        assert!(registry
+            .active(user(2), RestrictionKind::Mute, now)
+            .is_some());
+
+        // Only the timed mute expires
+        let later = now + TimeDelta::seconds(60);
+        assert!(registry
+            .active(user(2), RestrictionKind::Mute, later)
+            .is_none());
+        let lifted = registry.lift_expired(later);
+        assert!(matches!(
+            lifted.as_slice(),
+            [ControlMessage::RestrictionLifted {
+                kind: RestrictionKind::Mute,
+                ..
+            }]
+        ));
+        assert!(matches!(
+            changes.try_recv(),
+            Ok(ControlMessage::UserRestricted { .. })
+        ));
+        assert!(matches!(
+            changes.try_recv(),
+            Ok(ControlMessage::RestrictionLifted {
+                kind: RestrictionKind::Mute,
+                ..
+            })
+        ));
+        assert_eq!(registry.restrictions(user(2)).len(), 1);
+
+        let lift = ControlMessage::LiftRestriction {
+            target: user(2),
+            kind: RestrictionKind::Ban,
+        };
+        registry.apply(&issuer, &lift, later).unwrap();
+        assert!(registry.restrictions(user(2)).is_empty());
+        assert!(registry.apply(&issuer, &lift, later).is_err());
+    }
+
+    #[test]
+    fn test_restricting_requires_permission() {
+        let registry = RestrictionRegistry::new();
+        let issuer = moderator(Permissions::MUTE_USERS);
+        let now = Utc::now();
+
+        let result = registry.apply(&issuer, &restrict(RestrictionKind::Ban, Some(60)), now);
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+
+        let too_long = restrict(RestrictionKind::Mute, Some(u64::MAX));
+        assert!(registry.apply(&issuer, &too_long, now).is_err());
+        assert!(registry.restrictions(user(2)).is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Server roles and role assignments.
+//!
+//! The registry holds the server's role definitions, including the built-in
+//! `@everyone` role, and the roles assigned to users directly. Before any
+//! permission check the roles a user holds are merged from their
+//! assignments and their Discord roles, see [`RoleRegistry::user_roles`].
+
+use fleet_net_common::channel::ChannelTree;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::role::{ensure_can_manage, Role, RoleAssignments};
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::user::User;
+use fleet_net_common::validation::Constraint;
+use std::borrow::Cow;
+use std::sync::RwLock;
+
+pub struct RoleRegistry {
+    roles: RwLock<Vec<Role>>,
+    assignments: RwLock<RoleAssignments>,
+}
+
+impl RoleRegistry {
+    /// A registry with only `@everyone`, granting `everyone` to all users.
+    pub fn new(everyone: Permissions) -> Self {
+        Self {
+            roles: RwLock::new(vec![Role::everyone(everyone)]),
+            assignments: RwLock::new(RoleAssignments::new()),
+        }
+    }
+
+    /// Adds `role`, or replaces the role with the same id.
+    pub fn upsert_role(&self, role: Role) {
+        let mut roles = self.roles.write().unwrap();
+        match roles.iter_mut().find(|existing| existing.id == role.id) {
+            Some(existing) => *existing = role,
+            None => roles.push(role),
+        }
+    }
+
+    /// Deletes a role and every assignment of it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error for `@everyone`, which cannot be removed.
+    pub fn remove_role(&self, role_id: &str) -> Result<Option<Role>, FleetNetError> {
+        let mut roles = self.roles.write().unwrap();
+        if roles
+            .iter()
+            .any(|role| role.id == role_id && role.is_everyone())
+        {
+            return Err(FleetNetError::invalid_field(
+                "role_id",
+                Constraint::Invalid(Cow::Borrowed("implicit_role")),
+            ));
+        }
+        let removed = roles
+            .iter()
+            .position(|role| role.id == role_id)
+            .map(|index| roles.remove(index));
+        self.assignments.write().unwrap().remove_role(role_id);
+        Ok(removed)
+    }
+
+    pub fn role(&self, role_id: &str) -> Option<Role> {
+        self.roles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|role| role.id == role_id)
+            .cloned()
+    }
+
+    /// Every role definition, `@everyone` first.
+    pub fn roles(&self) -> Vec<Role> {
+        self.roles.read().unwrap().clone()
+    }
+
+    /// Replaces every assignment, e.g. with ones loaded at startup.
+    pub fn load_assignments(&self, assignments: RoleAssignments) {
+        *self.assignments.write().unwrap() = assignments;
+    }
+
+    /// A copy of every assignment, e.g. to persist them.
+    pub fn assignments(&self) -> RoleAssignments {
+        self.assignments.read().unwrap().clone()
+    }
+
+    /// Every role `user` holds, highest priority first: `@everyone`, the
+    /// roles assigned to them and those their Discord roles map to.
+    pub fn user_roles(&self, user: &User) -> Vec<Role> {
+        self.assignments.read().unwrap().resolve(
+            &self.roles.read().unwrap(),
+            user.id,
+            &user.guild_roles,
+        )
+    }
+
+    /// The priority of `user`'s highest role, ranking them for
+    /// [preemption](fleet_net_common::channel::AudioPolicy::preemption).
+    pub fn transmit_priority(&self, user: &User) -> u32 {
+        self.user_roles(user)
+            .first()
+            .map_or(u32::MAX, |role| role.priority)
+    }
+
+    /// The permissions `user` has in `channel_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the channel is not in the tree.
+    pub fn permissions_in(
+        &self,
+        user: &User,
+        tree: &ChannelTree,
+        channel_id: ChannelId,
+    ) -> Result<Permissions, FleetNetError> {
+        tree.user_permissions(channel_id, &self.user_roles(user))
+    }
+
+    /// Assigns `role_id` to `target` on behalf of `actor`, returning whether
+    /// it was new.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if the role does not exist or is
+    /// `@everyone`, and a permission error if `actor` may not manage it.
+    pub fn assign(
+        &self,
+        actor: &User,
+        target: UserId,
+        role_id: &str,
+    ) -> Result<bool, FleetNetError> {
+        self.ensure_actor_manages(actor, role_id)?;
+        self.assignments.write().unwrap().assign(target, role_id)
+    }
+
+    /// Takes `role_id` away from `target` on behalf of `actor`, returning
+    /// whether they held it.
+    ///
+    /// # Errors
+    ///
+    /// As for [`assign`](Self::assign).
+    pub fn unassign(
+        &self,
+        actor: &User,
+        target: UserId,
+        role_id: &str,
+    ) -> Result<bool, FleetNetError> {
+        self.ensure_actor_manages(actor, role_id)?;
+        Ok(self.assignments.write().unwrap().unassign(target, role_id))
+    }
+
+    fn ensure_actor_manages(&self, actor: &User, role_id: &str) -> Result<(), FleetNetError> {
+        let role = self
+            .role(role_id)
+            .ok_or_else(|| FleetNetError::invalid_field("role_id", Constraint::NotFound))?;
+        ensure_can_manage(&self.user_roles(actor), &role)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::{AudioPolicy, Channel, ChannelType};
+    use std::collections::HashMap;
+
+    fn user(id: u16, guild_roles: &[&str]) -> User {
+        let mut user = User::new(UserId::new(id).unwrap());
+        user.guild_roles = guild_roles.iter().map(|role| role.to_string()).collect();
+        user
+    }
+
+    #[test]
+    fn test_assigned_and_discord_roles_are_merged() {
+        let registry = RoleRegistry::new(Permissions::CONNECT | Permissions::LISTEN);
+        registry.upsert_role(
+            Role::new("officer".to_string(), "Officer".to_string())
+                .with_permissions(Permissions::CONNECT | Permissions::MANAGE_ROLES)
+                .with_priority(1)
+                .with_discord_roles(vec!["discord_officer".to_string()]),
+        );
+        registry.upsert_role(
+            Role::new("pilot".to_string(), "Pilot".to_string())
+                .with_permissions(Permissions::CONNECT | Permissions::LISTEN | Permissions::SPEAK)
+                .with_priority(10),
+        );
+        let channel_id = ChannelId::new(1).unwrap();
+        let mut tree = ChannelTree::new();
+        tree.insert(Channel {
+            id: channel_id,
+            name: "Flight".to_string(),
+            description: None,
+            channel_type: ChannelType::Voice,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        })
+        .unwrap();
+
+        let officer = user(1, &["discord_officer"]);
+        let recruit = user(2, &[]);
+        let permissions = registry
+            .permissions_in(&recruit, &tree, channel_id)
+            .unwrap();
+        assert_eq!(permissions, Permissions::CONNECT | Permissions::LISTEN);
+
+        // Assigned by the server rather than through Discord
+        assert!(registry.assign(&officer, recruit.id, "pilot").unwrap());
+        let permissions = registry
+            .permissions_in(&recruit, &tree, channel_id)
+            .unwrap();
+        assert!(permissions.contains(Permissions::SPEAK));
+        assert_eq!(registry.assignments().roles_of(recruit.id).count(), 1);
+        assert_eq!(registry.transmit_priority(&officer), 1);
+        assert_eq!(registry.transmit_priority(&recruit), 10);
+
+        // Recruits cannot hand out roles, and @everyone cannot be assigned
+        assert!(matches!(
+            registry.assign(&recruit, officer.id, "pilot"),
+            Err(FleetNetError::PermissionError(_))
+        ));
+        assert!(registry.assign(&officer, recruit.id, "@everyone").is_err());
+        assert!(registry.remove_role("@everyone").is_err());
+
+        registry.remove_role("pilot").unwrap();
+        assert_eq!(registry.user_roles(&recruit).len(), 1);
+        assert_eq!(registry.transmit_priority(&recruit), u32::MAX);
+    }
+}
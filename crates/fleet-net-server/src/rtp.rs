@@ -0,0 +1,537 @@
+//! RTP export of channel audio.
+//!
+//! An [`RtpExporter`] listens to a channel like any other subscriber and
+//! re-sends the voice it hears as standard RTP/Opus (RFC 7587) to a fixed
+//! destination, where a streaming or production setup such as OBS, ffmpeg
+//! or a broadcast mixer picks it up. [`RtpExporter::sdp`] describes the
+//! stream for receivers that want a session description.
+//!
+//! The server never decodes audio, so it cannot mix speakers itself.
+//! Instead the exporter sends either one stream per speaker, each with its
+//! own SSRC for the receiving mixer to combine, or a single stream that
+//! follows whoever is talking, see [`RtpExportMode`]. Either way the Opus
+//! frames are passed through unchanged.
+
+use crate::subscriptions::{SubscriptionRegistry, TRANSMISSION_GAP};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::cluster::RelaySubscriber;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::packet::AudioPacket;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tracing::{debug, info};
+
+/// First dynamic payload type, the one most Opus streams use.
+pub const OPUS_PAYLOAD_TYPE: u8 = 111;
+
+/// RTP clock ticks per millisecond; Opus streams always run at 48 kHz.
+pub(crate) const TICKS_PER_MS: u32 = 48;
+
+/// RTP version 2, with no padding, extension or contributing sources.
+const RTP_VERSION: u8 = 2 << 6;
+
+/// How the speakers of a channel are laid out in RTP streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtpExportMode {
+    /// One stream per speaker, with SSRC `ssrc_base + user id`, so a mixer
+    /// can level and pan each voice separately.
+    PerSpeaker,
+    /// One stream carrying whoever holds the floor. Someone talking over
+    /// the current speaker is dropped until the speaker has been silent for
+    /// [`TRANSMISSION_GAP`].
+    ActiveSpeaker,
+}
+
+/// Settings of an [`RtpExporter`].
+#[derive(Debug, Clone)]
+pub struct RtpExportConfig {
+    /// Channel whose audio is exported, including what reaches it over a
+    /// linked radio net.
+    pub channel_id: ChannelId,
+    /// Where the RTP packets are sent.
+    pub destination: SocketAddr,
+    pub mode: RtpExportMode,
+    /// User id the exporter listens to the channel as. It must not clash
+    /// with ids of real users.
+    pub listener_id: UserId,
+    /// Address the exporter's socket binds to; the router must reach it.
+    pub bind_ip: IpAddr,
+    pub payload_type: u8,
+    /// SSRC of the active speaker stream, and base of the per-speaker ones.
+    pub ssrc_base: u32,
+}
+
+impl RtpExportConfig {
+    /// Per-speaker export of `channel_id` from the same host as the voice
+    /// router.
+    pub fn new(channel_id: ChannelId, destination: SocketAddr, listener_id: UserId) -> Self {
+        Self {
+            channel_id,
+            destination,
+            mode: RtpExportMode::PerSpeaker,
+            listener_id,
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            payload_type: OPUS_PAYLOAD_TYPE,
+            // "FN" in the high bytes, so exported streams are recognizable
+            ssrc_base: 0x464E_0000,
+        }
+    }
+}
+
+/// The fixed 12-byte header of an RTP packet (RFC 3550, section 5.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpHeader {
+    /// Set on the first packet after silence, so receivers can resync.
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    pub const SIZE: usize = 12;
+
+    /// Encodes the header followed by `payload`.
+    pub fn packet(&self, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(Self::SIZE + payload.len());
+        packet.push(RTP_VERSION);
+        packet.push(u8::from(self.marker) << 7 | self.payload_type & 0x7F);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// Splits an RTP packet into its header and payload.
+    ///
+    /// Returns `None` for anything but a version 2 packet without padding,
+    /// extension or contributing sources, which is all the exporter sends.
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        let (header, payload) = packet.split_at_checked(Self::SIZE)?;
+        if header[0] != RTP_VERSION {
+            return None;
+        }
+        let header = Self {
+            marker: header[1] & 0x80 != 0,
+            payload_type: header[1] & 0x7F,
+            sequence: u16::from_be_bytes([header[2], header[3]]),
+            timestamp: u32::from_be_bytes(header[4..8].try_into().ok()?),
+            ssrc: u32::from_be_bytes(header[8..12].try_into().ok()?),
+        };
+        Some((header, payload))
+    }
+}
+
+/// Audio in an Opus packet in microseconds, from its TOC byte and frame
+/// count (RFC 6716, section 3.1).
+pub fn opus_packet_duration_us(packet: &[u8]) -> Option<u32> {
+    let &toc = packet.first()?;
+    let config = toc >> 3;
+    let frame_us = match config {
+        // SILK: 10, 20, 40 and 60 ms for each bandwidth
+        0..=11 => [10_000, 20_000, 40_000, 60_000][usize::from(config % 4)],
+        // Hybrid: 10 and 20 ms
+        12..=15 => [10_000, 20_000][usize::from(config % 2)],
+        // CELT: 2.5, 5, 10 and 20 ms
+        _ => [2_500, 5_000, 10_000, 20_000][usize::from(config % 4)],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => u32::from(packet.get(1)? & 0x3F),
+    };
+    Some(frame_us * frames)
+}
+
+/// Sending state of one RTP stream.
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    ssrc: u32,
+    next_sequence: u16,
+    /// Added to a speaker's timestamps, in ticks, so the stream's clock
+    /// keeps running when the speaker changes.
+    timestamp_offset: u32,
+    /// Speaker currently carried; always the same one for per-speaker streams.
+    speaker: UserId,
+    last_packet: Instant,
+}
+
+impl Stream {
+    fn header(&mut self, packet: &AudioPacket, payload_type: u8, marker: bool) -> RtpHeader {
+        let ticks = packet.header.timestamp.wrapping_mul(TICKS_PER_MS);
+        let header = RtpHeader {
+            marker,
+            payload_type,
+            sequence: self.next_sequence,
+            timestamp: ticks.wrapping_add(self.timestamp_offset),
+            ssrc: self.ssrc,
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        header
+    }
+}
+
+/// Turns voice packets into RTP packets according to an [`RtpExportMode`].
+#[derive(Debug)]
+pub(crate) struct Packetizer {
+    mode: RtpExportMode,
+    payload_type: u8,
+    ssrc_base: u32,
+    started: Instant,
+    streams: HashMap<UserId, Stream>,
+    active: Option<Stream>,
+}
+
+impl Packetizer {
+    pub(crate) fn new(
+        mode: RtpExportMode,
+        payload_type: u8,
+        ssrc_base: u32,
+        started: Instant,
+    ) -> Self {
+        Self {
+            mode,
+            payload_type,
+            ssrc_base,
+            started,
+            streams: HashMap::new(),
+            active: None,
+        }
+    }
+
+    /// The RTP packet for voice received at `now`, or `None` if it is not
+    /// exported.
+    ///
+    /// DTX packets carry no audio; RTP receivers take the gap they leave
+    /// as silence (RFC 7587, section 3.1.3).
+    pub(crate) fn packetize(&mut self, packet: &AudioPacket, now: Instant) -> Option<Vec<u8>> {
+        if packet.header.dtx {
+            return None;
+        }
+        let speaker = packet.header.user_id;
+        let (stream, marker) = match self.mode {
+            RtpExportMode::PerSpeaker => {
+                let ssrc = self.ssrc_base.wrapping_add(speaker.get().into());
+                let mut marker = true;
+                let stream = self
+                    .streams
+                    .entry(speaker)
+                    .and_modify(|stream| {
+                        marker = now.duration_since(stream.last_packet) >= TRANSMISSION_GAP;
+                    })
+                    .or_insert(Stream {
+                        ssrc,
+                        next_sequence: 0,
+                        timestamp_offset: 0,
+                        speaker,
+                        last_packet: now,
+                    });
+                (stream, marker)
+            }
+            RtpExportMode::ActiveSpeaker => {
+                let clock = (now.duration_since(self.started).as_millis() as u32)
+                    .wrapping_mul(TICKS_PER_MS);
+                let ticks = packet.header.timestamp.wrapping_mul(TICKS_PER_MS);
+                let stream = self.active.get_or_insert(Stream {
+                    ssrc: self.ssrc_base,
+                    next_sequence: 0,
+                    timestamp_offset: clock.wrapping_sub(ticks),
+                    speaker,
+                    last_packet: now,
+                });
+                let idle = now.duration_since(stream.last_packet) >= TRANSMISSION_GAP;
+                if stream.speaker != speaker && !idle {
+                    return None;
+                }
+                // A new talk spurt is placed on the stream's own clock
+                let marker = stream.next_sequence == 0 || idle;
+                if marker {
+                    stream.speaker = speaker;
+                    stream.timestamp_offset = clock.wrapping_sub(ticks);
+                }
+                (stream, marker)
+            }
+        };
+        stream.last_packet = now;
+        let header = stream.header(packet, self.payload_type, marker);
+        Some(header.packet(&packet.opus_payload))
+    }
+}
+
+/// Mirrors a channel's voice out as RTP.
+///
+/// The exporter is a listener of its channel from [`bind`](Self::bind)
+/// until it is dropped.
+pub struct RtpExporter {
+    config: RtpExportConfig,
+    socket: UdpSocket,
+    subscriptions: Arc<SubscriptionRegistry>,
+}
+
+impl RtpExporter {
+    /// Binds the exporter's socket and starts listening to its channel.
+    pub async fn bind(
+        config: RtpExportConfig,
+        subscriptions: Arc<SubscriptionRegistry>,
+    ) -> Result<Self, FleetNetError> {
+        let socket = dual_stack::bind_udp((config.bind_ip, 0)).await?;
+        subscriptions.add_listener(
+            config.channel_id,
+            RelaySubscriber {
+                user_id: config.listener_id,
+                address: socket.local_addr()?,
+            },
+        );
+        Ok(Self {
+            config,
+            socket,
+            subscriptions,
+        })
+    }
+
+    /// Address the router sends the channel's voice to.
+    pub fn local_addr(&self) -> Result<SocketAddr, FleetNetError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Socket the RTP packets are sent from, e.g. to mark it for QoS.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// A session description (RFC 8866) for receivers of the export, such
+    /// as `ffmpeg -protocol_whitelist file,udp,rtp -i export.sdp`.
+    pub fn sdp(&self) -> String {
+        let destination = self.config.destination;
+        let family = if destination.is_ipv4() { "IP4" } else { "IP6" };
+        let payload_type = self.config.payload_type;
+        format!(
+            "v=0\r\n\
+             o=- 0 0 IN {family} {ip}\r\n\
+             s=Fleet Net channel {channel}\r\n\
+             c=IN {family} {ip}\r\n\
+             t=0 0\r\n\
+             m=audio {port} RTP/AVP {payload_type}\r\n\
+             a=rtpmap:{payload_type} opus/48000/2\r\n\
+             a=fmtp:{payload_type} sprop-stereo=0\r\n\
+             a=recvonly\r\n",
+            ip = destination.ip(),
+            port = destination.port(),
+            channel = self.config.channel_id,
+        )
+    }
+
+    /// Forwards the channel's voice to the destination until the socket
+    /// fails.
+    pub async fn run(self) -> Result<(), FleetNetError> {
+        info!(
+            "Exporting channel {} as RTP to {}",
+            self.config.channel_id, self.config.destination
+        );
+        let mut packetizer = Packetizer::new(
+            self.config.mode,
+            self.config.payload_type,
+            self.config.ssrc_base,
+            Instant::now(),
+        );
+        let destination =
+            dual_stack::reachable_from(self.socket.local_addr()?, self.config.destination);
+        let mut buf = vec![0u8; 65_535];
+        loop {
+            let (len, _) = self.socket.recv_from(&mut buf).await?;
+            let Ok(frames) =
+                AudioPacket::from_bytes(&buf[..len]).and_then(AudioPacket::into_frames)
+            else {
+                debug!("Dropping malformed voice packet for RTP export");
+                continue;
+            };
+            // Batched frames go out as an RTP packet each
+            for frame in &frames {
+                if let Some(rtp) = packetizer.packetize(frame, Instant::now()) {
+                    self.socket.send_to(&rtp, destination).await?;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RtpExporter {
+    fn drop(&mut self) {
+        self.subscriptions
+            .remove_listener(self.config.channel_id, self.config.listener_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_protocol::hmac::HmacKey;
+    use fleet_net_protocol::packet::PacketHeader;
+    use fleet_net_protocol::test_helpers::synthetic_opus_payload;
+    use fleet_test_support::recv_packet;
+    use std::time::Duration;
+
+    fn voice(user_id: u16, timestamp: u32) -> AudioPacket {
+        let header = PacketHeader {
+            channel_id: ChannelId::new(2).unwrap(),
+            user_id: UserId::new(user_id).unwrap(),
+            sequence: 0,
+            timestamp,
+            signal_strength: u8::MAX,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        AudioPacket::new_signed(
+            header,
+            synthetic_opus_payload(timestamp.into(), 20),
+            &HmacKey::from_bytes(&[7; 32]),
+        )
+    }
+
+    fn config(mode: RtpExportMode) -> RtpExportConfig {
+        RtpExportConfig {
+            mode,
+            ..RtpExportConfig::new(
+                ChannelId::new(2).unwrap(),
+                "127.0.0.1:5004".parse().unwrap(),
+                UserId::new(0xFFFF).unwrap(),
+            )
+        }
+    }
+
+    fn packetizer(mode: RtpExportMode, started: Instant) -> Packetizer {
+        let config = config(mode);
+        Packetizer::new(config.mode, config.payload_type, config.ssrc_base, started)
+    }
+
+    fn header(rtp: Option<Vec<u8>>) -> RtpHeader {
+        RtpHeader::parse(&rtp.expect("Packet is exported"))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_header_matches_rfc_3550_layout() {
+        let header = RtpHeader {
+            marker: true,
+            payload_type: OPUS_PAYLOAD_TYPE,
+            sequence: 0x0102,
+            timestamp: 0x0304_0506,
+            ssrc: 0x0708_090A,
+        };
+        let packet = header.packet(&[0xAA, 0xBB]);
+        assert_eq!(
+            packet,
+            [0x80, 0xEF, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 0xAA, 0xBB]
+        );
+        assert_eq!(RtpHeader::parse(&packet), Some((header, &[0xAA, 0xBB][..])));
+        assert_eq!(RtpHeader::parse(&packet[..11]), None);
+    }
+
+    #[test]
+    fn test_each_speaker_gets_a_stream_of_their_own() {
+        let start = Instant::now();
+        let mut packetizer = packetizer(RtpExportMode::PerSpeaker, start);
+
+        let alpha = header(packetizer.packetize(&voice(5, 1_000), start));
+        let bravo = header(packetizer.packetize(&voice(6, 70_000), start));
+        let ms = Duration::from_millis(20);
+        let alpha_next = header(packetizer.packetize(&voice(5, 1_020), start + ms));
+
+        assert_eq!(alpha.ssrc, 0x464E_0005);
+        assert_eq!(bravo.ssrc, 0x464E_0006);
+        assert!(alpha.marker && bravo.marker && !alpha_next.marker);
+        assert_eq!(alpha.timestamp, 48_000);
+        assert_eq!(alpha_next.timestamp - alpha.timestamp, 960);
+        assert_eq!(
+            (alpha.sequence, alpha_next.sequence, bravo.sequence),
+            (0, 1, 0)
+        );
+
+        // Talking again after a pause starts a new spurt on the same stream
+        let later = start + Duration::from_secs(3);
+        let resumed = header(packetizer.packetize(&voice(5, 4_000), later));
+        assert!(resumed.marker);
+        assert_eq!(resumed.sequence, 2);
+    }
+
+    #[test]
+    fn test_active_speaker_holds_the_floor_until_silent() {
+        let start = Instant::now();
+        let mut packetizer = packetizer(RtpExportMode::ActiveSpeaker, start);
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        let first = header(packetizer.packetize(&voice(5, 9_000), ms(0)));
+        assert!(first.marker);
+        assert_eq!(first.ssrc, 0x464E_0000);
+        assert_eq!(first.timestamp, 0);
+        // Talking over the speaker is dropped
+        assert!(packetizer.packetize(&voice(6, 300), ms(10)).is_none());
+        let second = header(packetizer.packetize(&voice(5, 9_020), ms(25)));
+        assert_eq!((second.sequence, second.timestamp), (1, 960));
+
+        // Once the speaker is silent the next one takes over the same
+        // stream, on its clock
+        let handover = header(packetizer.packetize(&voice(6, 2_000), ms(1_000)));
+        assert!(handover.marker);
+        assert_eq!(handover.ssrc, first.ssrc);
+        assert_eq!((handover.sequence, handover.timestamp), (2, 48_000));
+    }
+
+    #[tokio::test]
+    async fn test_channel_voice_is_exported_to_the_destination() {
+        let subscriptions = Arc::new(SubscriptionRegistry::new());
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = RtpExportConfig {
+            destination: receiver.local_addr().unwrap(),
+            ..config(RtpExportMode::PerSpeaker)
+        };
+        let exporter = RtpExporter::bind(config, subscriptions.clone())
+            .await
+            .unwrap();
+        assert!(exporter.sdp().contains("m=audio"));
+        let channel_id = ChannelId::new(2).unwrap();
+        assert_eq!(subscriptions.listeners(channel_id).len(), 1);
+        let export = tokio::spawn(exporter.run());
+
+        let speaker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        subscriptions.add_listener(
+            channel_id,
+            RelaySubscriber {
+                user_id: UserId::new(5).unwrap(),
+                address: speaker.local_addr().unwrap(),
+            },
+        );
+        let router = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let spoken = voice(5, 1_000);
+        let forwarded = subscriptions
+            .forward_packet(&router, &spoken.to_bytes(), speaker.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(forwarded, 1);
+
+        let rtp = recv_packet(&receiver, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let (header, payload) = RtpHeader::parse(&rtp).unwrap();
+        assert_eq!(header.payload_type, OPUS_PAYLOAD_TYPE);
+        assert_eq!(header.ssrc, 0x464E_0005);
+        assert_eq!(payload, spoken.opus_payload);
+
+        // Stopping the export stops listening
+        export.abort();
+        let _ = export.await;
+        let listeners = subscriptions.listeners(channel_id);
+        assert!(listeners.iter().all(|s| s.user_id.get() != 0xFFFF));
+    }
+}
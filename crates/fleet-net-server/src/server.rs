@@ -1,114 +1,740 @@
+use crate::announcements::{self, AnnouncementsConfig, Announcer};
+use crate::auth::TokenVerifier;
+use crate::channels::ChannelRegistry;
+use crate::cluster::{
+    ClusterMode, Coordinator, RelayConfig, RelayNode, RELAY_STATS_INTERVAL, ROUTE_SYNC_INTERVAL,
+};
+use crate::dispatch::Dispatcher;
+use crate::events::{self, EventsConfig};
+use crate::groups::GroupRegistry;
+use crate::health::{self, HealthState};
+use crate::journal::SessionJournal;
+use crate::nicknames::NicknameRegistry;
+use crate::presence::PresenceRegistry;
+use crate::propagation::PropagationConfig;
+use crate::realism::{self, RadioRealism};
+use crate::reception::ReceptionRegistry;
+use crate::reports::{self, ReportQueue, SpeakerHistory, DEFAULT_REPORT_WINDOW};
+use crate::restrictions::RestrictionRegistry;
+use crate::roles::RoleRegistry;
+use crate::rtp::{RtpExportConfig, RtpExporter};
+use crate::sessions::SessionLifecycle;
+use crate::store::{ChannelStore, SessionBackend, StoreConfig, Stores};
+use crate::subscriptions::SubscriptionRegistry;
+use crate::templates::{self, TemplateManager};
 use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::session::SessionState;
+use fleet_net_common::validation::{Constraint, Validate};
 use fleet_net_protocol::connection::Connection;
-use fleet_net_protocol::message::ControlMessage;
-use fleet_net_protocol::tls::TlsConfig;
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::message::ServerStatus;
+use fleet_net_protocol::ping;
+use fleet_net_protocol::qos::QosConfig;
+use fleet_net_protocol::tls::{CertResolver, TlsConfig};
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use tokio::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
 use tokio_rustls::TlsAcceptor;
-use tracing::info;
+use tracing::{error, info, warn};
 
 pub struct ServerConfig {
+    /// Name advertised to clients, in pings and on the health endpoint.
+    pub name: String,
+    /// Control address; `[::]` listens on IPv4 and IPv6 alike.
     pub bind_address: String,
     pub tls_cert_path: Option<PathBuf>,
     pub tls_key_path: Option<PathBuf>,
+    pub cluster: ClusterMode,
+    /// Address for the HTTP `/healthz` endpoint; disabled when `None`.
+    pub health_bind_address: Option<String>,
+    /// Bearer token for the admin API on the health address; disabled when
+    /// `None`. The server refuses to start with an empty one.
+    pub admin_token: Option<String>,
+    /// Region advertised to clients choosing between deployments, e.g. `eu-west`.
+    pub region: Option<String>,
+    /// UDP address answering latency probes; disabled when `None`.
+    pub ping_bind_address: Option<String>,
+    /// UDP address voice datagrams from clients are received on.
+    pub voice_bind_address: String,
+    /// Secret shared with the login service to sign client access tokens, see
+    /// [`TokenVerifier`]; every client is refused when `None`.
+    pub jwt_secret: Option<String>,
+    /// Limits enforced on clients and sent to them when they connect.
+    pub limits: ServerLimits,
+    /// DSCP marking for control connections and voice sockets.
+    pub qos: QosConfig,
+    /// Session journal used to resume sessions after a restart; disabled when `None`.
+    pub journal_path: Option<PathBuf>,
+    /// Message of the day sent to connecting clients, with `{ $server }` and
+    /// `{ $users }` filled in, see [`fleet_net_common::i18n::render`].
+    pub motd: Option<String>,
+    /// Channels mirrored out as RTP streams for external mixing.
+    pub rtp_exports: Vec<RtpExportConfig>,
+    /// Broker server events are published to; disabled when `None`.
+    pub events: Option<EventsConfig>,
+    /// Models computing the signal strength of radio traffic per listener;
+    /// the sender's own is forwarded when `None`.
+    pub propagation: Option<PropagationConfig>,
+    /// Spoken announcements made through the admin API or on a schedule;
+    /// disabled when `None`.
+    pub announcements: Option<AnnouncementsConfig>,
+    /// Hardening against eavesdroppers on the network path.
+    pub security: SecurityConfig,
+    /// Where session, channel and nickname state is kept.
+    pub store: StoreConfig,
 }
 
+/// The `security` section of the server configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecurityConfig {
+    /// Block size, in bytes, every voice packet is padded to a multiple of,
+    /// so packet sizes don't give away who is speaking or the codec settings
+    /// in use; see [`fleet_net_protocol::packet::pad_datagram`]. Advertised
+    /// to clients so they pad what they send too. Disabled when `None`.
+    pub voice_padding: Option<u16>,
+}
+
+/// How long after its last update a journaled session can still be resumed.
+pub const RESUME_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// What the `@everyone` role grants until an operator changes it.
+pub const DEFAULT_EVERYONE_PERMISSIONS: Permissions = Permissions::CONNECT
+    .union(Permissions::SPEAK)
+    .union(Permissions::LISTEN);
+
 pub struct Server {
     config: ServerConfig,
     listener: Option<TcpListener>,
     tls_acceptor: Option<TlsAcceptor>,
+    cert_resolver: Option<Arc<CertResolver>>,
+    health: Arc<HealthState>,
+    ping_port: Option<u16>,
+    voice_port: Option<u16>,
+    dispatcher: Option<Arc<Dispatcher>>,
+    coordinator: Option<Arc<Coordinator>>,
+    relay_address: Option<SocketAddr>,
+    relay: Option<Arc<RelayNode>>,
+    journal: Option<Arc<SessionJournal>>,
+    reports: Arc<ReportQueue>,
+    subscriptions: Arc<SubscriptionRegistry>,
+    reception: Arc<ReceptionRegistry>,
+    realism: Arc<RadioRealism>,
+    restrictions: Arc<RestrictionRegistry>,
+    presence: Arc<PresenceRegistry>,
+    nicknames: Arc<NicknameRegistry>,
+    session_store: Arc<SessionBackend>,
+    channels: Arc<ChannelRegistry>,
+    roles: Arc<RoleRegistry>,
+    groups: Arc<GroupRegistry>,
+    templates: Arc<TemplateManager>,
+    sessions: Arc<SessionLifecycle>,
 }
 
 impl Server {
     pub fn new(config: ServerConfig) -> Result<Self, FleetNetError> {
         // Initialize TLS if cert and key paths are provided
-        let tls_acceptor = if let (Some(cert_path), Some(key_path)) =
+        let tls = if let (Some(cert_path), Some(key_path)) =
             (&config.tls_cert_path, &config.tls_key_path)
         {
-            let tls_config = TlsConfig::new_server(cert_path, key_path)?;
-            Some(TlsAcceptor::from(tls_config.server_config.unwrap()))
+            let resolver = Arc::new(CertResolver::from_files(cert_path, key_path)?);
+            let tls_config = TlsConfig::new_server_with_resolver(resolver.clone());
+            Some((resolver, tls_config))
         } else {
             None
         };
 
+        Self::build(config, tls)
+    }
+
+    /// Creates a server whose certificate is managed externally, e.g. by ACME
+    /// with the HTTP-01 challenge.
+    ///
+    /// The certificate paths in `config` are ignored.
+    pub fn with_cert_resolver(
+        config: ServerConfig,
+        resolver: Arc<CertResolver>,
+    ) -> Result<Self, FleetNetError> {
+        let tls_config = TlsConfig::new_server_with_resolver(resolver.clone());
+        Self::build(config, Some((resolver, tls_config)))
+    }
+
+    /// Like [`with_cert_resolver`](Self::with_cert_resolver), but the control
+    /// port also answers ACME TLS-ALPN-01 validation handshakes.
+    pub fn with_acme_resolver(
+        config: ServerConfig,
+        resolver: Arc<CertResolver>,
+    ) -> Result<Self, FleetNetError> {
+        let tls_config = TlsConfig::new_server_with_acme_resolver(resolver.clone());
+        Self::build(config, Some((resolver, tls_config)))
+    }
+
+    fn build(
+        config: ServerConfig,
+        tls: Option<(Arc<CertResolver>, TlsConfig)>,
+    ) -> Result<Self, FleetNetError> {
+        let (cert_resolver, tls_acceptor) = match tls {
+            Some((resolver, tls_config)) => (
+                Some(resolver),
+                tls_config.server_config.map(TlsAcceptor::from),
+            ),
+            None => (None, None),
+        };
+        let limits = config.limits;
+        let stores = Stores::open(&config.store)?;
+        let channels = Arc::new(ChannelRegistry::new(stores.channels).with_limits(limits));
+        let roles = Arc::new(RoleRegistry::new(DEFAULT_EVERYONE_PERMISSIONS));
+        let realism = Arc::new(RadioRealism::new().with_limits(limits));
+        let mut subscriptions = SubscriptionRegistry::new()
+            .with_limits(limits)
+            .with_realism(realism.clone());
+        if let Some(propagation) = &config.propagation {
+            subscriptions = subscriptions.with_propagation(propagation.clone());
+        }
+        if let Some(block) = config.security.voice_padding {
+            subscriptions = subscriptions.with_padding(block);
+        }
+        let subscriptions = Arc::new(subscriptions);
+
         Ok(Self {
             config,
             listener: None,
             tls_acceptor,
+            cert_resolver,
+            health: Arc::new(HealthState::new(None, None)),
+            ping_port: None,
+            voice_port: None,
+            dispatcher: None,
+            coordinator: None,
+            relay_address: None,
+            relay: None,
+            journal: None,
+            reports: Arc::new(ReportQueue::new(Arc::new(SpeakerHistory::new(
+                DEFAULT_REPORT_WINDOW,
+            )))),
+            reception: Arc::new(ReceptionRegistry::new(subscriptions.clone())),
+            subscriptions,
+            realism,
+            restrictions: Arc::new(RestrictionRegistry::new().with_limits(limits)),
+            presence: Arc::new(PresenceRegistry::new().with_limits(limits)),
+            nicknames: Arc::new(NicknameRegistry::new(stores.nicknames).with_limits(limits)),
+            session_store: Arc::new(stores.sessions),
+            templates: Arc::new(
+                TemplateManager::new(channels.clone(), roles.clone()).with_limits(limits),
+            ),
+            channels,
+            groups: Arc::new(GroupRegistry::new().with_limits(limits)),
+            roles,
+            sessions: Arc::new(SessionLifecycle::new()),
         })
     }
 
+    /// Re-reads the configured certificate and key without dropping connections.
+    pub fn reload_certificates(&self) -> Result<(), FleetNetError> {
+        match (
+            &self.cert_resolver,
+            &self.config.tls_cert_path,
+            &self.config.tls_key_path,
+        ) {
+            (Some(resolver), Some(cert_path), Some(key_path)) => {
+                resolver.reload(cert_path, key_path)?;
+                info!("Reloaded TLS certificate from {}", cert_path.display());
+                Ok(())
+            }
+            _ => Err(FleetNetError::EncryptionError(Cow::Borrowed(
+                "TLS certificate paths are not configured",
+            ))),
+        }
+    }
+
+    pub fn cluster_mode(&self) -> &ClusterMode {
+        &self.config.cluster
+    }
+
+    /// Relays of a coordinator, once it has started.
+    pub fn coordinator(&self) -> Option<&Arc<Coordinator>> {
+        self.coordinator.as_ref()
+    }
+
+    /// Address a coordinator accepts relays on, once it has started.
+    pub fn relay_address(&self) -> Option<SocketAddr> {
+        self.relay_address
+    }
+
+    /// Forwarding state of a relay, once it has registered.
+    pub fn relay(&self) -> Option<&Arc<RelayNode>> {
+        self.relay.as_ref()
+    }
+
+    pub fn health(&self) -> &Arc<HealthState> {
+        &self.health
+    }
+
+    /// Replaces the health state, e.g. to attach a database pool or Discord API URL.
+    pub fn set_health(&mut self, health: Arc<HealthState>) {
+        self.health = health;
+    }
+
+    pub fn reports(&self) -> &Arc<ReportQueue> {
+        &self.reports
+    }
+
+    /// Channel listeners used to fan voice packets out to subscribers.
+    pub fn subscriptions(&self) -> &Arc<SubscriptionRegistry> {
+        &self.subscriptions
+    }
+
+    /// Reception reports from listeners, answered with feedback for speakers
+    /// to adapt their bitrate to.
+    pub fn reception(&self) -> &Arc<ReceptionRegistry> {
+        &self.reception
+    }
+
+    /// Hopping, jamming and interference scenario applied to radio nets.
+    pub fn realism(&self) -> &Arc<RadioRealism> {
+        &self.realism
+    }
+
+    /// Active mutes and bans; lifts are published to its subscribers.
+    pub fn restrictions(&self) -> &Arc<RestrictionRegistry> {
+        &self.restrictions
+    }
+
+    /// Presence of connected users; changes are published to its subscribers.
+    pub fn presence(&self) -> &Arc<PresenceRegistry> {
+        &self.presence
+    }
+
+    /// Nicknames of users; changes are published to its subscribers.
+    pub fn nicknames(&self) -> &Arc<NicknameRegistry> {
+        &self.nicknames
+    }
+
+    /// Replaces the nickname registry, e.g. to require callsigns.
+    pub fn set_nicknames(&mut self, nicknames: Arc<NicknameRegistry>) {
+        self.nicknames = nicknames;
+    }
+
+    /// Snapshots of active sessions, shared with other nodes when kept in Redis.
+    pub fn session_store(&self) -> &Arc<SessionBackend> {
+        &self.session_store
+    }
+
+    /// Channels of this server; info changes are published to its subscribers.
+    pub fn channels(&self) -> &Arc<ChannelRegistry> {
+        &self.channels
+    }
+
+    /// Roles and role assignments, merged with Discord roles for permission
+    /// checks.
+    pub fn roles(&self) -> &Arc<RoleRegistry> {
+        &self.roles
+    }
+
+    /// Export and import of the channel tree and roles.
+    pub fn templates(&self) -> &Arc<TemplateManager> {
+        &self.templates
+    }
+
+    /// Groups such as fireteams, and who each member's whispers reach.
+    pub fn groups(&self) -> &Arc<GroupRegistry> {
+        &self.groups
+    }
+
+    /// Session state changes; every transition is published to its subscribers.
+    pub fn sessions(&self) -> &Arc<SessionLifecycle> {
+        &self.sessions
+    }
+
+    /// Journal of resumable sessions, available once the server has started.
+    pub fn journal(&self) -> Option<&Arc<SessionJournal>> {
+        self.journal.as_ref()
+    }
+
     pub async fn start(&mut self) -> Result<SocketAddr, FleetNetError> {
-        let listener = TcpListener::bind(&self.config.bind_address).await?;
+        // Refuse to publish a misconfigured name or region to clients.
+        let status = self.initial_status();
+        status.validate(&status.limits)?;
+        // An empty token would open the admin API to requests without one
+        if self.config.admin_token.as_deref() == Some("") {
+            return Err(FleetNetError::invalid_field(
+                "admin_token",
+                Constraint::Required,
+            ));
+        }
+        if let ClusterMode::Relay(relay) = &self.config.cluster {
+            return self.start_relay(relay.clone()).await;
+        }
+        self.session_store.connect().await?;
+
+        if let Some(journal_path) = &self.config.journal_path {
+            let journal = SessionJournal::open(journal_path, RESUME_WINDOW).await?;
+            info!("Recovered {} resumable sessions", journal.len());
+            let journal = Arc::new(journal);
+            // Detached: the journal is compacted for the life of the server.
+            tokio::spawn({
+                let journal = journal.clone();
+                async move {
+                    let mut interval = tokio::time::interval(RESUME_WINDOW);
+                    interval.tick().await;
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = journal.compact().await {
+                            warn!("Failed to compact the session journal: {e}");
+                        }
+                    }
+                }
+            });
+            self.journal = Some(journal);
+        }
+
+        let listener = dual_stack::bind_tcp(&self.config.bind_address).await?;
         let addr = listener.local_addr()?;
         info!("Server listening on {}", addr);
 
+        if let Some(ping_address) = &self.config.ping_bind_address {
+            let socket = dual_stack::bind_udp(ping_address).await?;
+            // Probes share the voice marking so they measure the path voice will take.
+            self.config.qos.apply_voice(&socket)?;
+            self.ping_port = Some(socket.local_addr()?.port());
+
+            let health = self.health.clone();
+            tokio::spawn(async move {
+                let load = || {
+                    health.status().map_or((0, 0), |status| {
+                        (status.user_count, status.limits.max_users.unwrap_or(0))
+                    })
+                };
+                if let Err(e) = ping::serve_ping(socket, load).await {
+                    error!("Ping responder stopped: {e}");
+                }
+            });
+        }
+
+        let voice = dual_stack::bind_udp(&self.config.voice_bind_address).await?;
+        self.config.qos.apply_voice(&voice)?;
+        self.voice_port = Some(voice.local_addr()?.port());
+        info!("Voice socket listening on {}", voice.local_addr()?);
+        let tokens = self
+            .config
+            .jwt_secret
+            .as_deref()
+            .map(|secret| TokenVerifier::new(secret.as_bytes()));
+        if tokens.is_none() {
+            warn!("No JWT secret configured, every client will be refused");
+        }
+
+        // The coordinator comes first, for the dispatcher to assign relays
+        if let ClusterMode::Coordinator {
+            relay_bind_address,
+            cluster_secret,
+        } = &self.config.cluster
+        {
+            let acceptor = self
+                .tls_acceptor
+                .clone()
+                .ok_or(FleetNetError::EncryptionError(Cow::Borrowed(
+                    "A coordinator needs a TLS certificate for its relays",
+                )))?;
+            let relay_listener = dual_stack::bind_tcp(relay_bind_address).await?;
+            self.relay_address = Some(relay_listener.local_addr()?);
+            info!("Accepting relays on {}", relay_listener.local_addr()?);
+            let coordinator = Arc::new(Coordinator::new(cluster_secret.clone()));
+            tokio::spawn({
+                let coordinator = coordinator.clone();
+                async move {
+                    if let Err(e) = coordinator.serve_relays(relay_listener, acceptor).await {
+                        error!("Relay listener stopped: {e}");
+                    }
+                }
+            });
+            self.coordinator = Some(coordinator);
+        }
+
+        let dispatcher = Arc::new(Dispatcher::new(self, Arc::new(voice), tokens));
+        dispatcher.spawn_relays().await?;
+        tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move {
+                if let Err(e) = dispatcher.run_voice().await {
+                    error!("Voice socket stopped: {e}");
+                }
+            }
+        });
+        self.dispatcher = Some(dispatcher.clone());
+
+        if let Some(coordinator) = self.coordinator.clone() {
+            let channels = self.channels.clone();
+            let subscriptions = self.subscriptions.clone();
+            // Detached: relays are kept in sync for the life of the server.
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(ROUTE_SYNC_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    match channels.store().list().await {
+                        Ok(channels) => coordinator.sync_channels(channels),
+                        Err(e) => warn!("Failed to list channels for relays: {e}"),
+                    }
+                    coordinator.sync_senders(dispatcher.relay_senders());
+                    coordinator.sync_routes(subscriptions.all_listeners());
+                }
+            });
+        }
+
+        self.health.set_status(self.initial_status());
+        self.spawn_status_updates();
+
+        for export in &self.config.rtp_exports {
+            let exporter = RtpExporter::bind(export.clone(), self.subscriptions.clone()).await?;
+            self.config.qos.apply_voice(exporter.socket())?;
+            let channel_id = export.channel_id;
+            tokio::spawn(async move {
+                if let Err(e) = exporter.run().await {
+                    error!("RTP export of channel {channel_id} stopped: {e}");
+                }
+            });
+        }
+
+        let mut announcer = None;
+        if let Some(config) = &self.config.announcements {
+            let bound = Announcer::bind(
+                config.engine.clone(),
+                config.user_id,
+                config.bind_ip,
+                self.subscriptions.clone(),
+            )
+            .await?;
+            self.config.qos.apply_voice(bound.socket())?;
+            let bound = Arc::new(bound);
+            // Detached: scheduled announcements repeat for the life of the server.
+            bound.spawn_schedule(&config.schedule);
+            announcer = Some(bound);
+        }
+
+        if let Some(health_address) = &self.config.health_bind_address {
+            let health_listener = dual_stack::bind_tcp(health_address).await?;
+            let mut router = health::router(self.health.clone());
+            if let Some(admin_token) = &self.config.admin_token {
+                router = router
+                    .merge(reports::admin_router(self.reports.clone(), admin_token))
+                    .merge(templates::admin_router(self.templates.clone(), admin_token))
+                    .merge(realism::admin_router(self.realism.clone(), admin_token));
+                if let Some(announcer) = announcer {
+                    router = router.merge(announcements::admin_router(announcer, admin_token));
+                }
+            }
+            tokio::spawn(async move {
+                if let Err(e) = health::serve(health_listener, router).await {
+                    error!("Health endpoint stopped: {e}");
+                }
+            });
+        }
+
+        if let Some(events) = &self.config.events {
+            // Detached: events are published for the life of the server.
+            events::spawn(events, self).await?;
+        }
+
+        // Detached: expired mutes and bans are lifted for the life of the server.
+        self.restrictions.spawn_expiry();
+        self.subscriptions.spawn_transmission_sweep();
+        self.spawn_session_cleanup();
+
         self.listener = Some(listener);
+        self.health.set_listener_up(true);
+        health::notify_ready();
+        // Detached: the watchdog should keep pinging for the life of the process.
+        let _ = health::systemd::spawn_watchdog(self.health.clone());
         Ok(addr)
     }
 
-    pub async fn accept_connection(&self) -> Result<(), FleetNetError> {
-        let listener = self
-            .listener
-            .as_ref()
-            .ok_or(FleetNetError::NetworkError(Cow::Borrowed(
+    /// Registers with the coordinator and forwards voice for it; a relay
+    /// takes no clients. Returns the voice address.
+    async fn start_relay(&mut self, config: RelayConfig) -> Result<SocketAddr, FleetNetError> {
+        let voice = dual_stack::bind_udp(&config.voice_bind_address).await?;
+        self.config.qos.apply_voice(&voice)?;
+        let voice_address = voice.local_addr()?;
+        let relay = Arc::new(
+            RelayNode::new(config.relay_id.clone()).with_subscriptions(self.subscriptions.clone()),
+        );
+        let conn = relay.connect(&config, voice_address).await?;
+        info!(
+            "Relay {} registered with {}, forwarding voice on {voice_address}",
+            config.relay_id, config.coordinator_address
+        );
+
+        let health = self.health.clone();
+        let node = relay.clone();
+        tokio::spawn(async move {
+            let result = tokio::select! {
+                result = node.clone().run_control(conn, RELAY_STATS_INTERVAL) => result,
+                result = node.run_voice(voice) => result,
+            };
+            if let Err(e) = result {
+                error!("Relay stopped: {e}");
+            }
+            health.set_listener_up(false);
+        });
+
+        self.relay = Some(relay);
+        self.health.set_listener_up(true);
+        health::notify_ready();
+        // Detached: the watchdog should keep pinging for the life of the process.
+        let _ = health::systemd::spawn_watchdog(self.health.clone());
+        Ok(voice_address)
+    }
+
+    /// Stops fanning audio out to sessions and forgets their reception
+    /// reports, presence and group as soon as they start disconnecting.
+    fn spawn_session_cleanup(&self) {
+        let mut transitions = self.sessions.subscribe();
+        let subscriptions = self.subscriptions.clone();
+        let reception = self.reception.clone();
+        let presence = self.presence.clone();
+        let groups = self.groups.clone();
+        tokio::spawn(async move {
+            loop {
+                match transitions.recv().await {
+                    Ok(transition) if transition.to == SessionState::Disconnecting => {
+                        subscriptions.remove_user(transition.user_id);
+                        reception.remove_user(transition.user_id);
+                        presence.remove_user(transition.user_id);
+                        groups.remove_user(transition.user_id);
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Session cleanup missed {skipped} transitions");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Keeps the advertised user and channel counts current as sessions
+    /// become active and disconnect.
+    fn spawn_status_updates(&self) {
+        let mut transitions = self.sessions.subscribe();
+        let health = self.health.clone();
+        let channels = self.channels.clone();
+        let mut status = self.initial_status();
+        tokio::spawn(async move {
+            let mut active = HashSet::new();
+            loop {
+                match transitions.recv().await {
+                    Ok(transition) => {
+                        let changed = match transition.to {
+                            SessionState::Active => active.insert(transition.session_id),
+                            SessionState::Disconnecting => active.remove(&transition.session_id),
+                            SessionState::Authenticating | SessionState::Away => false,
+                        };
+                        if !changed {
+                            continue;
+                        }
+                        status.user_count = active.len() as u32;
+                        if let Ok(channels) = channels.store().list().await {
+                            status.channel_count = channels.len() as u32;
+                        }
+                        health.set_status(status.clone());
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Status updates missed {skipped} session transitions");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn initial_status(&self) -> ServerStatus {
+        ServerStatus {
+            name: self.config.name.clone(),
+            version: Cow::Borrowed(env!("CARGO_PKG_VERSION")),
+            region: self.config.region.clone(),
+            ping_port: self.ping_port,
+            voice_port: self.voice_port,
+            user_count: 0,
+            channel_count: 0,
+            limits: self.config.limits,
+            motd: self.config.motd.clone(),
+            voice_padding: self.config.security.voice_padding,
+        }
+    }
+
+    /// Current server state as sent to clients on connect.
+    pub fn status(&self) -> ServerStatus {
+        self.health
+            .status()
+            .unwrap_or_else(|| self.initial_status())
+    }
+
+    fn mark_control_stream(&self, stream: &TcpStream) {
+        // Marking is an optimization; never refuse a client over it.
+        if let Err(e) = self.config.qos.apply_control(stream) {
+            warn!("Failed to mark control connection: {e}");
+        }
+    }
+
+    fn started(&self) -> Result<(&TcpListener, &Arc<Dispatcher>), FleetNetError> {
+        match (&self.listener, &self.dispatcher) {
+            (Some(listener), Some(dispatcher)) => Ok((listener, dispatcher)),
+            _ => Err(FleetNetError::NetworkError(Cow::Borrowed(
                 "Server not started",
-            )))?;
+            ))),
+        }
+    }
+
+    /// Accepts one client and serves it until it disconnects.
+    pub async fn accept_connection(&self) -> Result<(), FleetNetError> {
+        let (listener, dispatcher) = self.started()?;
         let (stream, addr) = listener.accept().await?;
         info!("Accepted connection from {}", addr);
+        self.mark_control_stream(&stream);
 
         // Handle TLS if configured
         if let Some(acceptor) = &self.tls_acceptor {
             let tls_stream = acceptor.accept(stream).await?;
-            let mut conn = Connection::new(tls_stream);
-
-            // Send server info message
-            let msg = ControlMessage::ServerInfo {
-                name: "Fleet Net Server".to_string(),
-                version: Cow::Borrowed("0.1.0"),
-                user_count: 0,
-                channel_count: 0,
-            };
-            conn.write_message(&msg).await?;
+            let conn = Connection::new(tls_stream);
+            dispatcher
+                .clone()
+                .handle(conn, addr, self.status().server_info())
+                .await?;
         }
 
         Ok(())
     }
 
     pub async fn run(&self) -> Result<(), FleetNetError> {
-        let listener = self
-            .listener
-            .as_ref()
-            .ok_or(FleetNetError::NetworkError(Cow::Borrowed(
-                "Server not started",
-            )))?;
+        if self.relay.is_some() {
+            // Relays only forward voice, in the tasks started with them
+            return std::future::pending().await;
+        }
+        let (listener, dispatcher) = self.started()?;
 
         loop {
             let (stream, addr) = listener.accept().await?;
             info!("Accepted connection from {addr}");
+            self.mark_control_stream(&stream);
 
             // CLone what we need for the spawned task.
             let acceptor = self.tls_acceptor.clone();
+            let dispatcher = dispatcher.clone();
+            let msg = self.status().server_info();
 
             // Spawn a task to handle this connection
             tokio::spawn(async move {
                 if let Some(acceptor) = acceptor {
                     match acceptor.accept(stream).await {
                         Ok(tls_stream) => {
-                            let mut conn = Connection::new(tls_stream);
-
-                            // Send server info message
-                            let msg = ControlMessage::ServerInfo {
-                                name: "Fleet Net Server".to_string(),
-                                version: Cow::Borrowed("0.1.0"),
-                                user_count: 0,
-                                channel_count: 0,
-                            };
-
-                            if let Err(e) = conn.write_message(&msg).await {
-                                tracing::error!("Failed to send server info: {e}");
+                            let conn = Connection::new(tls_stream);
+                            if let Err(e) = dispatcher.handle(conn, addr, msg).await {
+                                tracing::info!("Connection from {addr} ended: {e}");
                             }
                         }
                         Err(e) => {
@@ -124,11 +750,17 @@ impl Server {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use fleet_test_support::{generate_test_certs, init_crypto_once};
-    use std::time::Duration;
-    use tokio::net::TcpStream;
+    use crate::testing::{test_channel, test_config, TestCluster};
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::Session;
+    use fleet_net_common::types::{ChannelId, UserId};
+    use fleet_net_common::user::User;
+    use fleet_net_protocol::message::ControlMessage;
+    use fleet_net_protocol::test_helpers::assert_is_server_info;
+    use fleet_test_support::{generate_test_certs, init_crypto_once, wait_until};
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
     use tokio_rustls::TlsConnector;
-    use tracing::log::trace;
 
     #[tokio::test]
     async fn test_server_accepts_single_tls_connection() {
@@ -139,9 +771,9 @@ mod tests {
 
         // Create server configuration
         let config = ServerConfig {
-            bind_address: "127.0.0.1:0".to_string(), // Use port 0 for auto-assignment
             tls_cert_path: Some(bundle.cert_path.clone()),
             tls_key_path: Some(bundle.key_path.clone()),
+            ..test_config()
         };
 
         // When: Create and start the server
@@ -187,73 +819,293 @@ mod tests {
 
     #[tokio::test]
     async fn test_server_handles_multiple_concurrent_connections() {
-        init_crypto_once();
+        let (cluster, mut clients) = TestCluster::start(3).await;
+        for client in &clients {
+            assert_is_server_info(client.server_info(), "Fleet Net Server");
+        }
 
-        // Given: Generate a self-signed certificate for testing
-        let bundle = generate_test_certs("localhost");
+        // Later clients are greeted the same way
+        clients.extend(cluster.connect_many(2).await);
+        assert_eq!(clients.len(), 5);
+        assert_is_server_info(clients[4].server_info(), "Fleet Net Server");
+    }
 
-        // Create server configuration
+    #[tokio::test]
+    async fn test_server_advertises_region_and_answers_pings() {
         let config = ServerConfig {
-            bind_address: "127.0.0.1:0".to_string(), // Use port 0 for auto-assignment
-            tls_cert_path: Some(bundle.cert_path.clone()),
-            tls_key_path: Some(bundle.key_path.clone()),
+            region: Some("eu-west".to_string()),
+            ping_bind_address: Some("127.0.0.1:0".to_string()),
+            limits: ServerLimits {
+                max_users: Some(64),
+                ..ServerLimits::default()
+            },
+            motd: Some("Welcome to { $server }".to_string()),
+            security: SecurityConfig {
+                voice_padding: Some(256),
+            },
+            ..test_config()
         };
 
-        // Create and start server
         let mut server = Server::new(config).expect("Failed to create server");
         let addr = server.start().await.expect("Failed to start server");
 
-        let server = std::sync::Arc::new(server);
-        let server_clone = server.clone();
-        let server_handle = tokio::spawn(async move { server_clone.run().await });
+        let status = server.status();
+        assert_eq!(status.region.as_deref(), Some("eu-west"));
+        let ping_port = status.ping_port.expect("Ping responder bound");
+
+        // Clients probe the control host on the advertised ping port
+        let ping_addr = SocketAddr::new(addr.ip(), ping_port);
+        let result = ping::measure_rtt(ping_addr, 2, Duration::from_millis(500))
+            .await
+            .expect("Ping answered");
+        assert_eq!((result.user_count, result.max_users), (0, 64));
+
+        match status.server_info() {
+            ControlMessage::ServerInfo {
+                region,
+                max_users,
+                motd,
+                voice_padding,
+                ..
+            } => {
+                assert_eq!(region.as_deref(), Some("eu-west"));
+                assert_eq!(max_users, Some(64));
+                assert_eq!(motd.as_deref(), Some("Welcome to Fleet Net Server"));
+                assert_eq!(voice_padding, Some(256));
+            }
+            other => panic!("Expected ServerInfo message, got {other:?}"),
+        }
+
+        // Padding too small to hide anything is refused
+        let config = ServerConfig {
+            security: SecurityConfig {
+                voice_padding: Some(8),
+            },
+            ..test_config()
+        };
+        let mut server = Server::new(config).expect("Failed to create server");
+        assert!(server.start().await.is_err());
 
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        // So is an empty admin token
+        let config = ServerConfig {
+            health_bind_address: Some("127.0.0.1:0".to_string()),
+            admin_token: Some(String::new()),
+            ..test_config()
+        };
+        let mut server = Server::new(config).expect("Failed to create server");
+        assert!(server.start().await.is_err());
+    }
 
-        let num_clients = 3;
-        let mut client_handles = vec![];
+    #[tokio::test]
+    async fn test_status_follows_session_transitions() {
+        let config = ServerConfig {
+            name: "Carrier Air Wing".to_string(),
+            ping_bind_address: Some("127.0.0.1:0".to_string()),
+            ..test_config()
+        };
+        let mut server = Server::new(config).expect("Failed to create server");
+        server.start().await.expect("Failed to start server");
+        assert_eq!(server.status().name, "Carrier Air Wing");
+        assert_eq!(server.status().version, env!("CARGO_PKG_VERSION"));
 
-        for i in 0..num_clients {
-            //let addr = addr;
-            let cert_path = bundle.cert_path.clone();
+        let mut session = Session {
+            id: "s1".to_string(),
+            user: User::new(UserId::new(1).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Authenticating,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::new(),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        };
+        let sessions = server.sessions().clone();
+        sessions
+            .transition(&mut session, SessionState::Active)
+            .unwrap();
+        assert!(
+            wait_until(Duration::from_secs(1), Duration::from_millis(10), || {
+                server.status().user_count == 1
+            })
+            .await
+        );
 
-            let handle = tokio::spawn(async move {
-                // Create client TLS config
-                let client_config =
-                    TlsConfig::new_client(&cert_path).expect("Failed to create client config");
-                let connector = TlsConnector::from(client_config.client_config.unwrap());
+        sessions
+            .transition(&mut session, SessionState::Disconnecting)
+            .unwrap();
+        assert!(
+            wait_until(Duration::from_secs(1), Duration::from_millis(10), || {
+                server.status().user_count == 0
+            })
+            .await
+        );
+    }
 
-                // connect to server
-                let tcp_stream = TcpStream::connect(addr).await.expect("Failed to connect");
+    #[tokio::test]
+    async fn test_unspecified_ipv6_listens_on_both_families() {
+        if !fleet_test_support::ipv6_available() {
+            return;
+        }
+        let config = ServerConfig {
+            bind_address: "[::]:0".to_string(),
+            ping_bind_address: Some("[::]:0".to_string()),
+            ..test_config()
+        };
+        let mut server = Server::new(config).expect("Failed to create server");
+        let addr = server.start().await.expect("Failed to start server");
+        let ping_port = server.status().ping_port.expect("Ping responder bound");
 
-                let domain = rustls::pki_types::ServerName::try_from("localhost".to_owned())
-                    .expect("Invalid domain");
-                let tls_stream = connector
-                    .connect(domain, tcp_stream)
-                    .await
-                    .expect("Failed to establish TLS");
+        for host in ["127.0.0.1", "::1"] {
+            let ip: IpAddr = host.parse().unwrap();
+            TcpStream::connect(SocketAddr::new(ip, addr.port()))
+                .await
+                .expect("Control port reachable");
+            ping::measure_rtt(
+                SocketAddr::new(ip, ping_port),
+                1,
+                Duration::from_millis(500),
+            )
+            .await
+            .expect("Ping answered");
+        }
+    }
 
-                let mut conn = Connection::new(tls_stream);
+    #[tokio::test]
+    async fn test_configured_rtp_exports_listen_to_their_channels() {
+        let channel_id = ChannelId::new(3).unwrap();
+        let listener_id = UserId::new(0xFFFF).unwrap();
+        let config = ServerConfig {
+            rtp_exports: vec![RtpExportConfig::new(
+                channel_id,
+                "127.0.0.1:5004".parse().unwrap(),
+                listener_id,
+            )],
+            ..test_config()
+        };
 
-                // Read ServerInfo message
-                let msg = conn.read_message().await.expect("Failed to read message");
+        let mut server = Server::new(config).expect("Failed to create server");
+        server.start().await.expect("Failed to start server");
 
-                // Verify we got ServerInfo
-                match msg {
-                    ControlMessage::ServerInfo { .. } => i,
-                    _ => panic!("Client {i} got unexpected message"),
-                }
-            });
+        let listeners = server.subscriptions().listeners(channel_id);
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].user_id, listener_id);
+    }
 
-            client_handles.push(handle);
+    #[tokio::test]
+    async fn test_voice_reaches_only_the_speakers_channel() {
+        let (cluster, mut clients) = TestCluster::start(3).await;
+        for id in [1, 2] {
+            let store = cluster.server().channels().store();
+            store.save(test_channel(id)).await.unwrap();
+        }
+        let (ops, lobby) = (ChannelId::new(1).unwrap(), ChannelId::new(2).unwrap());
+        for (id, (client, channel_id)) in clients.iter_mut().zip([ops, lobby, ops]).enumerate() {
+            client
+                .authenticate(UserId::new(id as u16 + 1).unwrap())
+                .await;
+            client.join_channel(channel_id).await;
         }
 
-        // Wait for all clients to complete
-        for handle in client_handles {
-            let client_num = handle.await.expect("Client task failed");
-            trace!("Client {client_num} successfully connected");
+        clients[0].transmit(ops, b"opus").await;
+        let heard = clients[2].recv_voice().await;
+        assert_eq!(heard.header.user_id, UserId::new(1).unwrap());
+        assert_eq!(heard.opus_payload, b"opus");
+
+        // Lobby voice sent in between never reaches the ops channel
+        clients[1].transmit(lobby, b"lobby").await;
+        clients[0].transmit(ops, b"again").await;
+        assert_eq!(clients[2].recv_voice().await.opus_payload, b"again");
+    }
+
+    #[tokio::test]
+    async fn test_relays_register_with_a_coordinator_and_forward_its_clients_voice() {
+        const SECRET: &str = "cluster-secret";
+        let config = ServerConfig {
+            cluster: ClusterMode::Coordinator {
+                relay_bind_address: "127.0.0.1:0".to_string(),
+                cluster_secret: SECRET.to_string(),
+            },
+            ..test_config()
+        };
+        let (cluster, mut clients) = TestCluster::start_with(config, 2).await;
+        let coordinator = cluster.server().coordinator().expect("Coordinator started");
+        let relay_config = |cluster_secret: &str| RelayConfig {
+            relay_id: "relay-1".to_string(),
+            coordinator_address: cluster.server().relay_address().unwrap().to_string(),
+            coordinator_hostname: crate::testing::TEST_HOSTNAME.to_string(),
+            coordinator_ca_path: Some(cluster.certs().cert_path.clone()),
+            voice_bind_address: "127.0.0.1:0".to_string(),
+            capacity: 10,
+            cluster_secret: cluster_secret.to_string(),
+        };
+
+        // Relays without the cluster secret are turned away
+        let mut intruder = Server::new(ServerConfig {
+            cluster: ClusterMode::Relay(relay_config("guess")),
+            ..test_config()
+        })
+        .unwrap();
+        assert!(intruder.start().await.is_err());
+
+        let mut relay = Server::new(ServerConfig {
+            cluster: ClusterMode::Relay(relay_config(SECRET)),
+            ..test_config()
+        })
+        .unwrap();
+        let voice_address = relay.start().await.expect("Relay registered");
+        let registered = coordinator.relay("relay-1").expect("Relay registered");
+        assert_eq!(registered.voice_address, voice_address);
+
+        // Listeners joining on the coordinator reach the relay
+        cluster
+            .server()
+            .channels()
+            .store()
+            .save(test_channel(1))
+            .await
+            .unwrap();
+        // Clients are assigned the relay, which learns their sessions first
+        let node = relay.relay().unwrap().clone();
+        let ops = ChannelId::new(1).unwrap();
+        for (client, user_id) in clients.iter_mut().zip([3, 4]) {
+            client.authenticate(UserId::new(user_id).unwrap()).await;
+            assert_eq!(client.voice_addr(), voice_address);
         }
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                node.sender_count() == 2
+            })
+            .await
+        );
+        for client in clients.iter_mut() {
+            client.join_channel(ops).await;
+        }
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                node.active_users() == 2
+            })
+            .await
+        );
 
-        // Cleanup: stop the server.as
-        server_handle.abort();
+        // The addresses the relay learned reach the coordinator's routes
+        assert!(
+            wait_until(Duration::from_secs(2), Duration::from_millis(10), || {
+                let mut relayed = node.listeners(ops);
+                relayed.sort_unstable_by_key(|listener| listener.user_id);
+                let mut routed = cluster.server().subscriptions().listeners(ops);
+                routed.sort_unstable_by_key(|listener| listener.user_id);
+                relayed.len() == 2 && relayed == routed
+            })
+            .await
+        );
+
+        // Voice goes through the relay, signed for each listener
+        clients[0].transmit(ops, b"opus").await;
+        let heard = clients[1].recv_voice().await;
+        assert_eq!(heard.header.user_id, UserId::new(3).unwrap());
+        assert_eq!(heard.opus_payload, b"opus");
+        assert_eq!(node.packets_forwarded(), 1);
     }
 }
@@ -0,0 +1,161 @@
+//! Session lifecycle transitions.
+//!
+//! Every state change goes through [`SessionLifecycle::transition`], which
+//! enforces the legal lifecycle from [`SessionState::can_transition_to`] and
+//! publishes each [`SessionTransition`], e.g. so the subscription registry
+//! can drop a disconnecting user's listeners.
+//!
+//! [`user_joined`] builds the announcement other users receive once a
+//! session is active.
+
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::session::{Session, SessionState, SessionTransition};
+use fleet_net_protocol::message::ControlMessage;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// Transitions buffered for slow subscribers.
+const TRANSITION_BUFFER: usize = 256;
+
+pub struct SessionLifecycle {
+    transitions: broadcast::Sender<SessionTransition>,
+}
+
+impl SessionLifecycle {
+    pub fn new() -> Self {
+        Self {
+            transitions: broadcast::channel(TRANSITION_BUFFER).0,
+        }
+    }
+
+    /// Receives every session transition from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionTransition> {
+        self.transitions.subscribe()
+    }
+
+    /// Moves `session` to `to` and publishes the transition.
+    ///
+    /// Illegal transitions are logged and rejected, leaving the session as
+    /// it was.
+    pub fn transition(
+        &self,
+        session: &mut Session,
+        to: SessionState,
+    ) -> Result<SessionTransition, FleetNetError> {
+        let transition = session.transition(to).inspect_err(|e| warn!("{e}"))?;
+        debug!(
+            "Session {} went from {:?} to {:?}",
+            transition.session_id, transition.from, transition.to
+        );
+        // Nobody listening is fine; the session itself is up to date.
+        let _ = self.transitions.send(transition.clone());
+        Ok(transition)
+    }
+}
+
+impl Default for SessionLifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The [`ControlMessage::UserJoined`] announcing `session` to other users.
+///
+/// Users who signed in with Discord are announced by their Discord username
+/// and avatar; others by their user id.
+pub fn user_joined(session: &Session) -> ControlMessage {
+    let user = &session.user;
+    let username = match &user.discord_user {
+        Some(discord_user) => discord_user.username.clone(),
+        None => format!("User {}", user.id),
+    };
+    ControlMessage::UserJoined {
+        user_id: user.id,
+        username,
+        channel_id: session.current_channel,
+        nickname: user.nickname.clone(),
+        avatar_url: user.avatar_url(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::types::UserId;
+    use fleet_net_common::user::{DiscordUser, User};
+    use std::time::Instant;
+
+    fn authenticating() -> Session {
+        Session {
+            id: "session".to_string(),
+            user: User::new(UserId::new(1).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Authenticating,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::new(),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_transitions_are_published() {
+        let lifecycle = SessionLifecycle::new();
+        let mut transitions = lifecycle.subscribe();
+        let mut session = authenticating();
+
+        assert!(lifecycle
+            .transition(&mut session, SessionState::Away)
+            .is_err());
+        lifecycle
+            .transition(&mut session, SessionState::Active)
+            .unwrap();
+        lifecycle
+            .transition(&mut session, SessionState::Disconnecting)
+            .unwrap();
+
+        let published: Vec<_> = std::iter::from_fn(|| transitions.try_recv().ok())
+            .map(|transition| (transition.from, transition.to))
+            .collect();
+        assert_eq!(
+            published,
+            [
+                (SessionState::Authenticating, SessionState::Active),
+                (SessionState::Active, SessionState::Disconnecting),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_joined_carries_discord_avatar() {
+        let mut session = authenticating();
+        assert!(matches!(
+            user_joined(&session),
+            ControlMessage::UserJoined { username, avatar_url: None, .. } if username == "User 1"
+        ));
+
+        session.user.discord_user = Some(DiscordUser {
+            id: "987654321".to_string(),
+            username: "nelly".to_string(),
+            discriminator: None,
+            avatar: Some("a_1269e74af4df7417b13759eae50c83dc".to_string()),
+        });
+        let ControlMessage::UserJoined {
+            username,
+            avatar_url,
+            ..
+        } = user_joined(&session)
+        else {
+            panic!("expected user_joined");
+        };
+        assert_eq!(username, "nelly");
+        assert_eq!(
+            avatar_url.as_deref(),
+            Some("https://cdn.discordapp.com/avatars/987654321/a_1269e74af4df7417b13759eae50c83dc.gif?size=128")
+        );
+    }
+}
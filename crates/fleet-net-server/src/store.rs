@@ -0,0 +1,679 @@
+//! Session and channel state stores.
+//!
+//! The server keeps its session and channel state behind the [`SessionStore`]
+//! and [`ChannelStore`] traits, and users' nicknames behind [`NicknameStore`].
+//! A single node uses the in-memory stores; a clustered or highly available
+//! deployment can enable the `redis` feature and set [`StoreConfig::Redis`]
+//! to share state between nodes and across restarts. [`Stores::open`] picks
+//! the backend the configuration names, and the server's registries keep
+//! their state in it.
+
+use dashmap::DashMap;
+use fleet_net_common::channel::Channel;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::session::SessionSnapshot;
+use fleet_net_common::types::{ChannelId, UserId};
+use std::future::Future;
+
+/// Where the server keeps session, channel and nickname state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StoreConfig {
+    /// In this process, lost on restart.
+    #[default]
+    InMemory,
+    /// In Redis at `url`, e.g. `redis://127.0.0.1:6379`, under keys starting
+    /// with `prefix`. Needs the `redis` feature.
+    Redis { url: String, prefix: String },
+}
+
+/// Storage for session snapshots, keyed by session id.
+pub trait SessionStore: Send + Sync {
+    /// Inserts or replaces a session snapshot.
+    fn save(
+        &self,
+        snapshot: SessionSnapshot,
+    ) -> impl Future<Output = Result<(), FleetNetError>> + Send;
+
+    fn load(
+        &self,
+        session_id: &str,
+    ) -> impl Future<Output = Result<Option<SessionSnapshot>, FleetNetError>> + Send;
+
+    fn remove(&self, session_id: &str) -> impl Future<Output = Result<(), FleetNetError>> + Send;
+
+    fn list(&self) -> impl Future<Output = Result<Vec<SessionSnapshot>, FleetNetError>> + Send;
+}
+
+/// Storage for channel definitions, keyed by channel id.
+pub trait ChannelStore: Send + Sync {
+    /// Inserts or replaces a channel.
+    fn save(&self, channel: Channel) -> impl Future<Output = Result<(), FleetNetError>> + Send;
+
+    fn load(
+        &self,
+        channel_id: ChannelId,
+    ) -> impl Future<Output = Result<Option<Channel>, FleetNetError>> + Send;
+
+    fn remove(
+        &self,
+        channel_id: ChannelId,
+    ) -> impl Future<Output = Result<(), FleetNetError>> + Send;
+
+    fn list(&self) -> impl Future<Output = Result<Vec<Channel>, FleetNetError>> + Send;
+}
+
+/// Storage for per-server nicknames, keyed by user id.
+///
+/// Nicknames outlive sessions so users keep them across reconnects.
+pub trait NicknameStore: Send + Sync {
+    /// Sets the nickname of `user_id`, or clears it when `None`.
+    fn save(
+        &self,
+        user_id: UserId,
+        nickname: Option<String>,
+    ) -> impl Future<Output = Result<(), FleetNetError>> + Send;
+
+    fn load(
+        &self,
+        user_id: UserId,
+    ) -> impl Future<Output = Result<Option<String>, FleetNetError>> + Send;
+}
+
+/// Process-local session store used by standalone servers.
+#[derive(Default)]
+pub struct InMemorySessionStore {
+    sessions: DashMap<String, SessionSnapshot>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for InMemorySessionStore {
+    async fn save(&self, snapshot: SessionSnapshot) -> Result<(), FleetNetError> {
+        self.sessions.insert(snapshot.id.clone(), snapshot);
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<SessionSnapshot>, FleetNetError> {
+        Ok(self.sessions.get(session_id).map(|entry| entry.clone()))
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), FleetNetError> {
+        self.sessions.remove(session_id);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<SessionSnapshot>, FleetNetError> {
+        Ok(self
+            .sessions
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect())
+    }
+}
+
+/// Process-local channel store used by standalone servers.
+#[derive(Default)]
+pub struct InMemoryChannelStore {
+    channels: DashMap<ChannelId, Channel>,
+}
+
+impl InMemoryChannelStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChannelStore for InMemoryChannelStore {
+    async fn save(&self, channel: Channel) -> Result<(), FleetNetError> {
+        self.channels.insert(channel.id, channel);
+        Ok(())
+    }
+
+    async fn load(&self, channel_id: ChannelId) -> Result<Option<Channel>, FleetNetError> {
+        Ok(self.channels.get(&channel_id).map(|entry| entry.clone()))
+    }
+
+    async fn remove(&self, channel_id: ChannelId) -> Result<(), FleetNetError> {
+        self.channels.remove(&channel_id);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Channel>, FleetNetError> {
+        Ok(self
+            .channels
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect())
+    }
+}
+
+/// Process-local nickname store used by standalone servers.
+#[derive(Default)]
+pub struct InMemoryNicknameStore {
+    nicknames: DashMap<UserId, String>,
+}
+
+impl InMemoryNicknameStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl NicknameStore for InMemoryNicknameStore {
+    async fn save(&self, user_id: UserId, nickname: Option<String>) -> Result<(), FleetNetError> {
+        match nickname {
+            Some(nickname) => self.nicknames.insert(user_id, nickname),
+            None => self
+                .nicknames
+                .remove(&user_id)
+                .map(|(_, nickname)| nickname),
+        };
+        Ok(())
+    }
+
+    async fn load(&self, user_id: UserId) -> Result<Option<String>, FleetNetError> {
+        Ok(self.nicknames.get(&user_id).map(|entry| entry.clone()))
+    }
+}
+
+/// Forwards a store call to whichever backend was configured.
+macro_rules! delegate {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        match $self {
+            Self::InMemory(store) => store.$method($($arg),*).await,
+            #[cfg(feature = "redis")]
+            Self::Redis(store) => store.$method($($arg),*).await,
+        }
+    };
+}
+
+/// The session store [`StoreConfig`] chose.
+pub enum SessionBackend {
+    InMemory(InMemorySessionStore),
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisSessionStore),
+}
+
+impl SessionBackend {
+    /// Connects to the backend, which all stores of [`Stores::open`] share,
+    /// so a server refuses to start with a store it cannot reach rather than
+    /// failing its first clients.
+    pub async fn connect(&self) -> Result<(), FleetNetError> {
+        match self {
+            Self::InMemory(_) => Ok(()),
+            #[cfg(feature = "redis")]
+            Self::Redis(store) => store.connect().await,
+        }
+    }
+}
+
+impl SessionStore for SessionBackend {
+    async fn save(&self, snapshot: SessionSnapshot) -> Result<(), FleetNetError> {
+        delegate!(self.save(snapshot))
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<SessionSnapshot>, FleetNetError> {
+        delegate!(self.load(session_id))
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), FleetNetError> {
+        delegate!(self.remove(session_id))
+    }
+
+    async fn list(&self) -> Result<Vec<SessionSnapshot>, FleetNetError> {
+        delegate!(self.list())
+    }
+}
+
+/// The channel store [`StoreConfig`] chose.
+pub enum ChannelBackend {
+    InMemory(InMemoryChannelStore),
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisChannelStore),
+}
+
+impl ChannelStore for ChannelBackend {
+    async fn save(&self, channel: Channel) -> Result<(), FleetNetError> {
+        delegate!(self.save(channel))
+    }
+
+    async fn load(&self, channel_id: ChannelId) -> Result<Option<Channel>, FleetNetError> {
+        delegate!(self.load(channel_id))
+    }
+
+    async fn remove(&self, channel_id: ChannelId) -> Result<(), FleetNetError> {
+        delegate!(self.remove(channel_id))
+    }
+
+    async fn list(&self) -> Result<Vec<Channel>, FleetNetError> {
+        delegate!(self.list())
+    }
+}
+
+/// The nickname store [`StoreConfig`] chose.
+pub enum NicknameBackend {
+    InMemory(InMemoryNicknameStore),
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisNicknameStore),
+}
+
+impl NicknameStore for NicknameBackend {
+    async fn save(&self, user_id: UserId, nickname: Option<String>) -> Result<(), FleetNetError> {
+        delegate!(self.save(user_id, nickname))
+    }
+
+    async fn load(&self, user_id: UserId) -> Result<Option<String>, FleetNetError> {
+        delegate!(self.load(user_id))
+    }
+}
+
+/// One store of each kind, all in the backend [`StoreConfig`] names.
+pub struct Stores {
+    pub sessions: SessionBackend,
+    pub channels: ChannelBackend,
+    pub nicknames: NicknameBackend,
+}
+
+impl Stores {
+    pub fn in_memory() -> Self {
+        Self {
+            sessions: SessionBackend::InMemory(InMemorySessionStore::new()),
+            channels: ChannelBackend::InMemory(InMemoryChannelStore::new()),
+            nicknames: NicknameBackend::InMemory(InMemoryNicknameStore::new()),
+        }
+    }
+
+    /// Opens the stores `config` names. Redis is connected to on first use,
+    /// see [`SessionBackend::connect`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error if `config` names Redis and this build
+    /// lacks the `redis` feature.
+    pub fn open(config: &StoreConfig) -> Result<Self, FleetNetError> {
+        match config {
+            StoreConfig::InMemory => Ok(Self::in_memory()),
+            #[cfg(feature = "redis")]
+            StoreConfig::Redis { url, prefix } => {
+                let conn = redis::SharedConnection::new(url.clone());
+                Ok(Self {
+                    sessions: SessionBackend::Redis(redis::RedisSessionStore::new(
+                        conn.clone(),
+                        prefix.clone(),
+                    )),
+                    channels: ChannelBackend::Redis(redis::RedisChannelStore::new(
+                        conn.clone(),
+                        prefix.clone(),
+                    )),
+                    nicknames: NicknameBackend::Redis(redis::RedisNicknameStore::new(
+                        conn,
+                        prefix.clone(),
+                    )),
+                })
+            }
+            #[cfg(not(feature = "redis"))]
+            StoreConfig::Redis { .. } => Err(FleetNetError::invalid_field(
+                "store",
+                fleet_net_common::validation::Constraint::Invalid(std::borrow::Cow::Borrowed(
+                    "unsupported_store(redis)",
+                )),
+            )),
+        }
+    }
+}
+
+/// Redis-backed stores shared between cluster nodes.
+///
+/// Each record is stored as JSON under `{prefix}:session:{id}`,
+/// `{prefix}:channel:{id}` or `{prefix}:nickname:{id}`, with a set of ids per
+/// record type so the stores can be listed without scanning the keyspace.
+#[cfg(feature = "redis")]
+pub mod redis {
+    use super::{ChannelStore, NicknameStore, SessionStore};
+    use ::redis::aio::MultiplexedConnection;
+    use ::redis::AsyncCommands;
+    use fleet_net_common::channel::Channel;
+    use fleet_net_common::error::FleetNetError;
+    use fleet_net_common::session::SessionSnapshot;
+    use fleet_net_common::types::{ChannelId, UserId};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::borrow::Cow;
+    use std::sync::Arc;
+    use tokio::sync::OnceCell;
+
+    fn redis_error(err: ::redis::RedisError) -> FleetNetError {
+        FleetNetError::NetworkError(Cow::Owned(format!("Redis error: {err}")))
+    }
+
+    /// A multiplexed connection shared by all stores, opened on first use so
+    /// stores can be set up before the server starts.
+    #[derive(Clone)]
+    pub struct SharedConnection {
+        url: String,
+        conn: Arc<OnceCell<MultiplexedConnection>>,
+    }
+
+    impl SharedConnection {
+        pub fn new(url: impl Into<String>) -> Self {
+            Self {
+                url: url.into(),
+                conn: Arc::new(OnceCell::new()),
+            }
+        }
+
+        async fn get(&self) -> Result<MultiplexedConnection, FleetNetError> {
+            self.conn
+                .get_or_try_init(|| async {
+                    let client = ::redis::Client::open(self.url.as_str()).map_err(redis_error)?;
+                    client
+                        .get_multiplexed_async_connection()
+                        .await
+                        .map_err(redis_error)
+                })
+                .await
+                .cloned()
+        }
+    }
+
+    /// Key layout shared by the Redis stores.
+    #[derive(Clone)]
+    struct Keyspace {
+        prefix: String,
+        kind: &'static str,
+    }
+
+    impl Keyspace {
+        fn record(&self, id: &str) -> String {
+            format!("{}:{}:{id}", self.prefix, self.kind)
+        }
+
+        fn index(&self) -> String {
+            format!("{}:{}s", self.prefix, self.kind)
+        }
+
+        async fn save<T: Serialize>(
+            &self,
+            mut conn: MultiplexedConnection,
+            id: &str,
+            value: &T,
+        ) -> Result<(), FleetNetError> {
+            let json = serde_json::to_string(value)?;
+            ::redis::pipe()
+                .atomic()
+                .set(self.record(id), json)
+                .sadd(self.index(), id)
+                .query_async::<()>(&mut conn)
+                .await
+                .map_err(redis_error)
+        }
+
+        async fn load<T: DeserializeOwned>(
+            &self,
+            mut conn: MultiplexedConnection,
+            id: &str,
+        ) -> Result<Option<T>, FleetNetError> {
+            let json: Option<String> = conn.get(self.record(id)).await.map_err(redis_error)?;
+            json.map(|json| serde_json::from_str(&json).map_err(FleetNetError::from))
+                .transpose()
+        }
+
+        async fn remove(
+            &self,
+            mut conn: MultiplexedConnection,
+            id: &str,
+        ) -> Result<(), FleetNetError> {
+            ::redis::pipe()
+                .atomic()
+                .del(self.record(id))
+                .srem(self.index(), id)
+                .query_async::<()>(&mut conn)
+                .await
+                .map_err(redis_error)
+        }
+
+        async fn list<T: DeserializeOwned>(
+            &self,
+            mut conn: MultiplexedConnection,
+        ) -> Result<Vec<T>, FleetNetError> {
+            let ids: Vec<String> = conn.smembers(self.index()).await.map_err(redis_error)?;
+            let mut records = Vec::with_capacity(ids.len());
+            for id in ids {
+                // Entries can disappear between SMEMBERS and GET; skip them.
+                if let Some(record) = self.load(conn.clone(), &id).await? {
+                    records.push(record);
+                }
+            }
+            Ok(records)
+        }
+    }
+
+    /// Session store persisted in Redis.
+    pub struct RedisSessionStore {
+        conn: SharedConnection,
+        keys: Keyspace,
+    }
+
+    impl RedisSessionStore {
+        pub fn new(conn: SharedConnection, prefix: impl Into<String>) -> Self {
+            Self {
+                conn,
+                keys: Keyspace {
+                    prefix: prefix.into(),
+                    kind: "session",
+                },
+            }
+        }
+
+        /// Opens the shared connection unless it already is.
+        pub async fn connect(&self) -> Result<(), FleetNetError> {
+            self.conn.get().await.map(drop)
+        }
+    }
+
+    impl SessionStore for RedisSessionStore {
+        async fn save(&self, snapshot: SessionSnapshot) -> Result<(), FleetNetError> {
+            self.keys
+                .save(self.conn.get().await?, &snapshot.id, &snapshot)
+                .await
+        }
+
+        async fn load(&self, session_id: &str) -> Result<Option<SessionSnapshot>, FleetNetError> {
+            self.keys.load(self.conn.get().await?, session_id).await
+        }
+
+        async fn remove(&self, session_id: &str) -> Result<(), FleetNetError> {
+            self.keys.remove(self.conn.get().await?, session_id).await
+        }
+
+        async fn list(&self) -> Result<Vec<SessionSnapshot>, FleetNetError> {
+            self.keys.list(self.conn.get().await?).await
+        }
+    }
+
+    /// Channel store persisted in Redis.
+    pub struct RedisChannelStore {
+        conn: SharedConnection,
+        keys: Keyspace,
+    }
+
+    impl RedisChannelStore {
+        pub fn new(conn: SharedConnection, prefix: impl Into<String>) -> Self {
+            Self {
+                conn,
+                keys: Keyspace {
+                    prefix: prefix.into(),
+                    kind: "channel",
+                },
+            }
+        }
+    }
+
+    impl ChannelStore for RedisChannelStore {
+        async fn save(&self, channel: Channel) -> Result<(), FleetNetError> {
+            self.keys
+                .save(self.conn.get().await?, &channel.id.to_string(), &channel)
+                .await
+        }
+
+        async fn load(&self, channel_id: ChannelId) -> Result<Option<Channel>, FleetNetError> {
+            self.keys
+                .load(self.conn.get().await?, &channel_id.to_string())
+                .await
+        }
+
+        async fn remove(&self, channel_id: ChannelId) -> Result<(), FleetNetError> {
+            self.keys
+                .remove(self.conn.get().await?, &channel_id.to_string())
+                .await
+        }
+
+        async fn list(&self) -> Result<Vec<Channel>, FleetNetError> {
+            self.keys.list(self.conn.get().await?).await
+        }
+    }
+
+    /// Nickname store persisted in Redis.
+    pub struct RedisNicknameStore {
+        conn: SharedConnection,
+        keys: Keyspace,
+    }
+
+    impl RedisNicknameStore {
+        pub fn new(conn: SharedConnection, prefix: impl Into<String>) -> Self {
+            Self {
+                conn,
+                keys: Keyspace {
+                    prefix: prefix.into(),
+                    kind: "nickname",
+                },
+            }
+        }
+    }
+
+    impl NicknameStore for RedisNicknameStore {
+        async fn save(
+            &self,
+            user_id: UserId,
+            nickname: Option<String>,
+        ) -> Result<(), FleetNetError> {
+            let id = user_id.to_string();
+            match nickname {
+                Some(nickname) => self.keys.save(self.conn.get().await?, &id, &nickname).await,
+                None => self.keys.remove(self.conn.get().await?, &id).await,
+            }
+        }
+
+        async fn load(&self, user_id: UserId) -> Result<Option<String>, FleetNetError> {
+            self.keys
+                .load(self.conn.get().await?, &user_id.to_string())
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::{AudioPolicy, ChannelType};
+    use fleet_net_common::session::{Session, SessionState};
+    use fleet_net_common::types::UserId;
+    use fleet_net_common::user::User;
+    use fleet_net_common::PermissionSet;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Instant;
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    fn test_snapshot(id: &str) -> SessionSnapshot {
+        Session {
+            id: id.to_string(),
+            user: User::new(user(1)),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: Some(channel(3)),
+            subscribed_channels: HashSet::from([channel(3)]),
+            permission: PermissionSet::new(),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+        .snapshot()
+    }
+
+    fn test_channel(id: ChannelId) -> Channel {
+        Channel {
+            id,
+            name: format!("Channel {id}"),
+            description: None,
+            channel_type: ChannelType::Voice,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_session_store() {
+        let store = InMemorySessionStore::new();
+        store.save(test_snapshot("a")).await.unwrap();
+        store.save(test_snapshot("b")).await.unwrap();
+
+        let loaded = store.load("a").await.unwrap().expect("Session a stored");
+        assert_eq!(loaded.current_channel, Some(channel(3)));
+        assert_eq!(store.list().await.unwrap().len(), 2);
+
+        store.remove("a").await.unwrap();
+        assert!(store.load("a").await.unwrap().is_none());
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_channel_store() {
+        let store = InMemoryChannelStore::new();
+        store.save(test_channel(channel(1))).await.unwrap();
+
+        // Saving again replaces the existing channel
+        let mut renamed = test_channel(channel(1));
+        renamed.name = "Renamed".to_string();
+        store.save(renamed).await.unwrap();
+
+        let loaded = store
+            .load(channel(1))
+            .await
+            .unwrap()
+            .expect("Channel 1 stored");
+        assert_eq!(loaded.name, "Renamed");
+        assert_eq!(store.list().await.unwrap().len(), 1);
+
+        store.remove(channel(1)).await.unwrap();
+        assert!(store.load(channel(1)).await.unwrap().is_none());
+    }
+
+    #[cfg(not(feature = "redis"))]
+    #[test]
+    fn test_redis_store_needs_the_feature() {
+        let config = StoreConfig::Redis {
+            url: "redis://127.0.0.1:6379".to_string(),
+            prefix: "fleet-net:".to_string(),
+        };
+        assert!(Stores::open(&config).is_err());
+        assert!(Stores::open(&StoreConfig::InMemory).is_ok());
+    }
+}
@@ -0,0 +1,2821 @@
+//! Radio channel subscriptions and voice fan-out.
+//!
+//! A session hears its current channel plus any radio channels it subscribes
+//! to with [`ControlMessage::SubscribeChannel`]. The registry keeps the voice
+//! address of every listener per channel so a packet sent on a channel can be
+//! forwarded to all of them, and its subscriber lists double as the routing
+//! tables published to relays in a cluster.
+//!
+//! Radio channels tuned to the same net (see
+//! [`RadioChannelConfig`](fleet_net_common::channel::RadioChannelConfig))
+//! are linked, so a transmission on one reaches the listeners of all of
+//! them. Listeners of radio channels on the frequency of an encrypted net
+//! without its key get its transmissions [scrambled](PacketHeader::scrambled),
+//! with the audio left out for their client to play noise instead.
+//!
+//! Holders of [`Permissions::SIMULCAST`] can key several radios at once by
+//! declaring their transmit targets with
+//! [`ControlMessage::SetTransmitTargets`]. A packet sent on one of the
+//! targets then goes out on all of them, each target's listeners getting it
+//! on that channel and nobody getting it twice.
+//!
+//! Packets are checked against the [`AudioPolicy`] of every channel they
+//! go out on before they are forwarded; a refused packet yields an error
+//! explaining the rule, for the connection to pass on to the sender. In
+//! channels in [spectator mode](AudioPolicy::spectator_mode), that refuses
+//! every packet from listeners without [`Permissions::SPEAK`].
+//!
+//! In channels with [preemption](AudioPolicy::preemption) one sender is on
+//! the air at a time. Senders rank by their [transmit
+//! priority](SubscriptionRegistry::set_transmit_priority), lower values
+//! first like role priorities: one outranking the sender on the air takes
+//! the channel over, and anyone else keying up meanwhile is cut off. Either
+//! way the loser is stepped on, published as [`TransmissionEvent::SteppedOn`]
+//! once per transmission, and their packets are dropped until the channel
+//! has been silent for [`TRANSMISSION_GAP`]; ties go to whoever was first.
+//!
+//! Each transmission is published as a [`TransmissionEvent`] when it starts
+//! and once it has been silent for [`TRANSMISSION_GAP`], the hangover that
+//! keeps the gaps between words from ending it. The same moments are
+//! broadcast to clients as [`ControlMessage::UserSpeaking`], so those not
+//! hearing a channel can still show who is talking on it.
+//!
+//! In channels that allow [retransmission](AudioPolicy::retransmit) the
+//! packets forwarded in the last [`RETRANSMIT_WINDOW`] are kept, and a
+//! listener who lost some can ask for them with
+//! [`ControlMessage::RetransmitRequest`]. Each is sent again once, as that
+//! listener got it the first time; [`ControlMessage::SubscriptionsChanged`]
+//! tells clients which of their channels allow it. Asking for any frame of
+//! a batched packet sends the whole batch again.
+//!
+//! Packets on radio nets then pass the [`RadioRealism`] scenario, which may
+//! weaken their signal strength or drop them. With a [`PropagationConfig`]
+//! the signal strength each listener gets is computed from where the sender
+//! and the listener last were, as put in their packets by game telemetry;
+//! listeners too far away don't get the packet. Listeners whose position
+//! is unknown, e.g. as they have not transmitted for a while, get the
+//! strength the sender put in the packet.
+//!
+//! With [padding](SubscriptionRegistry::with_padding) configured, every
+//! packet goes out padded to a multiple of the block size, noise and
+//! retransmissions included, whether or not its sender padded it.
+//!
+//! Clients on networks that block UDP tunnel their voice through the
+//! control connection as [`ControlMessage::VoiceTunnel`] messages. Their
+//! packets are [forwarded](SubscriptionRegistry::forward_tunneled) like any
+//! other, and packets for a user with an [open
+//! tunnel](SubscriptionRegistry::open_tunnel) go to it instead of their
+//! voice address.
+//!
+//! Every listener gets a packet's header signed with their own [session
+//! key](SubscriptionRegistry::set_session_key), as the header is rewritten
+//! per listener on the way. Listeners without one, e.g. the bridges, get
+//! rewritten headers unsigned.
+
+use crate::propagation::PropagationConfig;
+use crate::realism::{RadioRealism, Reception};
+use dashmap::{DashMap, DashSet};
+use fleet_net_common::audio::TransmitMode;
+use fleet_net_common::channel::{AudioPolicy, ChannelTree, RadioChannelConfig};
+use fleet_net_common::clock::{self, Clock};
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::session::Session;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_common::validation::{Constraint, Validate};
+use fleet_net_protocol::cluster::{RelaySender, RelaySubscriber};
+use fleet_net_protocol::dual_stack;
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::{pad_datagram, PacketHeader, SpeakerPosition};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+/// Silence after which the next packet starts a new transmission.
+pub const TRANSMISSION_GAP: Duration = Duration::from_millis(500);
+
+/// Audio received before a transmission's bitrate is judged, so a short
+/// burst of small frames is not mistaken for a low bitrate.
+const BITRATE_WINDOW_MS: u64 = 1_000;
+
+/// How long a speaker's last position is used for propagation.
+pub const POSITION_TTL: Duration = Duration::from_secs(120);
+
+/// Transmission events buffered for slow subscribers.
+const EVENT_BUFFER: usize = 256;
+
+/// How long forwarded packets are kept for listeners to ask for again.
+pub const RETRANSMIT_WINDOW: Duration = Duration::from_secs(1);
+
+/// A transmission starting, ending or being stepped on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransmissionEvent {
+    Started {
+        user_id: UserId,
+        channel_id: ChannelId,
+    },
+    Stopped {
+        user_id: UserId,
+        channel_id: ChannelId,
+        /// From the first packet to the last.
+        duration: Duration,
+    },
+    /// `user_id` is cut off on `channel_id`, a channel with preemption, as
+    /// `by` outranks them and is on the air.
+    SteppedOn {
+        user_id: UserId,
+        channel_id: ChannelId,
+        by: UserId,
+    },
+}
+
+impl TransmissionEvent {
+    /// The [`ControlMessage::UserSpeaking`] broadcast for transmissions
+    /// starting or stopping.
+    pub fn speaking_update(&self) -> Option<ControlMessage> {
+        match *self {
+            TransmissionEvent::Started {
+                user_id,
+                channel_id,
+            } => Some(ControlMessage::UserSpeaking {
+                user_id,
+                channel_id,
+                speaking: true,
+            }),
+            TransmissionEvent::Stopped {
+                user_id,
+                channel_id,
+                ..
+            } => Some(ControlMessage::UserSpeaking {
+                user_id,
+                channel_id,
+                speaking: false,
+            }),
+            TransmissionEvent::SteppedOn { .. } => None,
+        }
+    }
+
+    /// The [`ControlMessage::TransmissionSteppedOn`] to send the user cut
+    /// off, for events telling of one.
+    pub fn stepped_on_notice(&self) -> Option<(UserId, ControlMessage)> {
+        match *self {
+            TransmissionEvent::SteppedOn {
+                user_id,
+                channel_id,
+                by,
+            } => Some((
+                user_id,
+                ControlMessage::TransmissionSteppedOn { channel_id, by },
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Who is on the air in a channel with preemption.
+#[derive(Debug, Clone)]
+struct Floor {
+    user_id: UserId,
+    priority: u32,
+    last_packet: Instant,
+    /// Senders already told they are stepped on while it is held.
+    stepped_on: Vec<UserId>,
+}
+
+/// A user's ongoing transmission, for policy checks.
+#[derive(Debug, Clone, Copy)]
+struct Transmission {
+    channel_id: ChannelId,
+    started: Instant,
+    last_packet: Instant,
+    audio_bytes: u64,
+    audio_ms: u64,
+}
+
+/// A packet forwarded on channels that allow retransmission.
+#[derive(Debug, Clone)]
+struct Forwarded {
+    /// Sequence number of its first frame.
+    sequence: u16,
+    /// Frames it batches, see [`PacketHeader::frames`].
+    frames: u8,
+    received: Instant,
+    datagram: Vec<u8>,
+    /// Who got it in the clear on such a channel, with the header they got.
+    listeners: Vec<(RelaySubscriber, PacketHeader)>,
+    /// Listeners it was already sent again.
+    retransmitted: Vec<UserId>,
+}
+
+/// Who hears a packet on one of the channels it goes out on.
+struct Route {
+    /// The packet's header, on that channel.
+    header: PacketHeader,
+    clear: Vec<RelaySubscriber>,
+    scrambled: Vec<RelaySubscriber>,
+}
+
+/// Listeners per channel, keyed by the channel they receive audio from.
+pub struct SubscriptionRegistry {
+    channels: DashMap<ChannelId, Vec<RelaySubscriber>>,
+    /// Other radio channels hearing each tuned channel's transmissions.
+    radio_nets: DashMap<ChannelId, Vec<ChannelId>>,
+    /// Other radio channels hearing each encrypted channel's transmissions
+    /// as noise.
+    keyless_nets: DashMap<ChannelId, Vec<ChannelId>>,
+    /// Tuning of every radio channel, for propagation.
+    radio_tunings: DashMap<ChannelId, RadioChannelConfig>,
+    /// Policies of channels with other than the default one.
+    audio_policies: DashMap<ChannelId, AudioPolicy>,
+    transmit_modes: DashMap<UserId, TransmitMode>,
+    /// Users without the permission to speak, who only spectate in
+    /// channels in spectator mode.
+    listen_only: DashSet<UserId>,
+    /// Channels each simulcasting user transmits on at once.
+    transmit_targets: DashMap<UserId, Vec<ChannelId>>,
+    transmissions: DashMap<UserId, Transmission>,
+    /// Rank of each user in channels with preemption; lower values win.
+    transmit_priorities: DashMap<UserId, u32>,
+    /// Who holds each channel with preemption.
+    floors: DashMap<ChannelId, Floor>,
+    /// Packets each speaker sent in the last [`RETRANSMIT_WINDOW`], oldest
+    /// first, on channels that allow retransmission.
+    forwarded: DashMap<UserId, VecDeque<Forwarded>>,
+    transmission_events: broadcast::Sender<TransmissionEvent>,
+    speaking: broadcast::Sender<ControlMessage>,
+    realism: Arc<RadioRealism>,
+    /// Last position of each speaker and when it was received.
+    positions: DashMap<UserId, (SpeakerPosition, Instant)>,
+    propagation: Option<PropagationConfig>,
+    /// Block size forwarded packets are padded to.
+    padding: Option<u16>,
+    /// Users getting their voice over the control connection.
+    tunnels: DashMap<UserId, mpsc::UnboundedSender<ControlMessage>>,
+    /// Keys forwarded headers are signed with, per listener.
+    session_keys: DashMap<UserId, HmacKey>,
+    packets_forwarded: AtomicU64,
+    limits: ServerLimits,
+    clock: Arc<dyn Clock>,
+}
+
+impl SubscriptionRegistry {
+    pub fn new() -> Self {
+        Self {
+            channels: DashMap::new(),
+            radio_nets: DashMap::new(),
+            keyless_nets: DashMap::new(),
+            radio_tunings: DashMap::new(),
+            audio_policies: DashMap::new(),
+            transmit_modes: DashMap::new(),
+            listen_only: DashSet::new(),
+            transmit_targets: DashMap::new(),
+            transmissions: DashMap::new(),
+            transmit_priorities: DashMap::new(),
+            floors: DashMap::new(),
+            forwarded: DashMap::new(),
+            transmission_events: broadcast::channel(EVENT_BUFFER).0,
+            speaking: broadcast::channel(EVENT_BUFFER).0,
+            realism: Arc::new(RadioRealism::new()),
+            positions: DashMap::new(),
+            propagation: None,
+            padding: None,
+            tunnels: DashMap::new(),
+            session_keys: DashMap::new(),
+            packets_forwarded: AtomicU64::new(0),
+            limits: ServerLimits::default(),
+            clock: clock::system(),
+        }
+    }
+
+    /// Checks requests against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Times forwarded packets with `clock`, for policy checks.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Judges radio traffic with `realism`, shared with its admin API.
+    pub fn with_realism(mut self, realism: Arc<RadioRealism>) -> Self {
+        self.realism = realism;
+        self
+    }
+
+    /// Computes the signal strength of radio traffic per listener with
+    /// `propagation` instead of forwarding the sender's.
+    pub fn with_propagation(mut self, propagation: PropagationConfig) -> Self {
+        self.propagation = Some(propagation);
+        self
+    }
+
+    /// Pads every forwarded packet to a multiple of `block` bytes, see
+    /// [`pad_datagram`].
+    pub fn with_padding(mut self, block: u16) -> Self {
+        self.padding = Some(block);
+        self
+    }
+
+    /// Relinks and retunes radio channels and reloads audio policies after
+    /// channels in `tree` changed.
+    pub fn update_channels(&self, tree: &ChannelTree) {
+        self.realism.update_channels(tree);
+        self.radio_nets.clear();
+        self.keyless_nets.clear();
+        self.radio_tunings.clear();
+        self.audio_policies.clear();
+        for (_, channel) in tree.iter() {
+            if let Some(radio) = &channel.radio {
+                self.radio_tunings.insert(channel.id, radio.clone());
+            }
+            if channel.audio_policy != AudioPolicy::default() {
+                self.audio_policies.insert(channel.id, channel.audio_policy);
+            }
+            let linked: Vec<ChannelId> = tree
+                .radio_net(channel.id)
+                .into_iter()
+                .filter(|&id| id != channel.id)
+                .collect();
+            if !linked.is_empty() {
+                self.radio_nets.insert(channel.id, linked);
+            }
+            let keyless = tree.radio_net_without_key(channel.id);
+            if !keyless.is_empty() {
+                self.keyless_nets.insert(channel.id, keyless);
+            }
+        }
+    }
+
+    /// Records how `user_id` decides when to transmit, as announced with
+    /// [`ControlMessage::SetTransmitMode`].
+    pub fn set_transmit_mode(&self, user_id: UserId, mode: TransmitMode) {
+        self.transmit_modes.insert(user_id, mode);
+    }
+
+    /// Makes `user_id` transmit on all of `channel_ids` when sending on one
+    /// of them, as declared with [`ControlMessage::SetTransmitTargets`];
+    /// none clears the targets. Permissions and subscriptions are not
+    /// checked.
+    pub fn set_transmit_targets(&self, user_id: UserId, channel_ids: Vec<ChannelId>) {
+        if channel_ids.is_empty() {
+            self.transmit_targets.remove(&user_id);
+        } else {
+            self.transmit_targets.insert(user_id, channel_ids);
+        }
+    }
+
+    /// Ranks `user_id` for preemption, lower values first, e.g. with the
+    /// priority of their highest role from
+    /// [`RoleRegistry::transmit_priority`](crate::roles::RoleRegistry::transmit_priority).
+    /// Users never ranked come last.
+    pub fn set_transmit_priority(&self, user_id: UserId, priority: u32) {
+        self.transmit_priorities.insert(user_id, priority);
+    }
+
+    /// Receives every transmission starting or ending from now on.
+    pub fn subscribe_transmissions(&self) -> broadcast::Receiver<TransmissionEvent> {
+        self.transmission_events.subscribe()
+    }
+
+    /// Receives a [`ControlMessage::UserSpeaking`] for every transmission
+    /// starting or stopping from now on, for every client.
+    pub fn subscribe_speaking(&self) -> broadcast::Receiver<ControlMessage> {
+        self.speaking.subscribe()
+    }
+
+    pub fn packets_forwarded(&self) -> u64 {
+        self.packets_forwarded.load(Ordering::Relaxed)
+    }
+
+    /// Adds `subscriber` as a listener of `channel_id`, replacing any earlier
+    /// address for the same user.
+    pub fn add_listener(&self, channel_id: ChannelId, subscriber: RelaySubscriber) {
+        let subscriber = RelaySubscriber {
+            address: dual_stack::canonical(subscriber.address),
+            ..subscriber
+        };
+        let mut listeners = self.channels.entry(channel_id).or_default();
+        listeners.retain(|existing| existing.user_id != subscriber.user_id);
+        listeners.push(subscriber);
+    }
+
+    /// Points every channel `user_id` listens to at `address`, e.g. once
+    /// their voice socket is known.
+    pub fn set_voice_address(&self, user_id: UserId, address: SocketAddr) {
+        let address = dual_stack::canonical(address);
+        for mut listeners in self.channels.iter_mut() {
+            for listener in listeners.iter_mut() {
+                if listener.user_id == user_id {
+                    listener.address = address;
+                }
+            }
+        }
+    }
+
+    /// Replaces everyone listening to `channel_id`, e.g. with the routes a
+    /// relay gets from its coordinator; none removes the channel.
+    pub fn replace_listeners(&self, channel_id: ChannelId, subscribers: Vec<RelaySubscriber>) {
+        if subscribers.is_empty() {
+            self.channels.remove(&channel_id);
+            return;
+        }
+        let subscribers = subscribers
+            .into_iter()
+            .map(|subscriber| RelaySubscriber {
+                address: dual_stack::canonical(subscriber.address),
+                ..subscriber
+            })
+            .collect();
+        self.channels.insert(channel_id, subscribers);
+    }
+
+    /// Returns whether `user_id` was listening to `channel_id`.
+    pub fn remove_listener(&self, channel_id: ChannelId, user_id: UserId) -> bool {
+        let removed = match self.channels.get_mut(&channel_id) {
+            Some(mut listeners) => {
+                let before = listeners.len();
+                listeners.retain(|existing| existing.user_id != user_id);
+                listeners.len() != before
+            }
+            None => false,
+        };
+        self.channels
+            .remove_if(&channel_id, |_, listeners| listeners.is_empty());
+        removed
+    }
+
+    /// Sends the voice `user_id` hears to the returned receiver as
+    /// [`ControlMessage::VoiceTunnel`] messages, for their connection to
+    /// write out, instead of to their voice address. Replaces any earlier
+    /// tunnel; dropping the receiver closes it.
+    pub fn open_tunnel(&self, user_id: UserId) -> mpsc::UnboundedReceiver<ControlMessage> {
+        let (tunnel, receiver) = mpsc::unbounded_channel();
+        self.tunnels.insert(user_id, tunnel);
+        receiver
+    }
+
+    /// Sends the voice `user_id` hears to their voice address again.
+    pub fn close_tunnel(&self, user_id: UserId) {
+        self.tunnels.remove(&user_id);
+    }
+
+    /// Signs the headers forwarded to `user_id` with `key`, the session key
+    /// their client got when authenticating.
+    pub fn set_session_key(&self, user_id: UserId, key: HmacKey) {
+        self.session_keys.insert(user_id, key);
+    }
+
+    /// Sends `packet` through `user_id`'s tunnel, e.g. as forwarded to them
+    /// by a relay. Returns whether they have one open.
+    pub fn deliver_tunneled(&self, user_id: UserId, packet: Vec<u8>) -> bool {
+        self.tunnels
+            .get(&user_id)
+            .is_some_and(|tunnel| tunnel.send(ControlMessage::VoiceTunnel { packet }).is_ok())
+    }
+
+    /// What relays need to forward `user_id`'s voice like this registry
+    /// does, for users with a session key. Whether they may transmit is
+    /// up to the caller.
+    pub fn relay_sender(&self, user_id: UserId, may_transmit: bool) -> Option<RelaySender> {
+        let udp_key = self.session_keys.get(&user_id)?.clone();
+        Some(RelaySender {
+            udp_key,
+            may_transmit,
+            listen_only: self.listen_only.contains(&user_id),
+            transmit_mode: self
+                .transmit_modes
+                .get(&user_id)
+                .map(|mode| *mode)
+                .unwrap_or_default(),
+            transmit_priority: self
+                .transmit_priorities
+                .get(&user_id)
+                .map(|priority| *priority),
+            transmit_targets: self
+                .transmit_targets
+                .get(&user_id)
+                .map(|targets| targets.clone())
+                .unwrap_or_default(),
+            tunneled: self.tunnels.contains_key(&user_id),
+        })
+    }
+
+    /// Takes on a user's state from a [`RelaySender`], on a relay. Their
+    /// tunnel is left to the relay.
+    pub fn apply_relay_sender(&self, user_id: UserId, sender: &RelaySender) {
+        self.set_session_key(user_id, sender.udp_key.clone());
+        self.set_transmit_mode(user_id, sender.transmit_mode);
+        self.set_transmit_targets(user_id, sender.transmit_targets.clone());
+        match sender.transmit_priority {
+            Some(priority) => self.set_transmit_priority(user_id, priority),
+            None => {
+                self.transmit_priorities.remove(&user_id);
+            }
+        }
+        if sender.listen_only {
+            self.listen_only.insert(user_id);
+        } else {
+            self.listen_only.remove(&user_id);
+        }
+    }
+
+    /// Stops all fan-out to a disconnected user.
+    pub fn remove_user(&self, user_id: UserId) {
+        self.tunnels.remove(&user_id);
+        self.session_keys.remove(&user_id);
+        self.transmit_modes.remove(&user_id);
+        self.listen_only.remove(&user_id);
+        self.transmit_targets.remove(&user_id);
+        self.transmit_priorities.remove(&user_id);
+        self.floors.retain(|_, floor| floor.user_id != user_id);
+        self.forwarded.remove(&user_id);
+        self.positions.remove(&user_id);
+        if let Some((_, transmission)) = self.transmissions.remove(&user_id) {
+            self.publish_stopped(user_id, &transmission);
+        }
+        self.channels
+            .iter_mut()
+            .for_each(|mut listeners| listeners.retain(|existing| existing.user_id != user_id));
+        self.channels.retain(|_, listeners| !listeners.is_empty());
+    }
+
+    /// Everyone listening to `channel_id`, e.g. to publish as relay routes.
+    pub fn listeners(&self, channel_id: ChannelId) -> Vec<RelaySubscriber> {
+        self.channels
+            .get(&channel_id)
+            .map(|listeners| listeners.clone())
+            .unwrap_or_default()
+    }
+
+    /// Everyone listening to each channel, e.g. to sync relay routes.
+    pub fn all_listeners(&self) -> HashMap<ChannelId, Vec<RelaySubscriber>> {
+        self.channels
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Listeners of `channel_id` who only spectate there, e.g. for the
+    /// `spectators` of [`ControlMessage::ChannelJoined`].
+    pub fn spectators(&self, channel_id: ChannelId) -> Vec<UserId> {
+        if !self
+            .audio_policies
+            .get(&channel_id)
+            .is_some_and(|policy| policy.spectator_mode)
+        {
+            return Vec::new();
+        }
+        self.listeners(channel_id)
+            .into_iter()
+            .map(|listener| listener.user_id)
+            .filter(|user_id| self.listen_only.contains(user_id))
+            .collect()
+    }
+
+    /// The lowest and highest bitrate packets from `user_id` on
+    /// `channel_id` may have, per the audio policies of every channel they
+    /// go out on.
+    pub fn bitrate_range(&self, user_id: UserId, channel_id: ChannelId) -> (u32, u32) {
+        self.transmit_channels(user_id, channel_id)
+            .into_iter()
+            .map(|channel_id| {
+                self.audio_policies
+                    .get(&channel_id)
+                    .map_or_else(AudioPolicy::default, |policy| *policy)
+            })
+            .fold((0, u32::MAX), |(min, max), policy| {
+                (min.max(policy.min_bitrate), max.min(policy.max_bitrate))
+            })
+    }
+
+    /// Handles a subscription or transmit target request from `session`,
+    /// whose voice socket is reachable at `voice_address`, and returns the
+    /// acknowledgement to send.
+    pub fn apply(
+        &self,
+        session: &mut Session,
+        voice_address: SocketAddr,
+        message: &ControlMessage,
+    ) -> Result<ControlMessage, FleetNetError> {
+        session.ensure_interactive()?;
+        let user_id = session.user.id;
+        if session.permission.has(Permissions::SPEAK) {
+            self.listen_only.remove(&user_id);
+        } else {
+            self.listen_only.insert(user_id);
+        }
+        match message {
+            ControlMessage::SubscribeChannel { channel_id } => {
+                if !session.permission.has(Permissions::LISTEN) {
+                    return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                        "Missing permission to listen to channels",
+                    )));
+                }
+                let max = self.limits.max_subscriptions as usize;
+                if !session.subscribed_channels.contains(channel_id)
+                    && session.subscribed_channels.len() >= max
+                {
+                    return Err(FleetNetError::invalid_field(
+                        "channel_id",
+                        Constraint::Invalid(Cow::Owned(format!("too_many_subscriptions({max})"))),
+                    ));
+                }
+                session.subscribed_channels.insert(*channel_id);
+                self.add_listener(
+                    *channel_id,
+                    RelaySubscriber {
+                        user_id,
+                        address: voice_address,
+                    },
+                );
+            }
+            ControlMessage::UnsubscribeChannel { channel_id } => {
+                session.subscribed_channels.remove(channel_id);
+                // The current channel stays audible after dropping its radio.
+                if session.current_channel != Some(*channel_id) {
+                    self.remove_listener(*channel_id, user_id);
+                }
+            }
+            ControlMessage::SetTransmitTargets { channel_ids } => {
+                let mut targets = channel_ids.clone();
+                targets.sort_unstable();
+                targets.dedup();
+                if targets.len() > 1 && !session.permission.has(Permissions::SIMULCAST) {
+                    return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                        "Missing permission to transmit on several channels at once",
+                    )));
+                }
+                let listening = |channel_id: &ChannelId| {
+                    session.subscribed_channels.contains(channel_id)
+                        || session.current_channel == Some(*channel_id)
+                };
+                if !targets.iter().all(listening) {
+                    return Err(FleetNetError::invalid_field(
+                        "channel_ids",
+                        Constraint::Invalid(Cow::Borrowed("not_listening")),
+                    ));
+                }
+                self.set_transmit_targets(user_id, targets.clone());
+                session.update_activity();
+                return Ok(ControlMessage::TransmitTargetsChanged {
+                    channel_ids: targets,
+                });
+            }
+            _ => {
+                return Err(FleetNetError::invalid_field(
+                    "type",
+                    Constraint::Invalid(Cow::Borrowed(
+                        "expected subscribe_channel, unsubscribe_channel or set_transmit_targets",
+                    )),
+                ))
+            }
+        }
+
+        session.update_activity();
+        let subscribed_channels = session.sorted_subscriptions();
+        let retransmit_channels = subscribed_channels
+            .iter()
+            .copied()
+            .filter(|&channel_id| self.allows_retransmit(channel_id))
+            .collect();
+        Ok(ControlMessage::SubscriptionsChanged {
+            subscribed_channels,
+            retransmit_channels,
+        })
+    }
+
+    /// Whether the audio policy of `channel_id` allows retransmission.
+    fn allows_retransmit(&self, channel_id: ChannelId) -> bool {
+        self.audio_policies
+            .get(&channel_id)
+            .is_some_and(|policy| policy.retransmit)
+    }
+
+    /// Determines where a voice packet should be forwarded.
+    ///
+    /// Only listeners of a channel may transmit on it, and only from the
+    /// address they registered, so spoofed user ids are dropped.
+    pub fn forward_targets(&self, header: &PacketHeader, source: SocketAddr) -> Vec<SocketAddr> {
+        self.forward_subscribers(header, source)
+            .into_iter()
+            .map(|s| s.address)
+            .collect()
+    }
+
+    /// The listeners behind [`Self::forward_targets`], one per address.
+    pub fn forward_subscribers(
+        &self,
+        header: &PacketHeader,
+        source: SocketAddr,
+    ) -> Vec<RelaySubscriber> {
+        self.routes(header, source)
+            .into_iter()
+            .flat_map(|route| route.clear)
+            .collect()
+    }
+
+    /// Who hears the packet with `header` from `source` on each channel it
+    /// goes out on, reaching every address once. Listeners only hear it
+    /// scrambled if no channel they monitor carries it in the clear.
+    fn routes(&self, header: &PacketHeader, source: SocketAddr) -> Vec<Route> {
+        if !self.sender_known(header.user_id, header.channel_id, source) {
+            return Vec::new();
+        }
+        let mut routes: Vec<Route> = self
+            .transmit_channels(header.user_id, header.channel_id)
+            .into_iter()
+            .filter(|&channel_id| self.sender_known(header.user_id, channel_id, source))
+            .map(|channel_id| Route {
+                header: PacketHeader {
+                    channel_id,
+                    ..*header
+                },
+                clear: Vec::new(),
+                scrambled: Vec::new(),
+            })
+            .collect();
+
+        let mut reached: Vec<SocketAddr> = Vec::new();
+        for route in &mut routes {
+            let mut channel_ids = vec![route.header.channel_id];
+            if let Some(linked) = self.radio_nets.get(&route.header.channel_id) {
+                channel_ids.extend(linked.iter());
+            }
+            route.clear = self.listeners_of(&channel_ids, header.user_id);
+            route.clear.retain(|s| !reached.contains(&s.address));
+            reached.extend(route.clear.iter().map(|s| s.address));
+        }
+        for route in &mut routes {
+            if let Some(keyless) = self.keyless_nets.get(&route.header.channel_id) {
+                route.scrambled = self.listeners_of(&keyless, header.user_id);
+            }
+            // Also monitoring a channel with the key, they hear the clear audio
+            route.scrambled.retain(|s| !reached.contains(&s.address));
+            reached.extend(route.scrambled.iter().map(|s| s.address));
+        }
+        routes
+    }
+
+    /// Channels `user_id` transmits on when sending on `channel_id`: that
+    /// one, then their other transmit targets if it is one of them.
+    fn transmit_channels(&self, user_id: UserId, channel_id: ChannelId) -> Vec<ChannelId> {
+        let mut channel_ids = vec![channel_id];
+        if let Some(targets) = self.transmit_targets.get(&user_id) {
+            if targets.contains(&channel_id) {
+                channel_ids.extend(targets.iter().filter(|&&id| id != channel_id));
+            }
+        }
+        channel_ids
+    }
+
+    /// Whether `user_id` listens to `channel_id` from `source`.
+    fn sender_known(&self, user_id: UserId, channel_id: ChannelId, source: SocketAddr) -> bool {
+        let source = dual_stack::canonical(source);
+        self.channels.get(&channel_id).is_some_and(|listeners| {
+            listeners
+                .iter()
+                .any(|s| s.user_id == user_id && s.address == source)
+        })
+    }
+
+    /// Listeners of `channel_ids` other than `sender`, one per address.
+    fn listeners_of(&self, channel_ids: &[ChannelId], sender: UserId) -> Vec<RelaySubscriber> {
+        let mut targets: Vec<RelaySubscriber> = Vec::new();
+        for channel_id in channel_ids {
+            if let Some(listeners) = self.channels.get(channel_id) {
+                targets.extend(listeners.iter().filter(|s| s.user_id != sender).copied());
+            }
+        }
+        // Monitoring several channels of a net still delivers once
+        targets.sort_by_key(|s| s.address);
+        targets.dedup_by_key(|s| s.address);
+        targets
+    }
+
+    /// Checks a voice packet received at `now` against the audio policy of
+    /// every channel it goes out on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the broken rule if the sender only spectates,
+    /// their transmit mode is refused, the transmission ran too long or its
+    /// average bitrate is out of range.
+    pub fn check_policy(&self, header: &PacketHeader, now: Instant) -> Result<(), FleetNetError> {
+        let channel_ids = self.transmit_channels(header.user_id, header.channel_id);
+        if self.listen_only.contains(&header.user_id)
+            && channel_ids.iter().any(|channel_id| {
+                self.audio_policies
+                    .get(channel_id)
+                    .is_some_and(|policy| policy.spectator_mode)
+            })
+        {
+            return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                "Spectators can only listen in this channel",
+            )));
+        }
+
+        let fresh = Transmission {
+            channel_id: header.channel_id,
+            started: now,
+            last_packet: now,
+            audio_bytes: 0,
+            audio_ms: 0,
+        };
+        let mut started = false;
+        let mut transmission = self.transmissions.entry(header.user_id).or_insert_with(|| {
+            started = true;
+            fresh
+        });
+        if transmission.channel_id != header.channel_id
+            || now.duration_since(transmission.last_packet) > TRANSMISSION_GAP
+        {
+            self.publish_stopped(header.user_id, &transmission);
+            *transmission = fresh;
+            started = true;
+        }
+        if started {
+            self.publish(TransmissionEvent::Started {
+                user_id: header.user_id,
+                channel_id: header.channel_id,
+            });
+        }
+        transmission.last_packet = now;
+        // Pauses in discontinuous transmission hold the transmission open
+        // but would drag the measured bitrate down
+        if !header.dtx {
+            transmission.audio_bytes += u64::from(header.audio_length);
+            transmission.audio_ms += u64::from(header.duration_ms());
+        }
+
+        let mode = self
+            .transmit_modes
+            .get(&header.user_id)
+            .map(|mode| *mode)
+            .unwrap_or_default();
+        for channel_id in channel_ids {
+            let Some(policy) = self.audio_policies.get(&channel_id) else {
+                continue;
+            };
+            if !policy.allows_mode(mode) {
+                return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                    "Voice activation is not allowed in this channel, use push-to-talk",
+                )));
+            }
+            if let Some(max_secs) = policy.max_transmit_secs {
+                if now.duration_since(transmission.started) > Duration::from_secs(max_secs.into()) {
+                    return Err(FleetNetError::PermissionError(Cow::Owned(format!(
+                        "Transmissions in this channel are limited to {max_secs} seconds"
+                    ))));
+                }
+            }
+            if transmission.audio_ms >= BITRATE_WINDOW_MS {
+                let bitrate = transmission.audio_bytes * 8 * 1000 / transmission.audio_ms;
+                let bitrate = u32::try_from(bitrate).unwrap_or(u32::MAX);
+                if !policy.allows_bitrate(bitrate) {
+                    return Err(FleetNetError::AudioError(Cow::Owned(format!(
+                        "Bitrate of {bitrate} bps is outside the {}-{} bps this channel allows",
+                        policy.min_bitrate, policy.max_bitrate
+                    ))));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Channels with preemption the packet with `header` goes out on.
+    fn contested_channels(&self, header: &PacketHeader) -> Vec<ChannelId> {
+        let mut channel_ids = self.transmit_channels(header.user_id, header.channel_id);
+        channel_ids.retain(|channel_id| {
+            self.audio_policies
+                .get(channel_id)
+                .is_some_and(|policy| policy.preemption)
+        });
+        channel_ids
+    }
+
+    fn transmit_priority(&self, user_id: UserId) -> u32 {
+        self.transmit_priorities
+            .get(&user_id)
+            .map_or(u32::MAX, |priority| *priority)
+    }
+
+    /// Whether the sender of `header` is cut off at `now` by someone on the
+    /// air who ranks at least as high, publishing it the first time during
+    /// that sender's hold.
+    fn stepped_on(&self, header: &PacketHeader, now: Instant) -> bool {
+        let priority = self.transmit_priority(header.user_id);
+        for channel_id in self.contested_channels(header) {
+            let Some(mut floor) = self.floors.get_mut(&channel_id) else {
+                continue;
+            };
+            if floor.user_id == header.user_id
+                || now.duration_since(floor.last_packet) > TRANSMISSION_GAP
+                || floor.priority > priority
+            {
+                continue;
+            }
+            if !floor.stepped_on.contains(&header.user_id) {
+                floor.stepped_on.push(header.user_id);
+                self.publish(TransmissionEvent::SteppedOn {
+                    user_id: header.user_id,
+                    channel_id,
+                    by: floor.user_id,
+                });
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Puts the sender of `header` on the air at `now`, stepping on whoever
+    /// they took a channel over from.
+    fn take_the_air(&self, header: &PacketHeader, now: Instant) {
+        let priority = self.transmit_priority(header.user_id);
+        for channel_id in self.contested_channels(header) {
+            let mut floor = self.floors.entry(channel_id).or_insert_with(|| Floor {
+                user_id: header.user_id,
+                priority,
+                last_packet: now,
+                stepped_on: Vec::new(),
+            });
+            if floor.user_id != header.user_id {
+                let preempted = now.duration_since(floor.last_packet) <= TRANSMISSION_GAP;
+                if preempted {
+                    self.publish(TransmissionEvent::SteppedOn {
+                        user_id: floor.user_id,
+                        channel_id,
+                        by: header.user_id,
+                    });
+                }
+                *floor = Floor {
+                    user_id: header.user_id,
+                    priority,
+                    last_packet: now,
+                    stepped_on: if preempted {
+                        vec![floor.user_id]
+                    } else {
+                        Vec::new()
+                    },
+                };
+            }
+            floor.last_packet = now;
+        }
+    }
+
+    /// Ends every transmission silent for longer than [`TRANSMISSION_GAP`]
+    /// at `now`, publishing that it stopped, and forgets packets kept for
+    /// retransmission from speakers silent for longer than
+    /// [`RETRANSMIT_WINDOW`].
+    pub fn end_idle_transmissions(&self, now: Instant) {
+        self.transmissions.retain(|&user_id, transmission| {
+            let idle = now.duration_since(transmission.last_packet) > TRANSMISSION_GAP;
+            if idle {
+                self.publish_stopped(user_id, transmission);
+            }
+            !idle
+        });
+        self.floors
+            .retain(|_, floor| now.duration_since(floor.last_packet) <= TRANSMISSION_GAP);
+        self.forwarded.retain(|_, kept| {
+            kept.back()
+                .is_some_and(|packet| now.duration_since(packet.received) <= RETRANSMIT_WINDOW)
+        });
+    }
+
+    /// Ends idle transmissions every half [`TRANSMISSION_GAP`].
+    pub fn spawn_transmission_sweep(self: &Arc<Self>) -> JoinHandle<()> {
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TRANSMISSION_GAP / 2);
+            loop {
+                interval.tick().await;
+                registry.end_idle_transmissions(registry.clock.now());
+            }
+        })
+    }
+
+    fn publish(&self, event: TransmissionEvent) {
+        // Nobody listening is fine; events are informational.
+        if let Some(update) = event.speaking_update() {
+            let _ = self.speaking.send(update);
+        }
+        let _ = self.transmission_events.send(event);
+    }
+
+    fn publish_stopped(&self, user_id: UserId, transmission: &Transmission) {
+        self.publish(TransmissionEvent::Stopped {
+            user_id,
+            channel_id: transmission.channel_id,
+            duration: transmission
+                .last_packet
+                .duration_since(transmission.started),
+        });
+    }
+
+    /// Forwards one datagram, returning the number of listeners it was sent
+    /// to; none if the sender is stepped on, the realism scenario drops it
+    /// or it carries to nobody.
+    ///
+    /// # Errors
+    ///
+    /// Returns the policy error for packets the channel refuses, see
+    /// [`Self::check_policy`].
+    pub async fn forward_packet(
+        &self,
+        socket: &UdpSocket,
+        datagram: &[u8],
+        source: SocketAddr,
+    ) -> Result<usize, FleetNetError> {
+        let mut buf = datagram;
+        let wire = PacketHeader::read_from(&mut buf)?;
+        let Some(body) = wire.unpadded(buf).filter(|body| wire.fits_body(body.len())) else {
+            return Ok(0);
+        };
+        // The sender's padding is dropped, and ours added on the way out
+        let datagram = &datagram[..PacketHeader::SIZE + body.len()];
+        let header = PacketHeader {
+            padded: false,
+            ..wire
+        };
+
+        let routes = self.routes(&header, source);
+        if routes
+            .iter()
+            .all(|route| route.clear.is_empty() && route.scrambled.is_empty())
+        {
+            return Ok(0);
+        }
+        let now = self.clock.now();
+        if self.stepped_on(&header, now) {
+            return Ok(0);
+        }
+        self.check_policy(&header, now)?;
+        self.take_the_air(&header, now);
+
+        let position = body
+            .get(usize::from(header.audio_length)..)
+            .filter(|rest| rest.len() == SpeakerPosition::SIZE)
+            .and_then(|mut rest| SpeakerPosition::read_from(&mut rest).ok());
+        if let Some(position) = position {
+            self.positions.insert(header.user_id, (position, now));
+        }
+        let mut deliveries: Vec<(RelaySubscriber, PacketHeader, bool)> = Vec::new();
+        for route in &routes {
+            let signal_loss = match self
+                .realism
+                .reception(&route.header, position.as_ref(), now)
+            {
+                Reception::Clear => 0,
+                Reception::Weakened(loss) => loss,
+                Reception::Blocked => continue,
+            };
+            let targets = route
+                .clear
+                .iter()
+                .map(|target| (target, false))
+                .chain(route.scrambled.iter().map(|target| (target, true)));
+            deliveries.extend(targets.filter_map(|(target, scrambled)| {
+                let modelled = self.modelled_signal(&route.header, target.user_id, now);
+                if modelled.is_none() && signal_loss == 0 {
+                    return Some((*target, route.header, scrambled));
+                }
+                let signal_strength = modelled
+                    .unwrap_or(header.signal_strength)
+                    .saturating_sub(signal_loss);
+                let header = PacketHeader {
+                    signal_strength,
+                    ..route.header
+                };
+                (signal_strength > 0).then_some((*target, header, scrambled))
+            }));
+        }
+
+        let local = socket.local_addr()?;
+        let mut rewritten = Vec::new();
+        let mut noise = Vec::with_capacity(PacketHeader::SIZE);
+        for &(target, delivered, scrambled) in &deliveries {
+            let key = self
+                .session_keys
+                .get(&target.user_id)
+                .map(|key| key.clone());
+            let packet = if scrambled {
+                noise.clear();
+                signed(delivered.scrambled(), key.as_ref(), &[]).write_to(&mut noise);
+                self.pad(&mut noise);
+                &noise
+            } else if key.is_none() && delivered == wire && self.padding.is_none() {
+                datagram
+            } else {
+                if rewritten.is_empty() {
+                    rewritten.extend_from_slice(datagram);
+                    self.pad(&mut rewritten);
+                }
+                signed(self.outgoing(delivered), key.as_ref(), body)
+                    .write_to(&mut &mut rewritten[..PacketHeader::SIZE]);
+                &rewritten
+            };
+            self.deliver(socket, local, &target, packet).await?;
+        }
+        self.keep_for_retransmit(&header, datagram, &deliveries, now);
+
+        self.packets_forwarded
+            .fetch_add(deliveries.len() as u64, Ordering::Relaxed);
+        Ok(deliveries.len())
+    }
+
+    /// Forwards the packet of a [`ControlMessage::VoiceTunnel`] from
+    /// `session` as if it arrived on `socket` from `voice_address`, the
+    /// address their subscriptions were made with; see
+    /// [`Self::forward_packet`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is malformed, or the policy error
+    /// for packets the channel refuses.
+    pub async fn forward_tunneled(
+        &self,
+        socket: &UdpSocket,
+        session: &Session,
+        voice_address: SocketAddr,
+        message: &ControlMessage,
+    ) -> Result<usize, FleetNetError> {
+        session.ensure_can_transmit()?;
+        let ControlMessage::VoiceTunnel { packet } = message else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected voice_tunnel")),
+            ));
+        };
+        message.validate(&self.limits)?;
+        self.forward_packet(socket, packet, voice_address).await
+    }
+
+    /// Sends `packet` to `target`, through their tunnel if they have one
+    /// open.
+    async fn deliver(
+        &self,
+        socket: &UdpSocket,
+        local: SocketAddr,
+        target: &RelaySubscriber,
+        packet: &[u8],
+    ) -> Result<(), FleetNetError> {
+        if let Some(tunnel) = self.tunnels.get(&target.user_id) {
+            let message = ControlMessage::VoiceTunnel {
+                packet: packet.to_vec(),
+            };
+            if tunnel.send(message).is_ok() {
+                return Ok(());
+            }
+            drop(tunnel);
+            self.tunnels.remove(&target.user_id);
+        }
+        socket
+            .send_to(packet, dual_stack::reachable_from(local, target.address))
+            .await?;
+        Ok(())
+    }
+
+    /// Pads an outgoing datagram, if padding is configured.
+    fn pad(&self, datagram: &mut Vec<u8>) {
+        if let Some(block) = self.padding {
+            pad_datagram(datagram, block);
+        }
+    }
+
+    /// `header` as it goes out, flagged as padded if padding is configured.
+    fn outgoing(&self, header: PacketHeader) -> PacketHeader {
+        PacketHeader {
+            padded: self.padding.is_some(),
+            ..header
+        }
+    }
+
+    /// Keeps a forwarded packet for those of its listeners who got it in
+    /// the clear on a channel that allows retransmission.
+    fn keep_for_retransmit(
+        &self,
+        header: &PacketHeader,
+        datagram: &[u8],
+        deliveries: &[(RelaySubscriber, PacketHeader, bool)],
+        now: Instant,
+    ) {
+        let listeners: Vec<(RelaySubscriber, PacketHeader)> = deliveries
+            .iter()
+            .filter(|(_, delivered, scrambled)| {
+                !scrambled && self.allows_retransmit(delivered.channel_id)
+            })
+            .map(|&(target, delivered, _)| (target, delivered))
+            .collect();
+        if listeners.is_empty() {
+            return;
+        }
+        let mut kept = self.forwarded.entry(header.user_id).or_default();
+        while kept
+            .front()
+            .is_some_and(|packet| now.duration_since(packet.received) > RETRANSMIT_WINDOW)
+        {
+            kept.pop_front();
+        }
+        kept.push_back(Forwarded {
+            sequence: header.sequence,
+            frames: header.frames,
+            received: now,
+            datagram: datagram.to_vec(),
+            listeners,
+            retransmitted: Vec::new(),
+        });
+    }
+
+    /// Sends the packets `session` asked for with
+    /// [`ControlMessage::RetransmitRequest`] again, returning how many were
+    /// sent. Packets they never got, already got again or that are older
+    /// than [`RETRANSMIT_WINDOW`] are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request is malformed or the channel does not
+    /// allow retransmission.
+    pub async fn retransmit(
+        &self,
+        socket: &UdpSocket,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<usize, FleetNetError> {
+        session.ensure_interactive()?;
+        let ControlMessage::RetransmitRequest {
+            user_id: speaker,
+            channel_id,
+            sequences,
+        } = message
+        else {
+            return Err(FleetNetError::invalid_field(
+                "type",
+                Constraint::Invalid(Cow::Borrowed("expected retransmit_request")),
+            ));
+        };
+        message.validate(&self.limits)?;
+        if !self.allows_retransmit(*channel_id) {
+            return Err(FleetNetError::invalid_field(
+                "channel_id",
+                Constraint::Invalid(Cow::Borrowed("retransmit_disabled")),
+            ));
+        }
+        session.update_activity();
+
+        let listener = session.user.id;
+        let now = self.clock.now();
+        let packets: Vec<(RelaySubscriber, Vec<u8>)> = match self.forwarded.get_mut(speaker) {
+            Some(mut kept) => kept
+                .iter_mut()
+                .filter(|packet| {
+                    sequences.iter().any(|&sequence| {
+                        sequence.wrapping_sub(packet.sequence) < u16::from(packet.frames.max(1))
+                    }) && now.duration_since(packet.received) <= RETRANSMIT_WINDOW
+                        && !packet.retransmitted.contains(&listener)
+                })
+                .filter_map(|packet| {
+                    let &(target, delivered) =
+                        packet.listeners.iter().find(|(target, header)| {
+                            target.user_id == listener && header.channel_id == *channel_id
+                        })?;
+                    packet.retransmitted.push(listener);
+                    let mut datagram = packet.datagram.clone();
+                    self.pad(&mut datagram);
+                    let key = self.session_keys.get(&listener).map(|key| key.clone());
+                    let body = &packet.datagram[PacketHeader::SIZE..];
+                    signed(self.outgoing(delivered), key.as_ref(), body)
+                        .write_to(&mut &mut datagram[..PacketHeader::SIZE]);
+                    Some((target, datagram))
+                })
+                .collect(),
+            None => return Ok(0),
+        };
+        let local = socket.local_addr()?;
+        for (target, datagram) in &packets {
+            self.deliver(socket, local, target, datagram).await?;
+        }
+        Ok(packets.len())
+    }
+
+    /// The signal strength `receiver` gets the radio packet with `header`
+    /// at, when propagation is configured and both ends have a position.
+    fn modelled_signal(&self, header: &PacketHeader, receiver: UserId, now: Instant) -> Option<u8> {
+        let propagation = self.propagation.as_ref()?;
+        let tuning = self.radio_tunings.get(&header.channel_id)?;
+        let from = self.position(header.user_id, now)?;
+        let to = self.position(receiver, now)?;
+        Some(propagation.signal_strength(&tuning, &from, &to))
+    }
+
+    /// Where `user_id` last was, unless that is older than [`POSITION_TTL`].
+    fn position(&self, user_id: UserId, now: Instant) -> Option<SpeakerPosition> {
+        self.positions
+            .get(&user_id)
+            .filter(|entry| now.saturating_duration_since(entry.1) <= POSITION_TTL)
+            .map(|entry| entry.0)
+    }
+}
+
+/// `header` signed over `body` with a listener's session `key`, or unsigned
+/// for listeners without one.
+fn signed(mut header: PacketHeader, key: Option<&HmacKey>, body: &[u8]) -> PacketHeader {
+    match key {
+        Some(key) => header.sign_body(key, body),
+        None => header.hmac_prefix = 0,
+    }
+    header
+}
+
+impl Default for SubscriptionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realism::{FrequencyBand, Interference, JamEffect, Jammer, Scenario, Zone};
+    use fleet_net_common::channel::{
+        AudioPolicy, Channel, ChannelType, Modulation, RadioChannelConfig,
+    };
+    use fleet_net_common::clock::ManualClock;
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::user::User;
+    use fleet_net_protocol::packet::{AudioPacket, SpeakerPosition};
+    use std::collections::{HashMap, HashSet};
+    use std::time::{Duration, Instant};
+
+    fn user(id: u16) -> UserId {
+        UserId::new(id).unwrap()
+    }
+
+    fn channel(id: u16) -> ChannelId {
+        ChannelId::new(id).unwrap()
+    }
+
+    fn session(user_id: UserId, permissions: Permissions) -> Session {
+        Session {
+            id: format!("session_{user_id}"),
+            user: User::new(user_id),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: HashSet::new(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    fn header(channel_id: ChannelId, user_id: UserId, audio_length: u16) -> PacketHeader {
+        PacketHeader {
+            channel_id,
+            user_id,
+            sequence: 1,
+            timestamp: 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            frames: 1,
+            padded: false,
+            audio_length,
+            hmac_prefix: 0,
+        }
+    }
+
+    fn subscribe(
+        registry: &SubscriptionRegistry,
+        session: &mut Session,
+        address: SocketAddr,
+        channel_id: ChannelId,
+    ) -> Result<ControlMessage, FleetNetError> {
+        registry.apply(
+            session,
+            address,
+            &ControlMessage::SubscribeChannel { channel_id },
+        )
+    }
+
+    #[test]
+    fn test_subscribe_to_several_channels() {
+        let registry = SubscriptionRegistry::new();
+        let mut alice = session(user(1), Permissions::LISTEN);
+        let address: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+
+        subscribe(&registry, &mut alice, address, channel(7)).unwrap();
+        let ack = subscribe(&registry, &mut alice, address, channel(3)).unwrap();
+        match ack {
+            ControlMessage::SubscriptionsChanged {
+                subscribed_channels,
+                retransmit_channels,
+            } => {
+                assert_eq!(subscribed_channels, vec![channel(3), channel(7)]);
+                assert!(retransmit_channels.is_empty());
+            }
+            other => panic!("Expected SubscriptionsChanged, got {other:?}"),
+        }
+        assert_eq!(registry.listeners(channel(3)).len(), 1);
+        assert_eq!(registry.listeners(channel(7)).len(), 1);
+
+        registry
+            .apply(
+                &mut alice,
+                address,
+                &ControlMessage::UnsubscribeChannel {
+                    channel_id: channel(7),
+                },
+            )
+            .unwrap();
+        assert!(!alice.hears(channel(7)));
+        assert!(registry.listeners(channel(7)).is_empty());
+        assert_eq!(alice.sorted_subscriptions(), vec![channel(3)]);
+    }
+
+    #[test]
+    fn test_subscribe_requires_listen_permission() {
+        let registry = SubscriptionRegistry::new();
+        let mut muted = session(user(1), Permissions::CONNECT);
+
+        let result = subscribe(
+            &registry,
+            &mut muted,
+            "127.0.0.1:5001".parse().unwrap(),
+            channel(7),
+        );
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+        assert!(muted.subscribed_channels.is_empty());
+        assert!(registry.listeners(channel(7)).is_empty());
+
+        // Unauthenticated sessions never become listeners, so cannot transmit
+        let mut pending = session(user(2), Permissions::LISTEN);
+        pending.state = SessionState::Authenticating;
+        let result = subscribe(
+            &registry,
+            &mut pending,
+            "127.0.0.1:5002".parse().unwrap(),
+            channel(7),
+        );
+        assert!(matches!(result, Err(FleetNetError::SessionStateError(_))));
+        assert!(registry.listeners(channel(7)).is_empty());
+    }
+
+    #[test]
+    fn test_subscriptions_are_capped_by_limits() {
+        let registry = SubscriptionRegistry::new().with_limits(ServerLimits {
+            max_subscriptions: 2,
+            ..ServerLimits::default()
+        });
+        let mut alice = session(user(1), Permissions::LISTEN);
+        let address: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+
+        subscribe(&registry, &mut alice, address, channel(1)).unwrap();
+        subscribe(&registry, &mut alice, address, channel(2)).unwrap();
+        let result = subscribe(&registry, &mut alice, address, channel(3));
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+        assert!(registry.listeners(channel(3)).is_empty());
+
+        // Subscribing again to a monitored channel is not a new subscription
+        subscribe(&registry, &mut alice, address, channel(2)).unwrap();
+    }
+
+    #[test]
+    fn test_unsubscribe_keeps_current_channel_audible() {
+        let registry = SubscriptionRegistry::new();
+        let mut alice = session(user(1), Permissions::LISTEN);
+        let address: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        alice.current_channel = Some(channel(7));
+
+        subscribe(&registry, &mut alice, address, channel(7)).unwrap();
+        registry
+            .apply(
+                &mut alice,
+                address,
+                &ControlMessage::UnsubscribeChannel {
+                    channel_id: channel(7),
+                },
+            )
+            .unwrap();
+        assert_eq!(registry.listeners(channel(7)).len(), 1);
+    }
+
+    #[test]
+    fn test_fan_out_reaches_every_subscriber() {
+        let registry = SubscriptionRegistry::new();
+        let alice: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let bob: SocketAddr = "127.0.0.1:5002".parse().unwrap();
+        let carol: SocketAddr = "127.0.0.1:5003".parse().unwrap();
+
+        subscribe(
+            &registry,
+            &mut session(user(1), Permissions::LISTEN),
+            alice,
+            channel(1),
+        )
+        .unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(2), Permissions::LISTEN),
+            bob,
+            channel(1),
+        )
+        .unwrap();
+        // Carol monitors channel 1 alongside another radio
+        let mut carol_session = session(user(3), Permissions::LISTEN);
+        subscribe(&registry, &mut carol_session, carol, channel(2)).unwrap();
+        subscribe(&registry, &mut carol_session, carol, channel(1)).unwrap();
+
+        assert_eq!(
+            registry.forward_targets(&header(channel(1), user(1), 0), alice),
+            vec![bob, carol]
+        );
+        assert!(registry
+            .forward_targets(&header(channel(2), user(3), 0), carol)
+            .is_empty());
+
+        // Spoofed sender and non-listeners are dropped
+        assert!(registry
+            .forward_targets(&header(channel(1), user(1), 0), bob)
+            .is_empty());
+        assert!(registry
+            .forward_targets(&header(channel(2), user(1), 0), alice)
+            .is_empty());
+
+        registry.remove_user(user(3));
+        assert_eq!(
+            registry.forward_targets(&header(channel(1), user(1), 0), alice),
+            vec![bob]
+        );
+        assert!(registry.listeners(channel(2)).is_empty());
+    }
+
+    #[test]
+    fn test_packets_are_checked_against_the_audio_policy() {
+        let mut tree = ChannelTree::new();
+        let policy = AudioPolicy {
+            max_bitrate: 32_000,
+            vad_forbidden: true,
+            max_transmit_secs: Some(2),
+            ..AudioPolicy::default()
+        };
+        tree.insert(Channel {
+            id: channel(1),
+            name: "Guard".to_string(),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: policy,
+        })
+        .unwrap();
+        let registry = SubscriptionRegistry::new();
+        registry.update_channels(&tree);
+        let start = Instant::now();
+        // 80 bytes every 20 ms is 32 kbps
+        let packet = header(channel(1), user(1), 80);
+
+        for frame in 0..100 {
+            let now = start + Duration::from_millis(frame * 20);
+            registry.check_policy(&packet, now).unwrap();
+        }
+        let err = registry
+            .check_policy(&packet, start + Duration::from_millis(2_100))
+            .unwrap_err();
+        assert!(err.to_string().contains("limited to 2 seconds"), "{err}");
+
+        // A pause starts a new transmission, judged on its own bitrate
+        let loud = header(channel(1), user(1), 160);
+        let later = start + Duration::from_secs(5);
+        let err = (0..50)
+            .map(|frame| registry.check_policy(&loud, later + Duration::from_millis(frame * 20)))
+            .find_map(Result::err)
+            .unwrap();
+        assert!(matches!(err, FleetNetError::AudioError(_)), "{err}");
+
+        // A batch counts the time of every frame it carries
+        let batch = PacketHeader {
+            frames: 4,
+            ..header(channel(1), user(1), 320)
+        };
+        let batched = later + Duration::from_secs(2);
+        for packet in 0..14 {
+            registry
+                .check_policy(&batch, batched + Duration::from_millis(packet * 80))
+                .unwrap();
+        }
+
+        registry.set_transmit_mode(user(1), TransmitMode::VoiceActivity);
+        let err = registry
+            .check_policy(&packet, later + Duration::from_secs(5))
+            .unwrap_err();
+        assert!(matches!(err, FleetNetError::PermissionError(_)));
+
+        // Channels without a policy accept anything
+        registry
+            .check_policy(
+                &header(channel(2), user(1), 1_000),
+                later + Duration::from_secs(10),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_dtx_pauses_hold_the_transmission_without_counting_as_audio() {
+        let mut tree = ChannelTree::new();
+        tree.insert(Channel {
+            id: channel(1),
+            name: "Guard".to_string(),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy {
+                min_bitrate: 16_000,
+                ..AudioPolicy::default()
+            },
+        })
+        .unwrap();
+        let registry = SubscriptionRegistry::new();
+        registry.update_channels(&tree);
+        let mut events = registry.subscribe_transmissions();
+        let start = Instant::now();
+        let voice = header(channel(1), user(1), 80);
+        let pause = PacketHeader {
+            dtx: true,
+            ..header(channel(1), user(1), 0)
+        };
+
+        // A second of speech at 32 kbps, two of silence, then speech again
+        for frame in 0..200 {
+            let packet = if (50..150).contains(&frame) {
+                &pause
+            } else {
+                &voice
+            };
+            registry
+                .check_policy(packet, start + Duration::from_millis(frame * 20))
+                .unwrap();
+        }
+        assert!(matches!(
+            events.try_recv(),
+            Ok(TransmissionEvent::Started { .. })
+        ));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_spectators_only_listen() {
+        let mut tree = ChannelTree::new();
+        for (id, spectator_mode) in [(1, true), (2, false)] {
+            tree.insert(Channel {
+                id: channel(id),
+                name: format!("Stage {id}"),
+                description: None,
+                channel_type: ChannelType::Voice,
+                role_permissions: HashMap::new(),
+                position: id.into(),
+                parent_id: None,
+                topic: None,
+                icon: None,
+                metadata: HashMap::new(),
+                radio: None,
+                audio_policy: AudioPolicy {
+                    spectator_mode,
+                    ..AudioPolicy::default()
+                },
+            })
+            .unwrap();
+        }
+        let registry = SubscriptionRegistry::new();
+        registry.update_channels(&tree);
+        let address: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let mut zeus = session(user(1), Permissions::LISTEN);
+        let mut pilot = session(user(2), Permissions::LISTEN | Permissions::SPEAK);
+        for channel_id in [channel(1), channel(2)] {
+            subscribe(&registry, &mut zeus, address, channel_id).unwrap();
+            subscribe(&registry, &mut pilot, address, channel_id).unwrap();
+        }
+
+        assert_eq!(registry.spectators(channel(1)), vec![user(1)]);
+        assert!(registry.spectators(channel(2)).is_empty());
+        let now = Instant::now();
+        let err = registry
+            .check_policy(&header(channel(1), user(1), 80), now)
+            .unwrap_err();
+        assert!(matches!(err, FleetNetError::PermissionError(_)), "{err}");
+        registry
+            .check_policy(&header(channel(1), user(2), 80), now)
+            .unwrap();
+        // Without spectator mode, the permission to speak isn't enforced
+        registry
+            .check_policy(&header(channel(2), user(1), 80), now)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transmissions_are_published_as_they_start_and_stop() {
+        let registry = SubscriptionRegistry::new();
+        let mut events = registry.subscribe_transmissions();
+        let start = Instant::now();
+        let packet = header(channel(1), user(1), 80);
+
+        for frame in 0..10 {
+            registry
+                .check_policy(&packet, start + Duration::from_millis(frame * 20))
+                .unwrap();
+        }
+        let started = TransmissionEvent::Started {
+            user_id: user(1),
+            channel_id: channel(1),
+        };
+        assert_eq!(events.try_recv().unwrap(), started);
+        assert!(events.try_recv().is_err());
+
+        // Still within the gap after the last packet at 180 ms
+        registry.end_idle_transmissions(start + Duration::from_millis(600));
+        assert!(events.try_recv().is_err());
+        registry.end_idle_transmissions(start + Duration::from_millis(700));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            TransmissionEvent::Stopped {
+                user_id: user(1),
+                channel_id: channel(1),
+                duration: Duration::from_millis(180),
+            }
+        );
+
+        // Switching channels ends one transmission and starts another
+        let later = start + Duration::from_secs(1);
+        registry.check_policy(&packet, later).unwrap();
+        registry
+            .check_policy(&header(channel(2), user(1), 80), later)
+            .unwrap();
+        let received: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], started);
+        assert!(
+            matches!(received[1], TransmissionEvent::Stopped { channel_id, .. } if channel_id == channel(1))
+        );
+        assert!(
+            matches!(received[2], TransmissionEvent::Started { channel_id, .. } if channel_id == channel(2))
+        );
+
+        registry.remove_user(user(1));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(TransmissionEvent::Stopped { .. })
+        ));
+    }
+
+    #[test]
+    fn test_speaking_is_broadcast_after_a_hangover() {
+        let registry = SubscriptionRegistry::new();
+        let mut updates = registry.subscribe_speaking();
+        let start = Instant::now();
+        let packet = header(channel(3), user(2), 80);
+        let speaking = |updates: &mut broadcast::Receiver<ControlMessage>| {
+            std::iter::from_fn(|| updates.try_recv().ok())
+                .map(|update| match update {
+                    ControlMessage::UserSpeaking {
+                        user_id,
+                        channel_id,
+                        speaking,
+                    } => (user_id, channel_id, speaking),
+                    other => panic!("unexpected update {other:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // A pause between words shorter than the hangover keeps talking
+        for at_ms in [0, 20, 40, 400, 420] {
+            registry
+                .check_policy(&packet, start + Duration::from_millis(at_ms))
+                .unwrap();
+        }
+        registry.end_idle_transmissions(start + Duration::from_millis(800));
+        assert_eq!(speaking(&mut updates), [(user(2), channel(3), true)]);
+
+        registry.end_idle_transmissions(start + Duration::from_millis(1_000));
+        assert_eq!(speaking(&mut updates), [(user(2), channel(3), false)]);
+    }
+
+    #[test]
+    fn test_radio_nets_link_channels_on_the_same_frequency() {
+        let radio = |id: u16, frequency_hz: u64| Channel {
+            id: channel(id),
+            name: format!("Radio {id}"),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: Some(RadioChannelConfig {
+                frequency_hz,
+                modulation: Modulation::Am,
+                max_range_m: None,
+                crypto_key_id: None,
+            }),
+            audio_policy: AudioPolicy::default(),
+        };
+        let tree = ChannelTree::from_channels([
+            radio(1, 251_000_000),
+            radio(2, 251_000_000),
+            radio(3, 243_000_000),
+        ])
+        .unwrap();
+        let registry = SubscriptionRegistry::new();
+        registry.update_channels(&tree);
+
+        let alice: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let bob: SocketAddr = "127.0.0.1:5002".parse().unwrap();
+        let carol: SocketAddr = "127.0.0.1:5003".parse().unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(1), Permissions::LISTEN),
+            alice,
+            channel(1),
+        )
+        .unwrap();
+        // Bob monitors both channels of the net but hears each packet once
+        let mut bob_session = session(user(2), Permissions::LISTEN);
+        subscribe(&registry, &mut bob_session, bob, channel(1)).unwrap();
+        subscribe(&registry, &mut bob_session, bob, channel(2)).unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(3), Permissions::LISTEN),
+            carol,
+            channel(3),
+        )
+        .unwrap();
+
+        assert_eq!(
+            registry.forward_targets(&header(channel(1), user(1), 0), alice),
+            vec![bob]
+        );
+        assert_eq!(
+            registry.forward_targets(&header(channel(2), user(2), 0), bob),
+            vec![alice]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_packet_sends_to_listeners() {
+        let registry = SubscriptionRegistry::new();
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+
+        subscribe(
+            &registry,
+            &mut session(user(1), Permissions::LISTEN),
+            sender,
+            channel(4),
+        )
+        .unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(2), Permissions::LISTEN),
+            listener.local_addr().unwrap(),
+            channel(4),
+        )
+        .unwrap();
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+        assert_eq!(registry.packets_forwarded(), 1);
+
+        let mut buf = [0u8; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], datagram.as_slice());
+
+        // Truncated payloads are not forwarded
+        let sent = registry
+            .forward_packet(&server, &datagram[..datagram.len() - 1], sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 0);
+
+        // A speaker position after the payload travels with it
+        SpeakerPosition::default().write_to(&mut datagram);
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], datagram.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_socket_forwards_between_address_families() {
+        // Skip on hosts without IPv6
+        let Ok(server) = dual_stack::bind_udp("[::]:0").await else {
+            return;
+        };
+        let registry = SubscriptionRegistry::new();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+
+        // Registered as plain IPv4, but heard from as v4-mapped
+        subscribe(
+            &registry,
+            &mut session(user(1), Permissions::LISTEN),
+            sender,
+            channel(4),
+        )
+        .unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(2), Permissions::LISTEN),
+            listener.local_addr().unwrap(),
+            channel(4),
+        )
+        .unwrap();
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:5001".parse().unwrap();
+        let sent = registry
+            .forward_packet(&server, &datagram, mapped)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+
+        let mut buf = [0u8; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], datagram.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_tunneled_voice_is_forwarded_both_ways() {
+        let registry = SubscriptionRegistry::new();
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        // Tunneling clients subscribe with their control address
+        let alice_address: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let carol_address: SocketAddr = "127.0.0.1:5003".parse().unwrap();
+
+        let mut alice = session(user(1), Permissions::LISTEN);
+        subscribe(&registry, &mut alice, alice_address, channel(4)).unwrap();
+        for (user_id, address) in [
+            (user(2), listener.local_addr().unwrap()),
+            (user(3), carol_address),
+        ] {
+            subscribe(
+                &registry,
+                &mut session(user_id, Permissions::LISTEN),
+                address,
+                channel(4),
+            )
+            .unwrap();
+        }
+        let mut carol_tunnel = registry.open_tunnel(user(3));
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+        let message = ControlMessage::VoiceTunnel {
+            packet: datagram.clone(),
+        };
+        let sent = registry
+            .forward_tunneled(&server, &alice, alice_address, &message)
+            .await
+            .unwrap();
+        assert_eq!(sent, 2);
+
+        let mut buf = [0u8; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], datagram.as_slice());
+        match carol_tunnel.try_recv().unwrap() {
+            ControlMessage::VoiceTunnel { packet } => assert_eq!(packet, datagram),
+            other => panic!("Expected voice_tunnel, got {other:?}"),
+        }
+
+        // Empty packets are refused, and a closed tunnel falls back to UDP
+        assert!(registry
+            .forward_tunneled(
+                &server,
+                &alice,
+                alice_address,
+                &ControlMessage::VoiceTunnel { packet: Vec::new() }
+            )
+            .await
+            .is_err());
+        drop(carol_tunnel);
+        registry
+            .forward_tunneled(&server, &alice, alice_address, &message)
+            .await
+            .unwrap();
+        assert!(!registry.tunnels.contains_key(&user(3)));
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_packets_are_padded_per_server_policy() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let listen = |registry: &SubscriptionRegistry| {
+            for (user_id, address) in [(user(1), sender), (user(2), listener.local_addr().unwrap())]
+            {
+                subscribe(
+                    registry,
+                    &mut session(user_id, Permissions::LISTEN),
+                    address,
+                    channel(4),
+                )
+                .unwrap();
+            }
+        };
+        let receive = || async {
+            let mut buf = [0u8; 512];
+            let (len, _) =
+                tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            buf[..len].to_vec()
+        };
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+
+        // Unpadded packets go out padded
+        let padding = SubscriptionRegistry::new().with_padding(128);
+        listen(&padding);
+        padding
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        let received = receive().await;
+        assert_eq!(received.len(), 128);
+        assert_eq!(
+            AudioPacket::from_bytes(&received).unwrap(),
+            AudioPacket::from_bytes(&datagram).unwrap()
+        );
+
+        // Without padding configured, the sender's is stripped
+        let plain = SubscriptionRegistry::new();
+        listen(&plain);
+        let mut padded = datagram.clone();
+        pad_datagram(&mut padded, 64);
+        plain
+            .forward_packet(&server, &padded, sender)
+            .await
+            .unwrap();
+        assert_eq!(receive().await, datagram);
+
+        // Padding longer than the packet is dropped
+        let len = padded.len();
+        padded[len - 2..].copy_from_slice(&1000u16.to_be_bytes());
+        assert_eq!(
+            plain
+                .forward_packet(&server, &padded, sender)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    fn set_targets(
+        registry: &SubscriptionRegistry,
+        session: &mut Session,
+        address: SocketAddr,
+        channel_ids: &[u16],
+    ) -> Result<ControlMessage, FleetNetError> {
+        registry.apply(
+            session,
+            address,
+            &ControlMessage::SetTransmitTargets {
+                channel_ids: channel_ids.iter().map(|&id| channel(id)).collect(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_transmit_targets_need_simulcast_and_subscriptions() {
+        let registry = SubscriptionRegistry::new();
+        let address: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let mut alice = session(user(1), Permissions::LISTEN);
+        subscribe(&registry, &mut alice, address, channel(3)).unwrap();
+        subscribe(&registry, &mut alice, address, channel(5)).unwrap();
+
+        let result = set_targets(&registry, &mut alice, address, &[5, 3]);
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+        // One target is just transmitting as usual
+        set_targets(&registry, &mut alice, address, &[3]).unwrap();
+
+        let mut bob = session(user(2), Permissions::LISTEN | Permissions::SIMULCAST);
+        subscribe(&registry, &mut bob, address, channel(3)).unwrap();
+        subscribe(&registry, &mut bob, address, channel(5)).unwrap();
+        let ack = set_targets(&registry, &mut bob, address, &[5, 3, 5]).unwrap();
+        match ack {
+            ControlMessage::TransmitTargetsChanged { channel_ids } => {
+                assert_eq!(channel_ids, vec![channel(3), channel(5)])
+            }
+            other => panic!("Expected TransmitTargetsChanged, got {other:?}"),
+        }
+        // Only channels they hear can be targets
+        let result = set_targets(&registry, &mut bob, address, &[3, 9]);
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_simulcast_goes_out_on_every_target() {
+        let registry = SubscriptionRegistry::new();
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let mut alice = session(user(1), Permissions::LISTEN | Permissions::SIMULCAST);
+        subscribe(&registry, &mut alice, sender, channel(3)).unwrap();
+        subscribe(&registry, &mut alice, sender, channel(5)).unwrap();
+
+        // Bob monitors one net, Carol monitors both
+        let bob = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let carol = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut bob_session = session(user(2), Permissions::LISTEN);
+        let mut carol_session = session(user(3), Permissions::LISTEN);
+        subscribe(
+            &registry,
+            &mut bob_session,
+            bob.local_addr().unwrap(),
+            channel(5),
+        )
+        .unwrap();
+        for channel_id in [channel(3), channel(5)] {
+            subscribe(
+                &registry,
+                &mut carol_session,
+                carol.local_addr().unwrap(),
+                channel_id,
+            )
+            .unwrap();
+        }
+
+        let mut datagram = Vec::new();
+        header(channel(3), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+        async fn receive(socket: &UdpSocket) -> Vec<u8> {
+            let mut buf = [0u8; 64];
+            let (len, _) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            buf[..len].to_vec()
+        }
+
+        // Without targets only channel 3 hears it
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+        assert_eq!(receive(&carol).await, datagram);
+
+        set_targets(&registry, &mut alice, sender, &[3, 5]).unwrap();
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 2);
+        // Carol hears it once, on the channel it was sent on
+        assert_eq!(receive(&carol).await, datagram);
+        let received = receive(&bob).await;
+        let received_header = PacketHeader::read_from(&mut received.as_slice()).unwrap();
+        assert_eq!(received_header, header(channel(5), user(1), 3));
+        assert_eq!(&received[PacketHeader::SIZE..], &[1, 2, 3]);
+
+        // Cleared targets send on the packet's channel only
+        set_targets(&registry, &mut alice, sender, &[]).unwrap();
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+    }
+
+    #[tokio::test]
+    async fn test_forward_packet_follows_the_realism_scenario() {
+        let tree = ChannelTree::from_channels([Channel {
+            id: channel(4),
+            name: "Strike".to_string(),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: Some(RadioChannelConfig {
+                frequency_hz: 251_000_000,
+                modulation: Modulation::Am,
+                max_range_m: None,
+                crypto_key_id: None,
+            }),
+            audio_policy: AudioPolicy::default(),
+        }])
+        .unwrap();
+        let realism = Arc::new(RadioRealism::new());
+        let registry = SubscriptionRegistry::new().with_realism(realism.clone());
+        registry.update_channels(&tree);
+        let band = FrequencyBand {
+            from_hz: 225_000_000,
+            to_hz: 400_000_000,
+        };
+        realism
+            .set_scenario(Scenario {
+                hop_sets: Vec::new(),
+                jammers: vec![Jammer {
+                    name: "Site".to_string(),
+                    band,
+                    zone: Some(Zone {
+                        x: 0.0,
+                        y: 0.0,
+                        radius_m: 500.0,
+                    }),
+                    effect: JamEffect::Block,
+                }],
+                interference: vec![Interference {
+                    name: "Static".to_string(),
+                    band,
+                    signal_loss: 55,
+                }],
+            })
+            .unwrap();
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5003".parse().unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(1), Permissions::LISTEN),
+            sender,
+            channel(4),
+        )
+        .unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(2), Permissions::LISTEN),
+            listener.local_addr().unwrap(),
+            channel(4),
+        )
+        .unwrap();
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+        let mut buf = [0u8; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let received = PacketHeader::read_from(&mut &buf[..len]).unwrap();
+        assert_eq!(received.signal_strength, 200);
+        assert_eq!(&buf[PacketHeader::SIZE..len], &[1, 2, 3]);
+
+        // Inside the jammer's zone nothing gets through
+        SpeakerPosition {
+            x: 100.0,
+            ..SpeakerPosition::default()
+        }
+        .write_to(&mut datagram);
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 0);
+    }
+
+    #[tokio::test]
+    async fn test_forward_packet_computes_the_signal_per_listener() {
+        let tree = ChannelTree::from_channels([Channel {
+            id: channel(4),
+            name: "Tower".to_string(),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: Some(RadioChannelConfig {
+                frequency_hz: 305_000_000,
+                modulation: Modulation::Am,
+                max_range_m: None,
+                crypto_key_id: None,
+            }),
+            audio_policy: AudioPolicy::default(),
+        }])
+        .unwrap();
+        let clock = ManualClock::new();
+        let propagation = PropagationConfig::default();
+        let registry = SubscriptionRegistry::new()
+            .with_clock(clock.shared())
+            .with_propagation(propagation.clone());
+        registry.update_channels(&tree);
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let near = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let far = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let quiet = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5004".parse().unwrap();
+        let listeners = [
+            (1, sender),
+            (2, near.local_addr().unwrap()),
+            (3, far.local_addr().unwrap()),
+            (4, quiet.local_addr().unwrap()),
+        ];
+        for (id, address) in listeners {
+            subscribe(
+                &registry,
+                &mut session(user(id), Permissions::LISTEN),
+                address,
+                channel(4),
+            )
+            .unwrap();
+        }
+        // Positions as the listeners' own packets would have left them
+        let at = |x| SpeakerPosition {
+            x,
+            ..SpeakerPosition::default()
+        };
+        registry
+            .positions
+            .insert(user(2), (at(5_000.0), clock.now()));
+        registry
+            .positions
+            .insert(user(3), (at(20_000.0), clock.now()));
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+        let mut positioned = datagram.clone();
+        at(0.0).write_to(&mut positioned);
+        let sent = registry
+            .forward_packet(&server, &positioned, sender)
+            .await
+            .unwrap();
+        // Beyond the radio horizon the listener hears nothing
+        assert_eq!(sent, 2);
+
+        let mut buf = [0u8; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), near.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let received = PacketHeader::read_from(&mut &buf[..len]).unwrap();
+        let expected = propagation.signal_strength(
+            tree.get(channel(4)).unwrap().radio.as_ref().unwrap(),
+            &at(0.0),
+            &at(5_000.0),
+        );
+        assert!(expected > 0 && expected < 255, "{expected}");
+        assert_eq!(received.signal_strength, expected);
+        assert_eq!(
+            &buf[PacketHeader::SIZE..len],
+            &positioned[PacketHeader::SIZE..]
+        );
+
+        // Without a position the sender's own signal strength is kept
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), quiet.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], positioned.as_slice());
+
+        // Positions go stale, after which everyone hears the sender's
+        clock.advance(POSITION_TTL * 2);
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 3);
+    }
+
+    #[tokio::test]
+    async fn test_listeners_without_the_key_get_encrypted_traffic_scrambled() {
+        let radio = |id: u16, crypto_key_id: Option<&str>| Channel {
+            id: channel(id),
+            name: format!("Strike {id}"),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: Some(RadioChannelConfig {
+                frequency_hz: 251_000_000,
+                modulation: Modulation::Am,
+                max_range_m: None,
+                crypto_key_id: crypto_key_id.map(str::to_string),
+            }),
+            audio_policy: AudioPolicy::default(),
+        };
+        let tree = ChannelTree::from_channels([
+            radio(4, Some("kilo-1")),
+            radio(5, Some("kilo-1")),
+            radio(6, None),
+        ])
+        .unwrap();
+        let registry = SubscriptionRegistry::new();
+        registry.update_channels(&tree);
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let keyed = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let keyless = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5005".parse().unwrap();
+        let listeners = [
+            (1, sender, 4),
+            (2, keyed.local_addr().unwrap(), 5),
+            (3, keyless.local_addr().unwrap(), 6),
+        ];
+        for (id, address, channel_id) in listeners {
+            subscribe(
+                &registry,
+                &mut session(user(id), Permissions::LISTEN),
+                address,
+                channel(channel_id),
+            )
+            .unwrap();
+        }
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 3).write_to(&mut datagram);
+        datagram.extend_from_slice(&[1, 2, 3]);
+        let sent = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+        assert_eq!(sent, 2);
+
+        let mut buf = [0u8; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), keyed.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], datagram.as_slice());
+
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), keyless.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let scrambled = AudioPacket::from_bytes(&buf[..len]).unwrap();
+        assert!(scrambled.is_scrambled());
+        assert_eq!(scrambled.header.sequence, 1);
+
+        // Clear traffic on the frequency does not reach the encrypted net
+        let mut clear = Vec::new();
+        header(channel(6), user(3), 3).write_to(&mut clear);
+        clear.extend_from_slice(&[1, 2, 3]);
+        let sent = registry
+            .forward_packet(&server, &clear, keyless.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(sent, 0);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_transmissions_are_timed_by_the_clock() {
+        let mut tree = ChannelTree::new();
+        tree.insert(Channel {
+            id: channel(4),
+            name: "Command".to_string(),
+            description: None,
+            channel_type: ChannelType::Voice,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy {
+                max_transmit_secs: Some(1),
+                ..AudioPolicy::default()
+            },
+        })
+        .unwrap();
+        let clock = ManualClock::new();
+        let registry = SubscriptionRegistry::new().with_clock(clock.shared());
+        registry.update_channels(&tree);
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5002".parse().unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(1), Permissions::LISTEN),
+            sender,
+            channel(4),
+        )
+        .unwrap();
+        subscribe(
+            &registry,
+            &mut session(user(2), Permissions::LISTEN),
+            listener.local_addr().unwrap(),
+            channel(4),
+        )
+        .unwrap();
+
+        let mut datagram = Vec::new();
+        header(channel(4), user(1), 80).write_to(&mut datagram);
+        datagram.extend_from_slice(&[0; 80]);
+        for _ in 0..50 {
+            let sent = registry
+                .forward_packet(&server, &datagram, sender)
+                .await
+                .unwrap();
+            assert_eq!(sent, 1);
+            clock.advance(Duration::from_millis(20));
+        }
+        clock.advance(Duration::from_millis(20));
+        let err = registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("limited to 1 seconds"), "{err}");
+
+        // Without a real second passing, a pause is enough to start over
+        clock.advance(TRANSMISSION_GAP * 2);
+        registry
+            .forward_packet(&server, &datagram, sender)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_higher_priority_preempts_on_contested_channels() {
+        let mut tree = ChannelTree::new();
+        tree.insert(Channel {
+            id: channel(1),
+            name: "Battalion".to_string(),
+            description: None,
+            channel_type: ChannelType::Radio,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy {
+                preemption: true,
+                ..AudioPolicy::default()
+            },
+        })
+        .unwrap();
+        let clock = ManualClock::new();
+        let registry = SubscriptionRegistry::new().with_clock(clock.shared());
+        registry.update_channels(&tree);
+        let mut events = registry.subscribe_transmissions();
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sockets = Vec::new();
+        for id in 1..=4 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            subscribe(
+                &registry,
+                &mut session(user(id), Permissions::LISTEN | Permissions::SPEAK),
+                socket.local_addr().unwrap(),
+                channel(1),
+            )
+            .unwrap();
+            sockets.push(socket);
+        }
+        // Two riflemen and their commander; user 4 only listens
+        registry.set_transmit_priority(user(1), 10);
+        registry.set_transmit_priority(user(2), 10);
+        registry.set_transmit_priority(user(3), 1);
+
+        let send = |id: u16| {
+            let mut datagram = Vec::new();
+            header(channel(1), user(id), 2).write_to(&mut datagram);
+            datagram.extend_from_slice(&[0; 2]);
+            let source = sockets[usize::from(id) - 1].local_addr().unwrap();
+            let registry = &registry;
+            let server = &server;
+            async move {
+                registry
+                    .forward_packet(server, &datagram, source)
+                    .await
+                    .unwrap()
+            }
+        };
+        let mut stepped_on = || {
+            std::iter::from_fn(|| events.try_recv().ok())
+                .filter_map(|event| event.stepped_on_notice())
+                .map(|(user_id, notice)| match notice {
+                    ControlMessage::TransmissionSteppedOn { channel_id, by } => {
+                        (user_id, channel_id, by)
+                    }
+                    other => panic!("unexpected notice {other:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(send(1).await, 3);
+        // An equal rank doesn't get through, and is told once
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(send(2).await, 0);
+        assert_eq!(send(2).await, 0);
+        assert_eq!(stepped_on(), [(user(2), channel(1), user(1))]);
+
+        // The commander takes over, cutting the rifleman off
+        assert_eq!(send(3).await, 3);
+        assert_eq!(stepped_on(), [(user(1), channel(1), user(3))]);
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(send(1).await, 0);
+        assert_eq!(send(3).await, 3);
+        assert!(stepped_on().is_empty());
+
+        // Once the commander unkeys, the channel is free again
+        clock.advance(TRANSMISSION_GAP * 2);
+        assert_eq!(send(1).await, 3);
+        assert!(stepped_on().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lost_packets_are_retransmitted_once() {
+        let mut tree = ChannelTree::new();
+        for (id, retransmit) in [(1, true), (2, false)] {
+            tree.insert(Channel {
+                id: channel(id),
+                name: format!("Net {id}"),
+                description: None,
+                channel_type: ChannelType::Radio,
+                role_permissions: HashMap::new(),
+                position: 0,
+                parent_id: None,
+                topic: None,
+                icon: None,
+                metadata: HashMap::new(),
+                radio: None,
+                audio_policy: AudioPolicy {
+                    retransmit,
+                    ..AudioPolicy::default()
+                },
+            })
+            .unwrap();
+        }
+        let clock = ManualClock::new();
+        let registry = SubscriptionRegistry::new().with_clock(clock.shared());
+        registry.update_channels(&tree);
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender: SocketAddr = "127.0.0.1:5003".parse().unwrap();
+        let mut speaker = session(user(1), Permissions::LISTEN);
+        let mut pilot = session(user(2), Permissions::LISTEN);
+        subscribe(&registry, &mut speaker, sender, channel(1)).unwrap();
+        subscribe(
+            &registry,
+            &mut pilot,
+            listener.local_addr().unwrap(),
+            channel(2),
+        )
+        .unwrap();
+        let ack = subscribe(
+            &registry,
+            &mut pilot,
+            listener.local_addr().unwrap(),
+            channel(1),
+        );
+        match ack.unwrap() {
+            ControlMessage::SubscriptionsChanged {
+                retransmit_channels,
+                ..
+            } => assert_eq!(retransmit_channels, vec![channel(1)]),
+            other => panic!("Expected SubscriptionsChanged, got {other:?}"),
+        }
+
+        let mut sent = Vec::new();
+        for sequence in 0..3 {
+            let mut datagram = Vec::new();
+            PacketHeader {
+                sequence,
+                ..header(channel(1), user(1), 1)
+            }
+            .write_to(&mut datagram);
+            datagram.push(sequence as u8);
+            registry
+                .forward_packet(&server, &datagram, sender)
+                .await
+                .unwrap();
+            sent.push(datagram);
+        }
+        let listener = &listener;
+        let receive = || async move {
+            let mut buf = [0u8; 64];
+            let (len, _) =
+                tokio::time::timeout(Duration::from_secs(2), listener.recv_from(&mut buf))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            buf[..len].to_vec()
+        };
+        for _ in 0..3 {
+            receive().await;
+        }
+
+        let request = |sequences: Vec<u16>| ControlMessage::RetransmitRequest {
+            user_id: user(1),
+            channel_id: channel(1),
+            sequences,
+        };
+        let resent = registry
+            .retransmit(&server, &mut pilot, &request(vec![1, 7]))
+            .await
+            .unwrap();
+        assert_eq!(resent, 1);
+        assert_eq!(receive().await, sent[1]);
+
+        // Once only, and only to those who got it
+        let resent = registry
+            .retransmit(&server, &mut pilot, &request(vec![1]))
+            .await
+            .unwrap();
+        assert_eq!(resent, 0);
+        let resent = registry
+            .retransmit(&server, &mut speaker, &request(vec![2]))
+            .await
+            .unwrap();
+        assert_eq!(resent, 0);
+
+        // Not on channels without retransmission, nor for nothing
+        let refused = ControlMessage::RetransmitRequest {
+            user_id: user(1),
+            channel_id: channel(2),
+            sequences: vec![2],
+        };
+        assert!(registry
+            .retransmit(&server, &mut pilot, &refused)
+            .await
+            .is_err());
+        assert!(registry
+            .retransmit(&server, &mut pilot, &request(Vec::new()))
+            .await
+            .is_err());
+
+        // Too late to be of use
+        clock.advance(RETRANSMIT_WINDOW * 2);
+        let resent = registry
+            .retransmit(&server, &mut pilot, &request(vec![2]))
+            .await
+            .unwrap();
+        assert_eq!(resent, 0);
+        registry.end_idle_transmissions(clock.now());
+        assert!(registry.forwarded.is_empty());
+    }
+}
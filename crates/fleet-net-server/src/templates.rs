@@ -0,0 +1,469 @@
+//! Exporting and importing the channel tree and roles.
+//!
+//! A [`ServerTemplate`] captures the channels, their role permission
+//! overrides and the role definitions, so a community can keep its setup
+//! under version control or copy it to another server. Users holding both
+//! [`Permissions::MANAGE_CHANNELS`] and [`Permissions::MANAGE_ROLES`] export
+//! with [`ControlMessage::ExportTemplate`]; importing with
+//! [`ControlMessage::ImportTemplate`] grants roles arbitrary permissions, so
+//! it takes [`Permissions::ADMINISTRATOR`].
+//!
+//! The admin API built by [`admin_router`] does the same in JSON or YAML.
+//! Every import is validated as a whole before anything changes, and a dry
+//! run stops there, returning what the import would change.
+
+use crate::channels::ChannelRegistry;
+use crate::roles::RoleRegistry;
+use crate::store::ChannelStore;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use fleet_net_common::error::FleetNetError;
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::permission::Permissions;
+use fleet_net_common::session::Session;
+use fleet_net_common::template::{ServerTemplate, TemplateChanges};
+use fleet_net_common::validation::{Constraint, Validate};
+use fleet_net_protocol::message::ControlMessage;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+pub struct TemplateManager {
+    channels: Arc<ChannelRegistry>,
+    roles: Arc<RoleRegistry>,
+    limits: ServerLimits,
+}
+
+impl TemplateManager {
+    pub fn new(channels: Arc<ChannelRegistry>, roles: Arc<RoleRegistry>) -> Self {
+        Self {
+            channels,
+            roles,
+            limits: ServerLimits::default(),
+        }
+    }
+
+    /// Checks imports against `limits` instead of the defaults.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The current channel tree and roles, channels sorted by id.
+    pub async fn export(&self) -> Result<ServerTemplate, FleetNetError> {
+        let mut channels = self.channels.store().list().await?;
+        channels.sort_by_key(|channel| channel.id);
+        Ok(ServerTemplate {
+            channels,
+            roles: self.roles.roles(),
+        })
+    }
+
+    /// Replaces the channel tree and roles with `template`, or with
+    /// `dry_run` only works out the changes, and returns them.
+    ///
+    /// Removed roles are taken away from everyone holding them.
+    ///
+    /// # Errors
+    ///
+    /// Returns a validation error listing every problem with `template`,
+    /// in which case nothing changes.
+    pub async fn import(
+        &self,
+        template: &ServerTemplate,
+        dry_run: bool,
+    ) -> Result<TemplateChanges, FleetNetError> {
+        template.validate(&self.limits)?;
+        let changes = template.diff(&self.export().await?);
+        if dry_run {
+            return Ok(changes);
+        }
+
+        let store = self.channels.store();
+        for channel in &template.channels {
+            if changes.channels_added.contains(&channel.id)
+                || changes.channels_updated.contains(&channel.id)
+            {
+                store.save(channel.clone()).await?;
+            }
+        }
+        for &channel_id in &changes.channels_removed {
+            store.remove(channel_id).await?;
+        }
+        for role in &template.roles {
+            self.roles.upsert_role(role.clone());
+        }
+        for role_id in &changes.roles_removed {
+            self.roles.remove_role(role_id)?;
+        }
+        Ok(changes)
+    }
+
+    /// Handles a template export or import from `session` and returns the
+    /// reply.
+    pub async fn apply(
+        &self,
+        session: &mut Session,
+        message: &ControlMessage,
+    ) -> Result<ControlMessage, FleetNetError> {
+        session.ensure_interactive()?;
+        let reply = match message {
+            ControlMessage::ExportTemplate => {
+                if !session
+                    .permission
+                    .has_all(&[Permissions::MANAGE_CHANNELS, Permissions::MANAGE_ROLES])
+                {
+                    return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                        "Missing permission to manage channels and roles",
+                    )));
+                }
+                ControlMessage::TemplateExported {
+                    template: self.export().await?,
+                }
+            }
+            ControlMessage::ImportTemplate { template, dry_run } => {
+                if !session.permission.has(Permissions::ADMINISTRATOR) {
+                    return Err(FleetNetError::PermissionError(Cow::Borrowed(
+                        "Missing permission to import templates",
+                    )));
+                }
+                ControlMessage::TemplateImported {
+                    changes: self.import(template, *dry_run).await?,
+                    dry_run: *dry_run,
+                }
+            }
+            _ => {
+                return Err(FleetNetError::invalid_field(
+                    "type",
+                    Constraint::Invalid(Cow::Borrowed(
+                        "expected export_template or import_template",
+                    )),
+                ))
+            }
+        };
+        session.update_activity();
+        Ok(reply)
+    }
+}
+
+/// Serialization of templates in the admin API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl TemplateFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Yaml => "application/yaml",
+        }
+    }
+
+    pub fn render<T: Serialize>(self, value: &T) -> Result<String, FleetNetError> {
+        match self {
+            Self::Json => Ok(serde_json::to_string_pretty(value)?),
+            Self::Yaml => serde_yaml::to_string(value)
+                .map_err(|e| FleetNetError::JsonError(Cow::Owned(e.to_string()))),
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, text: &str) -> Result<T, FleetNetError> {
+        match self {
+            Self::Json => Ok(serde_json::from_str(text)?),
+            Self::Yaml => serde_yaml::from_str(text)
+                .map_err(|e| FleetNetError::JsonError(Cow::Owned(e.to_string()))),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AdminState {
+    templates: Arc<TemplateManager>,
+    token: Arc<str>,
+}
+
+#[derive(Deserialize)]
+struct TemplateQuery {
+    #[serde(default)]
+    format: TemplateFormat,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// What an import through the admin API changed, or would change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub changes: TemplateChanges,
+    pub dry_run: bool,
+}
+
+/// Builds the template admin API:
+///
+/// - `GET /admin/template[?format=yaml]`
+/// - `PUT /admin/template[?format=yaml][&dry_run=true]` with the template
+///
+/// Replies use the requested format, JSON by default. Templates that do not
+/// validate are rejected with `422 Unprocessable Entity` and the field
+/// errors as JSON. Every request must carry `Authorization: Bearer
+/// <admin_token>`.
+pub fn admin_router(templates: Arc<TemplateManager>, admin_token: &str) -> Router {
+    Router::new()
+        .route("/admin/template", get(export_template).put(import_template))
+        .with_state(AdminState {
+            templates,
+            token: admin_token.into(),
+        })
+}
+
+async fn export_template(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(query): Query<TemplateQuery>,
+) -> Result<Response, Response> {
+    crate::reports::authorize(&state.token, &headers).map_err(IntoResponse::into_response)?;
+    let template = state.templates.export().await.map_err(error_response)?;
+    Ok(render(query.format, &template))
+}
+
+async fn import_template(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(query): Query<TemplateQuery>,
+    body: String,
+) -> Result<Response, Response> {
+    crate::reports::authorize(&state.token, &headers).map_err(IntoResponse::into_response)?;
+    let template: ServerTemplate = query.format.parse(&body).map_err(error_response)?;
+    let changes = state
+        .templates
+        .import(&template, query.dry_run)
+        .await
+        .map_err(error_response)?;
+    Ok(render(
+        query.format,
+        &ImportReport {
+            changes,
+            dry_run: query.dry_run,
+        },
+    ))
+}
+
+fn render<T: Serialize>(format: TemplateFormat, value: &T) -> Response {
+    match format.render(value) {
+        Ok(body) => ([(header::CONTENT_TYPE, format.content_type())], body).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+fn error_response(error: FleetNetError) -> Response {
+    match error {
+        FleetNetError::ValidationError(errors) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, axum::Json(errors)).into_response()
+        }
+        FleetNetError::JsonError(message) => {
+            (StatusCode::BAD_REQUEST, message.into_owned()).into_response()
+        }
+        error => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fleet_net_common::channel::{AudioPolicy, Channel, ChannelPermissions, ChannelType};
+    use fleet_net_common::permission::PermissionSet;
+    use fleet_net_common::role::Role;
+    use fleet_net_common::session::SessionState;
+    use fleet_net_common::types::{ChannelId, UserId};
+    use fleet_net_common::user::User;
+    use fleet_net_common::validation::FieldErrors;
+    use std::collections::HashMap;
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    fn channel(id: u16, name: &str) -> Channel {
+        Channel {
+            id: ChannelId::new(id).unwrap(),
+            name: name.to_string(),
+            description: None,
+            channel_type: ChannelType::Voice,
+            role_permissions: HashMap::new(),
+            position: 0,
+            parent_id: None,
+            topic: None,
+            icon: None,
+            metadata: HashMap::new(),
+            radio: None,
+            audio_policy: AudioPolicy::default(),
+        }
+    }
+
+    fn session(permissions: Permissions) -> Session {
+        Session {
+            id: "session".to_string(),
+            user: User::new(UserId::new(1).unwrap()),
+            socket_addr: "127.0.0.1:9000".parse().unwrap(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            state: SessionState::Active,
+            current_channel: None,
+            subscribed_channels: Default::default(),
+            permission: PermissionSet::from(permissions),
+            auth_token: "token".to_string(),
+            client_version: "1.0.0".to_string(),
+        }
+    }
+
+    async fn manager() -> Arc<TemplateManager> {
+        let channels = Arc::new(ChannelRegistry::in_memory());
+        channels.store().save(channel(1, "Ops")).await.unwrap();
+        channels.store().save(channel(2, "Lobby")).await.unwrap();
+        let roles = Arc::new(RoleRegistry::new(Permissions::CONNECT));
+        roles.upsert_role(Role::new("atc".to_string(), "ATC".to_string()));
+        Arc::new(TemplateManager::new(channels, roles))
+    }
+
+    #[tokio::test]
+    async fn test_imports_are_checked_before_replacing_the_setup() {
+        let templates = manager().await;
+        let mut template = templates.export().await.unwrap();
+        template.channels.remove(1);
+        template.channels.push(channel(3, "Tower"));
+        template.channels[1].role_permissions.insert(
+            "pilot".to_string(),
+            ChannelPermissions {
+                allow: Permissions::SPEAK,
+                deny: Permissions::empty(),
+            },
+        );
+        template.roles[1] = Role::new("pilot".to_string(), "Pilot".to_string());
+        let import = ControlMessage::ImportTemplate {
+            template: template.clone(),
+            dry_run: true,
+        };
+
+        // Channel managers may export but not grant roles permissions
+        let mut manager = session(Permissions::MANAGE_CHANNELS | Permissions::MANAGE_ROLES);
+        let export = templates
+            .apply(&mut manager, &ControlMessage::ExportTemplate)
+            .await
+            .unwrap();
+        assert!(matches!(export, ControlMessage::TemplateExported { .. }));
+        let result = templates.apply(&mut manager, &import).await;
+        assert!(matches!(result, Err(FleetNetError::PermissionError(_))));
+
+        let mut admin = session(Permissions::ADMINISTRATOR);
+        let reply = templates.apply(&mut admin, &import).await.unwrap();
+        let ControlMessage::TemplateImported { changes, dry_run } = reply else {
+            panic!("unexpected reply {reply:?}");
+        };
+        assert!(dry_run);
+        assert_eq!(changes.channels_added, [ChannelId::new(3).unwrap()]);
+        assert_eq!(changes.channels_removed, [ChannelId::new(2).unwrap()]);
+        assert_eq!(changes.roles_added, ["pilot"]);
+        assert_eq!(changes.roles_removed, ["atc"]);
+        assert_eq!(templates.export().await.unwrap().channels.len(), 2);
+
+        templates.import(&template, false).await.unwrap();
+        assert_eq!(templates.export().await.unwrap(), template);
+
+        // A broken template changes nothing
+        let mut broken = template.clone();
+        broken.channels[0].parent_id = ChannelId::new(9);
+        broken.channels.remove(1);
+        let result = templates.import(&broken, false).await;
+        assert!(matches!(result, Err(FleetNetError::ValidationError(_))));
+        assert_eq!(templates.export().await.unwrap(), template);
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_round_trips_yaml() {
+        let templates = manager().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = admin_router(templates.clone(), "s3cret");
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = reqwest::Client::new();
+        let base = format!("http://{addr}/admin/template");
+
+        let response = client.get(&base).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let yaml = client
+            .get(format!("{base}?format=yaml"))
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(yaml.contains("name: Lobby"));
+
+        let edited = yaml.replace("name: Lobby", "name: Briefing");
+        let report: ImportReport = serde_yaml::from_str(
+            &client
+                .put(format!("{base}?format=yaml&dry_run=true"))
+                .bearer_auth("s3cret")
+                .body(edited.clone())
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            report.changes.channels_updated,
+            [ChannelId::new(2).unwrap()]
+        );
+        assert!(report.dry_run);
+        let lobby = templates.export().await.unwrap().channels[1].clone();
+        assert_eq!(lobby.name, "Lobby");
+
+        let response = client
+            .put(format!("{base}?format=yaml"))
+            .bearer_auth("s3cret")
+            .body(edited)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let briefing = templates.export().await.unwrap().channels[1].clone();
+        assert_eq!(briefing.name, "Briefing");
+
+        // Validation problems come back as field errors
+        let nameless = ServerTemplate {
+            channels: Vec::new(),
+            roles: vec![Role::new(String::new(), "Nobody".to_string())],
+        };
+        let response = client
+            .put(&base)
+            .bearer_auth("s3cret")
+            .json(&nameless)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let errors: FieldErrors = response.json().await.unwrap();
+        assert_eq!(errors.to_string(), "roles[0].id: too_short(1)");
+
+        let response = client
+            .put(&base)
+            .bearer_auth("s3cret")
+            .body("not json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
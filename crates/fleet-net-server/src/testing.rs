@@ -0,0 +1,389 @@
+//! Full-stack test harness: a real [`Server`] on ephemeral ports with
+//! protocol-level clients connected to it over TLS.
+//!
+//! ```ignore
+//! let (cluster, mut clients) = TestCluster::start(3).await;
+//! for client in &mut clients {
+//!     assert!(matches!(client.server_info(), ControlMessage::ServerInfo { .. }));
+//! }
+//! cluster.server().channels();
+//! ```
+//!
+//! Clients speak the control protocol directly, without the reconnecting
+//! client, so tests see exactly what the server sends. They sign in with
+//! [`TestClient::authenticate`], join channels with
+//! [`TestClient::join_channel`] and talk with [`TestClient::transmit`],
+//! going through the server's dispatch loop and voice socket like real
+//! clients. State the server has no message for yet is driven through its
+//! registries via [`TestCluster::server`].
+
+use crate::auth::TokenVerifier;
+use crate::cluster::ClusterMode;
+use crate::server::{SecurityConfig, Server, ServerConfig};
+use crate::store::StoreConfig;
+use fleet_net_common::channel::{AudioPolicy, Channel, ChannelType};
+use fleet_net_common::limits::ServerLimits;
+use fleet_net_common::types::{ChannelId, UserId};
+use fleet_net_protocol::connection::Connection;
+use fleet_net_protocol::hmac::HmacKey;
+use fleet_net_protocol::message::ControlMessage;
+use fleet_net_protocol::packet::{AudioPacket, PacketHeader};
+use fleet_net_protocol::qos::QosConfig;
+use fleet_net_protocol::tls::TlsConfig;
+use fleet_test_support::time::with_default_timeout;
+use fleet_test_support::{generate_test_certs, init_crypto_once, TestCertBundle};
+use rustls::pki_types::ServerName;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+/// Hostname the generated certificate is issued for.
+pub const TEST_HOSTNAME: &str = "localhost";
+
+/// Secret [`test_config`] verifies client tokens with.
+pub const TEST_JWT_SECRET: &str = "fleet-net-test-secret";
+
+/// A standalone server on loopback with every optional listener disabled
+/// and no TLS; [`TestCluster`] adds generated certificates.
+pub fn test_config() -> ServerConfig {
+    ServerConfig {
+        name: "Fleet Net Server".to_string(),
+        bind_address: "127.0.0.1:0".to_string(),
+        tls_cert_path: None,
+        tls_key_path: None,
+        cluster: ClusterMode::Standalone,
+        health_bind_address: None,
+        admin_token: None,
+        region: None,
+        ping_bind_address: None,
+        voice_bind_address: "127.0.0.1:0".to_string(),
+        jwt_secret: Some(TEST_JWT_SECRET.to_string()),
+        limits: ServerLimits::default(),
+        qos: QosConfig::default(),
+        journal_path: None,
+        motd: None,
+        rtp_exports: Vec::new(),
+        events: None,
+        propagation: None,
+        announcements: None,
+        security: SecurityConfig::default(),
+        store: StoreConfig::default(),
+    }
+}
+
+/// A voice channel anyone may join, for saving into the server's store.
+pub fn test_channel(id: u16) -> Channel {
+    Channel {
+        id: ChannelId::new(id).unwrap(),
+        name: format!("Channel {id}"),
+        description: None,
+        channel_type: ChannelType::Voice,
+        role_permissions: HashMap::new(),
+        position: 0,
+        parent_id: None,
+        topic: None,
+        icon: None,
+        metadata: HashMap::new(),
+        radio: None,
+        audio_policy: AudioPolicy::default(),
+    }
+}
+
+/// A running server and what clients need to reach it.
+///
+/// The server stops accepting connections when the cluster is dropped.
+pub struct TestCluster {
+    server: Arc<Server>,
+    addr: SocketAddr,
+    certs: TestCertBundle,
+    accept_loop: JoinHandle<()>,
+}
+
+impl TestCluster {
+    /// Boots a server from [`test_config`] and connects `clients` to it.
+    pub async fn start(clients: usize) -> (Self, Vec<TestClient>) {
+        Self::start_with(test_config(), clients).await
+    }
+
+    /// Boots a server from `config` with a generated certificate in place
+    /// of any configured one, and connects `clients` to it.
+    pub async fn start_with(mut config: ServerConfig, clients: usize) -> (Self, Vec<TestClient>) {
+        init_crypto_once();
+        let certs = generate_test_certs(TEST_HOSTNAME);
+        config.tls_cert_path = Some(certs.cert_path.clone());
+        config.tls_key_path = Some(certs.key_path.clone());
+
+        let mut server = Server::new(config).expect("Failed to create server");
+        let addr = server.start().await.expect("Failed to start server");
+        let server = Arc::new(server);
+        let accept_loop = tokio::spawn({
+            let server = server.clone();
+            async move {
+                if let Err(e) = server.run().await {
+                    tracing::error!("Test server stopped: {e}");
+                }
+            }
+        });
+
+        let cluster = Self {
+            server,
+            addr,
+            certs,
+            accept_loop,
+        };
+        let clients = cluster.connect_many(clients).await;
+        (cluster, clients)
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Address of the control listener.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Address of the voice socket.
+    pub fn voice_addr(&self) -> SocketAddr {
+        let port = self.server.status().voice_port.expect("Voice socket bound");
+        SocketAddr::new(self.addr.ip(), port)
+    }
+
+    /// The server's certificate, for clients built by hand.
+    pub fn certs(&self) -> &TestCertBundle {
+        &self.certs
+    }
+
+    /// Connects one more client, once it has received the server's
+    /// `ServerInfo`.
+    pub async fn connect(&self) -> TestClient {
+        let client_config = TlsConfig::new_client(&self.certs.cert_path)
+            .expect("Failed to create client config")
+            .client_config
+            .expect("Client config present");
+        let stream = TcpStream::connect(self.addr)
+            .await
+            .expect("Failed to connect to server");
+        let domain = ServerName::try_from(TEST_HOSTNAME).expect("Invalid domain");
+        let stream = TlsConnector::from(client_config)
+            .connect(domain, stream)
+            .await
+            .expect("Failed to establish TLS connection");
+
+        let mut conn = Connection::new(stream);
+        let server_info = with_default_timeout(conn.read_message())
+            .await
+            .expect("Timed out waiting for ServerInfo")
+            .expect("Failed to read ServerInfo");
+        TestClient {
+            conn,
+            server_info,
+            voice_addr: self.voice_addr(),
+            voice: None,
+            user_id: None,
+            udp_key: None,
+            sequence: 0,
+        }
+    }
+
+    /// Connects `count` more clients, one after another.
+    pub async fn connect_many(&self, count: usize) -> Vec<TestClient> {
+        let mut clients = Vec::with_capacity(count);
+        for _ in 0..count {
+            clients.push(self.connect().await);
+        }
+        clients
+    }
+}
+
+impl Drop for TestCluster {
+    fn drop(&mut self) {
+        self.accept_loop.abort();
+    }
+}
+
+/// One protocol-level client of a [`TestCluster`].
+pub struct TestClient {
+    conn: Connection<TlsStream<TcpStream>>,
+    server_info: ControlMessage,
+    /// Where voice is sent: the server's voice socket, or the relay it
+    /// assigned.
+    voice_addr: SocketAddr,
+    /// Bound when the client first joins a channel.
+    voice: Option<UdpSocket>,
+    user_id: Option<UserId>,
+    /// The session key the server issued.
+    udp_key: Option<HmacKey>,
+    sequence: u16,
+}
+
+impl TestClient {
+    /// Where the client sends its voice.
+    pub fn voice_addr(&self) -> SocketAddr {
+        self.voice_addr
+    }
+
+    /// The `ServerInfo` the server greeted this client with.
+    pub fn server_info(&self) -> &ControlMessage {
+        &self.server_info
+    }
+
+    pub async fn send(&mut self, message: &ControlMessage) {
+        self.conn
+            .write_message(message)
+            .await
+            .expect("Failed to send message");
+    }
+
+    /// The next message from the server, failing the test if none arrives
+    /// within the default timeout.
+    pub async fn recv(&mut self) -> ControlMessage {
+        with_default_timeout(self.conn.read_message())
+            .await
+            .expect("Timed out waiting for a message")
+            .expect("Failed to read message")
+    }
+
+    /// Skips messages until one matches `predicate`, and returns it.
+    pub async fn recv_until(
+        &mut self,
+        predicate: impl Fn(&ControlMessage) -> bool,
+    ) -> ControlMessage {
+        loop {
+            let message = self.recv().await;
+            if predicate(&message) {
+                return message;
+            }
+        }
+    }
+
+    /// Signs in as `user_id` with a token signed with [`TEST_JWT_SECRET`],
+    /// failing the test if the server refuses it.
+    pub async fn authenticate(&mut self, user_id: UserId) {
+        let token = TokenVerifier::new(TEST_JWT_SECRET.as_bytes())
+            .issue(user_id)
+            .expect("Failed to sign token");
+        self.send(&ControlMessage::Authenticate {
+            token,
+            client_version: Cow::Borrowed(env!("CARGO_PKG_VERSION")),
+            resume_token: None,
+        })
+        .await;
+        match self
+            .recv_until(|message| matches!(message, ControlMessage::AuthResponse { .. }))
+            .await
+        {
+            ControlMessage::AuthResponse {
+                success: true,
+                udp_key,
+                voice_relay,
+                ..
+            } => {
+                self.user_id = Some(user_id);
+                self.udp_key = udp_key;
+                self.voice_addr = voice_relay.unwrap_or(self.voice_addr);
+            }
+            other => panic!("User {user_id} was refused: {other:?}"),
+        }
+    }
+
+    /// Joins `channel_id` and registers the client's voice address, so it
+    /// hears the channel. Returns the `ChannelJoined`, failing the test if
+    /// the server refuses.
+    pub async fn join_channel(&mut self, channel_id: ChannelId) -> ControlMessage {
+        self.send(&ControlMessage::JoinChannel { channel_id }).await;
+        let joined = self
+            .recv_until(|message| {
+                matches!(
+                    message,
+                    ControlMessage::ChannelJoined { .. } | ControlMessage::Error { .. }
+                )
+            })
+            .await;
+        assert!(
+            matches!(joined, ControlMessage::ChannelJoined { .. }),
+            "Failed to join channel {channel_id}: {joined:?}"
+        );
+
+        // A bare header registers the address voice is sent to
+        let registration = self.packet(channel_id, Vec::new());
+        self.send_voice(&registration.to_bytes()).await;
+        joined
+    }
+
+    /// Sends `opus_payload` to `channel_id` over the voice socket.
+    pub async fn transmit(&mut self, channel_id: ChannelId, opus_payload: &[u8]) {
+        let packet = self.packet(channel_id, opus_payload.to_vec());
+        self.send_voice(&packet.to_bytes()).await;
+    }
+
+    /// The next voice packet forwarded to this client, failing the test if
+    /// none arrives within the default timeout or it is not signed with the
+    /// client's session key.
+    pub async fn recv_voice(&mut self) -> AudioPacket {
+        let socket = self.voice_socket().await;
+        let mut buf = [0u8; 1500];
+        let (len, _) = with_default_timeout(socket.recv_from(&mut buf))
+            .await
+            .expect("Timed out waiting for voice")
+            .expect("Failed to receive voice");
+        let packet = AudioPacket::from_bytes(&buf[..len]).expect("Malformed voice packet");
+        let key = self
+            .udp_key
+            .as_ref()
+            .expect("Authenticate before listening");
+        assert!(packet.validate_hmac(key), "Voice packet is not signed");
+        packet
+    }
+
+    /// The next packet this client sends to `channel_id`, signed with its
+    /// session key.
+    fn packet(&mut self, channel_id: ChannelId, opus_payload: Vec<u8>) -> AudioPacket {
+        self.sequence = self.sequence.wrapping_add(1);
+        let header = PacketHeader {
+            channel_id,
+            user_id: self.user_id.expect("Authenticate before talking"),
+            sequence: self.sequence,
+            timestamp: u32::from(self.sequence) * 20,
+            signal_strength: 255,
+            frame_duration: 20,
+            dtx: false,
+            padded: false,
+            frames: 1,
+            audio_length: 0,
+            hmac_prefix: 0,
+        };
+        let key = self.udp_key.as_ref().expect("Authenticate before talking");
+        AudioPacket::new_signed(header, opus_payload, key)
+    }
+
+    async fn send_voice(&mut self, datagram: &[u8]) {
+        let voice_addr = self.voice_addr;
+        self.voice_socket()
+            .await
+            .send_to(datagram, voice_addr)
+            .await
+            .expect("Failed to send voice datagram");
+    }
+
+    async fn voice_socket(&mut self) -> &UdpSocket {
+        if self.voice.is_none() {
+            let socket = UdpSocket::bind(SocketAddr::new(self.voice_addr.ip(), 0))
+                .await
+                .expect("Failed to bind voice socket");
+            self.voice = Some(socket);
+        }
+        self.voice.as_ref().expect("Voice socket bound")
+    }
+
+    /// The underlying connection, e.g. to send raw frames.
+    pub fn connection(&mut self) -> &mut Connection<TlsStream<TcpStream>> {
+        &mut self.conn
+    }
+}
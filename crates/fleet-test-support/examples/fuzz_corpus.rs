@@ -0,0 +1,25 @@
+//! Writes the seed corpora for the protocol fuzz targets.
+//!
+//! ```text
+//! cargo run -p fleet-test-support --example fuzz_corpus -- crates/fleet-net-protocol/fuzz/corpus
+//! ```
+
+use fleet_test_support::fuzz::{frame_seeds, packet_seeds, write_corpus};
+use std::path::PathBuf;
+
+fn main() -> std::io::Result<()> {
+    tracing_subscriber::fmt().init();
+    let root = std::env::args()
+        .nth(1)
+        .map_or_else(|| PathBuf::from("fuzz/corpus"), PathBuf::from);
+
+    for (target, seeds) in [
+        ("audio_packet", packet_seeds()),
+        ("packet_header", packet_seeds()),
+        ("control_frame", frame_seeds()),
+    ] {
+        let written = write_corpus(&root.join(target), &seeds)?;
+        tracing::info!("{target}: {written} seeds");
+    }
+    Ok(())
+}
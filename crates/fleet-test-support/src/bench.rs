@@ -0,0 +1,77 @@
+//! Inputs for the hot-path benchmarks
+//!
+//! Benchmarks are only comparable across commits if they measure the same
+//! work, so the payloads and control frames they run on live here rather
+//! than in each bench file. Like the fuzz seeds they are raw bytes and JSON,
+//! independent of the protocol crate's types.
+
+use crate::fuzz::frame;
+
+/// Opus payload sizes of a 20 ms frame at 16, 32 and 64 kbit/s.
+pub const PAYLOAD_SIZES: [usize; 3] = [40, 80, 160];
+
+/// Users in the `channel_joined` control frame, a busy channel.
+const JOINED_USERS: u16 = 100;
+
+/// `len` bytes of deterministic filler standing in for encoded audio.
+pub fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+/// A fixed 32-byte key for signing benchmark packets.
+pub fn hmac_key_bytes() -> [u8; 32] {
+    std::array::from_fn(|i| i as u8 ^ 0x5A)
+}
+
+/// Control message bodies from smallest to largest, each with a name for
+/// the benchmark id.
+pub fn control_messages() -> Vec<(&'static str, String)> {
+    let users: Vec<String> = (1..=JOINED_USERS).map(|id| id.to_string()).collect();
+    vec![
+        ("ping", r#"{"type":"ping"}"#.to_string()),
+        (
+            "join_channel",
+            r#"{"type":"join_channel","channel_id":12}"#.to_string(),
+        ),
+        (
+            "authenticate",
+            r#"{"type":"authenticate","token":"discord_token","client_version":"1.0.0","resume_token":null}"#
+                .to_string(),
+        ),
+        (
+            "channel_joined",
+            format!(
+                r#"{{"type":"channel_joined","channel_id":12,"users":[{}]}}"#,
+                users.join(",")
+            ),
+        ),
+    ]
+}
+
+/// [`control_messages`] behind their length prefixes, as they arrive on a
+/// control connection.
+pub fn control_frames() -> Vec<(&'static str, Vec<u8>)> {
+    control_messages()
+        .into_iter()
+        .map(|(name, json)| (name, frame(json.as_bytes())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixtures_are_stable_and_well_formed() {
+        assert_eq!(payload(160), payload(160));
+        assert_eq!(payload(40).len(), 40);
+
+        for (name, json) in control_messages() {
+            let message: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(message["type"], name);
+        }
+        let (_, joined) = control_frames().pop().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&joined[4..]).unwrap();
+        assert_eq!(body["users"].as_array().unwrap().len(), 100);
+    }
+}
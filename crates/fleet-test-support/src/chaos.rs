@@ -0,0 +1,411 @@
+//! Scripted network chaos for end-to-end resilience tests
+//!
+//! A [`ChaosController`] names the links of a test topology, wraps the
+//! streams and sockets that travel over them, and plays a script of
+//! partitions, flaps and slowdowns against them over time:
+//!
+//! ```no_run
+//! use fleet_test_support::chaos::ChaosController;
+//! use std::time::Duration;
+//!
+//! # async fn example(stream: tokio::net::TcpStream) {
+//! let mut chaos = ChaosController::new();
+//! let stream = chaos.wrap("client-a", stream);
+//!
+//! // Drop server <-> client A for 5s at t=10s
+//! chaos.partition("client-a", Duration::from_secs(10), Duration::from_secs(5));
+//! let script = chaos.run();
+//! # }
+//! ```
+//!
+//! A partitioned link stalls like a pulled cable: nothing is lost or
+//! reported, reads and writes simply wait until it heals, and datagrams sent
+//! meanwhile are dropped. A severed link fails every operation, like a reset
+//! connection.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Sleep};
+
+/// What a link currently does to the traffic crossing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkCondition {
+    #[default]
+    Up,
+    /// Traffic stalls until the link heals; datagrams are dropped.
+    Partitioned,
+    /// Every read, write and datagram is delayed by this long.
+    Slow(Duration),
+    /// Every operation fails, as after a connection reset.
+    Severed,
+}
+
+#[derive(Debug, Default)]
+struct LinkShared {
+    condition: LinkCondition,
+    /// Operations stalled by a partition, woken when it changes.
+    waiting: Vec<Waker>,
+}
+
+/// Shared control over one named link.
+#[derive(Debug, Clone, Default)]
+pub struct LinkHandle(Arc<Mutex<LinkShared>>);
+
+impl LinkHandle {
+    pub fn condition(&self) -> LinkCondition {
+        self.0.lock().unwrap().condition
+    }
+
+    /// Changes the link's condition, resuming anything it stalled.
+    pub fn set(&self, condition: LinkCondition) {
+        let mut shared = self.0.lock().unwrap();
+        shared.condition = condition;
+        for waker in shared.waiting.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// The condition, with `cx` woken when it next changes if the link is
+    /// partitioned.
+    fn poll_condition(&self, cx: &mut Context<'_>) -> LinkCondition {
+        let mut shared = self.0.lock().unwrap();
+        if shared.condition == LinkCondition::Partitioned {
+            shared.waiting.push(cx.waker().clone());
+        }
+        shared.condition
+    }
+}
+
+fn severed() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "Link severed")
+}
+
+/// Per-direction slowdown state of a [`ChaosStream`].
+#[derive(Default)]
+struct Slowdown {
+    sleep: Option<Pin<Box<Sleep>>>,
+    elapsed: bool,
+}
+
+impl Slowdown {
+    /// Ready once the link lets this operation through.
+    fn poll_pass(&mut self, cx: &mut Context<'_>, link: &LinkHandle) -> Poll<io::Result<()>> {
+        match link.poll_condition(cx) {
+            LinkCondition::Up => Poll::Ready(Ok(())),
+            LinkCondition::Partitioned => Poll::Pending,
+            LinkCondition::Severed => Poll::Ready(Err(severed())),
+            LinkCondition::Slow(_) if self.elapsed => Poll::Ready(Ok(())),
+            LinkCondition::Slow(delay) => {
+                let sleep = self
+                    .sleep
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep(delay)));
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+                self.elapsed = true;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.elapsed = false;
+    }
+}
+
+/// A stream crossing a chaos-controlled link.
+pub struct ChaosStream<S> {
+    inner: S,
+    link: LinkHandle,
+    read: Slowdown,
+    write: Slowdown,
+}
+
+impl<S> ChaosStream<S> {
+    pub fn new(inner: S, link: LinkHandle) -> Self {
+        Self {
+            inner,
+            link,
+            read: Slowdown::default(),
+            write: Slowdown::default(),
+        }
+    }
+
+    pub fn link(&self) -> &LinkHandle {
+        &self.link
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.read.poll_pass(cx, &this.link))?;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if result.is_ready() {
+            this.read.reset();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.write.poll_pass(cx, &this.link))?;
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if result.is_ready() {
+            this.write.reset();
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.link.poll_condition(cx) == LinkCondition::Severed {
+            return Poll::Ready(Err(severed()));
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A UDP socket crossing a chaos-controlled link.
+///
+/// Datagrams sent or received while the link is partitioned are dropped,
+/// as a real network would.
+pub struct ChaosSocket {
+    socket: UdpSocket,
+    link: LinkHandle,
+}
+
+impl ChaosSocket {
+    pub fn new(socket: UdpSocket, link: LinkHandle) -> Self {
+        Self { socket, link }
+    }
+
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends `datagram` to `target` unless the link drops it; a dropped
+    /// datagram still reports its full length as sent.
+    pub async fn send_to(&self, datagram: &[u8], target: SocketAddr) -> io::Result<usize> {
+        match self.link.condition() {
+            LinkCondition::Up => self.socket.send_to(datagram, target).await,
+            LinkCondition::Partitioned => Ok(datagram.len()),
+            LinkCondition::Slow(delay) => {
+                tokio::time::sleep(delay).await;
+                self.socket.send_to(datagram, target).await
+            }
+            LinkCondition::Severed => Err(severed()),
+        }
+    }
+
+    /// Receives the next datagram the link lets through.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            let received = self.socket.recv_from(buf).await?;
+            match self.link.condition() {
+                LinkCondition::Up => return Ok(received),
+                LinkCondition::Partitioned => continue,
+                LinkCondition::Slow(delay) => {
+                    tokio::time::sleep(delay).await;
+                    return Ok(received);
+                }
+                LinkCondition::Severed => return Err(severed()),
+            }
+        }
+    }
+}
+
+/// A change to a link at some time into the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScheduledChange {
+    at: Duration,
+    link: String,
+    condition: LinkCondition,
+}
+
+/// Owns the links of a test topology and plays a script against them.
+#[derive(Debug, Default)]
+pub struct ChaosController {
+    links: HashMap<String, LinkHandle>,
+    script: Vec<ScheduledChange>,
+}
+
+impl ChaosController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The link named `name`, created up if it is new.
+    pub fn link(&mut self, name: &str) -> LinkHandle {
+        self.links.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Route `stream` over the link named `name`.
+    pub fn wrap<S>(&mut self, name: &str, stream: S) -> ChaosStream<S> {
+        ChaosStream::new(stream, self.link(name))
+    }
+
+    /// Route `socket` over the link named `name`.
+    pub fn wrap_socket(&mut self, name: &str, socket: UdpSocket) -> ChaosSocket {
+        ChaosSocket::new(socket, self.link(name))
+    }
+
+    /// Set `name` to `condition` at `at` into the script.
+    pub fn at(&mut self, at: Duration, name: &str, condition: LinkCondition) -> &mut Self {
+        self.link(name);
+        self.script.push(ScheduledChange {
+            at,
+            link: name.to_string(),
+            condition,
+        });
+        self
+    }
+
+    /// Partition `name` at `at` and heal it `duration` later.
+    pub fn partition(&mut self, name: &str, at: Duration, duration: Duration) -> &mut Self {
+        self.at(at, name, LinkCondition::Partitioned)
+            .at(at + duration, name, LinkCondition::Up)
+    }
+
+    /// Partition and heal `name` `times` times starting at `at`, each state
+    /// lasting `period`.
+    pub fn flap(&mut self, name: &str, at: Duration, period: Duration, times: u32) -> &mut Self {
+        for i in 0..times {
+            self.partition(name, at + period * (2 * i), period);
+        }
+        self
+    }
+
+    /// Delay traffic on `name` by `delay` from `at` for `duration`.
+    pub fn slow_down(
+        &mut self,
+        name: &str,
+        at: Duration,
+        duration: Duration,
+        delay: Duration,
+    ) -> &mut Self {
+        self.at(at, name, LinkCondition::Slow(delay))
+            .at(at + duration, name, LinkCondition::Up)
+    }
+
+    /// Sever `name` for good at `at`.
+    pub fn sever(&mut self, name: &str, at: Duration) -> &mut Self {
+        self.at(at, name, LinkCondition::Severed)
+    }
+
+    /// Play the script from now on in a background task, which finishes
+    /// after the last change.
+    pub fn run(&self) -> JoinHandle<()> {
+        let mut script: Vec<(ScheduledChange, LinkHandle)> = self
+            .script
+            .iter()
+            .map(|change| (change.clone(), self.links[&change.link].clone()))
+            .collect();
+        // Stable, so changes scheduled for the same time apply in order
+        script.sort_by_key(|(change, _)| change.at);
+        let start = Instant::now();
+        tokio::spawn(async move {
+            for (change, link) in script {
+                tokio::time::sleep_until(start + change.at).await;
+                tracing::debug!("Chaos: {} is now {:?}", change.link, change.condition);
+                link.set(change.condition);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udp::connected_udp_pair;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_partitions_stall_streams_until_healed() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut chaos = ChaosController::new();
+        let mut client = chaos.wrap("client-a", client);
+        chaos.partition("client-a", Duration::ZERO, Duration::from_millis(100));
+        let script = chaos.run();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        server.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        let stalled =
+            tokio::time::timeout(Duration::from_millis(30), client.read_exact(&mut buf)).await;
+        assert!(stalled.is_err());
+
+        // Nothing was lost while partitioned
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        script.await.unwrap();
+
+        chaos.link("client-a").set(LinkCondition::Severed);
+        let err = client.write_all(b"bye").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test]
+    async fn test_partitions_drop_datagrams() {
+        let (sender, receiver) = connected_udp_pair().await.unwrap();
+        let target = receiver.local_addr().unwrap();
+        let mut chaos = ChaosController::new();
+        let sender = chaos.wrap_socket("voice", sender);
+        let link = chaos.link("voice");
+
+        link.set(LinkCondition::Partitioned);
+        sender.send_to(b"lost", target).await.unwrap();
+        link.set(LinkCondition::Up);
+        sender.send_to(b"kept", target).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"kept");
+    }
+
+    #[test]
+    fn test_flaps_alternate_partitions_and_heals() {
+        let mut chaos = ChaosController::new();
+        chaos.flap("relay", Duration::from_secs(1), Duration::from_secs(2), 2);
+        let conditions: Vec<_> = chaos
+            .script
+            .iter()
+            .map(|change| (change.at.as_secs(), change.condition))
+            .collect();
+        assert_eq!(
+            conditions,
+            [
+                (1, LinkCondition::Partitioned),
+                (3, LinkCondition::Up),
+                (5, LinkCondition::Partitioned),
+                (7, LinkCondition::Up),
+            ]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! On-disk server configuration fixtures
+//!
+//! [`TestConfigBuilder`] writes out everything a server reads at startup —
+//! a `server.toml`, a TLS certificate and a SQLite database seeded with
+//! channels and roles — into a temporary directory, so config loading and
+//! persistence can be tested against real files:
+//!
+//! ```no_run
+//! use fleet_test_support::config::TestConfigBuilder;
+//! use serde_json::json;
+//!
+//! # async fn example() {
+//! let fixture = TestConfigBuilder::new()
+//!     .region("eu-west")
+//!     .channel(json!({ "id": 1, "name": "Lobby" }))
+//!     .role(json!({ "id": "admin", "name": "Administrator", "permissions": 1 }))
+//!     .build()
+//!     .await;
+//! let config = std::fs::read_to_string(fixture.config_path()).unwrap();
+//! # }
+//! ```
+//!
+//! Channels and roles are taken as anything serializable, such as the
+//! workspace's own `Channel` and `Role`, and stored whole as JSON next to
+//! their `id` and `name`, so this crate needs no knowledge of their types.
+
+use crate::crypto::generate_test_certs;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::Connection;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Tables of the seeded database.
+const SCHEMA: &str = "
+    CREATE TABLE channels (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE roles (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+";
+
+/// Hostname the fixture's certificate is issued for.
+const FIXTURE_HOSTNAME: &str = "localhost";
+
+/// Builds a [`TestConfigDir`].
+///
+/// Settings not given keep the server's defaults: a loopback address on an
+/// ephemeral port, no region and no message of the day.
+#[derive(Debug, Clone)]
+pub struct TestConfigBuilder {
+    settings: toml::Table,
+    tls: bool,
+    channels: Vec<Value>,
+    roles: Vec<Value>,
+}
+
+impl TestConfigBuilder {
+    pub fn new() -> Self {
+        let mut settings = toml::Table::new();
+        settings.insert("bind_address".to_string(), "127.0.0.1:0".into());
+        Self {
+            settings,
+            tls: true,
+            channels: Vec::new(),
+            roles: Vec::new(),
+        }
+    }
+
+    pub fn bind_address(self, address: impl Into<String>) -> Self {
+        self.setting("bind_address", address.into())
+    }
+
+    pub fn region(self, region: impl Into<String>) -> Self {
+        self.setting("region", region.into())
+    }
+
+    pub fn motd(self, motd: impl Into<String>) -> Self {
+        self.setting("motd", motd.into())
+    }
+
+    /// Sets any top-level key of `server.toml`, replacing an earlier value.
+    pub fn setting(mut self, key: &str, value: impl Into<toml::Value>) -> Self {
+        self.settings.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Leaves out the certificate and the TLS paths.
+    pub fn without_tls(mut self) -> Self {
+        self.tls = false;
+        self
+    }
+
+    /// Seeds a channel row.
+    ///
+    /// # Panics
+    ///
+    /// If `channel` has no integer `id` or string `name`.
+    pub fn channel(mut self, channel: impl Serialize) -> Self {
+        let channel = to_record(channel, Value::is_u64);
+        self.channels.push(channel);
+        self
+    }
+
+    /// Seeds a role row.
+    ///
+    /// # Panics
+    ///
+    /// If `role` has no string `id` or `name`.
+    pub fn role(mut self, role: impl Serialize) -> Self {
+        let role = to_record(role, Value::is_string);
+        self.roles.push(role);
+        self
+    }
+
+    /// Writes the configuration out.
+    ///
+    /// # Panics
+    ///
+    /// If a file or the database cannot be written, or two seeded rows
+    /// share an id.
+    pub async fn build(self) -> TestConfigDir {
+        let dir = TempDir::new().expect("Failed to create config directory");
+        let mut settings = self.settings;
+
+        let database_path = dir.path().join("fleet-net.db");
+        settings.insert(
+            "database_url".to_string(),
+            sqlite_url(&database_path).into(),
+        );
+
+        let (cert_path, key_path) = if self.tls {
+            let certs = generate_test_certs(FIXTURE_HOSTNAME);
+            let cert_path = dir.path().join("cert.pem");
+            let key_path = dir.path().join("key.pem");
+            std::fs::copy(&certs.cert_path, &cert_path).expect("Failed to copy certificate");
+            std::fs::copy(&certs.key_path, &key_path).expect("Failed to copy private key");
+            settings.insert("tls_cert_path".to_string(), path_value(&cert_path));
+            settings.insert("tls_key_path".to_string(), path_value(&key_path));
+            (Some(cert_path), Some(key_path))
+        } else {
+            (None, None)
+        };
+
+        let config_path = dir.path().join("server.toml");
+        let config = toml::to_string(&settings).expect("Settings are valid TOML");
+        std::fs::write(&config_path, config).expect("Failed to write server.toml");
+
+        seed_database(&database_path, &self.channels, &self.roles).await;
+
+        TestConfigDir {
+            dir,
+            config_path,
+            database_path,
+            cert_path,
+            key_path,
+        }
+    }
+}
+
+impl Default for TestConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A server configuration directory, removed when dropped.
+#[derive(Debug)]
+pub struct TestConfigDir {
+    dir: TempDir,
+    config_path: PathBuf,
+    database_path: PathBuf,
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+}
+
+impl TestConfigDir {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The `server.toml` file.
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    pub fn database_path(&self) -> &Path {
+        &self.database_path
+    }
+
+    /// The database as `server.toml` names it, e.g. for `SqlitePool::connect`.
+    pub fn database_url(&self) -> String {
+        sqlite_url(&self.database_path)
+    }
+
+    /// The certificate PEM, unless built [`without_tls`](TestConfigBuilder::without_tls).
+    pub fn cert_path(&self) -> Option<&Path> {
+        self.cert_path.as_deref()
+    }
+
+    pub fn key_path(&self) -> Option<&Path> {
+        self.key_path.as_deref()
+    }
+}
+
+fn to_record(record: impl Serialize, id_is_valid: fn(&Value) -> bool) -> Value {
+    let record = serde_json::to_value(record).expect("Record must serialize");
+    assert!(
+        record.get("id").is_some_and(id_is_valid),
+        "Record has no usable id: {record}"
+    );
+    assert!(
+        record.get("name").is_some_and(Value::is_string),
+        "Record has no name: {record}"
+    );
+    record
+}
+
+fn sqlite_url(path: &Path) -> String {
+    format!("sqlite://{}", path.display())
+}
+
+fn path_value(path: &Path) -> toml::Value {
+    path.to_str().expect("Temporary paths are UTF-8").into()
+}
+
+async fn seed_database(path: &Path, channels: &[Value], roles: &[Value]) {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    let mut db = SqliteConnection::connect_with(&options)
+        .await
+        .expect("Failed to create database");
+    sqlx::raw_sql(SCHEMA)
+        .execute(&mut db)
+        .await
+        .expect("Failed to create tables");
+
+    for (table, rows) in [("channels", channels), ("roles", roles)] {
+        let insert = format!("INSERT INTO {table} (id, name, data) VALUES (?, ?, ?)");
+        for row in rows {
+            let query = match &row["id"] {
+                Value::Number(id) => sqlx::query(&insert).bind(id.as_i64()),
+                id => sqlx::query(&insert).bind(id.as_str().map(str::to_string)),
+            };
+            query
+                .bind(row["name"].as_str().map(str::to_string))
+                .bind(row.to_string())
+                .execute(&mut db)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to seed {table} with {row}: {e}"));
+        }
+    }
+    db.close().await.expect("Failed to close database");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sqlx::Row;
+
+    #[tokio::test]
+    async fn test_fixture_holds_config_certs_and_seeded_database() {
+        let fixture = TestConfigBuilder::new()
+            .region("eu-west")
+            .motd("Welcome to { $server }")
+            .setting("admin_token", "secret")
+            .channel(json!({ "id": 2, "name": "Ops", "position": 1 }))
+            .channel(json!({ "id": 1, "name": "Lobby", "position": 0 }))
+            .role(json!({ "id": "admin", "name": "Administrator", "permissions": 1 }))
+            .build()
+            .await;
+
+        let config: toml::Table =
+            toml::from_str(&std::fs::read_to_string(fixture.config_path()).unwrap()).unwrap();
+        assert_eq!(config["bind_address"].as_str(), Some("127.0.0.1:0"));
+        assert_eq!(config["region"].as_str(), Some("eu-west"));
+        assert_eq!(config["motd"].as_str(), Some("Welcome to { $server }"));
+        assert_eq!(config["admin_token"].as_str(), Some("secret"));
+        assert_eq!(
+            config["database_url"].as_str(),
+            Some(fixture.database_url().as_str())
+        );
+        let cert_path = fixture.cert_path().unwrap();
+        assert_eq!(config["tls_cert_path"].as_str(), cert_path.to_str());
+        assert!(std::fs::read_to_string(cert_path)
+            .unwrap()
+            .starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(fixture.key_path().unwrap().starts_with(fixture.path()));
+
+        let mut db = SqliteConnection::connect(&fixture.database_url())
+            .await
+            .unwrap();
+        let channels = sqlx::query("SELECT id, name, data FROM channels ORDER BY id")
+            .fetch_all(&mut db)
+            .await
+            .unwrap();
+        let names: Vec<String> = channels.iter().map(|row| row.get("name")).collect();
+        assert_eq!(names, ["Lobby", "Ops"]);
+        let data: Value = serde_json::from_str(channels[1].get("data")).unwrap();
+        assert_eq!(data["position"], 1);
+        let role: String = sqlx::query("SELECT id FROM roles")
+            .fetch_one(&mut db)
+            .await
+            .unwrap()
+            .get("id");
+        assert_eq!(role, "admin");
+    }
+
+    #[tokio::test]
+    async fn test_tls_can_be_left_out() {
+        let fixture = TestConfigBuilder::new().without_tls().build().await;
+        let config = std::fs::read_to_string(fixture.config_path()).unwrap();
+
+        assert!(fixture.cert_path().is_none());
+        assert!(!config.contains("tls_cert_path"), "{config}");
+        assert!(fixture.database_path().exists());
+    }
+}
@@ -1,7 +1,10 @@
 //! Cryptography test helpers including certificate generation and provider initialization
 
 use once_cell::sync::OnceCell;
-use rcgen::{generate_simple_self_signed, CertifiedKey};
+use rcgen::{
+    date_time_ymd, generate_simple_self_signed, BasicConstraints, CertificateParams, CertifiedKey,
+    DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
 use std::path::PathBuf;
 use tempfile::TempDir;
 
@@ -28,24 +31,233 @@ pub struct TestCertBundle {
     pub cert: CertifiedKey,
 }
 
+/// Most intermediate CAs rustls' verifier follows between a server
+/// certificate and a trusted root.
+pub const MAX_VERIFIED_INTERMEDIATES: usize = 6;
+
+/// Names every test server certificate is valid for besides its hostname.
+const LOCAL_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
 /// Generate a self-signed certificate for testing with the given hostname.
 ///
 /// The certificate will be valid for the specified hostname as well as
 /// "localhost", "127.0.0.1", and "::1" to cover common test scenarios.
 pub fn generate_test_certs(hostname: &str) -> TestCertBundle {
-    let cert = generate_simple_self_signed(vec![
-        hostname.to_string(),
-        "localhost".to_string(),
-        "127.0.0.1".to_string(),
-        "::1".to_string(),
-    ])
-    .expect("Failed to generate certificate");
+    let cert = generate_simple_self_signed(server_names(hostname))
+        .expect("Failed to generate certificate");
+    let pem = cert.cert.pem();
+    write_bundle(cert, pem, "cert.pem", "key.pem")
+}
+
+/// A test PKI: a root CA, any intermediate CAs, and a server certificate
+/// issued at the end of the chain.
+pub struct TestCertChain {
+    /// The self-signed root, the only certificate a client should trust.
+    pub root: TestCertBundle,
+    /// Intermediate CAs, the one signed by the root first.
+    pub intermediates: Vec<TestCertBundle>,
+    /// The server certificate. Its PEM file holds the leaf followed by the
+    /// intermediates, ready to present as a full chain.
+    pub server: TestCertBundle,
+}
+
+/// Generate a CA certificate and a server certificate signed by it.
+/// Useful for testing certificate chain validation.
+pub fn generate_ca_and_server_certs(server_hostname: &str) -> (TestCertBundle, TestCertBundle) {
+    let chain = generate_cert_chain(server_hostname, 0);
+    (chain.root, chain.server)
+}
+
+/// Generate a root CA, `intermediates` intermediate CAs below it, and a
+/// server certificate for `server_hostname` signed by the last of them.
+///
+/// Each intermediate's path length constraint allows exactly the CAs below
+/// it, and a client trusting only the root validates the whole chain.
+pub fn generate_cert_chain(server_hostname: &str, intermediates: usize) -> TestCertChain {
+    issue_chain(intermediates, server_params(&server_names(server_hostname)))
+}
+
+/// Generate a CA and a server certificate for `server_hostname` that
+/// expired in 2001.
+pub fn generate_expired_certs(server_hostname: &str) -> (TestCertBundle, TestCertBundle) {
+    let mut params = server_params(&server_names(server_hostname));
+    params.not_before = date_time_ymd(2000, 1, 1);
+    params.not_after = date_time_ymd(2001, 1, 1);
+    let chain = issue_chain(0, params);
+    (chain.root, chain.server)
+}
+
+/// Generate a CA and a server certificate for `server_hostname` that only
+/// becomes valid in 2999.
+pub fn generate_not_yet_valid_certs(server_hostname: &str) -> (TestCertBundle, TestCertBundle) {
+    let mut params = server_params(&server_names(server_hostname));
+    params.not_before = date_time_ymd(2999, 1, 1);
+    params.not_after = date_time_ymd(3000, 1, 1);
+    let chain = issue_chain(0, params);
+    (chain.root, chain.server)
+}
+
+/// Generate a CA and a server certificate valid for `cert_hostname` alone,
+/// without the local names other generators add, so connecting under any
+/// other name fails the hostname check.
+pub fn generate_wrong_hostname_certs(cert_hostname: &str) -> (TestCertBundle, TestCertBundle) {
+    let chain = issue_chain(0, server_params(&[cert_hostname.to_string()]));
+    (chain.root, chain.server)
+}
+
+/// Generate a chain for `server_hostname` with one intermediate more than
+/// verifiers follow, see [`MAX_VERIFIED_INTERMEDIATES`].
+///
+/// Every certificate in it is well formed and correctly signed; only the
+/// chain's length makes it fail.
+pub fn generate_oversized_chain(server_hostname: &str) -> TestCertChain {
+    generate_cert_chain(server_hostname, MAX_VERIFIED_INTERMEDIATES + 1)
+}
 
+/// Issue a root CA, `intermediates` intermediate CAs and a server
+/// certificate from `params` at the end of the chain.
+fn issue_chain(intermediates: usize, params: CertificateParams) -> TestCertChain {
+    let root_key = KeyPair::generate().expect("Failed to generate CA key");
+    let root_cert = ca_params("Fleet Net Test Root CA", BasicConstraints::Unconstrained)
+        .self_signed(&root_key)
+        .expect("Failed to generate CA certificate");
+    let mut issuer = CertifiedKey {
+        cert: root_cert,
+        key_pair: root_key,
+    };
+    let mut issued = Vec::with_capacity(intermediates);
+    for depth in 0..intermediates {
+        let key = KeyPair::generate().expect("Failed to generate intermediate key");
+        let remaining = (intermediates - depth - 1) as u8;
+        let cert = ca_params(
+            &format!("Fleet Net Test Intermediate CA {}", depth + 1),
+            BasicConstraints::Constrained(remaining),
+        )
+        .signed_by(&key, &issuer.cert, &issuer.key_pair)
+        .expect("Failed to sign intermediate certificate");
+        issued.push(std::mem::replace(
+            &mut issuer,
+            CertifiedKey {
+                cert,
+                key_pair: key,
+            },
+        ));
+    }
+    issued.push(issuer);
+    let issuer = issued.last().expect("chain has a root");
+
+    let server_key = KeyPair::generate().expect("Failed to generate server key");
+    let server_cert = params
+        .signed_by(&server_key, &issuer.cert, &issuer.key_pair)
+        .expect("Failed to sign server certificate");
+
+    // Servers send their leaf first, then each issuer up to the root
+    let mut chain_pem = server_cert.pem();
+    for ca in issued[1..].iter().rev() {
+        chain_pem.push_str(&ca.cert.pem());
+    }
+    let server = write_bundle(
+        CertifiedKey {
+            cert: server_cert,
+            key_pair: server_key,
+        },
+        chain_pem,
+        "cert.pem",
+        "key.pem",
+    );
+
+    let mut bundles = issued.into_iter().map(|ca| {
+        let pem = ca.cert.pem();
+        write_bundle(ca, pem, "ca_cert.pem", "ca_key.pem")
+    });
+    let root = bundles.next().expect("chain has a root");
+    TestCertChain {
+        root,
+        intermediates: bundles.collect(),
+        server,
+    }
+}
+
+/// Generate a client identity certificate for `client_name`, signed by
+/// `ca`, for mutual TLS.
+///
+/// The identity's PEM file holds only the leaf, so the server must trust
+/// `ca` itself.
+pub fn generate_client_cert(ca: &TestCertBundle, client_name: &str) -> TestCertBundle {
+    let mut params = CertificateParams::default();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, client_name);
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    params.use_authority_key_identifier_extension = true;
+
+    let key = KeyPair::generate().expect("Failed to generate client key");
+    let cert = params
+        .signed_by(&key, &ca.cert.cert, &ca.cert.key_pair)
+        .expect("Failed to sign client certificate");
+    let pem = cert.pem();
+    write_bundle(
+        CertifiedKey {
+            cert,
+            key_pair: key,
+        },
+        pem,
+        "client_cert.pem",
+        "client_key.pem",
+    )
+}
+
+fn server_names(hostname: &str) -> Vec<String> {
+    std::iter::once(hostname)
+        .chain(LOCAL_NAMES)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parameters for a server certificate valid for `names`, the first of
+/// which is also its common name.
+fn server_params(names: &[String]) -> CertificateParams {
+    let mut params =
+        CertificateParams::new(names).expect("Failed to create server certificate params");
+    params
+        .distinguished_name
+        .push(DnType::CommonName, names[0].as_str());
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    params
+}
+
+/// Parameters for a CA named `name` that may sign certificates and CRLs.
+fn ca_params(name: &str, constraints: BasicConstraints) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.is_ca = IsCa::Ca(constraints);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.use_authority_key_identifier_extension = true;
+    params
+}
+
+/// Write `cert_pem` and the key of `cert` to files in a new temp directory.
+fn write_bundle(
+    cert: CertifiedKey,
+    cert_pem: String,
+    cert_file: &str,
+    key_file: &str,
+) -> TestCertBundle {
     let temp_dir = TempDir::new().expect("Failed to create temp dir");
-    let cert_path = temp_dir.path().join("cert.pem");
-    let key_path = temp_dir.path().join("key.pem");
+    let cert_path = temp_dir.path().join(cert_file);
+    let key_path = temp_dir.path().join(key_file);
 
-    std::fs::write(&cert_path, cert.cert.pem()).expect("Failed to write cert");
+    std::fs::write(&cert_path, cert_pem).expect("Failed to write cert");
     std::fs::write(&key_path, cert.key_pair.serialize_pem()).expect("Failed to write key");
 
     TestCertBundle {
@@ -56,30 +268,60 @@ pub fn generate_test_certs(hostname: &str) -> TestCertBundle {
     }
 }
 
-/// Generate a CA certificate and a server certificate signed by it.
-/// Useful for testing certificate chain validation.
-pub fn generate_ca_and_server_certs(server_hostname: &str) -> (TestCertBundle, TestCertBundle) {
-    // Generate CA certificate
-    let ca_cert = generate_simple_self_signed(vec!["Test CA".to_string()])
-        .expect("Failed to generate CA certificate");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::bind_ephemeral;
+    use crate::tls::{
+        client_config_from_bundle, create_tls_acceptor, create_tls_connector,
+        server_config_from_bundle, tls_connect,
+    };
 
-    let ca_temp_dir = TempDir::new().expect("Failed to create CA temp dir");
-    let ca_cert_path = ca_temp_dir.path().join("ca_cert.pem");
-    let ca_key_path = ca_temp_dir.path().join("ca_key.pem");
+    /// Whether a client trusting only `trusted` accepts the certificate
+    /// chain of `server`.
+    async fn handshake(server: &TestCertBundle, trusted: &TestCertBundle) -> bool {
+        init_crypto_once();
+        let (listener, addr) = bind_ephemeral().await.unwrap();
+        let acceptor = create_tls_acceptor(server_config_from_bundle(server));
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        });
+        let connector = create_tls_connector(client_config_from_bundle(trusted));
+        let connected = tls_connect(&connector, addr, "fleet.test").await.is_ok();
+        accept.abort();
+        connected
+    }
 
-    std::fs::write(&ca_cert_path, ca_cert.cert.pem()).expect("Failed to write CA cert");
-    std::fs::write(&ca_key_path, ca_cert.key_pair.serialize_pem()).expect("Failed to write CA key");
+    #[tokio::test]
+    async fn test_ca_signed_chains_validate() {
+        let (ca, server) = generate_ca_and_server_certs("fleet.test");
+        assert!(handshake(&server, &ca).await);
 
-    let ca_bundle = TestCertBundle {
-        temp_dir: ca_temp_dir,
-        cert_path: ca_cert_path,
-        key_path: ca_key_path,
-        cert: ca_cert,
-    };
+        let chain = generate_cert_chain("fleet.test", 2);
+        assert_eq!(chain.intermediates.len(), 2);
+        let presented = std::fs::read_to_string(&chain.server.cert_path).unwrap();
+        assert_eq!(presented.matches("BEGIN CERTIFICATE").count(), 3);
+        assert!(handshake(&chain.server, &chain.root).await);
+
+        // A chain from another root is refused
+        assert!(!handshake(&chain.server, &ca).await);
+    }
 
-    // For now, we'll use a self-signed cert as the server cert
-    // (proper CA signing would require more complex rcgen setup)
-    let server_bundle = generate_test_certs(server_hostname);
+    #[tokio::test]
+    async fn test_invalid_certificates_are_refused() {
+        let (ca, server) = generate_expired_certs("fleet.test");
+        assert!(!handshake(&server, &ca).await);
 
-    (ca_bundle, server_bundle)
+        let (ca, server) = generate_not_yet_valid_certs("fleet.test");
+        assert!(!handshake(&server, &ca).await);
+
+        let (ca, server) = generate_wrong_hostname_certs("other.test");
+        assert!(!handshake(&server, &ca).await);
+
+        let chain = generate_oversized_chain("fleet.test");
+        assert!(!handshake(&chain.server, &chain.root).await);
+        let chain = generate_cert_chain("fleet.test", MAX_VERIFIED_INTERMEDIATES);
+        assert!(handshake(&chain.server, &chain.root).await);
+    }
 }
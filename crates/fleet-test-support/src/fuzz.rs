@@ -0,0 +1,177 @@
+//! Seed corpora for the protocol fuzz targets
+//!
+//! Fuzzers find deep bugs faster when they start from inputs that already
+//! get past the first checks. These builders produce well-formed voice
+//! packets and control frames along with the near misses a parser has to
+//! refuse: truncated headers, zero ids, lying length fields and frames that
+//! announce more than they carry. Like the UDP helpers they work on raw
+//! bytes, so they never drift with the protocol crate's types.
+//!
+//! Write them out with [`write_corpus`], or run the `fuzz_corpus` example.
+
+use crate::udp::PACKET_HEADER_LEN;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+
+/// Control messages every frame corpus starts from, as JSON.
+const FRAME_MESSAGES: &[&str] = &[
+    r#"{"type":"ping"}"#,
+    r#"{"type":"pong"}"#,
+    r#"{"type":"join_channel","channel_id":1}"#,
+    r#"{"type":"authenticate","token":"discord_token","client_version":"1.0.0","resume_token":null}"#,
+    r#"{"type":"channel_joined","channel_id":65535,"users":[1,2,3]}"#,
+    r#"{"type":"set_presence","presence":{"status":"in_game","game":"Arma 3"}}"#,
+    r#"{"type":"update_channel_info","channel_id":2,"topic":"Tasking","icon":"radio-tower","metadata":{"grid":"CA 123 456"}}"#,
+    r#"{"type":"error","code":1003,"message":"Encryption error"}"#,
+];
+
+/// The bytes of a voice packet header.
+#[allow(clippy::too_many_arguments)]
+pub fn packet_header(
+    channel_id: u16,
+    user_id: u16,
+    sequence: u16,
+    timestamp: u32,
+    signal_strength: u8,
+    frame_duration: u8,
+    audio_length: u16,
+    hmac_prefix: u16,
+) -> [u8; PACKET_HEADER_LEN] {
+    let mut header = [0u8; PACKET_HEADER_LEN];
+    header[0..2].copy_from_slice(&channel_id.to_be_bytes());
+    header[2..4].copy_from_slice(&user_id.to_be_bytes());
+    header[4..6].copy_from_slice(&sequence.to_be_bytes());
+    header[6..10].copy_from_slice(&timestamp.to_be_bytes());
+    header[10] = signal_strength;
+    header[11] = frame_duration;
+    header[12..14].copy_from_slice(&audio_length.to_be_bytes());
+    header[14..16].copy_from_slice(&hmac_prefix.to_be_bytes());
+    header
+}
+
+/// A voice packet whose header announces `audio_length` bytes of audio,
+/// followed by `payload_len` bytes of it.
+fn packet(channel_id: u16, user_id: u16, audio_length: u16, payload_len: usize) -> Vec<u8> {
+    let mut packet =
+        packet_header(channel_id, user_id, 7, 1_000, 200, 20, audio_length, 0xBEEF).to_vec();
+    packet.extend((0..payload_len).map(|i| i as u8));
+    packet
+}
+
+/// A voice packet batching frames of `lengths` bytes, with `excess` more
+/// bytes of audio than its extension header accounts for.
+fn batch(lengths: &[u16], excess: usize) -> Vec<u8> {
+    let mut audio: Vec<u8> = lengths.iter().flat_map(|len| len.to_be_bytes()).collect();
+    let frames: usize = lengths.iter().map(|&len| usize::from(len)).sum();
+    audio.extend((0..frames + excess).map(|i| i as u8));
+    let length_field = (lengths.len() as u16 - 1) << 12 | audio.len() as u16;
+    let mut packet = packet_header(4, 9, 7, 1_000, 200, 20, length_field, 0xBEEF).to_vec();
+    packet.extend(audio);
+    packet
+}
+
+/// A 40-byte voice packet flagged as padded, followed by `padding` bytes
+/// ending in the padding length `claimed`.
+fn padded(padding: usize, claimed: u16) -> Vec<u8> {
+    let mut packet = packet(1, 1, 40, 40);
+    packet[11] |= 0x40;
+    packet.resize(packet.len() + padding - 2, 0);
+    packet.extend(claimed.to_be_bytes());
+    packet
+}
+
+/// Voice packets, valid and not, for `AudioPacket::from_bytes` and
+/// `PacketHeader::read_from`.
+pub fn packet_seeds() -> Vec<Vec<u8>> {
+    let mut seeds = vec![
+        packet(1, 1, 0, 0),
+        packet(3, 42, 40, 40),
+        packet(u16::MAX, u16::MAX, 160, 160),
+        // Zero ids are reserved
+        packet(0, 1, 0, 0),
+        packet(1, 0, 0, 0),
+        // Lengths that disagree with the payload
+        packet(1, 1, 40, 10),
+        packet(1, 1, 10, 40),
+        packet(1, 1, u16::MAX, 16),
+        // Batched frames, and batches whose frame lengths don't add up
+        batch(&[40, 38, 41], 0),
+        batch(&[40, 38, 41], 3),
+        // Padded packets, and padding longer than the packet
+        padded(8, 8),
+        padded(8, 200),
+        Vec::new(),
+    ];
+    let full = packet(2, 5, 20, 20);
+    seeds.extend((1..PACKET_HEADER_LEN).map(|len| full[..len].to_vec()));
+    seeds
+}
+
+/// Length-prefixed control frames, valid and not, for the frame decoder.
+pub fn frame_seeds() -> Vec<Vec<u8>> {
+    let mut seeds: Vec<Vec<u8>> = FRAME_MESSAGES
+        .iter()
+        .map(|json| frame(json.as_bytes()))
+        .collect();
+
+    // Several frames back to back
+    seeds.push(seeds[..3].concat());
+    // Bodies cut short, and prefixes claiming more than any frame may hold
+    let join = frame(FRAME_MESSAGES[2].as_bytes());
+    seeds.push(join[..join.len() - 5].to_vec());
+    seeds.push(join[..2].to_vec());
+    seeds.push(u32::MAX.to_be_bytes().to_vec());
+    // Bodies that are not a message
+    seeds.push(frame(b""));
+    seeds.push(frame(b"{\"type\":\"no_such_message\"}"));
+    seeds.push(frame(b"{\"type\":\"join_channel\",\"channel_id\":0}"));
+    seeds.push(frame(&[0xFF, 0xFE, 0x00, b'{']));
+    seeds.push(frame(&[b'['; 512]));
+    seeds
+}
+
+/// `body` behind its big-endian length prefix.
+pub fn frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// Write each seed to its own file in `dir`, creating it if needed, and
+/// return how many were written.
+///
+/// Files are named after a hash of their contents, like the corpus files
+/// fuzzers write, so rerunning never duplicates a seed.
+pub fn write_corpus(dir: &Path, seeds: &[Vec<u8>]) -> io::Result<usize> {
+    std::fs::create_dir_all(dir)?;
+    for seed in seeds {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        std::fs::write(dir.join(format!("seed-{:016x}", hasher.finish())), seed)?;
+    }
+    Ok(seeds.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_seeds_cover_valid_and_malformed_input() {
+        let packets = packet_seeds();
+        assert!(packets.iter().any(|p| p.len() == PACKET_HEADER_LEN));
+        assert!(packets.iter().any(|p| p.len() < PACKET_HEADER_LEN));
+
+        let frames = frame_seeds();
+        let ping = &frames[0];
+        assert_eq!(&ping[..4], &15u32.to_be_bytes());
+        assert_eq!(&ping[4..], br#"{"type":"ping"}"#);
+
+        let dir = TempDir::new().unwrap();
+        assert_eq!(write_corpus(dir.path(), &frames).unwrap(), frames.len());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), frames.len());
+    }
+}
@@ -0,0 +1,120 @@
+//! Golden-file snapshots of wire encodings
+//!
+//! [`assert_golden`] compares an encoding with a fixture committed next to
+//! the tests, so a change to how messages or packets look on the wire fails
+//! loudly instead of silently breaking older peers. When a change is
+//! intended, rerun the tests with `UPDATE_GOLDEN=1` to rewrite the fixtures
+//! and commit the diff along with the change.
+//!
+//! ```no_run
+//! use fleet_test_support::golden::{assert_golden, hex_dump};
+//!
+//! let header = [0x00, 0x07, 0x00, 0x2a];
+//! assert_golden(
+//!     concat!(env!("CARGO_MANIFEST_DIR"), "/golden/header.hex"),
+//!     &hex_dump(&header),
+//! );
+//! ```
+
+use std::path::Path;
+
+/// Environment variable that makes [`assert_golden`] rewrite fixtures
+/// instead of checking them.
+pub const UPDATE_GOLDEN_VAR: &str = "UPDATE_GOLDEN";
+
+/// Bytes per line of a [`hex_dump`].
+const HEX_DUMP_WIDTH: usize = 16;
+
+/// Asserts that `actual` matches the fixture at `path`, or rewrites the
+/// fixture when `UPDATE_GOLDEN` is set.
+///
+/// # Panics
+///
+/// If the fixture is missing or differs, naming the first differing line.
+pub fn assert_golden(path: impl AsRef<Path>, actual: &str) {
+    let update = std::env::var_os(UPDATE_GOLDEN_VAR).is_some_and(|value| value != "0");
+    if let Err(message) = check_golden(path.as_ref(), actual, update) {
+        panic!("{message}");
+    }
+}
+
+fn check_golden(path: &Path, actual: &str, update: bool) -> Result<(), String> {
+    if update {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        return std::fs::write(path, actual).map_err(|e| format!("{}: {e}", path.display()));
+    }
+
+    let expected = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Golden file {} unreadable ({e}); run with {UPDATE_GOLDEN_VAR}=1 to create it",
+            path.display()
+        )
+    })?;
+    if expected == actual {
+        return Ok(());
+    }
+
+    let (line, expected_line, actual_line) = expected
+        .lines()
+        .map(Some)
+        .chain(std::iter::repeat(None))
+        .zip(actual.lines().map(Some).chain(std::iter::repeat(None)))
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+        .map(|(index, (expected, actual))| (index + 1, expected, actual))
+        .unwrap_or((expected.lines().count() + 1, None, None));
+    Err(format!(
+        "Encoding differs from golden file {} at line {line}:\n  expected: {}\n  actual:   {}\n\
+         If the change is intended, rerun with {UPDATE_GOLDEN_VAR}=1 and commit the fixture",
+        path.display(),
+        expected_line.unwrap_or("<end of file>"),
+        actual_line.unwrap_or("<end of file>"),
+    ))
+}
+
+/// `bytes` as offset-prefixed lines of hex, for binary layouts in
+/// fixtures.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(HEX_DUMP_WIDTH)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+            format!("{:04x}: {}\n", line * HEX_DUMP_WIDTH, hex.join(" "))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_golden_files_are_written_then_enforced() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("nested/frame.hex");
+        let dump = hex_dump(&(0u8..20).collect::<Vec<_>>());
+        assert_eq!(
+            dump,
+            "0000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n0010: 10 11 12 13\n"
+        );
+
+        let missing = check_golden(&path, &dump, false).unwrap_err();
+        assert!(missing.contains("UPDATE_GOLDEN=1"), "{missing}");
+
+        check_golden(&path, &dump, true).unwrap();
+        check_golden(&path, &dump, false).unwrap();
+
+        let changed = dump.replace("12", "ff");
+        let err = check_golden(&path, &changed, false).unwrap_err();
+        assert!(err.contains("at line 2"), "{err}");
+        assert!(err.contains("actual:   0010: 10 11 ff 13"), "{err}");
+
+        let longer = format!("{dump}0014: 14\n");
+        let err = check_golden(&path, &longer, false).unwrap_err();
+        assert!(err.contains("expected: <end of file>"), "{err}");
+    }
+}
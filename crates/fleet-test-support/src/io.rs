@@ -3,10 +3,10 @@
 use std::future::Future;
 use std::io;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{ready, Context, Poll};
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tokio::time::Sleep;
+use tokio::time::{Instant, Sleep};
 
 /// A reader that introduces artificial delays between reads to simulate slow networks.
 pub struct SlowReader<R> {
@@ -178,3 +178,464 @@ impl<S: AsyncWrite + Unpin> AsyncWrite for DisruptableStream<S> {
         }
     }
 }
+
+/// How long each read or write through a [`JitteryStream`] is held back.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Latency {
+    #[default]
+    None,
+    Fixed(Duration),
+    /// Normally distributed around `mean`, clamped at zero.
+    Normal {
+        mean: Duration,
+        std_dev: Duration,
+    },
+    /// Pareto distributed with `scale` as the minimum; a `shape` near 1
+    /// gives the heavy tail of a congested mobile link.
+    Pareto {
+        scale: Duration,
+        shape: f64,
+    },
+}
+
+/// Occasional long stalls on top of the regular latency, like a Wi-Fi scan
+/// or a bufferbloated uplink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Burst {
+    /// Chance of each operation stalling, from 0 to 1.
+    pub probability: f64,
+    pub delay: Duration,
+}
+
+/// Latency and bursts for one direction of a [`JitteryStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Jitter {
+    pub latency: Latency,
+    pub burst: Option<Burst>,
+}
+
+impl Jitter {
+    /// Draws the delay for one operation.
+    pub fn sample(&self, rng: &mut TestRng) -> Duration {
+        let latency = match self.latency {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(delay) => delay,
+            Latency::Normal { mean, std_dev } => {
+                let secs = mean.as_secs_f64() + std_dev.as_secs_f64() * rng.next_normal();
+                Duration::from_secs_f64(secs.max(0.0))
+            }
+            Latency::Pareto { scale, shape } => {
+                // Inverse transform; 1 - u is never zero.
+                let u = 1.0 - rng.next_f64();
+                scale.mul_f64(u.powf(-1.0 / shape))
+            }
+        };
+        match self.burst {
+            Some(burst) if rng.next_f64() < burst.probability => latency + burst.delay,
+            _ => latency,
+        }
+    }
+}
+
+/// A small seeded random number generator (SplitMix64), so jittery tests
+/// replay the same delays for a given seed.
+#[derive(Debug, Clone)]
+pub struct TestRng(u64);
+
+impl TestRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    pub fn next_normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+}
+
+/// One direction's pending delay.
+#[derive(Default)]
+struct Delay {
+    jitter: Jitter,
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Whether the delay for the current operation has already passed.
+    elapsed: bool,
+}
+
+impl Delay {
+    fn new(jitter: Jitter) -> Self {
+        Self {
+            jitter,
+            ..Self::default()
+        }
+    }
+
+    /// Ready once this operation's delay has passed.
+    fn poll_elapsed(&mut self, cx: &mut Context<'_>, rng: &mut TestRng) -> Poll<()> {
+        if self.elapsed {
+            return Poll::Ready(());
+        }
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(self.jitter.sample(rng))));
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                self.sleep = None;
+                self.elapsed = true;
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Arms a fresh delay for the next operation.
+    fn reset(&mut self) {
+        self.elapsed = false;
+    }
+}
+
+/// A stream delaying every read and write by a randomly drawn latency, with
+/// separate settings per direction, for soak-testing heartbeats and
+/// reconnects under realistic network conditions.
+///
+/// Delays come from a seeded [`TestRng`], so a failing run can be replayed
+/// with the same seed.
+pub struct JitteryStream<S> {
+    inner: S,
+    read: Delay,
+    write: Delay,
+    rng: TestRng,
+}
+
+impl<S> JitteryStream<S> {
+    /// Create a stream applying `jitter` in both directions.
+    pub fn new(inner: S, jitter: Jitter) -> Self {
+        Self {
+            inner,
+            read: Delay::new(jitter),
+            write: Delay::new(jitter),
+            rng: TestRng::new(0),
+        }
+    }
+
+    /// Use `jitter` for reads only.
+    pub fn with_read_jitter(mut self, jitter: Jitter) -> Self {
+        self.read = Delay::new(jitter);
+        self
+    }
+
+    /// Use `jitter` for writes only.
+    pub fn with_write_jitter(mut self, jitter: Jitter) -> Self {
+        self.write = Delay::new(jitter);
+        self
+    }
+
+    /// Draw delays from a generator seeded with `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = TestRng::new(seed);
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for JitteryStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read.poll_elapsed(cx, &mut this.rng).is_pending() {
+            return Poll::Pending;
+        }
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if result.is_ready() {
+            this.read.reset();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for JitteryStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write.poll_elapsed(cx, &mut this.rng).is_pending() {
+            return Poll::Pending;
+        }
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if result.is_ready() {
+            this.write.reset();
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A link's capacity for a [`ThrottledStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bandwidth {
+    pub bytes_per_sec: u64,
+    /// Bytes that can go through at once after the link was idle.
+    pub burst: u64,
+}
+
+/// Token bucket for one direction.
+struct Bucket {
+    bandwidth: Bandwidth,
+    tokens: f64,
+    refilled_at: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Bucket {
+    fn new(bandwidth: Bandwidth) -> Self {
+        Self {
+            bandwidth,
+            tokens: bandwidth.burst as f64,
+            refilled_at: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled_at).as_secs_f64()
+            * self.bandwidth.bytes_per_sec as f64;
+        self.tokens = (self.tokens + earned).min(self.bandwidth.burst.max(1) as f64);
+        self.refilled_at = now;
+    }
+
+    /// Ready with how many of `wanted` bytes may pass now, at least one.
+    fn poll_acquire(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        loop {
+            if let Some(sleep) = self.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            self.refill();
+            if self.tokens >= 1.0 {
+                return Poll::Ready(wanted.min(self.tokens as usize));
+            }
+            let wait = (1.0 - self.tokens) / self.bandwidth.bytes_per_sec.max(1) as f64;
+            self.sleep = Some(Box::pin(tokio::time::sleep(Duration::from_secs_f64(wait))));
+        }
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// A stream limited to a [`Bandwidth`] in each direction by a token bucket,
+/// for testing large syncs and backpressure over constrained links.
+pub struct ThrottledStream<S> {
+    inner: S,
+    read: Option<Bucket>,
+    write: Option<Bucket>,
+    scratch: Vec<u8>,
+}
+
+impl<S> ThrottledStream<S> {
+    /// Create a stream limited to `bandwidth` in both directions.
+    pub fn new(inner: S, bandwidth: Bandwidth) -> Self {
+        Self {
+            inner,
+            read: Some(Bucket::new(bandwidth)),
+            write: Some(Bucket::new(bandwidth)),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Limit reads to `bandwidth`, or not at all.
+    pub fn with_read_limit(mut self, bandwidth: Option<Bandwidth>) -> Self {
+        self.read = bandwidth.map(Bucket::new);
+        self
+    }
+
+    /// Limit writes to `bandwidth`, or not at all.
+    pub fn with_write_limit(mut self, bandwidth: Option<Bandwidth>) -> Self {
+        self.write = bandwidth.map(Bucket::new);
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let Some(bucket) = this.read.as_mut() else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let allowed = ready!(bucket.poll_acquire(cx, buf.remaining()));
+
+        // Read into a scratch buffer so the inner stream cannot overrun the allowance.
+        this.scratch.resize(allowed, 0);
+        let mut limited = ReadBuf::new(&mut this.scratch);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let filled = limited.filled();
+        bucket.consume(filled.len());
+        buf.put_slice(filled);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(bucket) = this.write.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let allowed = ready!(bucket.poll_acquire(cx, buf.len()));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]))?;
+        bucket.consume(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_jitter_distributions() {
+        let mut rng = TestRng::new(7);
+        let normal = Jitter {
+            latency: Latency::Normal {
+                mean: Duration::from_millis(50),
+                std_dev: Duration::from_millis(100),
+            },
+            burst: None,
+        };
+        let samples: Vec<_> = (0..1000).map(|_| normal.sample(&mut rng)).collect();
+        // Clamped at zero rather than wrapping, and centred near the mean
+        assert!(samples.contains(&Duration::ZERO));
+        let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+        assert!(mean > Duration::from_millis(40) && mean < Duration::from_millis(90));
+
+        let pareto = Jitter {
+            latency: Latency::Pareto {
+                scale: Duration::from_millis(10),
+                shape: 1.5,
+            },
+            burst: Some(Burst {
+                probability: 1.0,
+                delay: Duration::from_secs(1),
+            }),
+        };
+        for _ in 0..100 {
+            assert!(pareto.sample(&mut rng) >= Duration::from_millis(1010));
+        }
+
+        // The same seed replays the same delays
+        let replay = |seed| {
+            let mut rng = TestRng::new(seed);
+            (0..5).map(|_| normal.sample(&mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(replay(3), replay(3));
+    }
+
+    #[tokio::test]
+    async fn test_jittery_stream_delays_each_direction() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut client = JitteryStream::new(client, Jitter::default()).with_write_jitter(Jitter {
+            latency: Latency::Fixed(Duration::from_millis(30)),
+            burst: None,
+        });
+
+        let started = tokio::time::Instant::now();
+        client.write_all(b"ping").await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Reads were left undelayed
+        server.write_all(b"pong").await.unwrap();
+        let started = tokio::time::Instant::now();
+        client.read_exact(&mut buf).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(30));
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_throttled_stream_limits_bandwidth() {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let link = Bandwidth {
+            bytes_per_sec: 10_000,
+            burst: 1_000,
+        };
+        let mut client = ThrottledStream::new(client, link).with_read_limit(None);
+
+        // The burst goes through at once, the remaining 2000 bytes take ~200ms
+        let started = tokio::time::Instant::now();
+        client.write_all(&[7u8; 3_000]).await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(150), "took {elapsed:?}");
+
+        let mut received = vec![0u8; 3_000];
+        server.read_exact(&mut received).await.unwrap();
+        assert!(received.iter().all(|&b| b == 7));
+
+        // Reads were left unlimited
+        server.write_all(&[9u8; 5_000]).await.unwrap();
+        let started = tokio::time::Instant::now();
+        let mut received = vec![0u8; 5_000];
+        client.read_exact(&mut received).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
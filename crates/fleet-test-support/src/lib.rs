@@ -3,13 +3,29 @@
 //! This crate provides common test helpers and utilities that are shared across
 //! the Fleet Net workspace for testing. It is not intended for production use.
 
+pub mod bench;
+pub mod chaos;
+pub mod config;
 pub mod crypto;
+pub mod fuzz;
+pub mod golden;
 pub mod io;
 pub mod net;
+pub mod recording;
 pub mod time;
 pub mod tls;
+pub mod udp;
 
 // Re-export commonly used items at the crate root
-pub use crypto::{generate_test_certs, init_crypto_once, TestCertBundle};
-pub use net::{connected_tcp_pair, mock_connection_pair};
+pub use config::{TestConfigBuilder, TestConfigDir};
+pub use crypto::{
+    generate_ca_and_server_certs, generate_cert_chain, generate_client_cert,
+    generate_expired_certs, generate_not_yet_valid_certs, generate_oversized_chain,
+    generate_test_certs, generate_wrong_hostname_certs, init_crypto_once, TestCertBundle,
+    TestCertChain,
+};
+pub use net::{connected_tcp_pair, connected_tcp_pair_v6, ipv6_available, mock_connection_pair};
 pub use time::{wait_until, with_timeout};
+pub use udp::{
+    connected_udp_pair, connected_udp_pair_v6, recv_packet, recv_packet_from, send_packet,
+};
@@ -9,7 +9,23 @@ use tokio::net::{TcpListener, TcpStream};
 /// This creates a real TCP connection over localhost, useful for testing
 /// actual network behavior including socket options and TCP features.
 pub async fn connected_tcp_pair() -> io::Result<(TcpStream, TcpStream)> {
-    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    connected_tcp_pair_on("127.0.0.1:0").await
+}
+
+/// Create a connected pair of TCP streams over the IPv6 loopback `[::1]`.
+///
+/// Fails on hosts without IPv6; check [`ipv6_available`] first to skip.
+pub async fn connected_tcp_pair_v6() -> io::Result<(TcpStream, TcpStream)> {
+    connected_tcp_pair_on("[::1]:0").await
+}
+
+/// Whether this host can bind the IPv6 loopback, so v6 tests can run.
+pub fn ipv6_available() -> bool {
+    std::net::UdpSocket::bind("[::1]:0").is_ok()
+}
+
+async fn connected_tcp_pair_on(address: &str) -> io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind(address).await?;
     let addr = listener.local_addr()?;
 
     // Connect client and accept server connection concurrently
@@ -68,6 +84,22 @@ mod tests {
         assert_eq!(&buf, b"world");
     }
 
+    #[tokio::test]
+    async fn test_connected_tcp_pair_v6() {
+        if !ipv6_available() {
+            return;
+        }
+        let (mut server, mut client) = connected_tcp_pair_v6()
+            .await
+            .expect("Failed to create TCP pair");
+        assert!(client.local_addr().unwrap().is_ipv6());
+
+        client.write_all(b"hello").await.expect("Failed to write");
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).await.expect("Failed to read");
+        assert_eq!(&buf, b"hello");
+    }
+
     #[tokio::test]
     async fn test_mock_connection_pair() {
         let (mut stream1, mut stream2) = mock_connection_pair_default();
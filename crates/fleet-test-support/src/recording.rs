@@ -0,0 +1,291 @@
+//! Byte capture for protocol ordering tests
+//!
+//! A [`RecordingStream`] passes everything through to the stream it wraps
+//! and keeps a timestamped copy of each read and write. The [`Recording`]
+//! it hands out splits that traffic back into length-prefixed control
+//! frames and decodes them into whatever message type the test names, so
+//! ordering can be asserted without this crate knowing the protocol:
+//!
+//! ```no_run
+//! use fleet_test_support::recording::{Direction, RecordingStream};
+//!
+//! # async fn example(stream: tokio::net::TcpStream) {
+//! let stream = RecordingStream::new(stream);
+//! let recording = stream.recording();
+//! // ... hand `stream` to the code under test ...
+//! recording.assert_before::<serde_json::Value>(
+//!     Direction::Written,
+//!     ("authenticate", |m| m["type"] == "authenticate"),
+//!     ("join_channel", |m| m["type"] == "join_channel"),
+//! );
+//! # }
+//! ```
+
+use serde::de::DeserializeOwned;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Instant;
+
+/// Bytes of a frame's big-endian length prefix.
+const FRAME_PREFIX_LEN: usize = 4;
+
+/// Which way bytes crossed the recorded stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Read by the code using the stream, i.e. sent by its peer.
+    Read,
+    /// Written by the code using the stream.
+    Written,
+}
+
+/// Bytes that crossed the stream in one read or write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub direction: Direction,
+    /// Time since the stream was wrapped.
+    pub at: Duration,
+    pub bytes: Vec<u8>,
+}
+
+/// One length-prefixed frame, without its prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// When the last byte of the frame crossed the stream.
+    pub at: Duration,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct RecordingShared {
+    started: Instant,
+    chunks: Vec<Chunk>,
+}
+
+/// Shared view of what a [`RecordingStream`] has seen so far.
+#[derive(Debug, Clone)]
+pub struct Recording(Arc<Mutex<RecordingShared>>);
+
+impl Recording {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(RecordingShared {
+            started: Instant::now(),
+            chunks: Vec::new(),
+        })))
+    }
+
+    fn record(&self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut shared = self.0.lock().unwrap();
+        let at = shared.started.elapsed();
+        shared.chunks.push(Chunk {
+            direction,
+            at,
+            bytes: bytes.to_vec(),
+        });
+    }
+
+    /// Every read and write, in the order they happened.
+    pub fn chunks(&self) -> Vec<Chunk> {
+        self.0.lock().unwrap().chunks.clone()
+    }
+
+    /// All bytes that went one way, concatenated.
+    pub fn bytes(&self, direction: Direction) -> Vec<u8> {
+        self.chunks()
+            .into_iter()
+            .filter(|chunk| chunk.direction == direction)
+            .flat_map(|chunk| chunk.bytes)
+            .collect()
+    }
+
+    /// The complete frames that went one way; a frame still being
+    /// transferred is left out.
+    pub fn frames(&self, direction: Direction) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut pending: Vec<u8> = Vec::new();
+        for chunk in self.chunks() {
+            if chunk.direction != direction {
+                continue;
+            }
+            pending.extend_from_slice(&chunk.bytes);
+            while let Some(prefix) = pending.first_chunk::<FRAME_PREFIX_LEN>() {
+                let end = FRAME_PREFIX_LEN + u32::from_be_bytes(*prefix) as usize;
+                if pending.len() < end {
+                    break;
+                }
+                let body = pending[FRAME_PREFIX_LEN..end].to_vec();
+                pending.drain(..end);
+                frames.push(Frame { at: chunk.at, body });
+            }
+        }
+        frames
+    }
+
+    /// The frames that went one way, decoded as JSON `T`.
+    ///
+    /// # Panics
+    ///
+    /// If a frame is not a `T`, naming the frame and its contents.
+    pub fn messages<T: DeserializeOwned>(&self, direction: Direction) -> Vec<T> {
+        self.frames(direction)
+            .into_iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                serde_json::from_slice(&frame.body).unwrap_or_else(|e| {
+                    panic!(
+                        "Frame {index} ({}) does not decode: {e}",
+                        String::from_utf8_lossy(&frame.body)
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Asserts that a message matching `first` went one way before any
+    /// message matching `then`.
+    ///
+    /// Each side is a description for the failure message and a predicate.
+    /// `then` need not have been sent at all, but `first` must have.
+    pub fn assert_before<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        direction: Direction,
+        (first_name, first): (&str, impl Fn(&T) -> bool),
+        (then_name, then): (&str, impl Fn(&T) -> bool),
+    ) {
+        let messages = self.messages::<T>(direction);
+        let first_at = messages.iter().position(&first);
+        let then_at = messages.iter().position(&then);
+        match (first_at, then_at) {
+            (None, _) => panic!("No {first_name} was {direction:?}, only {messages:?}"),
+            (Some(first_at), Some(then_at)) if then_at < first_at => panic!(
+                "{then_name} at {then_at} came before {first_name} at {first_at}: {messages:?}"
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// A stream that records everything read from and written to it.
+pub struct RecordingStream<S> {
+    inner: S,
+    recording: Recording,
+}
+
+impl<S> RecordingStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            recording: Recording::new(),
+        }
+    }
+
+    /// A handle that stays usable after the stream is moved or dropped.
+    pub fn recording(&self) -> Recording {
+        self.recording.clone()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for RecordingStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.recording
+                .record(Direction::Read, &buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for RecordingStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.recording.record(Direction::Written, &buf[..written]);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::frame;
+    use crate::net::mock_connection_pair_default;
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn is_type(name: &'static str) -> impl Fn(&Value) -> bool {
+        move |message| message["type"] == name
+    }
+
+    #[tokio::test]
+    async fn test_records_frames_in_both_directions() {
+        let (client, mut server) = mock_connection_pair_default();
+        let mut client = RecordingStream::new(client);
+        let recording = client.recording();
+
+        // A frame split across writes is still one frame
+        let auth = frame(br#"{"type":"authenticate"}"#);
+        client.write_all(&auth[..3]).await.unwrap();
+        client.write_all(&auth[3..]).await.unwrap();
+        client
+            .write_all(&frame(br#"{"type":"join_channel","channel_id":1}"#))
+            .await
+            .unwrap();
+        server
+            .write_all(&frame(br#"{"type":"pong"}"#))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 64];
+        let read = client.read(&mut buf).await.unwrap();
+
+        assert_eq!(recording.bytes(Direction::Read), &buf[..read]);
+        let written = recording.messages::<Value>(Direction::Written);
+        assert_eq!(written.len(), 2);
+        assert_eq!(written[1]["channel_id"], 1);
+        assert_eq!(recording.frames(Direction::Read).len(), 1);
+
+        recording.assert_before(
+            Direction::Written,
+            ("authenticate", is_type("authenticate")),
+            ("join_channel", is_type("join_channel")),
+        );
+        let reversed = std::panic::catch_unwind(|| {
+            recording.assert_before(
+                Direction::Written,
+                ("join_channel", is_type("join_channel")),
+                ("authenticate", is_type("authenticate")),
+            )
+        });
+        assert!(reversed.is_err());
+    }
+}
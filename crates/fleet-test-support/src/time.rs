@@ -1,4 +1,10 @@
 //! Time-related test helpers for timeouts and eventual consistency
+//!
+//! Code timed with `tokio::time` (heartbeats, retry backoff, timeouts) can
+//! be tested without real sleeps by pausing tokio's clock, with
+//! `#[tokio::test(start_paused = true)]`, and moving it with [`advance`].
+//! Synchronous code takes an injected clock instead; see
+//! `fleet_net_common::clock`.
 
 use std::future::Future;
 use std::time::Duration;
@@ -78,6 +84,40 @@ where
     condition().await
 }
 
+/// Yields this many times for woken tasks to run; enough for a chain of
+/// tasks handing work to each other.
+const SETTLE_YIELDS: usize = 16;
+
+/// Moves paused time forward by `duration` and lets the tasks it woke run.
+///
+/// Timers due within `duration` fire once, however many periods it spans;
+/// use [`advance_in_steps`] for intervals that should tick each period.
+///
+/// # Panics
+///
+/// If time is not paused.
+pub async fn advance(duration: Duration) {
+    tokio::time::advance(duration).await;
+    settle().await;
+}
+
+/// [`advance`] by `total` in increments of at most `step`.
+pub async fn advance_in_steps(total: Duration, step: Duration) {
+    let mut remaining = total;
+    while !remaining.is_zero() {
+        let next = remaining.min(step);
+        advance(next).await;
+        remaining -= next;
+    }
+}
+
+/// Lets tasks that are ready run before the test carries on.
+pub async fn settle() {
+    for _ in 0..SETTLE_YIELDS {
+        tokio::task::yield_now().await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +161,27 @@ mod tests {
 
         assert!(result);
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_advance_drives_paused_timers() {
+        let ticks = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = ticks.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        settle().await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 1);
+
+        advance_in_steps(Duration::from_secs(15), Duration::from_secs(5)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 4);
+
+        // One jump fires an overdue interval only once
+        advance(Duration::from_secs(60)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 5);
+    }
 }
@@ -2,6 +2,7 @@
 
 use crate::crypto::TestCertBundle;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
 use rustls::{ClientConfig, RootCertStore, ServerConfig};
 use std::io::BufReader;
 use std::path::Path;
@@ -22,8 +23,54 @@ pub fn server_config_from_bundle(bundle: &TestCertBundle) -> Arc<ServerConfig> {
     Arc::new(config)
 }
 
+/// Create a TLS server configuration that requires clients to present an
+/// identity signed by `client_ca`, for mutual TLS.
+pub fn mtls_server_config_from_bundle(
+    bundle: &TestCertBundle,
+    client_ca: &TestCertBundle,
+) -> Arc<ServerConfig> {
+    let certs = load_certs(&bundle.cert_path).expect("Failed to load server certs");
+    let key = load_private_key(&bundle.key_path).expect("Failed to load server key");
+    let verifier = WebPkiClientVerifier::builder(Arc::new(root_store(client_ca)))
+        .build()
+        .expect("Failed to create client verifier");
+
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .expect("Failed to create server config");
+
+    Arc::new(config)
+}
+
 /// Create a TLS client configuration that trusts the given certificate.
 pub fn client_config_from_bundle(bundle: &TestCertBundle) -> Arc<ClientConfig> {
+    let config = ClientConfig::builder()
+        .with_root_certificates(root_store(bundle))
+        .with_no_client_auth();
+
+    Arc::new(config)
+}
+
+/// Create a TLS client configuration that trusts `trusted` and presents
+/// `identity`, e.g. from [`generate_client_cert`](crate::crypto::generate_client_cert).
+pub fn client_config_with_identity(
+    trusted: &TestCertBundle,
+    identity: &TestCertBundle,
+) -> Arc<ClientConfig> {
+    let certs = load_certs(&identity.cert_path).expect("Failed to load client certs");
+    let key = load_private_key(&identity.key_path).expect("Failed to load client key");
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(root_store(trusted))
+        .with_client_auth_cert(certs, key)
+        .expect("Failed to create client config");
+
+    Arc::new(config)
+}
+
+/// A root store holding every certificate in the bundle's PEM file.
+fn root_store(bundle: &TestCertBundle) -> RootCertStore {
     let ca_certs = load_certs(&bundle.cert_path).expect("Failed to load CA certs");
 
     let mut root_store = RootCertStore::empty();
@@ -32,12 +79,7 @@ pub fn client_config_from_bundle(bundle: &TestCertBundle) -> Arc<ClientConfig> {
             .add(cert)
             .expect("Failed to add cert to root store");
     }
-
-    let config = ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
-
-    Arc::new(config)
+    root_store
 }
 
 /// Create a TLS acceptor from a server configuration.
@@ -111,3 +153,39 @@ fn load_private_key(path: &Path) -> std::io::Result<PrivateKeyDer<'static>> {
         "No valid private keys found",
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{generate_ca_and_server_certs, generate_client_cert, init_crypto_once};
+    use crate::net::connected_tcp_pair;
+
+    /// Whether the server accepts a client configured with `client`.
+    async fn server_accepts(server: Arc<ServerConfig>, client: Arc<ClientConfig>) -> bool {
+        let (server_stream, client_stream) = connected_tcp_pair().await.unwrap();
+        let domain = ServerName::try_from("localhost").unwrap();
+        let (server, _) = tokio::join!(
+            create_tls_acceptor(server).accept(server_stream),
+            create_tls_connector(client).connect(domain, client_stream)
+        );
+        server.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_requires_a_trusted_identity() {
+        init_crypto_once();
+        let (ca, server) = generate_ca_and_server_certs("localhost");
+        let (other_ca, _) = generate_ca_and_server_certs("localhost");
+        let server_config = mtls_server_config_from_bundle(&server, &ca);
+
+        let identity = generate_client_cert(&ca, "pilot-1");
+        let client = client_config_with_identity(&ca, &identity);
+        assert!(server_accepts(server_config.clone(), client).await);
+
+        assert!(!server_accepts(server_config.clone(), client_config_from_bundle(&ca)).await);
+
+        let stranger = generate_client_cert(&other_ca, "pilot-2");
+        let client = client_config_with_identity(&ca, &stranger);
+        assert!(!server_accepts(server_config, client).await);
+    }
+}
@@ -0,0 +1,193 @@
+//! UDP test helpers for the voice path
+//!
+//! Voice packets travel as single datagrams, so these helpers work on raw
+//! bytes: pass `AudioPacket::to_bytes()` in and parse what comes back with
+//! `AudioPacket::from_bytes`. The corruption helpers know just enough of the
+//! 16-byte packet header to break it in the ways a hostile or buggy peer
+//! would.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Size of a voice packet header, in bytes.
+pub const PACKET_HEADER_LEN: usize = 16;
+
+/// Offset of the 16-bit HMAC prefix within the header.
+const HMAC_PREFIX_OFFSET: usize = 14;
+
+/// Largest datagram the receive helpers accept.
+const MAX_DATAGRAM_LEN: usize = 65_535;
+
+/// Create a pair of UDP sockets on localhost, each connected to the other.
+///
+/// Connected sockets can use `send`/`recv` and only receive from their peer,
+/// so stray datagrams from other tests never arrive.
+pub async fn connected_udp_pair() -> io::Result<(UdpSocket, UdpSocket)> {
+    connected_udp_pair_on("127.0.0.1:0").await
+}
+
+/// Create a pair of UDP sockets on the IPv6 loopback `[::1]`, each
+/// connected to the other.
+///
+/// Fails on hosts without IPv6; check [`crate::net::ipv6_available`] first
+/// to skip.
+pub async fn connected_udp_pair_v6() -> io::Result<(UdpSocket, UdpSocket)> {
+    connected_udp_pair_on("[::1]:0").await
+}
+
+async fn connected_udp_pair_on(address: &str) -> io::Result<(UdpSocket, UdpSocket)> {
+    let first = UdpSocket::bind(address).await?;
+    let second = UdpSocket::bind(address).await?;
+    first.connect(second.local_addr()?).await?;
+    second.connect(first.local_addr()?).await?;
+    Ok((first, second))
+}
+
+/// Send one packet's bytes to the socket's connected peer.
+pub async fn send_packet(socket: &UdpSocket, packet: impl AsRef<[u8]>) -> io::Result<()> {
+    let packet = packet.as_ref();
+    let sent = socket.send(packet).await?;
+    if sent != packet.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            format!("Sent {sent} of {} bytes", packet.len()),
+        ));
+    }
+    Ok(())
+}
+
+/// Send a copy of `packet` whose HMAC prefix no longer matches its contents.
+pub async fn send_corrupted_hmac(socket: &UdpSocket, packet: impl AsRef<[u8]>) -> io::Result<()> {
+    send_packet(socket, corrupt_hmac(packet.as_ref())).await
+}
+
+/// Send the first `len` bytes of `packet`, cut short inside its header.
+pub async fn send_truncated_header(
+    socket: &UdpSocket,
+    packet: impl AsRef<[u8]>,
+    len: usize,
+) -> io::Result<()> {
+    send_packet(socket, truncate_header(packet.as_ref(), len)).await
+}
+
+/// A copy of `packet` with every bit of its HMAC prefix flipped.
+///
+/// # Panics
+///
+/// Panics if `packet` is shorter than a header.
+pub fn corrupt_hmac(packet: &[u8]) -> Vec<u8> {
+    assert!(
+        packet.len() >= PACKET_HEADER_LEN,
+        "packet of {} bytes has no full header",
+        packet.len()
+    );
+    let mut corrupted = packet.to_vec();
+    for byte in &mut corrupted[HMAC_PREFIX_OFFSET..PACKET_HEADER_LEN] {
+        *byte = !*byte;
+    }
+    corrupted
+}
+
+/// The first `len` bytes of `packet`, capped one byte short of a full
+/// header so the result can never parse.
+pub fn truncate_header(packet: &[u8], len: usize) -> Vec<u8> {
+    packet[..len.min(PACKET_HEADER_LEN - 1).min(packet.len())].to_vec()
+}
+
+/// Receive one datagram, failing with `TimedOut` after `wait`.
+pub async fn recv_packet(socket: &UdpSocket, wait: Duration) -> io::Result<Vec<u8>> {
+    recv_packet_from(socket, wait)
+        .await
+        .map(|(packet, _)| packet)
+}
+
+/// Receive one datagram and its sender, failing with `TimedOut` after `wait`.
+pub async fn recv_packet_from(
+    socket: &UdpSocket,
+    wait: Duration,
+) -> io::Result<(Vec<u8>, SocketAddr)> {
+    let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+    let (len, from) = tokio::time::timeout(wait, socket.recv_from(&mut buf))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No datagram arrived"))??;
+    buf.truncate(len);
+    Ok((buf, from))
+}
+
+/// Whether nothing arrives on `socket` within `wait`, e.g. to check that a
+/// corrupted packet was dropped rather than forwarded.
+pub async fn no_packet_within(socket: &UdpSocket, wait: Duration) -> bool {
+    matches!(
+        recv_packet(socket, wait).await,
+        Err(e) if e.kind() == io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet() -> Vec<u8> {
+        (0..PACKET_HEADER_LEN as u8 + 4).collect()
+    }
+
+    #[tokio::test]
+    async fn test_connected_udp_pair_v6() {
+        if !crate::net::ipv6_available() {
+            return;
+        }
+        let (first, second) = connected_udp_pair_v6()
+            .await
+            .expect("Failed to create UDP pair");
+        assert!(first.local_addr().unwrap().is_ipv6());
+
+        send_packet(&first, packet()).await.expect("Failed to send");
+        let (received, from) = recv_packet_from(&second, Duration::from_secs(1))
+            .await
+            .expect("Failed to receive");
+        assert_eq!(received, packet());
+        assert_eq!(from, first.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_connected_udp_pair() {
+        let (first, second) = connected_udp_pair()
+            .await
+            .expect("Failed to create UDP pair");
+
+        send_packet(&first, packet()).await.expect("Failed to send");
+        let (received, from) = recv_packet_from(&second, Duration::from_secs(1))
+            .await
+            .expect("Failed to receive");
+        assert_eq!(received, packet());
+        assert_eq!(from, first.local_addr().unwrap());
+
+        send_corrupted_hmac(&second, packet())
+            .await
+            .expect("Failed to send");
+        let corrupted = recv_packet(&first, Duration::from_secs(1))
+            .await
+            .expect("Failed to receive");
+        assert_eq!(
+            corrupted[..HMAC_PREFIX_OFFSET],
+            packet()[..HMAC_PREFIX_OFFSET]
+        );
+        assert_eq!(corrupted[HMAC_PREFIX_OFFSET..16], [!14, !15]);
+        assert_eq!(
+            corrupted[PACKET_HEADER_LEN..],
+            packet()[PACKET_HEADER_LEN..]
+        );
+
+        send_truncated_header(&first, packet(), 100)
+            .await
+            .expect("Failed to send");
+        let truncated = recv_packet(&second, Duration::from_secs(1))
+            .await
+            .expect("Failed to receive");
+        assert_eq!(truncated.len(), PACKET_HEADER_LEN - 1);
+
+        assert!(no_packet_within(&second, Duration::from_millis(20)).await);
+    }
+}